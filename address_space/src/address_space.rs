@@ -10,13 +10,14 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use arc_swap::ArcSwap;
 use std::fmt;
 use std::fmt::Debug;
 use std::io::Write;
 use std::sync::{Arc, Mutex};
 
+use hypervisor::kvm::KVM_FDS;
 use migration::{migration::Migratable, MigrationManager};
 use util::aio::Iovec;
 use util::byte_code::ByteCode;
@@ -106,6 +107,27 @@ impl FlatView {
 
         Ok(())
     }
+
+    /// Split the range into pieces according to flat ranges.
+    /// Return the flat range and the length of each piece, or Error
+    /// if any part of the range is not mapped.
+    fn split_range(&self, addr: GuestAddress, count: u64) -> Result<Vec<(&FlatRange, u64)>> {
+        let out_of_bounds = || anyhow!(AddressSpaceError::OutOfBounds(addr.raw_value(), count));
+        addr.checked_add(count).ok_or_else(out_of_bounds)?;
+
+        let mut pieces = Vec::new();
+        let mut len = count;
+        let mut start = addr;
+        while len > 0 {
+            let fr = self.find_flatrange(start).ok_or_else(out_of_bounds)?;
+            let fr_remain = fr.addr_range.end_addr().offset_from(start);
+            let l = std::cmp::min(len, fr_remain);
+            pieces.push((fr, l));
+            len -= l;
+            start = start.unchecked_add(l);
+        }
+        Ok(pieces)
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -116,6 +138,19 @@ pub struct RegionCache {
     pub end: u64,
 }
 
+/// Descriptor of the region which backs a guest address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionDescriptor {
+    /// Type of the backing region.
+    pub region_type: RegionType,
+    /// Host address of `guest_base`, None if the region is not backed by host memory.
+    pub host_base: Option<*mut u8>,
+    /// Start address of the visible part of region in address space.
+    pub guest_base: GuestAddress,
+    /// Size of the visible part of region.
+    pub size: u64,
+}
+
 type ListenerObj = Arc<Mutex<dyn Listener>>;
 
 /// Address Space of memory.
@@ -565,6 +600,26 @@ impl AddressSpace {
         None
     }
 
+    /// Return the descriptor of region which backs the given guest address.
+    /// As regions may overlap, the descriptor only covers the visible part of
+    /// region, the host address can be derived directly within it.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Guest address.
+    pub fn find_region_by_addr(&self, addr: GuestAddress) -> Option<RegionDescriptor> {
+        let view = self.flat_view.load();
+        view.find_flatrange(addr).map(|range| RegionDescriptor {
+            region_type: range.owner.region_type(),
+            host_base: range
+                .owner
+                .get_host_address()
+                .map(|host| (host + range.offset_in_region) as *mut u8),
+            guest_base: range.addr_range.base,
+            size: range.addr_range.size,
+        })
+    }
+
     /// Return the end address of memory according to all Ram regions in AddressSpace.
     pub fn memory_end_address(&self) -> GuestAddress {
         self.flat_view
@@ -636,6 +691,58 @@ impl AddressSpace {
         Ok(())
     }
 
+    /// Read memory segment to the slice `buf`, the segment may cross regions.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Start address.
+    /// * `buf` - Destination slice, the size of data is the length of slice.
+    ///
+    /// # Errors
+    ///
+    /// Return Error if any part of the segment is not mapped, and nothing is read in this case.
+    pub fn read_slice(&self, addr: GuestAddress, buf: &mut [u8]) -> Result<()> {
+        let view = self.flat_view.load();
+
+        let mut start = addr;
+        let mut offset = 0_usize;
+        for (fr, l) in view.split_range(addr, buf.len() as u64)? {
+            let region_offset = fr.offset_in_region + start.offset_from(fr.addr_range.base);
+            let region_base = fr.addr_range.base.unchecked_sub(fr.offset_in_region);
+            let mut dst = &mut buf[offset..offset + l as usize];
+            fr.owner.read(&mut dst, region_base, region_offset, l)?;
+            offset += l as usize;
+            start = start.unchecked_add(l);
+        }
+        Ok(())
+    }
+
+    /// Write the slice `buf` to memory segment, the segment may cross regions.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Start address.
+    /// * `buf` - Source slice, the size of data is the length of slice.
+    ///
+    /// # Errors
+    ///
+    /// Return Error if any part of the segment is not mapped, and nothing is written in this case.
+    pub fn write_slice(&self, addr: GuestAddress, buf: &[u8]) -> Result<()> {
+        let view = self.flat_view.load();
+
+        let mut start = addr;
+        let mut offset = 0_usize;
+        for (fr, l) in view.split_range(addr, buf.len() as u64)? {
+            let region_offset = fr.offset_in_region + start.offset_from(fr.addr_range.base);
+            let region_base = fr.addr_range.base.unchecked_sub(fr.offset_in_region);
+            let mut src = &buf[offset..offset + l as usize];
+            fr.owner.write(&mut src, region_base, region_offset, l)?;
+            offset += l as usize;
+            start = start.unchecked_add(l);
+        }
+        Ok(())
+    }
+
     /// Write an object to memory.
     ///
     /// # Arguments
@@ -709,6 +816,48 @@ impl AddressSpace {
         Ok(obj)
     }
 
+    /// Start dirty page tracking for all the kvm memory slots, the pages
+    /// written by vmm are tracked in the dirty bitmaps of migration.
+    pub fn enable_dirty_tracking(&self) -> Result<()> {
+        if KVM_FDS.load().vm_fd.is_none() {
+            bail!("Failed to enable dirty tracking: kvm vm is not created");
+        }
+        MigrationManager::start_dirty_log()
+    }
+
+    /// Stop dirty page tracking for all the kvm memory slots.
+    pub fn disable_dirty_tracking(&self) -> Result<()> {
+        MigrationManager::stop_dirty_log()
+    }
+
+    /// Get the dirty page bitmap of kvm memory slot, one bit per page. The
+    /// bitmap is cleared after it is got.
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - Index of kvm memory slot.
+    pub fn get_dirty_bitmap(&self, slot: u32) -> Result<Vec<u64>> {
+        let mem_slot = KVM_FDS
+            .load()
+            .get_mem_slots()
+            .lock()
+            .unwrap()
+            .get(&slot)
+            .copied()
+            .with_context(|| format!("Invalid kvm_mem_slot {}", slot))?;
+        MigrationManager::get_dirty_bitmap(&mem_slot)
+    }
+
+    /// Clear the dirty page bitmap of kvm memory slot.
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - Index of kvm memory slot.
+    pub fn clear_dirty_bitmap(&self, slot: u32) -> Result<()> {
+        self.get_dirty_bitmap(slot)?;
+        Ok(())
+    }
+
     /// Update the topology of memory.
     pub fn update_topology(&self) -> Result<()> {
         let old_fv = self.flat_view.load();
@@ -733,10 +882,13 @@ impl AddressSpace {
 
 #[cfg(test)]
 mod test {
+    use hypervisor::kvm::KVMFds;
+    use serial_test::serial;
+    use util::unix::host_page_size;
     use vmm_sys_util::eventfd::EventFd;
 
     use super::*;
-    use crate::{HostMemMapping, RegionOps};
+    use crate::{HostMemMapping, HostMemOptions, KvmMemoryListener, RegionOps};
 
     #[derive(Default, Clone)]
     struct TestListener {
@@ -1131,10 +1283,18 @@ mod test {
         };
 
         let ram1 = Arc::new(
-            HostMemMapping::new(GuestAddress(0), None, 1000, None, false, false, false).unwrap(),
+            HostMemMapping::new(GuestAddress(0), None, 1000, None, HostMemOptions::default())
+                .unwrap(),
         );
         let ram2 = Arc::new(
-            HostMemMapping::new(GuestAddress(2000), None, 1000, None, false, false, false).unwrap(),
+            HostMemMapping::new(
+                GuestAddress(2000),
+                None,
+                1000,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
         );
         let region_a = Region::init_ram_region(ram1.clone(), "region_a");
         let region_b = Region::init_ram_region(ram2.clone(), "region_b");
@@ -1199,7 +1359,8 @@ mod test {
         let root = Region::init_container_region(8000, "root");
         let space = AddressSpace::new(root.clone(), "space").unwrap();
         let ram1 = Arc::new(
-            HostMemMapping::new(GuestAddress(0), None, 1000, None, false, false, false).unwrap(),
+            HostMemMapping::new(GuestAddress(0), None, 1000, None, HostMemOptions::default())
+                .unwrap(),
         );
         let region_a = Region::init_ram_region(ram1.clone(), "region_a");
         root.add_subregion(region_a, ram1.start_address().raw_value())
@@ -1211,4 +1372,240 @@ mod test {
         assert_eq!(data1, 10000);
         assert!(space.write_object(&data, GuestAddress(993)).is_err());
     }
+
+    #[test]
+    #[serial]
+    fn test_dirty_tracking() {
+        let kvm_fds = KVMFds::new();
+        if kvm_fds.vm_fd.is_none() {
+            return;
+        }
+        KVM_FDS.store(Arc::new(kvm_fds));
+
+        let page_size = host_page_size();
+        let root = Region::init_container_region(page_size * 16, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        space
+            .register_listener(Arc::new(Mutex::new(KvmMemoryListener::new(4))))
+            .unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                page_size * 8,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        root.add_subregion(Region::init_ram_region(ram.clone(), "ram"), 0)
+            .unwrap();
+        let slot = *KVM_FDS
+            .load()
+            .get_mem_slots()
+            .lock()
+            .unwrap()
+            .keys()
+            .next()
+            .unwrap();
+
+        // Dirty tracking is not enabled.
+        assert!(space.get_dirty_bitmap(slot).is_err());
+        assert!(space.clear_dirty_bitmap(slot).is_err());
+
+        space.enable_dirty_tracking().unwrap();
+        assert_eq!(space.get_dirty_bitmap(slot).unwrap(), vec![0]);
+        assert!(space.get_dirty_bitmap(slot + 1).is_err());
+
+        // Write to page 1, and the data crossing page 3 and page 4.
+        space
+            .write_object(&u64::MAX, GuestAddress(page_size + 8))
+            .unwrap();
+        space
+            .write_slice(GuestAddress(page_size * 4 - 4), &[0xff_u8; 8])
+            .unwrap();
+        assert_eq!(space.get_dirty_bitmap(slot).unwrap(), vec![0b1_1010]);
+        // The bitmap is cleared after it is got.
+        assert_eq!(space.get_dirty_bitmap(slot).unwrap(), vec![0]);
+
+        // Write to page 6 via host address.
+        space
+            .write_object_direct(&u64::MAX, ram.host_address() + page_size * 6)
+            .unwrap();
+        space.clear_dirty_bitmap(slot).unwrap();
+        assert_eq!(space.get_dirty_bitmap(slot).unwrap(), vec![0]);
+        space
+            .write_object_direct(&u64::MAX, ram.host_address() + page_size * 6)
+            .unwrap();
+        assert_eq!(space.get_dirty_bitmap(slot).unwrap(), vec![0b100_0000]);
+
+        space.disable_dirty_tracking().unwrap();
+        assert!(space.get_dirty_bitmap(slot).is_err());
+    }
+
+    #[test]
+    fn test_find_region_by_addr() {
+        let root = Region::init_container_region(8000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let default_ops = RegionOps {
+            read: Arc::new(|_: &mut [u8], _: GuestAddress, _: u64| -> bool { true }),
+            write: Arc::new(|_: &[u8], _: GuestAddress, _: u64| -> bool { true }),
+        };
+
+        // memory region layout
+        //        0      1000   2000   3000   4000   5000   6000   7000   8000
+        //        |------|------|------|------|------|------|------|------|
+        //  A:    [AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA]
+        //  B:           [                           ]
+        //  C:                  [CCCCCC]
+        //  D:                                              [DDDDDD]
+        //
+        // the flat_view is as follows, region-b is container which will not appear in the flat-view
+        //        [AAAAAAAAAAAA][CCCCCC][AAAAAAAAAAAAAAAA]      [DDDDDD]
+        let ram_a = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 5000, None, HostMemOptions::default())
+                .unwrap(),
+        );
+        let ram_c = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(2000),
+                None,
+                1000,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        let region_a = Region::init_ram_region(ram_a.clone(), "region_a");
+        let region_b = Region::init_container_region(4000, "region_b");
+        let region_c = Region::init_ram_region(ram_c.clone(), "region_c");
+        let region_d = Region::init_io_region(1000, default_ops, "region_d");
+        region_a.set_priority(1);
+        region_b.set_priority(2);
+        root.add_subregion(region_a, 0).unwrap();
+        root.add_subregion(region_b.clone(), 1000).unwrap();
+        region_b.add_subregion(region_c, 1000).unwrap();
+        root.add_subregion(region_d, 6000).unwrap();
+
+        // Address in the ram region below the container.
+        let desc = space.find_region_by_addr(GuestAddress(1500)).unwrap();
+        assert_eq!(desc.region_type, RegionType::Ram);
+        assert_eq!(desc.guest_base, GuestAddress(0));
+        assert_eq!(desc.size, 2000);
+        assert_eq!(desc.host_base, Some(ram_a.host_address() as *mut u8));
+
+        // Address in the ram region inside the container.
+        let desc = space.find_region_by_addr(GuestAddress(2999)).unwrap();
+        assert_eq!(desc.region_type, RegionType::Ram);
+        assert_eq!(desc.guest_base, GuestAddress(2000));
+        assert_eq!(desc.size, 1000);
+        assert_eq!(desc.host_base, Some(ram_c.host_address() as *mut u8));
+
+        // The rest of ram region which is covered by the container partly.
+        let desc = space.find_region_by_addr(GuestAddress(3000)).unwrap();
+        assert_eq!(desc.region_type, RegionType::Ram);
+        assert_eq!(desc.guest_base, GuestAddress(3000));
+        assert_eq!(desc.size, 2000);
+        assert_eq!(
+            desc.host_base,
+            Some((ram_a.host_address() + 3000) as *mut u8)
+        );
+        let data = 0x1234_5678_u32;
+        space.write_object(&data, GuestAddress(3000)).unwrap();
+        // SAFETY: the host address is mapped and valid.
+        let read = unsafe { *(desc.host_base.unwrap() as *const u32) };
+        assert_eq!(read, data);
+
+        // Io region is not backed by host memory.
+        let desc = space.find_region_by_addr(GuestAddress(6000)).unwrap();
+        assert_eq!(desc.region_type, RegionType::IO);
+        assert_eq!(desc.guest_base, GuestAddress(6000));
+        assert_eq!(desc.size, 1000);
+        assert!(desc.host_base.is_none());
+
+        // Address is not mapped.
+        assert!(space.find_region_by_addr(GuestAddress(5000)).is_none());
+        assert!(space.find_region_by_addr(GuestAddress(7000)).is_none());
+    }
+
+    #[test]
+    fn test_read_and_write_slice() {
+        let root = Region::init_container_region(8000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram1 = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 1000, None, HostMemOptions::default())
+                .unwrap(),
+        );
+        let ram2 = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(1000),
+                None,
+                1000,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        let ram3 = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(3000),
+                None,
+                1000,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        for (ram, name) in [
+            (&ram1, "region_a"),
+            (&ram2, "region_b"),
+            (&ram3, "region_c"),
+        ] {
+            let region = Region::init_ram_region(ram.clone(), name);
+            root.add_subregion(region, ram.start_address().raw_value())
+                .unwrap();
+        }
+
+        // Aligned access.
+        let data: Vec<u8> = (0..64).collect();
+        let mut buf = vec![0_u8; 64];
+        assert!(space.write_slice(GuestAddress(64), &data).is_ok());
+        assert!(space.read_slice(GuestAddress(64), &mut buf).is_ok());
+        assert_eq!(buf, data);
+
+        // Misaligned access.
+        let mut buf = vec![0_u8; 13];
+        assert!(space.write_slice(GuestAddress(257), &data[..13]).is_ok());
+        assert!(space.read_slice(GuestAddress(257), &mut buf).is_ok());
+        assert_eq!(buf, data[..13]);
+
+        // Cross-region access.
+        let data: Vec<u8> = (0..200).collect();
+        let mut buf = vec![0_u8; 200];
+        assert!(space.write_slice(GuestAddress(900), &data).is_ok());
+        assert!(space.read_slice(GuestAddress(900), &mut buf).is_ok());
+        assert_eq!(buf, data);
+        let data1: u64 = space.read_object(GuestAddress(1000)).unwrap();
+        assert_eq!(
+            data1,
+            u64::from_le_bytes([100, 101, 102, 103, 104, 105, 106, 107])
+        );
+
+        // Part of the range is not mapped, nothing is written.
+        let mut buf = vec![0_u8; 16];
+        assert!(space
+            .write_slice(GuestAddress(1992), &[0xff_u8; 16])
+            .is_err());
+        assert!(space.read_slice(GuestAddress(1984), &mut buf[..8]).is_ok());
+        assert_eq!(buf[..8], [0_u8; 8]);
+        assert!(space.read_slice(GuestAddress(1992), &mut buf).is_err());
+        assert!(space.read_slice(GuestAddress(2500), &mut buf).is_err());
+        assert!(space.read_slice(GuestAddress(3990), &mut buf).is_err());
+        assert!(space
+            .read_slice(GuestAddress(u64::MAX - 4), &mut buf)
+            .is_err());
+
+        // Empty slice.
+        assert!(space.read_slice(GuestAddress(2500), &mut []).is_ok());
+    }
 }
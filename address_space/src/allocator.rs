@@ -0,0 +1,251 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, Result};
+
+use crate::{AddressSpaceError, GuestAddress};
+
+/// Shift of the minimum block, which is a guest page.
+const MIN_ORDER: u32 = 12;
+/// Shift of the maximum block.
+const MAX_ORDER: u32 = 63;
+
+/// Buddy allocator of guest physical memory, such as the descriptor table of
+/// virtio queue. The memory is split into blocks whose size is a power of two
+/// and not smaller than a page, and each block is aligned to its size.
+pub struct GuestMemoryAllocator {
+    /// Start address of the managed range.
+    base: GuestAddress,
+    /// Size of the managed range.
+    size: u64,
+    /// Free blocks of each order, indexed by `order - MIN_ORDER`.
+    free_lists: Vec<BTreeSet<u64>>,
+    /// Allocated blocks, the key is the start address and the value is order.
+    allocated: BTreeMap<u64, u32>,
+}
+
+impl GuestMemoryAllocator {
+    /// Create allocator which manages guest memory range `[base, base + size)`.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - Start address of the range, must be page aligned.
+    /// * `size` - Size of the range, must be page aligned.
+    ///
+    /// # Errors
+    ///
+    /// Return Error if the range is not page aligned or overflows.
+    pub fn new(base: GuestAddress, size: u64) -> Result<Self> {
+        let page_mask = (1_u64 << MIN_ORDER) - 1;
+        if base.raw_value() & page_mask != 0 || size & page_mask != 0 {
+            return Err(anyhow!(AddressSpaceError::InvalidOffset(
+                base.raw_value(),
+                size,
+                1 << MIN_ORDER
+            )));
+        }
+        let end = base
+            .raw_value()
+            .checked_add(size)
+            .ok_or_else(|| anyhow!(AddressSpaceError::Overflow(base.raw_value())))?;
+
+        let mut allocator = GuestMemoryAllocator {
+            base,
+            size,
+            free_lists: vec![BTreeSet::new(); (MAX_ORDER - MIN_ORDER + 1) as usize],
+            allocated: BTreeMap::new(),
+        };
+        // Split the range into the largest blocks aligned to their size.
+        let mut addr = base.raw_value();
+        while addr < end {
+            let mut order = MIN_ORDER;
+            while order < MAX_ORDER
+                && addr & ((1 << (order + 1)) - 1) == 0
+                && end - addr >= 1 << (order + 1)
+            {
+                order += 1;
+            }
+            allocator.free_list(order).insert(addr);
+            addr += 1 << order;
+        }
+        Ok(allocator)
+    }
+
+    /// Start address of the managed range.
+    pub fn base(&self) -> GuestAddress {
+        self.base
+    }
+
+    /// Size of the managed range.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn free_list(&mut self, order: u32) -> &mut BTreeSet<u64> {
+        &mut self.free_lists[(order - MIN_ORDER) as usize]
+    }
+
+    /// Get the order of block which holds `size` bytes and is aligned to `align`.
+    fn block_order(size: u64, align: u64) -> Option<u32> {
+        let block_size = size
+            .checked_next_power_of_two()?
+            .max(align)
+            .max(1 << MIN_ORDER);
+        Some(block_size.trailing_zeros())
+    }
+
+    /// Allocate guest memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Size of the memory.
+    /// * `align` - Alignment of the start address, must be a power of two.
+    ///
+    /// # Errors
+    ///
+    /// Return Error if the arguments are invalid, or there is no free block
+    /// large enough.
+    pub fn alloc(&mut self, size: u64, align: u64) -> Result<GuestAddress> {
+        if size == 0 || !align.is_power_of_two() {
+            return Err(anyhow!(AddressSpaceError::AllocFailed(size, align)));
+        }
+        let order = Self::block_order(size, align)
+            .filter(|order| *order <= MAX_ORDER)
+            .ok_or_else(|| anyhow!(AddressSpaceError::AllocFailed(size, align)))?;
+
+        // Find the smallest free block, then split it down to the wanted order.
+        let mut cur_order = order;
+        let addr = loop {
+            let free_list = self.free_list(cur_order);
+            if let Some(addr) = free_list.iter().next().copied() {
+                free_list.remove(&addr);
+                break addr;
+            }
+            if cur_order == MAX_ORDER {
+                return Err(anyhow!(AddressSpaceError::AllocFailed(size, align)));
+            }
+            cur_order += 1;
+        };
+        while cur_order > order {
+            cur_order -= 1;
+            self.free_list(cur_order).insert(addr + (1 << cur_order));
+        }
+
+        self.allocated.insert(addr, order);
+        Ok(GuestAddress(addr))
+    }
+
+    /// Free guest memory allocated by `alloc`, and merge the freed block with
+    /// its free buddies.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Start address of the memory.
+    /// * `size` - Size of the memory, the same as it is allocated.
+    ///
+    /// # Errors
+    ///
+    /// Return Error if the memory is not allocated, such as double free.
+    pub fn free(&mut self, addr: GuestAddress, size: u64) -> Result<()> {
+        let mut addr = addr.raw_value();
+        let mut order = match self.allocated.get(&addr) {
+            Some(order) if size != 0 && size <= 1 << *order => *order,
+            _ => return Err(anyhow!(AddressSpaceError::NotAllocated(addr, size))),
+        };
+        self.allocated.remove(&addr);
+
+        while order < MAX_ORDER {
+            let buddy = addr ^ (1 << order);
+            if !self.free_list(order).remove(&buddy) {
+                break;
+            }
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        self.free_list(order).insert(addr);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alloc() {
+        let mut allocator = GuestMemoryAllocator::new(GuestAddress(0x10_0000), 0x10_000).unwrap();
+        assert_eq!(allocator.base(), GuestAddress(0x10_0000));
+        assert_eq!(allocator.size(), 0x10_000);
+
+        // The size is rounded up to a page at least.
+        let addr1 = allocator.alloc(0x100, 0x10).unwrap();
+        assert_eq!(addr1, GuestAddress(0x10_0000));
+        let addr2 = allocator.alloc(0x1000, 0x1000).unwrap();
+        assert_eq!(addr2, GuestAddress(0x10_1000));
+
+        // The alignment is honored.
+        let addr3 = allocator.alloc(0x1000, 0x4000).unwrap();
+        assert_eq!(addr3, GuestAddress(0x10_4000));
+        let addr4 = allocator.alloc(0x3000, 0x1000).unwrap();
+        assert_eq!(addr4, GuestAddress(0x10_8000));
+        let addr5 = allocator.alloc(0x1000, 0x1000).unwrap();
+        assert_eq!(addr5, GuestAddress(0x10_2000));
+
+        // No enough memory.
+        assert!(allocator.alloc(0x8000, 0x1000).is_err());
+        // Invalid arguments.
+        assert!(allocator.alloc(0, 0x1000).is_err());
+        assert!(allocator.alloc(0x1000, 0x3000).is_err());
+        assert!(allocator.alloc(u64::MAX, 0x1000).is_err());
+
+        // Unaligned range.
+        assert!(GuestMemoryAllocator::new(GuestAddress(0x800), 0x1000).is_err());
+        assert!(GuestMemoryAllocator::new(GuestAddress(0), 0x1800).is_err());
+    }
+
+    #[test]
+    fn test_free() {
+        let mut allocator = GuestMemoryAllocator::new(GuestAddress(0), 0x4000).unwrap();
+        let addr1 = allocator.alloc(0x1000, 0x1000).unwrap();
+        let addr2 = allocator.alloc(0x1000, 0x1000).unwrap();
+        let addr3 = allocator.alloc(0x2000, 0x1000).unwrap();
+        assert!(allocator.alloc(0x1000, 0x1000).is_err());
+
+        // The freed block is merged with its buddies.
+        allocator.free(addr1, 0x1000).unwrap();
+        allocator.free(addr2, 0x1000).unwrap();
+        allocator.free(addr3, 0x2000).unwrap();
+        assert_eq!(allocator.alloc(0x4000, 0x4000).unwrap(), GuestAddress(0));
+        allocator.free(GuestAddress(0), 0x4000).unwrap();
+
+        // The size is larger than the allocated one.
+        let addr = allocator.alloc(0x1000, 0x1000).unwrap();
+        assert!(allocator.free(addr, 0x2000).is_err());
+        allocator.free(addr, 0x1000).unwrap();
+    }
+
+    #[test]
+    fn test_double_free() {
+        let mut allocator = GuestMemoryAllocator::new(GuestAddress(0), 0x4000).unwrap();
+        let addr = allocator.alloc(0x1000, 0x1000).unwrap();
+        allocator.free(addr, 0x1000).unwrap();
+        assert!(allocator.free(addr, 0x1000).is_err());
+
+        // Never allocated.
+        assert!(allocator.free(GuestAddress(0x2000), 0x1000).is_err());
+
+        // The whole range is still available after the invalid free.
+        assert_eq!(allocator.alloc(0x4000, 0x1000).unwrap(), GuestAddress(0));
+    }
+}
@@ -50,4 +50,10 @@ pub enum AddressSpaceError {
     KvmSlotOverlap { add: (u64, u64), exist: (u64, u64) },
     #[error("Invalid offset: offset 0x{0:X}, data length 0x{1:X}, region size 0x{2:X}")]
     InvalidOffset(u64, u64, u64),
+    #[error("Access out of bounds of mapped regions, addr 0x{0:X}, size 0x{1:X}")]
+    OutOfBounds(u64, u64),
+    #[error("Failed to allocate guest memory, size 0x{0:X}, align 0x{1:X}")]
+    AllocFailed(u64, u64),
+    #[error("Guest memory is not allocated, addr 0x{0:X}, size 0x{1:X}")]
+    NotAllocated(u64, u64),
 }
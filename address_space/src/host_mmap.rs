@@ -11,14 +11,16 @@
 // See the Mulan PSL v2 for more details.
 
 use std::cmp::min;
+use std::ffi::CString;
 use std::fs::File;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
 use std::sync::Arc;
 use std::thread;
 
 use anyhow::{bail, Context, Result};
 use log::{error, info};
-use machine_manager::config::{HostMemPolicy, MachineMemConfig, MemZoneConfig};
+use machine_manager::config::{HostMemPolicy, HugePageSize, MachineMemConfig, MemZoneConfig};
 use util::{
     syscall::mbind,
     unix::{do_mmap, host_page_size},
@@ -31,6 +33,9 @@ const MAX_PREALLOC_THREAD: u8 = 16;
 const MPOL_MF_STRICT: u32 = 1;
 /// Move pages owned by this process to conform to mapping.
 const MPOL_MF_MOVE: u32 = 2;
+/// Seals added to sealed memfd. `F_SEAL_FUTURE_WRITE` is used instead of `F_SEAL_WRITE`,
+/// because the latter forbids the writable shared mapping of guest memory itself.
+const MEMFD_SEALS: i32 = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_FUTURE_WRITE;
 
 /// FileBackend represents backend-file of `HostMemMapping`.
 #[derive(Clone, Debug)]
@@ -241,6 +246,24 @@ pub fn mem_prealloc(host_addr: u64, size: u64, nr_vcpus: u8) {
 pub fn create_default_mem(mem_config: &MachineMemConfig, thread_num: u8) -> Result<Region> {
     let mut f_back: Option<FileBackend> = None;
 
+    if let (Some(page_size), Some(path)) = (mem_config.mem_hugepage_size, &mem_config.mem_path) {
+        let block = Arc::new(HostMemMapping::new_hugetlb(
+            mem_config.mem_size,
+            Path::new(path),
+            page_size,
+            HostMemOptions {
+                dump_guest_core: mem_config.dump_guest_core,
+                is_share: mem_config.mem_share,
+                prefault: mem_config.mem_prefault,
+                ..Default::default()
+            },
+        )?);
+        if mem_config.mem_prealloc {
+            mem_prealloc(block.host_address(), mem_config.mem_size, thread_num);
+        }
+        return Ok(Region::init_ram_region(block, "DefaultRam"));
+    }
+
     if mem_config.mem_share {
         let anon_mem_name = String::from("stratovirt_anon_mem");
 
@@ -271,9 +294,12 @@ pub fn create_default_mem(mem_config: &MachineMemConfig, thread_num: u8) -> Resu
         None,
         mem_config.mem_size,
         f_back,
-        mem_config.dump_guest_core,
-        mem_config.mem_share,
-        false,
+        HostMemOptions {
+            dump_guest_core: mem_config.dump_guest_core,
+            is_share: mem_config.mem_share,
+            prefault: mem_config.mem_prefault,
+            ..Default::default()
+        },
     )?);
 
     if mem_config.mem_prealloc {
@@ -288,51 +314,87 @@ pub fn create_default_mem(mem_config: &MachineMemConfig, thread_num: u8) -> Resu
 ///
 /// # Arguments
 ///
-/// * `mem_config` - The config of default memory.
+/// * `mem_config` - The config of machine memory.
+/// * `zone` - The config of memory zone.
 /// * `thread_num` - The num of mem preallocv threads, typically the number of vCPUs.
-pub fn create_backend_mem(mem_config: &MemZoneConfig, thread_num: u8) -> Result<Region> {
-    let mut f_back: Option<FileBackend> = None;
+pub fn create_backend_mem(
+    mem_config: &MachineMemConfig,
+    zone: &MemZoneConfig,
+    thread_num: u8,
+) -> Result<Region> {
+    let hugetlb = mem_config.mem_hugepage_size.zip(zone.mem_path.as_ref());
+    let block = if let Some((page_size, path)) = hugetlb {
+        Arc::new(HostMemMapping::new_hugetlb(
+            zone.size,
+            Path::new(path),
+            page_size,
+            HostMemOptions {
+                dump_guest_core: zone.dump_guest_core,
+                is_share: zone.share,
+                prefault: mem_config.mem_prefault,
+                ..Default::default()
+            },
+        )?)
+    } else if zone.seal {
+        Arc::new(HostMemMapping::new_memfd(
+            zone.size,
+            true,
+            HostMemOptions {
+                dump_guest_core: zone.dump_guest_core,
+                is_share: zone.share,
+                prefault: mem_config.mem_prefault,
+                ..Default::default()
+            },
+        )?)
+    } else {
+        let mut f_back: Option<FileBackend> = None;
 
-    if mem_config.memfd {
-        let anon_mem_name = String::from("stratovirt_anon_mem");
+        if zone.memfd {
+            let anon_mem_name = String::from("stratovirt_anon_mem");
 
-        let anon_fd =
-            unsafe { libc::syscall(libc::SYS_memfd_create, anon_mem_name.as_ptr(), 0) } as RawFd;
-        if anon_fd < 0 {
-            return Err(std::io::Error::last_os_error()).with_context(|| "Failed to create memfd");
-        }
+            let anon_fd =
+                unsafe { libc::syscall(libc::SYS_memfd_create, anon_mem_name.as_ptr(), 0) }
+                    as RawFd;
+            if anon_fd < 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| "Failed to create memfd");
+            }
 
-        let anon_file = unsafe { File::from_raw_fd(anon_fd) };
-        anon_file
-            .set_len(mem_config.size)
-            .with_context(|| "Failed to set the length of anonymous file that backs memory")?;
+            let anon_file = unsafe { File::from_raw_fd(anon_fd) };
+            anon_file
+                .set_len(zone.size)
+                .with_context(|| "Failed to set the length of anonymous file that backs memory")?;
 
-        f_back = Some(FileBackend {
-            file: Arc::new(anon_file),
-            offset: 0,
-            page_size: host_page_size(),
-        });
-    } else if let Some(path) = &mem_config.mem_path {
-        f_back = Some(
-            FileBackend::new_mem(path, mem_config.size)
-                .with_context(|| "Failed to create file that backs memory")?,
-        );
-    }
-    let block = Arc::new(HostMemMapping::new(
-        GuestAddress(0),
-        None,
-        mem_config.size,
-        f_back,
-        mem_config.dump_guest_core,
-        mem_config.share,
-        false,
-    )?);
-    if mem_config.prealloc {
-        mem_prealloc(block.host_address(), mem_config.size, thread_num);
+            f_back = Some(FileBackend {
+                file: Arc::new(anon_file),
+                offset: 0,
+                page_size: host_page_size(),
+            });
+        } else if let Some(path) = &zone.mem_path {
+            f_back = Some(
+                FileBackend::new_mem(path, zone.size)
+                    .with_context(|| "Failed to create file that backs memory")?,
+            );
+        }
+        Arc::new(HostMemMapping::new(
+            GuestAddress(0),
+            None,
+            zone.size,
+            f_back,
+            HostMemOptions {
+                dump_guest_core: zone.dump_guest_core,
+                is_share: zone.share,
+                prefault: mem_config.mem_prefault,
+                ..Default::default()
+            },
+        )?)
+    };
+    if zone.prealloc {
+        mem_prealloc(block.host_address(), zone.size, thread_num);
     }
-    set_host_memory_policy(&block, mem_config)?;
+    set_host_memory_policy(&block, zone)?;
 
-    let region = Region::init_ram_region(block, mem_config.id.as_str());
+    let region = Region::init_ram_region(block, zone.id.as_str());
     Ok(region)
 }
 
@@ -381,6 +443,19 @@ pub fn set_host_memory_policy(
     Ok(())
 }
 
+/// Options of the memory mapping of `HostMemMapping`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HostMemOptions {
+    /// Dump guest memory during coredump or not.
+    pub dump_guest_core: bool,
+    /// This mapping is sharable or not.
+    pub is_share: bool,
+    /// This mapping is read only or not.
+    pub read_only: bool,
+    /// Fault in all pages of this mapping at creation time or not.
+    pub prefault: bool,
+}
+
 /// Record information of memory mapping.
 #[derive(Debug)]
 pub struct HostMemMapping {
@@ -409,17 +484,13 @@ impl HostMemMapping {
     /// * `host_addr` - Base HVA.
     /// * `size` - Size of memory that will be mapped.
     /// * `file_back` - File backend for memory.
-    /// * `dump_guest_core` - Dump guest memory during coredump or not.
-    /// * `is_share` - This mapping is sharable or not.
-    /// * `read_only` - This mapping is read only or not.
+    /// * `options` - Options of the mapping, used if `host_addr` is None.
     pub fn new(
         guest_addr: GuestAddress,
         host_addr: Option<u64>,
         size: u64,
         file_back: Option<FileBackend>,
-        dump_guest_core: bool,
-        is_share: bool,
-        read_only: bool,
+        options: HostMemOptions,
     ) -> Result<Self> {
         let host_addr = if let Some(addr) = host_addr {
             addr
@@ -429,9 +500,10 @@ impl HostMemMapping {
                 &fb.map(|f| f.file.as_ref()),
                 size,
                 fb.map_or(0, |f| f.offset),
-                read_only,
-                is_share,
-                dump_guest_core,
+                options.read_only,
+                options.is_share,
+                options.dump_guest_core,
+                options.prefault,
             )?
         };
 
@@ -442,7 +514,141 @@ impl HostMemMapping {
             },
             host_addr: host_addr as *mut u8,
             file_back,
-            is_share,
+            is_share: options.is_share,
+        })
+    }
+
+    /// Construct a new HostMemMapping backed by an anonymous memfd.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Size of memory that will be mapped.
+    /// * `sealed` - Seal the memfd or not. A sealed memfd can not be resized,
+    ///   and can not be mapped writable by others after creation.
+    /// * `options` - Options of the mapping.
+    pub fn new_memfd(size: u64, sealed: bool, options: HostMemOptions) -> Result<Self> {
+        let anon_mem_name = CString::new("stratovirt_anon_mem")?;
+        let flags = if sealed {
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING
+        } else {
+            libc::MFD_CLOEXEC
+        };
+        // SAFETY: anon_mem_name is a valid C string and the return value is checked.
+        let anon_fd = unsafe { libc::memfd_create(anon_mem_name.as_ptr(), flags) };
+        if anon_fd < 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| "Failed to create memfd");
+        }
+
+        // SAFETY: anon_fd is a valid fd owned by nobody else.
+        let anon_file = unsafe { File::from_raw_fd(anon_fd) };
+        anon_file
+            .set_len(size)
+            .with_context(|| "Failed to set the length of anonymous file that backs memory")?;
+
+        let host_addr = do_mmap(
+            &Some(&anon_file),
+            size,
+            0,
+            options.read_only,
+            options.is_share,
+            options.dump_guest_core,
+            options.prefault,
+        )?;
+        if sealed {
+            // Seal after mapping, otherwise the writable shared mapping is forbidden.
+            // SAFETY: anon_file is valid and the return value is checked.
+            let ret = unsafe { libc::fcntl(anon_file.as_raw_fd(), libc::F_ADD_SEALS, MEMFD_SEALS) };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                // SAFETY: host_addr and size are just mapped above.
+                unsafe { libc::munmap(host_addr as *mut libc::c_void, size as libc::size_t) };
+                return Err(err).with_context(|| "Failed to seal memfd");
+            }
+        }
+
+        Ok(Self {
+            address_range: AddressRange {
+                base: GuestAddress(0),
+                size,
+            },
+            host_addr: host_addr as *mut u8,
+            file_back: Some(FileBackend {
+                file: Arc::new(anon_file),
+                offset: 0,
+                page_size: host_page_size(),
+            }),
+            is_share: options.is_share,
+        })
+    }
+
+    /// Construct a new HostMemMapping backed by a temporary file on hugetlbfs.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Size of memory that will be mapped, aligned with `page_size`.
+    /// * `hugetlb_path` - Directory where hugetlbfs is mounted.
+    /// * `page_size` - Huge page size of the hugetlbfs.
+    /// * `options` - Options of the mapping.
+    ///
+    /// # Errors
+    ///
+    /// Return Error if
+    /// * the size is not aligned with huge page size.
+    /// * the page size of `hugetlb_path` mismatches `page_size`.
+    /// * there are no enough free huge pages.
+    pub fn new_hugetlb(
+        size: u64,
+        hugetlb_path: &Path,
+        page_size: HugePageSize,
+        options: HostMemOptions,
+    ) -> Result<Self> {
+        if size == 0 || size % page_size.size() != 0 {
+            bail!(
+                "Memory size 0x{:X} is not aligned with huge page size 0x{:X}",
+                size,
+                page_size.size()
+            );
+        }
+        if !hugetlb_path.is_dir() {
+            bail!(
+                "Hugetlbfs path {} is not a directory",
+                hugetlb_path.display()
+            );
+        }
+        let path = hugetlb_path
+            .to_str()
+            .with_context(|| format!("Invalid hugetlbfs path {}", hugetlb_path.display()))?;
+        let file_back = FileBackend::new_mem(path, size)
+            .with_context(|| "Failed to create file that backs memory")?;
+        if file_back.page_size != page_size.size() {
+            bail!(
+                "Page size of {} is 0x{:X}, which mismatches huge page size 0x{:X}",
+                path,
+                file_back.page_size,
+                page_size.size()
+            );
+        }
+
+        // The huge page size of the mapping follows the hugetlbfs backing file.
+        let host_addr = do_mmap(
+            &Some(file_back.file.as_ref()),
+            size,
+            0,
+            options.read_only,
+            options.is_share,
+            options.dump_guest_core,
+            options.prefault,
+        )
+        .with_context(|| format!("Failed to mmap hugetlbfs file in {}", path))?;
+
+        Ok(Self {
+            address_range: AddressRange {
+                base: GuestAddress(0),
+                size,
+            },
+            host_addr: host_addr as *mut u8,
+            file_back: Some(file_back),
+            is_share: options.is_share,
         })
     }
 
@@ -489,6 +695,7 @@ impl Drop for HostMemMapping {
 mod test {
     use super::*;
     use std::io::{Read, Seek, SeekFrom, Write};
+    use std::os::unix::fs::FileExt;
     use vmm_sys_util::tempfile::TempFile;
 
     fn identify(ram: HostMemMapping, st: u64, end: u64) {
@@ -498,8 +705,8 @@ mod test {
 
     #[test]
     fn test_ramblock_creation() {
-        let ram1 =
-            HostMemMapping::new(GuestAddress(0), None, 100, None, false, false, false).unwrap();
+        let ram1 = HostMemMapping::new(GuestAddress(0), None, 100, None, HostMemOptions::default())
+            .unwrap();
         let host_addr = ram1.host_address();
         let slice = unsafe { std::slice::from_raw_parts_mut(host_addr as *mut u8, 1) };
 
@@ -517,7 +724,17 @@ mod test {
         const BAD_ADDRESS: i32 = 14;
 
         let ram1 = Arc::new(
-            HostMemMapping::new(GuestAddress(0), None, 100, None, false, false, true).unwrap(),
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                100,
+                None,
+                HostMemOptions {
+                    read_only: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
         );
         let host_addr = ram1.host_address();
         let slice = unsafe { std::slice::from_raw_parts_mut(host_addr as *mut u8, 1) };
@@ -589,7 +806,7 @@ mod test {
     #[test]
     fn test_memory_prealloc() {
         // Mmap and prealloc with anonymous memory.
-        let host_addr = do_mmap(&None, 0x20_0000, 0, false, false, false).unwrap();
+        let host_addr = do_mmap(&None, 0x20_0000, 0, false, false, false, false).unwrap();
         // Check the thread number equals to minimum value.
         assert_eq!(max_nr_threads(1), 1);
         // The max threads limit is 16, or the number of host CPUs, it will never be 20.
@@ -607,8 +824,285 @@ mod test {
             false,
             true,
             false,
+            false,
         )
         .unwrap();
         mem_prealloc(host_addr, 0x10_0000, 2);
     }
+
+    fn resident_pages(host_addr: u64, size: u64) -> usize {
+        let page_size = host_page_size();
+        let nr_pages = size.div_ceil(page_size) as usize;
+        let mut vec = vec![0_u8; nr_pages];
+        let ret = unsafe {
+            libc::mincore(
+                host_addr as *mut libc::c_void,
+                size as libc::size_t,
+                vec.as_mut_ptr(),
+            )
+        };
+        assert_eq!(ret, 0);
+        vec.iter().filter(|v| *v & 0x1 != 0).count()
+    }
+
+    /// Touch every page of the mapping, and return the elapsed time.
+    fn first_access_latency(ram: &HostMemMapping) -> std::time::Duration {
+        let page_size = host_page_size();
+        let start = std::time::Instant::now();
+        let mut offset = 0;
+        while offset < ram.size() {
+            unsafe { std::ptr::write_volatile((ram.host_address() + offset) as *mut u8, 1) };
+            offset += page_size;
+        }
+        start.elapsed()
+    }
+
+    #[test]
+    fn test_memory_prefault() {
+        let size = 0x10_0000;
+        let ram = HostMemMapping::new(
+            GuestAddress(0),
+            None,
+            size,
+            None,
+            HostMemOptions {
+                prefault: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            resident_pages(ram.host_address(), ram.size()),
+            (size / host_page_size()) as usize
+        );
+
+        let ram = HostMemMapping::new(GuestAddress(0), None, size, None, HostMemOptions::default())
+            .unwrap();
+        assert_eq!(resident_pages(ram.host_address(), ram.size()), 0);
+    }
+
+    #[test]
+    fn test_backend_mem_prefault() {
+        let mem_config = MachineMemConfig {
+            mem_prefault: true,
+            ..Default::default()
+        };
+        let size = 0x10_0000;
+        let anon_zone = MemZoneConfig {
+            id: "mem0".to_string(),
+            size,
+            ..Default::default()
+        };
+        let memfd_zone = MemZoneConfig {
+            id: "mem1".to_string(),
+            size,
+            memfd: true,
+            ..Default::default()
+        };
+        let sealed_zone = MemZoneConfig {
+            id: "mem2".to_string(),
+            size,
+            memfd: true,
+            seal: true,
+            ..Default::default()
+        };
+        for zone in [anon_zone, memfd_zone, sealed_zone] {
+            let region = create_backend_mem(&mem_config, &zone, 1).unwrap();
+            let host_addr = region.get_host_address().unwrap();
+            assert_eq!(
+                resident_pages(host_addr, size),
+                (size / host_page_size()) as usize
+            );
+        }
+
+        let zone = MemZoneConfig {
+            id: "mem3".to_string(),
+            size,
+            ..Default::default()
+        };
+        let region = create_backend_mem(&MachineMemConfig::default(), &zone, 1).unwrap();
+        assert_eq!(resident_pages(region.get_host_address().unwrap(), size), 0);
+    }
+
+    fn memfd_seals(ram: &HostMemMapping) -> i32 {
+        let fd = ram.file_backend().unwrap().file.as_raw_fd();
+        unsafe { libc::fcntl(fd, libc::F_GET_SEALS) }
+    }
+
+    #[test]
+    fn test_memfd_mapping() {
+        let ram = HostMemMapping::new_memfd(
+            0x2000,
+            false,
+            HostMemOptions {
+                dump_guest_core: true,
+                is_share: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(ram.size(), 0x2000);
+        assert!(ram.mem_shared());
+        // Sealing is not allowed.
+        assert_eq!(memfd_seals(&ram), libc::F_SEAL_SEAL);
+        let file = ram.file_backend().unwrap().file;
+        assert!(file.set_len(0x3000).is_ok());
+    }
+
+    #[test]
+    fn test_memfd_mapping_sealed() {
+        let ram = HostMemMapping::new_memfd(
+            0x2000,
+            true,
+            HostMemOptions {
+                dump_guest_core: true,
+                is_share: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(memfd_seals(&ram), MEMFD_SEALS);
+
+        // The mapping is still writable.
+        let host_addr = ram.host_address() as *mut u8;
+        unsafe {
+            std::ptr::write_volatile(host_addr, 0x5a);
+            assert_eq!(std::ptr::read_volatile(host_addr), 0x5a);
+        }
+
+        // Resizing and new writable mapping are denied.
+        let file = ram.file_backend().unwrap().file;
+        assert!(file.set_len(0x1000).is_err());
+        assert!(file.set_len(0x3000).is_err());
+        assert!(do_mmap(&Some(file.as_ref()), 0x2000, 0, false, true, true, false).is_err());
+        assert!(do_mmap(&Some(file.as_ref()), 0x2000, 0, true, true, true, false).is_ok());
+
+        // The private mapping is not written back to the sealed memfd.
+        let ram = HostMemMapping::new_memfd(0x2000, true, HostMemOptions::default()).unwrap();
+        assert!(!ram.mem_shared());
+        let host_addr = ram.host_address() as *mut u8;
+        unsafe { std::ptr::write_volatile(host_addr, 0x5a) };
+        let mut buf = [0_u8; 1];
+        let file = ram.file_backend().unwrap().file;
+        file.read_exact_at(&mut buf, 0).unwrap();
+        assert_eq!(buf[0], 0);
+    }
+
+    /// Find the mount point of hugetlbfs with the huge page size, None if it is
+    /// not mounted or there are no enough free huge pages.
+    fn hugetlbfs_mount(page_size: HugePageSize, nr_pages: u64) -> Option<std::path::PathBuf> {
+        let free_pages = std::fs::read_to_string(format!(
+            "/sys/kernel/mm/hugepages/hugepages-{}kB/free_hugepages",
+            page_size.size() >> 10
+        ))
+        .ok()?;
+        if free_pages.trim().parse::<u64>().ok()? < nr_pages {
+            return None;
+        }
+
+        let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+        mounts
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+            .filter(|fields| fields.len() > 2 && fields[2] == "hugetlbfs")
+            .map(|fields| std::path::PathBuf::from(fields[1]))
+            .find(|path| {
+                let path = CString::new(path.to_str().unwrap()).unwrap();
+                let mut fstat: libc::statfs = unsafe { std::mem::zeroed() };
+                let ret = unsafe { libc::statfs(path.as_ptr(), &mut fstat) };
+                ret == 0 && fstat.f_bsize as u64 == page_size.size()
+            })
+    }
+
+    #[test]
+    fn test_hugetlb_mapping() {
+        // Size is not aligned with huge page size.
+        let tmp_dir = std::env::temp_dir();
+        let new_hugetlb = |size: u64, path: &Path, page_size: HugePageSize| {
+            let options = HostMemOptions {
+                dump_guest_core: true,
+                is_share: true,
+                ..Default::default()
+            };
+            HostMemMapping::new_hugetlb(size, path, page_size, options)
+        };
+        assert!(new_hugetlb(0x1000, &tmp_dir, HugePageSize::Size2M).is_err());
+        assert!(new_hugetlb(0, &tmp_dir, HugePageSize::Size2M).is_err());
+        // The directory is not on hugetlbfs.
+        assert!(new_hugetlb(0x20_0000, &tmp_dir, HugePageSize::Size2M).is_err());
+
+        for page_size in [HugePageSize::Size2M, HugePageSize::Size1G] {
+            let path = match hugetlbfs_mount(page_size, 1) {
+                Some(path) => path,
+                // Skip if huge pages are not available on host.
+                None => continue,
+            };
+            let size = page_size.size();
+            for is_share in [true, false] {
+                let ram = HostMemMapping::new_hugetlb(
+                    size,
+                    &path,
+                    page_size,
+                    HostMemOptions {
+                        is_share,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                assert_eq!(ram.size(), size);
+                assert_eq!(ram.mem_shared(), is_share);
+                assert_eq!(ram.file_backend().unwrap().page_size, size);
+                let host_addr = ram.host_address() as *mut u8;
+                unsafe {
+                    std::ptr::write_volatile(host_addr, 0x5a);
+                    assert_eq!(std::ptr::read_volatile(host_addr), 0x5a);
+                }
+            }
+
+            // Memory zones on hugetlbfs are prefaulted as well.
+            let mem_config = MachineMemConfig {
+                mem_prefault: true,
+                mem_hugepage_size: Some(page_size),
+                ..Default::default()
+            };
+            let zone = MemZoneConfig {
+                id: "mem0".to_string(),
+                size,
+                mem_path: Some(path.to_str().unwrap().to_string()),
+                ..Default::default()
+            };
+            let region = create_backend_mem(&mem_config, &zone, 1).unwrap();
+            assert_eq!(
+                resident_pages(region.get_host_address().unwrap(), size),
+                (size / host_page_size()) as usize
+            );
+        }
+    }
+
+    // Benchmark of the first access to 1 GiB memory, run it with `--ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_memory_prefault() {
+        let size = 1 << 30;
+        for prefault in [false, true] {
+            let start = std::time::Instant::now();
+            let ram = HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                size,
+                None,
+                HostMemOptions {
+                    prefault,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let map_time = start.elapsed();
+            let access_time = first_access_latency(&ram);
+            println!(
+                "prefault {}: mmap {:?}, first access {:?}",
+                prefault, map_time, access_time
+            );
+        }
+    }
 }
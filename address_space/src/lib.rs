@@ -18,7 +18,7 @@
 //! ```rust
 //! use std::sync::{Arc, Mutex};
 //! extern crate address_space;
-//! use address_space::{AddressSpace, Region, GuestAddress, HostMemMapping, RegionOps, FileBackend};
+//! use address_space::{AddressSpace, Region, GuestAddress, HostMemMapping, HostMemOptions, RegionOps, FileBackend};
 //!
 //! struct DummyDevice;
 //! impl DummyDevice {
@@ -42,9 +42,7 @@
 //!         None,
 //!         0x1000,
 //!         None,
-//!         false,
-//!         false,
-//!         false,
+//!         HostMemOptions::default(),
 //!     ).unwrap());
 //!     let ram_region = Region::init_ram_region(mem_mapping.clone(), "ram");
 //!     ram_region.set_priority(10);
@@ -79,17 +77,21 @@
 
 mod address;
 mod address_space;
+mod allocator;
 pub mod error;
 mod host_mmap;
 mod listener;
 mod region;
 mod state;
 
-pub use crate::address_space::{AddressSpace, RegionCache};
+pub use crate::address_space::{AddressSpace, RegionCache, RegionDescriptor};
 pub use address::{AddressRange, GuestAddress};
+pub use allocator::GuestMemoryAllocator;
 pub use anyhow::Result;
 pub use error::AddressSpaceError;
-pub use host_mmap::{create_backend_mem, create_default_mem, FileBackend, HostMemMapping};
+pub use host_mmap::{
+    create_backend_mem, create_default_mem, FileBackend, HostMemMapping, HostMemOptions,
+};
 #[cfg(target_arch = "x86_64")]
 pub use listener::KvmIoListener;
 pub use listener::KvmMemoryListener;
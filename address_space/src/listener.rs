@@ -621,7 +621,7 @@ mod test {
     use vmm_sys_util::eventfd::EventFd;
 
     use super::*;
-    use crate::{GuestAddress, HostMemMapping, Region, RegionIoEventFd};
+    use crate::{GuestAddress, HostMemMapping, HostMemOptions, Region, RegionIoEventFd};
 
     fn generate_region_ioeventfd<T: Into<u64>>(addr: u64, datamatch: T) -> RegionIoEventFd {
         let data = datamatch.into();
@@ -635,7 +635,14 @@ mod test {
 
     fn create_ram_range(addr: u64, size: u64, offset_in_region: u64) -> FlatRange {
         let mem_mapping = Arc::new(
-            HostMemMapping::new(GuestAddress(addr), None, size, None, false, false, false).unwrap(),
+            HostMemMapping::new(
+                GuestAddress(addr),
+                None,
+                size,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
         );
         FlatRange {
             addr_range: AddressRange::new(
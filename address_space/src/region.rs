@@ -75,6 +75,9 @@ pub struct Region {
     alias: Option<Arc<Region>>,
     /// Offset in parent Alias-type region.
     alias_offset: u64,
+    /// Physical memory region which IOVA is translated to, only for IOMMU
+    /// Container-type region.
+    iommu_target: Option<Arc<Region>>,
 }
 
 impl fmt::Debug for Region {
@@ -261,6 +264,7 @@ impl Region {
             max_access_size: None,
             alias: None,
             alias_offset: 0_u64,
+            iommu_target: None,
         }
     }
 
@@ -365,6 +369,21 @@ impl Region {
         region
     }
 
+    /// Initialize IOMMU container region, whose sub-regions are aliases that map
+    /// IOVA ranges to the physical memory region `target`.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Size of IOVA space.
+    /// * `target` - Physical memory region which IOVA is translated to.
+    /// * `name` - Region name.
+    pub fn init_iommu_region(size: u64, target: Arc<Region>, name: &str) -> Region {
+        let mut region =
+            Region::init_region_internal(name, size, RegionType::Container, None, None);
+        region.iommu_target = Some(target);
+        region
+    }
+
     /// Get the type of this region.
     pub fn region_type(&self) -> RegionType {
         self.region_type
@@ -504,7 +523,7 @@ impl Region {
 
     /// Return all sub-regions of this Region, the returned vector is not empty,
     /// iff this region is a container.
-    pub(crate) fn subregions(&self) -> Vec<Region> {
+    pub fn subregions(&self) -> Vec<Region> {
         self.subregions.read().unwrap().clone()
     }
 
@@ -843,6 +862,139 @@ impl Region {
         Ok(())
     }
 
+    /// Get the physical memory region of IOMMU container region.
+    fn iommu_target(&self) -> Result<Arc<Region>> {
+        if self.region_type() != RegionType::Container {
+            return Err(anyhow!(AddressSpaceError::RegionType(self.region_type())));
+        }
+        self.iommu_target
+            .clone()
+            .with_context(|| format!("Region {} is not an IOMMU region", self.name))
+    }
+
+    /// Add the mapping from IOVA range `[iova, iova + size)` to the physical
+    /// range starting from `target`, without updating topology.
+    fn add_iommu_mapping(
+        &self,
+        phys: Arc<Region>,
+        iova: u64,
+        size: u64,
+        target: u64,
+    ) -> Result<()> {
+        let name = format!("{}-iova-0x{:X}", self.name, iova);
+        let mapping = Region::init_alias_region(phys, target, size, &name);
+        self.add_subregion_not_update(mapping, iova)
+    }
+
+    /// Remove `[iova, iova + size)` from the IOVA mappings overlapping with it,
+    /// and return whether any mapping is changed. The parts of a mapping out of
+    /// the range are kept.
+    fn evict_iommu_mappings(&self, iova: u64, size: u64) -> Result<bool> {
+        let range = AddressRange::new(GuestAddress(iova), size);
+        let mut sub_regions = self.subregions.write().unwrap();
+        let mut evicted = Vec::new();
+        sub_regions.retain(|sub_r| {
+            let sub_range = AddressRange::new(sub_r.offset(), sub_r.size());
+            if sub_range.find_intersection(range).is_some() {
+                evicted.push(sub_r.clone());
+                return false;
+            }
+            true
+        });
+        drop(sub_regions);
+
+        let end = iova.saturating_add(size);
+        for sub_r in evicted.iter() {
+            sub_r.del_belonged_address_space();
+
+            let phys = sub_r.alias.clone().unwrap();
+            let sub_start = sub_r.offset().raw_value();
+            let sub_end = sub_start + sub_r.size();
+            if sub_start < iova {
+                self.add_iommu_mapping(
+                    phys.clone(),
+                    sub_start,
+                    iova - sub_start,
+                    sub_r.alias_offset,
+                )?;
+            }
+            if sub_end > end {
+                self.add_iommu_mapping(
+                    phys,
+                    end,
+                    sub_end - end,
+                    sub_r.alias_offset + (end - sub_start),
+                )?;
+            }
+        }
+        Ok(!evicted.is_empty())
+    }
+
+    /// Update topology of the address space which this region belongs to.
+    fn update_space_topology(&self) -> Result<()> {
+        if let Some(space) = self.space.read().unwrap().upgrade() {
+            space
+                .update_topology()
+                .with_context(|| "Failed to update topology for address_space")?;
+        }
+        Ok(())
+    }
+
+    /// Map IOVA range `[iova, iova + size)` of this IOMMU region to the physical
+    /// range starting from `target`, the prior mappings are evicted from this
+    /// IOVA range.
+    ///
+    /// # Arguments
+    ///
+    /// * `iova` - Start IOVA of the mapping.
+    /// * `size` - Size of the mapping.
+    /// * `target` - Start address in the physical memory region.
+    ///
+    /// # Errors
+    ///
+    /// Return Error if
+    /// * This region is not an IOMMU region.
+    /// * The IOVA range or physical range exceeds the corresponding region.
+    /// * Failed to generate flat view.
+    pub fn iommu_remap(&self, iova: u64, size: u64, target: GuestAddress) -> Result<()> {
+        let phys = self.iommu_target()?;
+        self.check_valid_offset(iova, size)
+            .with_context(|| format!("Invalid IOVA range 0x{:X}, size 0x{:X}", iova, size))?;
+        phys.check_valid_offset(target.raw_value(), size)
+            .with_context(|| {
+                format!(
+                    "Invalid IOMMU target 0x{:X}, size 0x{:X}",
+                    target.raw_value(),
+                    size
+                )
+            })?;
+
+        self.evict_iommu_mappings(iova, size)?;
+        self.add_iommu_mapping(phys, iova, size, target.raw_value())?;
+        self.update_space_topology()
+    }
+
+    /// Unmap IOVA range `[iova, iova + size)` of this IOMMU region, the parts of
+    /// the mappings out of this range are kept. It is a no-op if there is no
+    /// mapping in this range.
+    ///
+    /// # Arguments
+    ///
+    /// * `iova` - Start IOVA of the range.
+    /// * `size` - Size of the range.
+    ///
+    /// # Errors
+    ///
+    /// Return Error if this region is not an IOMMU region, or failed to
+    /// generate flat view.
+    pub fn iommu_unmap(&self, iova: u64, size: u64) -> Result<()> {
+        self.iommu_target()?;
+        if self.evict_iommu_mappings(iova, size)? {
+            self.update_space_topology()?;
+        }
+        Ok(())
+    }
+
     /// Recursive function to render region, terminate if this region is not a container.
     ///
     /// # Arguments
@@ -1093,6 +1245,7 @@ mod test {
     use vmm_sys_util::tempfile::TempFile;
 
     use super::*;
+    use crate::HostMemOptions;
 
     #[derive(Default)]
     struct TestDevice {
@@ -1126,7 +1279,8 @@ mod test {
     #[test]
     fn test_ram_region() {
         let mem_mapping = Arc::new(
-            HostMemMapping::new(GuestAddress(0), None, 1024, None, false, false, false).unwrap(),
+            HostMemMapping::new(GuestAddress(0), None, 1024, None, HostMemOptions::default())
+                .unwrap(),
         );
         let ram_region = Region::init_ram_region(mem_mapping.clone(), "mem_mapping");
         let data: [u8; 10] = [10; 10];
@@ -1162,7 +1316,8 @@ mod test {
         // the target guest address is 0~1024 (1024 not included)
         let rgn_start = GuestAddress(0);
         let host_mmap = Arc::new(
-            HostMemMapping::new(GuestAddress(0), None, 1024, None, false, false, false).unwrap(),
+            HostMemMapping::new(GuestAddress(0), None, 1024, None, HostMemOptions::default())
+                .unwrap(),
         );
         let ram_region = Region::init_ram_region(host_mmap, "mem_mapping");
 
@@ -1330,6 +1485,115 @@ mod test {
         assert_eq!(container.subregions.read().unwrap().len(), 0);
     }
 
+    #[test]
+    fn test_iommu_remap_unmap() {
+        let sys_root = Region::init_container_region(0x4000, "sys_root");
+        let sys_space = AddressSpace::new(sys_root.clone(), "sys_space").unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                0x4000,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        sys_root
+            .add_subregion(Region::init_ram_region(ram, "ram"), 0)
+            .unwrap();
+        sys_space
+            .write_object(&0x1234_u64, GuestAddress(0x2000))
+            .unwrap();
+        sys_space
+            .write_object(&0x5678_u64, GuestAddress(0x3000))
+            .unwrap();
+
+        let iommu = Region::init_iommu_region(0x10_0000, Arc::new(sys_root), "iommu");
+        let iommu_space = AddressSpace::new(iommu.clone(), "iommu_space").unwrap();
+
+        // Remap IOVA to physical range.
+        iommu
+            .iommu_remap(0x8000, 0x1000, GuestAddress(0x2000))
+            .unwrap();
+        assert_eq!(
+            iommu_space
+                .read_object::<u64>(GuestAddress(0x8000))
+                .unwrap(),
+            0x1234
+        );
+
+        // The prior mapping of the same IOVA range is evicted.
+        iommu
+            .iommu_remap(0x8000, 0x1000, GuestAddress(0x3000))
+            .unwrap();
+        assert_eq!(iommu.subregions().len(), 1);
+        assert_eq!(
+            iommu_space
+                .read_object::<u64>(GuestAddress(0x8000))
+                .unwrap(),
+            0x5678
+        );
+
+        // Physical range or IOVA range out of bounds.
+        assert!(iommu
+            .iommu_remap(0x9000, 0x2000, GuestAddress(0x3000))
+            .is_err());
+        assert!(iommu
+            .iommu_remap(0x10_0000, 0x1000, GuestAddress(0))
+            .is_err());
+
+        // Unmap the middle of a mapping, the parts on both sides are kept.
+        iommu
+            .iommu_remap(0x8000, 0x3000, GuestAddress(0x1000))
+            .unwrap();
+        iommu.iommu_unmap(0x9000, 0x1000).unwrap();
+        assert_eq!(iommu.subregions().len(), 2);
+        assert!(iommu_space
+            .read_object::<u64>(GuestAddress(0x9000))
+            .is_err());
+        assert_eq!(
+            iommu_space
+                .read_object::<u64>(GuestAddress(0xA000))
+                .unwrap(),
+            0x5678
+        );
+        // Remap the head of the left part, the rest of it is kept.
+        iommu
+            .iommu_remap(0x8000, 0x800, GuestAddress(0x3000))
+            .unwrap();
+        assert_eq!(iommu.subregions().len(), 3);
+        assert_eq!(
+            iommu_space
+                .read_object::<u64>(GuestAddress(0x8000))
+                .unwrap(),
+            0x5678
+        );
+        sys_space
+            .write_object(&0x9abc_u64, GuestAddress(0x1800))
+            .unwrap();
+        assert_eq!(
+            iommu_space
+                .read_object::<u64>(GuestAddress(0x8800))
+                .unwrap(),
+            0x9abc
+        );
+        iommu.iommu_unmap(0x9000, 0x2000).unwrap();
+
+        // Unmap the IOVA range, and unmap it again is a no-op.
+        iommu.iommu_unmap(0x8000, 0x1000).unwrap();
+        assert!(iommu.subregions().is_empty());
+        assert!(iommu_space
+            .read_object::<u64>(GuestAddress(0x8000))
+            .is_err());
+        assert!(iommu.iommu_unmap(0x8000, 0x1000).is_ok());
+
+        // Not an IOMMU region.
+        let container = Region::init_container_region(0x1000, "container");
+        assert!(container.iommu_remap(0, 0x1000, GuestAddress(0)).is_err());
+        assert!(container.iommu_unmap(0, 0x1000).is_err());
+    }
+
     #[test]
     fn test_generate_flatview() {
         let default_ops = RegionOps {
@@ -24,7 +24,7 @@ use migration_derive::{ByteCode, Desc};
 use util::byte_code::ByteCode;
 use util::unix::host_page_size;
 
-use crate::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region};
+use crate::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, HostMemOptions, Region};
 
 const MIGRATION_HEADER_LENGTH: usize = 4096;
 
@@ -120,9 +120,7 @@ impl MigrationHook for AddressSpace {
                     None,
                     ram_state.size,
                     Some(file_backend),
-                    false,
-                    false,
-                    false,
+                    HostMemOptions::default(),
                 )
                 .map_err(|e| MigrationError::RestoreVmMemoryErr(e.to_string()))?,
             );
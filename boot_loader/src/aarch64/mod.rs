@@ -156,6 +156,14 @@ pub fn load_linux(
     config: &AArch64BootLoaderConfig,
     sys_mem: &Arc<AddressSpace>,
     fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
+) -> std::result::Result<AArch64BootLoader, BootLoaderError> {
+    load_boot_source(config, sys_mem, fwcfg).map_err(BootLoaderError::from_anyhow)
+}
+
+fn load_boot_source(
+    config: &AArch64BootLoaderConfig,
+    sys_mem: &Arc<AddressSpace>,
+    fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
 ) -> Result<AArch64BootLoader> {
     // The memory layout is as follow:
     // 1. dtb address: memory start
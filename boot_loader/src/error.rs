@@ -46,6 +46,8 @@ pub enum BootLoaderError {
     BootLoaderOpenInitrd,
     #[error("Configure cpu number({0}) above supported max cpu numbers(254)")]
     MaxCpus(u8),
+    #[error("Configure cpu number(0) below supported min cpu numbers(1)")]
+    ZeroCpus,
     #[error("Invalid bzImage kernel file")]
     #[cfg(target_arch = "x86_64")]
     InvalidBzImage,
@@ -55,4 +57,29 @@ pub enum BootLoaderError {
     #[error("ELF-format kernel is not supported")]
     #[cfg(target_arch = "x86_64")]
     ElfKernel,
+    #[error("Failed to load linux: No FwCfg provided")]
+    FwCfgNotProvided,
+    #[error("Invalid E820 range: end 0x{1:X} is below start 0x{0:X}")]
+    #[cfg(target_arch = "x86_64")]
+    E820RangeUnderflow(u64, u64),
+    #[error("Setup data node [0x{0:X}, 0x{1:X}) exceeds the setup data area end 0x{2:X}")]
+    #[cfg(target_arch = "x86_64")]
+    SetupDataOverflow(u64, u64, u64),
+    #[error("Type of loader is not set in the setup header of kernel")]
+    #[cfg(target_arch = "x86_64")]
+    LoaderTypeUnset,
+    #[error("Kernel image [0x{0:X}, 0x{1:X}) overlaps with initrd image [0x{2:X}, 0x{3:X})")]
+    LayoutOverlap(u64, u64, u64, u64),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl BootLoaderError {
+    /// Convert the error used inside this crate, keep the specific variant if there is one.
+    pub(crate) fn from_anyhow(err: anyhow::Error) -> Self {
+        match err.downcast::<BootLoaderError>() {
+            Ok(e) => e,
+            Err(e) => BootLoaderError::Other(e),
+        }
+    }
 }
@@ -52,6 +52,10 @@
 //!         lapic_addr: 0xFEE0_0000,
 //!         prot64_mode: true,
 //!         ident_tss_range: None,
+//!         five_level_paging: false,
+//!         reserve_pci_hole: false,
+//!         reserve_kernel_init: false,
+//!         auto_serial_console: false,
 //!     };
 //!
 //!     let layout = load_linux(&bootloader_config, &guest_mem, None).unwrap();
@@ -88,9 +92,22 @@ pub use aarch64::AArch64BootLoader as BootLoader;
 pub use aarch64::AArch64BootLoaderConfig as BootLoaderConfig;
 pub use error::BootLoaderError;
 
+/// Fetcher of kernel image, such as the one downloading kernel by TFTP or
+/// HTTP, which supplies the kernel instead of the kernel path of config.
+/// Only x86_64 supports to load the fetched kernel now.
+#[cfg(target_arch = "x86_64")]
+pub trait KernelFetcher {
+    /// Fetch the whole kernel image.
+    fn fetch(&self) -> anyhow::Result<Vec<u8>>;
+}
+
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::build_cmdline;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::load_linux;
 #[cfg(target_arch = "x86_64")]
+pub use x86_64::load_linux_with_fetcher;
+#[cfg(target_arch = "x86_64")]
 pub use x86_64::X86BootLoader as BootLoader;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::X86BootLoaderConfig as BootLoaderConfig;
@@ -12,15 +12,15 @@
 
 use std::sync::Arc;
 
-use address_space::AddressSpace;
+use address_space::{AddressSpace, GuestAddress};
 use util::byte_code::ByteCode;
 
 use super::{
-    X86BootLoaderConfig, EBDA_START, MB_BIOS_BEGIN, REAL_MODE_IVT_BEGIN, VGA_RAM_BEGIN,
-    VMLINUX_RAM_START,
+    X86BootLoaderConfig, EBDA_START, MB_BIOS_BEGIN, REAL_MODE_IVT_BEGIN, SETUP_DATA_END,
+    SETUP_DATA_START, VGA_RAM_BEGIN, VMLINUX_RAM_START,
 };
 use crate::error::BootLoaderError;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 
 pub const E820_RAM: u32 = 1;
 pub const E820_RESERVED: u32 = 2;
@@ -28,6 +28,16 @@ pub const BOOT_VERSION: u16 = 0x0200;
 pub const BOOT_FLAG: u16 = 0xAA55;
 pub const HDRS: u32 = 0x5372_6448;
 pub const UNDEFINED_ID: u8 = 0xFF;
+// Types of the setup_data node.
+pub const SETUP_E820_EXT: u32 = 1;
+pub const SETUP_EFI: u32 = 4;
+pub const SETUP_RNG_SEED: u32 = 9;
+
+/// Get the size of range `[start, end)`, fail if `end` is below `start`.
+fn e820_range_size(start: u64, end: u64) -> Result<u64> {
+    end.checked_sub(start)
+        .ok_or_else(|| anyhow!(BootLoaderError::E820RangeUnderflow(start, end)))
+}
 // Loader type ID: OVMF UEFI virtualization stack.
 pub const UEFI_OVMF_ID: u8 = 0xB;
 
@@ -172,6 +182,15 @@ impl RealModeKernelHeader {
         Ok(())
     }
 
+    /// Check the type of loader is set, the zero value read from image means
+    /// the header is not filled by loader.
+    pub fn check_loader_type(&self) -> Result<()> {
+        if self.type_of_loader == 0 {
+            return Err(anyhow!(BootLoaderError::LoaderTypeUnset));
+        }
+        Ok(())
+    }
+
     pub fn set_cmdline(&mut self, cmdline_addr: u32, cmdline_size: u32) {
         self.cmdline_ptr = cmdline_addr;
         self.cmdline_size = cmdline_size;
@@ -181,6 +200,47 @@ impl RealModeKernelHeader {
         self.ramdisk_image = addr;
         self.ramdisk_size = size;
     }
+
+    /// Get the address and size of ramdisk.
+    pub fn ramdisk(&self) -> (u32, u32) {
+        (self.ramdisk_image, self.ramdisk_size)
+    }
+
+    /// Get the max address of initrd advertised by kernel, which is only
+    /// provided since boot protocol 2.03.
+    pub fn initrd_addr_max(&self) -> Option<u64> {
+        if self.version < 0x203 || self.initrd_addr_max == 0 {
+            return None;
+        }
+        Some(self.initrd_addr_max as u64)
+    }
+
+    /// Get the amount of linear contiguous memory starting at the kernel
+    /// runtime start address that kernel needs to initialize.
+    pub fn init_size(&self) -> u32 {
+        self.init_size
+    }
+
+    /// Get the preferred load address of relocatable kernel.
+    pub fn pref_address(&self) -> u64 {
+        self.pref_address
+    }
+
+    /// Get the alignment unit required by kernel if it is relocatable.
+    pub fn kernel_alignment(&self) -> u32 {
+        self.kernel_alignment
+    }
+
+    /// Get the flags of extended boot protocol, such as 64-bit entry.
+    pub fn xloadflags(&self) -> u16 {
+        self.xloadflags
+    }
+
+    /// Whether kernel is relocatable to any address aligned to
+    /// `kernel_alignment`.
+    pub fn relocatable_kernel(&self) -> bool {
+        self.relocatable_kernel != 0
+    }
 }
 
 // E820内存映射表（E820 Memory Map）是一种由BIOS或UEFI固件提供的数据结构，用于描述系统中可用的内存区域。它提供了有关内存地址范围、大小和类型（如RAM、保留、ACPI等）的信息。
@@ -206,10 +266,29 @@ impl E820Entry {
     pub(crate) fn new(addr: u64, size: u64, type_: u32) -> E820Entry {
         E820Entry { addr, size, type_ }
     }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(crate) fn is_ram(&self) -> bool {
+        self.type_ == E820_RAM
+    }
 }
 
 impl ByteCode for E820Entry {}
 
+/// Header of the node in setup_data linked list, followed by `len` bytes data.
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SetupDataHeader {
+    next: u64,
+    type_: u32,
+    len: u32,
+}
+
+impl ByteCode for SetupDataHeader {}
+
 
 // BootParams 结构体是引导参数的主要结构。它包含了引导过程中所需的各种信息，如屏幕信息、APM BIOS信息、硬盘信息、E820内存映射表等。
 // 其中，kernel_header 字段是一个 RealModeKernelHeader 结构体，用于描述内核的头部信息。
@@ -336,6 +415,12 @@ impl BootParams {
         }
     }
 
+    /// Check the fields set by loader are kept in the setup header before
+    /// handing off to kernel.
+    pub fn check_loader_fields(&self) -> Result<()> {
+        self.kernel_header.check_loader_type()
+    }
+
     pub fn add_e820_entry(&mut self, addr: u64, size: u64, type_: u32) {
         self.e820_table[self.e820_entries as usize] = E820Entry::new(addr, size, type_);
         self.e820_entries += 1;
@@ -345,7 +430,7 @@ impl BootParams {
         &mut self,
         config: &X86BootLoaderConfig,
         sys_mem: &Arc<AddressSpace>,
-    ) {
+    ) -> Result<()> {
         // e820 条目类型
         // Usable：已经被映射到物理内存的物理地址。
         // Reserved：这些区间是没有被映射到任何地方，不能当作RAM来使用，但是kernel可以决定将这些区间映射到其他地方，比如PCI设备。通过检查/proc/iomem这个虚拟文件，就可以知道这些reserved的空间，是如何进一步分配给不同的设备来使用了。
@@ -363,7 +448,7 @@ impl BootParams {
 
         self.add_e820_entry(
             REAL_MODE_IVT_BEGIN,
-            EBDA_START - REAL_MODE_IVT_BEGIN,
+            e820_range_size(REAL_MODE_IVT_BEGIN, EBDA_START)?,
             E820_RAM,
         ); // 为 IVT（Interrupt Vector Table）设置了一个 E820 内存映射条目，类型为 RAM。
 
@@ -382,7 +467,11 @@ impl BootParams {
         // 4. 临时存储区域：在系统引导过程中，EBDA可以用作临时存储区域，存储一些暂时性的数据或临时变量。
         //
         // EBDA的具体大小和位置可以通过读取BIOS数据区域（BIOS Data Area）的相关字段获取。在实模式下，软件可以通过访问EBDA来获取和修改其中存储的数据，以满足特定的系统需求和配置。然而，随着计算机体系结构的发展，随着进入保护模式和64位模式，EBDA的重要性和使用情况逐渐减少，由更高级的机制和数据结构取而代之。
-        self.add_e820_entry(EBDA_START, VGA_RAM_BEGIN - EBDA_START, E820_RESERVED);
+        self.add_e820_entry(
+            EBDA_START,
+            e820_range_size(EBDA_START, VGA_RAM_BEGIN)?,
+            E820_RESERVED,
+        );
         // 为 MB_BIOS_BEGIN 设置了一个 E820 内存映射条目，类型为保留。
         self.add_e820_entry(MB_BIOS_BEGIN, 0, E820_RESERVED);
 
@@ -399,19 +488,149 @@ impl BootParams {
         //
         // 这个值将用于设置 e820_table 中的相应内存映射表条目，以标识实模式下 32 位布局间隙的起始和结束地址，并将其类型设置为 RAM 类型。这样，操作系统内核在加载和管理内存时可以正确识别和处理这段地址空间。
         if mem_end < layout_32bit_gap_end {
-            self.add_e820_entry(high_memory_start, mem_end - high_memory_start, E820_RAM);
+            self.add_e820_entry(
+                high_memory_start,
+                e820_range_size(high_memory_start, mem_end)?,
+                E820_RAM,
+            );
         } else {
             self.add_e820_entry(
                 high_memory_start,
-                config.gap_range.0 - high_memory_start,
+                e820_range_size(high_memory_start, config.gap_range.0)?,
                 E820_RAM,
             );
             self.add_e820_entry(
                 layout_32bit_gap_end,
-                mem_end - layout_32bit_gap_end,
+                e820_range_size(layout_32bit_gap_end, mem_end)?,
                 E820_RAM,
             );
         }
+        if config.reserve_pci_hole {
+            self.add_e820_entry(config.gap_range.0, config.gap_range.1, E820_RESERVED);
+            // Keep the entries sorted by address.
+            let entries = self.e820_entries as usize;
+            self.e820_table[..entries].sort_by_key(|entry| entry.addr);
+        }
+        let init_size = self.kernel_header.init_size() as u64;
+        if config.reserve_kernel_init && init_size != 0 {
+            let load_addr = self.kernel_header.code32_start as u64;
+            self.reserve_e820_range(load_addr, init_size)
+                .with_context(|| "Failed to reserve init region of kernel")?;
+        }
+        Ok(())
+    }
+
+    /// Carve the range out of the RAM entry which contains it, and mark it as
+    /// reserved. The entries are kept sorted by address.
+    fn reserve_e820_range(&mut self, addr: u64, size: u64) -> Result<()> {
+        let end = addr
+            .checked_add(size)
+            .with_context(|| format!("Range 0x{:X} + 0x{:X} overflows", addr, size))?;
+        let entries = self.e820_entries as usize;
+        let index = self.e820_table[..entries]
+            .iter()
+            .position(|entry| {
+                let (start, size, type_) = (entry.addr, entry.size, entry.type_);
+                type_ == E820_RAM && addr >= start && end - start <= size
+            })
+            .with_context(|| format!("Range [0x{:X}, 0x{:X}) is not in RAM", addr, end))?;
+
+        let (ram_start, ram_end) = {
+            let entry = &self.e820_table[index];
+            (entry.addr, entry.addr + entry.size)
+        };
+        self.e820_table[index] = E820Entry::new(addr, size, E820_RESERVED);
+        if addr > ram_start {
+            self.add_e820_entry(ram_start, addr - ram_start, E820_RAM);
+        }
+        if end < ram_end {
+            self.add_e820_entry(end, ram_end - end, E820_RAM);
+        }
+        let entries = self.e820_entries as usize;
+        self.e820_table[..entries].sort_by_key(|entry| entry.addr);
+        Ok(())
+    }
+
+    /// Allocate a node in the setup data area, and prepend it to the
+    /// setup_data linked list. The nodes are allocated upwards from
+    /// `SETUP_DATA_START` and aligned to 8 bytes. The boot params must be
+    /// written to guest memory after all the nodes are pushed.
+    ///
+    /// # Arguments
+    ///
+    /// * `type_` - Type of the node, such as `SETUP_E820_EXT` or `SETUP_EFI`.
+    /// * `payload` - Data of the node.
+    /// * `sys_mem` - Guest memory.
+    ///
+    /// # Returns
+    ///
+    /// The guest address of the node.
+    fn push_setup_data(
+        &mut self,
+        type_: u32,
+        payload: &[u8],
+        sys_mem: &Arc<AddressSpace>,
+    ) -> Result<u64> {
+        let header_size = std::mem::size_of::<SetupDataHeader>() as u64;
+        let mut addr = SETUP_DATA_START;
+        let mut next = self.kernel_header.setup_data;
+        while next != 0 {
+            let header = sys_mem
+                .read_object::<SetupDataHeader>(GuestAddress(next))
+                .with_context(|| format!("Failed to read setup data node at 0x{:X}", next))?;
+            addr = std::cmp::max(addr, (next + header_size + header.len as u64 + 7) & !7);
+            next = header.next;
+        }
+
+        let end = addr + header_size + payload.len() as u64;
+        if end > SETUP_DATA_END {
+            return Err(anyhow!(BootLoaderError::SetupDataOverflow(
+                addr,
+                end,
+                SETUP_DATA_END
+            )));
+        }
+        let header = SetupDataHeader {
+            next: self.kernel_header.setup_data,
+            type_,
+            len: payload.len() as u32,
+        };
+        sys_mem.write_object(&header, GuestAddress(addr))?;
+        sys_mem.write(
+            &mut std::io::Cursor::new(payload),
+            GuestAddress(addr + header_size),
+            payload.len() as u64,
+        )?;
+        self.kernel_header.setup_data = addr;
+        Ok(addr)
+    }
+
+    /// Add a `SETUP_EFI` node to the setup_data linked list, which passes the
+    /// EFI memory map and system table info to kernel.
+    ///
+    /// # Arguments
+    ///
+    /// * `efi_info` - Data of the EFI setup node.
+    /// * `sys_mem` - Guest memory.
+    pub fn add_efi_setup_data(
+        &mut self,
+        efi_info: &[u8],
+        sys_mem: &Arc<AddressSpace>,
+    ) -> Result<()> {
+        self.push_setup_data(SETUP_EFI, efi_info, sys_mem)
+            .with_context(|| "Failed to add EFI setup data")?;
+        Ok(())
+    }
+
+    /// Check whether the guest address `addr` lies in a RAM range of the
+    /// populated E820 table.
+    pub fn is_ram(&self, addr: u64) -> bool {
+        self.e820_table[..self.e820_entries as usize]
+            .iter()
+            .any(|entry| {
+                let (start, size, type_) = (entry.addr, entry.size, entry.type_);
+                type_ == E820_RAM && addr >= start && addr - start < size
+            })
     }
 }
 
@@ -420,9 +639,12 @@ mod test {
     use std::path::PathBuf;
     use std::sync::Arc;
 
-    use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
+    use address_space::{AddressSpace, GuestAddress, HostMemMapping, HostMemOptions, Region};
 
-    use super::super::X86BootLoaderConfig;
+    use super::super::{
+        initrd_addr_limit, initrd_load_addr, X86BootLoaderConfig, BOOT_HDR_START, INITRD_ADDR_MAX,
+        INITRD_ALIGN,
+    };
     use super::*;
 
     #[test]
@@ -435,9 +657,7 @@ mod test {
                 None,
                 0x1000_0000,
                 None,
-                false,
-                false,
-                false,
+                HostMemOptions::default(),
             )
             .unwrap(),
         );
@@ -455,11 +675,15 @@ mod test {
             lapic_addr: 0xFEE0_0000,
             prot64_mode: false,
             ident_tss_range: None,
+            five_level_paging: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: false,
+            auto_serial_console: false,
         };
 
         let boot_hdr = RealModeKernelHeader::default();
         let mut boot_params = BootParams::new(boot_hdr);
-        boot_params.setup_e820_entries(&config, &space);
+        boot_params.setup_e820_entries(&config, &space).unwrap();
         assert_eq!(boot_params.e820_entries, 4);
 
         assert!(boot_params.e820_table[0].addr == 0);
@@ -479,5 +703,359 @@ mod test {
         assert!(boot_params.e820_table[3].addr == 0x0010_0000);
         assert!(boot_params.e820_table[3].size == 0x0ff0_0000);
         assert!(boot_params.e820_table[3].type_ == 1);
+
+        // Low ram and kernel ram.
+        assert!(boot_params.is_ram(0x1000));
+        assert!(boot_params.is_ram(0x0009_FBFF));
+        assert!(boot_params.is_ram(0x0010_0000));
+        assert!(boot_params.is_ram(0x0FFF_FFFF));
+        // EBDA and BIOS are reserved.
+        assert!(!boot_params.is_ram(0x0009_FC00));
+        assert!(!boot_params.is_ram(0x0009_FFFF));
+        assert!(!boot_params.is_ram(0x000F_0000));
+        // Beyond the end of ram.
+        assert!(!boot_params.is_ram(0x1000_0000));
+    }
+
+    #[test]
+    fn test_initrd_addr_max() {
+        let mut boot_hdr = RealModeKernelHeader::new();
+        assert_eq!(boot_hdr.initrd_addr_max(), None);
+        assert_eq!(initrd_addr_limit(&boot_hdr, u64::MAX), INITRD_ADDR_MAX);
+
+        // Kernel advertises a lower max address than the default.
+        boot_hdr.version = 0x20f;
+        boot_hdr.initrd_addr_max = 0x1fff_ffff;
+        assert_eq!(boot_hdr.initrd_addr_max(), Some(0x1fff_ffff));
+        let addr_max = initrd_addr_limit(&boot_hdr, u64::MAX);
+        assert_eq!(addr_max, 0x1fff_ffff);
+        let addr = initrd_load_addr(addr_max, 0x12_3456, INITRD_ALIGN).unwrap();
+        assert!(addr + 0x12_3456 <= 0x1fff_ffff);
+        // Guest memory ends below the max address.
+        assert_eq!(initrd_addr_limit(&boot_hdr, 0x1000_0000), 0x1000_0000);
+
+        // The higher one is capped by the default.
+        boot_hdr.initrd_addr_max = 0x7fff_ffff;
+        assert_eq!(initrd_addr_limit(&boot_hdr, u64::MAX), INITRD_ADDR_MAX);
+
+        // The field is not provided before boot protocol 2.03.
+        boot_hdr.version = 0x202;
+        boot_hdr.initrd_addr_max = 0x1fff_ffff;
+        assert_eq!(boot_hdr.initrd_addr_max(), None);
+        assert_eq!(initrd_addr_limit(&boot_hdr, u64::MAX), INITRD_ADDR_MAX);
+    }
+
+    #[test]
+    fn test_kernel_header_getters() {
+        // Header of bzImage, the offsets refer to linux boot protocol.
+        let mut image = vec![0_u8; 0x400];
+        image[0x202..0x206].copy_from_slice(&HDRS.to_le_bytes());
+        image[0x206..0x208].copy_from_slice(&0x20f_u16.to_le_bytes());
+        image[0x211] = 0x1;
+        image[0x230..0x234].copy_from_slice(&0x20_0000_u32.to_le_bytes());
+        image[0x234] = 1;
+        image[0x236..0x238].copy_from_slice(&0x7f_u16.to_le_bytes());
+        image[0x258..0x260].copy_from_slice(&0x100_0000_u64.to_le_bytes());
+        image[0x260..0x264].copy_from_slice(&0x2a6_8000_u32.to_le_bytes());
+
+        let mut boot_hdr = RealModeKernelHeader::default();
+        let start = BOOT_HDR_START as usize;
+        let len = boot_hdr.as_bytes().len();
+        boot_hdr
+            .as_mut_bytes()
+            .copy_from_slice(&image[start..start + len]);
+        assert!(boot_hdr.check_valid_kernel().is_ok());
+        assert_eq!(boot_hdr.init_size(), 0x2a6_8000);
+        assert_eq!(boot_hdr.pref_address(), 0x100_0000);
+        assert_eq!(boot_hdr.kernel_alignment(), 0x20_0000);
+        assert_eq!(boot_hdr.xloadflags(), 0x7f);
+        assert!(boot_hdr.relocatable_kernel());
+
+        // Kernel without the fields of newer boot protocol.
+        let boot_hdr = RealModeKernelHeader::new();
+        assert_eq!(boot_hdr.init_size(), 0);
+        assert_eq!(boot_hdr.pref_address(), 0);
+        assert!(!boot_hdr.relocatable_kernel());
+    }
+
+    #[test]
+    fn test_e820_reserve_pci_hole() {
+        let root = Region::init_container_region(0x1_2000_0000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        for (addr, name) in [(0, "low_ram"), (0x1_0000_0000, "high_ram")] {
+            let ram = Arc::new(
+                HostMemMapping::new(
+                    GuestAddress(addr),
+                    None,
+                    0x1000_0000,
+                    None,
+                    HostMemOptions::default(),
+                )
+                .unwrap(),
+            );
+            let region = Region::init_ram_region(ram.clone(), name);
+            root.add_subregion(region, ram.start_address().raw_value())
+                .unwrap();
+        }
+
+        let mut config = X86BootLoaderConfig {
+            kernel: Some(PathBuf::new()),
+            initrd: Some(PathBuf::new()),
+            kernel_cmdline: String::new(),
+            cpu_count: 2,
+            gap_range: (0x1000_0000, 0xF000_0000),
+            ioapic_addr: 0xFEC0_0000,
+            lapic_addr: 0xFEE0_0000,
+            prot64_mode: false,
+            ident_tss_range: None,
+            five_level_paging: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: false,
+            auto_serial_console: false,
+        };
+
+        // The hole is absent by default.
+        let mut boot_params = BootParams::new(RealModeKernelHeader::default());
+        boot_params.setup_e820_entries(&config, &space).unwrap();
+        assert_eq!(boot_params.e820_entries, 5);
+        assert!(boot_params.e820_table[3].addr == 0x0010_0000);
+        assert!(boot_params.e820_table[3].size == 0x0ff0_0000);
+        assert!(boot_params.e820_table[4].addr == 0x1_0000_0000);
+        assert!(boot_params.e820_table[4].size == 0x1000_0000);
+
+        // The reserved hole is between the low and high ram.
+        config.reserve_pci_hole = true;
+        let mut boot_params = BootParams::new(RealModeKernelHeader::default());
+        boot_params.setup_e820_entries(&config, &space).unwrap();
+        assert_eq!(boot_params.e820_entries, 6);
+        assert!(boot_params.e820_table[3].addr == 0x0010_0000);
+        assert!(boot_params.e820_table[3].type_ == E820_RAM);
+        assert!(boot_params.e820_table[4].addr == 0x1000_0000);
+        assert!(boot_params.e820_table[4].size == 0xF000_0000);
+        assert!(boot_params.e820_table[4].type_ == E820_RESERVED);
+        assert!(boot_params.e820_table[5].addr == 0x1_0000_0000);
+        assert!(boot_params.e820_table[5].type_ == E820_RAM);
+    }
+
+    #[test]
+    fn test_e820_reserve_kernel_init() {
+        let root = Region::init_container_region(0x1000_0000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                0x1000_0000,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        let region = Region::init_ram_region(ram.clone(), "ram");
+        root.add_subregion(region, ram.start_address().raw_value())
+            .unwrap();
+
+        let mut config = X86BootLoaderConfig {
+            kernel: Some(PathBuf::new()),
+            initrd: None,
+            kernel_cmdline: String::new(),
+            cpu_count: 2,
+            gap_range: (0xC000_0000, 0x4000_0000),
+            ioapic_addr: 0xFEC0_0000,
+            lapic_addr: 0xFEE0_0000,
+            prot64_mode: true,
+            ident_tss_range: None,
+            five_level_paging: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: true,
+            auto_serial_console: false,
+        };
+        let load_addr = 0x100_0000_u64;
+        let init_size = 0x2a6_8000_u64;
+        let mut boot_hdr = RealModeKernelHeader::new();
+        boot_hdr.code32_start = load_addr as u32;
+        boot_hdr.init_size = init_size as u32;
+
+        // The init region is carved out of the high ram.
+        let mut boot_params = BootParams::new(boot_hdr);
+        boot_params.setup_e820_entries(&config, &space).unwrap();
+        assert_eq!(boot_params.e820_entries, 6);
+        let entries: Vec<(u64, u64, u32)> = boot_params.e820_table[3..6]
+            .iter()
+            .map(|entry| (entry.addr, entry.size, entry.type_))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (VMLINUX_RAM_START, load_addr - VMLINUX_RAM_START, E820_RAM),
+                (load_addr, init_size, E820_RESERVED),
+                (
+                    load_addr + init_size,
+                    0x1000_0000 - load_addr - init_size,
+                    E820_RAM
+                ),
+            ]
+        );
+        assert!(boot_params.is_ram(load_addr - 1));
+        assert!(!boot_params.is_ram(load_addr));
+        assert!(!boot_params.is_ram(load_addr + init_size - 1));
+        assert!(boot_params.is_ram(load_addr + init_size));
+
+        // Kernel loaded at the start of high ram.
+        boot_hdr.code32_start = VMLINUX_RAM_START as u32;
+        let mut boot_params = BootParams::new(boot_hdr);
+        boot_params.setup_e820_entries(&config, &space).unwrap();
+        assert_eq!(boot_params.e820_entries, 5);
+        assert!(!boot_params.is_ram(VMLINUX_RAM_START));
+        assert!(boot_params.is_ram(VMLINUX_RAM_START + init_size));
+
+        // The init region beyond the end of memory.
+        boot_hdr.code32_start = 0xF00_0000;
+        let mut boot_params = BootParams::new(boot_hdr);
+        assert!(boot_params.setup_e820_entries(&config, &space).is_err());
+
+        // Nothing is reserved if the option is off or there is no init size.
+        config.reserve_kernel_init = false;
+        let mut boot_params = BootParams::new(boot_hdr);
+        boot_params.setup_e820_entries(&config, &space).unwrap();
+        assert_eq!(boot_params.e820_entries, 4);
+        config.reserve_kernel_init = true;
+        let mut boot_params = BootParams::new(RealModeKernelHeader::new());
+        boot_params.setup_e820_entries(&config, &space).unwrap();
+        assert_eq!(boot_params.e820_entries, 4);
+    }
+
+    #[test]
+    fn test_e820_range_underflow() {
+        let create_space = |size: u64| {
+            let root = Region::init_container_region(0x1000_0000, "root");
+            let space = AddressSpace::new(root.clone(), "space").unwrap();
+            let ram = Arc::new(
+                HostMemMapping::new(GuestAddress(0), None, size, None, HostMemOptions::default())
+                    .unwrap(),
+            );
+            let region = Region::init_ram_region(ram.clone(), "ram");
+            root.add_subregion(region, ram.start_address().raw_value())
+                .unwrap();
+            space
+        };
+        let mut config = X86BootLoaderConfig {
+            kernel: Some(PathBuf::new()),
+            initrd: Some(PathBuf::new()),
+            kernel_cmdline: String::new(),
+            cpu_count: 2,
+            gap_range: (0xC000_0000, 0x4000_0000),
+            ioapic_addr: 0xFEC0_0000,
+            lapic_addr: 0xFEE0_0000,
+            prot64_mode: false,
+            ident_tss_range: None,
+            five_level_paging: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: false,
+            auto_serial_console: false,
+        };
+
+        // The end of memory is below the start of high memory.
+        let space = create_space(0x8_0000);
+        let mut boot_params = BootParams::new(RealModeKernelHeader::default());
+        let err = boot_params.setup_e820_entries(&config, &space).unwrap_err();
+        match err.downcast_ref::<BootLoaderError>() {
+            Some(BootLoaderError::E820RangeUnderflow(start, end)) => {
+                assert_eq!(*start, VMLINUX_RAM_START);
+                assert_eq!(*end, 0x8_0000);
+            }
+            _ => panic!("Unexpected error: {:?}", err),
+        }
+
+        // The gap starts below the start of high memory.
+        config.gap_range = (0x8_0000, 0x8_0000);
+        let space = create_space(0x20_0000);
+        let mut boot_params = BootParams::new(RealModeKernelHeader::default());
+        let err = boot_params.setup_e820_entries(&config, &space).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BootLoaderError>(),
+            Some(BootLoaderError::E820RangeUnderflow(
+                VMLINUX_RAM_START,
+                0x8_0000
+            ))
+        ));
+
+        // The end of memory is the same as the start of high memory.
+        config.gap_range = (0xC000_0000, 0x4000_0000);
+        let space = create_space(VMLINUX_RAM_START);
+        let mut boot_params = BootParams::new(RealModeKernelHeader::default());
+        boot_params.setup_e820_entries(&config, &space).unwrap();
+        assert!(!boot_params.is_ram(VMLINUX_RAM_START));
+    }
+    #[test]
+    fn test_setup_data() {
+        let root = Region::init_container_region(0x10_0000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                0x10_0000,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        let region = Region::init_ram_region(ram.clone(), "ram");
+        root.add_subregion(region, ram.start_address().raw_value())
+            .unwrap();
+        let read_data = |addr: u64, len: u32| {
+            let mut data = Vec::new();
+            space
+                .read(&mut data, GuestAddress(addr), len as u64)
+                .unwrap();
+            data
+        };
+
+        // Walk the setup_data linked list from the head.
+        let walk = |head: u64| {
+            let mut nodes = Vec::new();
+            let mut next = head;
+            while next != 0 {
+                let header = space
+                    .read_object::<SetupDataHeader>(GuestAddress(next))
+                    .unwrap();
+                let (type_, len) = (header.type_, header.len);
+                nodes.push((next, type_, read_data(next + 16, len)));
+                next = header.next;
+            }
+            nodes
+        };
+
+        let mut boot_params = BootParams::new(RealModeKernelHeader::default());
+        let efi_info = vec![0x5a_u8; 0x1d];
+        boot_params.add_efi_setup_data(&efi_info, &space).unwrap();
+        let first = boot_params.kernel_header.setup_data;
+        assert_eq!(first, SETUP_DATA_START);
+        assert_eq!(walk(first), vec![(first, SETUP_EFI, efi_info.clone())]);
+
+        // The second node is allocated after the first one and aligned, and
+        // it is prepended to the list.
+        let seed = vec![1_u8, 2, 3, 4, 5, 6, 7, 8];
+        let second = boot_params
+            .push_setup_data(SETUP_RNG_SEED, &seed, &space)
+            .unwrap();
+        assert_eq!(second, first + 0x30);
+        assert_eq!({ boot_params.kernel_header.setup_data }, second);
+        assert_eq!(
+            walk(second),
+            vec![(second, SETUP_RNG_SEED, seed), (first, SETUP_EFI, efi_info)]
+        );
+
+        // No room for the node in setup data area.
+        let err = boot_params
+            .push_setup_data(SETUP_E820_EXT, &[0; 0x3000], &space)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BootLoaderError>(),
+            Some(BootLoaderError::SetupDataOverflow(_, _, SETUP_DATA_END))
+        ));
+        assert_eq!({ boot_params.kernel_header.setup_data }, second);
+        assert_eq!(walk(second).len(), 2);
     }
 }
@@ -14,10 +14,10 @@ mod gdt;
 mod mptable;
 
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use log::info;
 
 use address_space::{AddressSpace, GuestAddress};
@@ -26,10 +26,10 @@ use util::byte_code::ByteCode;
 use self::gdt::setup_gdt;
 use self::mptable::setup_isa_mptable;
 use super::bootparam::{BootParams, RealModeKernelHeader, UNDEFINED_ID};
-use super::{X86BootLoader, X86BootLoaderConfig};
+use super::{initrd_addr_limit, initrd_load_addr, X86BootLoader, X86BootLoaderConfig};
 use super::{
-    BOOT_HDR_START, BOOT_LOADER_SP, BZIMAGE_BOOT_OFFSET, CMDLINE_START, EBDA_START,
-    INITRD_ADDR_MAX, PDE_START, PDPTE_START, PML4_START, VMLINUX_STARTUP, ZERO_PAGE_START,
+    BOOT_HDR_START, BOOT_LOADER_SP, BZIMAGE_BOOT_OFFSET, CMDLINE_START, EBDA_START, INITRD_ALIGN,
+    PDE_START, PDPTE_START, PML4_START, PML5_START, VMLINUX_STARTUP, ZERO_PAGE_START,
 };
 use crate::error::BootLoaderError;
 
@@ -53,14 +53,13 @@ use crate::error::BootLoaderError;
 ///
 /// * Invalid BzImage header or version.
 /// * Failed to write bzImage linux kernel to guest memory.
-fn load_bzimage(kernel_image: &mut File) -> Result<RealModeKernelHeader> {
+fn load_bzimage<R: Read + Seek>(kernel_image: &mut R) -> Result<RealModeKernelHeader> {
     let mut boot_hdr = RealModeKernelHeader::new();
 
     kernel_image.seek(SeekFrom::Start(BOOT_HDR_START))?;
     kernel_image
         .read_exact(boot_hdr.as_mut_bytes())
         .with_context(|| "Failed to read boot_hdr from bzImage kernel")?;
-    boot_hdr.type_of_loader = UNDEFINED_ID;
 
     if let Err(e) = boot_hdr.check_valid_kernel() {
         kernel_image.seek(SeekFrom::Start(0))?;
@@ -87,25 +86,28 @@ fn load_bzimage(kernel_image: &mut File) -> Result<RealModeKernelHeader> {
 /// # Errors
 ///
 /// * Write image to guest memory failed.
-fn load_image(image: &mut File, start_addr: u64, sys_mem: &Arc<AddressSpace>) -> Result<()> {
+fn load_image<R: Read + Seek>(
+    image: &mut R,
+    start_addr: u64,
+    sys_mem: &Arc<AddressSpace>,
+) -> Result<u64> {
     let curr_loc = image.stream_position()?;
     let len = image.seek(SeekFrom::End(0))?;
     image.seek(SeekFrom::Start(curr_loc))?;
 
     sys_mem.write(image, GuestAddress(start_addr), len - curr_loc)?;
 
-    Ok(())
+    Ok(len - curr_loc)
 }
 
-fn load_kernel_image(
-    kernel_path: &std::path::Path,
+/// Load kernel image to guest memory, return the kernel header and
+/// the range of kernel image in guest memory.
+fn load_kernel_image<R: Read + Seek>(
+    kernel_image: &mut R,
     sys_mem: &Arc<AddressSpace>,
     boot_layout: &mut X86BootLoader,
-) -> Result<RealModeKernelHeader> {
-    let mut kernel_image =
-        File::open(kernel_path).with_context(|| BootLoaderError::BootLoaderOpenKernel)?;
-
-    let (boot_hdr, kernel_start, vmlinux_start) = if let Ok(hdr) = load_bzimage(&mut kernel_image) {
+) -> Result<(RealModeKernelHeader, (u64, u64))> {
+    let (mut boot_hdr, kernel_start, vmlinux_start) = if let Ok(hdr) = load_bzimage(kernel_image) {
         (
             hdr,
             hdr.code32_start as u64 + BZIMAGE_BOOT_OFFSET,
@@ -118,44 +120,62 @@ fn load_kernel_image(
             VMLINUX_STARTUP,
         )
     };
+    // The header read from bzImage keeps the type of loader in image, which
+    // is set by us explicitly.
+    boot_hdr.type_of_loader = UNDEFINED_ID;
 
-    load_image(&mut kernel_image, vmlinux_start, sys_mem)
-        .with_context(|| "Failed to load image")?;
+    let kernel_size =
+        load_image(kernel_image, vmlinux_start, sys_mem).with_context(|| "Failed to load image")?;
 
     boot_layout.boot_ip = kernel_start;
 
-    Ok(boot_hdr)
+    Ok((boot_hdr, (vmlinux_start, vmlinux_start + kernel_size)))
 }
 
+/// Load initrd image to guest memory, return the range of initrd image in
+/// guest memory, which is empty if there is no initrd.
 fn load_initrd(
     config: &X86BootLoaderConfig,
     sys_mem: &Arc<AddressSpace>,
     header: &mut RealModeKernelHeader,
-) -> Result<()> {
+    kernel_range: (u64, u64),
+) -> Result<(u64, u64)> {
     if config.initrd.is_none() {
         info!("No initrd image file.");
-        return Ok(());
+        // Kernel takes the ramdisk as absent only if both fields are zero.
+        header.set_ramdisk(0, 0);
+        return Ok((0, 0));
     };
 
-    let mut initrd_addr_max = INITRD_ADDR_MAX;
-    if initrd_addr_max > sys_mem.memory_end_address().raw_value() {
-        initrd_addr_max = sys_mem.memory_end_address().raw_value();
-    };
+    let initrd_addr_max = initrd_addr_limit(header, sys_mem.memory_end_address().raw_value());
 
     let mut initrd_image = File::open(config.initrd.as_ref().unwrap())
         .with_context(|| BootLoaderError::BootLoaderOpenInitrd)?;
     let initrd_size = initrd_image.metadata().unwrap().len();
-    let initrd_addr = (initrd_addr_max - initrd_size) & !0xfff_u64;
+    let initrd_addr = initrd_load_addr(initrd_addr_max, initrd_size, INITRD_ALIGN)?;
+    if initrd_addr < kernel_range.1 && kernel_range.0 < initrd_addr + initrd_size {
+        return Err(anyhow!(BootLoaderError::LayoutOverlap(
+            kernel_range.0,
+            kernel_range.1,
+            initrd_addr,
+            initrd_addr + initrd_size
+        )));
+    }
 
     load_image(&mut initrd_image, initrd_addr, sys_mem).with_context(|| "Failed to load image")?;
 
     header.set_ramdisk(initrd_addr as u32, initrd_size as u32);
 
-    Ok(())
+    Ok((initrd_addr, initrd_addr + initrd_size))
 }
 
-/// Initial pagetables.
-fn setup_page_table(sys_mem: &Arc<AddressSpace>) -> Result<u64> {
+/// Initial pagetables, return the address of the top level page table.
+///
+/// # Arguments
+///
+/// * `sys_mem` - guest memory.
+/// * `five_level_paging` - Setup PML5 table above the PML4 table.
+fn setup_page_table(sys_mem: &Arc<AddressSpace>, five_level_paging: bool) -> Result<u64> {
     // Puts PML4 right after zero page but aligned to 4k.
     let boot_pml4_addr = PML4_START;
     let boot_pdpte_addr = PDPTE_START;
@@ -182,6 +202,16 @@ fn setup_page_table(sys_mem: &Arc<AddressSpace>) -> Result<u64> {
             .with_context(|| format!("Failed to load PDE to 0x{:x}", boot_pde_addr + i * 8))?;
     }
 
+    if five_level_paging {
+        // Entry covering VA [0..256TB)
+        let boot_pml5_addr = PML5_START;
+        let pml4e = boot_pml4_addr | 0x03;
+        sys_mem
+            .write_object(&pml4e, GuestAddress(boot_pml5_addr))
+            .with_context(|| format!("Failed to load PML4E to 0x{:x}", boot_pml5_addr))?;
+        return Ok(boot_pml5_addr);
+    }
+
     Ok(boot_pml4_addr)
 }
 
@@ -191,7 +221,8 @@ fn setup_boot_params(
     boot_hdr: &RealModeKernelHeader,
 ) -> Result<()> {
     let mut boot_params = BootParams::new(*boot_hdr);
-    boot_params.setup_e820_entries(config, sys_mem);
+    boot_params.setup_e820_entries(config, sys_mem)?;
+    boot_params.check_loader_fields()?;
     sys_mem
         .write_object(&boot_params, GuestAddress(ZERO_PAGE_START))
         .with_context(|| format!("Failed to load zero page to 0x{:x}", ZERO_PAGE_START))?;
@@ -199,21 +230,24 @@ fn setup_boot_params(
     Ok(())
 }
 
+/// Write kernel cmdline to guest memory, return the range of cmdline in
+/// guest memory.
 fn setup_kernel_cmdline(
     config: &X86BootLoaderConfig,
     sys_mem: &Arc<AddressSpace>,
     boot_hdr: &mut RealModeKernelHeader,
-) -> Result<()> {
-    let cmdline_len = config.kernel_cmdline.len() as u32;
+) -> Result<(u64, u64)> {
+    let cmdline = config.boot_cmdline();
+    let cmdline_len = cmdline.len() as u32;
     boot_hdr.set_cmdline(CMDLINE_START as u32, cmdline_len);
 
     sys_mem.write(
-        &mut config.kernel_cmdline.as_bytes(),
+        &mut cmdline.as_bytes(),
         GuestAddress(CMDLINE_START),
         cmdline_len as u64,
     )?;
 
-    Ok(())
+    Ok((CMDLINE_START, CMDLINE_START + cmdline_len as u64))
 }
 
 /// Load PE(vmlinux.bin) linux kernel / bzImage linux kernel and
@@ -230,6 +264,8 @@ fn setup_kernel_cmdline(
 ///
 /// * `config` - boot source config, contains kernel, initrd and kernel cmdline.
 /// * `sys_mem` - guest memory.
+/// * `kernel_data` - kernel image fetched already, which is used instead of
+///   the kernel path in `config`.
 ///
 /// # Errors
 ///
@@ -238,22 +274,31 @@ fn setup_kernel_cmdline(
 pub fn load_linux(
     config: &X86BootLoaderConfig,
     sys_mem: &Arc<AddressSpace>,
+    kernel_data: Option<Vec<u8>>,
 ) -> Result<X86BootLoader> {
-    let kernel_path = config
-        .kernel
-        .as_ref()
-        .with_context(|| "Kernel is required for direct-boot mode.")?;
     let mut boot_loader_layout = X86BootLoader {
         boot_sp: BOOT_LOADER_SP,
         zero_page_addr: ZERO_PAGE_START,
         ..Default::default()
     };
-    let mut boot_header = load_kernel_image(kernel_path, sys_mem, &mut boot_loader_layout)?;
+    let (mut boot_header, kernel_range) = match kernel_data {
+        Some(data) => load_kernel_image(&mut Cursor::new(data), sys_mem, &mut boot_loader_layout)?,
+        None => {
+            let kernel_path = config
+                .kernel
+                .as_ref()
+                .with_context(|| "Kernel is required for direct-boot mode.")?;
+            let mut kernel_image =
+                File::open(kernel_path).with_context(|| BootLoaderError::BootLoaderOpenKernel)?;
+            load_kernel_image(&mut kernel_image, sys_mem, &mut boot_loader_layout)?
+        }
+    };
+    boot_loader_layout.kernel_range = kernel_range;
 
-    load_initrd(config, sys_mem, &mut boot_header)
+    boot_loader_layout.initrd_range = load_initrd(config, sys_mem, &mut boot_header, kernel_range)
         .with_context(|| "Failed to load initrd to vm memory")?;
 
-    setup_kernel_cmdline(config, sys_mem, &mut boot_header)
+    boot_loader_layout.cmdline_range = setup_kernel_cmdline(config, sys_mem, &mut boot_header)
         .with_context(|| "Failed to setup kernel cmdline")?;
 
     setup_boot_params(config, sys_mem, &boot_header)
@@ -267,8 +312,8 @@ pub fn load_linux(
         config.lapic_addr,
     )?;
 
-    boot_loader_layout.boot_pml4_addr =
-        setup_page_table(sys_mem).with_context(|| "Failed to setup page table")?;
+    boot_loader_layout.boot_pml4_addr = setup_page_table(sys_mem, config.five_level_paging)
+        .with_context(|| "Failed to setup page table")?;
     boot_loader_layout.segments = setup_gdt(sys_mem).with_context(|| "Failed to setup gdt")?;
 
     Ok(boot_loader_layout)
@@ -294,16 +339,14 @@ mod test {
                 None,
                 0x1000_0000,
                 None,
-                false,
-                false,
-                false,
+                HostMemOptions::default(),
             )
             .unwrap(),
         );
         let region_a = Region::init_ram_region(ram1.clone(), "region_a");
         root.add_subregion(region_a, ram1.start_address().raw_value())
             .unwrap();
-        assert_eq!(setup_page_table(&space).unwrap(), 0x0000_9000);
+        assert_eq!(setup_page_table(&space, false).unwrap(), 0x0000_9000);
         assert_eq!(
             space.read_object::<u64>(GuestAddress(0x0000_9000)).unwrap(),
             0x0000_a003
@@ -333,6 +376,10 @@ mod test {
             lapic_addr: 0xFEE0_0000,
             prot64_mode: false,
             ident_tss_range: None,
+            five_level_paging: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: false,
+            auto_serial_console: false,
         };
         let mut boot_hdr = RealModeKernelHeader::new();
         assert!(setup_boot_params(&config, &space, &boot_hdr).is_ok());
@@ -400,4 +447,168 @@ mod test {
         let s = String::from_utf8(read_buffer.to_vec()).unwrap();
         assert_eq!(s, "this_is_a_piece_of_test_string".to_string());
     }
+
+    #[test]
+    fn test_setup_five_level_page_table() {
+        let root = Region::init_container_region(0x2000_0000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram1 = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                0x10_0000,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        let region_a = Region::init_ram_region(ram1.clone(), "region_a");
+        root.add_subregion(region_a, ram1.start_address().raw_value())
+            .unwrap();
+
+        // 4-level paging by default, PML5 table is not setup.
+        assert_eq!(setup_page_table(&space, false).unwrap(), PML4_START);
+        assert_eq!(
+            space.read_object::<u64>(GuestAddress(PML5_START)).unwrap(),
+            0
+        );
+
+        // The PML5 entry points at the PML4 table.
+        assert_eq!(setup_page_table(&space, true).unwrap(), PML5_START);
+        assert_eq!(
+            space.read_object::<u64>(GuestAddress(PML5_START)).unwrap(),
+            PML4_START | 0x03
+        );
+        assert_eq!(
+            space.read_object::<u64>(GuestAddress(PML4_START)).unwrap(),
+            PDPTE_START | 0x03
+        );
+        assert_eq!(
+            space.read_object::<u64>(GuestAddress(PDPTE_START)).unwrap(),
+            PDE_START | 0x03
+        );
+    }
+
+    #[test]
+    fn test_load_without_initrd() {
+        let root = Region::init_container_region(0x2000_0000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                0x1000_0000,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        let region = Region::init_ram_region(ram.clone(), "ram");
+        root.add_subregion(region, ram.start_address().raw_value())
+            .unwrap();
+
+        let config = X86BootLoaderConfig {
+            kernel: Some(PathBuf::new()),
+            initrd: None,
+            kernel_cmdline: String::new(),
+            cpu_count: 2,
+            gap_range: (0xC000_0000, 0x4000_0000),
+            ioapic_addr: 0xFEC0_0000,
+            lapic_addr: 0xFEE0_0000,
+            prot64_mode: false,
+            ident_tss_range: None,
+            five_level_paging: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: false,
+            auto_serial_console: false,
+        };
+
+        // The stale ramdisk fields are cleared.
+        let mut boot_hdr = RealModeKernelHeader::new();
+        boot_hdr.set_ramdisk(0x0F00_0000, 0x10_0000);
+        load_initrd(&config, &space, &mut boot_hdr, (0x0010_0000, 0x0020_0000)).unwrap();
+        assert_eq!(boot_hdr.ramdisk(), (0, 0));
+
+        // No range is carved out of ram for initrd.
+        let mut boot_params = BootParams::new(boot_hdr);
+        boot_params.setup_e820_entries(&config, &space).unwrap();
+        assert!(boot_params.is_ram(0x0010_0000));
+        assert!(boot_params.is_ram(0x0F00_0000));
+        assert!(boot_params.is_ram(0x0FFF_FFFF));
+    }
+
+    #[test]
+    fn test_loader_type_in_boot_params() {
+        let root = Region::init_container_region(0x2000_0000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                0x1000_0000,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        let region = Region::init_ram_region(ram.clone(), "ram");
+        root.add_subregion(region, ram.start_address().raw_value())
+            .unwrap();
+
+        let config = X86BootLoaderConfig {
+            kernel: None,
+            initrd: None,
+            kernel_cmdline: String::new(),
+            cpu_count: 2,
+            gap_range: (0xC000_0000, 0x4000_0000),
+            ioapic_addr: 0xFEC0_0000,
+            lapic_addr: 0xFEE0_0000,
+            prot64_mode: false,
+            ident_tss_range: None,
+            five_level_paging: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: false,
+            auto_serial_console: false,
+        };
+
+        // BzImage with one setup sector, whose type of loader is not set.
+        let mut image = vec![0_u8; 0x1000];
+        image[0x1f1] = 1;
+        image[0x202..0x206].copy_from_slice(&0x5372_6448_u32.to_le_bytes());
+        image[0x206..0x208].copy_from_slice(&0x20f_u16.to_le_bytes());
+        image[0x211] = 0x1;
+        image[0x214..0x218].copy_from_slice(&0x10_0000_u32.to_le_bytes());
+        let mut kernel = std::env::temp_dir();
+        kernel.push("stratovirt_test_loader_type_kernel");
+        std::fs::write(&kernel, &image).unwrap();
+
+        // The freshly-read header is rejected before handing off to kernel.
+        let mut boot_hdr = RealModeKernelHeader::default();
+        let hdr_len = boot_hdr.as_bytes().len();
+        boot_hdr
+            .as_mut_bytes()
+            .copy_from_slice(&image[BOOT_HDR_START as usize..][..hdr_len]);
+        let err = setup_boot_params(&config, &space, &boot_hdr).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BootLoaderError>(),
+            Some(BootLoaderError::LoaderTypeUnset)
+        ));
+
+        // The type of loader is set by loader and kept in zero page.
+        let mut boot_layout = X86BootLoader::default();
+        let (boot_hdr, kernel_range) =
+            load_kernel_image(&mut File::open(&kernel).unwrap(), &space, &mut boot_layout).unwrap();
+        std::fs::remove_file(&kernel).unwrap();
+        assert_eq!(kernel_range, (0x10_0000, 0x10_0c00));
+        assert_eq!(boot_hdr.type_of_loader, UNDEFINED_ID);
+        setup_boot_params(&config, &space, &boot_hdr).unwrap();
+        let boot_params = space
+            .read_object::<BootParams>(GuestAddress(ZERO_PAGE_START))
+            .unwrap();
+        boot_params.check_loader_fields().unwrap();
+        let zero_page_hdr = space
+            .read_object::<RealModeKernelHeader>(GuestAddress(ZERO_PAGE_START + BOOT_HDR_START))
+            .unwrap();
+        assert_eq!(zero_page_hdr.type_of_loader, UNDEFINED_ID);
+    }
 }
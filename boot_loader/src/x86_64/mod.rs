@@ -59,9 +59,12 @@ mod standard_boot;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use kvm_bindings::kvm_segment;
+use log::info;
 
+use crate::error::BootLoaderError;
+use crate::KernelFetcher;
 use address_space::AddressSpace;
 use devices::legacy::FwCfgOps;
 
@@ -69,6 +72,10 @@ const ZERO_PAGE_START: u64 = 0x0000_7000;
 const PML4_START: u64 = 0x0000_9000;
 const PDPTE_START: u64 = 0x0000_a000;
 const PDE_START: u64 = 0x0000_b000;
+const PML5_START: u64 = 0x0000_c000;
+/// Area for the nodes of setup_data linked list in direct boot.
+const SETUP_DATA_START: u64 = 0x0000_d000;
+const SETUP_DATA_END: u64 = 0x0001_0000;
 const SETUP_START: u64 = 0x0001_0000;
 const CMDLINE_START: u64 = 0x0002_0000;
 const BOOT_HDR_START: u64 = 0x0000_01F1;
@@ -79,6 +86,8 @@ const VGA_RAM_BEGIN: u64 = 0x000a_0000;
 const MB_BIOS_BEGIN: u64 = 0x000f_0000;
 pub const VMLINUX_RAM_START: u64 = 0x0010_0000;
 const INITRD_ADDR_MAX: u64 = 0x37ff_ffff;
+/// Default alignment of initrd load address, which is the guest page size.
+const INITRD_ALIGN: u64 = 0x1000;
 
 const VMLINUX_STARTUP: u64 = 0x0100_0000;
 const BOOT_LOADER_SP: u64 = 0x0000_8ff0;
@@ -92,6 +101,13 @@ const BOOT_GDT_MAX: usize = 4;
 
 const REAL_MODE_IVT_BEGIN: u64 = 0x0000_0000;
 
+/// Serial console appended to kernel cmdline if `auto_serial_console` is set.
+const SERIAL_CONSOLE_PARAM: &str = "console=ttyS0,115200";
+
+/// Max CPU count of VM. The MP table supports 255 APIC IDs, and one of them
+/// is reserved for IO APIC.
+pub const X86_MAX_CPU_COUNT: u8 = 254;
+
 /// Boot loader config used for x86_64.
 pub struct X86BootLoaderConfig {
     /// Path of the kernel image.
@@ -100,7 +116,7 @@ pub struct X86BootLoaderConfig {
     pub initrd: Option<PathBuf>,
     /// Kernel cmdline parameters.
     pub kernel_cmdline: String,
-    /// VM's CPU count.
+    /// VM's CPU count, which should be in range [1, `X86_MAX_CPU_COUNT`].
     pub cpu_count: u8,
     /// (gap start, gap size)
     pub gap_range: (u64, u64),
@@ -112,6 +128,50 @@ pub struct X86BootLoaderConfig {
     pub ident_tss_range: Option<(u64, u64)>,
     /// Boot from 64-bit protection mode or not.
     pub prot64_mode: bool,
+    /// Setup 5-level paging (PML5) instead of 4-level paging in direct boot,
+    /// it requires LA57 support of CPU.
+    pub five_level_paging: bool,
+    /// Mark the 32-bit PCI hole described by `gap_range` as reserved in E820
+    /// table of direct boot, rather than leaving it absent.
+    pub reserve_pci_hole: bool,
+    /// Mark the init region of kernel, which is `init_size` of setup header
+    /// from the load address, as reserved in E820 table of direct boot.
+    pub reserve_kernel_init: bool,
+    /// Append serial console to kernel cmdline if no console is specified.
+    pub auto_serial_console: bool,
+}
+
+impl X86BootLoaderConfig {
+    /// Kernel cmdline passed to guest. `console=ttyS0,115200` is appended if
+    /// `auto_serial_console` is set and there is no `console=` in cmdline.
+    pub fn boot_cmdline(&self) -> String {
+        let has_console = self
+            .kernel_cmdline
+            .split_whitespace()
+            .any(|param| param.starts_with("console="));
+        if !self.auto_serial_console || has_console {
+            return self.kernel_cmdline.clone();
+        }
+        if self.kernel_cmdline.trim().is_empty() {
+            return SERIAL_CONSOLE_PARAM.to_string();
+        }
+        format!(
+            "{} {}",
+            self.kernel_cmdline.trim_end(),
+            SERIAL_CONSOLE_PARAM
+        )
+    }
+
+    /// Check the config before loading the boot source.
+    pub fn check(&self) -> std::result::Result<(), BootLoaderError> {
+        if self.cpu_count == 0 {
+            return Err(BootLoaderError::ZeroCpus);
+        }
+        if self.cpu_count > X86_MAX_CPU_COUNT {
+            return Err(BootLoaderError::MaxCpus(self.cpu_count));
+        }
+        Ok(())
+    }
 }
 
 // 这段代码是使用Rust语言定义的两个结构体：`X86BootLoader`和`BootGdtSegment`。这些结构体用于描述x86_64架构的引导加载程序（bootloader）在客户机内存中的起始地址和相关信息。
@@ -144,6 +204,15 @@ pub struct X86BootLoader {
     pub boot_pml4_addr: u64,
     pub zero_page_addr: u64,
     pub segments: BootGdtSegment,
+    /// Range of kernel image in guest memory, empty if it is not loaded by
+    /// boot loader.
+    pub kernel_range: (u64, u64),
+    /// Range of initrd image in guest memory, empty if it is not loaded by
+    /// boot loader.
+    pub initrd_range: (u64, u64),
+    /// Range of kernel cmdline in guest memory, empty if it is not loaded by
+    /// boot loader.
+    pub cmdline_range: (u64, u64),
 }
 
 #[derive(Debug, Default, Copy, Clone)]
@@ -156,13 +225,171 @@ pub struct BootGdtSegment {
     pub idt_limit: u16,
 }
 
+impl X86BootLoader {
+    /// Format the resolved memory layout of boot source as an ASCII map like
+    /// the one in module doc. The regions not loaded by boot loader, such as
+    /// the kernel of standard boot which is loaded by firmware, are omitted.
+    pub fn layout_report(&self) -> String {
+        let segments = &self.segments;
+        let mut regions = Vec::new();
+        if segments.gdt_base != 0 {
+            let end = segments.gdt_base + u64::from(segments.gdt_limit) + 1;
+            regions.push(("GDT", segments.gdt_base, end));
+        }
+        if segments.idt_base != 0 {
+            let end = segments.idt_base + u64::from(segments.idt_limit) + 1;
+            regions.push(("IDT", segments.idt_base, end));
+        }
+        if self.zero_page_addr != 0 {
+            let end = self.zero_page_addr + std::mem::size_of::<bootparam::BootParams>() as u64;
+            regions.push(("Zero Page", self.zero_page_addr, end));
+        }
+        if self.boot_pml4_addr != 0 {
+            let end = std::cmp::max(self.boot_pml4_addr, PDE_START) + 0x1000;
+            regions.push(("Page Tables", PML4_START, end));
+        }
+        for (name, range) in [
+            ("Kernel Cmdline", self.cmdline_range),
+            ("Kernel", self.kernel_range),
+            ("Initrd Ram", self.initrd_range),
+        ] {
+            if range.1 > range.0 {
+                regions.push((name, range.0, range.1));
+            }
+        }
+        regions.sort_by_key(|region| region.1);
+
+        let border = "+------------------------+";
+        let mut report = String::new();
+        let mut last_end = None;
+        for (name, start, end) in regions {
+            if last_end != Some(start) {
+                if let Some(last_end) = last_end {
+                    report += &format!("  {:<14}{}\n", layout_addr(last_end), border);
+                    report += &format!("  {:<14}~{:24}~\n", "", "");
+                }
+                report += &format!("  {:<14}{}\n", layout_addr(start), border);
+            }
+            report += &format!("  {:<14}|  {:<22}|\n", "", name);
+            last_end = Some(end);
+        }
+        if let Some(last_end) = last_end {
+            report += &format!("  {:<14}{}\n", layout_addr(last_end), border);
+        }
+        report
+    }
+}
+
+/// Format the address in the form of `0x0000_7000`.
+fn layout_addr(addr: u64) -> String {
+    if addr >> 32 == 0 {
+        format!("0x{:04x}_{:04x}", addr >> 16, addr & 0xffff)
+    } else {
+        format!(
+            "0x{:04x}_{:04x}_{:04x}",
+            addr >> 32,
+            (addr >> 16) & 0xffff,
+            addr & 0xffff
+        )
+    }
+}
+
+/// Get the max end address of initrd, which is the lowest one among the
+/// default, the one advertised by kernel header and the end of guest memory.
+///
+/// # Arguments
+///
+/// * `header` - Kernel header.
+/// * `mem_end` - End address of guest memory.
+fn initrd_addr_limit(header: &bootparam::RealModeKernelHeader, mem_end: u64) -> u64 {
+    let mut addr_max = INITRD_ADDR_MAX.min(mem_end);
+    if let Some(header_max) = header.initrd_addr_max() {
+        addr_max = addr_max.min(header_max);
+    }
+    addr_max
+}
+
+/// Get the load address of initrd, which is the highest address aligned to
+/// `align` that still leaves room for the whole initrd below `addr_max`.
+///
+/// # Arguments
+///
+/// * `addr_max` - The max end address of initrd.
+/// * `initrd_size` - Size of initrd image.
+/// * `align` - Alignment of the load address, must be a power of two.
+fn initrd_load_addr(addr_max: u64, initrd_size: u64, align: u64) -> Result<u64> {
+    if !align.is_power_of_two() {
+        bail!("Invalid initrd alignment 0x{:x}", align);
+    }
+    // Aligning down never moves the end of initrd above `addr_max`.
+    let initrd_addr = addr_max
+        .checked_sub(initrd_size)
+        .with_context(|| BootLoaderError::InitrdOverflow(addr_max, initrd_size))?
+        & !(align - 1);
+    Ok(initrd_addr)
+}
+
+/// Build kernel cmdline from fragments, such as console, root and custom
+/// params. The fragments are trimmed and joined with single spaces, and the
+/// empty ones are skipped. The params are kept as they are, as some of them
+/// can be repeated, such as `console=`.
+///
+/// # Arguments
+///
+/// * `fragments` - Fragments of kernel cmdline.
+pub fn build_cmdline(fragments: &[&str]) -> String {
+    fragments
+        .iter()
+        .map(|fragment| fragment.trim())
+        .filter(|fragment| !fragment.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Load linux kernel and other boot source to guest memory or FwCfg.
+///
+/// # Errors
+///
+/// Return the specific `BootLoaderError` if the boot source is invalid or the
+/// memory layout is illegal, or `BootLoaderError::Other` for other failures.
 pub fn load_linux(
     config: &X86BootLoaderConfig,
     sys_mem: &Arc<AddressSpace>,
     fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
-) -> Result<X86BootLoader> {
+) -> std::result::Result<X86BootLoader, BootLoaderError> {
+    config.check()?;
+    load_boot_source(config, sys_mem, fwcfg, None)
+}
+
+/// Load linux kernel supplied by `fetcher` and other boot source to guest
+/// memory or FwCfg, the kernel path in `config` is ignored.
+///
+/// # Errors
+///
+/// Return `BootLoaderError::BootLoaderOpenKernel` if the kernel fails to be
+/// fetched, others are the same as `load_linux`.
+pub fn load_linux_with_fetcher(
+    config: &X86BootLoaderConfig,
+    sys_mem: &Arc<AddressSpace>,
+    fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
+    fetcher: &dyn KernelFetcher,
+) -> std::result::Result<X86BootLoader, BootLoaderError> {
+    config.check()?;
+    let kernel = fetcher
+        .fetch()
+        .with_context(|| BootLoaderError::BootLoaderOpenKernel)
+        .map_err(BootLoaderError::from_anyhow)?;
+    load_boot_source(config, sys_mem, fwcfg, Some(kernel))
+}
+
+fn load_boot_source(
+    config: &X86BootLoaderConfig,
+    sys_mem: &Arc<AddressSpace>,
+    fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
+    kernel_data: Option<Vec<u8>>,
+) -> std::result::Result<X86BootLoader, BootLoaderError> {
     if config.prot64_mode {
-        direct_boot::load_linux(config, sys_mem)
+        direct_boot::load_linux(config, sys_mem, kernel_data).map_err(BootLoaderError::from_anyhow)
     } else {
         // `fwcfg` 是指 Firmware Configuration（固件配置）的缩写，也称为 QEMU Firmware Configuration。它是 QEMU （Quick EMUlator）虚拟化软件中的一个组件，用于提供虚拟机中的固件配置。
         //
@@ -176,9 +403,14 @@ pub fn load_linux(
         //
         // 总结来说，fwcfg 是 QEMU 虚拟化软件中的一种机制，用于传递虚拟机的固件配置信息。通过 fwcfg 键值对，主机可以向虚拟机传递特定的配置参数，以便虚拟机中的组件根据这些参数进行初始化或配置。
 
-        let fwcfg = fwcfg.with_context(|| "Failed to load linux: No FwCfg provided")?;
+        let fwcfg = fwcfg.ok_or(BootLoaderError::FwCfgNotProvided)?;
         let mut locked_fwcfg = fwcfg.lock().unwrap();
-        standard_boot::load_linux(config, sys_mem, &mut *locked_fwcfg)?;
+        let e820 = standard_boot::load_linux(config, sys_mem, &mut *locked_fwcfg, kernel_data)
+            .map_err(BootLoaderError::from_anyhow)?;
+        info!(
+            "Published {} E820 entries with 0x{:x} bytes RAM",
+            e820.entries, e820.ram_size
+        );
 
         Ok(X86BootLoader {
             boot_ip: 0xFFF0,
@@ -188,3 +420,280 @@ pub fn load_linux(
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+    use address_space::{GuestAddress, HostMemMapping, HostMemOptions, Region};
+
+    fn create_space(size: u64) -> Arc<AddressSpace> {
+        let root = Region::init_container_region(size, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, size, None, HostMemOptions::default())
+                .unwrap(),
+        );
+        let region = Region::init_ram_region(ram.clone(), "ram");
+        root.add_subregion(region, ram.start_address().raw_value())
+            .unwrap();
+        space
+    }
+
+    fn create_config(kernel: Option<PathBuf>, initrd: Option<PathBuf>) -> X86BootLoaderConfig {
+        X86BootLoaderConfig {
+            kernel,
+            initrd,
+            kernel_cmdline: String::new(),
+            cpu_count: 1,
+            gap_range: (0xC000_0000, 0x4000_0000),
+            ioapic_addr: 0xFEC0_0000,
+            lapic_addr: 0xFEE0_0000,
+            ident_tss_range: None,
+            prot64_mode: true,
+            five_level_paging: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: false,
+            auto_serial_console: false,
+        }
+    }
+
+    #[test]
+    fn test_initrd_load_addr() {
+        for size in [0x1, 0x123, 0xfff, 0x1000, 0x1001, 0x12_3456, 0x200_0001] {
+            let addr = initrd_load_addr(INITRD_ADDR_MAX, size, INITRD_ALIGN).unwrap();
+            assert_eq!(addr % INITRD_ALIGN, 0);
+            assert!(addr + size <= INITRD_ADDR_MAX);
+            assert!(addr + size + INITRD_ALIGN > INITRD_ADDR_MAX);
+        }
+
+        // Alignment other than page size.
+        let addr = initrd_load_addr(0x1000_0000, 0x1234, 0x20_0000).unwrap();
+        assert_eq!(addr, 0xfe0_0000);
+        assert!(initrd_load_addr(0x1000_0000, 0x1234, 0x3000).is_err());
+
+        // No room for initrd below the max address.
+        let err = initrd_load_addr(0x1000, 0x2000, INITRD_ALIGN).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BootLoaderError>(),
+            Some(BootLoaderError::InitrdOverflow(0x1000, 0x2000))
+        ));
+    }
+
+    #[test]
+    fn test_cpu_count() {
+        let mut config = create_config(None, None);
+        assert!(config.check().is_ok());
+        config.cpu_count = X86_MAX_CPU_COUNT;
+        assert!(config.check().is_ok());
+
+        config.cpu_count = 0;
+        assert!(matches!(config.check(), Err(BootLoaderError::ZeroCpus)));
+        config.cpu_count = X86_MAX_CPU_COUNT + 1;
+        assert!(matches!(config.check(), Err(BootLoaderError::MaxCpus(255))));
+    }
+
+    #[test]
+    fn test_load_linux_errors() {
+        let space = create_space(0x120_0000);
+
+        // No cpu.
+        let mut config = create_config(None, None);
+        config.cpu_count = 0;
+        let err = load_linux(&config, &space, None).unwrap_err();
+        assert!(matches!(err, BootLoaderError::ZeroCpus));
+
+        // Standard boot without FwCfg.
+        let mut config = create_config(None, None);
+        config.prot64_mode = false;
+        let err = load_linux(&config, &space, None).unwrap_err();
+        assert!(matches!(err, BootLoaderError::FwCfgNotProvided));
+
+        // Kernel image does not exist.
+        let config = create_config(Some(PathBuf::from("/path/not/exist")), None);
+        let err = load_linux(&config, &space, None).unwrap_err();
+        assert!(matches!(err, BootLoaderError::BootLoaderOpenKernel));
+
+        // Kernel is loaded to [0x100_0000, 0x110_0000).
+        let mut kernel = env::temp_dir();
+        kernel.push("stratovirt_test_boot_kernel");
+        fs::write(&kernel, vec![0_u8; 0x10_0000]).unwrap();
+        let mut initrd = env::temp_dir();
+        initrd.push("stratovirt_test_boot_initrd");
+
+        // Initrd overlaps with kernel.
+        fs::write(&initrd, vec![0_u8; 0x20_0000]).unwrap();
+        let config = create_config(Some(kernel.clone()), Some(initrd.clone()));
+        let err = load_linux(&config, &space, None).unwrap_err();
+        assert!(matches!(
+            err,
+            BootLoaderError::LayoutOverlap(0x100_0000, 0x110_0000, 0x100_0000, 0x120_0000)
+        ));
+
+        // Initrd is larger than guest memory.
+        fs::write(&initrd, vec![0_u8; 0x200_0000]).unwrap();
+        let err = load_linux(&config, &space, None).unwrap_err();
+        assert!(matches!(
+            err,
+            BootLoaderError::InitrdOverflow(_, 0x200_0000)
+        ));
+
+        fs::remove_file(&kernel).unwrap();
+        fs::remove_file(&initrd).unwrap();
+    }
+
+    #[test]
+    fn test_layout_report() {
+        let space = create_space(0x1000_0000);
+        let mut kernel = env::temp_dir();
+        kernel.push("stratovirt_test_layout_kernel");
+        fs::write(&kernel, vec![0_u8; 0x10_0000]).unwrap();
+        let mut initrd = env::temp_dir();
+        initrd.push("stratovirt_test_layout_initrd");
+        fs::write(&initrd, vec![0_u8; 0x1000]).unwrap();
+
+        let mut config = create_config(Some(kernel.clone()), Some(initrd.clone()));
+        config.kernel_cmdline = String::from("console=ttyS0");
+        let boot_loader = load_linux(&config, &space, None).unwrap();
+        assert_eq!(boot_loader.initrd_range, (0xfff_f000, 0x1000_0000));
+        assert_eq!(
+            boot_loader.cmdline_range,
+            (CMDLINE_START, CMDLINE_START + 13)
+        );
+
+        let report = boot_loader.layout_report();
+        assert!(report.contains("0x0fff_f000"));
+        assert!(report.contains("0x0002_0000"));
+        assert!(report.contains("Initrd Ram"));
+        assert!(report.contains("Kernel Cmdline"));
+        assert!(report.contains("Zero Page"));
+        assert!(report.contains("GDT"));
+        // Regions are listed in the order of address.
+        let cmdline_pos = report.find("Kernel Cmdline").unwrap();
+        let initrd_pos = report.find("Initrd Ram").unwrap();
+        assert!(report.find("Zero Page").unwrap() < cmdline_pos);
+        assert!(cmdline_pos < initrd_pos);
+
+        // Standard boot does not load kernel and initrd by itself.
+        assert!(!X86BootLoader::default()
+            .layout_report()
+            .contains("Initrd Ram"));
+
+        fs::remove_file(&kernel).unwrap();
+        fs::remove_file(&initrd).unwrap();
+    }
+
+    struct TestKernelFetcher(Option<Vec<u8>>);
+
+    impl KernelFetcher for TestKernelFetcher {
+        fn fetch(&self) -> anyhow::Result<Vec<u8>> {
+            self.0.clone().with_context(|| "Failed to download kernel")
+        }
+    }
+
+    #[test]
+    fn test_load_linux_with_fetcher() {
+        // BzImage with one setup sector, followed by the payload of kernel.
+        let mut image: Vec<u8> = (0..0x2000)
+            .map(|i| if i < 0x400 { 0 } else { i as u8 })
+            .collect();
+        image[0x1f1] = 1;
+        image[0x202..0x206].copy_from_slice(&0x5372_6448_u32.to_le_bytes());
+        image[0x206..0x208].copy_from_slice(&0x20f_u16.to_le_bytes());
+        image[0x211] = 0x1;
+        image[0x214..0x218].copy_from_slice(&0x10_0000_u32.to_le_bytes());
+        let mut kernel = env::temp_dir();
+        kernel.push("stratovirt_test_fetcher_kernel");
+        fs::write(&kernel, &image).unwrap();
+
+        let path_space = create_space(0x1000_0000);
+        let mut config = create_config(Some(kernel.clone()), None);
+        config.kernel_cmdline = String::from("console=ttyS0");
+        let path_boot = load_linux(&config, &path_space, None).unwrap();
+        fs::remove_file(&kernel).unwrap();
+
+        // The kernel path is ignored if kernel is fetched.
+        let fetch_space = create_space(0x1000_0000);
+        config.kernel = Some(PathBuf::from("/path/not/exist"));
+        let fetcher = TestKernelFetcher(Some(image));
+        let fetch_boot = load_linux_with_fetcher(&config, &fetch_space, None, &fetcher).unwrap();
+        assert_eq!(fetch_boot.kernel_range, (0x10_0000, 0x10_1c00));
+        assert_eq!(format!("{:?}", path_boot), format!("{:?}", fetch_boot));
+
+        // The guest memory is the same as the one loaded from path.
+        let read_mem = |space: &Arc<AddressSpace>, start: u64, end: u64| {
+            let mut data = vec![0_u8; (end - start) as usize];
+            space
+                .read(&mut data.as_mut_slice(), GuestAddress(start), end - start)
+                .unwrap();
+            data
+        };
+        for (start, end) in [
+            (ZERO_PAGE_START, PML5_START),
+            (CMDLINE_START, CMDLINE_START + 0x1000),
+            (VMLINUX_RAM_START, VMLINUX_RAM_START + 0x2000),
+        ] {
+            assert_eq!(
+                read_mem(&path_space, start, end),
+                read_mem(&fetch_space, start, end)
+            );
+        }
+
+        // Failure of fetcher.
+        let err = load_linux_with_fetcher(&config, &fetch_space, None, &TestKernelFetcher(None))
+            .unwrap_err();
+        assert!(matches!(err, BootLoaderError::BootLoaderOpenKernel));
+    }
+
+    #[test]
+    fn test_build_cmdline() {
+        let cmdline = build_cmdline(&["console=ttyS0", "  root=/dev/vda rw ", "reboot=k panic=1"]);
+        assert_eq!(cmdline, "console=ttyS0 root=/dev/vda rw reboot=k panic=1");
+
+        // The repeated params are kept.
+        let cmdline = build_cmdline(&["console=ttyS0 console=tty0", "console=ttyS0", " quiet"]);
+        assert_eq!(cmdline, "console=ttyS0 console=tty0 console=ttyS0 quiet");
+
+        assert_eq!(build_cmdline(&[]), "");
+        assert_eq!(build_cmdline(&["", "   "]), "");
+        let mut config = create_config(None, None);
+        config.kernel_cmdline = build_cmdline(&["quiet", " pci=off"]);
+        assert_eq!(config.kernel_cmdline, "quiet pci=off");
+    }
+
+    #[test]
+    fn test_auto_serial_console() {
+        let mut config = create_config(None, None);
+        config.kernel_cmdline = String::from("root=/dev/vda rw");
+        assert_eq!(config.boot_cmdline(), "root=/dev/vda rw");
+
+        // No console is present, serial console is appended.
+        config.auto_serial_console = true;
+        assert_eq!(
+            config.boot_cmdline(),
+            "root=/dev/vda rw console=ttyS0,115200"
+        );
+        config.kernel_cmdline = String::new();
+        assert_eq!(config.boot_cmdline(), "console=ttyS0,115200");
+        // The params are kept as they are, only the trailing spaces are trimmed.
+        config.kernel_cmdline = String::from("quiet quiet  rw ");
+        assert_eq!(
+            config.boot_cmdline(),
+            "quiet quiet  rw console=ttyS0,115200"
+        );
+
+        // The existing console is left alone.
+        config.kernel_cmdline = String::from("console=tty0 root=/dev/vda");
+        assert_eq!(config.boot_cmdline(), "console=tty0 root=/dev/vda");
+        config.kernel_cmdline = String::from("root=/dev/vda console=hvc0");
+        assert_eq!(config.boot_cmdline(), "root=/dev/vda console=hvc0");
+        // Param which is not console.
+        config.kernel_cmdline = String::from("earlyconsole=ttyS1");
+        assert_eq!(
+            config.boot_cmdline(),
+            "earlyconsole=ttyS1 console=ttyS0,115200"
+        );
+    }
+}
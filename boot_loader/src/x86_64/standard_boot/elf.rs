@@ -10,7 +10,6 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 use std::sync::Arc;
 
@@ -83,7 +82,10 @@ impl Elf64Header {
         Ok(())
     }
 
-    fn parse_prog_hdrs(&self, kernel_image: &mut File) -> Result<Vec<Elf64ProgHeader>> {
+    fn parse_prog_hdrs<R: Read + Seek>(
+        &self,
+        kernel_image: &mut R,
+    ) -> Result<Vec<Elf64ProgHeader>> {
         kernel_image.seek(SeekFrom::Start(self.e_phoff))?;
 
         let mut elf_phs = Vec::with_capacity(self.e_phnum as usize);
@@ -128,13 +130,13 @@ impl ByteCode for Elf64NoteHeader {}
 /// `kernel_image` - ELF-format kernel file.
 /// `sys_mem` - Guest memory.
 /// `fwcfg` - FwCfg device.
-pub fn load_elf_kernel(
-    kernel_image: &mut File,
+pub fn load_elf_kernel<R: Read + Seek>(
+    kernel_image: &mut R,
     sys_mem: &Arc<AddressSpace>,
     fwcfg: &mut dyn FwCfgOps,
 ) -> Result<()> {
+    let kernel_length = kernel_image.seek(SeekFrom::End(0))?;
     kernel_image.seek(SeekFrom::Start(0))?;
-    let kernel_length = kernel_image.metadata().map(|m| m.len())?;
 
     let mut elf_header = Elf64Header::default();
     kernel_image.read_exact(elf_header.as_mut_bytes())?;
@@ -14,7 +14,7 @@
 mod elf;
 
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::sync::Arc;
 
 use address_space::AddressSpace;
@@ -24,20 +24,20 @@ use util::byte_code::ByteCode;
 
 use self::elf::load_elf_kernel;
 use super::bootparam::RealModeKernelHeader;
-use super::X86BootLoaderConfig;
+use super::{initrd_addr_limit, initrd_load_addr, X86BootLoaderConfig};
 use super::{BOOT_HDR_START, CMDLINE_START};
 use crate::error::BootLoaderError;
 use crate::x86_64::bootparam::{E820Entry, E820_RAM, E820_RESERVED, UEFI_OVMF_ID};
-use crate::x86_64::{INITRD_ADDR_MAX, SETUP_START};
+use crate::x86_64::{INITRD_ALIGN, SETUP_START};
 use anyhow::{bail, Context, Result};
 
-fn load_image(
-    image: &mut File,
+fn load_image<R: Read + Seek>(
+    image: &mut R,
     file_offset: u64,
     key: FwCfgEntryType,
     fwcfg: &mut dyn FwCfgOps,
 ) -> Result<()> {
-    let file_len = image.metadata().unwrap().len();
+    let file_len = image.seek(SeekFrom::End(0))?;
     if file_offset >= file_len {
         bail!(
             "File offset 0x{:x} overflows file length 0x{:x}",
@@ -54,8 +54,8 @@ fn load_image(
     Ok(())
 }
 
-fn load_kernel_image(
-    kernel_image: &mut File,
+fn load_kernel_image<R: Read + Seek>(
+    kernel_image: &mut R,
     header: &RealModeKernelHeader,
     fwcfg: &mut dyn FwCfgOps,
 ) -> Result<Vec<u8>> {
@@ -69,7 +69,7 @@ fn load_kernel_image(
     kernel_image.seek(SeekFrom::Start(0))?;
     kernel_image.read_exact(setup_data.as_mut_slice())?;
 
-    let kernel_size = kernel_image.metadata().unwrap().len() - setup_size;
+    let kernel_size = kernel_image.seek(SeekFrom::End(0))? - setup_size;
     load_image(kernel_image, setup_size, FwCfgEntryType::KernelData, fwcfg)
         .with_context(|| "Failed to load kernel image")?;
 
@@ -97,15 +97,12 @@ fn load_initrd(
         info!("No initrd image file.");
         return Ok(());
     };
-    let mut initrd_addr_max = INITRD_ADDR_MAX;
-    if initrd_addr_max > sys_mem.memory_end_address().raw_value() {
-        initrd_addr_max = sys_mem.memory_end_address().raw_value();
-    };
+    let initrd_addr_max = initrd_addr_limit(header, sys_mem.memory_end_address().raw_value());
 
     let mut initrd_image = File::open(config.initrd.as_ref().unwrap())
         .with_context(|| BootLoaderError::BootLoaderOpenInitrd)?;
     let initrd_size = initrd_image.metadata().unwrap().len();
-    let initrd_addr = (initrd_addr_max - initrd_size) & !0xfff_u64;
+    let initrd_addr = initrd_load_addr(initrd_addr_max, initrd_size, INITRD_ALIGN)?;
 
     load_image(&mut initrd_image, 0, FwCfgEntryType::InitrdData, fwcfg)
         .with_context(|| "Failed to load initrd")?;
@@ -126,11 +123,16 @@ fn load_initrd(
     Ok(())
 }
 
-fn setup_e820_table(
-    config: &X86BootLoaderConfig,
-    sys_mem: &Arc<AddressSpace>,
-    fwcfg: &mut dyn FwCfgOps,
-) -> Result<()> {
+/// Summary of the E820 table published to guest through FwCfg.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct E820Summary {
+    /// Number of E820 entries.
+    pub entries: usize,
+    /// Total size of RAM described by the E820 entries.
+    pub ram_size: u64,
+}
+
+fn build_e820_table(config: &X86BootLoaderConfig, sys_mem: &Arc<AddressSpace>) -> Vec<E820Entry> {
     let mut e820_table: Vec<E820Entry> = Vec::new();
     let mem_end = sys_mem.memory_end_address().raw_value();
     let mem_below_4g = std::cmp::min(mem_end, config.gap_range.0);
@@ -151,7 +153,23 @@ fn setup_e820_table(
     } else {
         error!("The page-table and TSS address is not provided");
     }
+    e820_table
+}
 
+fn setup_e820_table(
+    config: &X86BootLoaderConfig,
+    sys_mem: &Arc<AddressSpace>,
+    fwcfg: &mut dyn FwCfgOps,
+) -> Result<E820Summary> {
+    let e820_table = build_e820_table(config, sys_mem);
+    let summary = E820Summary {
+        entries: e820_table.len(),
+        ram_size: e820_table
+            .iter()
+            .filter(|entry| entry.is_ram())
+            .map(|entry| entry.size())
+            .sum(),
+    };
     let bytes = e820_table.iter().fold(Vec::new(), |mut bytes, entry| {
         bytes.extend(entry.as_bytes());
         bytes
@@ -159,7 +177,7 @@ fn setup_e820_table(
     fwcfg
         .add_file_entry("etc/e820", bytes)
         .with_context(|| "Failed to add e820 file entry to FwCfg")?;
-    Ok(())
+    Ok(summary)
 }
 
 fn load_kernel_cmdline(
@@ -167,7 +185,8 @@ fn load_kernel_cmdline(
     boot_hdr: &mut RealModeKernelHeader,
     fwcfg: &mut dyn FwCfgOps,
 ) -> Result<()> {
-    let cmdline_len = config.kernel_cmdline.len() as u32;
+    let cmdline = config.boot_cmdline();
+    let cmdline_len = cmdline.len() as u32;
     boot_hdr.set_cmdline(CMDLINE_START as u32, cmdline_len);
 
     fwcfg
@@ -184,7 +203,7 @@ fn load_kernel_cmdline(
         )
         .with_context(|| "Failed to add cmdline-size entry to FwCfg")?;
     fwcfg
-        .add_string_entry(FwCfgEntryType::CmdlineData, config.kernel_cmdline.as_ref())
+        .add_string_entry(FwCfgEntryType::CmdlineData, cmdline.as_ref())
         .with_context(|| "Failed to add cmdline-data entry to FwCfg")?;
 
     Ok(())
@@ -197,40 +216,58 @@ fn load_kernel_cmdline(
 /// * `config` - Boot source config, contains kernel, initrd and kernel cmdline.
 /// * `sys_mem` - Guest memory.
 /// * `fwcfg` - FwCfg device.
+/// * `kernel_data` - Kernel image fetched already, which is used instead of
+///   the kernel path in `config`.
+///
+/// # Returns
+///
+/// The summary of the E820 table published through FwCfg.
 pub fn load_linux(
     config: &X86BootLoaderConfig,
     sys_mem: &Arc<AddressSpace>,
     fwcfg: &mut dyn FwCfgOps,
-) -> Result<()> {
+    kernel_data: Option<Vec<u8>>,
+) -> Result<E820Summary> {
+    if let Some(data) = kernel_data {
+        return load_kernel(config, sys_mem, fwcfg, &mut Cursor::new(data));
+    }
     if config.kernel.is_none() {
-        setup_e820_table(config, sys_mem, fwcfg)?;
-        return Ok(());
+        return setup_e820_table(config, sys_mem, fwcfg);
     }
 
     let mut kernel_image = File::open(config.kernel.as_ref().unwrap().clone())
         .with_context(|| BootLoaderError::BootLoaderOpenKernel)?;
+    load_kernel(config, sys_mem, fwcfg, &mut kernel_image)
+}
 
+fn load_kernel<R: Read + Seek>(
+    config: &X86BootLoaderConfig,
+    sys_mem: &Arc<AddressSpace>,
+    fwcfg: &mut dyn FwCfgOps,
+    kernel_image: &mut R,
+) -> Result<E820Summary> {
     let mut boot_header = RealModeKernelHeader::default();
     kernel_image.seek(SeekFrom::Start(BOOT_HDR_START))?;
     kernel_image.read_exact(boot_header.as_mut_bytes())?;
     boot_header.type_of_loader = UEFI_OVMF_ID;
 
     load_kernel_cmdline(config, &mut boot_header, fwcfg)?;
-    setup_e820_table(config, sys_mem, fwcfg)?;
+    let e820 = setup_e820_table(config, sys_mem, fwcfg)?;
     load_initrd(config, sys_mem, &mut boot_header, fwcfg)?;
     if let Err(e) = boot_header.check_valid_kernel() {
         if let Some(err) = e.downcast_ref::<BootLoaderError>() {
             match err {
                 BootLoaderError::ElfKernel => {
-                    load_elf_kernel(&mut kernel_image, sys_mem, fwcfg)?;
-                    return Ok(());
+                    load_elf_kernel(kernel_image, sys_mem, fwcfg)?;
+                    return Ok(e820);
                 }
                 _ => return Err(e),
             }
         }
     }
 
-    let mut setup_data = load_kernel_image(&mut kernel_image, &boot_header, fwcfg)?;
+    boot_header.check_loader_type()?;
+    let mut setup_data = load_kernel_image(kernel_image, &boot_header, fwcfg)?;
     let min_setup_len = std::cmp::min(
         setup_data.len(),
         BOOT_HDR_START as usize + boot_header.as_bytes().len(),
@@ -254,5 +291,60 @@ pub fn load_linux(
         .add_data_entry(FwCfgEntryType::SetupData, setup_data)
         .with_context(|| "Failed to add setup-data entry to FwCfg")?;
 
-    Ok(())
+    Ok(e820)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use address_space::{GuestAddress, HostMemMapping, HostMemOptions, Region};
+    use devices::legacy::FwCfgIO;
+
+    fn create_space(size: u64) -> Arc<AddressSpace> {
+        let root = Region::init_container_region(size, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, size, None, HostMemOptions::default())
+                .unwrap(),
+        );
+        let region = Region::init_ram_region(ram.clone(), "ram");
+        root.add_subregion(region, ram.start_address().raw_value())
+            .unwrap();
+        space
+    }
+
+    #[test]
+    fn test_e820_summary() {
+        let space = create_space(0x2000_0000);
+        let mut config = X86BootLoaderConfig {
+            kernel: None,
+            initrd: None,
+            kernel_cmdline: String::new(),
+            cpu_count: 1,
+            gap_range: (0x800_0000, 0x800_0000),
+            ioapic_addr: 0xFEC0_0000,
+            lapic_addr: 0xFEE0_0000,
+            ident_tss_range: Some((0x7FF0_0000, 0x10_0000)),
+            prot64_mode: false,
+            five_level_paging: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: false,
+            auto_serial_console: false,
+        };
+
+        let mut fwcfg = FwCfgIO::new(space.clone());
+        let summary = load_linux(&config, &space, &mut fwcfg, None).unwrap();
+        assert_eq!(summary.entries, build_e820_table(&config, &space).len());
+        assert_eq!(summary.entries, 3);
+        assert_eq!(summary.ram_size, 0x1800_0000);
+
+        // No RAM above the gap and no identity map.
+        config.gap_range = (0x1000_0000, 0x1000_0000);
+        config.ident_tss_range = None;
+        let mut fwcfg = FwCfgIO::new(space.clone());
+        let summary = load_linux(&config, &space, &mut fwcfg, None).unwrap();
+        assert_eq!(summary.entries, build_e820_table(&config, &space).len());
+        assert_eq!(summary.entries, 1);
+        assert_eq!(summary.ram_size, 0x1000_0000);
+    }
 }
@@ -42,6 +42,15 @@ pub enum CpuError {
     VcpuLocalThreadNotPresent,
     #[error("No Machine Interface saved in CPU")]
     NoMachineInterface,
+    #[cfg(target_arch = "x86_64")]
+    #[error("Unsupported TSC frequency {0} kHz, host TSC frequency is {1} kHz!")]
+    TscFrequency(u64, u32),
+    #[cfg(target_arch = "x86_64")]
+    #[error("Failed to inject NMI: {0}!")]
+    InjectNmi(String),
+    #[cfg(target_arch = "x86_64")]
+    #[error("Failed to dump registers: {0}!")]
+    DumpRegisters(String),
     #[cfg(target_arch = "aarch64")]
     #[error("Failed to get system register: {0}!")]
     GetSysRegister(String),
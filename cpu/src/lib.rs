@@ -32,6 +32,8 @@
 #[cfg(target_arch = "aarch64")]
 mod aarch64;
 #[cfg(target_arch = "x86_64")]
+mod vcpu;
+#[cfg(target_arch = "x86_64")]
 mod x86_64;
 
 pub mod error;
@@ -61,6 +63,8 @@ pub use x86_64::X86CPUBootConfig as CPUBootConfig;
 pub use x86_64::X86CPUState as ArchCPU;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::X86CPUTopology as CPUTopology;
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::{check_la57, CpuFeatureFilter, CpuidMask};
 
 use std::cell::RefCell;
 use std::sync::atomic::{fence, AtomicBool, Ordering};
@@ -90,6 +94,10 @@ const VCPU_TASK_SIGNAL: i32 = 35;
 const VCPU_RESET_SIGNAL: i32 = 35;
 #[cfg(target_env = "musl")]
 const VCPU_RESET_SIGNAL: i32 = 36;
+#[cfg(all(target_arch = "x86_64", not(target_env = "musl")))]
+const VCPU_NMI_SIGNAL: i32 = 36;
+#[cfg(all(target_arch = "x86_64", target_env = "musl"))]
+const VCPU_NMI_SIGNAL: i32 = 37;
 
 /// Watch `0x3ff` IO port to record the magic value trapped from guest kernel.
 #[cfg(all(target_arch = "x86_64", feature = "boot_time"))]
@@ -189,6 +197,9 @@ pub struct CPU {
     boot_state: Arc<Mutex<ArchCPU>>,
     /// Sync the pause state of vCPU in kvm and userspace.
     pause_signal: Arc<AtomicBool>,
+    /// The filter which hides CPUID features of host from this VCPU.
+    #[cfg(target_arch = "x86_64")]
+    cpuid_filter: Mutex<CpuFeatureFilter>,
 }
 
 impl CPU {
@@ -217,6 +228,8 @@ impl CPU {
             caps: CPUCaps::init_capabilities(),
             boot_state: Arc::new(Mutex::new(ArchCPU::default())),
             pause_signal: Arc::new(AtomicBool::new(false)),
+            #[cfg(target_arch = "x86_64")]
+            cpuid_filter: Mutex::new(CpuFeatureFilter::default()),
         }
     }
 
@@ -244,6 +257,51 @@ impl CPU {
         &self.arch_cpu
     }
 
+    /// Pin the TSC frequency of this `CPU`.
+    ///
+    /// # Arguments
+    ///
+    /// * `khz` - The TSC frequency in kHz.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_tsc_frequency(&self, khz: u64) -> Result<()> {
+        x86_64::set_tsc_frequency(self.fd.as_ref(), self.caps.has_tsc_control, khz)
+            .with_context(|| format!("Failed to set TSC frequency for CPU {}/KVM", self.id))
+    }
+
+    /// Set the filter which hides CPUID features of host, it's applied when
+    /// this `CPU` is reset.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The filter of CPUID features.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_cpuid_filter(&self, filter: CpuFeatureFilter) {
+        *self.cpuid_filter.lock().unwrap() = filter;
+    }
+
+    /// Inject NMI into this `CPU`, which should be running. The NMI is
+    /// queued in the vcpu thread.
+    #[cfg(target_arch = "x86_64")]
+    pub fn inject_nmi(&self) -> Result<()> {
+        let state = *self.state.0.lock().unwrap();
+        x86_64::inject_nmi(self, self.id, state)
+    }
+
+    /// Dump the registers of this `CPU`. The `CPU` must not be running,
+    /// otherwise getting registers blocks until the guest exits from kvm.
+    #[cfg(target_arch = "x86_64")]
+    pub fn dump_registers(&self) -> Result<qmp_schema::RegisterDump> {
+        let state = *self.state.0.lock().unwrap();
+        if state == CpuLifecycleState::Running {
+            return Err(anyhow!(CpuError::DumpRegisters(format!(
+                "CPU {}/KVM is running, pause it first",
+                self.id
+            ))));
+        }
+        vcpu::dump_registers(self.fd.as_ref())
+            .with_context(|| format!("Failed to dump registers of CPU {}/KVM", self.id))
+    }
+
     /// Set task the `CPU` to handle.
     fn set_task(&self, task: Option<thread::JoinHandle<()>>) {
         let mut data = self.task.lock().unwrap();
@@ -580,6 +638,22 @@ impl CPUInterface for CPU {
     }
 }
 
+#[cfg(target_arch = "x86_64")]
+impl x86_64::NmiInjector for CPU {
+    fn nmi(&self) -> Result<()> {
+        let task = self.task.lock().unwrap();
+        match task.as_ref() {
+            Some(thread) => thread
+                .kill(VCPU_NMI_SIGNAL)
+                .with_context(|| CpuError::InjectNmi("Fail to kick vcpu".to_string())),
+            None => Err(anyhow!(CpuError::InjectNmi(format!(
+                "CPU {}/KVM thread not started",
+                self.id
+            )))),
+        }
+    }
+}
+
 /// The struct to handle events in cpu thread.
 #[allow(clippy::upper_case_acronyms)]
 struct CPUThreadWorker {
@@ -635,11 +709,21 @@ impl CPUThreadWorker {
                             &vcpu.fd,
                             #[cfg(target_arch = "x86_64")]
                             &vcpu.caps,
+                            #[cfg(target_arch = "x86_64")]
+                            &vcpu.cpuid_filter.lock().unwrap(),
                         ) {
                             error!("Failed to reset vcpu state: {:?}", e)
                         }
                     });
                 }
+                #[cfg(target_arch = "x86_64")]
+                VCPU_NMI_SIGNAL => {
+                    let _ = CPUThreadWorker::run_on_local_thread_vcpu(|vcpu| {
+                        if let Err(e) = x86_64::NmiInjector::nmi(vcpu.fd.as_ref()) {
+                            error!("Failed to inject NMI: {:?}", e)
+                        }
+                    });
+                }
                 _ => {}
             }
         }
@@ -648,6 +732,9 @@ impl CPUThreadWorker {
             .with_context(|| "Failed to register VCPU_TASK_SIGNAL signal.")?;
         register_signal_handler(VCPU_RESET_SIGNAL, handle_signal)
             .with_context(|| "Failed to register VCPU_TASK_SIGNAL signal.")?;
+        #[cfg(target_arch = "x86_64")]
+        register_signal_handler(VCPU_NMI_SIGNAL, handle_signal)
+            .with_context(|| "Failed to register VCPU_NMI_SIGNAL signal.")?;
 
         Ok(())
     }
@@ -702,6 +789,8 @@ impl CPUThreadWorker {
                 &self.thread_cpu.fd,
                 #[cfg(target_arch = "x86_64")]
                 &self.thread_cpu.caps,
+                #[cfg(target_arch = "x86_64")]
+                &self.thread_cpu.cpuid_filter.lock().unwrap(),
             )
             .with_context(|| "Failed to reset for cpu register state")?;
 
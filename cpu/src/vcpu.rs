@@ -0,0 +1,103 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{Context, Result};
+use kvm_ioctls::VcpuFd;
+use machine_manager::qmp::qmp_schema::RegisterDump;
+
+/// Dump the general purpose registers, control registers and segment
+/// selectors of KVM vcpu.
+///
+/// # Arguments
+///
+/// * `kvm_fd` - Vcpu file descriptor in kvm.
+pub fn dump_registers(kvm_fd: &VcpuFd) -> Result<RegisterDump> {
+    let regs = kvm_fd.get_regs().with_context(|| "Failed to get regs")?;
+    let sregs = kvm_fd.get_sregs().with_context(|| "Failed to get sregs")?;
+
+    Ok(RegisterDump {
+        rax: regs.rax,
+        rbx: regs.rbx,
+        rcx: regs.rcx,
+        rdx: regs.rdx,
+        rsi: regs.rsi,
+        rdi: regs.rdi,
+        rsp: regs.rsp,
+        rbp: regs.rbp,
+        r8: regs.r8,
+        r9: regs.r9,
+        r10: regs.r10,
+        r11: regs.r11,
+        r12: regs.r12,
+        r13: regs.r13,
+        r14: regs.r14,
+        r15: regs.r15,
+        rip: regs.rip,
+        rflags: regs.rflags,
+        cr0: sregs.cr0,
+        cr2: sregs.cr2,
+        cr3: sregs.cr3,
+        cr4: sregs.cr4,
+        cs: sregs.cs.selector,
+        ds: sregs.ds.selector,
+        es: sregs.es.selector,
+        fs: sregs.fs.selector,
+        gs: sregs.gs.selector,
+        ss: sregs.ss.selector,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use kvm_ioctls::Kvm;
+
+    use super::*;
+
+    #[test]
+    fn test_dump_registers() {
+        let kvm = match Kvm::new() {
+            Ok(kvm) => kvm,
+            Err(_) => return,
+        };
+        let vm_fd = kvm.create_vm().unwrap();
+        let vcpu = vm_fd.create_vcpu(0).unwrap();
+
+        let mut regs = vcpu.get_regs().unwrap();
+        regs.rax = 0x1234_5678;
+        regs.r15 = 0xdead_beef;
+        regs.rsp = 0x8ff0;
+        regs.rip = 0xfff0;
+        regs.rflags = 0x2;
+        vcpu.set_regs(&regs).unwrap();
+        let mut sregs = vcpu.get_sregs().unwrap();
+        sregs.cs.base = 0xf_0000;
+        sregs.cs.selector = 0xf000;
+        sregs.ss.selector = 0x18;
+        sregs.cr3 = 0x9000;
+        vcpu.set_sregs(&sregs).unwrap();
+
+        let dump = dump_registers(&vcpu).unwrap();
+        assert_eq!(dump.rax, 0x1234_5678);
+        assert_eq!(dump.r15, 0xdead_beef);
+        assert_eq!(dump.rsp, 0x8ff0);
+        assert_eq!(dump.rip, 0xfff0);
+        assert_eq!(dump.rflags, 0x2);
+        assert_eq!(dump.cr0, sregs.cr0);
+        assert_eq!(dump.cr3, 0x9000);
+        assert_eq!(dump.cs, 0xf000);
+        assert_eq!(dump.ss, 0x18);
+
+        let value = serde_json::to_value(&dump).unwrap();
+        assert_eq!(value["rax"], 0x1234_5678);
+        assert_eq!(value["cs"], 0xf000);
+    }
+}
@@ -30,6 +30,7 @@ const MSR_IA32_VMX_VMFUNC: ::std::os::raw::c_uint = 0x491;
 pub struct X86CPUCaps {
     pub has_xsave: bool,
     pub has_xcrs: bool,
+    pub has_tsc_control: bool,
     supported_msrs: Vec<u32>,
 }
 
@@ -41,6 +42,7 @@ impl X86CPUCaps {
         X86CPUCaps {
             has_xsave: kvm.check_extension(Cap::Xsave),
             has_xcrs: kvm.check_extension(Cap::Xcrs),
+            has_tsc_control: kvm.check_extension(Cap::TscControl),
             supported_msrs: kvm.get_msr_index_list().unwrap().as_slice().to_vec(),
         }
     }
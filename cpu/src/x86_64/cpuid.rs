@@ -10,7 +10,11 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::HashMap;
+
 use core::arch::x86_64::__cpuid_count;
+use kvm_bindings::kvm_cpuid_entry2;
+use machine_manager::config::CpuConfig;
 
 pub fn host_cpuid(
     leaf: u32,
@@ -29,3 +33,128 @@ pub fn host_cpuid(
         *edx = cpuid.edx;
     }
 }
+
+/// Mask of the registers of one CPUID leaf.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuidMask {
+    pub eax_mask: u32,
+    pub ebx_mask: u32,
+    pub ecx_mask: u32,
+    pub edx_mask: u32,
+}
+
+impl Default for CpuidMask {
+    fn default() -> Self {
+        CpuidMask {
+            eax_mask: u32::MAX,
+            ebx_mask: u32::MAX,
+            ecx_mask: u32::MAX,
+            edx_mask: u32::MAX,
+        }
+    }
+}
+
+/// Filter which hides CPUID features of host from guest.
+#[derive(Clone, Debug, Default)]
+pub struct CpuFeatureFilter {
+    /// Masks indexed by (leaf, subleaf).
+    pub leaves: HashMap<(u32, u32), CpuidMask>,
+}
+
+impl CpuFeatureFilter {
+    /// Mask the registers of the cpuid entry if its leaf is in filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The cpuid entry which will be exposed to guest.
+    pub fn apply(&self, entry: &mut kvm_cpuid_entry2) {
+        if let Some(mask) = self.leaves.get(&(entry.function, entry.index)) {
+            entry.eax &= mask.eax_mask;
+            entry.ebx &= mask.ebx_mask;
+            entry.ecx &= mask.ecx_mask;
+            entry.edx &= mask.edx_mask;
+        }
+    }
+}
+
+impl From<&CpuConfig> for CpuFeatureFilter {
+    fn from(conf: &CpuConfig) -> Self {
+        let mut leaves = HashMap::new();
+        for leaf in conf.feature_mask.iter() {
+            let mask: &mut CpuidMask = leaves.entry((leaf.leaf, leaf.subleaf)).or_default();
+            mask.eax_mask &= leaf.eax.unwrap_or(u32::MAX);
+            mask.ebx_mask &= leaf.ebx.unwrap_or(u32::MAX);
+            mask.ecx_mask &= leaf.ecx.unwrap_or(u32::MAX);
+            mask.edx_mask &= leaf.edx.unwrap_or(u32::MAX);
+        }
+        CpuFeatureFilter { leaves }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use machine_manager::config::CpuidMaskConfig;
+
+    use super::*;
+
+    fn cpuid_entry(function: u32, index: u32) -> kvm_cpuid_entry2 {
+        kvm_cpuid_entry2 {
+            function,
+            index,
+            eax: 0xffff_ffff,
+            ebx: 0xffff_ffff,
+            ecx: 0xffff_ffff,
+            edx: 0xffff_ffff,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cpu_feature_filter() {
+        let conf = CpuConfig {
+            feature_mask: vec![
+                // Hide SGX.
+                CpuidMaskConfig {
+                    leaf: 0x7,
+                    ebx: Some(!(1 << 2)),
+                    ..Default::default()
+                },
+                // Hide AMX.
+                CpuidMaskConfig {
+                    leaf: 0x7,
+                    edx: Some(!(0x7 << 22)),
+                    ..Default::default()
+                },
+                CpuidMaskConfig {
+                    leaf: 0xd,
+                    subleaf: 1,
+                    eax: Some(0),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let filter = CpuFeatureFilter::from(&conf);
+        assert_eq!(filter.leaves.len(), 2);
+
+        let mut entry = cpuid_entry(0x7, 0);
+        filter.apply(&mut entry);
+        assert_eq!(entry.eax, 0xffff_ffff);
+        assert_eq!(entry.ebx, 0xffff_fffb);
+        assert_eq!(entry.ecx, 0xffff_ffff);
+        assert_eq!(entry.edx, 0xfe3f_ffff);
+
+        let mut entry = cpuid_entry(0xd, 1);
+        filter.apply(&mut entry);
+        assert_eq!(entry.eax, 0);
+        assert_eq!(entry.ebx, 0xffff_ffff);
+
+        // Subleaf which is not in filter is kept unchanged.
+        let mut entry = cpuid_entry(0xd, 0);
+        filter.apply(&mut entry);
+        assert_eq!(entry.eax, 0xffff_ffff);
+        let mut entry = cpuid_entry(0x1, 0);
+        filter.apply(&mut entry);
+        assert_eq!(entry.ecx, 0xffff_ffff);
+    }
+}
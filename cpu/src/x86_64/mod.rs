@@ -12,6 +12,8 @@
 
 pub mod caps;
 mod cpuid;
+mod nmi;
+mod tsc;
 
 use std::sync::{Arc, Mutex};
 
@@ -30,11 +32,15 @@ use migration_derive::{ByteCode, Desc};
 use util::byte_code::ByteCode;
 
 use self::cpuid::host_cpuid;
+pub use self::cpuid::{CpuFeatureFilter, CpuidMask};
+pub use self::nmi::{inject_nmi, NmiInjector};
+pub use self::tsc::{set_tsc_frequency, TscFrequency};
 use crate::CPU;
 
 const ECX_EPB_SHIFT: u32 = 3;
 const X86_FEATURE_HYPERVISOR: u32 = 31;
 const X86_FEATURE_TSC_DEADLINE_TIMER: u32 = 24;
+const X86_FEATURE_LA57: u32 = 16;
 
 const MSR_LIST: &[u32] = &[
     0x0174,      // MSR_IA32_SYSENTER_CS
@@ -50,6 +56,10 @@ const MSR_LIST: &[u32] = &[
     0x2ff,       // MSR_MTRRdefType
 ];
 
+// X86_CR4_LA57: enable 5-level paging
+// arch/x86/include/uapi/asm/processor-flags.h
+const X86_CR4_LA57: u64 = 0x1000;
+
 const MSR_IA32_MISC_ENABLE: u32 = 0x01a0;
 const MSR_IA32_MISC_ENABLE_FAST_STRING: u64 = 0x1;
 
@@ -78,7 +88,10 @@ pub struct X86CPUBootConfig {
     pub gdt_size: u16,
     pub idt_base: u64,
     pub idt_size: u16,
+    /// Address of the top level page table, PML5 table if `la57` is set.
     pub pml4_start: u64,
+    /// Enable 5-level paging.
+    pub la57: bool,
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -102,6 +115,33 @@ impl X86CPUTopology {
     }
 }
 
+/// Check that LA57 (CPUID.(EAX=7,ECX=0):ECX[bit 16]) is exposed to guest,
+/// which is required to boot with 5-level paging.
+///
+/// # Arguments
+///
+/// * `cpuid_filter` - The filter of CPUID features which will be applied to guest.
+pub fn check_la57(cpuid_filter: &CpuFeatureFilter) -> Result<()> {
+    let sys_fd = match Kvm::new() {
+        Ok(fd) => fd,
+        _ => bail!("check_la57: Open /dev/kvm failed"),
+    };
+    let cpuid = sys_fd
+        .get_supported_cpuid(KVM_MAX_CPUID_ENTRIES)
+        .with_context(|| "Failed to get supported cpuid of KVM")?;
+    let mut entry = cpuid
+        .as_slice()
+        .iter()
+        .find(|entry| entry.function == 7 && entry.index == 0)
+        .copied()
+        .unwrap_or_default();
+    cpuid_filter.apply(&mut entry);
+    if entry.ecx & (1u32 << X86_FEATURE_LA57) == 0 {
+        bail!("5-level paging (la57) requires LA57 in guest CPUID, which is not supported by host or hidden by feature-mask");
+    }
+    Ok(())
+}
+
 /// The state of vCPU's register.
 #[allow(clippy::upper_case_acronyms)]
 #[repr(C)]
@@ -209,10 +249,108 @@ impl X86CPUState {
     ///
     /// * `vcpu_fd` - Vcpu file descriptor in kvm.
     /// * `caps` - Vcpu capabilities in kvm.
-    pub fn reset_vcpu(&self, vcpu_fd: &Arc<VcpuFd>, caps: &caps::X86CPUCaps) -> Result<()> {
-        self.setup_cpuid(vcpu_fd)
+    /// * `cpuid_filter` - Filter which hides CPUID features of host.
+    pub fn reset_vcpu(
+        &self,
+        vcpu_fd: &Arc<VcpuFd>,
+        caps: &caps::X86CPUCaps,
+        cpuid_filter: &CpuFeatureFilter,
+    ) -> Result<()> {
+        self.setup_cpuid(vcpu_fd, cpuid_filter)
             .with_context(|| format!("Failed to set cpuid for CPU {}", self.apic_id))?;
+        self.restore(vcpu_fd, caps)
+    }
+
+    /// Save the registers of KVM vcpu to `X86CPUState`.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    /// * `caps` - Vcpu capabilities in kvm.
+    pub fn save(&mut self, vcpu_fd: &VcpuFd, caps: &caps::X86CPUCaps) -> Result<()> {
+        let mut msr_entries = caps.create_msr_entries()?;
+        if msr_entries.as_slice().len() > self.msr_list.len() {
+            bail!(
+                "Too many msrs {} for CPU {}",
+                msr_entries.as_slice().len(),
+                self.apic_id
+            );
+        }
+
+        self.mp_state = vcpu_fd
+            .get_mp_state()
+            .with_context(|| format!("Failed to get mpstate for CPU {}", self.apic_id))?;
+        self.regs = vcpu_fd
+            .get_regs()
+            .with_context(|| format!("Failed to get regs for CPU {}", self.apic_id))?;
+        self.sregs = vcpu_fd
+            .get_sregs()
+            .with_context(|| format!("Failed to get sregs for CPU {}", self.apic_id))?;
+        if caps.has_xsave {
+            self.xsave = vcpu_fd
+                .get_xsave()
+                .with_context(|| format!("Failed to get xsave for CPU {}", self.apic_id))?;
+        } else {
+            self.fpu = vcpu_fd
+                .get_fpu()
+                .with_context(|| format!("Failed to get fpu for CPU {}", self.apic_id))?;
+        }
+        if caps.has_xcrs {
+            self.xcrs = vcpu_fd
+                .get_xcrs()
+                .with_context(|| format!("Failed to get xcrs for CPU {}", self.apic_id))?;
+        }
+        self.debugregs = vcpu_fd
+            .get_debug_regs()
+            .with_context(|| format!("Failed to get debug register for CPU {}", self.apic_id))?;
+        self.msr_len = vcpu_fd
+            .get_msrs(&mut msr_entries)
+            .with_context(|| format!("Failed to get msrs for CPU {}", self.apic_id))?;
+        for (i, entry) in msr_entries.as_slice()[..self.msr_len].iter().enumerate() {
+            self.msr_list[i] = *entry;
+        }
+
+        self.save_irq_state(vcpu_fd)
+    }
 
+    /// Save the interrupt state of KVM vcpu, which includes the in-kernel
+    /// lapic and the pending exceptions, interrupts and NMI.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    pub fn save_irq_state(&mut self, vcpu_fd: &VcpuFd) -> Result<()> {
+        self.lapic = vcpu_fd
+            .get_lapic()
+            .with_context(|| format!("Failed to get lapic for CPU {}", self.apic_id))?;
+        self.cpu_events = vcpu_fd
+            .get_vcpu_events()
+            .with_context(|| format!("Failed to get vcpu events for CPU {}", self.apic_id))?;
+        Ok(())
+    }
+
+    /// Restore the interrupt state of KVM vcpu saved by `save_irq_state`.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    pub fn restore_irq_state(&self, vcpu_fd: &VcpuFd) -> Result<()> {
+        vcpu_fd
+            .set_lapic(&self.lapic)
+            .with_context(|| format!("Failed to set lapic for CPU {}", self.apic_id))?;
+        vcpu_fd
+            .set_vcpu_events(&self.cpu_events)
+            .with_context(|| format!("Failed to set vcpu events for CPU {}", self.apic_id))?;
+        Ok(())
+    }
+
+    /// Restore the registers of KVM vcpu from `X86CPUState`.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    /// * `caps` - Vcpu capabilities in kvm.
+    pub fn restore(&self, vcpu_fd: &VcpuFd, caps: &caps::X86CPUCaps) -> Result<()> {
         vcpu_fd
             .set_mp_state(self.mp_state)
             .with_context(|| format!("Failed to set mpstate for CPU {}", self.apic_id))?;
@@ -239,17 +377,11 @@ impl X86CPUState {
         vcpu_fd
             .set_debug_regs(&self.debugregs)
             .with_context(|| format!("Failed to set debug register for CPU {}", self.apic_id))?;
-        vcpu_fd
-            .set_lapic(&self.lapic)
-            .with_context(|| format!("Failed to set lapic for CPU {}", self.apic_id))?;
         vcpu_fd
             .set_msrs(&Msrs::from_entries(&self.msr_list[0..self.msr_len])?)
             .with_context(|| format!("Failed to set msrs for CPU {}", self.apic_id))?;
-        vcpu_fd
-            .set_vcpu_events(&self.cpu_events)
-            .with_context(|| format!("Failed to set vcpu events for CPU {}", self.apic_id))?;
 
-        Ok(())
+        self.restore_irq_state(vcpu_fd)
     }
 
     fn setup_lapic(&mut self, vcpu_fd: &Arc<VcpuFd>) -> Result<()> {
@@ -359,6 +491,9 @@ impl X86CPUState {
         // Setup page table
         self.sregs.cr3 = boot_config.pml4_start;
         self.sregs.cr4 |= X86_CR4_PAE;
+        if boot_config.la57 {
+            self.sregs.cr4 |= X86_CR4_LA57;
+        }
         self.sregs.cr0 |= X86_CR0_PG;
     }
 
@@ -420,7 +555,7 @@ impl X86CPUState {
         Ok(())
     }
 
-    fn setup_cpuid(&self, vcpu_fd: &Arc<VcpuFd>) -> Result<()> {
+    fn setup_cpuid(&self, vcpu_fd: &Arc<VcpuFd>, cpuid_filter: &CpuFeatureFilter) -> Result<()> {
         let core_offset = 32u32 - (self.nr_threads - 1).leading_zeros();
         let die_offset = (32u32 - (self.nr_cores - 1).leading_zeros()) + core_offset;
         let pkg_offset = (32u32 - (self.nr_dies - 1).leading_zeros()) + die_offset;
@@ -555,6 +690,9 @@ impl X86CPUState {
                 _ => (),
             }
         }
+        for entry in entries.iter_mut() {
+            cpuid_filter.apply(entry);
+        }
 
         vcpu_fd
             .set_cpuid2(&cpuid)
@@ -565,27 +703,8 @@ impl X86CPUState {
 
 impl StateTransfer for CPU {
     fn get_state_vec(&self) -> Result<Vec<u8>> {
-        let mut msr_entries = self.caps.create_msr_entries()?;
         let mut cpu_state_locked = self.arch_cpu.lock().unwrap();
-
-        cpu_state_locked.mp_state = self.fd.get_mp_state()?;
-        cpu_state_locked.regs = self.fd.get_regs()?;
-        cpu_state_locked.sregs = self.fd.get_sregs()?;
-        if self.caps.has_xsave {
-            cpu_state_locked.xsave = self.fd.get_xsave()?;
-        } else {
-            cpu_state_locked.fpu = self.fd.get_fpu()?;
-        }
-        if self.caps.has_xcrs {
-            cpu_state_locked.xcrs = self.fd.get_xcrs()?;
-        }
-        cpu_state_locked.debugregs = self.fd.get_debug_regs()?;
-        cpu_state_locked.lapic = self.fd.get_lapic()?;
-        cpu_state_locked.msr_len = self.fd.get_msrs(&mut msr_entries)?;
-        for (i, entry) in msr_entries.as_slice().iter().enumerate() {
-            cpu_state_locked.msr_list[i] = *entry;
-        }
-        cpu_state_locked.cpu_events = self.fd.get_vcpu_events()?;
+        cpu_state_locked.save(&self.fd, &self.caps)?;
 
         Ok(cpu_state_locked.as_bytes().to_vec())
     }
@@ -611,7 +730,7 @@ impl MigrationHook for CPU {}
 mod test {
     use super::*;
     use hypervisor::kvm::{KVMFds, KVM_FDS};
-    use kvm_bindings::kvm_segment;
+    use kvm_bindings::{kvm_segment, KVM_VCPUEVENT_VALID_NMI_PENDING};
     use serial_test::serial;
     use std::sync::Arc;
 
@@ -667,6 +786,7 @@ mod test {
             idt_base: 0x520u64,
             idt_size: 8,
             pml4_start: 0x0000_9000,
+            la57: false,
         };
 
         // For `get_lapic` in realize function to work,
@@ -681,7 +801,9 @@ mod test {
 
         // test setup special registers
         let cpu_caps = caps::X86CPUCaps::init_capabilities();
-        assert!(x86_cpu.reset_vcpu(&vcpu, &cpu_caps).is_ok());
+        assert!(x86_cpu
+            .reset_vcpu(&vcpu, &cpu_caps, &CpuFeatureFilter::default())
+            .is_ok());
         let x86_sregs = vcpu.get_sregs().unwrap();
         assert_eq!(x86_sregs.cs, code_seg);
         assert_eq!(x86_sregs.ds, data_seg);
@@ -697,6 +819,7 @@ mod test {
         assert_eq!((x86_sregs.cr0 & 0x8000_0000) >> 31, 1);
         assert_eq!(x86_sregs.cr3, cpu_config.pml4_start);
         assert_eq!((x86_sregs.cr4 & 0x20) >> 5, 1);
+        assert_eq!(x86_sregs.cr4 & 0x1000, 0);
         assert_eq!((x86_sregs.efer & 0x700) >> 8, 5);
 
         // test setup_regs function
@@ -712,5 +835,141 @@ mod test {
             let x86_fpu = vcpu.get_fpu().unwrap();
             assert_eq!(x86_fpu.fcw, 0x37f);
         }
+
+        // test the cpuid filter hides the hypervisor feature
+        let hypervisor = |vcpu: &VcpuFd| {
+            let cpuid = vcpu.get_cpuid2(KVM_MAX_CPUID_ENTRIES).unwrap();
+            let entry = cpuid
+                .as_slice()
+                .iter()
+                .find(|entry| entry.function == 1 && entry.index == 0)
+                .copied()
+                .unwrap();
+            entry.ecx & (1 << X86_FEATURE_HYPERVISOR) != 0
+        };
+        assert!(hypervisor(&vcpu));
+        let mut cpuid_filter = CpuFeatureFilter::default();
+        cpuid_filter.leaves.insert(
+            (1, 0),
+            CpuidMask {
+                ecx_mask: !(1 << X86_FEATURE_HYPERVISOR),
+                ..Default::default()
+            },
+        );
+        assert!(x86_cpu.reset_vcpu(&vcpu, &cpu_caps, &cpuid_filter).is_ok());
+        assert!(!hypervisor(&vcpu));
+    }
+
+    #[test]
+    fn test_x86_cpu_state_save_restore() {
+        let kvm = match Kvm::new() {
+            Ok(kvm) => kvm,
+            Err(_) => return,
+        };
+        let caps = caps::X86CPUCaps::init_capabilities();
+        let create_vcpu = || {
+            let vm_fd = kvm.create_vm().unwrap();
+            vm_fd.create_irq_chip().unwrap();
+            let vcpu_fd = vm_fd.create_vcpu(0).unwrap();
+            (vm_fd, vcpu_fd)
+        };
+        let msr = |data| kvm_msr_entry {
+            index: 0x0174, // MSR_IA32_SYSENTER_CS
+            data,
+            ..Default::default()
+        };
+
+        let (_src_vm, src) = create_vcpu();
+        let mut regs = src.get_regs().unwrap();
+        regs.rax = 0x1234_5678;
+        regs.rip = 0xfff0;
+        src.set_regs(&regs).unwrap();
+        let mut sregs = src.get_sregs().unwrap();
+        sregs.cr3 = 0x9000;
+        src.set_sregs(&sregs).unwrap();
+        if caps.has_xsave {
+            // FCW is in the legacy region, and x87 state is marked in XSTATE_BV.
+            let mut xsave = src.get_xsave().unwrap();
+            xsave.region[0] = 0x27f;
+            xsave.region[128] |= 0x1;
+            src.set_xsave(&xsave).unwrap();
+        } else {
+            let mut fpu = src.get_fpu().unwrap();
+            fpu.fcw = 0x27f;
+            src.set_fpu(&fpu).unwrap();
+        }
+        src.set_msrs(&Msrs::from_entries(&[msr(0x10)]).unwrap())
+            .unwrap();
+
+        let mut state = X86CPUState::new(0, 1);
+        state.save(&src, &caps).unwrap();
+        // The state is transferred as bytes in migration.
+        let state = *X86CPUState::from_bytes(state.as_bytes()).unwrap();
+
+        let (_dst_vm, dst) = create_vcpu();
+        state.restore(&dst, &caps).unwrap();
+        let regs = dst.get_regs().unwrap();
+        assert_eq!(regs.rax, 0x1234_5678);
+        assert_eq!(regs.rip, 0xfff0);
+        assert_eq!(dst.get_sregs().unwrap().cr3, 0x9000);
+        assert_eq!(dst.get_fpu().unwrap().fcw, 0x27f);
+        let mut msrs = Msrs::from_entries(&[msr(0)]).unwrap();
+        assert_eq!(dst.get_msrs(&mut msrs).unwrap(), 1);
+        assert_eq!(msrs.as_slice()[0].data, 0x10);
+    }
+
+    #[test]
+    fn test_x86_cpu_irq_state_save_restore() {
+        // See: arch/x86/include/asm/apicdef.h
+        const APIC_TASKPRI: usize = 0x80;
+
+        let kvm = match Kvm::new() {
+            Ok(kvm) => kvm,
+            Err(_) => return,
+        };
+        let create_vcpu = || {
+            let vm_fd = kvm.create_vm().unwrap();
+            vm_fd.create_irq_chip().unwrap();
+            let vcpu_fd = vm_fd.create_vcpu(0).unwrap();
+            (vm_fd, vcpu_fd)
+        };
+
+        let (_src_vm, src) = create_vcpu();
+        let mut lapic = src.get_lapic().unwrap();
+        lapic.regs[APIC_TASKPRI] = 0x20;
+        src.set_lapic(&lapic).unwrap();
+        let mut events = src.get_vcpu_events().unwrap();
+        events.nmi.pending = 1;
+        events.flags |= KVM_VCPUEVENT_VALID_NMI_PENDING;
+        src.set_vcpu_events(&events).unwrap();
+
+        let mut state = X86CPUState::new(0, 1);
+        state.save_irq_state(&src).unwrap();
+        let state = *X86CPUState::from_bytes(state.as_bytes()).unwrap();
+        assert_eq!(state.lapic.regs[APIC_TASKPRI], 0x20);
+        assert_eq!(state.cpu_events.nmi.pending, 1);
+
+        let (_dst_vm, dst) = create_vcpu();
+        assert_eq!(dst.get_vcpu_events().unwrap().nmi.pending, 0);
+        state.restore_irq_state(&dst).unwrap();
+        assert_eq!(dst.get_lapic().unwrap().regs[APIC_TASKPRI], 0x20);
+        assert_eq!(dst.get_vcpu_events().unwrap().nmi.pending, 1);
+    }
+
+    #[test]
+    fn test_check_la57() {
+        if Kvm::new().is_err() {
+            return;
+        }
+
+        let mut cpuid_filter = CpuFeatureFilter::default();
+        cpuid_filter.leaves.insert(
+            (7, 0),
+            CpuidMask {
+                ecx_mask: !(1 << X86_FEATURE_LA57),
+                ..Default::default()
+            },
+        );
+        assert!(check_la57(&cpuid_filter).is_err());
     }
 }
@@ -0,0 +1,99 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, Result};
+use hypervisor::kvm::KVM_NMI;
+use kvm_ioctls::VcpuFd;
+use vmm_sys_util::errno;
+use vmm_sys_util::ioctl::ioctl;
+
+use crate::{CpuError, CpuLifecycleState};
+
+/// Inject NMI into vcpu.
+pub trait NmiInjector {
+    /// Queue an NMI on the vcpu by `KVM_NMI`.
+    fn nmi(&self) -> Result<()>;
+}
+
+impl NmiInjector for VcpuFd {
+    fn nmi(&self) -> Result<()> {
+        // SAFETY: The file is a vcpu fd of KVM and `KVM_NMI` has no argument.
+        let ret = unsafe { ioctl(self, KVM_NMI()) };
+        if ret < 0 {
+            return Err(anyhow!(CpuError::InjectNmi(format!(
+                "KVM_NMI failed: {}",
+                errno::Error::last()
+            ))));
+        }
+        Ok(())
+    }
+}
+
+/// Inject NMI into vcpu, which is only allowed when the vcpu is running.
+///
+/// # Arguments
+///
+/// * `vcpu` - The vcpu to inject NMI into.
+/// * `id` - ID of the vcpu.
+/// * `state` - Lifecycle state of the vcpu.
+pub fn inject_nmi(vcpu: &dyn NmiInjector, id: u8, state: CpuLifecycleState) -> Result<()> {
+    if state != CpuLifecycleState::Running {
+        return Err(anyhow!(CpuError::InjectNmi(format!(
+            "CPU {}/KVM is in {:?} state",
+            id, state
+        ))));
+    }
+    vcpu.nmi()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockVcpu {
+        nmi_count: Cell<u32>,
+    }
+
+    impl NmiInjector for MockVcpu {
+        fn nmi(&self) -> Result<()> {
+            self.nmi_count.set(self.nmi_count.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_inject_nmi() {
+        let vcpu = MockVcpu::default();
+        inject_nmi(&vcpu, 0, CpuLifecycleState::Running).unwrap();
+        assert_eq!(vcpu.nmi_count.get(), 1);
+        inject_nmi(&vcpu, 0, CpuLifecycleState::Running).unwrap();
+        assert_eq!(vcpu.nmi_count.get(), 2);
+
+        // The vcpu is not running.
+        for state in [
+            CpuLifecycleState::Created,
+            CpuLifecycleState::Paused,
+            CpuLifecycleState::Stopping,
+            CpuLifecycleState::Stopped,
+        ] {
+            let err = inject_nmi(&vcpu, 1, state).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<CpuError>(),
+                Some(CpuError::InjectNmi(_))
+            ));
+        }
+        assert_eq!(vcpu.nmi_count.get(), 2);
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, Context, Result};
+use kvm_ioctls::VcpuFd;
+use log::warn;
+
+use crate::CpuError;
+
+/// The TSC frequency can be set without scaling if it is within 250 ppm of
+/// the host, the same as the tolerance of KVM.
+const TSC_TOLERANCE_PPM: u64 = 250;
+/// The maximum TSC scaling ratio, which is limited by AMD SVM.
+const TSC_MAX_SCALING_RATIO: u64 = 255;
+/// Warn if the TSC frequency is scaled by more than 10 percent.
+const TSC_SCALING_WARN_PERCENT: u64 = 10;
+
+/// Get and set the TSC frequency of vcpu.
+pub trait TscFrequency {
+    /// Get the TSC frequency in kHz by `KVM_GET_TSC_KHZ`.
+    fn get_tsc_khz(&self) -> Result<u32>;
+    /// Set the TSC frequency in kHz by `KVM_SET_TSC_KHZ`.
+    fn set_tsc_khz(&self, khz: u32) -> Result<()>;
+}
+
+impl TscFrequency for VcpuFd {
+    fn get_tsc_khz(&self) -> Result<u32> {
+        VcpuFd::get_tsc_khz(self).with_context(|| "Failed to get TSC frequency")
+    }
+
+    fn set_tsc_khz(&self, khz: u32) -> Result<()> {
+        VcpuFd::set_tsc_khz(self, khz).with_context(|| "Failed to set TSC frequency")
+    }
+}
+
+/// Pin the TSC frequency of vcpu, so that the guest timing is the same on
+/// hosts with different TSC frequency.
+///
+/// # Arguments
+///
+/// * `vcpu` - The vcpu whose TSC frequency is set.
+/// * `tsc_control` - Whether TSC scaling is supported by KVM.
+/// * `khz` - The TSC frequency in kHz.
+pub fn set_tsc_frequency(vcpu: &dyn TscFrequency, tsc_control: bool, khz: u64) -> Result<()> {
+    let host_khz = vcpu.get_tsc_khz()?;
+    let host = u64::from(host_khz);
+    let diff = host.abs_diff(khz);
+    let in_range = if diff * 1_000_000 <= host * TSC_TOLERANCE_PPM {
+        true
+    } else {
+        tsc_control && khz > 0 && khz <= host * TSC_MAX_SCALING_RATIO
+    };
+    if !in_range || khz > u64::from(u32::MAX) {
+        return Err(anyhow!(CpuError::TscFrequency(khz, host_khz)));
+    }
+    if diff * 100 > host * TSC_SCALING_WARN_PERCENT {
+        warn!(
+            "TSC frequency {} kHz is scaled from host TSC frequency {} kHz",
+            khz, host_khz
+        );
+    }
+
+    vcpu.set_tsc_khz(khz as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    struct MockVcpu {
+        host_khz: u32,
+        tsc_khz: Cell<Option<u32>>,
+    }
+
+    impl MockVcpu {
+        fn new(host_khz: u32) -> Self {
+            MockVcpu {
+                host_khz,
+                tsc_khz: Cell::new(None),
+            }
+        }
+    }
+
+    impl TscFrequency for MockVcpu {
+        fn get_tsc_khz(&self) -> Result<u32> {
+            Ok(self.host_khz)
+        }
+
+        fn set_tsc_khz(&self, khz: u32) -> Result<()> {
+            self.tsc_khz.set(Some(khz));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_set_tsc_frequency() {
+        let vcpu = MockVcpu::new(2_000_000);
+        // Within the tolerance, no scaling is needed.
+        set_tsc_frequency(&vcpu, false, 2_000_000).unwrap();
+        assert_eq!(vcpu.tsc_khz.get(), Some(2_000_000));
+        set_tsc_frequency(&vcpu, false, 2_000_500).unwrap();
+        assert_eq!(vcpu.tsc_khz.get(), Some(2_000_500));
+        set_tsc_frequency(&vcpu, false, 1_999_500).unwrap();
+        assert_eq!(vcpu.tsc_khz.get(), Some(1_999_500));
+
+        // Scaling is not supported.
+        let vcpu = MockVcpu::new(2_000_000);
+        let err = set_tsc_frequency(&vcpu, false, 2_000_501).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CpuError>(),
+            Some(CpuError::TscFrequency(2_000_501, 2_000_000))
+        ));
+        assert!(vcpu.tsc_khz.get().is_none());
+
+        // Scaling is supported.
+        set_tsc_frequency(&vcpu, true, 2_500_000).unwrap();
+        assert_eq!(vcpu.tsc_khz.get(), Some(2_500_000));
+        set_tsc_frequency(&vcpu, true, 1).unwrap();
+        assert_eq!(vcpu.tsc_khz.get(), Some(1));
+        assert!(set_tsc_frequency(&vcpu, true, 0).is_err());
+        assert!(set_tsc_frequency(&vcpu, true, 2_000_000 * 255 + 1).is_err());
+        let vcpu = MockVcpu::new(u32::MAX);
+        assert!(set_tsc_frequency(&vcpu, true, u64::from(u32::MAX) + 1).is_err());
+    }
+
+    #[test]
+    fn test_get_tsc_khz_failed() {
+        struct FailedVcpu;
+        impl TscFrequency for FailedVcpu {
+            fn get_tsc_khz(&self) -> Result<u32> {
+                Err(anyhow!("KVM_GET_TSC_KHZ failed"))
+            }
+
+            fn set_tsc_khz(&self, _khz: u32) -> Result<()> {
+                panic!("TSC frequency is set without host frequency");
+            }
+        }
+        assert!(set_tsc_frequency(&FailedVcpu, true, 2_000_000).is_err());
+    }
+}
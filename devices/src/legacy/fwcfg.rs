@@ -619,17 +619,20 @@ impl FwCfgCommon {
                 }
 
                 if is_write {
-                    let mut dma_read_error = false;
-                    let data = &mut entry.data[offset as usize..];
-                    if read_dma_memory(&mem_space, GuestAddress(dma.address), data, len as u64)
-                        .is_err()
+                    // Check the permission before the data of entry is modified.
+                    if !entry.allow_write || len != dma.length {
+                        dma.control |= FW_CFG_DMA_CTL_ERROR;
+                    } else if read_dma_memory(
+                        &mem_space,
+                        GuestAddress(dma.address),
+                        &mut entry.data[offset as usize..],
+                        len as u64,
+                    )
+                    .is_err()
                     {
-                        dma_read_error = true;
-                    }
-
-                    if dma_read_error || !entry.allow_write || len != dma.length {
                         dma.control |= FW_CFG_DMA_CTL_ERROR;
-                    } else if true {
+                    } else {
+                        let data = &entry.data[offset as usize..];
                         if let Some(cb) = &entry.write_cb {
                             cb.lock().unwrap().write_callback(
                                 data.to_vec(),
@@ -1270,7 +1273,7 @@ impl AmlBuilder for FwCfgIO {
 #[cfg(test)]
 mod test {
     use super::*;
-    use address_space::{AddressSpace, HostMemMapping, Region};
+    use address_space::{AddressSpace, HostMemMapping, HostMemOptions, Region};
     use sysbus::{IRQ_BASE, IRQ_MAX};
 
     fn sysbus_init() -> SysBus {
@@ -1302,9 +1305,7 @@ mod test {
                 None,
                 0x1000_0000,
                 None,
-                false,
-                false,
-                false,
+                HostMemOptions::default(),
             )
             .unwrap(),
         );
@@ -1520,6 +1521,75 @@ mod test {
         assert_eq!(read_dma_buf, all_zero);
     }
 
+    #[test]
+    fn test_dma_write() {
+        let sys_mem = address_space_init();
+        let mut fwcfg_common = FwCfgCommon::new(sys_mem);
+        assert_eq!(fwcfg_common.common_realize().is_ok(), true);
+        assert_eq!(
+            fwcfg_common
+                .add_file_callback("writable", vec![0_u8; 8], None, None, true)
+                .is_ok(),
+            true
+        );
+        let id = fwcfg_common.files.last().unwrap().select;
+
+        // [1]prepare data in guest memory.
+        let data_addr = GuestAddress(0x1000);
+        let data = [0x5a_u8; 8];
+        fwcfg_common
+            .mem_space
+            .write(&mut data.as_ref(), data_addr, data.len() as u64)
+            .unwrap();
+
+        // [2]write dma request which selects the file entry and writes to it.
+        let mut dma_req = FwCfgDmaAccess::default();
+        let control = ((id as u32) << 16) | FW_CFG_DMA_CTL_SELECT | FW_CFG_DMA_CTL_WRITE;
+        dma_req.length = *u32::from_bytes(&8_u32.to_be_bytes()).unwrap();
+        dma_req.address = *u64::from_bytes(&data_addr.raw_value().to_be_bytes()).unwrap();
+        dma_req.control = *u32::from_bytes(&control.to_be_bytes()).unwrap();
+        let dma_request = dma_req.as_mut_bytes();
+        let addr = GuestAddress(0x0000);
+        fwcfg_common
+            .mem_space
+            .write(&mut dma_request.as_ref(), addr, dma_request.len() as u64)
+            .unwrap();
+
+        // [3]handle dma request.
+        fwcfg_common.dma_addr = addr;
+        assert_eq!(fwcfg_common.handle_dma_request().is_ok(), true);
+
+        // [4]check dma response and entry data.
+        assert_eq!(fwcfg_common.mem_space.read_object::<u32>(addr).unwrap(), 0);
+        fwcfg_common.select_entry(id);
+        let entry = fwcfg_common.get_entry_mut().unwrap();
+        assert_eq!(entry.data, data.to_vec());
+
+        // Write to a read-only entry.
+        let mut dma_req = FwCfgDmaAccess::default();
+        let control = ((FwCfgEntryType::Signature as u32) << 16)
+            | FW_CFG_DMA_CTL_SELECT
+            | FW_CFG_DMA_CTL_WRITE;
+        dma_req.length = *u32::from_bytes(&4_u32.to_be_bytes()).unwrap();
+        dma_req.address = *u64::from_bytes(&data_addr.raw_value().to_be_bytes()).unwrap();
+        dma_req.control = *u32::from_bytes(&control.to_be_bytes()).unwrap();
+        let dma_request = dma_req.as_mut_bytes();
+        fwcfg_common
+            .mem_space
+            .write(&mut dma_request.as_ref(), addr, dma_request.len() as u64)
+            .unwrap();
+
+        fwcfg_common.dma_addr = addr;
+        assert_eq!(fwcfg_common.handle_dma_request().is_ok(), true);
+
+        // Response should report error, and entry data should not be changed.
+        let response = fwcfg_common.mem_space.read_object::<u32>(addr).unwrap();
+        assert_eq!(u32::from_be(response), FW_CFG_DMA_CTL_ERROR);
+        fwcfg_common.select_entry(FwCfgEntryType::Signature as u16);
+        let entry = fwcfg_common.get_entry_mut().unwrap();
+        assert_eq!(entry.data, vec![b'Q', b'E', b'M', b'U']);
+    }
+
     #[test]
     #[cfg(target_arch = "aarch64")]
     fn test_read_write_aarch64() {
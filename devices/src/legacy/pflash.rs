@@ -16,7 +16,7 @@ use std::sync::{Arc, Mutex};
 
 use super::error::LegacyError;
 use acpi::AmlBuilder;
-use address_space::{FileBackend, GuestAddress, HostMemMapping, Region};
+use address_space::{FileBackend, GuestAddress, HostMemMapping, HostMemOptions, Region};
 use anyhow::{anyhow, bail, Context, Result};
 use log::{debug, error, warn};
 use sysbus::{SysBus, SysBusDevOps, SysBusDevType, SysRes};
@@ -207,9 +207,11 @@ impl PFlash {
             None,
             region_size,
             backend.map(FileBackend::new_common),
-            false,
-            true,
-            self.read_only,
+            HostMemOptions {
+                is_share: true,
+                read_only: self.read_only,
+                ..Default::default()
+            },
         )?);
 
         let dev = Arc::new(Mutex::new(self));
@@ -915,9 +917,10 @@ mod test {
                 None,
                 flash_size,
                 fd.map(FileBackend::new_common),
-                false,
-                true,
-                false,
+                HostMemOptions {
+                    is_share: true,
+                    ..Default::default()
+                },
             )
             .unwrap(),
         );
@@ -23,7 +23,7 @@ use std::{
     thread,
 };
 
-use address_space::{GuestAddress, HostMemMapping, Region};
+use address_space::{GuestAddress, HostMemMapping, HostMemOptions, Region};
 use anyhow::{bail, Context, Result};
 use core::time;
 use log::{error, warn};
@@ -382,9 +382,10 @@ impl Scream {
             None,
             self.size,
             None,
-            false,
-            true,
-            false,
+            HostMemOptions {
+                is_share: true,
+                ..Default::default()
+            },
         )?);
         self.hva = host_mmap.host_address();
 
@@ -14,7 +14,10 @@ use std::fmt::{Display, Formatter, Result as FmtResult};
 
 use log::{debug, error};
 
-use ui::input::set_kbd_led_state;
+use ui::input::{
+    set_kbd_led_state, INPUT_BUTTON_WHEEL_DOWN, INPUT_BUTTON_WHEEL_LEFT, INPUT_BUTTON_WHEEL_RIGHT,
+    INPUT_BUTTON_WHEEL_UP,
+};
 
 use super::config::*;
 use super::{UsbDeviceRequest, UsbPacket, UsbPacketStatus};
@@ -51,6 +54,10 @@ const KEYCODE_UP: u32 = 0x80;
 pub const QUEUE_LENGTH: u32 = 16;
 pub const QUEUE_MASK: u32 = QUEUE_LENGTH - 1;
 const HID_USAGE_ERROR_ROLLOVER: u8 = 0x1;
+/// Left, right and middle buttons of pointer.
+const INPUT_BUTTON_MASK: u32 = 0x7;
+/// Max motion of mouse in one report.
+const MOUSE_MOTION_MAX: i32 = 0x7f;
 
 /// QKeyCode to HID code table
 const HID_CODE: [u8; 0x100] = [
@@ -117,6 +124,35 @@ const TABLET_REPORT_DESCRIPTOR: [u8; 89] = [
     0x81, 0x06, // Input (Data, Variable, Relative)
     0xc0, 0xc0, // End Collection
 ];
+/// Mouse report descriptor
+const MOUSE_REPORT_DESCRIPTOR: [u8; 52] = [
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xa1, 0x01, // Collection (Application)
+    0x09, 0x01, // Usage (Pointer)
+    0xa1, 0x00, // Collection (Physical)
+    0x05, 0x09, // Usage Page (Button)
+    0x19, 0x01, // Usage Minimum (1)
+    0x29, 0x03, // Usage Maximum (3)
+    0x15, 0x00, // Logical Minimum (0)
+    0x25, 0x01, // Logical Maximum (1)
+    0x95, 0x03, // Report Count (3)
+    0x75, 0x01, // Report Size (1)
+    0x81, 0x02, // Input (Data, Variable, Absolute)
+    0x95, 0x01, // Report Count (1)
+    0x75, 0x05, // Report Size (5)
+    0x81, 0x01, // Input (Constant)
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x30, // Usage (X)
+    0x09, 0x31, // Usage (Y)
+    0x09, 0x38, // Usage (Wheel)
+    0x15, 0x81, // Logical Minimum (-0x7f)
+    0x25, 0x7f, // Logical Maximum (0x7f)
+    0x75, 0x08, // Report Size (8)
+    0x95, 0x03, // Report Count (3)
+    0x81, 0x06, // Input (Data, Variable, Relative)
+    0xc0, 0xc0, // End Collection
+];
 /// Keyboard report descriptor
 const KEYBOARD_REPORT_DESCRIPTOR: [u8; 63] = [
     0x05, 0x01, // Usage Page (Generic Desktop)
@@ -197,6 +233,9 @@ pub struct HidPointerEvent {
     pub pos_x: u32,
     /// Direction: up to down.
     pub pos_y: u32,
+    /// Motion of mouse, which is not reported yet.
+    pub dx: i32,
+    pub dy: i32,
     /// Vertical scroll wheel.
     pub v_wheel: i32,
     /// Horizontal scroll wheel.
@@ -204,6 +243,27 @@ pub struct HidPointerEvent {
     pub button_state: u32,
 }
 
+impl HidPointerEvent {
+    /// Set the buttons and the wheels from the button state of input.
+    pub fn set_button(&mut self, button: u32) {
+        if button & INPUT_BUTTON_WHEEL_UP == INPUT_BUTTON_WHEEL_UP {
+            self.v_wheel = 1;
+        } else if button & INPUT_BUTTON_WHEEL_DOWN == INPUT_BUTTON_WHEEL_DOWN {
+            self.v_wheel = -1;
+        } else {
+            self.v_wheel = 0;
+        }
+        if button & INPUT_BUTTON_WHEEL_LEFT == INPUT_BUTTON_WHEEL_LEFT {
+            self.h_wheel = -1;
+        } else if button & INPUT_BUTTON_WHEEL_RIGHT == INPUT_BUTTON_WHEEL_RIGHT {
+            self.h_wheel = 1;
+        } else {
+            self.h_wheel = 0;
+        }
+        self.button_state = button & INPUT_BUTTON_MASK;
+    }
+}
+
 /// HID pointer which include hid pointer event.
 pub struct HidPointer {
     pub queue: [HidPointerEvent; QUEUE_LENGTH as usize],
@@ -355,6 +415,29 @@ impl Hid {
         ]
     }
 
+    /// The motion of mouse is reported in several reports if it is out of
+    /// the range of one report, the event is consumed after all of its
+    /// motion is reported.
+    fn mouse_poll(&mut self) -> Vec<u8> {
+        let evt = &mut self.pointer.queue[(self.head & QUEUE_MASK) as usize];
+        let dx = evt.dx.clamp(-MOUSE_MOTION_MAX, MOUSE_MOTION_MAX);
+        let dy = evt.dy.clamp(-MOUSE_MOTION_MAX, MOUSE_MOTION_MAX);
+        evt.dx -= dx;
+        evt.dy -= dy;
+        let buf = vec![
+            evt.button_state as u8,
+            dx as u8,
+            dy as u8,
+            evt.v_wheel as u8,
+        ];
+        evt.v_wheel = 0;
+        if self.num != 0 && evt.dx == 0 && evt.dy == 0 {
+            self.increase_head();
+            self.num -= 1;
+        }
+        buf
+    }
+
     fn increase_head(&mut self) {
         if self.head + 1 >= QUEUE_LENGTH {
             self.head = 0;
@@ -401,6 +484,11 @@ impl Hid {
                             .clone_from_slice(&TABLET_REPORT_DESCRIPTOR[..]);
                         packet.actual_length = TABLET_REPORT_DESCRIPTOR.len() as u32;
                     }
+                    HidType::Mouse => {
+                        data[..MOUSE_REPORT_DESCRIPTOR.len()]
+                            .clone_from_slice(&MOUSE_REPORT_DESCRIPTOR[..]);
+                        packet.actual_length = MOUSE_REPORT_DESCRIPTOR.len() as u32;
+                    }
                     HidType::Keyboard => {
                         data[..KEYBOARD_REPORT_DESCRIPTOR.len()]
                             .clone_from_slice(&KEYBOARD_REPORT_DESCRIPTOR[..]);
@@ -436,6 +524,11 @@ impl Hid {
                     data[0..buf.len()].copy_from_slice(buf.as_slice());
                     packet.actual_length = buf.len() as u32;
                 }
+                HidType::Mouse => {
+                    let buf = self.mouse_poll();
+                    data[0..buf.len()].copy_from_slice(buf.as_slice());
+                    packet.actual_length = buf.len() as u32;
+                }
                 HidType::Keyboard => {
                     let buf = self.keyboard_poll();
                     data[0..buf.len()].copy_from_slice(buf.as_slice());
@@ -519,6 +612,9 @@ impl Hid {
                 HidType::Tablet => {
                     buf = self.pointer_poll();
                 }
+                HidType::Mouse => {
+                    buf = self.mouse_poll();
+                }
                 _ => {
                     error!("Unsupported HID device");
                     p.status = UsbPacketStatus::Stall;
@@ -542,3 +638,28 @@ impl Display for Hid {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mouse_poll() {
+        let mut hid = Hid::new(HidType::Mouse);
+        let evt = &mut hid.pointer.queue[0];
+        evt.set_button(INPUT_BUTTON_WHEEL_UP | 0x1);
+        evt.dx = 200;
+        evt.dy = -3;
+        hid.num = 1;
+
+        // The motion out of range is reported in two reports.
+        assert_eq!(hid.mouse_poll(), vec![0x1, 0x7f, (-3_i8) as u8, 0x1]);
+        assert_eq!(hid.num, 1);
+        assert_eq!(hid.mouse_poll(), vec![0x1, 73, 0, 0]);
+        assert_eq!(hid.num, 0);
+        assert_eq!(hid.head, 1);
+
+        // No event, report nothing moved.
+        assert_eq!(hid.mouse_poll(), vec![0, 0, 0, 0]);
+    }
+}
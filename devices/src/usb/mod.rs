@@ -27,6 +27,8 @@ pub mod hid;
 #[cfg(not(target_env = "musl"))]
 pub mod keyboard;
 #[cfg(not(target_env = "musl"))]
+pub mod mouse;
+#[cfg(not(target_env = "musl"))]
 pub mod storage;
 #[cfg(not(target_env = "musl"))]
 pub mod tablet;
@@ -0,0 +1,248 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use anyhow::Result;
+use log::{debug, error, info};
+use once_cell::sync::Lazy;
+
+use super::descriptor::{
+    UsbConfigDescriptor, UsbDescConfig, UsbDescDevice, UsbDescEndpoint, UsbDescIface, UsbDescOther,
+    UsbDescriptorOps, UsbDeviceDescriptor, UsbEndpointDescriptor, UsbInterfaceDescriptor,
+};
+use super::hid::{Hid, HidType, QUEUE_LENGTH, QUEUE_MASK};
+use super::xhci::xhci_controller::XhciDevice;
+use super::{config::*, USB_DEVICE_BUFFER_DEFAULT_LEN};
+use super::{
+    notify_controller, UsbDevice, UsbDeviceOps, UsbDeviceRequest, UsbEndpoint, UsbPacket,
+    UsbPacketStatus,
+};
+use ui::input::{register_pointer, unregister_pointer, PointerOpts};
+
+/// Mouse device descriptor
+static DESC_DEVICE_MOUSE: Lazy<Arc<UsbDescDevice>> = Lazy::new(|| {
+    Arc::new(UsbDescDevice {
+        device_desc: UsbDeviceDescriptor {
+            bLength: USB_DT_DEVICE_SIZE,
+            bDescriptorType: USB_DT_DEVICE,
+            idVendor: 0x0627,
+            idProduct: 0x0001,
+            bcdDevice: 0,
+            iManufacturer: STR_MANUFACTURER_INDEX,
+            iProduct: STR_PRODUCT_MOUSE_INDEX,
+            iSerialNumber: STR_SERIAL_MOUSE_INDEX,
+            bcdUSB: 0x0100,
+            bDeviceClass: 0,
+            bDeviceSubClass: 0,
+            bDeviceProtocol: 0,
+            bMaxPacketSize0: 8,
+            bNumConfigurations: 1,
+        },
+        configs: vec![Arc::new(UsbDescConfig {
+            config_desc: UsbConfigDescriptor {
+                bLength: USB_DT_CONFIG_SIZE,
+                bDescriptorType: USB_DT_CONFIGURATION,
+                wTotalLength: 0,
+                bNumInterfaces: 1,
+                bConfigurationValue: 1,
+                iConfiguration: STR_CONFIG_MOUSE_INDEX,
+                bmAttributes: USB_CONFIGURATION_ATTR_ONE | USB_CONFIGURATION_ATTR_REMOTE_WAKEUP,
+                bMaxPower: 50,
+            },
+            iad_desc: vec![],
+            interfaces: vec![DESC_IFACE_MOUSE.clone()],
+        })],
+    })
+});
+/// Mouse interface descriptor
+static DESC_IFACE_MOUSE: Lazy<Arc<UsbDescIface>> = Lazy::new(|| {
+    Arc::new(UsbDescIface {
+        interface_desc: UsbInterfaceDescriptor {
+            bLength: USB_DT_INTERFACE_SIZE,
+            bDescriptorType: USB_DT_INTERFACE,
+            bInterfaceNumber: 0,
+            bAlternateSetting: 0,
+            bNumEndpoints: 1,
+            bInterfaceClass: USB_CLASS_HID,
+            bInterfaceSubClass: 1,
+            bInterfaceProtocol: 2,
+            iInterface: 0,
+        },
+        other_desc: vec![Arc::new(UsbDescOther {
+            /// HID descriptor
+            data: vec![0x09, 0x21, 0x01, 0x0, 0x0, 0x01, 0x22, 52, 0x0],
+        })],
+        endpoints: vec![Arc::new(UsbDescEndpoint {
+            endpoint_desc: UsbEndpointDescriptor {
+                bLength: USB_DT_ENDPOINT_SIZE,
+                bDescriptorType: USB_DT_ENDPOINT,
+                bEndpointAddress: USB_DIRECTION_DEVICE_TO_HOST | 0x1,
+                bmAttributes: USB_ENDPOINT_ATTR_INT,
+                wMaxPacketSize: 4,
+                bInterval: 0xa,
+            },
+            extra: Vec::new(),
+        })],
+    })
+});
+
+/// String descriptor index
+const STR_MANUFACTURER_INDEX: u8 = 1;
+const STR_PRODUCT_MOUSE_INDEX: u8 = 2;
+const STR_CONFIG_MOUSE_INDEX: u8 = 3;
+const STR_SERIAL_MOUSE_INDEX: u8 = 4;
+
+/// String descriptor
+const DESC_STRINGS: [&str; 5] = ["", "StratoVirt", "StratoVirt USB Mouse", "HID Mouse", "3"];
+/// USB mouse device, which reports the relative motion.
+pub struct UsbMouse {
+    id: String,
+    usb_device: UsbDevice,
+    hid: Hid,
+    /// USB controller used to notify controller to transfer data.
+    cntlr: Option<Weak<Mutex<XhciDevice>>>,
+}
+
+impl UsbMouse {
+    pub fn new(id: String) -> Self {
+        Self {
+            id,
+            usb_device: UsbDevice::new(USB_DEVICE_BUFFER_DEFAULT_LEN),
+            hid: Hid::new(HidType::Mouse),
+            cntlr: None,
+        }
+    }
+}
+
+pub struct UsbMouseAdapter {
+    mouse: Arc<Mutex<UsbMouse>>,
+}
+
+impl UsbMouseAdapter {
+    fn queue_event(&mut self, button: u32, dx: i32, dy: i32) -> Result<()> {
+        let mut locked_mouse = self.mouse.lock().unwrap();
+        if locked_mouse.hid.num >= QUEUE_LENGTH {
+            debug!("Pointer queue is full!");
+            // Return ok to ignore the request.
+            return Ok(());
+        }
+        let index = ((locked_mouse.hid.head + locked_mouse.hid.num) & QUEUE_MASK) as usize;
+        let evt = &mut locked_mouse.hid.pointer.queue[index];
+        evt.set_button(button);
+        evt.dx = dx;
+        evt.dy = dy;
+        locked_mouse.hid.num += 1;
+        drop(locked_mouse);
+        let clone_mouse = self.mouse.clone();
+        notify_controller(&(clone_mouse as Arc<Mutex<dyn UsbDeviceOps>>))
+    }
+}
+
+impl PointerOpts for UsbMouseAdapter {
+    /// The mouse has no position, only the buttons are reported.
+    fn do_point_event(&mut self, button: u32, _x: u32, _y: u32) -> Result<()> {
+        self.queue_event(button, 0, 0)
+    }
+
+    fn is_absolute(&self) -> bool {
+        false
+    }
+
+    fn do_rel_point_event(&mut self, button: u32, dx: i32, dy: i32) -> Result<()> {
+        self.queue_event(button, dx, dy)
+    }
+}
+
+impl UsbDeviceOps for UsbMouse {
+    fn realize(mut self) -> Result<Arc<Mutex<dyn UsbDeviceOps>>> {
+        self.usb_device.reset_usb_endpoint();
+        self.usb_device.speed = USB_SPEED_FULL;
+        let s = DESC_STRINGS.iter().map(|&s| s.to_string()).collect();
+        self.usb_device
+            .init_descriptor(DESC_DEVICE_MOUSE.clone(), s)?;
+        let id = self.id.clone();
+        let mouse = Arc::new(Mutex::new(self));
+        let mouse_adapter = Arc::new(Mutex::new(UsbMouseAdapter {
+            mouse: mouse.clone(),
+        }));
+        register_pointer(&id, mouse_adapter);
+        Ok(mouse)
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        unregister_pointer(&self.id.clone());
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        info!("Mouse device reset");
+        self.usb_device.remote_wakeup = 0;
+        self.usb_device.addr = 0;
+        self.hid.reset();
+    }
+
+    fn handle_control(&mut self, packet: &Arc<Mutex<UsbPacket>>, device_req: &UsbDeviceRequest) {
+        debug!("handle_control request {:?}", device_req);
+        let mut locked_packet = packet.lock().unwrap();
+        match self
+            .usb_device
+            .handle_control_for_descriptor(&mut locked_packet, device_req)
+        {
+            Ok(handled) => {
+                if handled {
+                    debug!("Mouse control handled by descriptor, return directly.");
+                    return;
+                }
+            }
+            Err(e) => {
+                error!("Mouse descriptor error {:?}", e);
+                locked_packet.status = UsbPacketStatus::Stall;
+                return;
+            }
+        }
+        self.hid.handle_control_packet(
+            &mut locked_packet,
+            device_req,
+            &mut self.usb_device.data_buf,
+        );
+    }
+
+    fn handle_data(&mut self, p: &Arc<Mutex<UsbPacket>>) {
+        let mut locked_p = p.lock().unwrap();
+        self.hid.handle_data_packet(&mut locked_p);
+    }
+
+    fn device_id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn get_usb_device(&self) -> &UsbDevice {
+        &self.usb_device
+    }
+
+    fn get_mut_usb_device(&mut self) -> &mut UsbDevice {
+        &mut self.usb_device
+    }
+
+    fn set_controller(&mut self, cntlr: Weak<Mutex<XhciDevice>>) {
+        self.cntlr = Some(cntlr);
+    }
+
+    fn get_controller(&self) -> Option<Weak<Mutex<XhciDevice>>> {
+        self.cntlr.clone()
+    }
+
+    fn get_wakeup_endpoint(&self) -> &UsbEndpoint {
+        self.usb_device.get_endpoint(true, 1)
+    }
+}
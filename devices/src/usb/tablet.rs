@@ -28,12 +28,8 @@ use super::{
     notify_controller, UsbDevice, UsbDeviceOps, UsbDeviceRequest, UsbEndpoint, UsbPacket,
     UsbPacketStatus,
 };
-use ui::input::{
-    register_pointer, unregister_pointer, PointerOpts, INPUT_BUTTON_WHEEL_DOWN,
-    INPUT_BUTTON_WHEEL_LEFT, INPUT_BUTTON_WHEEL_RIGHT, INPUT_BUTTON_WHEEL_UP,
-};
+use ui::input::{register_pointer, unregister_pointer, PointerOpts};
 
-const INPUT_BUTTON_MASK: u32 = 0x7;
 const INPUT_COORDINATES_MAX: u32 = 0x7fff;
 
 /// Tablet device descriptor
@@ -145,21 +141,7 @@ impl PointerOpts for UsbTabletAdapter {
         }
         let index = ((locked_tablet.hid.head + locked_tablet.hid.num) & QUEUE_MASK) as usize;
         let mut evt = &mut locked_tablet.hid.pointer.queue[index];
-        if button & INPUT_BUTTON_WHEEL_UP == INPUT_BUTTON_WHEEL_UP {
-            evt.v_wheel = 1;
-        } else if button & INPUT_BUTTON_WHEEL_DOWN == INPUT_BUTTON_WHEEL_DOWN {
-            evt.v_wheel = -1;
-        } else {
-            evt.v_wheel = 0;
-        }
-        if button & INPUT_BUTTON_WHEEL_LEFT == INPUT_BUTTON_WHEEL_LEFT {
-            evt.h_wheel = -1;
-        } else if button & INPUT_BUTTON_WHEEL_RIGHT == INPUT_BUTTON_WHEEL_RIGHT {
-            evt.h_wheel = 1;
-        } else {
-            evt.h_wheel = 0;
-        }
-        evt.button_state = button & INPUT_BUTTON_MASK;
+        evt.set_button(button);
         evt.pos_x = min(x, INPUT_COORDINATES_MAX);
         evt.pos_y = min(y, INPUT_COORDINATES_MAX);
         locked_tablet.hid.num += 1;
@@ -40,6 +40,8 @@ pub const KVM_SIGNAL_MSI: u32 = 0x4020_aea5;
 ioctl_iow_nr!(KVM_SET_GSI_ROUTING, KVMIO, 0x6a, kvm_irq_routing);
 ioctl_iow_nr!(KVM_IRQFD, KVMIO, 0x76, kvm_irqfd);
 ioctl_io_nr!(KVM_GET_API_VERSION, KVMIO, 0x00);
+#[cfg(target_arch = "x86_64")]
+ioctl_io_nr!(KVM_NMI, KVMIO, 0x9a);
 ioctl_ior_nr!(KVM_GET_MP_STATE, KVMIO, 0x98, kvm_mp_state);
 ioctl_ior_nr!(KVM_GET_VCPU_EVENTS, KVMIO, 0x9f, kvm_vcpu_events);
 #[cfg(target_arch = "x86_64")]
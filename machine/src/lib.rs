@@ -58,15 +58,15 @@ use devices::InterruptController;
 
 #[cfg(not(target_env = "musl"))]
 use devices::usb::{
-    camera::UsbCamera, keyboard::UsbKeyboard, storage::UsbStorage, tablet::UsbTablet,
-    usbhost::UsbHost, xhci::xhci_pci::XhciPciDevice, UsbDeviceOps,
+    camera::UsbCamera, keyboard::UsbKeyboard, mouse::UsbMouse, storage::UsbStorage,
+    tablet::UsbTablet, usbhost::UsbHost, xhci::xhci_pci::XhciPciDevice, UsbDeviceOps,
 };
 use devices::ScsiDisk::{ScsiDevice, SCSI_TYPE_DISK, SCSI_TYPE_ROM};
 use hypervisor::kvm::KVM_FDS;
 use machine_manager::config::{
     complete_numa_node, get_multi_function, get_pci_bdf, parse_balloon, parse_blk, parse_demo_dev,
-    parse_device_id, parse_fs, parse_net, parse_numa_distance, parse_numa_mem, parse_rng_dev,
-    parse_root_port, parse_scsi_controller, parse_scsi_device, parse_vfio,
+    parse_device_id, parse_fs, parse_iommu, parse_net, parse_numa_distance, parse_numa_mem,
+    parse_rng_dev, parse_root_port, parse_scsi_controller, parse_scsi_device, parse_vfio,
     parse_vhost_user_blk_pci, parse_virtio_serial, parse_virtserialport, parse_vsock,
     BootIndexInfo, DriveFile, Incoming, MachineMemConfig, MigrateMode, NumaConfig, NumaDistance,
     NumaNode, NumaNodes, PFlashConfig, PciBdf, SerialConfig, VfioConfig, VmConfig, FAST_UNPLUG_ON,
@@ -74,8 +74,8 @@ use machine_manager::config::{
 };
 #[cfg(not(target_env = "musl"))]
 use machine_manager::config::{
-    parse_gpu, parse_usb_camera, parse_usb_host, parse_usb_keyboard, parse_usb_storage,
-    parse_usb_tablet, parse_xhci,
+    parse_gpu, parse_usb_camera, parse_usb_host, parse_usb_keyboard, parse_usb_mouse,
+    parse_usb_storage, parse_usb_tablet, parse_xhci,
 };
 use machine_manager::machine::{KvmVmState, MachineInterface};
 use migration::MigrationManager;
@@ -146,7 +146,7 @@ pub trait MachineOps {
         for (_, node) in numa_nodes.as_ref().unwrap().iter().enumerate() {
             for zone in zones.iter() {
                 if zone.id.eq(&node.1.mem_dev) {
-                    let ram = create_backend_mem(zone, thread_num)?;
+                    let ram = create_backend_mem(mem_config, zone, thread_num)?;
                     root.add_subregion_not_update(ram, offset)?;
                     offset += zone.size;
                     break;
@@ -493,13 +493,6 @@ pub trait MachineOps {
         let mut virtio_dev_h = virtio_dev.lock().unwrap();
         let serial = virtio_dev_h.as_any_mut().downcast_mut::<Serial>().unwrap();
 
-        if serialport_cfg.nr >= serial.max_nr_ports {
-            bail!(
-                "virtio serial port nr {} should be less than virtio serial's max_nr_ports {}",
-                serialport_cfg.nr,
-                serial.max_nr_ports
-            );
-        }
         if find_port_by_nr(&serial.ports, serialport_cfg.nr).is_some() {
             bail!("Repetitive virtio serial port nr {}.", serialport_cfg.nr,);
         }
@@ -843,6 +836,19 @@ pub trait MachineOps {
         let virtio_device = virtio_pcidev.get_virtio_device().lock().unwrap();
         let cntlr = virtio_device.as_any().downcast_ref::<ScsiCntlr>().unwrap();
 
+        if device_cfg.target as u16 > cntlr.config.max_target
+            || device_cfg.lun as u32 > cntlr.config.max_lun
+        {
+            bail!(
+                "Scsi-id {} or lun {} exceeds max-targets {} or max-lun {} of scsi controller {}",
+                device_cfg.target,
+                device_cfg.lun,
+                cntlr.config.max_target,
+                cntlr.config.max_lun,
+                device_cfg.cntlr
+            );
+        }
+
         let bus = cntlr.bus.as_ref().unwrap();
         if bus
             .lock()
@@ -1358,6 +1364,25 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Add usb mouse.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - Mouse Configuration.
+    #[cfg(not(target_env = "musl"))]
+    fn add_usb_mouse(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_usb_mouse(cfg_args)?;
+        // SAFETY: id is already checked not none in parse_usb_mouse().
+        let mouse = UsbMouse::new(device_cfg.id.unwrap());
+        let mse = mouse
+            .realize()
+            .with_context(|| "Failed to realize usb mouse device")?;
+
+        self.attach_usb_to_xhci_controller(vm_config, mse)?;
+
+        Ok(())
+    }
+
     /// Add usb camera.
     ///
     /// # Arguments
@@ -1490,6 +1515,9 @@ pub trait MachineOps {
                 "vfio-pci" => {
                     self.add_vfio_device(cfg_args)?;
                 }
+                "virtio-iommu-pci" => {
+                    self.add_virtio_iommu(cfg_args)?;
+                }
                 "vhost-user-blk-pci" => {
                     self.add_vhost_user_blk_pci(vm_config, cfg_args)?;
                 }
@@ -1509,6 +1537,10 @@ pub trait MachineOps {
                     self.add_usb_tablet(vm_config, cfg_args)?;
                 }
                 #[cfg(not(target_env = "musl"))]
+                "usb-mouse" => {
+                    self.add_usb_mouse(vm_config, cfg_args)?;
+                }
+                #[cfg(not(target_env = "musl"))]
                 "usb-camera" => {
                     self.add_usb_camera(vm_config, cfg_args)?;
                 }
@@ -1548,6 +1580,11 @@ pub trait MachineOps {
         bail!("Pflash device is not supported!");
     }
 
+    fn add_virtio_iommu(&mut self, cfg_args: &str) -> Result<()> {
+        parse_iommu(cfg_args)?;
+        bail!("virtio-iommu device is not supported!");
+    }
+
     fn add_ramfb(&mut self, _cfg_args: &str) -> Result<()> {
         bail!("ramfb device is not supported!");
     }
@@ -1778,6 +1815,47 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Inject NMI into vcpus, which should be running.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpus` - Cpus vector restore cpu structure.
+    /// * `cpu_index` - Index of the vcpu, all vcpus if it is `None`.
+    #[cfg(target_arch = "x86_64")]
+    fn vm_inject_nmi(&self, cpus: &[Arc<CPU>], cpu_index: Option<usize>) -> Result<()> {
+        let targets = match cpu_index {
+            Some(index) => match cpus.get(index) {
+                Some(cpu) => std::slice::from_ref(cpu),
+                None => bail!("Invalid cpu index {}", index),
+            },
+            None => cpus,
+        };
+        for cpu in targets {
+            cpu.inject_nmi()
+                .with_context(|| format!("Failed to inject NMI into vcpu{}", cpu.id()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Dump the registers of vcpu, which should not be running.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpus` - Cpus vector restore cpu structure.
+    /// * `cpu_index` - Index of the vcpu.
+    #[cfg(target_arch = "x86_64")]
+    fn vm_dump_guest_registers(
+        &self,
+        cpus: &[Arc<CPU>],
+        cpu_index: u32,
+    ) -> Result<machine_manager::qmp::qmp_schema::RegisterDump> {
+        match cpus.get(cpu_index as usize) {
+            Some(cpu) => cpu.dump_registers(),
+            None => bail!("Invalid cpu index {}", cpu_index),
+        }
+    }
+
     /// Transfer VM state from `old` to `new`.
     ///
     /// # Arguments
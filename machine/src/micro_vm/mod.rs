@@ -49,11 +49,15 @@ use std::sync::{Arc, Condvar, Mutex};
 use std::vec::Vec;
 
 use address_space::{AddressSpace, GuestAddress, Region};
+#[cfg(target_arch = "x86_64")]
+use boot_loader::build_cmdline;
 use boot_loader::{load_linux, BootLoaderConfig};
 #[cfg(target_arch = "aarch64")]
 use cpu::CPUFeatures;
 #[cfg(target_arch = "aarch64")]
 use cpu::PMU_INTR;
+#[cfg(target_arch = "x86_64")]
+use cpu::{check_la57, CpuFeatureFilter};
 use cpu::{CPUBootConfig, CPUTopology, CpuLifecycleState, CpuTopology, CPU};
 #[cfg(target_arch = "aarch64")]
 use devices::legacy::PL031;
@@ -69,13 +73,13 @@ use kvm_bindings::{kvm_pit_config, KVM_PIT_SPEAKER_DUMMY};
 use machine_manager::{
     config::{
         parse_blk, parse_incoming_uri, parse_net, BlkDevConfig, BootSource, ConfigCheck, DriveFile,
-        Incoming, MigrateMode, NetworkInterfaceConfig, NumaNodes, SerialConfig, VmConfig,
-        DEFAULT_VIRTQUEUE_SIZE,
+        Incoming, IoBackend, MigrateMode, NetworkInterfaceConfig, NumaNodes, SerialConfig,
+        VmConfig, DEFAULT_VIRTQUEUE_SIZE,
     },
     event,
     machine::{
-        DeviceInterface, KvmVmState, MachineAddressInterface, MachineExternalInterface,
-        MachineInterface, MachineLifecycle, MigrateInterface,
+        hotplug_check, DeviceInterface, KvmVmState, MachineAddressInterface,
+        MachineExternalInterface, MachineInterface, MachineLifecycle, MigrateInterface,
     },
     qmp::{qmp_schema, QmpChannel, Response},
 };
@@ -600,16 +604,22 @@ impl MachineOps for LightMachine {
         let gap_start = MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].0
             + MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].1;
         let gap_end = MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize].0;
+        let kernel_cmdline = build_cmdline(&[&boot_source.kernel_cmdline.to_string()]);
+        let vm_config = self.vm_config.lock().unwrap();
         let bootloader_config = BootLoaderConfig {
             kernel: boot_source.kernel_file.clone(),
             initrd,
-            kernel_cmdline: boot_source.kernel_cmdline.to_string(),
+            kernel_cmdline,
             cpu_count: self.cpu_topo.nrcpus,
             gap_range: (gap_start, gap_end - gap_start),
             ioapic_addr: MEM_LAYOUT[LayoutEntryType::IoApic as usize].0 as u32,
             lapic_addr: MEM_LAYOUT[LayoutEntryType::LocalApic as usize].0 as u32,
             ident_tss_range: None,
             prot64_mode: true,
+            five_level_paging: vm_config.machine_config.cpu_config.la57,
+            reserve_pci_hole: vm_config.machine_config.reserve_pci_hole,
+            reserve_kernel_init: vm_config.machine_config.reserve_kernel_init,
+            auto_serial_console: vm_config.machine_config.auto_serial_console,
         };
         let layout = load_linux(&bootloader_config, &self.sys_mem, fwcfg)
             .with_context(|| MachineError::LoadKernErr)?;
@@ -627,6 +637,7 @@ impl MachineOps for LightMachine {
             idt_base: layout.segments.idt_base,
             idt_size: layout.segments.idt_limit,
             pml4_start: layout.boot_pml4_addr,
+            la57: bootloader_config.five_level_paging,
         })
     }
 
@@ -852,6 +863,18 @@ impl MachineOps for LightMachine {
                 &topology,
                 &boot_config,
             )?);
+            if let Some(khz) = vm_config.machine_config.cpu_config.tsc_freq_khz {
+                for cpu in locked_vm.cpus.iter() {
+                    cpu.set_tsc_frequency(khz)?;
+                }
+            }
+            let cpuid_filter = CpuFeatureFilter::from(&vm_config.machine_config.cpu_config);
+            if vm_config.machine_config.cpu_config.la57 {
+                check_la57(&cpuid_filter)?;
+            }
+            for cpu in locked_vm.cpus.iter() {
+                cpu.set_cpuid_filter(cpuid_filter.clone());
+            }
         }
 
         #[cfg(target_arch = "aarch64")]
@@ -1111,6 +1134,28 @@ impl DeviceInterface for LightMachine {
         Response::create_response(hotplug_vec.into(), None)
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn inject_nmi(&self, cpu_index: Option<usize>) -> Response {
+        if let Err(e) = self.vm_inject_nmi(&self.cpus, cpu_index) {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            );
+        }
+        Response::create_empty_response()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn dump_guest_registers(&self, cpu_index: u32) -> Response {
+        match self.vm_dump_guest_registers(&self.cpus, cpu_index) {
+            Ok(dump) => Response::create_response(serde_json::to_value(dump).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
     fn balloon(&self, value: u64) -> Response {
         if qmp_balloon(value) {
             return Response::create_empty_response();
@@ -1152,6 +1197,10 @@ impl DeviceInterface for LightMachine {
     }
 
     fn device_add(&mut self, args: Box<qmp_schema::DeviceAddArgument>) -> Response {
+        if let Some(resp) = hotplug_check(&self.vm_state.0) {
+            return resp;
+        }
+
         // get slot of bus by addr or lun
         let mut slot = 0;
         if let Some(addr) = args.addr {
@@ -1218,6 +1267,8 @@ impl DeviceInterface for LightMachine {
             direct,
             serial_num: None,
             iothread: None,
+            queue_iothreads: Vec::new(),
+            io_backend: IoBackend::Sync,
             iops: None,
             queues: 1,
             boot_index: None,
@@ -1230,6 +1281,7 @@ impl DeviceInterface for LightMachine {
                 AioEngine::Off
             },
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            queue_overrides: Vec::new(),
             discard: false,
             write_zeroes: WriteZeroesState::Off,
             format: DiskFormat::Raw,
@@ -1284,6 +1336,9 @@ impl DeviceInterface for LightMachine {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            queue_overrides: Vec::new(),
+            tx_burst: 0,
+            rx_burst: 0,
         };
 
         if let Some(fds) = args.fds {
@@ -179,6 +179,7 @@ fn ioctl_arch_allow_list(bpf_rule: BpfRule) -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_XCRS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_LAPIC() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_MSRS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_NMI() as u32)
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -224,3 +225,14 @@ fn futex_rule() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, FUTEX_WAKE_OP_PRIVATE)
         .add_constraint(SeccompCmpOpt::Eq, 1, FUTEX_WAIT_BITSET_PRIVATE);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_ioctl_allow_nmi() {
+        assert!(ioctl_allow_list().allows_arg(1, KVM_NMI() as u32));
+    }
+}
@@ -27,7 +27,7 @@ use machine_manager::{config::get_cameradev_config, machine::MachineLifecycle};
 #[cfg(not(target_env = "musl"))]
 use ui::{
     input::{key_event, point_event},
-    vnc::qmp_query_vnc,
+    vnc::{qmp_expire_vnc_password, qmp_query_vnc, qmp_reload_vnc_tls_creds, qmp_set_vnc_password},
 };
 use util::aio::{AioEngine, WriteZeroesState};
 use util::loop_context::{read_fd, EventNotifier, NotifierCallback, NotifierOperation};
@@ -53,7 +53,8 @@ use acpi::{
     ACPI_TABLE_LOADER_FILE, TABLE_CHECKSUM_OFFSET,
 };
 use address_space::{
-    AddressRange, FileBackend, GuestAddress, HostMemMapping, Region, RegionIoEventFd, RegionOps,
+    AddressRange, FileBackend, GuestAddress, HostMemMapping, HostMemOptions, Region,
+    RegionIoEventFd, RegionOps,
 };
 pub use anyhow::Result;
 use anyhow::{bail, Context};
@@ -62,12 +63,13 @@ use cpu::{CpuTopology, CPU};
 use devices::legacy::FwCfgOps;
 use machine_manager::config::{
     get_chardev_config, get_netdev_config, get_pci_df, BlkDevConfig, ChardevType, ConfigCheck,
-    DiskFormat, DriveConfig, ExBool, NetworkInterfaceConfig, NumaNode, NumaNodes, PciBdf,
-    ScsiCntlrConfig, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_VIRTIO_QUEUE,
+    DiskFormat, DriveConfig, ExBool, IoBackend, NetworkInterfaceConfig, NumaNode, NumaNodes,
+    PciBdf, ScsiCntlrConfig, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_VIRTIO_QUEUE,
 };
-use machine_manager::machine::{DeviceInterface, KvmVmState};
+use machine_manager::machine::{hotplug_check, DeviceInterface, KvmVmState};
 use machine_manager::qmp::{qmp_schema, QmpChannel, Response};
-use migration::MigrationManager;
+use machine_manager::snapshot::{CpuState, DeviceState, SnapshotVm};
+use migration::{MigrationManager, StateTransfer};
 use pci::hotplug::{handle_plug, handle_unplug_pci_request};
 use pci::PciBus;
 use util::byte_code::ByteCode;
@@ -813,6 +815,8 @@ impl StdMachine {
                 direct: conf.direct,
                 serial_num: args.serial_num.clone(),
                 iothread: args.iothread.clone(),
+                queue_iothreads: Vec::new(),
+                io_backend: IoBackend::Sync,
                 iops: conf.iops,
                 queues: args.queues.unwrap_or_else(|| {
                     VirtioPciDevice::virtio_pci_auto_queues_num(0, nr_cpus, MAX_VIRTIO_QUEUE)
@@ -822,6 +826,7 @@ impl StdMachine {
                 socket_path: None,
                 aio: conf.aio,
                 queue_size,
+                queue_overrides: Vec::new(),
                 discard: conf.discard,
                 write_zeroes: conf.write_zeroes,
                 format: conf.format,
@@ -871,6 +876,7 @@ impl StdMachine {
             }) as u32,
             boot_prefix: None,
             queue_size,
+            ..Default::default()
         };
         dev_cfg.check()?;
 
@@ -980,6 +986,9 @@ impl StdMachine {
                 mq: conf.queues > 2,
                 socket_path,
                 queue_size,
+                queue_overrides: Vec::new(),
+                tx_burst: 0,
+                rx_burst: 0,
             };
             dev.check()?;
             dev
@@ -1022,6 +1031,9 @@ impl StdMachine {
             "usb-tablet" => {
                 self.add_usb_tablet(&mut locked_vmconfig, &cfg_args)?;
             }
+            "usb-mouse" => {
+                self.add_usb_mouse(&mut locked_vmconfig, &cfg_args)?;
+            }
             "usb-camera" => {
                 if let Some(cameradev) = &args.cameradev {
                     cfg_args = format!("{},cameradev={}", cfg_args, cameradev);
@@ -1168,6 +1180,28 @@ impl DeviceInterface for StdMachine {
         Response::create_empty_response()
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn inject_nmi(&self, cpu_index: Option<usize>) -> Response {
+        if let Err(e) = self.vm_inject_nmi(self.get_cpus(), cpu_index) {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            );
+        }
+        Response::create_empty_response()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn dump_guest_registers(&self, cpu_index: u32) -> Response {
+        match self.vm_dump_guest_registers(self.get_cpus(), cpu_index) {
+            Ok(dump) => Response::create_response(serde_json::to_value(dump).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
     fn balloon(&self, value: u64) -> Response {
         if qmp_balloon(value) {
             return Response::create_empty_response();
@@ -1211,7 +1245,84 @@ impl DeviceInterface for StdMachine {
         )
     }
 
+    fn display_reload(&self, args: qmp_schema::DisplayReloadArgument) -> Response {
+        if args.display_type != "vnc" {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!(
+                    "Unsupported display type {}",
+                    args.display_type
+                )),
+                None,
+            );
+        }
+        #[cfg(not(target_env = "musl"))]
+        if args.tls_certs {
+            if let Err(e) = qmp_reload_vnc_tls_creds() {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                    None,
+                );
+            }
+        }
+        Response::create_empty_response()
+    }
+
+    fn set_password(&self, args: qmp_schema::SetPasswordArgument) -> Response {
+        if args.protocol != "vnc" {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!(
+                    "Unsupported protocol {}",
+                    args.protocol
+                )),
+                None,
+            );
+        }
+        if let Some(connected) = &args.connected {
+            if connected != "keep" {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!(
+                        "Unsupported action {} for connected clients",
+                        connected
+                    )),
+                    None,
+                );
+            }
+        }
+        #[cfg(not(target_env = "musl"))]
+        if let Err(e) = qmp_set_vnc_password(&args.password) {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            );
+        }
+        Response::create_empty_response()
+    }
+
+    fn expire_password(&self, args: qmp_schema::ExpirePasswordArgument) -> Response {
+        if args.protocol != "vnc" {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!(
+                    "Unsupported protocol {}",
+                    args.protocol
+                )),
+                None,
+            );
+        }
+        #[cfg(not(target_env = "musl"))]
+        if let Err(e) = qmp_expire_vnc_password(&args.time) {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            );
+        }
+        Response::create_empty_response()
+    }
+
     fn device_add(&mut self, args: Box<qmp_schema::DeviceAddArgument>) -> Response {
+        if let Some(resp) = hotplug_check(&self.get_vm_state().0) {
+            return resp;
+        }
+
         if let Err(e) = self.check_device_id_existed(&args.id) {
             return Response::create_error_response(
                 qmp_schema::QmpErrorClass::GenericError(e.to_string()),
@@ -1282,7 +1393,7 @@ impl DeviceInterface for StdMachine {
                 }
             }
             #[cfg(not(target_env = "musl"))]
-            "usb-kbd" | "usb-tablet" | "usb-camera" | "usb-host" => {
+            "usb-kbd" | "usb-tablet" | "usb-mouse" | "usb-camera" | "usb-host" => {
                 if let Err(e) = self.plug_usb_device(args.as_ref()) {
                     error!("{:?}", e);
                     return Response::create_error_response(
@@ -1715,9 +1826,11 @@ impl DeviceInterface for StdMachine {
                             None,
                             args.size,
                             fd.map(FileBackend::new_common),
-                            false,
-                            true,
-                            true,
+                            HostMemOptions {
+                                is_share: true,
+                                read_only: true,
+                                ..Default::default()
+                            },
                         )
                         .unwrap(),
                     ),
@@ -1733,9 +1846,10 @@ impl DeviceInterface for StdMachine {
                             None,
                             args.size,
                             fd.map(FileBackend::new_common),
-                            false,
-                            true,
-                            false,
+                            HostMemOptions {
+                                is_share: true,
+                                ..Default::default()
+                            },
                         )
                         .unwrap(),
                     ),
@@ -2000,6 +2114,121 @@ impl DeviceInterface for StdMachine {
     }
 }
 
+impl SnapshotVm for StdMachine {
+    fn cpu_states(&self) -> Result<Vec<CpuState>> {
+        let mut states = Vec::new();
+        for (id, cpu) in self.get_cpus().iter().enumerate() {
+            let state = cpu
+                .get_state_vec()
+                .with_context(|| format!("Failed to get state of CPU {}", id))?;
+            states.push(CpuState {
+                id: id as u8,
+                state,
+            });
+        }
+        Ok(states)
+    }
+
+    fn restore_cpu_states(&self, states: Vec<CpuState>) -> Result<()> {
+        let cpus = self.get_cpus();
+        if states.len() != cpus.len() {
+            bail!(
+                "Snapshot of {} CPUs does not match {} CPUs",
+                states.len(),
+                cpus.len()
+            );
+        }
+        for state in states.iter() {
+            cpus.get(state.id as usize)
+                .with_context(|| format!("Invalid CPU id {} in snapshot", state.id))?
+                .set_state(&state.state)
+                .with_context(|| format!("Failed to set state of CPU {}", state.id))?;
+        }
+        Ok(())
+    }
+
+    fn device_states(&self) -> Result<Vec<DeviceState>> {
+        Ok(MigrationManager::get_device_states()?
+            .into_iter()
+            .map(|(id, state)| DeviceState { id, state })
+            .collect())
+    }
+
+    fn restore_device_states(&self, states: Vec<DeviceState>) -> Result<()> {
+        let states: Vec<(u64, Vec<u8>)> = states
+            .into_iter()
+            .map(|device| (device.id, device.state))
+            .collect();
+        MigrationManager::set_device_states(&states)
+    }
+
+    fn memory_size(&self) -> u64 {
+        self.get_vm_ram()
+            .subregions()
+            .iter()
+            .map(|region| region.offset().raw_value() + region.size())
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn read_memory(&self, dst: &mut dyn std::io::Write, offset: u64, len: u64) -> Result<()> {
+        access_machine_ram(self.get_vm_ram(), offset, len, |region, offset, count| {
+            region.read(dst, region.offset(), offset, count)
+        })
+    }
+
+    fn write_memory(&self, src: &mut dyn std::io::Read, offset: u64, len: u64) -> Result<()> {
+        access_machine_ram(self.get_vm_ram(), offset, len, |region, offset, count| {
+            region.write(src, region.offset(), offset, count)
+        })
+    }
+}
+
+/// Access the RAM regions of machine RAM in turn.
+///
+/// # Arguments
+///
+/// * `ram` - The container region of machine RAM.
+/// * `offset` - Offset in machine RAM.
+/// * `len` - Length of the data.
+/// * `access` - Access `count` bytes at `offset` of the RAM region.
+fn access_machine_ram(
+    ram: &Region,
+    offset: u64,
+    len: u64,
+    mut access: impl FnMut(&Region, u64, u64) -> Result<()>,
+) -> Result<()> {
+    let end = offset
+        .checked_add(len)
+        .with_context(|| format!("Machine RAM 0x{:x} + 0x{:x} overflows", offset, len))?;
+    let mut regions = ram.subregions();
+    regions.sort_by_key(|region| region.offset().raw_value());
+
+    let mut cur = offset;
+    for region in regions.iter() {
+        let start = region.offset().raw_value();
+        let region_end = start + region.size();
+        if cur >= end || cur < start {
+            break;
+        }
+        if cur >= region_end {
+            continue;
+        }
+        let count = std::cmp::min(end, region_end) - cur;
+        access(region, cur - start, count)?;
+        cur += count;
+    }
+    if cur != end {
+        bail!(
+            "Machine RAM [0x{:x}, 0x{:x}) is not mapped at 0x{:x}",
+            offset,
+            end,
+            cur
+        );
+    }
+    Ok(())
+}
+
 #[cfg(not(target_env = "musl"))]
 fn send_input_event(key: String, value: String) -> Result<()> {
     match key.as_str() {
@@ -28,9 +28,11 @@ use acpi::{
     AmlBuilder, AmlDevice, AmlInteger, AmlNameDecl, AmlPackage, AmlScope, AmlScopeBuilder,
     AmlString, TableLoader, IOAPIC_BASE_ADDR, LAPIC_BASE_ADDR,
 };
-use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
-use boot_loader::{load_linux, BootLoaderConfig};
-use cpu::{CPUBootConfig, CPUInterface, CPUTopology, CpuTopology, CPU};
+use address_space::{AddressSpace, GuestAddress, HostMemMapping, HostMemOptions, Region};
+use boot_loader::{build_cmdline, load_linux, BootLoaderConfig};
+use cpu::{
+    check_la57, CPUBootConfig, CPUInterface, CPUTopology, CpuFeatureFilter, CpuTopology, CPU,
+};
 use devices::legacy::{
     error::LegacyError as DevErrorKind, FwCfgEntryType, FwCfgIO, FwCfgOps, PFlash, Serial, RTC,
     SERIAL_ADDR,
@@ -417,16 +419,22 @@ impl MachineOps for StdMachine {
         let gap_start = MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].0
             + MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].1;
         let gap_end = MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize].0;
+        let kernel_cmdline = build_cmdline(&[&boot_source.kernel_cmdline.to_string()]);
+        let vm_config = self.vm_config.lock().unwrap();
         let bootloader_config = BootLoaderConfig {
             kernel: boot_source.kernel_file.clone(),
             initrd,
-            kernel_cmdline: boot_source.kernel_cmdline.to_string(),
+            kernel_cmdline,
             cpu_count: self.cpu_topo.nrcpus,
             gap_range: (gap_start, gap_end - gap_start),
             ioapic_addr: MEM_LAYOUT[LayoutEntryType::IoApic as usize].0 as u32,
             lapic_addr: MEM_LAYOUT[LayoutEntryType::LocalApic as usize].0 as u32,
             ident_tss_range: Some(MEM_LAYOUT[LayoutEntryType::IdentTss as usize]),
             prot64_mode: false,
+            five_level_paging: vm_config.machine_config.cpu_config.la57,
+            reserve_pci_hole: vm_config.machine_config.reserve_pci_hole,
+            reserve_kernel_init: vm_config.machine_config.reserve_kernel_init,
+            auto_serial_console: vm_config.machine_config.auto_serial_console,
         };
         let layout = load_linux(&bootloader_config, &self.sys_mem, fwcfg)
             .with_context(|| MachineError::LoadKernErr)?;
@@ -513,6 +521,18 @@ impl MachineOps for StdMachine {
             &topology,
             &boot_config,
         )?);
+        if let Some(khz) = vm_config.machine_config.cpu_config.tsc_freq_khz {
+            for cpu in locked_vm.cpus.iter() {
+                cpu.set_tsc_frequency(khz)?;
+            }
+        }
+        let cpuid_filter = CpuFeatureFilter::from(&vm_config.machine_config.cpu_config);
+        if vm_config.machine_config.cpu_config.la57 {
+            check_la57(&cpuid_filter)?;
+        }
+        for cpu in locked_vm.cpus.iter() {
+            cpu.set_cpuid_filter(cpuid_filter.clone());
+        }
 
         if migrate.0 == MigrateMode::Unknown {
             if let Some(fw_cfg) = fwcfg {
@@ -577,9 +597,7 @@ impl MachineOps for StdMachine {
                     None,
                     rom_size,
                     None,
-                    false,
-                    false,
-                    false,
+                    HostMemOptions::default(),
                 )?);
                 let rom_region = Region::init_ram_region(ram1, "PflashRam");
                 rom_region.write(&mut fd, GuestAddress(rom_base), 0, rom_size)?;
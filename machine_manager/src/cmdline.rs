@@ -112,8 +112,9 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("cpu")
             .long("cpu")
-            .value_name("host[,pmu=on|off]")
-            .help("set CPU model and features.")
+            .value_name("host[,pmu=on|off][,tsc-khz=<khz>][,feature-mask=<leaf>[.<subleaf>].<reg>@<mask>[:...]][,la57=on|off]")
+            .help("set CPU model and features. 'feature-mask' ANDs the register 'reg' (eax, ebx, ecx or edx) \
+                   of CPUID leaf 'leaf' and subleaf 'subleaf' (default to 0) with 'mask', multiple masks are separated by ':'.")
             .can_no_value(false)
             .takes_value(true)
         )
@@ -139,6 +140,13 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .help("configure file path that backs guest memory.")
             .takes_value(true),
         )
+        .arg(
+            Arg::with_name("mem-hugepage-size")
+            .long("mem-hugepage-size")
+            .value_name("<2M|1G>")
+            .help("configure huge page size of hugetlbfs that backs guest memory, used with mem-path.")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("mem-prealloc")
             .long("mem-prealloc")
@@ -146,6 +154,13 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .takes_value(false)
             .required(false),
         )
+        .arg(
+            Arg::with_name("mem-prefault")
+            .long("mem-prefault")
+            .help("Populate page tables of VM memory when it is mapped")
+            .takes_value(false)
+            .required(false),
+        )
         .arg(
             Arg::with_name("numa")
             .multiple(true)
@@ -192,6 +207,13 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .help("set QMP's unix socket path")
             .takes_value(true)
         )
+        .arg(
+            Arg::with_name("hmp")
+            .long("hmp")
+            .value_name("unix:<socket_path>")
+            .help("set HMP's unix socket path")
+            .takes_value(true)
+        )
         .arg(
             Arg::with_name("mod-test")
             .long("mod-test")
@@ -513,6 +535,11 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
     add_args_to_config!((args.value_of("accel")), vm_cfg, add_accel);
     add_args_to_config!((args.value_of("memory")), vm_cfg, add_memory);
     add_args_to_config!((args.value_of("mem-path")), vm_cfg, add_mem_path);
+    add_args_to_config!(
+        (args.value_of("mem-hugepage-size")),
+        vm_cfg,
+        add_mem_hugepage_size
+    );
     add_args_to_config!((args.value_of("smp")), vm_cfg, add_cpu);
     add_args_to_config!((args.value_of("cpu")), vm_cfg, add_cpu_feature);
     add_args_to_config!((args.value_of("kernel")), vm_cfg, add_kernel);
@@ -539,6 +566,12 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
         enable_mem_prealloc,
         bool
     );
+    add_args_to_config!(
+        (args.is_present("mem-prefault")),
+        vm_cfg,
+        enable_mem_prefault,
+        bool
+    );
     add_args_to_config!(
         (args.values_of("kernel-cmdline")),
         vm_cfg,
@@ -580,23 +613,7 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
 pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<Vec<UnixListener>> {
     let mut sock_paths = Vec::new();
     if let Some(qmp_config) = args.value_of("qmp") {
-        let mut cmd_parser = CmdParser::new("qmp");
-        cmd_parser.push("").push("server").push("nowait");
-
-        cmd_parser.parse(&qmp_config)?;
-        if let Some(uri) = cmd_parser.get_value::<String>("")? {
-            let api_path =
-                parse_unix_uri(&uri).with_context(|| "Failed to parse qmp socket path")?;
-            sock_paths.push(api_path);
-        } else {
-            bail!("No uri found for qmp");
-        }
-        if cmd_parser.get_value::<String>("server")?.is_none() {
-            bail!("Argument \'server\' is needed for qmp");
-        }
-        if cmd_parser.get_value::<String>("nowait")?.is_none() {
-            bail!("Argument \'nowait\' is needed for qmp");
-        }
+        sock_paths.push(parse_api_socket_path("qmp", &qmp_config)?);
     }
     if let Some(mon_config) = args.value_of("mon") {
         let mut cmd_parser = CmdParser::new("monitor");
@@ -651,6 +668,45 @@ pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<
     Ok(listeners)
 }
 
+/// This function is to parse hmp socket path and bind it.
+///
+/// # Arguments
+///
+/// * `args` - The structure accepted input cmdline arguments.
+///
+/// # Errors
+///
+/// The value of `hmp` is illegel.
+pub fn check_hmp_channel(args: &ArgMatches) -> Result<Option<UnixListener>> {
+    if let Some(hmp_config) = args.value_of("hmp") {
+        let path = parse_api_socket_path("hmp", &hmp_config)?;
+        let listener = bind_socket(path.clone())
+            .with_context(|| format!("Failed to bind socket for path: {:?}", &path))?;
+        return Ok(Some(listener));
+    }
+    Ok(None)
+}
+
+/// Parse the socket path of api channel, such as `unix:<path>,server,nowait`.
+fn parse_api_socket_path(name: &str, config: &str) -> Result<String> {
+    let mut cmd_parser = CmdParser::new(name);
+    cmd_parser.push("").push("server").push("nowait");
+
+    cmd_parser.parse(config)?;
+    let path = if let Some(uri) = cmd_parser.get_value::<String>("")? {
+        parse_unix_uri(&uri).with_context(|| format!("Failed to parse {} socket path", name))?
+    } else {
+        bail!("No uri found for {}", name);
+    };
+    if cmd_parser.get_value::<String>("server")?.is_none() {
+        bail!("Argument \'server\' is needed for {}", name);
+    }
+    if cmd_parser.get_value::<String>("nowait")?.is_none() {
+        bail!("Argument \'nowait\' is needed for {}", name);
+    }
+    Ok(path)
+}
+
 fn bind_socket(path: String) -> Result<UnixListener> {
     clear_file(path.clone())?;
     let listener = UnixListener::bind(&path)
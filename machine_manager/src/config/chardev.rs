@@ -25,6 +25,8 @@ const MIN_GUEST_CID: u64 = 3;
 
 /// Default value of max ports for virtio-serial.
 const DEFAULT_SERIAL_PORTS_NUMBER: u32 = 31;
+/// Max value of max ports for virtio-serial.
+const MAX_SERIAL_PORTS_NUMBER: u32 = 32;
 
 /// Character device options.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -292,6 +294,9 @@ pub fn parse_virtserialport(
             is_console,
         };
         port_cfg.check()?;
+        if let Some(serial) = vm_config.virtio_serial.as_ref() {
+            serial.check_port(&port_cfg)?;
+        }
         return Ok(port_cfg);
     }
     bail!("Chardev {:?} not found or is in use", &chardev_name);
@@ -457,12 +462,12 @@ impl ConfigCheck for VirtioSerialInfo {
     fn check(&self) -> Result<()> {
         check_arg_too_long(&self.id, "virtio-serial id")?;
 
-        if self.max_ports < 1 || self.max_ports > DEFAULT_SERIAL_PORTS_NUMBER {
+        if self.max_ports < 1 || self.max_ports > MAX_SERIAL_PORTS_NUMBER {
             return Err(anyhow!(ConfigError::IllegalValue(
                 "Virtio-serial max_ports".to_string(),
                 1,
                 true,
-                DEFAULT_SERIAL_PORTS_NUMBER as u64,
+                MAX_SERIAL_PORTS_NUMBER as u64,
                 true
             )));
         }
@@ -471,6 +476,23 @@ impl ConfigCheck for VirtioSerialInfo {
     }
 }
 
+impl VirtioSerialInfo {
+    /// Check the port attached to this virtio-serial, whose nr should be less
+    /// than `max_ports`.
+    fn check_port(&self, port: &VirtioSerialPort) -> Result<()> {
+        if port.nr >= self.max_ports {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                format!("nr of virtio serial port {}", port.id),
+                0,
+                true,
+                self.max_ports as u64,
+                false
+            )));
+        }
+        Ok(())
+    }
+}
+
 pub fn parse_virtio_serial(
     vm_config: &mut VmConfig,
     serial_config: &str,
@@ -532,9 +554,10 @@ mod tests {
         assert!(vm_config
             .add_chardev("socket,id=test_console,path=/path/to/socket,server,nowait")
             .is_ok());
+        // Micro_vm supports only one port.
         let virt_console = parse_virtserialport(
             &mut vm_config,
-            "virtconsole,chardev=test_console,id=console1,nr=1",
+            "virtconsole,chardev=test_console,id=console1,nr=0",
             true,
         );
         assert!(virt_console.is_ok());
@@ -613,6 +636,74 @@ mod tests {
         .is_ok());
     }
 
+    #[test]
+    fn test_virtio_serial_multi_ports() {
+        let mut vm_config = VmConfig::default();
+        let serial_info = parse_virtio_serial(
+            &mut vm_config,
+            "virtio-serial-pci,id=serial0,bus=pcie.0,addr=0x1,max_ports=4",
+        )
+        .unwrap();
+        assert_eq!(serial_info.max_ports, 4);
+        // Only one virtio serial device is supported.
+        assert!(parse_virtio_serial(
+            &mut vm_config,
+            "virtio-serial-pci,id=serial1,bus=pcie.0,addr=0x2"
+        )
+        .is_err());
+
+        let ports = [
+            ("virtconsole", "console0", 0, true),
+            ("virtserialport", "port1", 1, false),
+            ("virtserialport", "port3", 3, false),
+        ];
+        for (driver, id, nr, is_console) in ports {
+            vm_config
+                .add_chardev(&format!("pty,id=chardev_{}", id))
+                .unwrap();
+            let port = parse_virtserialport(
+                &mut vm_config,
+                &format!("{},chardev=chardev_{},id={},nr={}", driver, id, id, nr),
+                is_console,
+            )
+            .unwrap();
+            assert_eq!(port.nr, nr);
+            assert_eq!(port.is_console, is_console);
+            assert_eq!(port.chardev.backend, ChardevType::Pty);
+        }
+
+        // The nr of port is out of max_ports.
+        vm_config.add_chardev("pty,id=chardev_port4").unwrap();
+        assert!(parse_virtserialport(
+            &mut vm_config,
+            "virtserialport,chardev=chardev_port4,id=port4,nr=4",
+            false,
+        )
+        .is_err());
+
+        // The max_ports is up to 32.
+        let mut vm_config = VmConfig::default();
+        let serial_info = parse_virtio_serial(
+            &mut vm_config,
+            "virtio-serial-pci,bus=pcie.0,addr=0x1,max_ports=32",
+        )
+        .unwrap();
+        assert_eq!(serial_info.max_ports, 32);
+
+        // The max_ports is out of range.
+        for max_ports in [0, 33] {
+            let mut vm_config = VmConfig::default();
+            assert!(parse_virtio_serial(
+                &mut vm_config,
+                &format!(
+                    "virtio-serial-pci,bus=pcie.0,addr=0x1,max_ports={}",
+                    max_ports
+                ),
+            )
+            .is_err());
+        }
+    }
+
     #[test]
     fn test_vsock_config_cmdline_parser() {
         let vsock_cfg_op = parse_vsock("vhost-vsock-device,id=test_vsock,guest-cid=3");
@@ -10,10 +10,15 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use super::{CmdParser, VmConfig};
-use anyhow::Result;
+use std::collections::HashSet;
+
+use super::{error::ConfigError, get_pci_bdf, CmdParser, VmConfig};
+use anyhow::{anyhow, Result};
 use regex::Regex;
 
+/// Name of the root bus of pci host, which is not declared by any device.
+const PCIE_ROOT_BUS: &str = "pcie.0";
+
 impl VmConfig {
     pub fn add_device(&mut self, device_config: &str) -> Result<()> {
         let mut cmd_params = CmdParser::new("device");
@@ -38,6 +43,63 @@ impl VmConfig {
             }
         }
     }
+
+    /// Collect the ids declared by all devices, and check that the device
+    /// referred by another device exists.
+    pub fn check_device_refs(&self) -> Result<()> {
+        let mut ids = HashSet::from([PCIE_ROOT_BUS.to_string()]);
+        for (_, dev_cfg) in self.devices.iter() {
+            let id = parse_device_id(dev_cfg)?;
+            if !id.is_empty() {
+                ids.insert(id);
+            }
+        }
+
+        for (dev_type, dev_cfg) in self.devices.iter() {
+            if dev_type != "nec-usb-xhci" {
+                continue;
+            }
+            if let Some(bus) = get_device_param(dev_cfg, "bus")? {
+                if !ids.contains(&bus) {
+                    return Err(anyhow!(ConfigError::DanglingReference(
+                        bus,
+                        dev_type.clone()
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that no two pci devices are assigned to the same `bus` and `addr`.
+    pub fn check_device_bus_topology(&self) -> Result<()> {
+        let mut slots = HashSet::new();
+        for (_, dev_cfg) in self.devices.iter() {
+            if get_device_param(dev_cfg, "bus")?.is_none()
+                || get_device_param(dev_cfg, "addr")?.is_none()
+            {
+                continue;
+            }
+            let bdf = get_pci_bdf(dev_cfg)?;
+            if !slots.insert((bdf.bus.clone(), bdf.addr)) {
+                return Err(anyhow!(ConfigError::BusAddrConflict(
+                    bdf.bus,
+                    format!("{:#x}.{:#x}", bdf.addr.0, bdf.addr.1)
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn get_device_param(device_config: &str, key: &str) -> Result<Option<String>> {
+    let mut cmd_parser = CmdParser::new("device");
+    cmd_parser.push(key);
+
+    cmd_parser.get_parameters(device_config)?;
+    cmd_parser.get_value::<String>(key)
 }
 
 pub fn parse_device_id(device_config: &str) -> Result<String> {
@@ -70,4 +132,73 @@ mod tests {
         let id = ret.unwrap();
         assert_eq!("", id);
     }
+
+    #[test]
+    fn test_check_device_refs() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_device("nec-usb-xhci,id=xhci,bus=pcie.0,addr=0xa")
+            .unwrap();
+        assert!(vm_config.check_device_refs().is_ok());
+
+        // The bus of xhci is declared by root port.
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_device("nec-usb-xhci,id=xhci,bus=pcie.1,addr=0x0")
+            .unwrap();
+        vm_config
+            .add_device("pcie-root-port,port=0x1,addr=0x1,bus=pcie.0,id=pcie.1")
+            .unwrap();
+        assert!(vm_config.check_device_refs().is_ok());
+
+        // The bus of xhci does not exist.
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_device("nec-usb-xhci,id=xhci,bus=pcie.2,addr=0xa")
+            .unwrap();
+        let err = vm_config.check_device_refs().unwrap_err();
+        match err.downcast_ref::<ConfigError>() {
+            Some(ConfigError::DanglingReference(name, dev_type)) => {
+                assert_eq!(name, "pcie.2");
+                assert_eq!(dev_type, "nec-usb-xhci");
+            }
+            _ => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_check_device_bus_topology() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_device("pcie-root-port,port=0x1,addr=0x1,bus=pcie.0,id=pcie.1")
+            .unwrap();
+        vm_config
+            .add_device("virtio-blk-pci,drive=rootfs,id=blk0,bus=pcie.0,addr=0x2")
+            .unwrap();
+        vm_config
+            .add_device("virtio-net-pci,netdev=net0,id=net0,bus=pcie.0,addr=0x2.0x1")
+            .unwrap();
+        // The same addr on another bus.
+        vm_config
+            .add_device("virtio-rng-pci,rng=objrng0,id=rng0,bus=pcie.1,addr=0x2")
+            .unwrap();
+        // No bus or addr for mmio device.
+        vm_config
+            .add_device("virtio-blk-device,drive=rootfs1,id=blk1")
+            .unwrap();
+        assert!(vm_config.check_device_bus_topology().is_ok());
+
+        // Two devices on the same bus and addr.
+        vm_config
+            .add_device("virtio-balloon-pci,id=balloon0,bus=pcie.0,addr=0x2.0x0")
+            .unwrap();
+        let err = vm_config.check_device_bus_topology().unwrap_err();
+        match err.downcast_ref::<ConfigError>() {
+            Some(ConfigError::BusAddrConflict(bus, addr)) => {
+                assert_eq!(bus, "pcie.0");
+                assert_eq!(addr, "0x2.0x0");
+            }
+            _ => panic!("Unexpected error: {:?}", err),
+        }
+    }
 }
@@ -21,8 +21,9 @@ use serde::{Deserialize, Serialize};
 
 use super::{error::ConfigError, pci_args_check};
 use crate::config::{
-    check_arg_too_long, get_chardev_socket_path, CmdParser, ConfigCheck, ExBool, VmConfig,
-    DEFAULT_VIRTQUEUE_SIZE, MAX_PATH_LENGTH, MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
+    check_arg_too_long, check_virtio_queues, get_chardev_socket_path, parse_virtio_queues,
+    CmdParser, ConfigCheck, ExBool, VirtioQueueConfig, VmConfig, DEFAULT_VIRTQUEUE_SIZE,
+    MAX_PATH_LENGTH, MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
 };
 use crate::qmp::qmp_schema;
 use util::aio::{aio_probe, AioEngine, WriteZeroesState};
@@ -64,6 +65,14 @@ pub struct BlkDevConfig {
     pub direct: bool,
     pub serial_num: Option<String>,
     pub iothread: Option<String>,
+    /// IOThreads of the virtqueues, queue `i` is handled by the
+    /// `i % queue_iothreads.len()`th one. Empty means all the queues are
+    /// handled by `iothread`.
+    #[serde(default)]
+    pub queue_iothreads: Vec<String>,
+    /// Backend which serves the I/O requests of the virtqueues.
+    #[serde(default)]
+    pub io_backend: IoBackend,
     pub iops: Option<u64>,
     pub queues: u16,
     pub boot_index: Option<u8>,
@@ -71,11 +80,28 @@ pub struct BlkDevConfig {
     pub socket_path: Option<String>,
     pub aio: AioEngine,
     pub queue_size: u16,
+    /// Queue size overrides of some queues.
+    #[serde(default)]
+    pub queue_overrides: Vec<VirtioQueueConfig>,
     pub discard: bool,
     pub write_zeroes: WriteZeroesState,
     pub format: DiskFormat,
 }
 
+impl BlkDevConfig {
+    /// Get the iothread which handles the virtqueue.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the virtqueue.
+    pub fn queue_iothread(&self, index: usize) -> Option<&String> {
+        if self.queue_iothreads.is_empty() {
+            return self.iothread.as_ref();
+        }
+        self.queue_iothreads.get(index % self.queue_iothreads.len())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BootIndexInfo {
     pub boot_index: u8,
@@ -92,6 +118,8 @@ impl Default for BlkDevConfig {
             direct: true,
             serial_num: None,
             iothread: None,
+            queue_iothreads: Vec::new(),
+            io_backend: IoBackend::Sync,
             iops: None,
             queues: 1,
             boot_index: None,
@@ -99,6 +127,7 @@ impl Default for BlkDevConfig {
             socket_path: None,
             aio: AioEngine::Native,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            queue_overrides: Vec::new(),
             discard: false,
             write_zeroes: WriteZeroesState::Off,
             format: DiskFormat::Raw,
@@ -106,6 +135,30 @@ impl Default for BlkDevConfig {
     }
 }
 
+/// Backend of virtio-blk which serves the I/O requests of the virtqueues.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum IoBackend {
+    /// All the virtqueues share one block backend, whose I/O is done by the
+    /// `aio` engine of the drive.
+    #[default]
+    Sync,
+    /// Each virtqueue owns a block backend with a dedicated io_uring instance,
+    /// which is submitted and reaped in the iothread of the virtqueue.
+    IoUring,
+}
+
+impl FromStr for IoBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sync" => Ok(IoBackend::Sync),
+            "io_uring" => Ok(IoBackend::IoUring),
+            _ => Err(anyhow!("Unknown io backend type")),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum DiskFormat {
     Raw,
@@ -270,18 +323,60 @@ impl ConfigCheck for BlkDevConfig {
             )));
         }
 
-        if self.queue_size <= MIN_QUEUE_SIZE_BLK || self.queue_size > MAX_QUEUE_SIZE_BLK {
+        for iothread in &self.queue_iothreads {
+            check_arg_too_long(iothread, "iothread name")?;
+        }
+        if self.queue_iothreads.len() > self.queues as usize {
             return Err(anyhow!(ConfigError::IllegalValue(
-                "queue size of block device".to_string(),
-                MIN_QUEUE_SIZE_BLK as u64,
-                false,
-                MAX_QUEUE_SIZE_BLK as u64,
-                true
+                "number of queue iothreads of block device".to_string(),
+                0,
+                true,
+                self.queues as u64,
+                true,
             )));
         }
+        if self.io_backend == IoBackend::IoUring {
+            // Every queue submits its requests to its own io_uring in its own iothread.
+            let mut iothreads = self.queue_iothreads.clone();
+            iothreads.sort();
+            iothreads.dedup();
+            if iothreads.len() != self.queues as usize {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    "queue-iothreads".to_string(),
+                    "io_uring backend needs one distinct iothread for every queue".to_string(),
+                )));
+            }
+            // The queues can not share the metadata of qcow2 image.
+            if self.format != DiskFormat::Raw {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    "io-backend".to_string(),
+                    "io-backend=io_uring only supports raw format".to_string(),
+                )));
+            }
+            if self.aio != AioEngine::IoUring {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    "io-backend".to_string(),
+                    "io-backend=io_uring requires aio=io_uring of the drive".to_string(),
+                )));
+            }
+        }
+
+        check_virtio_queues(&self.queue_overrides, self.queues)?;
+        let queue_sizes = self.queue_overrides.iter().map(|queue| queue.size);
+        for queue_size in std::iter::once(self.queue_size).chain(queue_sizes) {
+            if queue_size <= MIN_QUEUE_SIZE_BLK || queue_size > MAX_QUEUE_SIZE_BLK {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "queue size of block device".to_string(),
+                    MIN_QUEUE_SIZE_BLK as u64,
+                    false,
+                    MAX_QUEUE_SIZE_BLK as u64,
+                    true
+                )));
+            }
 
-        if self.queue_size & (self.queue_size - 1) != 0 {
-            bail!("Queue size should be power of 2!");
+            if queue_size & (queue_size - 1) != 0 {
+                bail!("Queue size should be power of 2!");
+            }
         }
 
         let fake_drive = DriveConfig {
@@ -360,8 +455,11 @@ pub fn parse_blk(
         .push("bootindex")
         .push("serial")
         .push("iothread")
+        .push("queue-iothreads")
+        .push("io-backend")
         .push("num-queues")
-        .push("queue-size");
+        .push("queue-size")
+        .push("queue-override");
 
     cmd_parser.parse(drive_config)?;
 
@@ -380,6 +478,24 @@ pub fn parse_blk(
         blkdevcfg.iothread = Some(iothread);
     }
 
+    // Iothreads of the queues are separated by colon, such as
+    // `queue-iothreads=iothread0:iothread1`.
+    if let Some(iothreads) = cmd_parser.get_value::<String>("queue-iothreads")? {
+        for iothread in iothreads.split(':') {
+            if iothread.is_empty() {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    "queue-iothreads".to_string(),
+                    "iothread name should not be empty".to_string(),
+                )));
+            }
+            blkdevcfg.queue_iothreads.push(iothread.to_string());
+        }
+    }
+
+    if let Some(io_backend) = cmd_parser.get_value::<IoBackend>("io-backend")? {
+        blkdevcfg.io_backend = io_backend;
+    }
+
     if let Some(serial) = cmd_parser.get_value::<String>("serial")? {
         blkdevcfg.serial_num = Some(serial);
     }
@@ -397,6 +513,7 @@ pub fn parse_blk(
     if let Some(queue_size) = cmd_parser.get_value::<u16>("queue-size")? {
         blkdevcfg.queue_size = queue_size;
     }
+    blkdevcfg.queue_overrides = parse_virtio_queues(&cmd_parser)?;
 
     let drive_arg = &vm_config
         .drives
@@ -428,6 +545,7 @@ pub fn parse_vhost_user_blk_pci(
         .push("num-queues")
         .push("chardev")
         .push("queue-size")
+        .push("queue-override")
         .push("bootindex");
 
     cmd_parser.parse(drive_config)?;
@@ -460,6 +578,7 @@ pub fn parse_vhost_user_blk_pci(
     if let Some(size) = cmd_parser.get_value::<u16>("queue-size")? {
         blkdevcfg.queue_size = size;
     }
+    blkdevcfg.queue_overrides = parse_virtio_queues(&cmd_parser)?;
 
     if let Some(chardev) = &blkdevcfg.chardev {
         blkdevcfg.socket_path = Some(get_chardev_socket_path(chardev, vm_config)?);
@@ -751,6 +870,164 @@ mod tests {
         assert!(parse_blk(&mut vm_config, blk_cfg, None).is_ok());
     }
 
+    #[test]
+    fn test_block_queue_override() {
+        let add_drive = |vm_config: &mut VmConfig| {
+            vm_config
+                .add_drive("id=rootfs,file=/path/to/rootfs,readonly=off,direct=on")
+                .unwrap();
+        };
+        let mut vm_config = VmConfig::default();
+        add_drive(&mut vm_config);
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=4,\
+                       queue-override=1@128:3@1024";
+        let blk = parse_blk(&mut vm_config, blk_cfg, None).unwrap();
+        assert_eq!(
+            blk.queue_overrides,
+            vec![
+                VirtioQueueConfig {
+                    index: 1,
+                    size: 128
+                },
+                VirtioQueueConfig {
+                    index: 3,
+                    size: 1024
+                }
+            ]
+        );
+
+        // Index exceeds num-queues.
+        add_drive(&mut vm_config);
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=4,\
+                       queue-override=4@128";
+        assert!(parse_blk(&mut vm_config, blk_cfg, None).is_err());
+
+        // Size exceeds the range of block device.
+        vm_config = VmConfig::default();
+        add_drive(&mut vm_config);
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,\
+                       queue-override=0@2048";
+        assert!(parse_blk(&mut vm_config, blk_cfg, None).is_err());
+
+        // The overrides of vhost-user-blk-pci.
+        let add_chardev = |vm_config: &mut VmConfig| {
+            vm_config
+                .add_chardev("socket,id=char0,path=/path/to/socket")
+                .unwrap();
+        };
+        vm_config = VmConfig::default();
+        add_chardev(&mut vm_config);
+        let blk_cfg = "vhost-user-blk-pci,id=blk0,chardev=char0,bus=pcie.0,addr=0x1,\
+                       num-queues=2,queue-override=1@512";
+        let blk = parse_vhost_user_blk_pci(&mut vm_config, blk_cfg, None).unwrap();
+        assert_eq!(
+            blk.queue_overrides,
+            vec![VirtioQueueConfig {
+                index: 1,
+                size: 512
+            }]
+        );
+        add_chardev(&mut vm_config);
+        let blk_cfg = "vhost-user-blk-pci,id=blk0,chardev=char0,bus=pcie.0,addr=0x1,\
+                       num-queues=2,queue-override=2@512";
+        assert!(parse_vhost_user_blk_pci(&mut vm_config, blk_cfg, None).is_err());
+    }
+
+    #[test]
+    fn test_block_queue_iothreads() {
+        let add_drive = |vm_config: &mut VmConfig| {
+            vm_config
+                .add_drive("id=rootfs,file=/path/to/rootfs,readonly=off,direct=on")
+                .unwrap();
+        };
+        let mut vm_config = VmConfig::default();
+        add_drive(&mut vm_config);
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=4,\
+                       iothread=iothread0,queue-iothreads=iothread1:iothread2";
+        let blk = parse_blk(&mut vm_config, blk_cfg, None).unwrap();
+        assert_eq!(blk.queue_iothreads, vec!["iothread1", "iothread2"]);
+        let queue_iothreads: Vec<Option<&String>> =
+            (0..4).map(|index| blk.queue_iothread(index)).collect();
+        assert_eq!(
+            queue_iothreads,
+            vec![
+                Some(&"iothread1".to_string()),
+                Some(&"iothread2".to_string()),
+                Some(&"iothread1".to_string()),
+                Some(&"iothread2".to_string()),
+            ]
+        );
+
+        // All the queues are handled by `iothread` by default.
+        add_drive(&mut vm_config);
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=2,\
+                       iothread=iothread0";
+        let blk = parse_blk(&mut vm_config, blk_cfg, None).unwrap();
+        assert_eq!(blk.queue_iothread(1), Some(&"iothread0".to_string()));
+
+        // More iothreads than queues.
+        add_drive(&mut vm_config);
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=1,\
+                       queue-iothreads=iothread1:iothread2";
+        assert!(parse_blk(&mut vm_config, blk_cfg, None).is_err());
+
+        // Empty iothread name.
+        add_drive(&mut vm_config);
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=2,\
+                       queue-iothreads=iothread1:";
+        assert!(parse_blk(&mut vm_config, blk_cfg, None).is_err());
+    }
+
+    #[test]
+    fn test_block_io_backend() {
+        let add_drive = |vm_config: &mut VmConfig, format: &str, aio: &str| {
+            vm_config
+                .add_drive(&format!(
+                    "id=rootfs,file=/path/to/rootfs,readonly=off,direct=on,format={},aio={}",
+                    format, aio
+                ))
+                .unwrap();
+        };
+        let mut vm_config = VmConfig::default();
+        add_drive(&mut vm_config, "raw", "io_uring");
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=2";
+        let blk = parse_blk(&mut vm_config, blk_cfg, None).unwrap();
+        assert_eq!(blk.io_backend, IoBackend::Sync);
+
+        add_drive(&mut vm_config, "raw", "io_uring");
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=2,\
+                       queue-iothreads=iothread1:iothread2,io-backend=io_uring";
+        let blk = parse_blk(&mut vm_config, blk_cfg, None).unwrap();
+        assert_eq!(blk.io_backend, IoBackend::IoUring);
+
+        // Every queue needs its own iothread.
+        add_drive(&mut vm_config, "raw", "io_uring");
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=2,\
+                       queue-iothreads=iothread1,io-backend=io_uring";
+        assert!(parse_blk(&mut vm_config, blk_cfg, None).is_err());
+        add_drive(&mut vm_config, "raw", "io_uring");
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=2,\
+                       queue-iothreads=iothread1:iothread1,io-backend=io_uring";
+        assert!(parse_blk(&mut vm_config, blk_cfg, None).is_err());
+
+        // Qcow2 image is not supported.
+        add_drive(&mut vm_config, "qcow2", "io_uring");
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,num-queues=2,\
+                       queue-iothreads=iothread1:iothread2,io-backend=io_uring";
+        let err = parse_blk(&mut vm_config, blk_cfg, None).unwrap_err();
+        assert!(format!("{:?}", err).contains("io-backend=io_uring"));
+
+        // The aio engine of the drive must be io_uring as well.
+        add_drive(&mut vm_config, "raw", "native");
+        let err = parse_blk(&mut vm_config, blk_cfg, None).unwrap_err();
+        assert!(format!("{:?}", err).contains("io-backend=io_uring"));
+
+        add_drive(&mut vm_config, "raw", "io_uring");
+        let blk_cfg = "virtio-blk-pci,id=rootfs,bus=pcie.0,addr=0x1,drive=rootfs,\
+                       io-backend=async";
+        assert!(parse_blk(&mut vm_config, blk_cfg, None).is_err());
+    }
+
     #[test]
     fn test_pflash_config_cmdline_parser() {
         let mut vm_config = VmConfig::default();
@@ -28,6 +28,8 @@ pub enum ConfigError {
     InvalidJsonField(String),
     #[error("Invalid parameter \'{0}\' for \'{1}\'")]
     InvalidParam(String, String),
+    #[error("Invalid parameter \'{0}\' at position {1} for \'{2}\': {3}")]
+    InvalidParamAt(String, usize, String, String),
     #[error("Unable to parse \'{0}\' for \'{1}\'")]
     ConvertValueFailed(String, String),
     #[error("Input {0} string's length must be no more than {1}.")]
@@ -64,4 +66,10 @@ pub enum ConfigError {
     DirNotExist(String),
     #[error("File {0} does not exist")]
     FileNotExist(String),
+    #[error("\'{0}\' referred by {1} does not exist.")]
+    DanglingReference(String, String),
+    #[error("Too many parameters for \'{0}\', the limit is {1}.")]
+    TooManyParams(String, usize),
+    #[error("Multiple devices are assigned to bus \'{0}\' at addr \'{1}\'.")]
+    BusAddrConflict(String, String),
 }
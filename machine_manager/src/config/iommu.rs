@@ -0,0 +1,135 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::error::ConfigError;
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck, VmConfig};
+
+/// Device type of virtio-iommu.
+pub const VIRTIO_IOMMU_PCI: &str = "virtio-iommu-pci";
+
+/// Config structure for virtio-iommu.
+#[derive(Debug, Clone, Default)]
+pub struct IommuConfig {
+    pub id: String,
+    pub bus: String,
+}
+
+impl ConfigCheck for IommuConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "id")?;
+        check_arg_too_long(&self.bus, "bus")
+    }
+}
+
+pub fn parse_iommu(conf: &str) -> Result<IommuConfig> {
+    let mut cmd_parser = CmdParser::new(VIRTIO_IOMMU_PCI);
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction");
+    cmd_parser.parse(conf)?;
+
+    let iommu = IommuConfig {
+        id: cmd_parser.get_value::<String>("id")?.with_context(|| {
+            ConfigError::FieldIsMissing("id".to_string(), VIRTIO_IOMMU_PCI.to_string())
+        })?,
+        bus: cmd_parser.get_value::<String>("bus")?.with_context(|| {
+            ConfigError::FieldIsMissing("bus".to_string(), VIRTIO_IOMMU_PCI.to_string())
+        })?,
+    };
+    iommu.check()?;
+
+    Ok(iommu)
+}
+
+impl VmConfig {
+    /// Check that at most one virtio-iommu device is configured. DMA remapping
+    /// of vfio devices is not supported yet, so virtio-iommu can't be used
+    /// together with vfio-pci devices.
+    pub fn check_iommu(&self) -> Result<()> {
+        let iommus: Vec<&String> = self
+            .devices
+            .iter()
+            .filter(|(dev_type, _)| dev_type == VIRTIO_IOMMU_PCI)
+            .map(|(_, conf)| conf)
+            .collect();
+        if iommus.len() > 1 {
+            return Err(anyhow!(ConfigError::FieldRepeat(
+                VIRTIO_IOMMU_PCI.to_string(),
+                "machine".to_string()
+            )));
+        }
+        if let Some(conf) = iommus.first() {
+            let iommu = parse_iommu(conf)?;
+            if self
+                .devices
+                .iter()
+                .any(|(dev_type, _)| dev_type == "vfio-pci")
+            {
+                bail!(
+                    "DMA remapping of vfio-pci devices by {} \'{}\' is not supported",
+                    VIRTIO_IOMMU_PCI,
+                    iommu.id
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_iommu() {
+        let iommu = parse_iommu("virtio-iommu-pci,id=iommu0,bus=pcie.0,addr=0x3").unwrap();
+        assert_eq!(iommu.id, "iommu0");
+        assert_eq!(iommu.bus, "pcie.0");
+
+        assert!(parse_iommu("virtio-iommu-pci,bus=pcie.0,addr=0x3").is_err());
+        assert!(parse_iommu("virtio-iommu-pci,id=iommu0,addr=0x3").is_err());
+        assert!(parse_iommu("virtio-iommu-pci,id=iommu0,bus=pcie.0,unknown=1").is_err());
+        let long_id = "i".repeat(300);
+        assert!(parse_iommu(&format!("virtio-iommu-pci,id={},bus=pcie.0", long_id)).is_err());
+    }
+
+    #[test]
+    fn test_check_iommu() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.check_iommu().is_ok());
+
+        vm_config
+            .add_device("virtio-iommu-pci,id=iommu0,bus=pcie.0,addr=0x3")
+            .unwrap();
+        assert!(vm_config.check_iommu().is_ok());
+
+        vm_config
+            .add_device("virtio-iommu-pci,id=iommu1,bus=pcie.0,addr=0x5")
+            .unwrap();
+        assert!(vm_config.check_iommu().is_err());
+
+        // Vfio devices can't be remapped by virtio-iommu.
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_device("virtio-iommu-pci,id=iommu0,bus=pcie.0,addr=0x3")
+            .unwrap();
+        vm_config
+            .add_device("vfio-pci,host=0000:1a:00.3,id=vfio0,bus=pcie.0,addr=0x4")
+            .unwrap();
+        assert!(vm_config.check_iommu().is_err());
+    }
+}
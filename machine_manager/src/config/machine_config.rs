@@ -20,6 +20,7 @@ use crate::config::{
     check_arg_too_long, check_path_too_long, CmdParser, ConfigCheck, ExBool, IntegerList, VmConfig,
     MAX_NODES,
 };
+use util::num_ops::str_to_usize;
 
 const DEFAULT_CPUS: u8 = 1;
 const DEFAULT_THREADS: u8 = 1;
@@ -59,6 +60,35 @@ impl FromStr for MachineType {
     }
 }
 
+/// Size of huge page which backs guest memory.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HugePageSize {
+    Size2M,
+    Size1G,
+}
+
+impl HugePageSize {
+    /// Get the size of huge page in bytes.
+    pub fn size(&self) -> u64 {
+        match self {
+            HugePageSize::Size2M => 2 * M,
+            HugePageSize::Size1G => G,
+        }
+    }
+}
+
+impl FromStr for HugePageSize {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "2m" => Ok(HugePageSize::Size2M),
+            "1g" => Ok(HugePageSize::Size1G),
+            _ => Err(()),
+        }
+    }
+}
+
 #[repr(u32)]
 #[derive(PartialEq, Eq)]
 pub enum HostMemPolicy {
@@ -92,6 +122,7 @@ pub struct MemZoneConfig {
     pub share: bool,
     pub prealloc: bool,
     pub memfd: bool,
+    pub seal: bool,
 }
 
 impl Default for MemZoneConfig {
@@ -106,6 +137,7 @@ impl Default for MemZoneConfig {
             share: false,
             prealloc: false,
             memfd: false,
+            seal: false,
         }
     }
 }
@@ -118,6 +150,8 @@ pub struct MachineMemConfig {
     pub dump_guest_core: bool,
     pub mem_share: bool,
     pub mem_prealloc: bool,
+    pub mem_prefault: bool,
+    pub mem_hugepage_size: Option<HugePageSize>,
     pub mem_zones: Option<Vec<MemZoneConfig>>,
 }
 
@@ -129,6 +163,8 @@ impl Default for MachineMemConfig {
             dump_guest_core: true,
             mem_share: false,
             mem_prealloc: false,
+            mem_prefault: false,
+            mem_hugepage_size: None,
             mem_zones: None,
         }
     }
@@ -137,6 +173,75 @@ impl Default for MachineMemConfig {
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct CpuConfig {
     pub pmu: PmuConfig,
+    /// TSC frequency of vcpu in kHz, the host frequency is used if not set.
+    #[serde(default)]
+    pub tsc_freq_khz: Option<u64>,
+    /// Masks applied to the CPUID leaves exposed to guest.
+    #[serde(default)]
+    pub feature_mask: Vec<CpuidMaskConfig>,
+    /// Boot the kernel with 5-level paging.
+    #[serde(default)]
+    pub la57: bool,
+}
+
+/// Mask of one CPUID leaf, registers which are not set are kept unchanged.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct CpuidMaskConfig {
+    pub leaf: u32,
+    #[serde(default)]
+    pub subleaf: u32,
+    pub eax: Option<u32>,
+    pub ebx: Option<u32>,
+    pub ecx: Option<u32>,
+    pub edx: Option<u32>,
+}
+
+/// Parse the masks of CPUID leaves, the format is
+/// `<leaf>[.<subleaf>].<reg>@<mask>[:<leaf>[.<subleaf>].<reg>@<mask>...]`,
+/// in which `<reg>` is one of `eax`, `ebx`, `ecx` and `edx`.
+///
+/// # Arguments
+///
+/// * `conf` - The feature-mask config string.
+fn parse_cpuid_masks(conf: &str) -> Result<Vec<CpuidMaskConfig>> {
+    let invalid_mask = |item: &str| {
+        anyhow!(ConfigError::InvalidParam(
+            item.to_string(),
+            "feature-mask".to_string()
+        ))
+    };
+    let parse_num = |item: &str, num: &str| -> Result<u32> {
+        let num = str_to_usize(num.to_string()).map_err(|_| invalid_mask(item))?;
+        u32::try_from(num).map_err(|_| invalid_mask(item))
+    };
+
+    let mut masks = Vec::new();
+    for item in conf.split(':') {
+        let (reg, mask) = item.split_once('@').ok_or_else(|| invalid_mask(item))?;
+        let fields: Vec<&str> = reg.split('.').collect();
+        let mut cpuid_mask = match fields.len() {
+            2 => CpuidMaskConfig {
+                leaf: parse_num(item, fields[0])?,
+                ..Default::default()
+            },
+            3 => CpuidMaskConfig {
+                leaf: parse_num(item, fields[0])?,
+                subleaf: parse_num(item, fields[1])?,
+                ..Default::default()
+            },
+            _ => return Err(invalid_mask(item)),
+        };
+        let mask = Some(parse_num(item, mask)?);
+        match fields[fields.len() - 1] {
+            "eax" => cpuid_mask.eax = mask,
+            "ebx" => cpuid_mask.ebx = mask,
+            "ecx" => cpuid_mask.ecx = mask,
+            "edx" => cpuid_mask.edx = mask,
+            _ => return Err(invalid_mask(item)),
+        }
+        masks.push(cpuid_mask);
+    }
+    Ok(masks)
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
@@ -169,6 +274,12 @@ pub struct MachineConfig {
     pub cpu_config: CpuConfig,
     pub shutdown_action: ShutdownAction,
     pub battery: bool,
+    /// Mark the 32-bit PCI hole as reserved in e820.
+    pub reserve_pci_hole: bool,
+    /// Mark the memory used by kernel during early boot as reserved in e820.
+    pub reserve_kernel_init: bool,
+    /// Append serial console to kernel cmdline if no console is specified.
+    pub auto_serial_console: bool,
 }
 
 impl Default for MachineConfig {
@@ -187,6 +298,9 @@ impl Default for MachineConfig {
             cpu_config: CpuConfig::default(),
             shutdown_action: ShutdownAction::default(),
             battery: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: false,
+            auto_serial_console: false,
         }
     }
 }
@@ -198,6 +312,35 @@ impl ConfigCheck for MachineConfig {
             &self.mem_config.mem_size);
         }
 
+        if let Some(hugepage_size) = self.mem_config.mem_hugepage_size {
+            if let Some(zones) = &self.mem_config.mem_zones {
+                for zone in zones {
+                    if zone.mem_path.is_none() {
+                        bail!(
+                            "mem-hugepage-size requires mem-path on hugetlbfs for memory zone {}",
+                            zone.id
+                        );
+                    }
+                    if zone.size % hugepage_size.size() != 0 {
+                        return Err(anyhow!(ConfigError::Unaligned(
+                            format!("size of memory zone {}", zone.id),
+                            zone.size,
+                            hugepage_size.size(),
+                        )));
+                    }
+                }
+            } else if self.mem_config.mem_path.is_none() {
+                bail!("mem-hugepage-size requires mem-path on hugetlbfs");
+            }
+            if self.mem_config.mem_size % hugepage_size.size() != 0 {
+                return Err(anyhow!(ConfigError::Unaligned(
+                    "memory size".to_string(),
+                    self.mem_config.mem_size,
+                    hugepage_size.size(),
+                )));
+            }
+        }
+
         Ok(())
     }
 }
@@ -219,6 +362,11 @@ impl VmConfig {
             .push("mem-share");
         #[cfg(target_arch = "aarch64")]
         cmd_parser.push("gic-version");
+        #[cfg(target_arch = "x86_64")]
+        cmd_parser
+            .push("reserve-pci-hole")
+            .push("reserve-kernel-init")
+            .push("auto-serial-console");
         cmd_parser.parse(mach_config)?;
 
         #[cfg(target_arch = "aarch64")]
@@ -257,6 +405,18 @@ impl VmConfig {
         if let Some(mem_share) = cmd_parser.get_value::<ExBool>("mem-share")? {
             self.machine_config.mem_config.mem_share = mem_share.into();
         }
+        #[cfg(target_arch = "x86_64")]
+        if let Some(reserve) = cmd_parser.get_value::<ExBool>("reserve-pci-hole")? {
+            self.machine_config.reserve_pci_hole = reserve.into();
+        }
+        #[cfg(target_arch = "x86_64")]
+        if let Some(reserve) = cmd_parser.get_value::<ExBool>("reserve-kernel-init")? {
+            self.machine_config.reserve_kernel_init = reserve.into();
+        }
+        #[cfg(target_arch = "x86_64")]
+        if let Some(auto_console) = cmd_parser.get_value::<ExBool>("auto-serial-console")? {
+            self.machine_config.auto_serial_console = auto_console.into();
+        }
 
         Ok(())
     }
@@ -399,6 +559,9 @@ impl VmConfig {
         let mut cmd_parser = CmdParser::new("cpu");
         cmd_parser.push("");
         cmd_parser.push("pmu");
+        cmd_parser.push("tsc-khz");
+        cmd_parser.push("feature-mask");
+        cmd_parser.push("la57");
         cmd_parser.parse(features)?;
         //Check PMU when actually enabling PMU.
         if let Some(k) = cmd_parser.get_value::<String>("pmu")? {
@@ -408,6 +571,24 @@ impl VmConfig {
                 _ => bail!("Invalid PMU option,must be one of \'on\" or \"off\"."),
             }
         }
+        if let Some(khz) = cmd_parser.get_value::<u64>("tsc-khz")? {
+            if khz == 0 {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "tsc-khz".to_string(),
+                    1,
+                    true,
+                    u64::MAX,
+                    true
+                )));
+            }
+            self.machine_config.cpu_config.tsc_freq_khz = Some(khz);
+        }
+        if let Some(masks) = cmd_parser.get_value::<String>("feature-mask")? {
+            self.machine_config.cpu_config.feature_mask = parse_cpuid_masks(&masks)?;
+        }
+        if let Some(la57) = cmd_parser.get_value::<ExBool>("la57")? {
+            self.machine_config.cpu_config.la57 = la57.into();
+        }
         Ok(())
     }
 
@@ -416,10 +597,25 @@ impl VmConfig {
         Ok(())
     }
 
+    pub fn add_mem_hugepage_size(&mut self, hugepage_size: &str) -> Result<()> {
+        let hugepage_size = HugePageSize::from_str(hugepage_size).map_err(|_| {
+            anyhow!(ConfigError::InvalidParam(
+                hugepage_size.to_string(),
+                "mem-hugepage-size".to_string()
+            ))
+        })?;
+        self.machine_config.mem_config.mem_hugepage_size = Some(hugepage_size);
+        Ok(())
+    }
+
     pub fn enable_mem_prealloc(&mut self) {
         self.machine_config.mem_config.mem_prealloc = true;
     }
 
+    pub fn enable_mem_prefault(&mut self) {
+        self.machine_config.mem_config.mem_prefault = true;
+    }
+
     pub fn add_no_shutdown(&mut self) -> bool {
         self.machine_config.shutdown_action = ShutdownAction::ShutdownActionPause;
         true
@@ -516,6 +712,18 @@ impl VmConfig {
         }
     }
 
+    fn get_mem_seal(&self, cmd_parser: &CmdParser) -> Result<bool> {
+        let seal = cmd_parser
+            .get_value::<String>("seal")?
+            .unwrap_or_else(|| "off".to_string());
+
+        if seal.eq("on") || seal.eq("off") {
+            Ok(seal.eq("on"))
+        } else {
+            Err(anyhow!(ConfigError::InvalidParam("seal".to_string(), seal)))
+        }
+    }
+
     fn get_mem_dump(&self, cmd_parser: &CmdParser) -> Result<bool> {
         if let Some(dump_guest) = cmd_parser.get_value::<ExBool>("dump-guest-core")? {
             return Ok(dump_guest.into());
@@ -547,7 +755,8 @@ impl VmConfig {
             .push("share")
             .push("mem-path")
             .push("dump-guest-core")
-            .push("mem-prealloc");
+            .push("mem-prealloc")
+            .push("seal");
         cmd_parser.parse(mem_zone)?;
 
         let zone_config = MemZoneConfig {
@@ -560,6 +769,7 @@ impl VmConfig {
             mem_path: self.get_mem_path(&cmd_parser)?,
             prealloc: self.get_mem_prealloc(&cmd_parser)?,
             memfd: mem_type.eq("memory-backend-memfd"),
+            seal: self.get_mem_seal(&cmd_parser)?,
         };
 
         if (zone_config.mem_path.is_none() && mem_type.eq("memory-backend-file"))
@@ -567,6 +777,9 @@ impl VmConfig {
         {
             bail!("Object type: {} config path err", mem_type);
         }
+        if zone_config.seal && !zone_config.memfd {
+            bail!("Object type: {} does not support seal", mem_type);
+        }
 
         if self.object.mem_object.get(&zone_config.id).is_none() {
             self.object
@@ -710,6 +923,8 @@ mod tests {
             mem_share: false,
             dump_guest_core: false,
             mem_prealloc: false,
+            mem_prefault: false,
+            mem_hugepage_size: None,
             mem_zones: None,
         };
         let mut machine_config = MachineConfig {
@@ -725,6 +940,9 @@ mod tests {
             cpu_config: CpuConfig::default(),
             shutdown_action: ShutdownAction::default(),
             battery: false,
+            reserve_pci_hole: false,
+            reserve_kernel_init: false,
+            auto_serial_console: false,
         };
         assert!(machine_config.check().is_ok());
 
@@ -944,6 +1162,32 @@ mod tests {
         assert_eq!(machine_cfg.mach_type, MachineType::None);
         assert_eq!(machine_cfg.mem_config.dump_guest_core, false);
         assert_eq!(machine_cfg.mem_config.mem_share, false);
+        assert_eq!(machine_cfg.reserve_pci_hole, false);
+        assert_eq!(machine_cfg.reserve_kernel_init, false);
+        assert_eq!(machine_cfg.auto_serial_console, false);
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut vm_config = VmConfig::default();
+            let memory_cfg_str = "type=none,reserve-kernel-init=on";
+            assert!(vm_config.add_machine(memory_cfg_str).is_ok());
+            assert_eq!(vm_config.machine_config.reserve_kernel_init, true);
+
+            let mut vm_config = VmConfig::default();
+            let memory_cfg_str = "type=none,reserve-pci-hole=on";
+            assert!(vm_config.add_machine(memory_cfg_str).is_ok());
+            assert_eq!(vm_config.machine_config.reserve_pci_hole, true);
+            assert_eq!(vm_config.machine_config.reserve_kernel_init, false);
+            let memory_cfg_str = "type=none,reserve-pci-hole=invalid";
+            assert!(vm_config.add_machine(memory_cfg_str).is_err());
+
+            let mut vm_config = VmConfig::default();
+            let memory_cfg_str = "type=none,auto-serial-console=on";
+            assert!(vm_config.add_machine(memory_cfg_str).is_ok());
+            assert_eq!(vm_config.machine_config.auto_serial_console, true);
+            let memory_cfg_str = "type=none,auto-serial-console=invalid";
+            assert!(vm_config.add_machine(memory_cfg_str).is_err());
+        }
 
         let mut vm_config = VmConfig::default();
         let memory_cfg_str = "type=none,accel=kvm-tcg";
@@ -1010,6 +1254,63 @@ mod tests {
         assert_eq!(mem_prealloc, true);
     }
 
+    #[test]
+    fn test_mem_hugepage_size() {
+        let mut vm_config = VmConfig::default();
+        assert_eq!(vm_config.machine_config.mem_config.mem_hugepage_size, None);
+        assert!(vm_config.add_mem_hugepage_size("4M").is_err());
+        vm_config.add_mem_hugepage_size("1G").unwrap();
+        assert_eq!(
+            vm_config.machine_config.mem_config.mem_hugepage_size,
+            Some(HugePageSize::Size1G)
+        );
+        vm_config.add_mem_hugepage_size("2M").unwrap();
+        assert_eq!(
+            vm_config.machine_config.mem_config.mem_hugepage_size,
+            Some(HugePageSize::Size2M)
+        );
+        assert_eq!(HugePageSize::Size2M.size(), 2 * M);
+        assert_eq!(HugePageSize::Size1G.size(), G);
+
+        // Mem path is required.
+        assert!(vm_config.machine_config.check().is_err());
+        vm_config.add_mem_path("/dev/hugepages").unwrap();
+        assert!(vm_config.machine_config.check().is_ok());
+
+        // Memory size must be aligned with huge page size.
+        vm_config.machine_config.mem_config.mem_size = 257 * M;
+        assert!(vm_config.machine_config.check().is_err());
+        vm_config.machine_config.mem_config.mem_size = 2 * G;
+        vm_config.add_mem_hugepage_size("1G").unwrap();
+        assert!(vm_config.machine_config.check().is_ok());
+
+        // Memory zones require their own mem path, aligned with huge page size.
+        let mut zone = MemZoneConfig {
+            id: String::from("mem1"),
+            size: 2 * G,
+            ..Default::default()
+        };
+        vm_config.machine_config.mem_config.mem_zones = Some(vec![zone.clone()]);
+        assert!(vm_config.machine_config.check().is_err());
+        zone.mem_path = Some(String::from("/dev/hugepages"));
+        vm_config.machine_config.mem_config.mem_zones = Some(vec![zone.clone()]);
+        assert!(vm_config.machine_config.check().is_ok());
+        zone.size = G + 2 * M;
+        vm_config.machine_config.mem_config.mem_zones = Some(vec![zone]);
+        assert!(vm_config.machine_config.check().is_err());
+    }
+
+    #[test]
+    fn test_enable_memory_prefault() {
+        let mut vm_config = VmConfig::default();
+        let mem_prefault = vm_config.machine_config.mem_config.mem_prefault;
+        // default value is false.
+        assert_eq!(mem_prefault, false);
+        vm_config.enable_mem_prefault();
+        let mem_prefault = vm_config.machine_config.mem_config.mem_prefault;
+        assert_eq!(mem_prefault, true);
+    }
+
     #[test]
     fn test_add_cpu() {
         let mut vm_config = VmConfig::default();
@@ -1089,6 +1390,30 @@ mod tests {
             )
             .unwrap();
         assert_eq!(zone_config_5.memfd, true);
+        assert_eq!(zone_config_5.seal, false);
+
+        let zone_config_6 = vm_config
+            .add_mem_zone(
+                "-object memory-backend-memfd,size=2M,id=mem6,seal=on",
+                String::from("memory-backend-memfd"),
+            )
+            .unwrap();
+        assert_eq!(zone_config_6.memfd, true);
+        assert_eq!(zone_config_6.seal, true);
+
+        // Only memfd backend can be sealed.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-ram,size=2M,id=mem7,seal=on",
+                String::from("memory-backend-ram"),
+            )
+            .is_err());
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-memfd,size=2M,id=mem8,seal=yes",
+                String::from("memory-backend-memfd"),
+            )
+            .is_err());
     }
 
     #[test]
@@ -1119,4 +1444,100 @@ mod tests {
         vm_config.add_cpu_feature("pmu=on").unwrap();
         assert!(vm_config.machine_config.cpu_config.pmu == PmuConfig::On);
     }
+
+    #[test]
+    fn test_cpu_tsc_freq() {
+        let mut vm_config = VmConfig::default();
+        vm_config.add_cpu_feature("host").unwrap();
+        assert!(vm_config.machine_config.cpu_config.tsc_freq_khz.is_none());
+        vm_config.add_cpu_feature("host,tsc-khz=2000000").unwrap();
+        assert_eq!(
+            vm_config.machine_config.cpu_config.tsc_freq_khz,
+            Some(2_000_000)
+        );
+        assert!(vm_config.add_cpu_feature("host,tsc-khz=0").is_err());
+        assert!(vm_config.add_cpu_feature("host,tsc-khz=fast").is_err());
+    }
+
+    #[test]
+    fn test_cpu_la57() {
+        let mut vm_config = VmConfig::default();
+        vm_config.add_cpu_feature("host").unwrap();
+        assert!(!vm_config.machine_config.cpu_config.la57);
+        vm_config.add_cpu_feature("host,la57=on").unwrap();
+        assert!(vm_config.machine_config.cpu_config.la57);
+        vm_config.add_cpu_feature("host,la57=off").unwrap();
+        assert!(!vm_config.machine_config.cpu_config.la57);
+        assert!(vm_config.add_cpu_feature("host,la57=5").is_err());
+
+        let cpu_config: CpuConfig = serde_json::from_str(r#"{"pmu":"Off"}"#).unwrap();
+        assert!(!cpu_config.la57);
+    }
+
+    #[test]
+    fn test_cpu_feature_mask() {
+        let cpu_config: CpuConfig = serde_json::from_str(r#"{"pmu":"Off"}"#).unwrap();
+        assert!(cpu_config.feature_mask.is_empty());
+
+        let cpu_config: CpuConfig = serde_json::from_str(
+            r#"{"pmu":"Off","feature_mask":[{"leaf":7,"ebx":4294967291},{"leaf":13,"subleaf":1,"eax":0}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cpu_config.feature_mask,
+            vec![
+                CpuidMaskConfig {
+                    leaf: 7,
+                    ebx: Some(0xffff_fffb),
+                    ..Default::default()
+                },
+                CpuidMaskConfig {
+                    leaf: 13,
+                    subleaf: 1,
+                    eax: Some(0),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_cpu_feature("host,feature-mask=0x7.ebx@0xfffffffb:7.0.edx@0xfe3fffff:0xd.1.eax@0")
+            .unwrap();
+        assert_eq!(
+            vm_config.machine_config.cpu_config.feature_mask,
+            vec![
+                CpuidMaskConfig {
+                    leaf: 7,
+                    ebx: Some(0xffff_fffb),
+                    ..Default::default()
+                },
+                CpuidMaskConfig {
+                    leaf: 7,
+                    edx: Some(0xfe3f_ffff),
+                    ..Default::default()
+                },
+                CpuidMaskConfig {
+                    leaf: 13,
+                    subleaf: 1,
+                    eax: Some(0),
+                    ..Default::default()
+                },
+            ]
+        );
+        for mask in [
+            "7.ebx",
+            "7.ebx@",
+            "ebx@0",
+            "7.0.0.ebx@0",
+            "7.esp@0",
+            "x.ebx@0",
+            "7.ebx@0x100000000",
+            "7.ebx@0:",
+        ] {
+            assert!(vm_config
+                .add_cpu_feature(&format!("host,feature-mask={}", mask))
+                .is_err());
+        }
+    }
 }
@@ -22,6 +22,7 @@ pub use error::ConfigError;
 pub use fs::*;
 pub use gpu::*;
 pub use incoming::*;
+pub use iommu::*;
 pub use iothread::*;
 pub use machine_config::*;
 pub use network::*;
@@ -35,6 +36,7 @@ pub use smbios::*;
 pub use tls_creds::*;
 pub use usb::*;
 pub use vfio::*;
+pub use virtio_queue::*;
 pub use vnc::*;
 
 mod balloon;
@@ -49,6 +51,7 @@ pub mod error;
 mod fs;
 mod gpu;
 mod incoming;
+mod iommu;
 mod iothread;
 mod machine_config;
 mod network;
@@ -63,6 +66,7 @@ mod smbios;
 mod tls_creds;
 mod usb;
 mod vfio;
+mod virtio_queue;
 pub mod vnc;
 
 use std::collections::HashMap;
@@ -137,6 +141,9 @@ impl VmConfig {
         self.machine_config.check()?;
 
         check_arg_too_long(&self.guest_name, "name")?;
+        self.check_device_refs()?;
+        self.check_device_bus_topology()?;
+        self.check_iommu()?;
 
         if self.boot_source.kernel_file.is_none()
             && self.machine_config.mach_type == MachineType::MicroVm
@@ -408,10 +415,15 @@ pub trait ConfigCheck: AsAny + Send + Sync + std::fmt::Debug {
     fn check(&self) -> Result<()>;
 }
 
+/// Default limit of parameters parsed by `CmdParser`.
+pub const DEFAULT_MAX_CMD_PARAMS: usize = 64;
+
 /// Struct `CmdParser` used to parse and check cmdline parameters to vm config.
 pub struct CmdParser {
     name: String,
     params: HashMap<String, Option<String>>,
+    /// Maximum number of the comma separated parameters.
+    max_params: usize,
 }
 
 impl CmdParser {
@@ -420,9 +432,37 @@ impl CmdParser {
         CmdParser {
             name: name.to_string(),
             params: HashMap::<String, Option<String>>::new(),
+            max_params: DEFAULT_MAX_CMD_PARAMS,
         }
     }
 
+    /// Set the maximum number of parameters which can be parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_params`: The limit of comma separated parameters.
+    pub fn max_params(&mut self, max_params: usize) -> &mut Self {
+        self.max_params = max_params;
+
+        self
+    }
+
+    /// Split the cmdline parameters string by comma, the number of parameters
+    /// is limited by `max_params`.
+    fn split_params<'a>(&self, cmd_param: &'a str) -> Result<Vec<&'a str>> {
+        let param_items = cmd_param
+            .split(',')
+            .take(self.max_params.saturating_add(1))
+            .collect::<Vec<&str>>();
+        if param_items.len() > self.max_params {
+            return Err(anyhow!(ConfigError::TooManyParams(
+                self.name.clone(),
+                self.max_params
+            )));
+        }
+        Ok(param_items)
+    }
+
     /// Push a new param field into `params`.
     ///
     /// # Arguments
@@ -440,18 +480,27 @@ impl CmdParser {
     ///
     /// * `cmd_param`: The whole cmdline parameter string.
     pub fn parse(&mut self, cmd_param: &str) -> Result<()> {
-        if cmd_param.starts_with(',') || cmd_param.ends_with(',') {
-            return Err(anyhow!(ConfigError::InvalidParam(
-                cmd_param.to_string(),
-                self.name.clone()
-            )));
-        }
-        let param_items = cmd_param.split(',').collect::<Vec<&str>>();
+        let param_items = self.split_params(cmd_param)?;
+        // Position of the current item in `cmd_param`.
+        let mut pos = 0;
         for (i, param_item) in param_items.iter().enumerate() {
-            if param_item.starts_with('=') || param_item.ends_with('=') {
-                return Err(anyhow!(ConfigError::InvalidParam(
+            let item_pos = pos;
+            pos += param_item.len() + 1;
+            let reason = if param_item.is_empty() && param_items.len() > 1 {
+                Some("empty parameter".to_string())
+            } else if param_item.starts_with('=') {
+                Some("empty key".to_string())
+            } else if param_item.ends_with('=') {
+                Some("empty value".to_string())
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                return Err(anyhow!(ConfigError::InvalidParamAt(
                     param_item.to_string(),
-                    self.name.clone()
+                    item_pos,
+                    self.name.clone(),
+                    reason
                 )));
             }
             let param = param_item.splitn(2, '=').collect::<Vec<&str>>();
@@ -483,9 +532,11 @@ impl CmdParser {
                     )));
                 }
             } else {
-                return Err(anyhow!(ConfigError::InvalidParam(
+                return Err(anyhow!(ConfigError::InvalidParamAt(
                     param[0].to_string(),
-                    self.name.clone()
+                    item_pos,
+                    self.name.clone(),
+                    "unknown key".to_string()
                 )));
             }
         }
@@ -505,7 +556,7 @@ impl CmdParser {
                 self.name.clone()
             )));
         }
-        let param_items = cmd_param.split(',').collect::<Vec<&str>>();
+        let param_items = self.split_params(cmd_param)?;
         for param_item in param_items {
             let param = param_item.splitn(2, '=').collect::<Vec<&str>>();
             let (param_key, param_value) = match param.len() {
@@ -773,6 +824,90 @@ mod tests {
         assert!(cmd_parser.parse("random=false").is_err());
     }
 
+    #[test]
+    fn test_cmd_parser_error_token() {
+        let parse_err = |conf: &str| {
+            let mut cmd_parser = CmdParser::new("nec-usb-xhci");
+            cmd_parser.push("").push("id").push("bus");
+            cmd_parser.parse(conf).unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            parse_err("nec-usb-xhci,=value"),
+            "Invalid parameter '=value' at position 13 for 'nec-usb-xhci': empty key"
+        );
+        assert_eq!(
+            parse_err("nec-usb-xhci,id=xhci,bus="),
+            "Invalid parameter 'bus=' at position 21 for 'nec-usb-xhci': empty value"
+        );
+        assert_eq!(
+            parse_err("nec-usb-xhci,id=xhci,addr=0x1"),
+            "Invalid parameter 'addr' at position 21 for 'nec-usb-xhci': unknown key"
+        );
+        assert_eq!(
+            parse_err(",nec-usb-xhci"),
+            "Invalid parameter '' at position 0 for 'nec-usb-xhci': empty parameter"
+        );
+        assert_eq!(
+            parse_err("nec-usb-xhci,id=xhci,,bus=pcie.0"),
+            "Invalid parameter '' at position 21 for 'nec-usb-xhci': empty parameter"
+        );
+        assert_eq!(
+            parse_err("nec-usb-xhci,"),
+            "Invalid parameter '' at position 13 for 'nec-usb-xhci': empty parameter"
+        );
+    }
+
+    #[test]
+    fn test_cmd_parser_max_params() {
+        let keys = (0..100)
+            .map(|i| format!("key{}", i))
+            .collect::<Vec<String>>();
+        let mut cmd_parser = CmdParser::new("test");
+        for key in keys.iter() {
+            cmd_parser.push(key);
+        }
+        let conf = keys
+            .iter()
+            .map(|key| format!("{}=on", key))
+            .collect::<Vec<String>>()
+            .join(",");
+        let err = cmd_parser.parse(&conf).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Too many parameters for 'test', the limit is {}.",
+                DEFAULT_MAX_CMD_PARAMS
+            )
+        );
+        assert!(cmd_parser.get_parameters(&conf).is_err());
+
+        // Exactly at the limit.
+        let conf = keys[..DEFAULT_MAX_CMD_PARAMS]
+            .iter()
+            .map(|key| format!("{}=on", key))
+            .collect::<Vec<String>>()
+            .join(",");
+        assert!(cmd_parser.parse(&conf).is_ok());
+
+        // The limit is configurable.
+        let mut cmd_parser = CmdParser::new("test");
+        for key in keys.iter() {
+            cmd_parser.push(key);
+        }
+        cmd_parser.max_params(100);
+        let conf = keys
+            .iter()
+            .map(|key| format!("{}=on", key))
+            .collect::<Vec<String>>()
+            .join(",");
+        assert!(cmd_parser.parse(&conf).is_ok());
+        assert_eq!(
+            cmd_parser.get_value::<String>("key99").unwrap(),
+            Some("on".to_string())
+        );
+    }
+
     #[test]
     fn test_add_trace_events_01() {
         assert!(add_trace_events("event=test_trace_events").is_err());
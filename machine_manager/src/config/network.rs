@@ -18,8 +18,8 @@ use serde::{Deserialize, Serialize};
 use super::{error::ConfigError, pci_args_check};
 use crate::config::get_chardev_socket_path;
 use crate::config::{
-    check_arg_too_long, CmdParser, ConfigCheck, ExBool, VmConfig, DEFAULT_VIRTQUEUE_SIZE,
-    MAX_PATH_LENGTH, MAX_VIRTIO_QUEUE,
+    check_arg_too_long, check_virtio_queues, parse_virtio_queues, CmdParser, ConfigCheck, ExBool,
+    VirtioQueueConfig, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_PATH_LENGTH, MAX_VIRTIO_QUEUE,
 };
 use crate::qmp::{qmp_schema, QmpChannel};
 
@@ -95,8 +95,17 @@ pub struct NetworkInterfaceConfig {
     pub queues: u16,
     pub mq: bool,
     pub socket_path: Option<String>,
-    /// All queues of a net device have the same queue size now.
+    /// All queues of a net device have the same queue size by default.
     pub queue_size: u16,
+    /// Queue size overrides of some queues.
+    #[serde(default)]
+    pub queue_overrides: Vec<VirtioQueueConfig>,
+    /// Max packets sent in each wakeup of tx queue, 0 means the queue size.
+    #[serde(default)]
+    pub tx_burst: u16,
+    /// Max packets received in each wakeup of rx queue, 0 means the queue size.
+    #[serde(default)]
+    pub rx_burst: u16,
 }
 
 impl Default for NetworkInterfaceConfig {
@@ -113,6 +122,9 @@ impl Default for NetworkInterfaceConfig {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            queue_overrides: Vec::new(),
+            tx_burst: 0,
+            rx_burst: 0,
         }
     }
 }
@@ -138,18 +150,34 @@ impl ConfigCheck for NetworkInterfaceConfig {
             )));
         }
 
-        if self.queue_size < DEFAULT_VIRTQUEUE_SIZE || self.queue_size > MAX_QUEUE_SIZE_NET {
-            return Err(anyhow!(ConfigError::IllegalValue(
-                "queue size of net device".to_string(),
-                DEFAULT_VIRTQUEUE_SIZE as u64,
-                true,
-                MAX_QUEUE_SIZE_NET as u64,
-                true
-            )));
+        check_virtio_queues(&self.queue_overrides, self.queues)?;
+        let queue_sizes = self.queue_overrides.iter().map(|queue| queue.size);
+        for queue_size in std::iter::once(self.queue_size).chain(queue_sizes) {
+            if !(DEFAULT_VIRTQUEUE_SIZE..=MAX_QUEUE_SIZE_NET).contains(&queue_size) {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "queue size of net device".to_string(),
+                    DEFAULT_VIRTQUEUE_SIZE as u64,
+                    true,
+                    MAX_QUEUE_SIZE_NET as u64,
+                    true
+                )));
+            }
+
+            if queue_size & (queue_size - 1) != 0 {
+                bail!("queue size of net device should be power of 2!");
+            }
         }
 
-        if self.queue_size & (self.queue_size - 1) != 0 {
-            bail!("queue size of net device should be power of 2!");
+        for (name, burst) in [("tx burst", self.tx_burst), ("rx burst", self.rx_burst)] {
+            if burst > MAX_QUEUE_SIZE_NET {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    format!("{} of net device", name),
+                    0,
+                    true,
+                    MAX_QUEUE_SIZE_NET as u64,
+                    true
+                )));
+            }
         }
 
         Ok(())
@@ -265,7 +293,10 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
         .push("multifunction")
         .push("mac")
         .push("iothread")
-        .push("queue-size");
+        .push("queue-size")
+        .push("queue-override")
+        .push("tx-burst")
+        .push("rx-burst");
 
     cmd_parser.parse(net_config)?;
     pci_args_check(&cmd_parser)?;
@@ -284,6 +315,13 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
     if let Some(queue_size) = cmd_parser.get_value::<u16>("queue-size")? {
         netdevinterfacecfg.queue_size = queue_size;
     }
+    netdevinterfacecfg.queue_overrides = parse_virtio_queues(&cmd_parser)?;
+    if let Some(tx_burst) = cmd_parser.get_value::<u16>("tx-burst")? {
+        netdevinterfacecfg.tx_burst = tx_burst;
+    }
+    if let Some(rx_burst) = cmd_parser.get_value::<u16>("rx-burst")? {
+        netdevinterfacecfg.rx_burst = rx_burst;
+    }
 
     if let Some(netcfg) = &vm_config.netdevs.remove(&netdev) {
         netdevinterfacecfg.id = netid;
@@ -699,6 +737,28 @@ mod tests {
         assert!(net_cfg_res.is_err());
     }
 
+    #[test]
+    fn test_net_burst_config() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth1,ifname=tap1").is_ok());
+        let net_cfg = "virtio-net-pci,id=net1,netdev=eth1,bus=pcie.0,addr=0x1.0x0";
+        let network_configs = parse_net(&mut vm_config, net_cfg).unwrap();
+        assert_eq!(network_configs.tx_burst, 0);
+        assert_eq!(network_configs.rx_burst, 0);
+
+        assert!(vm_config.add_netdev("tap,id=eth1,ifname=tap1").is_ok());
+        let net_cfg =
+            "virtio-net-pci,id=net1,netdev=eth1,bus=pcie.0,addr=0x1.0x0,tx-burst=32,rx-burst=64";
+        let network_configs = parse_net(&mut vm_config, net_cfg).unwrap();
+        assert_eq!(network_configs.tx_burst, 32);
+        assert_eq!(network_configs.rx_burst, 64);
+
+        // The burst is at most the max queue size.
+        assert!(vm_config.add_netdev("tap,id=eth1,ifname=tap1").is_ok());
+        let net_cfg = "virtio-net-pci,id=net1,netdev=eth1,bus=pcie.0,addr=0x1.0x0,tx-burst=4097";
+        assert!(parse_net(&mut vm_config, net_cfg).is_err());
+    }
+
     #[test]
     fn test_netdev_config_check() {
         let mut netdev_conf = NetDevcfg::default();
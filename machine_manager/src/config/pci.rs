@@ -18,6 +18,11 @@ use super::{CmdParser, ConfigCheck, UnsignedInteger};
 use crate::config::{check_arg_too_long, ExBool};
 use util::num_ops::str_to_usize;
 
+/// Max slot number of pci device.
+pub const MAX_PCI_SLOT: u8 = 31;
+/// Max function number of pci device.
+pub const MAX_PCI_FUNC: u8 = 7;
+
 /// Basic information of pci devices such as bus number,
 /// slot number and function number.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -79,7 +84,7 @@ pub fn get_pci_df(addr: &str) -> Result<(u8, u8)> {
     let slot = addr_vec.first().unwrap();
     let slot =
         str_to_usize(slot.to_string()).with_context(|| format!("Invalid slot num: {}", slot))?;
-    if slot > 31 {
+    if slot > MAX_PCI_SLOT as usize {
         bail!("Invalid slot num: {}", slot);
     }
 
@@ -90,7 +95,7 @@ pub fn get_pci_df(addr: &str) -> Result<(u8, u8)> {
     } else {
         0
     };
-    if func > 7 {
+    if func > MAX_PCI_FUNC as usize {
         bail!("Invalid function num: {}", func);
     }
 
@@ -15,30 +15,139 @@ use crate::config::{
 };
 use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// Identity which authorizes all authenticated users.
+pub const SASL_IDENTITY_WILDCARD: &str = "*";
+/// Default max buffer size of the security layer in sasl.
+pub const DEFAULT_SASL_MAXBUFSIZE: u32 = 8192;
+/// Default minimum strength of the security layer in sasl.
+pub const DEFAULT_SASL_MIN_SSF: u32 = 56;
+/// Default maximum strength of the security layer in sasl.
+pub const DEFAULT_SASL_MAX_SSF: u32 = u32::MAX;
+/// Security flags of sasl, the same as `SASL_SEC_*` in sasl.h.
+const SASL_SEC_FLAGS: [(&str, u32); 7] = [
+    ("noplaintext", 0x0001),
+    ("noactive", 0x0002),
+    ("nodictionary", 0x0004),
+    ("forward-secrecy", 0x0008),
+    ("noanonymous", 0x0010),
+    ("pass-credentials", 0x0020),
+    ("mutual-auth", 0x0040),
+];
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SaslAuthObjConfig {
     /// Object Id.
     pub id: String,
-    /// Authentication User Name.
-    pub identity: String,
+    /// Authorized User Names.
+    pub identities: Vec<String>,
+    /// Authorize all authenticated users.
+    pub allow_all: bool,
+    /// Max buffer size of the security layer.
+    pub maxbufsize: u32,
+    /// Minimum strength of the security layer, 0 means no security layer is required.
+    pub min_ssf: u32,
+    /// Maximum strength of the security layer, 0 means the security layer is disabled.
+    pub max_ssf: u32,
+    /// Security flags which restrict the mechanisms, such as forbidding plaintext.
+    pub security_flags: u32,
+}
+
+impl SaslAuthObjConfig {
+    fn add_identity(&mut self, identity: &str) {
+        let identity = identity.trim();
+        if identity == SASL_IDENTITY_WILDCARD {
+            self.allow_all = true;
+        } else if !identity.is_empty() && !self.identities.iter().any(|id| id == identity) {
+            self.identities.push(identity.to_string());
+        }
+    }
+}
+
+/// Parse the security flags separated by ':', such as `noplaintext:noanonymous`.
+fn parse_security_flags(flags: &str) -> Result<u32> {
+    let mut security_flags = 0;
+    for flag in flags.split(':').map(|f| f.trim()).filter(|f| !f.is_empty()) {
+        let value = SASL_SEC_FLAGS
+            .iter()
+            .find(|(name, _)| *name == flag)
+            .map(|(_, value)| *value)
+            .with_context(|| {
+                ConfigError::InvalidParam(flag.to_string(), "sec-flags".to_string())
+            })?;
+        security_flags |= value;
+    }
+    Ok(security_flags)
+}
+
+/// Parse the acl file, each line contains one authorized identity.
+/// Empty lines and lines starting with '#' are ignored.
+fn parse_acl_file(saslauth: &mut SaslAuthObjConfig, acl_file: &str) -> Result<()> {
+    if !Path::new(acl_file).is_file() {
+        return Err(anyhow!(ConfigError::FileNotExist(acl_file.to_string())));
+    }
+    let content = fs::read_to_string(acl_file)
+        .with_context(|| format!("Failed to read sasl acl file {}", acl_file))?;
+    for line in content.lines() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        saslauth.add_identity(line);
+    }
+    Ok(())
 }
 
 impl VmConfig {
     pub fn add_saslauth(&mut self, saslauth_config: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("authz-simple");
-        cmd_parser.push("").push("id").push("identity");
+        cmd_parser
+            .push("")
+            .push("id")
+            .push("identity")
+            .push("acl-file")
+            .push("maxbufsize")
+            .push("min-ssf")
+            .push("max-ssf")
+            .push("sec-flags");
         cmd_parser.parse(saslauth_config)?;
 
         let mut saslauth = SaslAuthObjConfig {
             id: cmd_parser.get_value::<String>("id")?.with_context(|| {
                 ConfigError::FieldIsMissing("id".to_string(), "vnc sasl_auth".to_string())
             })?,
+            maxbufsize: cmd_parser
+                .get_value::<u32>("maxbufsize")?
+                .unwrap_or(DEFAULT_SASL_MAXBUFSIZE),
+            min_ssf: cmd_parser
+                .get_value::<u32>("min-ssf")?
+                .unwrap_or(DEFAULT_SASL_MIN_SSF),
+            max_ssf: cmd_parser
+                .get_value::<u32>("max-ssf")?
+                .unwrap_or(DEFAULT_SASL_MAX_SSF),
             ..Default::default()
         };
+        if saslauth.min_ssf > saslauth.max_ssf {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "min-ssf".to_string(),
+                0,
+                true,
+                saslauth.max_ssf as u64,
+                true,
+            )));
+        }
+        if let Some(flags) = cmd_parser.get_value::<String>("sec-flags")? {
+            saslauth.security_flags = parse_security_flags(&flags)?;
+        }
 
+        // Multiple identities are separated by ':'.
         if let Some(identity) = cmd_parser.get_value::<String>("identity")? {
-            saslauth.identity = identity;
+            for id in identity.split(':') {
+                saslauth.add_identity(id);
+            }
+        }
+        if let Some(acl_file) = cmd_parser.get_value::<String>("acl-file")? {
+            parse_acl_file(&mut saslauth, &acl_file)?;
         }
 
         let id = saslauth.id.clone();
@@ -55,6 +164,7 @@ impl VmConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::env;
 
     #[test]
     fn test_add_saslauth() {
@@ -65,14 +175,118 @@ mod tests {
             .is_ok());
         assert!(vm_config.object.sasl_object.get(&id).is_some());
         if let Some(obj_cfg) = vm_config.object.sasl_object.get(&id) {
-            assert_eq!(obj_cfg.identity, "test".to_string());
+            assert_eq!(obj_cfg.identities, vec!["test".to_string()]);
+            assert!(!obj_cfg.allow_all);
+            assert_eq!(obj_cfg.maxbufsize, DEFAULT_SASL_MAXBUFSIZE);
+        }
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("authz-simple,id=authz0,identity=test,maxbufsize=65536")
+            .is_ok());
+        if let Some(obj_cfg) = vm_config.object.sasl_object.get(&id) {
+            assert_eq!(obj_cfg.maxbufsize, 65536);
         }
 
         let mut vm_config = VmConfig::default();
         assert!(vm_config.add_object("authz-simple,id=authz0").is_ok());
         assert!(vm_config.object.sasl_object.get(&id).is_some());
         if let Some(obj_cfg) = vm_config.object.sasl_object.get(&id) {
-            assert!(obj_cfg.identity == "".to_string());
+            assert!(obj_cfg.identities.is_empty());
+            assert!(!obj_cfg.allow_all);
         }
     }
+
+    #[test]
+    fn test_add_saslauth_security_props() {
+        let id = String::from("authz0");
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_object("authz-simple,id=authz0").is_ok());
+        let obj_cfg = vm_config.object.sasl_object.get(&id).unwrap();
+        assert_eq!(obj_cfg.min_ssf, DEFAULT_SASL_MIN_SSF);
+        assert_eq!(obj_cfg.max_ssf, DEFAULT_SASL_MAX_SSF);
+        assert_eq!(obj_cfg.security_flags, 0);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object(
+                "authz-simple,id=authz0,min-ssf=112,max-ssf=256,sec-flags=noplaintext:noanonymous"
+            )
+            .is_ok());
+        let obj_cfg = vm_config.object.sasl_object.get(&id).unwrap();
+        assert_eq!(obj_cfg.min_ssf, 112);
+        assert_eq!(obj_cfg.max_ssf, 256);
+        assert_eq!(obj_cfg.security_flags, 0x0011);
+
+        // Security layer is disabled.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("authz-simple,id=authz0,min-ssf=0,max-ssf=0")
+            .is_ok());
+
+        // Min ssf is larger than max ssf.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("authz-simple,id=authz0,min-ssf=128,max-ssf=112")
+            .is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("authz-simple,id=authz0,max-ssf=0")
+            .is_err());
+
+        // Unknown security flag.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("authz-simple,id=authz0,sec-flags=noplaintext:unknown")
+            .is_err());
+    }
+
+    #[test]
+    fn test_add_saslauth_multiple_identities() {
+        let id = String::from("authz0");
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("authz-simple,id=authz0,identity=alice:bob:alice")
+            .is_ok());
+        let obj_cfg = vm_config.object.sasl_object.get(&id).unwrap();
+        assert_eq!(
+            obj_cfg.identities,
+            vec!["alice".to_string(), "bob".to_string()]
+        );
+        assert!(!obj_cfg.allow_all);
+
+        // Wildcard authorizes all users.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("authz-simple,id=authz0,identity=*")
+            .is_ok());
+        let obj_cfg = vm_config.object.sasl_object.get(&id).unwrap();
+        assert!(obj_cfg.identities.is_empty());
+        assert!(obj_cfg.allow_all);
+    }
+
+    #[test]
+    fn test_add_saslauth_acl_file() {
+        let mut acl_file = env::temp_dir();
+        acl_file.push("stratovirt_test_sasl_acl");
+        fs::write(&acl_file, "# operators\nalice\n\n  bob  \n").unwrap();
+
+        let id = String::from("authz0");
+        let mut vm_config = VmConfig::default();
+        let config = format!(
+            "authz-simple,id=authz0,identity=carol,acl-file={}",
+            acl_file.to_str().unwrap()
+        );
+        assert!(vm_config.add_object(&config).is_ok());
+        let obj_cfg = vm_config.object.sasl_object.get(&id).unwrap();
+        assert_eq!(
+            obj_cfg.identities,
+            vec!["carol".to_string(), "alice".to_string(), "bob".to_string()]
+        );
+        fs::remove_file(&acl_file).unwrap();
+
+        // Acl file does not exist.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_object(&config).is_err());
+    }
 }
@@ -46,6 +46,10 @@ pub struct ScsiCntlrConfig {
     pub boot_prefix: Option<String>,
     /// Virtqueue size for all queues.
     pub queue_size: u16,
+    /// Max target id of scsi devices attached to this controller.
+    pub max_target: u16,
+    /// Max lun id of scsi devices attached to this controller.
+    pub max_lun: u32,
 }
 
 impl Default for ScsiCntlrConfig {
@@ -57,6 +61,8 @@ impl Default for ScsiCntlrConfig {
             queues: 1,
             boot_prefix: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            max_target: VIRTIO_SCSI_MAX_TARGET,
+            max_lun: VIRTIO_SCSI_MAX_LUN as u32,
         }
     }
 }
@@ -93,6 +99,26 @@ impl ConfigCheck for ScsiCntlrConfig {
             bail!("Virtqueue size should be power of 2!");
         }
 
+        if self.max_target > VIRTIO_SCSI_MAX_TARGET {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "max-targets of scsi controller".to_string(),
+                0,
+                true,
+                VIRTIO_SCSI_MAX_TARGET as u64,
+                true,
+            )));
+        }
+
+        if self.max_lun > VIRTIO_SCSI_MAX_LUN as u32 {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "max-lun of scsi controller".to_string(),
+                0,
+                true,
+                VIRTIO_SCSI_MAX_LUN as u64,
+                true,
+            )));
+        }
+
         Ok(())
     }
 }
@@ -110,7 +136,9 @@ pub fn parse_scsi_controller(
         .push("multifunction")
         .push("iothread")
         .push("num-queues")
-        .push("queue-size");
+        .push("queue-size")
+        .push("max-targets")
+        .push("max-lun");
 
     cmd_parser.parse(drive_config)?;
 
@@ -136,6 +164,14 @@ pub fn parse_scsi_controller(
         cntlr_cfg.queue_size = size;
     }
 
+    if let Some(max_target) = cmd_parser.get_value::<u16>("max-targets")? {
+        cntlr_cfg.max_target = max_target;
+    }
+
+    if let Some(max_lun) = cmd_parser.get_value::<u32>("max-lun")? {
+        cntlr_cfg.max_lun = max_lun;
+    }
+
     cntlr_cfg.check()?;
     Ok(cntlr_cfg)
 }
@@ -271,3 +307,86 @@ pub fn parse_scsi_device(vm_config: &mut VmConfig, drive_config: &str) -> Result
 
     Ok(scsi_dev_cfg)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_pci_bdf;
+
+    #[test]
+    fn test_scsi_controller_config() {
+        let cntlr_cfg = parse_scsi_controller(
+            "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,num-queues=4,queue-size=512",
+            None,
+        )
+        .unwrap();
+        assert_eq!(cntlr_cfg.id, "scsi0");
+        assert_eq!(cntlr_cfg.queues, 4);
+        assert_eq!(cntlr_cfg.queue_size, 512);
+        assert_eq!(cntlr_cfg.max_target, VIRTIO_SCSI_MAX_TARGET);
+        assert_eq!(cntlr_cfg.max_lun, VIRTIO_SCSI_MAX_LUN as u32);
+        let pci_bdf = get_pci_bdf("virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3").unwrap();
+        assert_eq!(pci_bdf.addr, (3, 0));
+
+        let cntlr_cfg = parse_scsi_controller(
+            "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,max-targets=7,max-lun=16383",
+            Some(2),
+        )
+        .unwrap();
+        assert_eq!(cntlr_cfg.queues, 2);
+        assert_eq!(cntlr_cfg.max_target, 7);
+        assert_eq!(cntlr_cfg.max_lun, 16383);
+
+        for cfg in [
+            "virtio-scsi-pci,bus=pcie.0,addr=0x3",
+            "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,num-queues=0",
+            "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,num-queues=33",
+            "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,queue-size=2",
+            "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,queue-size=100",
+            "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,max-targets=256",
+            "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,max-lun=16384",
+        ] {
+            assert!(parse_scsi_controller(cfg, None).is_err());
+        }
+    }
+
+    #[test]
+    fn test_scsi_device_config() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_drive("id=drive-scsi0-0-1-2,file=/path/to/disk,readonly=on,direct=false,aio=off")
+            .unwrap();
+        let dev_cfg = parse_scsi_device(
+            &mut vm_config,
+            "scsi-hd,bus=scsi0.0,scsi-id=1,lun=2,drive=drive-scsi0-0-1-2,id=disk0,serial=123456",
+        )
+        .unwrap();
+        assert_eq!(dev_cfg.id, "disk0");
+        assert_eq!(dev_cfg.cntlr, "scsi0");
+        assert_eq!(dev_cfg.target, 1);
+        assert_eq!(dev_cfg.lun, 2);
+        assert_eq!(dev_cfg.serial, Some("123456".to_string()));
+        assert_eq!(dev_cfg.path_on_host, "/path/to/disk");
+        assert!(dev_cfg.read_only);
+        assert!(!dev_cfg.direct);
+        // The drive is consumed by the scsi device.
+        assert!(parse_scsi_device(
+            &mut vm_config,
+            "scsi-hd,bus=scsi0.0,scsi-id=1,lun=2,drive=drive-scsi0-0-1-2,id=disk1",
+        )
+        .is_err());
+
+        vm_config
+            .add_drive("id=drive-scsi0-0-0-0,file=/path/to/disk")
+            .unwrap();
+        for cfg in [
+            "scsi-hd,bus=scsi0.0,drive=drive-scsi0-0-0-0",
+            "scsi-hd,bus=scsi0,drive=drive-scsi0-0-0-0,id=disk0",
+            "scsi-hd,drive=drive-scsi0-0-0-0,id=disk0",
+            "scsi-hd,bus=scsi0.0,lun=256,drive=drive-scsi0-0-0-0,id=disk0",
+            "scsi-hd,bus=scsi0.0,drive=drive-none,id=disk0",
+        ] {
+            assert!(parse_scsi_device(&mut vm_config, cfg).is_err());
+        }
+    }
+}
@@ -11,7 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use crate::config::{
-    ConfigError, {CmdParser, VmConfig},
+    ConfigError, ExBool, {CmdParser, VmConfig},
 };
 use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
@@ -54,12 +54,8 @@ impl VmConfig {
         if let Some(endpoint) = cmd_parser.get_value::<String>("endpoint")? {
             tlscred.endpoint = Some(endpoint);
         }
-        if let Some(verifypeer) = cmd_parser.get_value::<String>("verify-peer")? {
-            if verifypeer == *"true" {
-                tlscred.verifypeer = true;
-            } else {
-                tlscred.verifypeer = false;
-            }
+        if let Some(verifypeer) = cmd_parser.get_value::<ExBool>("verify-peer")? {
+            tlscred.verifypeer = verifypeer.into();
         }
         tlscred.cred_type = "x509".to_string();
 
@@ -104,6 +100,25 @@ mod tests {
             assert_eq!(tls_cred_cfg.verifypeer, false);
         }
 
+        // Verify peer can be switched by on/off.
+        for (verifypeer, expect) in [("on", true), ("off", false), ("true", true)] {
+            let tls_config = format!(
+                "tls-creds-x509,id=vnc-tls-creds0,dir={},verify-peer={}",
+                dir.to_str().unwrap(),
+                verifypeer
+            );
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config.add_object(tls_config.as_str()).is_ok());
+            let tls_cred_cfg = vm_config.object.tls_object.get(&id).unwrap();
+            assert_eq!(tls_cred_cfg.verifypeer, expect);
+        }
+        let tls_config = format!(
+            "tls-creds-x509,id=vnc-tls-creds0,dir={},verify-peer=maybe",
+            dir.to_str().unwrap()
+        );
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_object(tls_config.as_str()).is_err());
+
         // Delete file.
         fs::remove_dir(dir.clone()).unwrap();
         assert_eq!(dir.is_dir(), false);
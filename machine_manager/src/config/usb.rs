@@ -12,7 +12,7 @@
 
 use anyhow::{anyhow, bail, Context, Result};
 
-use super::{error::ConfigError, get_cameradev_by_id, UnsignedInteger};
+use super::{error::ConfigError, get_cameradev_by_id, get_pci_df, UnsignedInteger};
 use crate::config::{
     check_arg_nonexist, check_arg_too_long, CamBackendType, CameraDevConfig, CmdParser,
     ConfigCheck, ScsiDevConfig, VmConfig,
@@ -29,6 +29,8 @@ pub struct XhciConfig {
     pub p2: Option<u8>,
     // number of usb3.0 ports
     pub p3: Option<u8>,
+    // pci slot and function number
+    pub addr: Option<(u8, u8)>,
 }
 
 impl XhciConfig {
@@ -37,6 +39,7 @@ impl XhciConfig {
             id: None,
             p2: None,
             p3: None,
+            addr: None,
         }
     }
 
@@ -91,6 +94,59 @@ pub fn parse_xhci(conf: &str) -> Result<XhciConfig> {
         dev.p3 = Some(p3);
     }
 
+    if let Some(addr) = cmd_parser.get_value::<String>("addr")? {
+        dev.addr = Some(get_pci_df(&addr).with_context(|| "Failed to get addr")?);
+    }
+
+    dev.check()?;
+    Ok(dev)
+}
+
+/// USB hub configuration.
+#[derive(Debug)]
+pub struct UsbHubConfig {
+    pub id: Option<String>,
+    // id of the usb bus which the hub is attached to
+    pub bus: Option<String>,
+    // index of the port on the usb bus, starts from 1
+    pub port: Option<u8>,
+}
+
+impl UsbHubConfig {
+    fn new() -> Self {
+        UsbHubConfig {
+            id: None,
+            bus: None,
+            port: None,
+        }
+    }
+}
+
+impl ConfigCheck for UsbHubConfig {
+    fn check(&self) -> Result<()> {
+        check_id(self.id.clone(), "usb-hub")?;
+        if self.port == Some(0) {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "usb hub port".to_string(),
+                1,
+                true,
+                u8::MAX as u64,
+                true,
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_usb_hub(conf: &str) -> Result<UsbHubConfig> {
+    let mut cmd_parser = CmdParser::new("usb-hub");
+    cmd_parser.push("").push("id").push("bus").push("port");
+    cmd_parser.parse(conf)?;
+    let mut dev = UsbHubConfig::new();
+    dev.id = cmd_parser.get_value::<String>("id")?;
+    dev.bus = cmd_parser.get_value::<String>("bus")?;
+    dev.port = cmd_parser.get_value::<u8>("port")?;
+
     dev.check()?;
     Ok(dev)
 }
@@ -151,6 +207,34 @@ pub fn parse_usb_tablet(conf: &str) -> Result<UsbTabletConfig> {
     Ok(dev)
 }
 
+#[derive(Debug)]
+pub struct UsbMouseConfig {
+    pub id: Option<String>,
+}
+
+impl UsbMouseConfig {
+    fn new() -> Self {
+        UsbMouseConfig { id: None }
+    }
+}
+
+impl ConfigCheck for UsbMouseConfig {
+    fn check(&self) -> Result<()> {
+        check_id(self.id.clone(), "usb-mouse")
+    }
+}
+
+pub fn parse_usb_mouse(conf: &str) -> Result<UsbMouseConfig> {
+    let mut cmd_parser = CmdParser::new("usb-mouse");
+    cmd_parser.push("").push("id").push("bus").push("port");
+    cmd_parser.parse(conf)?;
+    let mut dev = UsbMouseConfig::new();
+    dev.id = cmd_parser.get_value::<String>("id")?;
+
+    dev.check()?;
+    Ok(dev)
+}
+
 pub fn parse_usb_camera(vm_config: &mut VmConfig, conf: &str) -> Result<UsbCameraConfig> {
     let mut cmd_parser = CmdParser::new("usb-camera");
     cmd_parser
@@ -358,3 +442,136 @@ pub fn parse_usb_host(cfg_args: &str) -> Result<UsbHostConfig> {
     dev.check()?;
     Ok(dev)
 }
+
+/// Usb device configuration parsed from `-device` string.
+#[derive(Debug)]
+pub enum UsbDevice {
+    Xhci(XhciConfig),
+    Hub(UsbHubConfig),
+    Keyboard(UsbKeyboardConfig),
+    Tablet(UsbTabletConfig),
+    Mouse(UsbMouseConfig),
+    Storage(UsbStorageConfig),
+}
+
+/// Parse usb device string, the parser is chosen by the device type which is
+/// the leading token of the string.
+pub fn parse_usb_device(vm_config: &mut VmConfig, conf: &str) -> Result<UsbDevice> {
+    let mut cmd_parser = CmdParser::new("device");
+    cmd_parser.push("");
+    cmd_parser.get_parameters(conf)?;
+    let dev_type = cmd_parser.get_value::<String>("")?.unwrap_or_default();
+
+    let dev = match dev_type.as_str() {
+        "nec-usb-xhci" => UsbDevice::Xhci(parse_xhci(conf)?),
+        "usb-hub" => UsbDevice::Hub(parse_usb_hub(conf)?),
+        "usb-kbd" => UsbDevice::Keyboard(parse_usb_keyboard(conf)?),
+        "usb-tablet" => UsbDevice::Tablet(parse_usb_tablet(conf)?),
+        "usb-mouse" => UsbDevice::Mouse(parse_usb_mouse(conf)?),
+        "usb-storage" => UsbDevice::Storage(parse_usb_storage(vm_config, conf)?),
+        _ => return Err(anyhow!(ConfigError::UnknownDeviceType(dev_type))),
+    };
+    Ok(dev)
+}
+
+/// Parse a list of usb device strings, stop at the first invalid one.
+pub fn parse_usb_devices(vm_config: &mut VmConfig, confs: &[String]) -> Result<Vec<UsbDevice>> {
+    let mut devs = Vec::new();
+    for conf in confs {
+        let dev = parse_usb_device(vm_config, conf)
+            .with_context(|| format!("Failed to parse usb device {}", conf))?;
+        devs.push(dev);
+    }
+    Ok(devs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MAX_STRING_LENGTH;
+
+    #[test]
+    fn test_parse_xhci_addr() {
+        let xhci = parse_xhci("nec-usb-xhci,id=xhci,bus=pcie.0,addr=0x1f.0x2").unwrap();
+        assert_eq!(xhci.addr, Some((0x1f, 0x2)));
+
+        // Function number is 0 by default.
+        let xhci = parse_xhci("nec-usb-xhci,id=xhci,bus=pcie.0,addr=0x5").unwrap();
+        assert_eq!(xhci.addr, Some((0x5, 0)));
+
+        let xhci = parse_xhci("nec-usb-xhci,id=xhci,bus=pcie.0").unwrap();
+        assert_eq!(xhci.addr, None);
+
+        // Slot or function number is out of range.
+        assert!(parse_xhci("nec-usb-xhci,id=xhci,bus=pcie.0,addr=0x20").is_err());
+        assert!(parse_xhci("nec-usb-xhci,id=xhci,bus=pcie.0,addr=0x1f.0x8").is_err());
+        assert!(parse_xhci("nec-usb-xhci,id=xhci,bus=pcie.0,addr=0x100").is_err());
+        assert!(parse_xhci("nec-usb-xhci,id=xhci,bus=pcie.0,addr=0x1.0x2.0x3").is_err());
+    }
+
+    #[test]
+    fn test_parse_usb_hub() {
+        let hub = parse_usb_hub("usb-hub,id=hub0,bus=usb.0,port=2").unwrap();
+        assert_eq!(hub.id, Some("hub0".to_string()));
+        assert_eq!(hub.bus, Some("usb.0".to_string()));
+        assert_eq!(hub.port, Some(2));
+
+        let hub = parse_usb_hub("usb-hub,id=hub0").unwrap();
+        assert_eq!(hub.bus, None);
+        assert_eq!(hub.port, None);
+
+        // Id is missing or too long.
+        assert!(parse_usb_hub("usb-hub,bus=usb.0,port=1").is_err());
+        let hub_cfg = format!("usb-hub,id={},port=1", "h".repeat(MAX_STRING_LENGTH + 1));
+        assert!(parse_usb_hub(&hub_cfg).is_err());
+
+        // Port is invalid.
+        assert!(parse_usb_hub("usb-hub,id=hub0,bus=usb.0,port=0").is_err());
+        assert!(parse_usb_hub("usb-hub,id=hub0,bus=usb.0,port=256").is_err());
+        assert!(parse_usb_hub("usb-hub,id=hub0,bus=usb.0,port=a").is_err());
+    }
+
+    #[test]
+    fn test_parse_usb_device() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_drive("id=drive0,file=/path/to/disk,aio=off,direct=false")
+            .unwrap();
+
+        let confs = vec![
+            "nec-usb-xhci,id=xhci,bus=pcie.0,addr=0xa.0x0".to_string(),
+            "usb-hub,id=hub0,bus=usb.0,port=1".to_string(),
+            "usb-kbd,id=kbd".to_string(),
+            "usb-tablet,id=tablet".to_string(),
+            "usb-storage,id=storage,drive=drive0".to_string(),
+            "usb-mouse,id=mouse".to_string(),
+        ];
+        let devs = parse_usb_devices(&mut vm_config, &confs).unwrap();
+        assert_eq!(devs.len(), 6);
+        assert!(matches!(&devs[0], UsbDevice::Xhci(xhci) if xhci.addr == Some((0xa, 0))));
+        assert!(matches!(&devs[1], UsbDevice::Hub(hub) if hub.port == Some(1)));
+        assert!(matches!(&devs[2], UsbDevice::Keyboard(kbd) if kbd.id == Some("kbd".to_string())));
+        assert!(matches!(&devs[3], UsbDevice::Tablet(_)));
+        match &devs[4] {
+            UsbDevice::Storage(storage) => {
+                assert_eq!(storage.scsi_cfg.path_on_host, "/path/to/disk")
+            }
+            _ => panic!("usb-storage is expected"),
+        }
+        assert!(matches!(&devs[5], UsbDevice::Mouse(_)));
+
+        // Unknown device type.
+        let err = parse_usb_device(&mut vm_config, "usb-joystick,id=joystick").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ConfigError>(),
+            Some(ConfigError::UnknownDeviceType(dev_type)) if dev_type == "usb-joystick"
+        ));
+        // The parser of the known device type fails.
+        assert!(parse_usb_device(&mut vm_config, "usb-kbd").is_err());
+        let confs = vec![
+            "usb-kbd,id=kbd".to_string(),
+            "usb-joystick,id=joystick".to_string(),
+        ];
+        assert!(parse_usb_devices(&mut vm_config, &confs).is_err());
+    }
+}
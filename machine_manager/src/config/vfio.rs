@@ -11,8 +11,54 @@
 // See the Mulan PSL v2 for more details.
 
 use super::error::ConfigError;
-use crate::config::{check_arg_too_long, CmdParser, ConfigCheck};
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck, MAX_PCI_FUNC, MAX_PCI_SLOT};
 use anyhow::{anyhow, Result};
+
+/// Address of pci device on host, the format is `DDDD:BB:DD.F`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostPciBdf {
+    pub domain: u16,
+    pub bus: u8,
+    pub slot: u8,
+    pub func: u8,
+}
+
+/// Parse the address of pci device on host, such as `0000:1a:00.3`.
+///
+/// # Arguments
+///
+/// * `bdf` - The pci address string in format `DDDD:BB:DD.F`.
+pub fn parse_host_bdf(bdf: &str) -> Result<HostPciBdf> {
+    let invalid_bdf = || {
+        anyhow!(ConfigError::InvalidParam(
+            bdf.to_string(),
+            "host".to_string()
+        ))
+    };
+    let parse_hex = |field: &str, width: usize| -> Result<u16> {
+        if field.len() != width || !field.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid_bdf());
+        }
+        u16::from_str_radix(field, 16).map_err(|_| invalid_bdf())
+    };
+
+    let fields: Vec<&str> = bdf.split(':').collect();
+    if fields.len() != 3 {
+        return Err(invalid_bdf());
+    }
+    let (slot, func) = fields[2].split_once('.').ok_or_else(invalid_bdf)?;
+    let host_bdf = HostPciBdf {
+        domain: parse_hex(fields[0], 4)?,
+        bus: parse_hex(fields[1], 2)? as u8,
+        slot: parse_hex(slot, 2)? as u8,
+        func: parse_hex(func, 1)? as u8,
+    };
+    if host_bdf.slot > MAX_PCI_SLOT || host_bdf.func > MAX_PCI_FUNC {
+        return Err(invalid_bdf());
+    }
+    Ok(host_bdf)
+}
+
 #[derive(Default, Debug)]
 pub struct VfioConfig {
     pub sysfsdev: String,
@@ -24,6 +70,9 @@ impl ConfigCheck for VfioConfig {
     fn check(&self) -> Result<()> {
         check_arg_too_long(&self.host, "host")?;
         check_arg_too_long(&self.id, "id")?;
+        if !self.host.is_empty() {
+            parse_host_bdf(&self.host)?;
+        }
 
         Ok(())
     }
@@ -98,6 +147,46 @@ mod tests {
         assert!(vfio_config.check().is_err());
     }
 
+    #[test]
+    fn test_parse_host_bdf() {
+        assert_eq!(
+            parse_host_bdf("0000:1a:00.3").unwrap(),
+            HostPciBdf {
+                domain: 0,
+                bus: 0x1a,
+                slot: 0,
+                func: 3
+            }
+        );
+        assert_eq!(
+            parse_host_bdf("FFFF:ff:1f.7").unwrap(),
+            HostPciBdf {
+                domain: 0xffff,
+                bus: 0xff,
+                slot: 0x1f,
+                func: 7
+            }
+        );
+
+        // Malformed bdf strings.
+        assert!(parse_host_bdf("").is_err());
+        assert!(parse_host_bdf("1a:00.3").is_err());
+        assert!(parse_host_bdf("0000:1a:00").is_err());
+        assert!(parse_host_bdf("0000:1a:00.3.1").is_err());
+        assert!(parse_host_bdf("0000:1a:00:3").is_err());
+        assert!(parse_host_bdf("000:1a:00.3").is_err());
+        assert!(parse_host_bdf("0000:1a:0.3").is_err());
+        assert!(parse_host_bdf("0000:1g:00.3").is_err());
+        assert!(parse_host_bdf("0000:+a:00.3").is_err());
+        // Slot and function out of range.
+        assert!(parse_host_bdf("0000:1a:20.0").is_err());
+        assert!(parse_host_bdf("0000:1a:00.8").is_err());
+
+        // Host is checked when parsing vfio device.
+        assert!(parse_vfio("vfio-pci,host=1a:00.3,id=net").is_err());
+        assert!(parse_vfio("vfio-pci,sysfsdev=/sys/bus/pci/devices/0000:1a:00.3,id=net").is_ok());
+    }
+
     #[test]
     fn test_vfio_config_cmdline_parser() {
         let vfio_cfg = parse_vfio("vfio-pci,host=0000:1a:00.3,id=net");
@@ -0,0 +1,203 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::error::ConfigError;
+use crate::config::CmdParser;
+
+/// Maximum queue size of virtqueue, refer to Virtio Spec.
+pub const MAX_VIRTQUEUE_SIZE: u16 = 32768;
+
+/// Config of a single virtqueue which overrides the default one of device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VirtioQueueConfig {
+    /// Index of the virtqueue.
+    pub index: u16,
+    /// Queue size of the virtqueue.
+    pub size: u16,
+}
+
+impl VirtioQueueConfig {
+    /// Check the virtqueue config against the device.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_queues` - The maximum number of queues of the device.
+    pub fn check(&self, max_queues: u16) -> Result<()> {
+        if self.index >= max_queues {
+            return Err(anyhow!(ConfigError::IllegalValueUnilateral(
+                "index of virtqueue".to_string(),
+                false,
+                false,
+                max_queues as u64
+            )));
+        }
+        check_virtqueue_size(self.size)
+    }
+}
+
+fn check_virtqueue_size(size: u16) -> Result<()> {
+    if size == 0 || size > MAX_VIRTQUEUE_SIZE {
+        return Err(anyhow!(ConfigError::IllegalValue(
+            "size of virtqueue".to_string(),
+            1,
+            true,
+            MAX_VIRTQUEUE_SIZE as u64,
+            true
+        )));
+    }
+    if !size.is_power_of_two() {
+        bail!("size of virtqueue should be power of 2!");
+    }
+    Ok(())
+}
+
+/// Parse the config of a virtqueue, the format is `<index>@<size>`.
+///
+/// # Arguments
+///
+/// * `conf` - The virtqueue config string.
+pub fn parse_virtio_queue(conf: &str) -> Result<VirtioQueueConfig> {
+    let (index, size) = conf.split_once('@').ok_or_else(|| {
+        anyhow!(ConfigError::InvalidParam(
+            conf.to_string(),
+            "queue-override".to_string()
+        ))
+    })?;
+    let index = index.parse::<u16>().map_err(|_| {
+        anyhow!(ConfigError::ConvertValueFailed(
+            "index of virtqueue".to_string(),
+            index.to_string()
+        ))
+    })?;
+    let size = size.parse::<u16>().map_err(|_| {
+        anyhow!(ConfigError::ConvertValueFailed(
+            "size of virtqueue".to_string(),
+            size.to_string()
+        ))
+    })?;
+    check_virtqueue_size(size)?;
+
+    Ok(VirtioQueueConfig { index, size })
+}
+
+/// Parse the virtqueue overrides of a virtio device from the `queue-override`
+/// field, multiple virtqueues are separated by colon, such as
+/// `queue-override=0@256:1@512`.
+pub fn parse_virtio_queues(cmd_parser: &CmdParser) -> Result<Vec<VirtioQueueConfig>> {
+    let mut queues: Vec<VirtioQueueConfig> = Vec::new();
+    if let Some(conf) = cmd_parser.get_value::<String>("queue-override")? {
+        for queue_conf in conf.split(':') {
+            let queue = parse_virtio_queue(queue_conf)?;
+            if queues.iter().any(|q| q.index == queue.index) {
+                return Err(anyhow!(ConfigError::FieldRepeat(
+                    "queue-override".to_string(),
+                    queue.index.to_string()
+                )));
+            }
+            queues.push(queue);
+        }
+    }
+    Ok(queues)
+}
+
+/// Get the size of the virtqueue with index `queue_index`, which is
+/// `default_size` if the virtqueue is not overridden.
+///
+/// # Arguments
+///
+/// * `queues` - The virtqueue overrides.
+/// * `queue_index` - The index of virtqueue.
+/// * `default_size` - The default queue size of the device.
+pub fn get_virtio_queue_size(
+    queues: &[VirtioQueueConfig],
+    queue_index: usize,
+    default_size: u16,
+) -> u16 {
+    queues
+        .iter()
+        .find(|queue| queue.index as usize == queue_index)
+        .map_or(default_size, |queue| queue.size)
+}
+
+/// Check the virtqueue overrides of a virtio device.
+///
+/// # Arguments
+///
+/// * `queues` - The virtqueue overrides.
+/// * `max_queues` - The maximum number of queues of the device.
+pub fn check_virtio_queues(queues: &[VirtioQueueConfig], max_queues: u16) -> Result<()> {
+    for queue in queues {
+        queue.check(max_queues)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_virtio_queue() {
+        let queue = parse_virtio_queue("1@256").unwrap();
+        assert_eq!(
+            queue,
+            VirtioQueueConfig {
+                index: 1,
+                size: 256
+            }
+        );
+        assert!(queue.check(2).is_ok());
+
+        // Size must be power of 2 in [1, 32768].
+        assert!(parse_virtio_queue("0@1").is_ok());
+        assert!(parse_virtio_queue("0@32768").is_ok());
+        assert!(parse_virtio_queue("0@0").is_err());
+        assert!(parse_virtio_queue("0@100").is_err());
+        assert!(parse_virtio_queue("0@65535").is_err());
+        assert!(parse_virtio_queue("0@65536").is_err());
+
+        // Invalid format.
+        assert!(parse_virtio_queue("0").is_err());
+        assert!(parse_virtio_queue("@256").is_err());
+        assert!(parse_virtio_queue("a@256").is_err());
+        assert!(parse_virtio_queue("0@256@1").is_err());
+    }
+
+    #[test]
+    fn test_virtio_queue_index() {
+        let queue = parse_virtio_queue("2@128").unwrap();
+        assert!(queue.check(3).is_ok());
+        let err = queue.check(2).unwrap_err();
+        assert_eq!(err.to_string(), "index of virtqueue must < 2.");
+
+        let mut cmd_parser = CmdParser::new("virtio-blk");
+        cmd_parser.push("").push("queue-override");
+        cmd_parser
+            .parse("virtio-blk,queue-override=0@256:3@512")
+            .unwrap();
+        let queues = parse_virtio_queues(&cmd_parser).unwrap();
+        assert_eq!(queues.len(), 2);
+        assert!(check_virtio_queues(&queues, 4).is_ok());
+        assert!(check_virtio_queues(&queues, 3).is_err());
+
+        // Repeated index.
+        let mut cmd_parser = CmdParser::new("virtio-blk");
+        cmd_parser.push("").push("queue-override");
+        cmd_parser
+            .parse("virtio-blk,queue-override=0@256:0@512")
+            .unwrap();
+        assert!(parse_virtio_queues(&cmd_parser).is_err());
+    }
+}
@@ -14,7 +14,7 @@ use crate::config::{CmdParser, ConfigError, VmConfig};
 
 use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddrV4};
 
 /// Configuration of vnc.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -29,10 +29,50 @@ pub struct VncConfig {
     pub sasl: bool,
     /// Configuration of authentication.
     pub sasl_authz: String,
+    /// Application name of sasl, which names the sasl config file.
+    pub sasl_appname: String,
+    /// Directory to search the sasl config file.
+    pub sasl_config_dir: String,
+    /// Realm of sasl, such as the kerberos realm of GSSAPI.
+    pub sasl_realm: String,
+    /// Authorization of the client certificate.
+    pub tls_authz: String,
+    /// Password authentication switch.
+    pub password: bool,
+    /// Deadline in seconds for client to finish the handshake.
+    pub handshake_timeout: u64,
+    /// Deadline in seconds for client to finish each handshake step.
+    pub auth_timeout: u64,
+    /// Max number of framebuffer updates sent to each client per second.
+    pub max_fps: u64,
+    /// Max bytes of output queued for each client, 0 means it depends on
+    /// the size of framebuffer.
+    pub output_limit: u64,
+    /// Max bytes of the guest clipboard text sent to each client, 0 means
+    /// the guest clipboard is not shared.
+    pub clipboard_limit: u64,
+    /// Keyboard layout of guest which the keysyms of client are translated
+    /// with, empty means the US layout.
+    pub keymap: String,
+    /// Address listened for websocket client, empty means websocket is disabled.
+    pub websocket: String,
 }
 
 const VNC_MAX_PORT_NUM: i32 = 65535;
 const VNC_PORT_OFFSET: i32 = 5900;
+/// Default deadline in seconds for client to finish the handshake.
+pub const DEFAULT_VNC_HANDSHAKE_TIMEOUT: u64 = 60;
+const VNC_HANDSHAKE_TIMEOUT_MAX: u64 = 3600;
+/// Default deadline in seconds for client to finish each handshake step.
+pub const DEFAULT_VNC_AUTH_TIMEOUT: u64 = 30;
+/// Default max number of framebuffer updates sent to each client per second.
+pub const DEFAULT_VNC_MAX_FPS: u64 = 30;
+const VNC_MAX_FPS_MAX: u64 = 60;
+const VNC_OUTPUT_LIMIT_MIN: u64 = 1024 * 1024;
+const VNC_OUTPUT_LIMIT_MAX: u64 = 1024 * 1024 * 1024;
+/// Default max bytes of the guest clipboard text sent to each client.
+pub const DEFAULT_VNC_CLIPBOARD_LIMIT: u64 = 1024 * 1024;
+const VNC_CLIPBOARD_LIMIT_MAX: u64 = 16 * 1024 * 1024;
 
 impl VmConfig {
     /// Make configuration for vnc: "chardev" -> "vnc".
@@ -42,7 +82,19 @@ impl VmConfig {
             .push("")
             .push("tls-creds")
             .push("sasl")
-            .push("sasl-authz");
+            .push("sasl-authz")
+            .push("sasl-appname")
+            .push("sasl-config-dir")
+            .push("sasl-realm")
+            .push("tls-authz")
+            .push("password")
+            .push("handshake-timeout")
+            .push("auth-timeout")
+            .push("max-fps")
+            .push("output-limit")
+            .push("clipboard-limit")
+            .push("keymap")
+            .push("websocket");
         cmd_parser.parse(vnc_config)?;
 
         let mut vnc_config = VncConfig::default();
@@ -68,6 +120,88 @@ impl VmConfig {
         if let Some(sasl_authz) = cmd_parser.get_value::<String>("sasl-authz")? {
             vnc_config.sasl_authz = sasl_authz;
         }
+        if let Some(sasl_appname) = cmd_parser.get_value::<String>("sasl-appname")? {
+            vnc_config.sasl_appname = sasl_appname;
+        }
+        if let Some(sasl_config_dir) = cmd_parser.get_value::<String>("sasl-config-dir")? {
+            vnc_config.sasl_config_dir = sasl_config_dir;
+        }
+        if let Some(sasl_realm) = cmd_parser.get_value::<String>("sasl-realm")? {
+            vnc_config.sasl_realm = sasl_realm;
+        }
+        if let Some(tls_authz) = cmd_parser.get_value::<String>("tls-authz")? {
+            vnc_config.tls_authz = tls_authz;
+        }
+        vnc_config.password = cmd_parser.get_value::<String>("password")?.is_some();
+        vnc_config.handshake_timeout = cmd_parser
+            .get_value::<u64>("handshake-timeout")?
+            .unwrap_or(DEFAULT_VNC_HANDSHAKE_TIMEOUT);
+        if !(1..=VNC_HANDSHAKE_TIMEOUT_MAX).contains(&vnc_config.handshake_timeout) {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "vnc handshake-timeout".to_string(),
+                1,
+                true,
+                VNC_HANDSHAKE_TIMEOUT_MAX,
+                true,
+            )));
+        }
+        vnc_config.auth_timeout = cmd_parser
+            .get_value::<u64>("auth-timeout")?
+            .unwrap_or(DEFAULT_VNC_AUTH_TIMEOUT);
+        if !(1..=VNC_HANDSHAKE_TIMEOUT_MAX).contains(&vnc_config.auth_timeout) {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "vnc auth-timeout".to_string(),
+                1,
+                true,
+                VNC_HANDSHAKE_TIMEOUT_MAX,
+                true,
+            )));
+        }
+        vnc_config.max_fps = cmd_parser
+            .get_value::<u64>("max-fps")?
+            .unwrap_or(DEFAULT_VNC_MAX_FPS);
+        if !(1..=VNC_MAX_FPS_MAX).contains(&vnc_config.max_fps) {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "vnc max-fps".to_string(),
+                1,
+                true,
+                VNC_MAX_FPS_MAX,
+                true,
+            )));
+        }
+        if let Some(output_limit) = cmd_parser.get_value::<u64>("output-limit")? {
+            if !(VNC_OUTPUT_LIMIT_MIN..=VNC_OUTPUT_LIMIT_MAX).contains(&output_limit) {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "vnc output-limit".to_string(),
+                    VNC_OUTPUT_LIMIT_MIN,
+                    true,
+                    VNC_OUTPUT_LIMIT_MAX,
+                    true,
+                )));
+            }
+            vnc_config.output_limit = output_limit;
+        }
+        vnc_config.clipboard_limit = cmd_parser
+            .get_value::<u64>("clipboard-limit")?
+            .unwrap_or(DEFAULT_VNC_CLIPBOARD_LIMIT);
+        if vnc_config.clipboard_limit > VNC_CLIPBOARD_LIMIT_MAX {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "vnc clipboard-limit".to_string(),
+                0,
+                true,
+                VNC_CLIPBOARD_LIMIT_MAX,
+                true,
+            )));
+        }
+        if let Some(keymap) = cmd_parser.get_value::<String>("keymap")? {
+            vnc_config.keymap = keymap;
+        }
+        if let Some(websocket) = cmd_parser.get_value::<String>("websocket")? {
+            websocket
+                .parse::<SocketAddrV4>()
+                .with_context(|| format!("Invalid websocket address {} for vnc!", websocket))?;
+            vnc_config.websocket = websocket;
+        }
 
         self.vnc = Some(vnc_config);
         Ok(())
@@ -117,6 +251,29 @@ mod tests {
         assert_eq!(vnc_config.tls_creds, String::from("vnc-tls-creds0"));
         assert_eq!(vnc_config.sasl, true);
         assert_eq!(vnc_config.sasl_authz, String::from("authz0"));
+        assert_eq!(vnc_config.handshake_timeout, DEFAULT_VNC_HANDSHAKE_TIMEOUT);
+        assert_eq!(vnc_config.auth_timeout, DEFAULT_VNC_AUTH_TIMEOUT);
+        assert_eq!(vnc_config.max_fps, DEFAULT_VNC_MAX_FPS);
+        assert_eq!(vnc_config.output_limit, 0);
+        assert_eq!(vnc_config.clipboard_limit, DEFAULT_VNC_CLIPBOARD_LIMIT);
+        assert!(vnc_config.sasl_appname.is_empty());
+        assert!(vnc_config.sasl_config_dir.is_empty());
+        assert!(vnc_config.sasl_realm.is_empty());
+        assert!(vnc_config.websocket.is_empty());
+
+        let mut vm_config = VmConfig::default();
+        let config_line =
+            "0.0.0.0:1,sasl,sasl-authz=authz0,sasl-appname=vm1,sasl-config-dir=/etc/vm1/sasl2";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.sasl_appname, String::from("vm1"));
+        assert_eq!(vnc_config.sasl_config_dir, String::from("/etc/vm1/sasl2"));
+
+        let mut vm_config = VmConfig::default();
+        let config_line = "0.0.0.0:1,sasl,sasl-authz=authz0,sasl-realm=EXAMPLE.COM";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.sasl_realm, String::from("EXAMPLE.COM"));
 
         let mut vm_config = VmConfig::default();
         let config_line = "0.0.0.0:5900,tls-creds=vnc-tls-creds0";
@@ -131,6 +288,99 @@ mod tests {
         let vnc_config = vm_config.vnc.unwrap();
         assert_eq!(vnc_config.tls_creds, "".to_string());
 
+        let mut vm_config = VmConfig::default();
+        let config_line = "0.0.0.0:1,tls-creds=vnc-tls-creds0,tls-authz=authz1";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.tls_authz, String::from("authz1"));
+        assert_eq!(vnc_config.sasl_authz, "".to_string());
+        assert_eq!(vnc_config.password, false);
+
+        let mut vm_config = VmConfig::default();
+        let config_line = "0.0.0.0:1,tls-creds=vnc-tls-creds0,password";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.password, true);
+
+        let mut vm_config = VmConfig::default();
+        let config_line = "0.0.0.0:1,handshake-timeout=10";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.handshake_timeout, 10);
+
+        let mut vm_config = VmConfig::default();
+        let config_line = "0.0.0.0:1,auth-timeout=10";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.auth_timeout, 10);
+
+        let mut vm_config = VmConfig::default();
+        let config_line = "0.0.0.0:1,max-fps=10";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.max_fps, 10);
+
+        let mut vm_config = VmConfig::default();
+        let config_line = "0.0.0.0:1,output-limit=16777216";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.output_limit, 16 * 1024 * 1024);
+
+        let mut vm_config = VmConfig::default();
+        let config_line = "0.0.0.0:1,clipboard-limit=0";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.clipboard_limit, 0);
+
+        let mut vm_config = VmConfig::default();
+        let config_line = "0.0.0.0:1,keymap=de";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.keymap, String::from("de"));
+
+        let mut vm_config = VmConfig::default();
+        let config_line = "0.0.0.0:1,websocket=0.0.0.0:5700";
+        assert!(vm_config.add_vnc(config_line).is_ok());
+        let vnc_config = vm_config.vnc.unwrap();
+        assert_eq!(vnc_config.websocket, String::from("0.0.0.0:5700"));
+
+        // Invalid handshake timeout.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_vnc("0.0.0.0:1,handshake-timeout=0").is_err());
+        assert!(vm_config
+            .add_vnc("0.0.0.0:1,handshake-timeout=3601")
+            .is_err());
+
+        // Invalid auth timeout.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_vnc("0.0.0.0:1,auth-timeout=0").is_err());
+        assert!(vm_config.add_vnc("0.0.0.0:1,auth-timeout=3601").is_err());
+
+        // Invalid max fps.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_vnc("0.0.0.0:1,max-fps=0").is_err());
+        assert!(vm_config.add_vnc("0.0.0.0:1,max-fps=61").is_err());
+
+        // Invalid output limit.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_vnc("0.0.0.0:1,output-limit=1048575").is_err());
+        assert!(vm_config
+            .add_vnc("0.0.0.0:1,output-limit=1073741825")
+            .is_err());
+
+        // Invalid clipboard limit.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_vnc("0.0.0.0:1,clipboard-limit=16777217")
+            .is_err());
+
+        // Invalid websocket address.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_vnc("0.0.0.0:1,websocket=5700").is_err());
+        assert!(vm_config
+            .add_vnc("0.0.0.0:1,websocket=0.0.0.0:65536")
+            .is_err());
+
         // Invalie format of ip:port.
         let config_lines = [
             "tls-creds=vnc-tls-creds0", // No ip:port.
@@ -0,0 +1,21 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! This module implements a simple HMP console.
+//!
+//! [Hmp](https://qemu-project.gitlab.io/qemu/system/monitor.html) is the
+//! plain-text protocol for human to control a VM instance. The commands, such
+//! as `info status` and `device_add ...`, are translated into qmp commands and
+//! executed by the same backend as QMP.
+
+pub mod parser;
+pub mod server;
@@ -0,0 +1,199 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+
+/// Properties of `device_add` which are numbers in qmp arguments.
+const DEVICE_NUMBER_PROPS: [&str; 4] = ["lun", "num-queues", "boot_index", "queue-size"];
+/// Properties of `device_add` which are booleans in qmp arguments.
+const DEVICE_BOOL_PROPS: [&str; 1] = ["multifunction"];
+
+/// Hmp command translated into qmp command.
+#[derive(Debug, PartialEq)]
+pub struct HmpCommand {
+    /// Name of qmp command.
+    pub name: String,
+    /// Arguments of qmp command, `Value::Null` if there is no argument.
+    pub args: Value,
+}
+
+impl HmpCommand {
+    fn new(name: &str, args: Value) -> Self {
+        HmpCommand {
+            name: name.to_string(),
+            args,
+        }
+    }
+}
+
+/// Parse one line of hmp command, `None` is returned for the empty line.
+///
+/// # Arguments
+///
+/// * `line` - The line of hmp command, such as `info status` or
+///            `device_add virtio-blk-pci,id=blk1,drive=drive1,bus=pcie.0,addr=0x2`.
+pub fn parse_hmp_command(line: &str) -> Result<Option<HmpCommand>> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+
+    let cmd = match tokens[0] {
+        "info" => {
+            check_args_num(&tokens, 2)?;
+            let name = match tokens[1] {
+                "status" => "query-status",
+                "version" => "query-version",
+                "kvm" => "query-kvm",
+                item => bail!("Invalid info item '{}'", item),
+            };
+            HmpCommand::new(name, Value::Null)
+        }
+        "stop" | "cont" | "quit" => {
+            check_args_num(&tokens, 1)?;
+            HmpCommand::new(tokens[0], Value::Null)
+        }
+        "device_add" => {
+            check_args_num(&tokens, 2)?;
+            HmpCommand::new("device_add", parse_device_props(tokens[1])?)
+        }
+        "device_del" => {
+            check_args_num(&tokens, 2)?;
+            let mut args = Map::new();
+            args.insert("id".to_string(), Value::String(tokens[1].to_string()));
+            HmpCommand::new("device_del", Value::Object(args))
+        }
+        cmd => bail!("Unknown command '{}'", cmd),
+    };
+    Ok(Some(cmd))
+}
+
+fn check_args_num(tokens: &[&str], num: usize) -> Result<()> {
+    if tokens.len() != num {
+        bail!(
+            "Command '{}' expects {} argument(s), but {} given",
+            tokens[0],
+            num - 1,
+            tokens.len() - 1
+        );
+    }
+    Ok(())
+}
+
+/// Parse the properties of `device_add`, in which the driver is given by the
+/// first property without key, or by `driver=`.
+fn parse_device_props(props: &str) -> Result<Value> {
+    let mut args = Map::new();
+    for (index, prop) in props.split(',').enumerate() {
+        let (key, value) = match prop.split_once('=') {
+            Some((key, value)) => (key, value),
+            None if index == 0 => ("driver", prop),
+            None => bail!("Invalid device property '{}'", prop),
+        };
+        if key.is_empty() || value.is_empty() {
+            bail!("Invalid device property '{}'", prop);
+        }
+
+        let value = if DEVICE_NUMBER_PROPS.contains(&key) {
+            Value::from(
+                value
+                    .parse::<u64>()
+                    .map_err(|_| anyhow!("Invalid number '{}' of '{}'", value, key))?,
+            )
+        } else if DEVICE_BOOL_PROPS.contains(&key) {
+            match value {
+                "on" | "true" => Value::Bool(true),
+                "off" | "false" => Value::Bool(false),
+                _ => bail!("Invalid bool '{}' of '{}'", value, key),
+            }
+        } else {
+            Value::String(value.to_string())
+        };
+        if args.insert(key.to_string(), value).is_some() {
+            bail!("Device property '{}' is repeated", key);
+        }
+    }
+    if !args.contains_key("driver") {
+        bail!("Device driver is missing");
+    }
+    Ok(Value::Object(args))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_hmp_command() {
+        assert!(parse_hmp_command("").unwrap().is_none());
+        assert!(parse_hmp_command("  \t ").unwrap().is_none());
+
+        let cmd = parse_hmp_command("info status").unwrap().unwrap();
+        assert_eq!(cmd, HmpCommand::new("query-status", Value::Null));
+        let cmd = parse_hmp_command("  info   version\n").unwrap().unwrap();
+        assert_eq!(cmd, HmpCommand::new("query-version", Value::Null));
+        let cmd = parse_hmp_command("stop").unwrap().unwrap();
+        assert_eq!(cmd, HmpCommand::new("stop", Value::Null));
+        let cmd = parse_hmp_command("cont").unwrap().unwrap();
+        assert_eq!(cmd, HmpCommand::new("cont", Value::Null));
+        let cmd = parse_hmp_command("quit").unwrap().unwrap();
+        assert_eq!(cmd, HmpCommand::new("quit", Value::Null));
+        let cmd = parse_hmp_command("device_del net-0").unwrap().unwrap();
+        assert_eq!(cmd, HmpCommand::new("device_del", json!({"id": "net-0"})));
+
+        assert!(parse_hmp_command("info").is_err());
+        assert!(parse_hmp_command("info cpus").is_err());
+        assert!(parse_hmp_command("info status now").is_err());
+        assert!(parse_hmp_command("stop now").is_err());
+        assert!(parse_hmp_command("device_del").is_err());
+        assert!(parse_hmp_command("unknown").is_err());
+    }
+
+    #[test]
+    fn test_parse_device_add() {
+        let cmd = parse_hmp_command(
+            "device_add virtio-blk-pci,id=blk1,drive=drive1,bus=pcie.0,addr=0x2,num-queues=4,multifunction=on",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(cmd.name, "device_add");
+        assert_eq!(
+            cmd.args,
+            json!({
+                "driver": "virtio-blk-pci",
+                "id": "blk1",
+                "drive": "drive1",
+                "bus": "pcie.0",
+                "addr": "0x2",
+                "num-queues": 4,
+                "multifunction": true
+            })
+        );
+
+        let cmd = parse_hmp_command("device_add id=net-0,driver=virtio-net-pci")
+            .unwrap()
+            .unwrap();
+        assert_eq!(cmd.args, json!({"id": "net-0", "driver": "virtio-net-pci"}));
+
+        assert!(parse_hmp_command("device_add").is_err());
+        assert!(parse_hmp_command("device_add id=net-0").is_err());
+        assert!(parse_hmp_command("device_add virtio-net-pci,net-0").is_err());
+        assert!(parse_hmp_command("device_add virtio-net-pci,id=").is_err());
+        assert!(parse_hmp_command("device_add virtio-net-pci,id=a,id=b").is_err());
+        assert!(parse_hmp_command("device_add virtio-blk-pci,id=a,num-queues=x").is_err());
+        assert!(parse_hmp_command("device_add virtio-blk-pci,id=a,multifunction=1").is_err());
+        assert!(parse_hmp_command("device_add virtio-net-pci,id=a id=b").is_err());
+    }
+}
@@ -0,0 +1,410 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::io::{BufRead, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use log::{error, info};
+use serde_json::Value;
+use vmm_sys_util::epoll::EventSet;
+
+use super::parser::parse_hmp_command;
+use crate::machine::MachineExternalInterface;
+use crate::qmp::exit_by_host_quit;
+use crate::qmp::server::QmpServer;
+use util::loop_context::{
+    gen_delete_notifiers, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+/// Prompt of hmp console.
+pub const HMP_PROMPT: &str = "(stratovirt) ";
+/// Max length of one line of hmp command.
+const HMP_MAX_LINE_LEN: usize = 4096;
+
+/// Hmp server which executes the plain-text commands by `QmpServer`.
+pub struct HmpServer {
+    qmp: QmpServer,
+}
+
+impl HmpServer {
+    /// Constructs a `HmpServer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - The controller which execute actual command.
+    pub fn new(controller: Arc<Mutex<dyn MachineExternalInterface>>) -> Self {
+        HmpServer {
+            qmp: QmpServer::new(controller),
+        }
+    }
+
+    /// Execute one line of hmp command, return the output and whether `quit`
+    /// is executed.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line of hmp command.
+    pub fn handle_line(&self, line: &str) -> (String, bool) {
+        let cmd = match parse_hmp_command(line) {
+            Ok(Some(cmd)) => cmd,
+            Ok(None) => return (String::new(), false),
+            Err(e) => return (format!("Error: {}\n", e), false),
+        };
+
+        let resp = self.qmp.dispatch(&cmd.name, cmd.args);
+        if let Some(err) = resp.get("error") {
+            let desc = err["desc"].as_str().unwrap_or_default();
+            let output = if desc.is_empty() {
+                format!("Error: failed to execute '{}'\n", cmd.name)
+            } else {
+                format!("Error: {}\n", desc)
+            };
+            return (output, false);
+        }
+
+        let ret = &resp["return"];
+        let output = match cmd.name.as_str() {
+            "query-status" => format!("VM status: {}\n", str_value(&ret["status"])),
+            "query-version" => format!(
+                "{}.{}.{} ({})\n",
+                ret["qemu"]["major"],
+                ret["qemu"]["minor"],
+                ret["qemu"]["micro"],
+                str_value(&ret["package"])
+            ),
+            "query-kvm" => match ret["enabled"].as_bool() {
+                Some(true) => "kvm support: enabled\n".to_string(),
+                _ => "kvm support: disabled\n".to_string(),
+            },
+            _ => String::new(),
+        };
+        (output, cmd.name == "quit")
+    }
+
+    /// Serve the hmp client until the end of input or `quit` is executed.
+    /// Return whether `quit` is executed, exiting the process is left to the
+    /// caller.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The input stream of client.
+    /// * `writer` - The output stream of client.
+    pub fn serve<R: BufRead, W: Write>(&self, reader: R, writer: &mut W) -> Result<bool> {
+        writer.write_all(HMP_PROMPT.as_bytes())?;
+        writer.flush()?;
+        for line in reader.lines() {
+            let line = line.with_context(|| "Failed to read hmp command")?;
+            if self.execute_line(&line, writer)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Execute one line of hmp command and write the output followed by the
+    /// prompt, return whether `quit` is executed.
+    fn execute_line<W: Write>(&self, line: &str, writer: &mut W) -> Result<bool> {
+        info!("HMP: <-- {:?}", line);
+        let (output, quit) = self.handle_line(line);
+        writer.write_all(output.as_bytes())?;
+        if !quit {
+            writer.write_all(HMP_PROMPT.as_bytes())?;
+        }
+        writer.flush()?;
+        Ok(quit)
+    }
+}
+
+/// Hmp client connected to the Unix socket of `-hmp`.
+struct HmpClient {
+    stream: UnixStream,
+    /// Input of client which is not a complete line yet.
+    buf: Vec<u8>,
+    /// Whether the client is disconnected.
+    closed: bool,
+    server: Rc<HmpServer>,
+}
+
+impl HmpClient {
+    fn new(stream: UnixStream, server: Rc<HmpServer>) -> Self {
+        HmpClient {
+            stream,
+            buf: Vec::new(),
+            closed: false,
+            server,
+        }
+    }
+
+    /// Read the input of client and execute the complete lines, return
+    /// whether `quit` is executed.
+    fn handle_input(&mut self) -> Result<bool> {
+        let mut data = [0_u8; HMP_MAX_LINE_LEN];
+        let count = (&self.stream)
+            .read(&mut data)
+            .with_context(|| "Failed to read hmp command")?;
+        if count == 0 {
+            self.closed = true;
+            return Ok(false);
+        }
+        self.buf.extend_from_slice(&data[..count]);
+
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            if self.server.execute_line(&line, &mut &self.stream)? {
+                return Ok(true);
+            }
+        }
+        if self.buf.len() > HMP_MAX_LINE_LEN {
+            bail!("Hmp command exceeds {} bytes", HMP_MAX_LINE_LEN);
+        }
+        Ok(false)
+    }
+}
+
+/// Unix socket of `-hmp`, which serves one hmp client at one time.
+pub struct HmpSocket {
+    listener: UnixListener,
+    server: Rc<HmpServer>,
+}
+
+impl HmpSocket {
+    /// Constructs a `HmpSocket`.
+    ///
+    /// # Arguments
+    ///
+    /// * `listener` - The listener of Unix socket.
+    /// * `controller` - The controller which execute actual command.
+    pub fn new(
+        listener: UnixListener,
+        controller: Arc<Mutex<dyn MachineExternalInterface>>,
+    ) -> Self {
+        HmpSocket {
+            listener,
+            server: Rc::new(HmpServer::new(controller)),
+        }
+    }
+
+    /// Accept the hmp client and create the notifier of its stream. The
+    /// listener is parked until the client is disconnected.
+    fn accept(&self) -> Result<EventNotifier> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .with_context(|| "Failed to accept hmp client")?;
+        (&stream)
+            .write_all(HMP_PROMPT.as_bytes())
+            .with_context(|| "Failed to send hmp prompt")?;
+        let stream_fd = stream.as_raw_fd();
+        let client = Mutex::new(HmpClient::new(stream, self.server.clone()));
+
+        let handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
+            let mut locked_client = client.lock().unwrap();
+            if event & EventSet::IN == EventSet::IN {
+                match locked_client.handle_input() {
+                    Ok(true) => exit_by_host_quit(),
+                    Ok(false) => {}
+                    Err(e) => {
+                        error!("{:?}", e);
+                        locked_client.closed = true;
+                    }
+                }
+            }
+            if locked_client.closed || event & EventSet::HANG_UP == EventSet::HANG_UP {
+                Some(gen_delete_notifiers(&[stream_fd]))
+            } else {
+                None
+            }
+        });
+        Ok(EventNotifier::new(
+            NotifierOperation::AddShared,
+            stream_fd,
+            Some(self.listener.as_raw_fd()),
+            EventSet::IN | EventSet::HANG_UP,
+            vec![handler],
+        ))
+    }
+
+    fn get_listener_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl EventNotifierHelper for HmpSocket {
+    fn internal_notifiers(hmp_socket: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let socket = hmp_socket.clone();
+        let handler: Rc<NotifierCallback> =
+            Rc::new(move |_, _| match socket.lock().unwrap().accept() {
+                Ok(notifier) => Some(vec![notifier]),
+                Err(e) => {
+                    error!("{:?}", e);
+                    None
+                }
+            });
+        let listener_fd = hmp_socket.lock().unwrap().get_listener_fd();
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            listener_fd,
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
+
+fn str_value(value: &Value) -> &str {
+    value.as_str().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::machine::KvmVmState;
+    use crate::qmp::server::tests::TestVm;
+
+    fn create_server() -> (HmpServer, Arc<Mutex<TestVm>>) {
+        let vm = Arc::new(Mutex::new(TestVm::new()));
+        (HmpServer::new(vm.clone()), vm)
+    }
+
+    fn run_commands(server: &HmpServer, input: &str) -> (String, bool) {
+        let mut output = Vec::new();
+        let quit = server.serve(Cursor::new(input), &mut output).unwrap();
+        (String::from_utf8(output).unwrap(), quit)
+    }
+
+    #[test]
+    fn test_hmp_server_info() {
+        let (server, _) = create_server();
+        let (output, quit) = run_commands(&server, "info status\ninfo version\n\ninfo kvm\n");
+        assert!(!quit);
+        let lines: Vec<&str> = output.split(HMP_PROMPT).collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "");
+        assert_eq!(lines[1], "VM status: running\n");
+        assert!(lines[2].starts_with("5.0.1 (StratoVirt-"));
+        assert_eq!(lines[3], "");
+        assert_eq!(lines[4], "kvm support: enabled\n");
+        assert_eq!(lines[5], "");
+    }
+
+    #[test]
+    fn test_hmp_server_stop_and_cont() {
+        let (server, vm) = create_server();
+        let (output, _) = run_commands(&server, "stop\ninfo status\nstop\n");
+        assert_eq!(
+            output,
+            format!(
+                "{0}{0}VM status: paused\n{0}Error: failed to execute 'stop'\n{0}",
+                HMP_PROMPT
+            )
+        );
+        assert_eq!(
+            *vm.lock().unwrap().state.lock().unwrap(),
+            KvmVmState::Paused
+        );
+
+        let (output, _) = run_commands(&server, "cont\ninfo status");
+        assert_eq!(output, format!("{0}{0}VM status: running\n{0}", HMP_PROMPT));
+    }
+
+    #[test]
+    fn test_hmp_server_device() {
+        let (server, vm) = create_server();
+        let (output, _) = run_commands(
+            &server,
+            "device_add virtio-net-pci,id=net-0,bus=pcie.0,addr=0x2\n\
+             device_add virtio-net-pci,id=net-0,bus=pcie.0,addr=0x3\n",
+        );
+        assert_eq!(
+            output,
+            format!("{0}{0}Error: Device net-0 exists\n{0}", HMP_PROMPT)
+        );
+        assert_eq!(vm.lock().unwrap().devices, vec!["net-0".to_string()]);
+
+        let (output, _) = run_commands(&server, "device_del net-0\ndevice_del net-0\n");
+        assert_eq!(
+            output,
+            format!("{0}{0}Error: Device net-0 not found\n{0}", HMP_PROMPT)
+        );
+        assert!(vm.lock().unwrap().devices.is_empty());
+
+        // Invalid arguments of device_add.
+        let (output, _) = run_commands(&server, "device_add virtio-net-pci,id=net-1,foo=1\n");
+        assert!(output.starts_with(&format!("{}Error: Invalid arguments", HMP_PROMPT)));
+        assert!(vm.lock().unwrap().devices.is_empty());
+    }
+
+    #[test]
+    fn test_hmp_server_invalid_command() {
+        let (server, _) = create_server();
+        let (output, quit) = run_commands(&server, "info cpus\nfoo bar\n");
+        assert!(!quit);
+        assert_eq!(
+            output,
+            format!(
+                "{0}Error: Invalid info item 'cpus'\n{0}Error: Unknown command 'foo'\n{0}",
+                HMP_PROMPT
+            )
+        );
+    }
+
+    #[test]
+    fn test_hmp_server_quit() {
+        let (server, vm) = create_server();
+        let (output, quit) = run_commands(&server, "quit\ninfo status\n");
+        assert!(quit);
+        assert_eq!(output, HMP_PROMPT);
+        assert_eq!(
+            *vm.lock().unwrap().state.lock().unwrap(),
+            KvmVmState::Shutdown
+        );
+    }
+
+    #[test]
+    fn test_hmp_client_input() {
+        let (server, _) = create_server();
+        let (mut peer, stream) = UnixStream::pair().unwrap();
+        let mut client = HmpClient::new(stream, Rc::new(server));
+
+        // The incomplete line is executed once the rest of it is received.
+        peer.write_all(b"info st").unwrap();
+        assert!(!client.handle_input().unwrap());
+        assert_eq!(client.buf, b"info st");
+        peer.write_all(b"atus\ninfo kvm\n").unwrap();
+        assert!(!client.handle_input().unwrap());
+        assert!(client.buf.is_empty());
+        let expected = format!(
+            "VM status: running\n{0}kvm support: enabled\n{0}",
+            HMP_PROMPT
+        );
+        let mut output = vec![0_u8; expected.len()];
+        peer.read_exact(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        // Too long command.
+        peer.write_all(&[b'a'; HMP_MAX_LINE_LEN]).unwrap();
+        assert!(!client.handle_input().unwrap());
+        peer.write_all(b"a").unwrap();
+        assert!(client.handle_input().is_err());
+
+        drop(peer);
+        assert!(!client.handle_input().unwrap());
+        assert!(client.closed);
+    }
+}
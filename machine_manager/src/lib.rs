@@ -25,9 +25,11 @@ pub mod cmdline;
 pub mod config;
 pub mod error;
 pub mod event_loop;
+pub mod hmp;
 pub mod machine;
 pub mod qmp;
 pub mod signal_handler;
+pub mod snapshot;
 pub mod socket;
 pub mod temp_cleaner;
 pub use error::MachineManagerError;
@@ -20,9 +20,10 @@ use crate::config::ShutdownAction;
 use crate::qmp::qmp_schema::{
     BlockDevAddArgument, BlockdevSnapshotInternalArgument, CameraDevAddArgument,
     CharDevAddArgument, ChardevInfo, Cmd, CmdLine, CmdParameter, DeviceAddArgument, DeviceProps,
-    Events, GicCap, HumanMonitorCmdArgument, IothreadInfo, KvmInfo, MachineInfo,
-    MigrateCapabilities, NetDevAddArgument, PropList, QmpCommand, QmpErrorClass, QmpEvent, Target,
-    TypeLists, UpdateRegionArgument,
+    DisplayReloadArgument, Events, ExpirePasswordArgument, GicCap, HumanMonitorCmdArgument,
+    IothreadInfo, KvmInfo, MachineInfo, MigrateCapabilities, NetDevAddArgument, PropList,
+    QmpCommand, QmpErrorClass, QmpEvent, SetPasswordArgument, Target, TypeLists,
+    UpdateRegionArgument,
 };
 use crate::qmp::{Response, Version};
 
@@ -32,6 +33,9 @@ pub struct PathInfo {
     pub label: String,
 }
 
+/// Error message of hotplug in the VM state which doesn't allow it.
+pub const HOTPLUG_NOT_ALLOWED: &str = "Failed to add device: hotplug is not allowed in VM state";
+
 /// State for KVM VM.
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum KvmVmState {
@@ -43,6 +47,31 @@ pub enum KvmVmState {
     Shutdown = 6,
 }
 
+impl KvmVmState {
+    /// Whether devices can be hot-plugged in this state, which is only
+    /// allowed after VM is started and before it is shut down.
+    pub fn is_hotpluggable(self) -> bool {
+        matches!(self, KvmVmState::Running | KvmVmState::Paused)
+    }
+}
+
+/// Check the VM state before `device_add` of machines, return the error
+/// response if devices can't be hot-plugged in the current state.
+///
+/// # Arguments
+///
+/// * `vm_state` - The current state of VM.
+pub fn hotplug_check(vm_state: &Mutex<KvmVmState>) -> Option<Response> {
+    let state = *vm_state.lock().unwrap();
+    if state.is_hotpluggable() {
+        return None;
+    }
+    Some(Response::create_error_response(
+        QmpErrorClass::GenericError(format!("{} {:?}", HOTPLUG_NOT_ALLOWED, state)),
+        None,
+    ))
+}
+
 /// Event over StratoVirt lifetime.
 pub enum VmEvent {
     ShutdownCauseGuestReset,
@@ -159,6 +188,22 @@ pub trait DeviceInterface {
     /// Query each `hotpluggable_cpus`'s topology info and hotplug message.
     fn query_hotpluggable_cpus(&self) -> Response;
 
+    /// Inject NMI into the cpu with `cpu_index`, or all cpus if it is `None`.
+    fn inject_nmi(&self, _cpu_index: Option<usize>) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("inject-nmi is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Dump the registers of the cpu with `cpu_index`.
+    fn dump_guest_registers(&self, _cpu_index: u32) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("dump-guest-registers is not supported".to_string()),
+            None,
+        )
+    }
+
     /// Add a device with configuration.
     fn device_add(&mut self, args: Box<DeviceAddArgument>) -> Response;
 
@@ -317,6 +362,7 @@ pub trait DeviceInterface {
             ("gpex-pcihost", "pcie-host-bridge"),
             ("nec-usb-xhci", "base-xhci"),
             ("usb-tablet", "usb-hid"),
+            ("usb-mouse", "usb-hid"),
             ("usb-kbd", "usb-hid"),
             ("usb-storage", "usb-storage-dev"),
             ("virtio-gpu-pci", "virtio-gpu"),
@@ -471,6 +517,30 @@ pub trait DeviceInterface {
         )
     }
 
+    /// Reload the configuration of display.
+    fn display_reload(&self, _args: DisplayReloadArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("display-reload is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Set the password of display.
+    fn set_password(&self, _args: SetPasswordArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("set_password is not supported".to_string()),
+            None,
+        )
+    }
+
+    /// Set the expire time of display password.
+    fn expire_password(&self, _args: ExpirePasswordArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("expire_password is not supported".to_string()),
+            None,
+        )
+    }
+
     fn blockdev_snapshot_internal_sync(&self, _args: BlockdevSnapshotInternalArgument) -> Response {
         Response::create_empty_response()
     }
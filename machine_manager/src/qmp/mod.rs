@@ -30,6 +30,7 @@
 #[allow(non_camel_case_types)]
 #[allow(non_snake_case)]
 pub mod qmp_schema;
+pub mod server;
 
 use std::collections::BTreeMap;
 use std::io::Write;
@@ -46,6 +47,7 @@ use util::set_termi_canon_mode;
 use util::time::NANOSECONDS_PER_SECOND;
 
 use self::qmp_schema::{self as schema, QmpCommand};
+use self::server::QmpServer;
 use crate::event_loop::EventLoop;
 use crate::machine::MachineExternalInterface;
 use crate::socket::SocketRWHandler;
@@ -339,7 +341,7 @@ pub fn create_timestamp() -> TimeStamp {
 /// # Arguments
 ///
 /// * `stream_fd` - The input stream file description.
-/// * `controller` - The controller which execute actual qmp command.
+/// * `server` - The qmp server which execute actual qmp command.
 /// * `leak_bucket` - The LeakBucket flow controller for qmp command.
 ///
 /// # Errors
@@ -347,7 +349,7 @@ pub fn create_timestamp() -> TimeStamp {
 /// This function will fail when json parser failed or socket file description broke.
 pub fn handle_qmp(
     stream_fd: RawFd,
-    controller: &Arc<Mutex<dyn MachineExternalInterface>>,
+    server: &QmpServer,
     leak_bucket: &mut LeakBucket,
 ) -> Result<()> {
     let mut qmp_service = crate::socket::SocketHandler::new(stream_fd);
@@ -370,21 +372,13 @@ pub fn handle_qmp(
         (Ok(buffer), if_fd) => {
             info!("QMP: <-- {:?}", buffer);
             let qmp_command: schema::QmpCommand = buffer.unwrap();
-            let (return_msg, shutdown_flag) = qmp_command_exec(qmp_command, controller, if_fd);
+            let (return_msg, shutdown_flag) = server.execute(qmp_command, if_fd);
             info!("QMP: --> {:?}", return_msg);
             qmp_service.send_str(&return_msg)?;
 
             // handle shutdown command
             if shutdown_flag {
-                let shutdown_msg = schema::Shutdown {
-                    guest: false,
-                    reason: "host-qmp-quit".to_string(),
-                };
-                event!(Shutdown; shutdown_msg);
-                TempCleaner::clean();
-                set_termi_canon_mode().expect("Failed to set terminal to canonical mode.");
-
-                std::process::exit(0);
+                exit_by_host_quit();
             }
 
             Ok(())
@@ -400,6 +394,20 @@ pub fn handle_qmp(
     }
 }
 
+/// Send shutdown event, clean the temporary files and exit the process after
+/// the VM is destroyed by `quit` command of host.
+pub fn exit_by_host_quit() -> ! {
+    let shutdown_msg = schema::Shutdown {
+        guest: false,
+        reason: "host-qmp-quit".to_string(),
+    };
+    event!(Shutdown; shutdown_msg);
+    TempCleaner::clean();
+    set_termi_canon_mode().expect("Failed to set terminal to canonical mode.");
+
+    std::process::exit(0);
+}
+
 /// Create a match , where `qmp_command` and its arguments matching by handle
 /// function, and exec this qmp command.
 fn qmp_command_exec(
@@ -455,6 +463,8 @@ fn qmp_command_exec(
         (chardev_remove, chardev_remove, id),
         (cameradev_del, cameradev_del,id),
         (balloon, balloon, value),
+        (inject_nmi, inject_nmi, cpu_index),
+        (dump_guest_registers, dump_guest_registers, cpu_index),
         (migrate, migrate, uri);
         (device_add, device_add),
         (blockdev_add, blockdev_add),
@@ -463,6 +473,9 @@ fn qmp_command_exec(
         (cameradev_add, cameradev_add),
         (update_region, update_region),
         (human_monitor_command, human_monitor_command),
+        (display_reload, display_reload),
+        (set_password, set_password),
+        (expire_password, expire_password),
         (blockdev_snapshot_internal_sync, blockdev_snapshot_internal_sync),
         (blockdev_snapshot_delete_internal_sync, blockdev_snapshot_delete_internal_sync)
     );
@@ -92,6 +92,21 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "inject-nmi")]
+    #[strum(serialize = "inject-nmi")]
+    inject_nmi {
+        #[serde(default)]
+        arguments: inject_nmi,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "dump-guest-registers")]
+    #[strum(serialize = "dump-guest-registers")]
+    dump_guest_registers {
+        arguments: dump_guest_registers,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     device_add {
         arguments: Box<device_add>,
         #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -203,6 +218,23 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "display-reload")]
+    #[strum(serialize = "display-reload")]
+    display_reload {
+        arguments: display_reload,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    set_password {
+        arguments: set_password,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    expire_password {
+        arguments: expire_password,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "migrate")]
     migrate {
         arguments: migrate,
@@ -571,6 +603,103 @@ impl Command for system_reset {
     }
 }
 
+/// inject-nmi
+///
+/// Inject NMI into guest VCPUs, which should be running.
+///
+/// # Arguments
+///
+/// * `cpu-index` - Index of the VCPU, all VCPUs if it is not set. (optional)
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "inject-nmi", "arguments": { "cpu-index": 1 } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct inject_nmi {
+    #[serde(rename = "cpu-index", default, skip_serializing_if = "Option::is_none")]
+    pub cpu_index: Option<usize>,
+}
+
+impl Command for inject_nmi {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// dump-guest-registers
+///
+/// Dump the registers of a guest VCPU, which should not be running.
+///
+/// # Arguments
+///
+/// * `cpu-index` - Index of the VCPU.
+///
+/// # Returns
+///
+/// `RegisterDump` of the VCPU.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "dump-guest-registers", "arguments": { "cpu-index": 0 } }
+/// <- { "return": { "rax": 0, "rbx": 0, ..., "rip": 65520, "rflags": 2,
+///                  "cr0": 1610612752, ..., "cs": 61440, ... } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct dump_guest_registers {
+    #[serde(rename = "cpu-index")]
+    pub cpu_index: u32,
+}
+
+impl Command for dump_guest_registers {
+    type Res = RegisterDump;
+
+    fn back(self) -> RegisterDump {
+        Default::default()
+    }
+}
+
+/// General purpose registers, control registers and segment selectors of
+/// a x86_64 VCPU.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisterDump {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rsp: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub cs: u16,
+    pub ds: u16,
+    pub es: u16,
+    pub fs: u16,
+    pub gs: u16,
+    pub ss: u16,
+}
+
 /// device_add
 ///
 /// # Arguments
@@ -1561,6 +1690,7 @@ pub struct BalloonInfo {
 ///             "host": "127.0.0.1",
 ///             "service": "50401",
 ///             "family": "ipv4",
+///             "update-rate": 30,
 ///         ]
 ///         }
 ///     }
@@ -1598,6 +1728,105 @@ pub struct VncClientInfo {
     pub service: String,
     #[serde(rename = "family")]
     pub family: String,
+    /// Number of framebuffer updates sent to the client in the last second.
+    #[serde(rename = "update-rate", default)]
+    pub update_rate: u64,
+}
+
+/// display-reload:
+///
+/// Reload the configuration of display.
+///
+/// # Arguments
+///
+/// * `type` - Type of display, only `vnc` is supported.
+/// * `tls-certs` - Reload the tls credentials of vnc.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "display-reload", "arguments": { "type": "vnc", "tls-certs": true } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct display_reload {
+    #[serde(rename = "type")]
+    pub display_type: String,
+    #[serde(rename = "tls-certs", default)]
+    pub tls_certs: bool,
+}
+pub type DisplayReloadArgument = display_reload;
+
+impl Command for display_reload {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// set_password:
+///
+/// Set the password of display. The clients already connected are not affected.
+///
+/// # Arguments
+///
+/// * `protocol` - Protocol of display, only `vnc` is supported.
+/// * `password` - New password.
+/// * `connected` - Action for the connected clients, only `keep` is supported. (optional)
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "set_password", "arguments": { "protocol": "vnc", "password": "secret" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct set_password {
+    pub protocol: String,
+    pub password: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connected: Option<String>,
+}
+pub type SetPasswordArgument = set_password;
+
+impl Command for set_password {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// expire_password:
+///
+/// Set the expire time of display password.
+///
+/// # Arguments
+///
+/// * `protocol` - Protocol of display, only `vnc` is supported.
+/// * `time` - `now` expires immediately, `never` disables expiry, `+N` expires
+///   after N seconds, and `N` expires at N seconds since the Epoch.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "expire_password", "arguments": { "protocol": "vnc", "time": "+60" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct expire_password {
+    pub protocol: String,
+    pub time: String,
+}
+pub type ExpirePasswordArgument = expire_password;
+
+impl Command for expire_password {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
 }
 
 /// balloon:
@@ -2912,6 +3141,199 @@ mod tests {
         assert!(err_msg.contains(part_msg));
     }
 
+    #[test]
+    fn test_qmp_display_reload() {
+        let json_msg = r#"
+        {
+            "execute": "display-reload" ,
+            "arguments": {
+                "type": "vnc",
+                "tls-certs": true
+            }
+        }
+        "#;
+        match serde_json::from_str::<QmpCommand>(json_msg).unwrap() {
+            QmpCommand::display_reload { arguments, .. } => {
+                assert_eq!(arguments.display_type, "vnc");
+                assert!(arguments.tls_certs);
+            }
+            _ => panic!("Unexpected qmp command"),
+        }
+
+        // Abnormal test with invalid arguments.
+        let json_msg = r#"
+        {
+            "execute": "display-reload" ,
+            "arguments": {
+                "type": "vnc",
+                "invalid_key": true
+            }
+        }
+        "#;
+        assert!(serde_json::from_str::<QmpCommand>(json_msg).is_err());
+    }
+
+    #[test]
+    fn test_qmp_inject_nmi() {
+        let json_msg = r#"
+        {
+            "execute": "inject-nmi" ,
+            "arguments": {
+                "cpu-index": 1
+            }
+        }
+        "#;
+        match serde_json::from_str::<QmpCommand>(json_msg).unwrap() {
+            QmpCommand::inject_nmi { arguments, .. } => {
+                assert_eq!(arguments.cpu_index, Some(1));
+            }
+            _ => panic!("Unexpected qmp command"),
+        }
+
+        // All vcpus without the arguments.
+        let json_msg = r#"{ "execute": "inject-nmi" }"#;
+        match serde_json::from_str::<QmpCommand>(json_msg).unwrap() {
+            QmpCommand::inject_nmi { arguments, .. } => {
+                assert!(arguments.cpu_index.is_none());
+            }
+            _ => panic!("Unexpected qmp command"),
+        }
+
+        // Abnormal test with invalid arguments.
+        let json_msg = r#"
+        {
+            "execute": "inject-nmi" ,
+            "arguments": {
+                "cpu-index": -1
+            }
+        }
+        "#;
+        assert!(serde_json::from_str::<QmpCommand>(json_msg).is_err());
+        let json_msg = r#"
+        {
+            "execute": "inject-nmi" ,
+            "arguments": {
+                "cpu_index": 1
+            }
+        }
+        "#;
+        assert!(serde_json::from_str::<QmpCommand>(json_msg).is_err());
+    }
+
+    #[test]
+    fn test_qmp_dump_guest_registers() {
+        let json_msg = r#"
+        {
+            "execute": "dump-guest-registers" ,
+            "arguments": {
+                "cpu-index": 1
+            }
+        }
+        "#;
+        match serde_json::from_str::<QmpCommand>(json_msg).unwrap() {
+            QmpCommand::dump_guest_registers { arguments, .. } => {
+                assert_eq!(arguments.cpu_index, 1);
+            }
+            _ => panic!("Unexpected qmp command"),
+        }
+
+        // Abnormal test with missing or invalid arguments.
+        let json_msg = r#"{ "execute": "dump-guest-registers" }"#;
+        assert!(serde_json::from_str::<QmpCommand>(json_msg).is_err());
+        let json_msg = r#"
+        {
+            "execute": "dump-guest-registers" ,
+            "arguments": {
+                "cpu-index": -1
+            }
+        }
+        "#;
+        assert!(serde_json::from_str::<QmpCommand>(json_msg).is_err());
+    }
+
+    #[test]
+    fn test_register_dump_serialize() {
+        let dump = RegisterDump {
+            rax: 0x1234,
+            r15: u64::MAX,
+            rip: 0xfff0,
+            rflags: 0x2,
+            cr0: 0x6000_0010,
+            cr3: 0x9000,
+            cs: 0xf000,
+            ss: 0x18,
+            ..Default::default()
+        };
+        let value = serde_json::to_value(&dump).unwrap();
+        let regs = value.as_object().unwrap();
+        assert_eq!(regs.len(), 28);
+        assert_eq!(regs["rax"], 0x1234);
+        assert_eq!(regs["rbx"], 0);
+        assert_eq!(regs["r15"], u64::MAX);
+        assert_eq!(regs["rip"], 0xfff0);
+        assert_eq!(regs["rflags"], 0x2);
+        assert_eq!(regs["cr0"], 0x6000_0010);
+        assert_eq!(regs["cr2"], 0);
+        assert_eq!(regs["cr3"], 0x9000);
+        assert_eq!(regs["cs"], 0xf000);
+        assert_eq!(regs["ss"], 0x18);
+
+        let json = serde_json::to_string(&dump).unwrap();
+        assert!(json.starts_with(r#"{"rax":4660,"rbx":0,"#));
+        assert!(json.ends_with(r#""cs":61440,"ds":0,"es":0,"fs":0,"gs":0,"ss":24}"#));
+        let new_dump: RegisterDump = serde_json::from_str(&json).unwrap();
+        assert_eq!(new_dump, dump);
+    }
+
+    #[test]
+    fn test_qmp_set_and_expire_password() {
+        let json_msg = r#"
+        {
+            "execute": "set_password" ,
+            "arguments": {
+                "protocol": "vnc",
+                "password": "secret"
+            }
+        }
+        "#;
+        match serde_json::from_str::<QmpCommand>(json_msg).unwrap() {
+            QmpCommand::set_password { arguments, .. } => {
+                assert_eq!(arguments.protocol, "vnc");
+                assert_eq!(arguments.password, "secret");
+                assert!(arguments.connected.is_none());
+            }
+            _ => panic!("Unexpected qmp command"),
+        }
+
+        let json_msg = r#"
+        {
+            "execute": "expire_password" ,
+            "arguments": {
+                "protocol": "vnc",
+                "time": "+60"
+            }
+        }
+        "#;
+        match serde_json::from_str::<QmpCommand>(json_msg).unwrap() {
+            QmpCommand::expire_password { arguments, .. } => {
+                assert_eq!(arguments.protocol, "vnc");
+                assert_eq!(arguments.time, "+60");
+            }
+            _ => panic!("Unexpected qmp command"),
+        }
+
+        // Abnormal test with missing arguments.
+        let json_msg = r#"
+        {
+            "execute": "expire_password" ,
+            "arguments": {
+                "protocol": "vnc"
+            }
+        }
+        "#;
+        assert!(serde_json::from_str::<QmpCommand>(json_msg).is_err());
+    }
+
     #[test]
     fn test_qmp_human_monitor_command() {
         // Normal test.
@@ -0,0 +1,481 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! A QMP server which executes the QMP commands by the controller of VM.
+//!
+//! The Unix socket of `-qmp unix:<path>,server` executes the decoded commands
+//! by `QmpServer::execute`. `QmpServer::dispatch` executes the commands given
+//! by name and arguments in the same way, so that the commands can be executed
+//! and checked without socket.
+
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{Map, Value};
+
+use super::qmp_schema::{QmpCommand, QmpErrorClass};
+use super::{qmp_command_exec, Response};
+use crate::machine::MachineExternalInterface;
+
+/// Qmp server which executes the commands by the controller of VM.
+pub struct QmpServer {
+    /// The controller which execute actual qmp command.
+    controller: Arc<Mutex<dyn MachineExternalInterface>>,
+}
+
+impl QmpServer {
+    /// Constructs a `QmpServer`.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - The controller which execute actual qmp command.
+    pub fn new(controller: Arc<Mutex<dyn MachineExternalInterface>>) -> Self {
+        QmpServer { controller }
+    }
+
+    /// Execute qmp command, return the json response and whether the VM
+    /// is shut down by the command.
+    ///
+    /// # Arguments
+    ///
+    /// * `qmp_command` - The qmp command decoded from request.
+    /// * `if_fd` - The file descriptor passed with request, used by `getfd`.
+    pub fn execute(&self, qmp_command: QmpCommand, if_fd: Option<RawFd>) -> (String, bool) {
+        qmp_command_exec(qmp_command, &self.controller, if_fd)
+    }
+
+    /// Execute qmp command and return the json response.
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd` - The name of qmp command, as the `execute` field of request.
+    /// * `args` - The `arguments` field of request, `Value::Null` if there is
+    ///            no argument.
+    ///
+    /// # Notes
+    ///
+    /// `quit` only destroys the VM, exiting the process is left to the caller.
+    pub fn dispatch(&self, cmd: &str, args: Value) -> Value {
+        let mut request = Map::new();
+        request.insert("execute".to_string(), Value::String(cmd.to_string()));
+        if !args.is_null() {
+            request.insert("arguments".to_string(), args);
+        }
+        let qmp_command: QmpCommand = match serde_json::from_value(Value::Object(request)) {
+            Ok(command) => command,
+            Err(e) if e.to_string().starts_with("unknown variant") => {
+                let err = QmpErrorClass::CommandNotFound(format!(
+                    "The command {} has not been found",
+                    cmd
+                ));
+                return serde_json::to_value(Response::create_error_response(err, None)).unwrap();
+            }
+            Err(e) => {
+                let err = QmpErrorClass::GenericError(format!("Invalid arguments: {}", e));
+                return serde_json::to_value(Response::create_error_response(err, None)).unwrap();
+            }
+        };
+
+        let (return_msg, _) = self.execute(qmp_command, None);
+        serde_json::from_str(&return_msg).unwrap()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::machine::{
+        hotplug_check, DeviceInterface, KvmVmState, MachineLifecycle, MigrateInterface,
+        HOTPLUG_NOT_ALLOWED,
+    };
+    use crate::qmp::qmp_schema::{
+        BlockDevAddArgument, CameraDevAddArgument, CharDevAddArgument, DeviceAddArgument,
+        NetDevAddArgument, RunState, StatusInfo, UpdateRegionArgument,
+    };
+
+    /// Fake VM which records the state and the added devices.
+    pub(crate) struct TestVm {
+        pub(crate) state: Mutex<KvmVmState>,
+        pub(crate) devices: Vec<String>,
+        blocks: Mutex<Vec<String>>,
+    }
+
+    impl TestVm {
+        pub(crate) fn new() -> Self {
+            TestVm {
+                state: Mutex::new(KvmVmState::Running),
+                devices: Vec::new(),
+                blocks: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    /// Drivers of devices which can be hot-plugged into `TestVm`.
+    const TEST_DRIVERS: [&str; 2] = ["virtio-net-pci", "virtio-blk-pci"];
+
+    fn not_supported() -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("Not supported".to_string()),
+            None,
+        )
+    }
+
+    impl MachineLifecycle for TestVm {
+        fn notify_lifecycle(&self, old: KvmVmState, new: KvmVmState) -> bool {
+            let mut state = self.state.lock().unwrap();
+            if *state != old {
+                return false;
+            }
+            *state = new;
+            true
+        }
+    }
+
+    impl DeviceInterface for TestVm {
+        fn query_status(&self) -> Response {
+            let status = match *self.state.lock().unwrap() {
+                KvmVmState::Running => RunState::running,
+                KvmVmState::Paused => RunState::paused,
+                _ => RunState::shutdown,
+            };
+            let info = StatusInfo {
+                singlestep: false,
+                running: matches!(status, RunState::running),
+                status,
+            };
+            Response::create_response(serde_json::to_value(info).unwrap(), None)
+        }
+
+        fn query_cpus(&self) -> Response {
+            not_supported()
+        }
+
+        fn query_hotpluggable_cpus(&self) -> Response {
+            not_supported()
+        }
+
+        fn device_add(&mut self, args: Box<DeviceAddArgument>) -> Response {
+            if let Some(resp) = hotplug_check(&self.state) {
+                return resp;
+            }
+            if !TEST_DRIVERS.contains(&args.driver.as_str()) {
+                return Response::create_error_response(
+                    QmpErrorClass::GenericError(format!(
+                        "Failed to add device: Driver {} is not support",
+                        args.driver
+                    )),
+                    None,
+                );
+            }
+            if self.devices.contains(&args.id) {
+                return Response::create_error_response(
+                    QmpErrorClass::GenericError(format!("Device {} exists", args.id)),
+                    None,
+                );
+            }
+            self.devices.push(args.id);
+            Response::create_empty_response()
+        }
+
+        fn device_del(&mut self, device_id: String) -> Response {
+            match self.devices.iter().position(|id| *id == device_id) {
+                Some(index) => {
+                    self.devices.remove(index);
+                    Response::create_empty_response()
+                }
+                None => Response::create_error_response(
+                    QmpErrorClass::DeviceNotFound(format!("Device {} not found", device_id)),
+                    None,
+                ),
+            }
+        }
+
+        fn blockdev_add(&self, args: Box<BlockDevAddArgument>) -> Response {
+            self.blocks.lock().unwrap().push(args.node_name);
+            Response::create_empty_response()
+        }
+
+        fn blockdev_del(&self, _node_name: String) -> Response {
+            not_supported()
+        }
+
+        fn netdev_add(&mut self, _args: Box<NetDevAddArgument>) -> Response {
+            not_supported()
+        }
+
+        fn netdev_del(&mut self, _id: String) -> Response {
+            not_supported()
+        }
+
+        fn chardev_add(&mut self, _args: CharDevAddArgument) -> Response {
+            not_supported()
+        }
+
+        fn chardev_remove(&mut self, _id: String) -> Response {
+            not_supported()
+        }
+
+        fn cameradev_add(&mut self, _args: CameraDevAddArgument) -> Response {
+            not_supported()
+        }
+
+        fn cameradev_del(&mut self, _id: String) -> Response {
+            not_supported()
+        }
+
+        fn getfd(&self, _fd_name: String, _if_fd: Option<RawFd>) -> Response {
+            not_supported()
+        }
+
+        fn query_balloon(&self) -> Response {
+            not_supported()
+        }
+
+        fn query_mem(&self) -> Response {
+            not_supported()
+        }
+
+        fn query_vnc(&self) -> Response {
+            not_supported()
+        }
+
+        fn balloon(&self, _size: u64) -> Response {
+            not_supported()
+        }
+
+        fn update_region(&mut self, _args: UpdateRegionArgument) -> Response {
+            not_supported()
+        }
+    }
+
+    impl MigrateInterface for TestVm {}
+
+    impl MachineExternalInterface for TestVm {}
+
+    fn create_server() -> (QmpServer, Arc<Mutex<TestVm>>) {
+        let vm = Arc::new(Mutex::new(TestVm::new()));
+        (QmpServer::new(vm.clone()), vm)
+    }
+
+    fn error_class(resp: &Value) -> &str {
+        resp["error"]["class"].as_str().unwrap()
+    }
+
+    #[test]
+    fn test_qmp_server_query_version() {
+        let (server, _) = create_server();
+        let resp = server.dispatch("query-version", Value::Null);
+        assert_eq!(
+            resp["return"]["qemu"],
+            json!({"micro": 1, "minor": 0, "major": 5})
+        );
+        assert!(resp["return"]["package"]
+            .as_str()
+            .unwrap()
+            .starts_with("StratoVirt-"));
+
+        let resp = server.dispatch("query-version", json!("invalid"));
+        assert_eq!(error_class(&resp), "GenericError");
+    }
+
+    #[test]
+    fn test_qmp_server_query_kvm() {
+        let (server, _) = create_server();
+        let resp = server.dispatch("query-kvm", json!({}));
+        assert_eq!(resp, json!({"return": {"enabled": true, "present": true}}));
+    }
+
+    #[test]
+    fn test_qmp_server_query_status() {
+        let (server, _) = create_server();
+        let resp = server.dispatch("query-status", Value::Null);
+        assert_eq!(
+            resp,
+            json!({"return": {"running": true, "singlestep": false, "status": "running"}})
+        );
+    }
+
+    #[test]
+    fn test_qmp_server_stop_and_cont() {
+        let (server, vm) = create_server();
+        assert_eq!(server.dispatch("stop", Value::Null), json!({"return": {}}));
+        assert_eq!(
+            *vm.lock().unwrap().state.lock().unwrap(),
+            KvmVmState::Paused
+        );
+        let resp = server.dispatch("query-status", Value::Null);
+        assert_eq!(resp["return"]["status"], "paused");
+        assert_eq!(resp["return"]["running"], false);
+
+        // The VM can not be stopped again.
+        let resp = server.dispatch("stop", Value::Null);
+        assert_eq!(error_class(&resp), "GenericError");
+
+        assert_eq!(server.dispatch("cont", Value::Null), json!({"return": {}}));
+        let resp = server.dispatch("query-status", Value::Null);
+        assert_eq!(resp["return"]["status"], "running");
+
+        let resp = server.dispatch("cont", Value::Null);
+        assert_eq!(error_class(&resp), "GenericError");
+    }
+
+    #[test]
+    fn test_qmp_server_quit() {
+        let (server, vm) = create_server();
+        assert_eq!(server.dispatch("quit", Value::Null), json!({"return": {}}));
+        assert_eq!(
+            *vm.lock().unwrap().state.lock().unwrap(),
+            KvmVmState::Shutdown
+        );
+    }
+
+    #[test]
+    fn test_qmp_server_device_add_and_del() {
+        let (server, vm) = create_server();
+        let args =
+            json!({"id": "net-0", "driver": "virtio-net-pci", "bus": "pcie.0", "addr": "0x2"});
+        assert_eq!(
+            server.dispatch("device_add", args.clone()),
+            json!({"return": {}})
+        );
+        assert_eq!(vm.lock().unwrap().devices, vec!["net-0".to_string()]);
+        let resp = server.dispatch("device_add", args);
+        assert_eq!(error_class(&resp), "GenericError");
+
+        // Missing or unknown arguments.
+        let resp = server.dispatch("device_add", json!({"id": "net-1"}));
+        assert_eq!(error_class(&resp), "GenericError");
+        let resp = server.dispatch(
+            "device_add",
+            json!({"id": "net-1", "driver": "virtio-net-pci", "unknown": "1"}),
+        );
+        assert_eq!(error_class(&resp), "GenericError");
+        assert_eq!(vm.lock().unwrap().devices.len(), 1);
+
+        assert_eq!(
+            server.dispatch("device_del", json!({"id": "net-0"})),
+            json!({"return": {}})
+        );
+        assert!(vm.lock().unwrap().devices.is_empty());
+        let resp = server.dispatch("device_del", json!({"id": "net-0"}));
+        assert_eq!(error_class(&resp), "DeviceNotFound");
+        let resp = server.dispatch("device_del", Value::Null);
+        assert_eq!(error_class(&resp), "GenericError");
+    }
+
+    #[test]
+    fn test_qmp_server_device_hotplug_check() {
+        let (server, vm) = create_server();
+        let error_desc = |resp: &Value| resp["error"]["desc"].as_str().unwrap().to_string();
+
+        // Unknown driver.
+        let resp = server.dispatch("device_add", json!({"id": "gpu-0", "driver": "virtio-gpu"}));
+        assert_eq!(
+            error_desc(&resp),
+            "Failed to add device: Driver virtio-gpu is not support"
+        );
+
+        // Hotplug is allowed in paused VM.
+        assert_eq!(server.dispatch("stop", Value::Null), json!({"return": {}}));
+        let args = json!({"id": "blk-0", "driver": "virtio-blk-pci"});
+        assert_eq!(
+            server.dispatch("device_add", args.clone()),
+            json!({"return": {}})
+        );
+
+        // Hotplug is not allowed before VM starts or after VM shuts down.
+        for state in [KvmVmState::Created, KvmVmState::Shutdown] {
+            *vm.lock().unwrap().state.lock().unwrap() = state;
+            let args = json!({"id": "net-0", "driver": "virtio-net-pci"});
+            let resp = server.dispatch("device_add", args);
+            assert_eq!(
+                error_desc(&resp),
+                format!("{} {:?}", HOTPLUG_NOT_ALLOWED, state)
+            );
+        }
+        assert_eq!(vm.lock().unwrap().devices, vec!["blk-0".to_string()]);
+    }
+
+    #[test]
+    fn test_hotplug_check() {
+        // The check shared by `device_add` of the machines.
+        for state in [KvmVmState::Running, KvmVmState::Paused] {
+            assert!(hotplug_check(&Mutex::new(state)).is_none());
+        }
+        for state in [
+            KvmVmState::Created,
+            KvmVmState::InMigrating,
+            KvmVmState::Migrated,
+            KvmVmState::Shutdown,
+        ] {
+            let resp = serde_json::to_value(hotplug_check(&Mutex::new(state)).unwrap()).unwrap();
+            assert_eq!(error_class(&resp), "GenericError");
+            assert_eq!(
+                resp["error"]["desc"],
+                format!("{} {:?}", HOTPLUG_NOT_ALLOWED, state)
+            );
+        }
+    }
+
+    #[test]
+    fn test_qmp_server_blockdev_add() {
+        let (server, vm) = create_server();
+        let args = json!({
+            "node-name": "drive-0",
+            "file": {"driver": "file", "filename": "/path/to/block"},
+            "cache": {"direct": true},
+            "read-only": false
+        });
+        assert_eq!(server.dispatch("blockdev-add", args), json!({"return": {}}));
+        assert_eq!(
+            *vm.lock().unwrap().blocks.lock().unwrap(),
+            vec!["drive-0".to_string()]
+        );
+
+        let resp = server.dispatch("blockdev-add", json!({"node-name": "drive-1"}));
+        assert_eq!(error_class(&resp), "GenericError");
+    }
+
+    #[test]
+    fn test_qmp_server_command_not_found() {
+        let (server, _) = create_server();
+        for cmd in ["blockdev_add", "unknown", ""] {
+            let resp = server.dispatch(cmd, Value::Null);
+            assert_eq!(error_class(&resp), "CommandNotFound");
+        }
+    }
+
+    #[test]
+    fn test_qmp_server_execute() {
+        let (server, vm) = create_server();
+        let command = |request: Value| -> QmpCommand { serde_json::from_value(request).unwrap() };
+
+        // The commands decoded from socket are executed as `dispatch` does.
+        let (resp, shutdown) = server.execute(command(json!({"execute": "stop"})), None);
+        assert_eq!(resp, r#"{"return":{}}"#);
+        assert!(!shutdown);
+        assert_eq!(
+            *vm.lock().unwrap().state.lock().unwrap(),
+            KvmVmState::Paused
+        );
+        let (resp, _) = server.execute(command(json!({"execute": "query-cpus"})), None);
+        let resp: Value = serde_json::from_str(&resp).unwrap();
+        assert_eq!(resp, server.dispatch("query-cpus", Value::Null));
+
+        // The caller exits the process when the VM is shut down by `quit`.
+        let (resp, shutdown) = server.execute(command(json!({"execute": "quit"})), None);
+        assert_eq!(resp, r#"{"return":{}}"#);
+        assert!(shutdown);
+    }
+}
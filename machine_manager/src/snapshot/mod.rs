@@ -0,0 +1,465 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! This module saves the VM to a snapshot directory and restores it.
+//!
+//! The snapshot directory is laid out as:
+//!
+//! ```text
+//! <path>/
+//!   cpus.json      - CPU states, serialized from `Vec<CpuState>`.
+//!   devices.json   - Device states, serialized from `Vec<DeviceState>`.
+//!   memory         - Raw dump of guest memory, the zero pages are holes.
+//!   memory.bitmap  - Delta-encoded bitmap of the dirty pages in `memory`.
+//! ```
+//!
+//! All the pages of guest memory are saved, a page is dirty if it is not
+//! filled with zero. The bitmap file starts with the page size and the number
+//! of pages, each as u64 in little endian. Then follow the lengths of the
+//! alternate clean and dirty page runs, starting with a clean run, each as u64
+//! in little endian.
+
+use std::fs::{create_dir_all, File};
+use std::io::{repeat, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use util::unix::host_page_size;
+
+/// File of CPU states in snapshot directory.
+const CPU_STATE_FILE: &str = "cpus.json";
+/// File of device states in snapshot directory.
+const DEVICE_STATE_FILE: &str = "devices.json";
+/// File of memory dump in snapshot directory.
+const MEMORY_FILE: &str = "memory";
+/// File of dirty page bitmap in snapshot directory.
+const MEMORY_BITMAP_FILE: &str = "memory.bitmap";
+
+/// State of one vcpu.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuState {
+    /// Index of the vcpu.
+    pub id: u8,
+    /// Migration state of the vcpu, which is the bytes of `X86CPUState` or
+    /// `ArmCPUState`.
+    pub state: Vec<u8>,
+}
+
+/// State of one device.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceState {
+    /// Instance id of the device in migration.
+    pub id: u64,
+    /// Migration state of the device.
+    pub state: Vec<u8>,
+}
+
+/// The VM which is saved to and restored from snapshot.
+pub trait SnapshotVm: Send {
+    /// Get states of all the vcpus.
+    fn cpu_states(&self) -> Result<Vec<CpuState>>;
+
+    /// Set states of vcpus.
+    fn restore_cpu_states(&self, states: Vec<CpuState>) -> Result<()>;
+
+    /// Get states of all the devices.
+    fn device_states(&self) -> Result<Vec<DeviceState>>;
+
+    /// Set states of devices.
+    fn restore_device_states(&self, states: Vec<DeviceState>) -> Result<()>;
+
+    /// Size of guest memory in bytes.
+    fn memory_size(&self) -> u64;
+
+    /// Read guest memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `dst` - Destination of the data.
+    /// * `offset` - Offset in guest memory.
+    /// * `len` - Length of the data.
+    fn read_memory(&self, dst: &mut dyn Write, offset: u64, len: u64) -> Result<()>;
+
+    /// Write guest memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `src` - Source of the data.
+    /// * `offset` - Offset in guest memory.
+    /// * `len` - Length of the data.
+    fn write_memory(&self, src: &mut dyn Read, offset: u64, len: u64) -> Result<()>;
+}
+
+/// Manager which saves the VM to snapshot and restores it.
+pub struct SnapshotManager {
+    vm: Arc<Mutex<dyn SnapshotVm>>,
+}
+
+impl SnapshotManager {
+    /// Constructs a `SnapshotManager`.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm` - The VM to be saved or restored.
+    pub fn new(vm: Arc<Mutex<dyn SnapshotVm>>) -> Self {
+        SnapshotManager { vm }
+    }
+
+    /// Save the VM to snapshot directory, which is created if it does not exist.
+    /// The VM should be paused before it is saved.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of snapshot directory.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        create_dir_all(path)
+            .with_context(|| format!("Failed to create snapshot dir {:?}", path))?;
+
+        let vm = self.vm.lock().unwrap();
+        let cpu_states = vm.cpu_states()?;
+        save_json(&path.join(CPU_STATE_FILE), &cpu_states)?;
+        let device_states = vm.device_states()?;
+        save_json(&path.join(DEVICE_STATE_FILE), &device_states)?;
+        save_memory(&*vm, path).with_context(|| "Failed to save snapshot memory")
+    }
+
+    /// Restore the VM from snapshot directory. The memory is restored first,
+    /// then the CPU states and the device states.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of snapshot directory.
+    pub fn restore(&self, path: &Path) -> Result<()> {
+        if !path.is_dir() {
+            bail!("Invalid snapshot dir {:?}", path);
+        }
+        let cpu_states: Vec<CpuState> = load_json(&path.join(CPU_STATE_FILE))?;
+        let device_states: Vec<DeviceState> = load_json(&path.join(DEVICE_STATE_FILE))?;
+
+        let vm = self.vm.lock().unwrap();
+        restore_memory(&*vm, path).with_context(|| "Failed to restore snapshot memory")?;
+        vm.restore_cpu_states(cpu_states)
+            .with_context(|| "Failed to restore CPU states")?;
+        vm.restore_device_states(device_states)
+            .with_context(|| "Failed to restore device states")
+    }
+}
+
+/// Save all the pages of guest memory, the zero pages are left as holes.
+fn save_memory(vm: &dyn SnapshotVm, path: &Path) -> Result<()> {
+    let page_size = host_page_size();
+    let size = vm.memory_size();
+    let pages = size.div_ceil(page_size);
+    let mut bitmap = vec![0_u64; pages.div_ceil(64) as usize];
+
+    let mut memory_file = File::create(path.join(MEMORY_FILE))?;
+    memory_file.set_len(size)?;
+    // Read 64 pages at a time, which are one word of bitmap.
+    let mut buf = Vec::with_capacity((page_size * 64) as usize);
+    for (index, word) in bitmap.iter_mut().enumerate() {
+        let offset = index as u64 * 64 * page_size;
+        let len = std::cmp::min(64 * page_size, size - offset);
+        buf.clear();
+        vm.read_memory(&mut buf, offset, len)?;
+        for (bit, data) in buf.chunks(page_size as usize).enumerate() {
+            if data.iter().all(|b| *b == 0) {
+                continue;
+            }
+            *word |= 1 << bit;
+            memory_file.seek(SeekFrom::Start(offset + bit as u64 * page_size))?;
+            memory_file.write_all(data)?;
+        }
+    }
+    memory_file.sync_all()?;
+
+    let mut bitmap_file = File::create(path.join(MEMORY_BITMAP_FILE))?;
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&page_size.to_le_bytes());
+    buf.extend_from_slice(&pages.to_le_bytes());
+    for run in encode_dirty_bitmap(&bitmap, pages) {
+        buf.extend_from_slice(&run.to_le_bytes());
+    }
+    bitmap_file.write_all(&buf)?;
+    bitmap_file.sync_all()?;
+    Ok(())
+}
+
+/// Restore all the pages of guest memory, the clean pages are filled with zero.
+fn restore_memory(vm: &dyn SnapshotVm, path: &Path) -> Result<()> {
+    let mut buf = Vec::new();
+    File::open(path.join(MEMORY_BITMAP_FILE))?.read_to_end(&mut buf)?;
+    if buf.len() < 16 || buf.len() % 8 != 0 {
+        bail!("Invalid dirty bitmap file");
+    }
+    let values: Vec<u64> = buf
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect();
+    let (page_size, pages) = (values[0], values[1]);
+
+    let size = vm.memory_size();
+    if page_size == 0 || pages != size.div_ceil(page_size) {
+        bail!(
+            "Snapshot of {} pages of size {} does not match guest memory size {}",
+            pages,
+            page_size,
+            size
+        );
+    }
+    let mut memory_file = File::open(path.join(MEMORY_FILE))?;
+    if memory_file.metadata()?.len() != size {
+        bail!("Memory dump does not match guest memory size {}", size);
+    }
+
+    let mut next = 0;
+    for (start, len) in decode_dirty_runs(&values[2..], pages)? {
+        let offset = start * page_size;
+        let len = std::cmp::min(len * page_size, size - offset);
+        vm.write_memory(&mut repeat(0), next, offset - next)?;
+        memory_file.seek(SeekFrom::Start(offset))?;
+        vm.write_memory(&mut (&mut memory_file).take(len), offset, len)?;
+        next = offset + len;
+    }
+    vm.write_memory(&mut repeat(0), next, size - next)?;
+    Ok(())
+}
+
+fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+    serde_json::to_writer(&file, value).with_context(|| format!("Failed to write {:?}", path))?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn load_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    serde_json::from_reader(file).with_context(|| format!("Failed to parse {:?}", path))
+}
+
+/// Get the ranges of dirty pages in bitmap, as (first page, number of pages).
+fn dirty_ranges(bitmap: &[u64], pages: u64) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for page in 0..pages {
+        if bitmap[(page / 64) as usize] & (1 << (page % 64)) == 0 {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some((start, len)) if *start + *len == page => *len += 1,
+            _ => ranges.push((page, 1)),
+        }
+    }
+    ranges
+}
+
+/// Encode the dirty bitmap as the lengths of alternate clean and dirty runs,
+/// starting with a clean run.
+fn encode_dirty_bitmap(bitmap: &[u64], pages: u64) -> Vec<u64> {
+    let mut runs = Vec::new();
+    let mut next = 0;
+    for (start, len) in dirty_ranges(bitmap, pages) {
+        runs.push(start - next);
+        runs.push(len);
+        next = start + len;
+    }
+    runs
+}
+
+/// Decode the runs encoded by `encode_dirty_bitmap` to the ranges of dirty
+/// pages, as (first page, number of pages).
+fn decode_dirty_runs(runs: &[u64], pages: u64) -> Result<Vec<(u64, u64)>> {
+    let chunks = runs.chunks_exact(2);
+    if !chunks.remainder().is_empty() {
+        bail!("Dirty page runs are not paired");
+    }
+    let mut ranges = Vec::new();
+    let mut next: u64 = 0;
+    for run in chunks {
+        let start = next.checked_add(run[0]);
+        let end = start.and_then(|start| start.checked_add(run[1]));
+        match (start, end) {
+            (Some(start), Some(end)) if end <= pages => {
+                ranges.push((start, run[1]));
+                next = end;
+            }
+            _ => bail!("Dirty page runs exceed {} pages", pages),
+        }
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_state_serialize() {
+        let state = CpuState {
+            id: 3,
+            state: vec![1, 2, 255],
+        };
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(json, r#"{"id":3,"state":[1,2,255]}"#);
+        assert_eq!(serde_json::from_str::<CpuState>(&json).unwrap(), state);
+
+        let states = vec![state.clone(), CpuState::default()];
+        let json = serde_json::to_vec(&states).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Vec<CpuState>>(&json).unwrap(),
+            states
+        );
+
+        // Missing field, wrong type and value out of range.
+        assert!(serde_json::from_str::<CpuState>(r#"{"id":0}"#).is_err());
+        assert!(serde_json::from_str::<CpuState>(r#"{"id":0,"state":"0"}"#).is_err());
+        assert!(serde_json::from_str::<CpuState>(r#"{"id":256,"state":[]}"#).is_err());
+        assert!(serde_json::from_str::<CpuState>(r#"{"id":0,"state":[256]}"#).is_err());
+    }
+
+    #[test]
+    fn test_dirty_bitmap_encode() {
+        let bitmap = [0b1100_0111, 1 << 63];
+        assert_eq!(dirty_ranges(&bitmap, 128), vec![(0, 3), (6, 2), (127, 1)]);
+        let runs = encode_dirty_bitmap(&bitmap, 128);
+        assert_eq!(runs, vec![0, 3, 3, 2, 119, 1]);
+        assert_eq!(
+            decode_dirty_runs(&runs, 128).unwrap(),
+            dirty_ranges(&bitmap, 128)
+        );
+        // The pages out of range are ignored.
+        assert_eq!(encode_dirty_bitmap(&bitmap, 100), vec![0, 3, 3, 2]);
+        assert!(encode_dirty_bitmap(&[0, 0], 128).is_empty());
+
+        assert!(decode_dirty_runs(&[0, 3, 3], 128).is_err());
+        assert!(decode_dirty_runs(&[0, 3, 3, 2, 119, 1], 127).is_err());
+        assert!(decode_dirty_runs(&[u64::MAX, 2], 128).is_err());
+    }
+
+    /// Fake VM which keeps the states and memory in buffers.
+    struct TestVm {
+        cpus: Mutex<Vec<CpuState>>,
+        devices: Mutex<Vec<DeviceState>>,
+        memory: Mutex<Vec<u8>>,
+    }
+
+    impl TestVm {
+        fn new(pages: usize) -> Self {
+            TestVm {
+                cpus: Mutex::new(Vec::new()),
+                devices: Mutex::new(Vec::new()),
+                memory: Mutex::new(vec![0; pages * host_page_size() as usize]),
+            }
+        }
+    }
+
+    impl SnapshotVm for TestVm {
+        fn cpu_states(&self) -> Result<Vec<CpuState>> {
+            Ok(self.cpus.lock().unwrap().clone())
+        }
+
+        fn restore_cpu_states(&self, states: Vec<CpuState>) -> Result<()> {
+            *self.cpus.lock().unwrap() = states;
+            Ok(())
+        }
+
+        fn device_states(&self) -> Result<Vec<DeviceState>> {
+            Ok(self.devices.lock().unwrap().clone())
+        }
+
+        fn restore_device_states(&self, states: Vec<DeviceState>) -> Result<()> {
+            *self.devices.lock().unwrap() = states;
+            Ok(())
+        }
+
+        fn memory_size(&self) -> u64 {
+            self.memory.lock().unwrap().len() as u64
+        }
+
+        fn read_memory(&self, dst: &mut dyn Write, offset: u64, len: u64) -> Result<()> {
+            let memory = self.memory.lock().unwrap();
+            dst.write_all(&memory[offset as usize..(offset + len) as usize])?;
+            Ok(())
+        }
+
+        fn write_memory(&self, src: &mut dyn Read, offset: u64, len: u64) -> Result<()> {
+            let mut memory = self.memory.lock().unwrap();
+            src.read_exact(&mut memory[offset as usize..(offset + len) as usize])?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_snapshot_save_and_restore() {
+        let page_size = host_page_size() as usize;
+        let path = std::env::temp_dir().join(format!("test_snapshot_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+
+        let vm = Arc::new(Mutex::new(TestVm::new(70)));
+        let locked_vm = vm.lock().unwrap();
+        *locked_vm.cpus.lock().unwrap() = vec![CpuState {
+            id: 0,
+            state: vec![7; 8],
+        }];
+        *locked_vm.devices.lock().unwrap() = vec![DeviceState {
+            id: u64::MAX,
+            state: vec![1, 2, 3],
+        }];
+        {
+            let mut memory = locked_vm.memory.lock().unwrap();
+            memory[1] = 0x11;
+            memory[page_size * 2 + 5] = 0x22;
+            memory[page_size * 3] = 0x33;
+            memory[page_size * 70 - 1] = 0x44;
+        }
+        drop(locked_vm);
+        SnapshotManager::new(vm.clone()).save(&path).unwrap();
+        assert_eq!(
+            std::fs::metadata(path.join(MEMORY_FILE)).unwrap().len(),
+            (page_size * 70) as u64
+        );
+        // Pages 0, 2, 3 and 69 are dirty.
+        let bitmap = std::fs::read(path.join(MEMORY_BITMAP_FILE)).unwrap();
+        assert_eq!(bitmap.len(), 8 * 8);
+        assert_eq!(bitmap[8..16], 70_u64.to_le_bytes());
+        assert_eq!(bitmap[16..24], 0_u64.to_le_bytes());
+        assert_eq!(bitmap[24..32], 1_u64.to_le_bytes());
+        assert_eq!(bitmap[48..56], 65_u64.to_le_bytes());
+
+        // The clean pages of the restored VM are filled with zero.
+        let new_vm = Arc::new(Mutex::new(TestVm::new(70)));
+        new_vm.lock().unwrap().memory.lock().unwrap()[page_size * 4] = 0x55;
+        let manager = SnapshotManager::new(new_vm.clone());
+        manager.restore(&path).unwrap();
+        let (new_vm_locked, vm_locked) = (new_vm.lock().unwrap(), vm.lock().unwrap());
+        assert_eq!(
+            *new_vm_locked.cpus.lock().unwrap(),
+            *vm_locked.cpus.lock().unwrap()
+        );
+        assert_eq!(
+            *new_vm_locked.devices.lock().unwrap(),
+            *vm_locked.devices.lock().unwrap()
+        );
+        assert!(*new_vm_locked.memory.lock().unwrap() == *vm_locked.memory.lock().unwrap());
+        drop(new_vm_locked);
+
+        // The guest memory size is changed.
+        let manager = SnapshotManager::new(Arc::new(Mutex::new(TestVm::new(71))));
+        assert!(manager.restore(&path).is_err());
+        // The snapshot is broken.
+        std::fs::write(path.join(CPU_STATE_FILE), "[{}]").unwrap();
+        assert!(SnapshotManager::new(new_vm).restore(&path).is_err());
+        std::fs::remove_dir_all(&path).unwrap();
+        assert!(manager.restore(&path).is_err());
+    }
+}
@@ -27,6 +27,7 @@ use serde::Deserialize;
 use vmm_sys_util::epoll::EventSet;
 
 use crate::machine::MachineExternalInterface;
+use crate::qmp::server::QmpServer;
 use crate::qmp::{QmpChannel, QmpGreeting, Response};
 use util::leak_bucket::LeakBucket;
 use util::loop_context::{
@@ -72,7 +73,7 @@ pub struct Socket {
     /// Socket stream with RwLock
     stream: RwLock<Option<SocketStream>>,
     /// Perform socket command
-    performer: Option<Arc<Mutex<dyn MachineExternalInterface>>>,
+    performer: Option<QmpServer>,
 }
 
 impl Socket {
@@ -90,7 +91,7 @@ impl Socket {
             sock_type: SocketType::Unix,
             listener,
             stream: RwLock::new(None),
-            performer,
+            performer: performer.map(QmpServer::new),
         }
     }
 
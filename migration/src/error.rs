@@ -10,7 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use crate::protocol::MigrationStatus;
+use crate::protocol::{MigrationState, MigrationStatus};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -46,6 +46,8 @@ pub enum MigrationError {
     HeaderItemNotFit(String),
     #[error("Failed to transfer migration status from {0} to {1}.")]
     InvalidStatusTransfer(MigrationStatus, MigrationStatus),
+    #[error("Failed to transfer migration state from {0} to {1}.")]
+    InvalidStateTransfer(MigrationState, MigrationState),
     #[error("Can't restore structure from raw slice: {0}")]
     FromBytesError(&'static str),
     #[error("Failed to get GIC {0} register: {1}")]
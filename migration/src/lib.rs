@@ -22,7 +22,7 @@ pub mod protocol;
 pub mod snapshot;
 
 use std::time::Duration;
-use std::{net::TcpStream, os::unix::net::UnixStream, thread};
+use std::{net::ToSocketAddrs, os::unix::net::UnixStream, thread};
 
 pub use anyhow::Result;
 use log::error;
@@ -30,7 +30,10 @@ use log::error;
 pub use error::MigrationError;
 use machine_manager::qmp::{qmp_schema, Response};
 pub use manager::{MigrationHook, MigrationManager};
-pub use protocol::{DeviceStateDesc, FieldDesc, MemBlock, MigrationStatus, StateTransfer};
+pub use protocol::{
+    DeviceStateDesc, FieldDesc, MemBlock, MigrationState, MigrationStats, MigrationStatus,
+    StateTransfer,
+};
 
 /// Start to snapshot VM.
 ///
@@ -81,9 +84,6 @@ pub fn migration_unix_mode(path: String) -> Response {
         .spawn(move || {
             if let Err(e) = MigrationManager::send_migration(&mut socket) {
                 error!("Failed to send migration: {:?}", e);
-                let _ = MigrationManager::recover_from_migration();
-                let _ = MigrationManager::set_status(MigrationStatus::Failed)
-                    .map_err(|e| error!("{:?}", e));
             }
         })
     {
@@ -102,17 +102,16 @@ pub fn migration_unix_mode(path: String) -> Response {
 ///
 /// * `path` - Tcp ip and port, as 192.168.1.1:4446.
 pub fn migration_tcp_mode(path: String) -> Response {
-    let mut socket = match TcpStream::connect(path) {
-        Ok(_sock) => {
-            // Specify the tcp receiving or send timeout.
-            let time_out = Some(Duration::from_secs(30));
-            _sock
-                .set_read_timeout(time_out)
-                .unwrap_or_else(|e| error!("{:?}", e));
-            _sock
-                .set_write_timeout(time_out)
-                .unwrap_or_else(|e| error!("{:?}", e));
-            _sock
+    let dest_addr = match path.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => addr,
+        Ok(None) => {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!(
+                    "No address is resolved for {}",
+                    path
+                )),
+                None,
+            )
         }
         Err(e) => {
             return Response::create_error_response(
@@ -122,22 +121,12 @@ pub fn migration_tcp_mode(path: String) -> Response {
         }
     };
 
-    if let Err(e) = thread::Builder::new()
-        .name("tcp_migrate".to_string())
-        .spawn(move || {
-            if let Err(e) = MigrationManager::send_migration(&mut socket) {
-                error!("Failed to send migration: {:?}", e);
-                let _ = MigrationManager::recover_from_migration();
-                let _ = MigrationManager::set_status(MigrationStatus::Failed)
-                    .map_err(|e| error!("{:?}", e));
-            }
-        })
-    {
+    if let Err(e) = MigrationManager::start_precopy(dest_addr) {
         return Response::create_error_response(
-            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
             None,
         );
-    };
+    }
 
     Response::create_empty_response()
 }
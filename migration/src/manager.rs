@@ -22,7 +22,9 @@ use once_cell::sync::Lazy;
 
 use crate::general::translate_id;
 use crate::migration::DirtyBitmap;
-use crate::protocol::{DeviceStateDesc, MemBlock, MigrationStatus, StateTransfer};
+use crate::protocol::{
+    DeviceStateDesc, MemBlock, MigrationState, MigrationStats, MigrationStatus, StateTransfer,
+};
 use anyhow::{Context, Result};
 use machine_manager::config::VmConfig;
 use machine_manager::machine::MachineLifecycle;
@@ -35,6 +37,8 @@ pub(crate) static MIGRATION_MANAGER: Lazy<MigrationManager> = Lazy::new(|| Migra
     status: Arc::new(RwLock::new(MigrationStatus::None)),
     vmm_bitmaps: Arc::new(RwLock::new(HashMap::new())),
     limit: Arc::new(RwLock::new(MigrationLimit::default())),
+    precopy_state: Arc::new(RwLock::new(MigrationState::Idle)),
+    precopy_stats: Arc::new(RwLock::new(MigrationStats::default())),
 });
 
 /// A hook for `Device` to save device state to `Write` object and load device
@@ -212,6 +216,10 @@ pub struct MigrationManager {
     pub vmm_bitmaps: Arc<RwLock<HashMap<u32, DirtyBitmap>>>,
     /// Limiting elements of migration.
     pub limit: Arc<RwLock<MigrationLimit>>,
+    /// The state of pre-copy at source VM.
+    pub precopy_state: Arc<RwLock<MigrationState>>,
+    /// The statistics of pre-copy at source VM.
+    pub precopy_stats: Arc<RwLock<MigrationStats>>,
 }
 
 impl MigrationManager {
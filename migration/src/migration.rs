@@ -13,15 +13,19 @@
 use std::collections::HashMap;
 use std::io::{Read, Write};
 use std::mem::size_of;
+use std::net::{SocketAddr, TcpStream};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
 use std::time::{Duration, Instant};
 
 use kvm_bindings::kvm_userspace_memory_region as MemorySlot;
-use log::{info, warn};
+use log::{error, info, warn};
 
 use crate::general::Lifecycle;
 use crate::manager::MIGRATION_MANAGER;
-use crate::protocol::{MemBlock, MigrationStatus, Request, Response, TransStatus};
+use crate::protocol::{
+    MemBlock, MigrationState, MigrationStats, MigrationStatus, Request, Response, TransStatus,
+};
 use crate::{MigrationError, MigrationManager};
 use anyhow::{anyhow, bail, Context, Result};
 use hypervisor::kvm::KVM_FDS;
@@ -29,6 +33,32 @@ use machine_manager::config::{get_pci_bdf, PciBdf, VmConfig};
 use util::unix::host_page_size;
 
 impl MigrationManager {
+    /// Start pre-copy live migration to the destination VM. The destination
+    /// is connected here, and the migration is sent by a new thread, whose
+    /// progress is given by `status` and `precopy_state`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dest_addr` - The tcp address of destination VM.
+    pub fn start_precopy(dest_addr: SocketAddr) -> Result<()> {
+        let mut stream = TcpStream::connect(dest_addr)
+            .with_context(|| format!("Failed to connect to migration destination {}", dest_addr))?;
+        // Specify the tcp receiving or send timeout.
+        let time_out = Some(Duration::from_secs(30));
+        stream.set_read_timeout(time_out)?;
+        stream.set_write_timeout(time_out)?;
+
+        thread::Builder::new()
+            .name("tcp_migrate".to_string())
+            .spawn(move || {
+                if let Err(e) = Self::send_migration(&mut stream) {
+                    error!("Failed to send migration: {:?}", e);
+                }
+            })
+            .with_context(|| "Failed to create migration thread")?;
+        Ok(())
+    }
+
     /// Start VM live migration at source VM.
     ///
     /// # Arguments
@@ -36,7 +66,44 @@ impl MigrationManager {
     /// * `fd` - The fd implements `Read` and `Write` trait object. it
     /// will send source VM memory data and devices state to destination VM.
     /// And, it will receive confirmation from destination VM.
+    ///
+    /// # Notes
+    ///
+    /// If migration is failed, the source VM is resumed if it has been paused.
     pub fn send_migration<T>(fd: &mut T) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        // Reset the finished pre-copy, and reject the migration in progress.
+        {
+            let mut state = MIGRATION_MANAGER.precopy_state.write().unwrap();
+            if matches!(
+                *state,
+                MigrationState::Completed | MigrationState::Failed(_)
+            ) {
+                *state = state.transfer(MigrationState::Idle)?;
+            }
+            *state = state.transfer(MigrationState::PreCopy { iteration: 0 })?;
+        }
+        *MIGRATION_MANAGER.precopy_stats.write().unwrap() = MigrationStats::default();
+
+        if let Err(e) = Self::do_send_migration(fd) {
+            // VM is paused in final stop, and it is still in charge of source.
+            if Self::precopy_state() == MigrationState::FinalStop {
+                Self::recover_from_migration()?;
+            }
+            Self::stop_dirty_log()
+                .unwrap_or_else(|e| warn!("Failed to stop logging dirty page: {:?}", e));
+            Self::set_status(MigrationStatus::Failed)
+                .unwrap_or_else(|e| warn!("Failed to set migration status: {:?}", e));
+            Self::set_precopy_state(MigrationState::Failed(format!("{:?}", e)))?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    fn do_send_migration<T>(fd: &mut T) -> Result<()>
     where
         T: Read + Write,
     {
@@ -54,12 +121,14 @@ impl MigrationManager {
 
         // Iteratively send virtual machine dirty memory.
         let iterations = MIGRATION_MANAGER.limit.read().unwrap().max_dirty_iterations;
-        for _ in 0..iterations {
+        let iterations = u8::try_from(iterations).unwrap_or(u8::MAX);
+        for iteration in 1..=iterations {
             // Check the migration is active.
             if !Self::is_active() {
                 break;
             }
 
+            Self::set_precopy_state(MigrationState::PreCopy { iteration })?;
             if !Self::iteration_send(fd)? {
                 break;
             }
@@ -69,14 +138,18 @@ impl MigrationManager {
         if Self::is_canceled() {
             // Cancel the migration of source and destination.
             Self::cancel_migration(fd).with_context(|| "Failed to cancel migration")?;
+            Self::set_precopy_state(MigrationState::Failed("canceled".to_string()))?;
             return Ok(());
         }
 
         // Pause virtual machine.
+        Self::set_precopy_state(MigrationState::FinalStop)?;
         Self::pause()?;
 
         // Send remaining virtual machine dirty memory.
-        Self::send_dirty_memory(fd).with_context(|| "Failed to send dirty memory")?;
+        let dirty_pages =
+            Self::send_dirty_memory(fd).with_context(|| "Failed to send dirty memory")?;
+        MIGRATION_MANAGER.precopy_stats.write().unwrap().dirty_pages = dirty_pages;
 
         // Stop logging dirty pages.
         Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
@@ -86,6 +159,7 @@ impl MigrationManager {
 
         // Complete the migration.
         Self::complete_migration(fd).with_context(|| "Failed to completing migration")?;
+        Self::set_precopy_state(MigrationState::Completed)?;
 
         // Destroy virtual machine.
         Self::clear_migration().with_context(|| "Failed to clear migration")?;
@@ -93,6 +167,33 @@ impl MigrationManager {
         Ok(())
     }
 
+    /// Get current pre-copy state of source VM.
+    pub fn precopy_state() -> MigrationState {
+        MIGRATION_MANAGER.precopy_state.read().unwrap().clone()
+    }
+
+    /// Get the statistics of current pre-copy of source VM.
+    pub fn precopy_stats() -> MigrationStats {
+        *MIGRATION_MANAGER.precopy_stats.read().unwrap()
+    }
+
+    /// Set a new pre-copy state for source VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `new_state`: new pre-copy state, the transform must be legal.
+    fn set_precopy_state(new_state: MigrationState) -> Result<()> {
+        let mut state = MIGRATION_MANAGER.precopy_state.write().unwrap();
+        *state = state.transfer(new_state)?;
+        info!(
+            "Migration state: {}, {:?}",
+            *state,
+            *MIGRATION_MANAGER.precopy_stats.read().unwrap()
+        );
+
+        Ok(())
+    }
+
     /// Start VM live migration at destination VM.
     ///
     /// # Arguments
@@ -285,9 +386,17 @@ impl MigrationManager {
     where
         T: Write + Read,
     {
-        let mut state =
+        let dirty_pages =
             Self::send_dirty_memory(fd).with_context(|| "Failed to send dirty memory")?;
 
+        // Stop iterating if the dirty ratio is below the threshold.
+        let mut state = {
+            let mut stats = MIGRATION_MANAGER.precopy_stats.write().unwrap();
+            stats.dirty_pages = dirty_pages;
+            stats.iterations += 1;
+            !stats.converged()
+        };
+
         // Check the virtual machine downtime.
         if MIGRATION_MANAGER
             .limit
@@ -398,18 +507,24 @@ impl MigrationManager {
                 len: slot.memory_size,
             });
         }
+        let total_pages = Self::count_pages(&blocks);
 
         Self::send_memory(fd, blocks)?;
 
+        let mut stats = MIGRATION_MANAGER.precopy_stats.write().unwrap();
+        stats.total_pages = total_pages;
+        stats.iterations = 1;
+
         Ok(())
     }
 
-    /// Send dirty memory data to destination VM.
+    /// Send dirty memory data to destination VM, return the number of dirty
+    /// pages sent.
     ///
     /// # Arguments
     ///
     /// * `fd` - The fd implements `Read` and `Write` trait object.
-    fn send_dirty_memory<T>(fd: &mut T) -> Result<bool>
+    fn send_dirty_memory<T>(fd: &mut T) -> Result<u64>
     where
         T: Read + Write,
     {
@@ -421,12 +536,26 @@ impl MigrationManager {
         }
 
         if blocks.is_empty() {
-            return Ok(false);
+            return Ok(0);
         }
+        let dirty_pages = Self::count_pages(&blocks);
 
         Self::send_memory(fd, blocks)?;
 
-        Ok(true)
+        Ok(dirty_pages)
+    }
+
+    /// Count the pages of memory blocks.
+    ///
+    /// # Arguments
+    ///
+    /// * `blocks` - The memory blocks.
+    fn count_pages(blocks: &[MemBlock]) -> u64 {
+        let page_size = host_page_size();
+        blocks
+            .iter()
+            .map(|block| (block.len + page_size - 1) / page_size)
+            .sum()
     }
 
     /// Send VM state data to destination VM.
@@ -686,30 +815,36 @@ pub trait Migratable {
     ///
     /// * `slot` - The memory slot.
     fn get_dirty_log(slot: &MemorySlot) -> Result<Vec<MemBlock>> {
+        let dirty_bitmap = Self::get_dirty_bitmap(slot)?;
+
+        // Convert dirty bitmaps to memory blocks.
+        Ok(Self::sync_dirty_bitmap(dirty_bitmap, slot.guest_phys_addr))
+    }
+
+    /// Get and clear the dirty bitmap of the memory slot, which merges the
+    /// dirty log in kvm and vmm, one bit per page.
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - The memory slot.
+    fn get_dirty_bitmap(slot: &MemorySlot) -> Result<Vec<u64>> {
         // Get dirty memory from vmm.
-        let mut vmm_dirty_bitmap = Vec::new();
-        let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.write().unwrap();
-        for (_, map) in bitmaps.iter() {
-            if (slot.guest_phys_addr == map.gpa) && (slot.memory_size == map.len) {
-                vmm_dirty_bitmap = map.get_and_clear_dirty();
-            }
-        }
+        let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.read().unwrap();
+        let vmm_dirty_bitmap = bitmaps
+            .values()
+            .find(|map| (slot.guest_phys_addr == map.gpa) && (slot.memory_size == map.len))
+            .with_context(|| format!("Dirty log is not started for memory slot {}", slot.slot))?
+            .get_and_clear_dirty();
 
         // Get dirty memory from kvm.
-        let vm_dirty_bitmap = KVM_FDS
-            .load()
-            .get_dirty_log(slot.slot, slot.memory_size)
-            .unwrap();
+        let vm_dirty_bitmap = KVM_FDS.load().get_dirty_log(slot.slot, slot.memory_size)?;
 
         // Merge dirty bitmap.
-        let dirty_bitmap: Vec<u64> = vm_dirty_bitmap
+        Ok(vm_dirty_bitmap
             .iter()
             .zip(vmm_dirty_bitmap.iter())
             .map(|(x, y)| x | y)
-            .collect();
-
-        // Convert dirty bitmaps to memory blocks.
-        Ok(Self::sync_dirty_bitmap(dirty_bitmap, slot.guest_phys_addr))
+            .collect())
     }
 
     /// mark the dirty log into vmm.
@@ -719,11 +854,8 @@ pub trait Migratable {
     /// * `addr` - Start address of dirty memory.
     /// * `len` - Length of dirty memory.
     fn mark_dirty_log(addr: u64, len: u64) {
-        if !MigrationManager::is_active() {
-            return;
-        }
-
-        let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.write().unwrap();
+        // The bitmaps are empty if the dirty log is not started.
+        let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.read().unwrap();
         for (_, map) in bitmaps.iter() {
             if (addr >= map.hva) && ((addr + len) <= (map.hva + map.len)) {
                 map.mark_bitmap(addr - map.hva + map.gpa, len);
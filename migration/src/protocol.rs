@@ -109,6 +109,98 @@ impl MigrationStatus {
     }
 }
 
+/// The dirty memory is sent iteratively, until the percentage of dirty pages
+/// in one iteration is below this threshold.
+pub const DIRTY_RATIO_THRESHOLD: u64 = 5;
+
+/// This state for pre-copy of source VM in migration process.
+///
+/// # Notes
+///
+/// `MigrationStatus` is authoritative for the status of migration, which is
+/// reported by `query-migrate` and checked by both source and destination VM.
+/// This state only records the progress of pre-copy at source VM, it becomes
+/// `Completed` or `Failed` after `MigrationStatus` does, and the canceled
+/// migration is `Failed("canceled")`.
+///
+/// State transfer:
+/// Idle -----------> PreCopy(0): send the whole memory.
+/// PreCopy(n) -----> PreCopy(n + 1): send the dirty memory iteratively.
+/// PreCopy(n) -----> FinalStop: pause VM and send the remaining dirty memory.
+/// FinalStop ------> Completed: migration is successful.
+/// Completed ------> Idle: make migration become ready again.
+/// Failed ---------> Idle: make migration become ready again.
+/// Any but Idle ---> Failed: something wrong in migration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MigrationState {
+    /// No pre-copy is in progress.
+    Idle,
+    /// Memory is sent while VM is running, iteration 0 sends the whole memory.
+    PreCopy { iteration: u8 },
+    /// VM is paused and the remaining dirty memory is sent.
+    FinalStop,
+    /// Pre-copy completed.
+    Completed,
+    /// Pre-copy failed with the reason.
+    Failed(String),
+}
+
+impl std::fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationState::Idle => write!(f, "idle"),
+            MigrationState::PreCopy { iteration } => write!(f, "pre-copy({})", iteration),
+            MigrationState::FinalStop => write!(f, "final-stop"),
+            MigrationState::Completed => write!(f, "completed"),
+            MigrationState::Failed(reason) => write!(f, "failed({})", reason),
+        }
+    }
+}
+
+impl MigrationState {
+    // Check and transfer pre-copy state.
+    pub fn transfer(&self, new_state: MigrationState) -> Result<MigrationState> {
+        let allowed = match (self, &new_state) {
+            (_, MigrationState::Failed(_)) => *self != MigrationState::Idle,
+            (MigrationState::Idle, MigrationState::PreCopy { iteration }) => *iteration == 0,
+            (MigrationState::PreCopy { iteration: old }, MigrationState::PreCopy { iteration }) => {
+                old.checked_add(1) == Some(*iteration)
+            }
+            (MigrationState::PreCopy { .. }, MigrationState::FinalStop) => true,
+            (MigrationState::FinalStop, MigrationState::Completed) => true,
+            (MigrationState::Completed, MigrationState::Idle)
+            | (MigrationState::Failed(_), MigrationState::Idle) => true,
+            _ => false,
+        };
+        if !allowed {
+            return Err(anyhow!(MigrationError::InvalidStateTransfer(
+                self.clone(),
+                new_state
+            )));
+        }
+
+        Ok(new_state)
+    }
+}
+
+/// Statistics of pre-copy in migration process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MigrationStats {
+    /// Number of pages of VM memory.
+    pub total_pages: u64,
+    /// Number of dirty pages sent in the latest iteration.
+    pub dirty_pages: u64,
+    /// Number of finished iterations, including the one sending the whole memory.
+    pub iterations: u64,
+}
+
+impl MigrationStats {
+    /// Check whether the dirty ratio of the latest iteration is below the threshold.
+    pub fn converged(&self) -> bool {
+        self.dirty_pages == 0 || self.dirty_pages * 100 < self.total_pages * DIRTY_RATIO_THRESHOLD
+    }
+}
+
 /// Structure defines the transmission protocol between the source with destination VM.
 #[repr(u16)]
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -882,6 +974,86 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_precopy_state_transfer() {
+        let mut state = MigrationState::Idle;
+
+        // Idle to PreCopy, iteration 0 sends the whole memory.
+        assert!(state
+            .transfer(MigrationState::PreCopy { iteration: 1 })
+            .is_err());
+        state = state
+            .transfer(MigrationState::PreCopy { iteration: 0 })
+            .unwrap();
+
+        // The iterations can't be skipped.
+        state = state
+            .transfer(MigrationState::PreCopy { iteration: 1 })
+            .unwrap();
+        assert!(state
+            .transfer(MigrationState::PreCopy { iteration: 3 })
+            .is_err());
+        assert!(state.transfer(MigrationState::Completed).is_err());
+        assert!(state.transfer(MigrationState::Idle).is_err());
+
+        // PreCopy to FinalStop to Completed.
+        state = state.transfer(MigrationState::FinalStop).unwrap();
+        state = state.transfer(MigrationState::Completed).unwrap();
+        assert!(state.transfer(MigrationState::FinalStop).is_err());
+
+        // Completed to Idle.
+        state = state.transfer(MigrationState::Idle).unwrap();
+        assert!(state.transfer(MigrationState::FinalStop).is_err());
+        if let Err(e) = state.transfer(MigrationState::Failed("error".to_string())) {
+            assert_eq!(
+                e.to_string(),
+                "Failed to transfer migration state from idle to failed(error)."
+            );
+        } else {
+            panic!("Idle can't be failed");
+        }
+
+        // Any but Idle to Failed, then Failed to Idle.
+        for state in [
+            MigrationState::PreCopy { iteration: 5 },
+            MigrationState::FinalStop,
+            MigrationState::Completed,
+        ] {
+            let state = state
+                .transfer(MigrationState::Failed("error".to_string()))
+                .unwrap();
+            assert!(state
+                .transfer(MigrationState::PreCopy { iteration: 0 })
+                .is_err());
+            assert_eq!(
+                state.transfer(MigrationState::Idle).unwrap(),
+                MigrationState::Idle
+            );
+        }
+
+        let state = MigrationState::PreCopy { iteration: u8::MAX };
+        assert!(state
+            .transfer(MigrationState::PreCopy { iteration: 0 })
+            .is_err());
+    }
+
+    #[test]
+    fn test_precopy_stats_converged() {
+        let mut stats = MigrationStats {
+            total_pages: 1000,
+            dirty_pages: 50,
+            iterations: 2,
+        };
+        assert!(!stats.converged());
+        stats.dirty_pages = 49;
+        assert!(stats.converged());
+
+        stats.total_pages = 0;
+        assert!(!stats.converged());
+        stats.dirty_pages = 0;
+        assert!(stats.converged());
+    }
+
     #[test]
     fn test_desc_basic_padding() {
         /*
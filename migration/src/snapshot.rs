@@ -11,7 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use crate::general::{translate_id, Lifecycle};
-use crate::manager::{MigrationManager, MIGRATION_MANAGER};
+use crate::manager::{MigrationManager, Vmm, MIGRATION_MANAGER};
 use crate::protocol::{DeviceStateDesc, FileFormat, MigrationStatus, HEADER_LENGTH};
 use crate::MigrationError;
 use anyhow::{anyhow, bail, Context, Result};
@@ -302,4 +302,97 @@ impl MigrationManager {
 
         Ok(())
     }
+
+    /// Get the states of transports and devices, including the kvm device on
+    /// x86_64 and the GIC devices on aarch64, as (instance id, state). The
+    /// CPU states are not included.
+    pub fn get_device_states() -> Result<Vec<(u64, Vec<u8>)>> {
+        let locked_vmm = MIGRATION_MANAGER.vmm.read().unwrap();
+        let mut states = Vec::new();
+        for (id, transport) in locked_vmm.transports.iter() {
+            let state = transport
+                .lock()
+                .unwrap()
+                .get_state_vec()
+                .with_context(|| "Failed to get transport state")?;
+            states.push((*id, state));
+        }
+
+        for (id, device) in locked_vmm.devices.iter() {
+            let state = device
+                .lock()
+                .unwrap()
+                .get_state_vec()
+                .with_context(|| "Failed to get device state")?;
+            states.push((*id, state));
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if let Some(kvm) = &locked_vmm.kvm {
+            let state = kvm
+                .get_state_vec()
+                .with_context(|| "Failed to get kvm state")?;
+            states.push((translate_id(KVM_SNAPSHOT_ID), state));
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        for (id, gic) in locked_vmm.gic_group.iter() {
+            let state = gic
+                .get_state_vec()
+                .with_context(|| "Failed to get gic state")?;
+            states.push((*id, state));
+        }
+
+        Ok(states)
+    }
+
+    /// Restore the states got by `get_device_states`, then resume the devices.
+    ///
+    /// # Arguments
+    ///
+    /// * `states` - The device states, as (instance id, state).
+    pub fn set_device_states(states: &[(u64, Vec<u8>)]) -> Result<()> {
+        {
+            let locked_vmm = MIGRATION_MANAGER.vmm.read().unwrap();
+            for (id, state) in states.iter() {
+                if let Some(transport) = locked_vmm.transports.get(id) {
+                    transport
+                        .lock()
+                        .unwrap()
+                        .restore_mut_device(state)
+                        .with_context(|| "Failed to restore transport state")?;
+                } else if let Some(device) = locked_vmm.devices.get(id) {
+                    device
+                        .lock()
+                        .unwrap()
+                        .restore_mut_device(state)
+                        .with_context(|| "Failed to restore device state")?;
+                } else {
+                    Self::set_arch_device_state(&locked_vmm, *id, state)?;
+                }
+            }
+        }
+
+        Self::resume()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn set_arch_device_state(vmm: &Vmm, id: u64, state: &[u8]) -> Result<()> {
+        match &vmm.kvm {
+            Some(kvm) if id == translate_id(KVM_SNAPSHOT_ID) => kvm
+                .restore_device(state)
+                .with_context(|| "Failed to restore kvm state"),
+            _ => bail!("Unknown device instance {}", id),
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn set_arch_device_state(vmm: &Vmm, id: u64, state: &[u8]) -> Result<()> {
+        match vmm.gic_group.get(&id) {
+            Some(gic) => gic
+                .restore_device(state)
+                .with_context(|| "Failed to restore gic state"),
+            None => bail!("Unknown device instance {}", id),
+        }
+    }
 }
@@ -18,10 +18,11 @@ use anyhow::{bail, Context, Result};
 use log::{error, info};
 use machine::{LightMachine, MachineOps, StdMachine};
 use machine_manager::{
-    cmdline::{check_api_channel, create_args_parser, create_vmconfig},
+    cmdline::{check_api_channel, check_hmp_channel, create_args_parser, create_vmconfig},
     config::MachineType,
     config::VmConfig,
     event_loop::EventLoop,
+    hmp::server::HmpSocket,
     qmp::QmpChannel,
     signal_handler::{exit_with_code, register_kill_signal, VM_EXIT_GENE_ERR},
     socket::Socket,
@@ -177,7 +178,9 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
     register_kill_signal();
 
     let listeners = check_api_channel(cmd_args, vm_config)?;
+    let hmp_listener = check_hmp_channel(cmd_args)?;
     let mut sockets = Vec::new();
+    let hmp_socket;
     let vm: Arc<Mutex<dyn MachineOps + Send + Sync>> = match vm_config.machine_config.mach_type {
         MachineType::MicroVm => {
             if is_test_enabled() {
@@ -192,6 +195,7 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             for listener in listeners {
                 sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
             }
+            hmp_socket = hmp_listener.map(|listener| HmpSocket::new(listener, vm.clone()));
             vm
         }
         MachineType::StandardVm => {
@@ -217,6 +221,7 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             for listener in listeners {
                 sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
             }
+            hmp_socket = hmp_listener.map(|listener| HmpSocket::new(listener, vm.clone()));
             vm
         }
         MachineType::None => {
@@ -231,6 +236,7 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             for listener in listeners {
                 sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
             }
+            hmp_socket = hmp_listener.map(|listener| HmpSocket::new(listener, vm.clone()));
             vm
         }
     };
@@ -242,6 +248,13 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
         )
         .with_context(|| "Failed to add api event to MainLoop")?;
     }
+    if let Some(socket) = hmp_socket {
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(socket))),
+            None,
+        )
+        .with_context(|| "Failed to add hmp event to MainLoop")?;
+    }
 
     machine::vm_run(&vm, cmd_args).with_context(|| "Failed to start VM.")?;
 
@@ -18,7 +18,7 @@ use std::{
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use log::error;
 use once_cell::sync::Lazy;
 
@@ -35,6 +35,8 @@ static CONSOLES: Lazy<Arc<Mutex<ConsoleList>>> =
     Lazy::new(|| Arc::new(Mutex::new(ConsoleList::new())));
 static DISPLAY_STATE: Lazy<Arc<Mutex<DisplayState>>> =
     Lazy::new(|| Arc::new(Mutex::new(DisplayState::new())));
+static CLIPBOARD_NOTIFIERS: Lazy<Mutex<Vec<ClipboardNotifier>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
 
 /// Width of font.
 const FONT_WIDTH: i32 = 8;
@@ -155,6 +157,10 @@ pub trait DisplayChangeListenerOperations {
     fn dpy_set_major(&self) -> Result<()> {
         Ok(())
     }
+    /// Update the text of guest clipboard.
+    fn dpy_clipboard_update(&self, _text: &str) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Callback functions registered by graphic hardware.
@@ -163,6 +169,10 @@ pub trait HardWareOperations {
     fn hw_update(&self, _con: Arc<Mutex<DisplayConsole>>) {}
     /// Ui configuration changed.
     fn hw_ui_info(&self, _con: Arc<Mutex<DisplayConsole>>, _width: u32, _height: u32) {}
+    /// Whether the resolution can be changed by `hw_ui_info`.
+    fn hw_ui_resizable(&self) -> bool {
+        false
+    }
 }
 
 /// Listen to the change of image and call the related
@@ -494,6 +504,51 @@ pub fn display_cursor_define(
     Ok(())
 }
 
+/// Propagate the text of guest clipboard to all the displays, such as the
+/// clipboard pushed by guest agent.
+///
+/// # Arguments
+///
+/// * `text` - text of guest clipboard.
+pub fn display_clipboard_update(text: &str) -> Result<()> {
+    let listeners: Vec<Arc<Mutex<DisplayChangeListener>>> = DISPLAY_STATE
+        .lock()
+        .unwrap()
+        .listeners
+        .iter()
+        .flatten()
+        .cloned()
+        .collect();
+
+    for dcl in listeners.iter() {
+        let dcl_opts = dcl.lock().unwrap().dpy_opts.clone();
+        (*dcl_opts).dpy_clipboard_update(text)?;
+    }
+    Ok(())
+}
+
+/// Notifier called with the clipboard text received from display clients.
+pub type ClipboardNotifier = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Register notifier of the clipboard text from display clients, such as
+/// the guest agent which pastes the text into guest.
+pub fn register_clipboard_notifier(notifier: ClipboardNotifier) {
+    CLIPBOARD_NOTIFIERS.lock().unwrap().push(notifier);
+}
+
+/// Propagate the clipboard text received from display clients to the
+/// registered notifiers.
+///
+/// # Arguments
+///
+/// * `text` - text of client clipboard.
+pub fn display_clipboard_receive(text: &str) {
+    let notifiers = CLIPBOARD_NOTIFIERS.lock().unwrap().clone();
+    for notifier in notifiers {
+        notifier(text);
+    }
+}
+
 /// Set specific screen as the main display screen.
 pub fn display_set_major_screen(dev_name: &str) -> Result<()> {
     let con = match CONSOLES
@@ -550,6 +605,25 @@ pub fn graphic_hardware_ui_info(
     Ok(())
 }
 
+/// Request the graphic hardware of console to change the resolution, which
+/// is refused if the hardware does not support it.
+///
+/// # Arguments
+///
+/// * `con_id` - Id of console, the active console is used if it is `None`.
+/// * `width` - Width of the resolution.
+/// * `height` - Height of the resolution.
+pub fn graphic_hardware_resize(con_id: Option<usize>, width: u32, height: u32) -> Result<()> {
+    let con = match CONSOLES.lock().unwrap().get_console_by_id(con_id) {
+        Some(con) => con,
+        None => bail!("No console to resize"),
+    };
+    if !con.lock().unwrap().dev_opts.hw_ui_resizable() {
+        bail!("The graphic hardware does not support resizing");
+    }
+    graphic_hardware_ui_info(con, width, height)
+}
+
 /// Get the weak reference of all active consoles from the console lists.
 pub fn get_active_console() -> Vec<Weak<Mutex<DisplayConsole>>> {
     let mut res: Vec<Weak<Mutex<DisplayConsole>>> = vec![];
@@ -801,10 +875,17 @@ pub fn create_msg_surface(width: i32, height: i32, msg: String) -> Option<Displa
     Some(surface)
 }
 
+/// Init the global event loop once for all the tests, as it can not be
+/// initialized by the tests concurrently.
+#[cfg(test)]
+pub(crate) fn event_loop_test_init() {
+    static EVENT_LOOP_INIT: std::sync::Once = std::sync::Once::new();
+    EVENT_LOOP_INIT.call_once(|| EventLoop::object_init(&None).unwrap());
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use machine_manager::config::VmConfig;
     pub struct DclOpts {}
     impl DisplayChangeListenerOperations for DclOpts {
         fn dpy_switch(&self, _surface: &DisplaySurface) -> Result<()> {
@@ -861,8 +942,7 @@ mod tests {
 
     #[test]
     fn test_register_display() {
-        let vm_config = VmConfig::default();
-        assert!(EventLoop::object_init(&vm_config.iothreads).is_ok());
+        event_loop_test_init();
         let dcl_opts = Arc::new(DclOpts {});
         let dcl_0 = Arc::new(Mutex::new(DisplayChangeListener::new(
             None,
@@ -186,3 +186,199 @@ pub const KEYSYM2KEYCODE: [(u16, u16); 173] = [
     (0xFFEC, 0x00DC),
     (0xFFFF, 0x00D3),
 ];
+
+/// The key of the keycode in the layout tables is pressed with shift.
+pub const KEYCODE_SHIFT_FLAG: u16 = 0x100;
+/// The key of the keycode in the layout tables is pressed with AltGr.
+pub const KEYCODE_ALTGR_FLAG: u16 = 0x200;
+
+/// Printable keysyms of German layout, which replace the ones of US layout.
+pub const KEYMAP_DE: [(u16, u16); 83] = [
+    // (Keysym , Keycode)
+    (0x0020, 0x0039),
+    (0x0021, 0x0102),
+    (0x0022, 0x0103),
+    (0x0023, 0x002B),
+    (0x0024, 0x0105),
+    (0x0025, 0x0106),
+    (0x0026, 0x0107),
+    (0x0027, 0x012B),
+    (0x0028, 0x0109),
+    (0x0029, 0x010A),
+    (0x002A, 0x011B),
+    (0x002B, 0x001B),
+    (0x002C, 0x0033),
+    (0x002D, 0x0035),
+    (0x002E, 0x0034),
+    (0x002F, 0x0108),
+    (0x0030, 0x000B),
+    (0x0031, 0x0002),
+    (0x0032, 0x0003),
+    (0x0033, 0x0004),
+    (0x0034, 0x0005),
+    (0x0035, 0x0006),
+    (0x0036, 0x0007),
+    (0x0037, 0x0008),
+    (0x0038, 0x0009),
+    (0x0039, 0x000A),
+    (0x003A, 0x0134),
+    (0x003B, 0x0133),
+    (0x003C, 0x0056),
+    (0x003D, 0x010B),
+    (0x003E, 0x0156),
+    (0x003F, 0x010C),
+    (0x0040, 0x0210),
+    (0x005B, 0x0209),
+    (0x005C, 0x020C),
+    (0x005D, 0x020A),
+    (0x005E, 0x0029),
+    (0x005F, 0x0135),
+    (0x0060, 0x010D),
+    (0x0061, 0x001E),
+    (0x0062, 0x0030),
+    (0x0063, 0x002E),
+    (0x0064, 0x0020),
+    (0x0065, 0x0012),
+    (0x0066, 0x0021),
+    (0x0067, 0x0022),
+    (0x0068, 0x0023),
+    (0x0069, 0x0017),
+    (0x006A, 0x0024),
+    (0x006B, 0x0025),
+    (0x006C, 0x0026),
+    (0x006D, 0x0032),
+    (0x006E, 0x0031),
+    (0x006F, 0x0018),
+    (0x0070, 0x0019),
+    (0x0071, 0x0010),
+    (0x0072, 0x0013),
+    (0x0073, 0x001F),
+    (0x0074, 0x0014),
+    (0x0075, 0x0016),
+    (0x0076, 0x002F),
+    (0x0077, 0x0011),
+    (0x0078, 0x002D),
+    (0x0079, 0x002C),
+    (0x007A, 0x0015),
+    (0x007B, 0x0208),
+    (0x007C, 0x0256),
+    (0x007D, 0x020B),
+    (0x007E, 0x021B),
+    (0x00A7, 0x0104),
+    (0x00B0, 0x0129),
+    (0x00B2, 0x0203),
+    (0x00B3, 0x0204),
+    (0x00B4, 0x000D),
+    (0x00B5, 0x0232),
+    (0x00DF, 0x000C),
+    (0x00E4, 0x0028),
+    (0x00F6, 0x0027),
+    (0x00FC, 0x001A),
+    (0x20AC, 0x0212),
+    (0xFE50, 0x010D),
+    (0xFE51, 0x000D),
+    (0xFE52, 0x0029),
+];
+
+/// Printable keysyms of French layout, which replace the ones of US layout.
+pub const KEYMAP_FR: [(u16, u16); 84] = [
+    // (Keysym , Keycode)
+    (0x0020, 0x0039),
+    (0x0021, 0x0035),
+    (0x0022, 0x0004),
+    (0x0023, 0x0204),
+    (0x0024, 0x001B),
+    (0x0025, 0x0128),
+    (0x0026, 0x0002),
+    (0x0027, 0x0005),
+    (0x0028, 0x0006),
+    (0x0029, 0x000C),
+    (0x002A, 0x002B),
+    (0x002B, 0x010D),
+    (0x002C, 0x0032),
+    (0x002D, 0x0007),
+    (0x002E, 0x0133),
+    (0x002F, 0x0134),
+    (0x0030, 0x010B),
+    (0x0031, 0x0102),
+    (0x0032, 0x0103),
+    (0x0033, 0x0104),
+    (0x0034, 0x0105),
+    (0x0035, 0x0106),
+    (0x0036, 0x0107),
+    (0x0037, 0x0108),
+    (0x0038, 0x0109),
+    (0x0039, 0x010A),
+    (0x003A, 0x0034),
+    (0x003B, 0x0033),
+    (0x003C, 0x0056),
+    (0x003D, 0x000D),
+    (0x003E, 0x0156),
+    (0x003F, 0x0132),
+    (0x0040, 0x020B),
+    (0x005B, 0x0206),
+    (0x005C, 0x0209),
+    (0x005D, 0x020C),
+    (0x005E, 0x020A),
+    (0x005F, 0x0009),
+    (0x0060, 0x0208),
+    (0x0061, 0x0010),
+    (0x0062, 0x0030),
+    (0x0063, 0x002E),
+    (0x0064, 0x0020),
+    (0x0065, 0x0012),
+    (0x0066, 0x0021),
+    (0x0067, 0x0022),
+    (0x0068, 0x0023),
+    (0x0069, 0x0017),
+    (0x006A, 0x0024),
+    (0x006B, 0x0025),
+    (0x006C, 0x0026),
+    (0x006D, 0x0027),
+    (0x006E, 0x0031),
+    (0x006F, 0x0018),
+    (0x0070, 0x0019),
+    (0x0071, 0x001E),
+    (0x0072, 0x0013),
+    (0x0073, 0x001F),
+    (0x0074, 0x0014),
+    (0x0075, 0x0016),
+    (0x0076, 0x002F),
+    (0x0077, 0x002C),
+    (0x0078, 0x002D),
+    (0x0079, 0x0015),
+    (0x007A, 0x0011),
+    (0x007B, 0x0205),
+    (0x007C, 0x0207),
+    (0x007D, 0x020D),
+    (0x007E, 0x0203),
+    (0x00A3, 0x011B),
+    (0x00A4, 0x021B),
+    (0x00A7, 0x0135),
+    (0x00A8, 0x011A),
+    (0x00B0, 0x010C),
+    (0x00B2, 0x0029),
+    (0x00B5, 0x012B),
+    (0x00E0, 0x000B),
+    (0x00E7, 0x000A),
+    (0x00E8, 0x0008),
+    (0x00E9, 0x0003),
+    (0x00F9, 0x0028),
+    (0x20AC, 0x0212),
+    (0xFE52, 0x001A),
+    (0xFE57, 0x011A),
+];
+
+/// Dead keysyms and their spacing equivalents, which are used if the dead keysym
+/// is not in the layout.
+pub const DEAD_KEYSYMS: [(u16, u16); 8] = [
+    // (Dead keysym , Spacing keysym)
+    (0xFE50, 0x0060),
+    (0xFE51, 0x00B4),
+    (0xFE52, 0x005E),
+    (0xFE53, 0x007E),
+    (0xFE54, 0x00AF),
+    (0xFE57, 0x00A8),
+    (0xFE58, 0x00B0),
+    (0xFE5B, 0x00B8),
+];
@@ -31,12 +31,16 @@ pub enum VncError {
     MakeTlsConnectionFailed(String),
     #[error("ProtocolMessage failed: {0}")]
     ProtocolMessageFailed(String),
+    #[error("Unsupported pixel format: {0}")]
+    UnsupportedPixelFormat(String),
     #[error("Read buf form tcpstream failed: {0}")]
     ReadMessageFailed(String),
     #[error("Authentication failed: func: {0} reason: {0}")]
     AuthFailed(String, String),
     #[error("ParseKeyBoardFailed: {0}")]
     ParseKeyBoardFailed(String),
+    #[error("WebSocket failed: {0}")]
+    WebSocketFailed(String),
     #[error("Disconnection")]
     Disconnection,
 }
@@ -15,7 +15,10 @@ use log::debug;
 use once_cell::sync::Lazy;
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
 use util::bitmap::Bitmap;
 
@@ -40,13 +43,13 @@ pub const KEYCODE_1: u16 = 2;
 pub const KEYCODE_9: u16 = 10;
 const KEYCODE_CTRL: u16 = 29;
 pub const KEYCODE_RET: u16 = 38;
-const KEYCODE_SHIFT: u16 = 42;
-const KEYCODE_SHIFT_R: u16 = 54;
+pub const KEYCODE_SHIFT: u16 = 42;
+pub const KEYCODE_SHIFT_R: u16 = 54;
 const KEYCODE_ALT: u16 = 56;
 const KEYCODE_CAPS_LOCK: u16 = 58;
 const KEYCODE_NUM_LOCK: u16 = 69;
 const KEYCODE_CTRL_R: u16 = 157;
-const KEYCODE_ALT_R: u16 = 184;
+pub const KEYCODE_ALT_R: u16 = 184;
 const KEYPAD_1: u16 = 0xffb0;
 const KEYPAD_9: u16 = 0xffb9;
 const KEYPAD_SEPARATOR: u16 = 0xffac;
@@ -65,9 +68,18 @@ pub const INPUT_BUTTON_WHEEL_RIGHT: u32 = 0x40;
 
 static INPUTS: Lazy<Arc<Mutex<Inputs>>> = Lazy::new(|| Arc::new(Mutex::new(Inputs::default())));
 
+/// Mode of the active pointer device, which is read for every pointer event
+/// of the display. It is absolute if there is no pointer device.
+static POINTER_ABSOLUTE: AtomicBool = AtomicBool::new(true);
+
 static LED_STATE: Lazy<Arc<Mutex<LedState>>> =
     Lazy::new(|| Arc::new(Mutex::new(LedState::default())));
 
+/// Serialize the tests which send input events, as the events go to the
+/// input devices registered latest.
+#[cfg(test)]
+pub(crate) static INPUT_TEST_LOCK: Mutex<()> = Mutex::new(());
+
 // Keyboard Modifier State
 pub enum KeyboardModifier {
     KeyModNone = 0,
@@ -206,11 +218,19 @@ impl KeyBoardState {
     }
 }
 
+/// Notifier called with the new keyboard led state when it is changed.
+pub type KbdLedNotifier = Arc<dyn Fn(u8) + Send + Sync>;
+
 #[derive(Default)]
 struct LedState {
     kbd_led: u8,
+    notifiers: Vec<KbdLedNotifier>,
 }
 
+/// Notifier called with the new mode of pointer when it is changed, true
+/// means the active pointer device is absolute.
+pub type PointerModeNotifier = Arc<dyn Fn(bool) + Send + Sync>;
+
 #[derive(Default)]
 struct Inputs {
     kbd_ids: Vec<String>,
@@ -218,6 +238,7 @@ struct Inputs {
     tablet_ids: Vec<String>,
     tablet_lists: HashMap<String, Arc<Mutex<dyn PointerOpts>>>,
     keyboard_state: KeyBoardState,
+    pointer_mode_notifiers: Vec<PointerModeNotifier>,
 }
 
 impl Inputs {
@@ -271,6 +292,15 @@ impl Inputs {
         }
     }
 
+    /// The pointer is absolute if there is no pointer device, as the
+    /// absolute position of client is harmless then.
+    fn pointer_is_absolute(&mut self) -> bool {
+        match self.get_active_mouse() {
+            Some(mouse) => mouse.lock().unwrap().is_absolute(),
+            None => true,
+        }
+    }
+
     fn press_key(&mut self, keycode: u16) -> Result<()> {
         self.keyboard_state.keyboard_state_update(keycode, true)?;
         let kbd = self.get_active_kbd();
@@ -294,11 +324,41 @@ pub fn unregister_keyboard(device: &str) {
 }
 
 pub fn register_pointer(device: &str, tablet: Arc<Mutex<dyn PointerOpts>>) {
-    INPUTS.lock().unwrap().register_mouse(device, tablet);
+    update_pointer_mode(|inputs| inputs.register_mouse(device, tablet));
 }
 
 pub fn unregister_pointer(device: &str) {
-    INPUTS.lock().unwrap().unregister_mouse(device);
+    update_pointer_mode(|inputs| inputs.unregister_mouse(device));
+}
+
+/// Change the pointer devices, and notify the new mode of pointer if the
+/// active device is changed between absolute and relative one, such as the
+/// hotplug of usb tablet.
+fn update_pointer_mode<F: FnOnce(&mut Inputs)>(change: F) {
+    let mut locked_input = INPUTS.lock().unwrap();
+    let absolute = locked_input.pointer_is_absolute();
+    change(&mut locked_input);
+    let new_absolute = locked_input.pointer_is_absolute();
+    if absolute == new_absolute {
+        return;
+    }
+    POINTER_ABSOLUTE.store(new_absolute, Ordering::SeqCst);
+    let notifiers = locked_input.pointer_mode_notifiers.clone();
+    drop(locked_input);
+    for notifier in notifiers {
+        notifier(new_absolute);
+    }
+}
+
+/// Whether the active pointer device is absolute, such as usb tablet.
+pub fn pointer_is_absolute() -> bool {
+    POINTER_ABSOLUTE.load(Ordering::SeqCst)
+}
+
+/// Register notifier of pointer mode, such as the display which switches
+/// its client between absolute and relative mode.
+pub fn register_pointer_mode_notifier(notifier: PointerModeNotifier) {
+    INPUTS.lock().unwrap().pointer_mode_notifiers.push(notifier);
 }
 
 pub fn key_event(keycode: u16, down: bool) -> Result<()> {
@@ -328,6 +388,16 @@ pub fn point_event(button: u32, x: u32, y: u32) -> Result<()> {
     Ok(())
 }
 
+/// Relative motion of the pointer, used if the active pointer device is not
+/// absolute.
+pub fn rel_point_event(button: u32, dx: i32, dy: i32) -> Result<()> {
+    let mouse = INPUTS.lock().unwrap().get_active_mouse();
+    if let Some(m) = mouse {
+        m.lock().unwrap().do_rel_point_event(button, dx, dy)?;
+    }
+    Ok(())
+}
+
 /// 1. Keep the key state in keyboard_state.
 /// 2. Sync the caps lock and num lock state to guest.
 pub fn update_key_state(down: bool, keysym: i32, keycode: u16) -> Result<()> {
@@ -383,8 +453,28 @@ pub fn get_kbd_led_state(state: u8) -> bool {
     LED_STATE.lock().unwrap().kbd_led & state == state
 }
 
+/// Get the whole keyboard led state, in HID led bits.
+pub fn kbd_led_state() -> u8 {
+    LED_STATE.lock().unwrap().kbd_led
+}
+
 pub fn set_kbd_led_state(state: u8) {
-    LED_STATE.lock().unwrap().kbd_led = state;
+    let mut locked_led = LED_STATE.lock().unwrap();
+    if locked_led.kbd_led == state {
+        return;
+    }
+    locked_led.kbd_led = state;
+    let notifiers = locked_led.notifiers.clone();
+    drop(locked_led);
+    for notifier in notifiers {
+        notifier(state);
+    }
+}
+
+/// Register notifier of keyboard led state, such as the display which
+/// syncs the lock state to its client.
+pub fn register_kbd_led_notifier(notifier: KbdLedNotifier) {
+    LED_STATE.lock().unwrap().notifiers.push(notifier);
 }
 
 pub fn keyboard_modifier_get(key_mod: KeyboardModifier) -> bool {
@@ -412,6 +502,16 @@ pub trait KeyboardOpts: Send {
 
 pub trait PointerOpts: Send {
     fn do_point_event(&mut self, button: u32, x: u32, y: u32) -> Result<()>;
+
+    /// The device reports the absolute position, otherwise the motion.
+    fn is_absolute(&self) -> bool {
+        true
+    }
+
+    /// Motion of the relative device, which is ignored by absolute device.
+    fn do_rel_point_event(&mut self, _button: u32, _dx: i32, _dy: i32) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -448,6 +548,7 @@ mod tests {
 
     #[test]
     fn test_input_basic() {
+        let _guard = INPUT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
         // Test keyboard event.
         let test_kdb = Arc::new(Mutex::new(TestKbd {
             keycode: 0,
@@ -469,4 +570,78 @@ mod tests {
         assert_eq!(test_mouse.lock().unwrap().x, 54);
         assert_eq!(test_mouse.lock().unwrap().y, 12);
     }
+
+    #[test]
+    fn test_kbd_led_notifier() {
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let states_clone = states.clone();
+        register_kbd_led_notifier(Arc::new(move |state| {
+            // Only record the states set by this test.
+            if state & 0x80 != 0 {
+                states_clone.lock().unwrap().push(state);
+            }
+        }));
+
+        set_kbd_led_state(0x80 | CAPS_LOCK_LED);
+        assert!(get_kbd_led_state(CAPS_LOCK_LED));
+        // The state is not changed.
+        set_kbd_led_state(0x80 | CAPS_LOCK_LED);
+        set_kbd_led_state(0x80 | NUM_LOCK_LED | SCROLL_LOCK_LED);
+        assert_eq!(kbd_led_state(), 0x80 | NUM_LOCK_LED | SCROLL_LOCK_LED);
+        assert!(!get_kbd_led_state(CAPS_LOCK_LED));
+        set_kbd_led_state(0);
+        assert_eq!(
+            *states.lock().unwrap(),
+            vec![0x82, 0x80 | NUM_LOCK_LED | SCROLL_LOCK_LED]
+        );
+    }
+
+    #[derive(Default)]
+    struct TestMouse {
+        motions: Vec<(u32, i32, i32)>,
+    }
+
+    impl PointerOpts for TestMouse {
+        fn do_point_event(&mut self, _button: u32, _x: u32, _y: u32) -> Result<()> {
+            Ok(())
+        }
+
+        fn is_absolute(&self) -> bool {
+            false
+        }
+
+        fn do_rel_point_event(&mut self, button: u32, dx: i32, dy: i32) -> Result<()> {
+            self.motions.push((button, dx, dy));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pointer_mode_notifier() {
+        let _guard = INPUT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let modes = Arc::new(Mutex::new(Vec::new()));
+        let modes_clone = modes.clone();
+        register_pointer_mode_notifier(Arc::new(move |absolute| {
+            modes_clone.lock().unwrap().push(absolute);
+        }));
+        assert!(pointer_is_absolute());
+
+        // The relative mouse is active.
+        let test_mouse = Arc::new(Mutex::new(TestMouse::default()));
+        register_pointer("TestRelMouse", test_mouse.clone());
+        assert!(!pointer_is_absolute());
+        assert!(rel_point_event(1, -3, 5).is_ok());
+        assert_eq!(test_mouse.lock().unwrap().motions, vec![(1, -3, 5)]);
+
+        // The tablet is hotplugged and unplugged.
+        let test_tablet = Arc::new(Mutex::new(TestTablet::default()));
+        register_pointer("TestHotplugTablet", test_tablet);
+        assert!(pointer_is_absolute());
+        unregister_pointer("TestHotplugTablet");
+        assert!(!pointer_is_absolute());
+
+        unregister_pointer("TestRelMouse");
+        assert!(pointer_is_absolute());
+        assert_eq!(*modes.lock().unwrap(), vec![false, true, false, true]);
+    }
 }
@@ -0,0 +1,290 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    data::keycode::{
+        DEAD_KEYSYMS, KEYCODE_ALTGR_FLAG, KEYCODE_SHIFT_FLAG, KEYMAP_DE, KEYMAP_FR, KEYSYM2KEYCODE,
+    },
+    error::VncError,
+    input::{KEYCODE_ALT_R, KEYCODE_SHIFT, KEYCODE_SHIFT_R},
+};
+
+/// US layout, which is the default keymap of guest.
+pub const KEYMAP_EN_US: &str = "en-us";
+/// The keysyms from it are not printable, such as function keys, keypad and
+/// modifiers, which are the same in all layouts.
+const KEYSYM_NON_PRINTABLE: u16 = 0xfe00;
+/// Range of dead keysyms, which are input as the printable ones.
+const KEYSYM_DEAD_FIRST: u16 = 0xfe50;
+const KEYSYM_DEAD_LAST: u16 = 0xfe8f;
+const KEYSYM_SPACE: u16 = 0x0020;
+
+/// The key to press in guest for a keysym.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStroke {
+    /// Keycode of the key.
+    pub keycode: u16,
+    /// The keysym is input with shift.
+    pub shift: bool,
+    /// The keysym is input with AltGr.
+    pub altgr: bool,
+    /// The keysym depends on the state of shift and AltGr, which should be
+    /// fixed up to the required one before the key is pressed.
+    pub fix_mods: bool,
+}
+
+impl KeyStroke {
+    /// The events of modifiers to send before the key is pressed, so that
+    /// the state of shift and AltGr is the required one. The events are
+    /// reverted after the key is pressed.
+    ///
+    /// # Arguments
+    ///
+    /// * `pressed_keys` - The keys held by client.
+    pub fn modifier_events(&self, pressed_keys: &HashSet<u16>) -> Vec<(u16, bool)> {
+        let mut events = Vec::new();
+        if !self.fix_mods {
+            return events;
+        }
+
+        let shift_keys: Vec<u16> = [KEYCODE_SHIFT, KEYCODE_SHIFT_R]
+            .into_iter()
+            .filter(|keycode| pressed_keys.contains(keycode))
+            .collect();
+        if self.shift && shift_keys.is_empty() {
+            events.push((KEYCODE_SHIFT, true));
+        } else if !self.shift {
+            events.extend(shift_keys.into_iter().map(|keycode| (keycode, false)));
+        }
+        if self.altgr != pressed_keys.contains(&KEYCODE_ALT_R) {
+            events.push((KEYCODE_ALT_R, self.altgr));
+        }
+        events
+    }
+}
+
+/// Build the mapping from keysym to keycode for the keyboard layout of
+/// guest. The printable keysyms of US layout are replaced by the ones of the
+/// layout, and the others are shared.
+///
+/// # Arguments
+///
+/// * `layout` - Name of the layout, empty means the US layout.
+pub fn keysym2keycode_map(layout: &str) -> Result<HashMap<u16, u16>> {
+    let mut keysym2keycode: HashMap<u16, u16> = KEYSYM2KEYCODE.iter().copied().collect();
+    let table: &[(u16, u16)] = match layout {
+        "" | KEYMAP_EN_US => return Ok(keysym2keycode),
+        "de" => &KEYMAP_DE,
+        "fr" => &KEYMAP_FR,
+        _ => {
+            return Err(anyhow!(VncError::ParseKeyBoardFailed(format!(
+                "unsupported keymap {}",
+                layout
+            ))))
+        }
+    };
+    keysym2keycode.retain(|&keysym, _| keysym >= KEYSYM_NON_PRINTABLE);
+    keysym2keycode.extend(table.iter().copied());
+    Ok(keysym2keycode)
+}
+
+/// Lowercase of the latin-1 letter, None if the keysym is not an uppercase letter.
+fn keysym_to_lower(keysym: u16) -> Option<u16> {
+    match keysym {
+        0x41..=0x5a | 0xc0..=0xd6 | 0xd8..=0xde => Some(keysym + 0x20),
+        _ => None,
+    }
+}
+
+fn keysym_is_letter(keysym: u16) -> bool {
+    matches!(keysym, 0x61..=0x7a | 0xe0..=0xf6 | 0xf8..=0xfe)
+}
+
+/// Translate the keysym to the key stroke with the mapping of the layout.
+///
+/// The case of letters is left to the caps lock and shift of guest. Dead
+/// keysyms fall back to their spacing equivalents if the layout has no such
+/// dead keys.
+pub fn keysym_lookup(keysym2keycode: &HashMap<u16, u16>, keysym: u32) -> Option<KeyStroke> {
+    let mut keysym = u16::try_from(keysym).ok()?;
+    if let Some(lower) = keysym_to_lower(keysym) {
+        keysym = lower;
+    }
+    if !keysym2keycode.contains_key(&keysym) {
+        if let Some(&(_, spacing)) = DEAD_KEYSYMS.iter().find(|(dead, _)| *dead == keysym) {
+            keysym = spacing;
+        }
+    }
+    let keycode = *keysym2keycode.get(&keysym)?;
+
+    if keysym >= KEYSYM_NON_PRINTABLE && !(KEYSYM_DEAD_FIRST..=KEYSYM_DEAD_LAST).contains(&keysym) {
+        return Some(KeyStroke {
+            keycode,
+            shift: false,
+            altgr: false,
+            fix_mods: false,
+        });
+    }
+    Some(KeyStroke {
+        keycode: keycode & 0xff,
+        shift: keycode & KEYCODE_SHIFT_FLAG != 0,
+        altgr: keycode & KEYCODE_ALTGR_FLAG != 0,
+        fix_mods: !keysym_is_letter(keysym) && keysym != KEYSYM_SPACE,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(map: &HashMap<u16, u16>, keysym: u32) -> Option<(u16, bool, bool)> {
+        keysym_lookup(map, keysym).map(|key| (key.keycode, key.shift, key.altgr))
+    }
+
+    #[test]
+    fn test_keymap_lookup() {
+        assert!(keysym2keycode_map("xx").is_err());
+        let us = keysym2keycode_map("").unwrap();
+        let de = keysym2keycode_map("de").unwrap();
+        let fr = keysym2keycode_map("fr").unwrap();
+
+        // (keysym, US, German, French) with (keycode, shift, altgr).
+        let samples = [
+            // 'y' and 'z' are swapped in German layout.
+            (
+                0x7a,
+                (0x2c, false, false),
+                (0x15, false, false),
+                (0x11, false, false),
+            ),
+            // Uppercase letters are the same keys.
+            (
+                0x59,
+                (0x15, false, false),
+                (0x2c, false, false),
+                (0x15, false, false),
+            ),
+            // '@'
+            (
+                0x40,
+                (0x03, true, false),
+                (0x10, false, true),
+                (0x0b, false, true),
+            ),
+            // '/'
+            (
+                0x2f,
+                (0x35, false, false),
+                (0x08, true, false),
+                (0x34, true, false),
+            ),
+            // '1' is shifted in French layout.
+            (
+                0x31,
+                (0x02, false, false),
+                (0x02, false, false),
+                (0x02, true, false),
+            ),
+            // 'EuroSign'
+            (
+                0x20ac,
+                (0, false, false),
+                (0x12, false, true),
+                (0x12, false, true),
+            ),
+        ];
+        let keys = |key: (u16, bool, bool)| Some(key).filter(|k| k.0 != 0);
+        for (keysym, in_us, in_de, in_fr) in samples {
+            assert_eq!(lookup(&us, keysym), keys(in_us));
+            assert_eq!(lookup(&de, keysym), keys(in_de));
+            assert_eq!(lookup(&fr, keysym), keys(in_fr));
+        }
+
+        // German umlauts and sharp s, 'Adiaeresis' is the key of 'adiaeresis'.
+        assert_eq!(lookup(&de, 0xe4), Some((0x28, false, false)));
+        assert_eq!(lookup(&de, 0xc4), Some((0x28, false, false)));
+        assert_eq!(lookup(&de, 0xf6), Some((0x27, false, false)));
+        assert_eq!(lookup(&de, 0xfc), Some((0x1a, false, false)));
+        assert_eq!(lookup(&de, 0xdf), Some((0x0c, false, false)));
+        // French accented letters and symbols.
+        assert_eq!(lookup(&fr, 0xe9), Some((0x03, false, false)));
+        assert_eq!(lookup(&fr, 0xe7), Some((0x0a, false, false)));
+        assert_eq!(lookup(&fr, 0xa3), Some((0x1b, true, false)));
+        assert_eq!(lookup(&de, 0xe9), None);
+
+        // Dead keys of the layout, and the spacing equivalents of the ones
+        // not in the layout.
+        assert_eq!(lookup(&de, 0xfe52), Some((0x29, false, false)));
+        assert_eq!(lookup(&fr, 0xfe57), Some((0x1a, true, false)));
+        assert_eq!(lookup(&fr, 0xfe50), Some((0x08, false, true)));
+        assert_eq!(lookup(&us, 0xfe53), Some((0x29, true, false)));
+
+        // Numpad and function keys are the same in all layouts.
+        for map in [&us, &de, &fr] {
+            assert_eq!(lookup(map, 0xffb7), Some((0x47, false, false)));
+            assert_eq!(lookup(map, 0xffaf), Some((0xb5, false, false)));
+            assert_eq!(lookup(map, 0xff8d), Some((0x9c, false, false)));
+            assert_eq!(lookup(map, 0xffbe), Some((0x3b, false, false)));
+            assert!(!keysym_lookup(map, 0xffe1).unwrap().fix_mods);
+        }
+
+        // Only the symbols need the modifiers to be fixed up.
+        assert!(keysym_lookup(&de, 0x40).unwrap().fix_mods);
+        assert!(!keysym_lookup(&de, 0x41).unwrap().fix_mods);
+        assert!(!keysym_lookup(&de, 0x20).unwrap().fix_mods);
+        // Unicode keysyms are not supported.
+        assert_eq!(lookup(&us, 0x0100_20ac), None);
+    }
+
+    #[test]
+    fn test_keymap_modifier_events() {
+        let de = keysym2keycode_map("de").unwrap();
+        let pressed = |keys: &[u16]| keys.iter().copied().collect::<HashSet<u16>>();
+
+        // '@' typed by Shift + 2 in US layout is AltGr + Q in German layout.
+        let at = keysym_lookup(&de, 0x40).unwrap();
+        assert_eq!(
+            at.modifier_events(&pressed(&[KEYCODE_SHIFT])),
+            vec![(KEYCODE_SHIFT, false), (KEYCODE_ALT_R, true)]
+        );
+        // AltGr is held by the client of German layout.
+        assert!(at.modifier_events(&pressed(&[KEYCODE_ALT_R])).is_empty());
+
+        // '?' is shifted, both of the shift keys are released for '#'.
+        let question = keysym_lookup(&de, 0x3f).unwrap();
+        assert_eq!(
+            question.modifier_events(&pressed(&[])),
+            vec![(KEYCODE_SHIFT, true)]
+        );
+        assert!(question
+            .modifier_events(&pressed(&[KEYCODE_SHIFT_R]))
+            .is_empty());
+        let hash = keysym_lookup(&de, 0x23).unwrap();
+        let mut events = hash.modifier_events(&pressed(&[KEYCODE_SHIFT, KEYCODE_SHIFT_R]));
+        events.sort_unstable();
+        assert_eq!(
+            events,
+            vec![(KEYCODE_SHIFT, false), (KEYCODE_SHIFT_R, false)]
+        );
+
+        // The modifiers of letters are left to the client.
+        let letter = keysym_lookup(&de, 0x5a).unwrap();
+        assert!(letter.modifier_events(&pressed(&[])).is_empty());
+        assert!(letter
+            .modifier_events(&pressed(&[KEYCODE_ALT_R, KEYCODE_SHIFT]))
+            .is_empty());
+    }
+}
@@ -15,6 +15,7 @@ mod data;
 pub mod error;
 pub mod gtk;
 pub mod input;
+pub mod keymap;
 pub mod pixman;
 pub mod utils;
 pub mod vnc;
@@ -92,10 +92,7 @@ impl PixelFormat {
 
     pub fn is_default_pixel_format(&self) -> bool {
         // Check if type is PIXMAN_TYPE_ARGB.
-        if self.red.shift <= self.green.shift
-            || self.green.shift <= self.blue.shift
-            || self.blue.shift != 0
-        {
+        if self.red.shift != 16 || self.green.shift != 8 || self.blue.shift != 0 {
             return false;
         }
 
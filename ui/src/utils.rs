@@ -83,6 +83,11 @@ impl BuffPool {
         self.limit = limit;
     }
 
+    /// Get the limitation for bufferpool.
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
     /// Add data to the bufferpool. If the remaining
     /// free space is not enough, it will not work. So it is
     /// recommended to call is_enough() before this function.
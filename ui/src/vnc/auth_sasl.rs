@@ -12,20 +12,29 @@
 
 use crate::{
     error::VncError,
-    vnc::client_io::{vnc_flush, vnc_write, ClientIoHandler, APP_NAME},
+    vnc::client_io::{auth_failure_reason, vnc_flush, vnc_write, ClientIoHandler, APP_NAME},
 };
 use anyhow::{anyhow, Result};
 use libc::{c_char, c_int, c_uint, c_void};
-use log::info;
+use log::{debug, info};
+use machine_manager::config::{
+    DEFAULT_SASL_MAXBUFSIZE, DEFAULT_SASL_MAX_SSF, DEFAULT_SASL_MIN_SSF, SASL_IDENTITY_WILDCARD,
+};
+use once_cell::sync::Lazy;
 use sasl2_sys::prelude::{
-    sasl_conn_t, sasl_dispose, sasl_getprop, sasl_listmech, sasl_security_properties_t,
-    sasl_server_init, sasl_server_new, sasl_server_start, sasl_server_step, sasl_setprop,
-    sasl_ssf_t, SASL_CONTINUE, SASL_OK, SASL_SEC_PROPS, SASL_SSF, SASL_SSF_EXTERNAL,
-    SASL_SUCCESS_DATA,
+    sasl_callback_t, sasl_conn_t, sasl_getprop, sasl_listmech, sasl_security_properties_t,
+    sasl_server_start, sasl_server_step, sasl_setprop, sasl_ssf_t, SASL_CB_GETCONFPATH,
+    SASL_CB_LIST_END, SASL_CONTINUE, SASL_FAIL, SASL_OK, SASL_SEC_PROPS, SASL_SSF,
+    SASL_SSF_EXTERNAL,
 };
 use sasl2_sys::sasl::SASL_USERNAME;
+use std::collections::HashSet;
 use std::ffi::{CStr, CString};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
 use std::ptr;
+use std::sync::Mutex;
 use util::byte_code::ByteCode;
 
 /// Vnc Service.
@@ -33,8 +42,6 @@ const SERVICE: &str = "vnc";
 const MECHNAME_MAX_LEN: u32 = 100;
 const MECHNAME_MIN_LEN: u32 = 1;
 const SASL_DATA_MAX_LEN: u32 = 1024 * 1024;
-/// Minimum supported encryption length of ssf layer in sasl.
-const MIN_SSF_LENGTH: usize = 56;
 
 /// Authentication type
 #[derive(Clone, Copy)]
@@ -53,8 +60,12 @@ pub enum SubAuthState {
     VncAuthVencryptPlain = 256,
     /// Tls vencry with anon + no auth.
     VncAuthVencryptTlNone = 257,
+    /// Tls vencrypt with anon + vnc password.
+    VncAuthVencryptTlsVnc = 258,
     /// Tls vencrypt with x509 + no auth.
     VncAuthVencryptX509None = 260,
+    /// Tls vencrypt with x509 + vnc password.
+    VncAuthVencryptX509Vnc = 261,
     /// Tls vencrypt with x509 + sasl.
     VncAuthVencryptX509Sasl = 263,
     /// Tls vencrypt + sasl.
@@ -62,20 +73,80 @@ pub enum SubAuthState {
 }
 
 /// Configuration for authentication.
-/// Identity: authentication user.
+/// Identities: authorized users.
+/// Allow_all: authorize all authenticated users.
+/// Maxbufsize: max buffer size of the security layer.
+/// Min_ssf/Max_ssf: range of the strength of the security layer.
+/// Security_flags: flags which restrict the mechanisms, `SASL_SEC_*` in sasl.h.
+/// Appname: application name of sasl, the config file is `<appname>.conf`.
+/// Config_dir: directory to search the config file, None means the default path of sasl.
+/// Realm: realm of the sasl server, such as the kerberos realm of GSSAPI, None means
+/// the default realm of the host.
 #[derive(Debug, Clone)]
 pub struct SaslAuth {
-    pub identity: String,
+    pub identities: HashSet<String>,
+    pub allow_all: bool,
+    pub maxbufsize: u32,
+    pub min_ssf: u32,
+    pub max_ssf: u32,
+    pub security_flags: u32,
+    pub appname: String,
+    pub config_dir: Option<String>,
+    pub realm: Option<String>,
 }
 
 impl SaslAuth {
-    pub fn new(identity: String) -> Self {
-        SaslAuth { identity }
+    pub fn new(identities: Vec<String>, allow_all: bool) -> Self {
+        SaslAuth {
+            identities: identities.into_iter().collect(),
+            allow_all,
+            maxbufsize: DEFAULT_SASL_MAXBUFSIZE,
+            min_ssf: DEFAULT_SASL_MIN_SSF,
+            max_ssf: DEFAULT_SASL_MAX_SSF,
+            security_flags: 0,
+            appname: APP_NAME.to_string(),
+            config_dir: None,
+            realm: None,
+        }
+    }
+
+    /// Set the application name and the directory of sasl config file,
+    /// empty value keeps the default one.
+    pub fn set_config_path(&mut self, appname: &str, config_dir: &str) -> Result<()> {
+        if !appname.is_empty() {
+            self.appname = appname.to_string();
+        }
+        if config_dir.is_empty() {
+            return Ok(());
+        }
+
+        let config_file = Path::new(config_dir).join(format!("{}.conf", self.appname));
+        if !config_file.is_file() {
+            return Err(anyhow!(VncError::AuthFailed(
+                "set_config_path".to_string(),
+                format!("sasl config file {} does not exist", config_file.display())
+            )));
+        }
+        self.config_dir = Some(config_dir.to_string());
+        Ok(())
+    }
+
+    /// Check whether the username is authorized.
+    /// Return the identity which matched the username.
+    pub fn authorize(&self, username: &str) -> Option<String> {
+        if self.identities.contains(username) {
+            return Some(username.to_string());
+        }
+        if self.allow_all {
+            return Some(SASL_IDENTITY_WILDCARD.to_string());
+        }
+        None
     }
 }
 
-/// Struct of sasl authentication.
-#[derive(Debug, Clone)]
+/// Sasl authentication state of a client. The sasl connection is owned by
+/// it, so it is disposed when the struct is dropped.
+#[derive(Debug)]
 pub struct SaslConfig {
     /// State of sasl connection .
     pub sasl_conn: *mut sasl_conn_t,
@@ -89,6 +160,8 @@ pub struct SaslConfig {
     pub want_ssf: bool,
     /// Strength of ssf.
     pub run_ssf: u32,
+    /// Strength of ssf provided by the external transport, such as tls.
+    pub external_ssf: u32,
 }
 
 impl Default for SaslConfig {
@@ -100,7 +173,35 @@ impl Default for SaslConfig {
             sasl_stage: SaslStage::SaslServerStart,
             want_ssf: false,
             run_ssf: 0,
+            external_ssf: 0,
+        }
+    }
+}
+
+impl SaslConfig {
+    /// Strength of the security layer negotiated by sasl, 0 means no security layer.
+    pub fn effective_ssf(&self) -> u32 {
+        self.run_ssf
+    }
+
+    /// Whether the session is encrypted, either by tls or by the security layer of sasl.
+    pub fn is_encrypted(&self) -> bool {
+        self.external_ssf > 0 || self.effective_ssf() > 0
+    }
+
+    /// Dispose the sasl connection and reset the authentication state.
+    pub fn dispose_conn(&mut self) {
+        if !self.sasl_conn.is_null() {
+            sasl_conn_dispose(&mut self.sasl_conn);
+            self.sasl_conn = ptr::null_mut();
         }
+        self.sasl_stage = SaslStage::SaslServerStart;
+    }
+}
+
+impl Drop for SaslConfig {
+    fn drop(&mut self) {
+        self.dispose_conn();
     }
 }
 
@@ -111,12 +212,27 @@ pub enum SaslStage {
     SaslServerStep,
 }
 
+/// Output of the client written through the security layer of sasl.
+#[derive(Default)]
+pub struct SaslOutput {
+    /// Bytes queued before the security layer is active, which are sent
+    /// without encoding.
+    pub wait_write_ssf: usize,
+    /// Data encoded by sasl which has not been completely written.
+    encoded: Vec<u8>,
+    /// Bytes of the encoded data which have been written.
+    encoded_offset: usize,
+    /// Length of the plain data which is encoded.
+    encoded_raw_len: usize,
+}
+
 impl ClientIoHandler {
     /// Get length of mechname send form client.
     pub fn get_mechname_length(&mut self) -> Result<()> {
         let buf = self.read_incoming_msg();
         let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
         if !(MECHNAME_MIN_LEN..MECHNAME_MAX_LEN).contains(&len) {
+            self.fail_auth("SASL mechname too short or too long");
             return Err(anyhow!(VncError::AuthFailed(
                 "get_mechname_length".to_string(),
                 "SASL mechname too short or too long".to_string()
@@ -132,13 +248,14 @@ impl ClientIoHandler {
     /// 2. Get the mechlist support by Sasl server.
     /// 3. Send the mechlist to client.
     pub fn start_sasl_auth(&mut self) -> Result<()> {
-        self.sasl_server_init()?;
-
-        self.set_ssf_for_sasl()?;
-
-        self.send_mech_list()?;
-
-        Ok(())
+        let result = self
+            .sasl_server_init()
+            .and_then(|_| self.set_ssf_for_sasl())
+            .and_then(|_| self.send_mech_list());
+        if let Err(e) = &result {
+            self.fail_auth(&auth_failure_reason(e));
+        }
+        result
     }
 
     /// Get authentication mechanism supported by client.
@@ -146,22 +263,26 @@ impl ClientIoHandler {
         let buf = self.read_incoming_msg();
         let mech_name = String::from_utf8_lossy(&buf).to_string();
 
-        let mut security = self.server.security_type.borrow_mut();
-        let mech_list: Vec<&str> = security.saslconfig.mech_list.split(',').collect();
-        for mech in mech_list {
-            if mech_name == *mech {
-                security.saslconfig.mech_name = mech_name;
-                break;
-            }
+        if self
+            .saslconfig
+            .mech_list
+            .split(',')
+            .any(|mech| mech == mech_name)
+        {
+            self.saslconfig.mech_name = mech_name.clone();
         }
         // Unsupported mechanism.
-        if security.saslconfig.mech_name.is_empty() {
+        if self.saslconfig.mech_name.is_empty() {
+            debug!(
+                "Rejected sasl mechanism: requested={:?}, supported={:?}",
+                mech_name, self.saslconfig.mech_list
+            );
+            self.fail_auth("Unsupported mechanism");
             return Err(anyhow!(VncError::AuthFailed(
                 "get_sasl_mechname".to_string(),
                 "Unsupported mechanism".to_string()
             )));
         }
-        drop(security);
 
         self.update_event_handler(4, ClientIoHandler::get_authmessage_length);
         Ok(())
@@ -174,6 +295,7 @@ impl ClientIoHandler {
         let len = u32::from_be_bytes(buf);
 
         if len > SASL_DATA_MAX_LEN {
+            self.fail_auth("SASL start len too large");
             return Err(anyhow!(VncError::AuthFailed(
                 "get_authmessage_length".to_string(),
                 "SASL start len too large".to_string()
@@ -181,6 +303,8 @@ impl ClientIoHandler {
         }
 
         if len == 0 {
+            // No data follows, the authentication message is handled at once.
+            self.update_event_handler(0, ClientIoHandler::client_sasl_auth);
             return self.client_sasl_auth();
         }
         self.update_event_handler(len as usize, ClientIoHandler::client_sasl_auth);
@@ -199,20 +323,18 @@ impl ClientIoHandler {
             client_data[self.expect - 1] = 0_u8;
         }
 
-        let server = self.server.clone();
         let client = self.client.clone();
-        let mut security = server.security_type.borrow_mut();
         let mut serverout: *const c_char = ptr::null_mut();
         let mut serverout_len: c_uint = 0;
-        let mech_name = CString::new(security.saslconfig.mech_name.as_str())?;
+        let mech_name = CString::new(self.saslconfig.mech_name.as_str())?;
 
         // Start authentication.
-        let err: c_int = match security.saslconfig.sasl_stage {
+        let err: c_int = match self.saslconfig.sasl_stage {
             // SAFETY: sasl_server_start() and sasl_server_step() is C function. All parameters passed of the
             // function have been checked. Memory will be allocated for the incoming pointer inside the function.
             SaslStage::SaslServerStart => unsafe {
                 sasl_server_start(
-                    security.saslconfig.sasl_conn,
+                    self.saslconfig.sasl_conn,
                     mech_name.as_ptr(),
                     client_data.as_ptr() as *const c_char,
                     client_len,
@@ -222,7 +344,7 @@ impl ClientIoHandler {
             },
             SaslStage::SaslServerStep => unsafe {
                 sasl_server_step(
-                    security.saslconfig.sasl_conn,
+                    self.saslconfig.sasl_conn,
                     client_data.as_ptr() as *const c_char,
                     client_len,
                     &mut serverout,
@@ -232,160 +354,194 @@ impl ClientIoHandler {
         };
 
         if err != SASL_OK && err != SASL_CONTINUE {
-            // SAFETY: sasl_dispose() is C function. All parameters passed of the
-            // function have been checked.
-            unsafe { sasl_dispose(&mut security.saslconfig.sasl_conn) }
+            self.saslconfig.dispose_conn();
+            self.fail_auth("Authentication failed");
             return Err(anyhow!(VncError::AuthFailed(
                 "client_sasl_auth".to_string(),
                 "Auth failed!".to_string()
             )));
         }
-        if serverout_len > SASL_DATA_MAX_LEN {
-            unsafe { sasl_dispose(&mut security.saslconfig.sasl_conn) }
-            return Err(anyhow!(VncError::AuthFailed(
-                "client_sasl_auth".to_string(),
-                "SASL data too long".to_string()
-            )));
-        }
-
-        let mut buf = Vec::new();
-        if serverout_len > 0 {
-            // Authentication related information.
-            let serverout = unsafe { CStr::from_ptr(serverout as *const c_char) };
-            let auth_message = String::from(serverout.to_str().unwrap_or(""));
-            buf.append(&mut (serverout_len + 1).to_be_bytes().to_vec());
-            buf.append(&mut auth_message.as_bytes().to_vec());
-        } else {
-            buf.append(&mut (0_u32).to_be_bytes().to_vec());
-        }
+        // Authentication related information, the length is checked inside.
+        let mut buf = match sasl_server_out(serverout, serverout_len) {
+            Ok(buf) => buf,
+            Err(e) => {
+                self.saslconfig.dispose_conn();
+                self.fail_auth("SASL data invalid");
+                return Err(e);
+            }
+        };
 
         if err == SASL_OK {
             buf.append(&mut (1_u8).as_bytes().to_vec());
         } else if err == SASL_CONTINUE {
             buf.append(&mut (0_u8).as_bytes().to_vec());
         }
-        drop(security);
 
         if err == SASL_CONTINUE {
-            // Authentication continue.
-            let mut security = server.security_type.borrow_mut();
-            security.saslconfig.sasl_stage = SaslStage::SaslServerStep;
+            // Authentication continue, the challenge is sent to client.
+            vnc_write(&client, buf);
+            vnc_flush(&client);
+            self.saslconfig.sasl_stage = SaslStage::SaslServerStep;
             self.update_event_handler(4, ClientIoHandler::get_authmessage_length);
-            drop(security);
             return Ok(());
-        } else {
-            if let Err(err) = self.sasl_check_ssf() {
+        }
+
+        let ssf = match self.sasl_check_ssf() {
+            Ok(ssf) => ssf,
+            Err(err) => {
                 // Reject auth: the strength of ssf is too weak.
-                auth_reject(&mut buf);
                 vnc_write(&client, buf);
-                vnc_flush(&client);
+                self.fail_auth(&auth_failure_reason(&err));
                 return Err(err);
             }
+        };
 
-            if let Err(err) = self.sasl_check_authz() {
-                // Reject auth: wrong sasl username.
-                auth_reject(&mut buf);
-                vnc_write(&client, buf);
-                vnc_flush(&client);
-                return Err(err);
-            }
-            // Accept auth.
-            buf.append(&mut (0_u32).as_bytes().to_vec());
+        if let Err(err) = self.sasl_check_authz() {
+            // Reject auth: wrong sasl username.
+            vnc_write(&client, buf);
+            self.fail_auth(&auth_failure_reason(&err));
+            return Err(err);
         }
+        // Accept auth.
+        buf.append(&mut (0_u32).as_bytes().to_vec());
 
         vnc_write(&client, buf);
+        if ssf > 0 {
+            // The security layer starts after the result of authentication,
+            // the output queued until now is sent as is.
+            self.saslconfig.run_ssf = ssf;
+            self.sasl_output.wait_write_ssf = client.out_buffer.lock().unwrap().len();
+        }
         vnc_flush(&client);
         self.update_event_handler(1, ClientIoHandler::handle_client_init);
         Ok(())
     }
 
-    /// Sasl server init.
-    fn sasl_server_init(&mut self) -> Result<()> {
-        let mut err: c_int;
-        let service = CString::new(SERVICE)?;
-        let appname = CString::new(APP_NAME)?;
-        let local_addr = self.stream.local_addr()?.to_string().replace(':', ";");
-        let remote_addr = self.stream.peer_addr()?.to_string().replace(':', ";");
-        info!("local_addr: {} remote_addr: {}", local_addr, remote_addr);
-        let local_addr = CString::new(local_addr)?;
-        let remote_addr = CString::new(remote_addr)?;
-        // SAFETY: sasl_server_init() and sasl_server_new() is C function. All parameters passed of the
-        // function have been checked. Memory will be allocated for the incoming pointer inside the function.
+    /// Sasl server init, the sasl connection is created for this client.
+    pub fn sasl_server_init(&mut self) -> Result<()> {
+        let (local_addr, remote_addr) = sasl_transport_addrs(&self.stream)?;
+        info!(
+            "local_addr: {:?} remote_addr: {:?}",
+            local_addr, remote_addr
+        );
         // Sasl server init.
-        unsafe {
-            err = sasl_server_init(ptr::null_mut(), appname.as_ptr());
+        let (appname, config_dir, realm) =
+            match self.server.security_type.borrow().saslauth.as_ref() {
+                Some(saslauth) => (
+                    saslauth.appname.clone(),
+                    saslauth.config_dir.clone(),
+                    saslauth.realm.clone(),
+                ),
+                None => (APP_NAME.to_string(), None, None),
+            };
+        sasl_lib_init(&appname, config_dir.as_deref())?;
+        let mut saslconfig = SaslConfig::default();
+        if let Err(e) = sasl_server_conn_new(
+            realm.as_deref(),
+            local_addr.as_deref(),
+            remote_addr.as_deref(),
+            &mut saslconfig.sasl_conn,
+        ) {
+            sasl_lib_done();
+            return Err(e);
         }
-        if err != SASL_OK {
-            return Err(anyhow!(VncError::AuthFailed(
-                "sasl_server_init".to_string(),
-                format!("SASL_FAIL error code {}", err)
-            )));
+        self.saslconfig = saslconfig;
+        self.sasl_started = true;
+
+        Ok(())
+    }
+
+    /// Release the sasl connection of the client and the reference of the
+    /// sasl server library. It is called on every teardown path of the client.
+    pub fn sasl_teardown(&mut self) {
+        if !self.sasl_started {
+            return;
         }
-        let mut saslconfig = SaslConfig::default();
-        unsafe {
-            err = sasl_server_new(
-                service.as_ptr(),
-                ptr::null_mut(),
-                ptr::null_mut(),
-                local_addr.as_ptr(),
-                remote_addr.as_ptr(),
-                ptr::null_mut(),
-                SASL_SUCCESS_DATA,
-                &mut saslconfig.sasl_conn,
-            );
+        self.saslconfig.dispose_conn();
+        sasl_lib_done();
+        self.sasl_started = false;
+        self.sasl_output = SaslOutput::default();
+    }
+
+    /// Whether the security layer of sasl is negotiated, the traffic after
+    /// authentication is encoded and decoded by sasl then.
+    pub fn sasl_ssf_active(&self) -> bool {
+        self.sasl_started && !self.saslconfig.sasl_conn.is_null() && self.saslconfig.run_ssf > 0
+    }
+
+    /// Write the data to client through the security layer of sasl. The data
+    /// is reported as written only after all of its encoded data is sent, and
+    /// the data passed in is ignored until then.
+    /// Return the length of the plain data written.
+    pub fn sasl_write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.sasl_output.encoded.is_empty() {
+            self.sasl_output.encoded = sasl_encode_data(self.saslconfig.sasl_conn, buf)?;
+            self.sasl_output.encoded_offset = 0;
+            self.sasl_output.encoded_raw_len = buf.len();
         }
-        if err != SASL_OK {
-            return Err(anyhow!(VncError::AuthFailed(
-                "sasl_server_init".to_string(),
-                format!("SASL_FAIL error code {}", err)
-            )));
+
+        let output = &self.sasl_output;
+        let len = self
+            .io_channel
+            .borrow_mut()
+            .channel_write(&output.encoded[output.encoded_offset..])?;
+        self.sasl_output.encoded_offset += len;
+        if self.sasl_output.encoded_offset < self.sasl_output.encoded.len() {
+            return Ok(0);
         }
-        self.server.security_type.borrow_mut().saslconfig = saslconfig;
+        self.sasl_output.encoded.clear();
+        self.sasl_output.encoded_offset = 0;
+        Ok(self.sasl_output.encoded_raw_len)
+    }
 
-        Ok(())
+    /// Decode the data read from client by the security layer of sasl.
+    pub fn sasl_read(&mut self, buf: &[u8]) -> Result<Vec<u8>> {
+        sasl_decode_data(self.saslconfig.sasl_conn, buf)
     }
 
     /// Set properties for sasl.
     fn set_ssf_for_sasl(&mut self) -> Result<()> {
         // Set the relevant properties of sasl.
         let mut err: c_int;
-        let ssf: sasl_ssf_t = 256;
-        let ssf = &ssf as *const sasl_ssf_t;
-        let security = self.server.security_type.borrow_mut();
-        // SAFETY: sasl_setprop() and sasl_server_new() is C function. It can be ensure
-        // that security.saslconfig.sasl_conn is not null.
-        unsafe {
-            err = sasl_setprop(
-                security.saslconfig.sasl_conn,
-                SASL_SSF_EXTERNAL as i32,
-                ssf as *const c_void,
-            );
-        }
-        if err != SASL_OK {
-            return Err(anyhow!(VncError::AuthFailed(
-                "set_ssf_for_sasl".to_string(),
-                format!("SASL_FAIL error code {}", err)
-            )));
+        // The strength of the tls channel is told to sasl, plaintext has none.
+        let ssf: sasl_ssf_t = self.io_channel.borrow().tls_ssf();
+        if ssf > 0 {
+            let ssf_ptr = &ssf as *const sasl_ssf_t;
+            // SAFETY: sasl_setprop() and sasl_server_new() is C function. It can be ensure
+            // that self.saslconfig.sasl_conn is not null.
+            unsafe {
+                err = sasl_setprop(
+                    self.saslconfig.sasl_conn,
+                    SASL_SSF_EXTERNAL as i32,
+                    ssf_ptr as *const c_void,
+                );
+            }
+            if err != SASL_OK {
+                return Err(anyhow!(VncError::AuthFailed(
+                    "set_ssf_for_sasl".to_string(),
+                    format!("SASL_FAIL error code {}", err)
+                )));
+            }
         }
+        self.saslconfig.external_ssf = ssf;
 
-        // Already using tls, disable ssf in sasl.
-        let props_name = ptr::null_mut() as *mut *const c_char;
-        let props_value = ptr::null_mut() as *mut *const c_char;
-        let saslprops = sasl_security_properties_t {
-            min_ssf: 0,
-            max_ssf: 0,
-            maxbufsize: 8192,
-            security_flags: 0,
-            property_names: props_name,
-            property_values: props_value,
-        };
+        let saslauth = self
+            .server
+            .security_type
+            .borrow()
+            .saslauth
+            .clone()
+            .unwrap_or_else(|| SaslAuth::new(Vec::new(), false));
+        // Already using tls or ssf is disabled by config, disable ssf in sasl.
+        self.saslconfig.want_ssf = !self.saslconfig.is_encrypted() && saslauth.max_ssf > 0;
+        let saslprops = sasl_security_props(self.saslconfig.want_ssf, &saslauth)?;
 
         let props = &saslprops as *const sasl_security_properties_t;
         // SAFETY: sasl_setprop() and sasl_server_new() is C function. It can be ensure
-        // that security.saslconfig.sasl_conn is not null.
+        // that self.saslconfig.sasl_conn is not null.
         unsafe {
             err = sasl_setprop(
-                security.saslconfig.sasl_conn,
+                self.saslconfig.sasl_conn,
                 SASL_SEC_PROPS.try_into()?,
                 props as *const c_void,
             );
@@ -408,13 +564,12 @@ impl ClientIoHandler {
         let sep = CString::new(",")?;
         let suffix = CString::new("")?;
         let mut mechlist: *const c_char = ptr::null_mut();
-        let mut security = self.server.security_type.borrow_mut();
         let client = self.client.clone();
         // SAFETY: sasl_listmech() is C function. It can be ensure
-        // that security.saslconfig.sasl_conn is not null.
+        // that self.saslconfig.sasl_conn is not null.
         unsafe {
             err = sasl_listmech(
-                security.saslconfig.sasl_conn,
+                self.saslconfig.sasl_conn,
                 ptr::null_mut(),
                 prefix.as_ptr(),
                 sep.as_ptr(),
@@ -432,12 +587,11 @@ impl ClientIoHandler {
         }
         // SAFETY: It can be ensure that the pointer of mechlist is not null.
         let mech_list = unsafe { CStr::from_ptr(mechlist as *const c_char) };
-        security.saslconfig.mech_list = String::from(mech_list.to_str()?);
+        self.saslconfig.mech_list = String::from(mech_list.to_str()?);
         let mut buf = Vec::new();
-        let len = security.saslconfig.mech_list.len();
+        let len = self.saslconfig.mech_list.len();
         buf.append(&mut (len as u32).to_be_bytes().to_vec());
-        buf.append(&mut security.saslconfig.mech_list.as_bytes().to_vec());
-        drop(security);
+        buf.append(&mut self.saslconfig.mech_list.as_bytes().to_vec());
         vnc_write(&client, buf);
         vnc_flush(&client);
 
@@ -445,17 +599,16 @@ impl ClientIoHandler {
     }
 
     /// Check whether the ssf layer of sasl meets the strength requirements.
-    fn sasl_check_ssf(&mut self) -> Result<()> {
-        let server = self.server.clone();
-        let mut security = server.security_type.borrow_mut();
-        if !security.saslconfig.want_ssf {
-            return Ok(());
+    /// Return the strength of the security layer, 0 if it is not wanted.
+    fn sasl_check_ssf(&mut self) -> Result<u32> {
+        if !self.saslconfig.want_ssf {
+            return Ok(0);
         }
         let err: c_int;
         let mut val: *const c_void = ptr::null_mut();
         // SAFETY: sasl_getprop() is C function. It can be ensure
-        // that security.saslconfig.sasl_conn is not null.
-        unsafe { err = sasl_getprop(security.saslconfig.sasl_conn, SASL_SSF as c_int, &mut val) }
+        // that self.saslconfig.sasl_conn is not null.
+        unsafe { err = sasl_getprop(self.saslconfig.sasl_conn, SASL_SSF as c_int, &mut val) }
         if err != SASL_OK {
             return Err(anyhow!(VncError::AuthFailed(
                 "sasl_check_ssf".to_string(),
@@ -464,33 +617,35 @@ impl ClientIoHandler {
         }
 
         // SAFETY: It can be ensure that the ptr of val is not null.
-        let ssf: usize = unsafe { *(val as *const usize) };
-        if ssf < MIN_SSF_LENGTH {
+        let ssf: sasl_ssf_t = unsafe { *(val as *const sasl_ssf_t) };
+        let min_ssf = self
+            .server
+            .security_type
+            .borrow()
+            .saslauth
+            .as_ref()
+            .map_or(DEFAULT_SASL_MIN_SSF, |saslauth| saslauth.min_ssf);
+        if ssf < min_ssf {
             return Err(anyhow!(VncError::AuthFailed(
                 "sasl_check_ssf".to_string(),
-                "SASL SSF too weak".to_string()
+                format!(
+                    "SASL SSF {} too weak, at least {} is required",
+                    ssf, min_ssf
+                )
             )));
         }
 
-        security.saslconfig.run_ssf = 1;
-        drop(security);
-        Ok(())
+        Ok(ssf)
     }
 
     /// Check username.
-    fn sasl_check_authz(&mut self) -> Result<()> {
-        let security = self.server.security_type.borrow_mut();
+    /// Return the identity which authorized the username.
+    fn sasl_check_authz(&mut self) -> Result<String> {
         let mut val: *const c_void = ptr::null_mut();
         // SAFETY: sasl_getprop() is C function. It can be ensure
-        // that security.saslconfig.sasl_conn is not null.
-        let err = unsafe {
-            sasl_getprop(
-                security.saslconfig.sasl_conn,
-                SASL_USERNAME as c_int,
-                &mut val,
-            )
-        };
-        drop(security);
+        // that self.saslconfig.sasl_conn is not null.
+        let err =
+            unsafe { sasl_getprop(self.saslconfig.sasl_conn, SASL_USERNAME as c_int, &mut val) };
         if err != SASL_OK {
             return Err(anyhow!(VncError::AuthFailed(
                 "sasl_check_authz".to_string(),
@@ -503,26 +658,819 @@ impl ClientIoHandler {
                 "No SASL username set".to_string()
             )));
         }
-        // SAFETY: It can ensure that the pointer val is not null.
-        let username = unsafe { CStr::from_ptr(val as *const c_char) };
-        let username = String::from(username.to_str()?);
+        // SAFETY: It can ensure that the pointer val is not null, and the
+        // username is a C string.
+        let username = unsafe { sasl_username(val as *const c_char) };
 
-        let server = self.server.clone();
-        let security = server.security_type.borrow_mut();
-        match &security.saslauth {
-            Some(saslauth) if saslauth.identity == username => Ok(()),
-            _ => Err(anyhow!(VncError::AuthFailed(
+        let identity = self
+            .server
+            .security_type
+            .borrow()
+            .saslauth
+            .as_ref()
+            .and_then(|saslauth| saslauth.authorize(&username));
+        match identity {
+            Some(identity) => {
+                info!(
+                    "SASL user {} is authorized by identity {}",
+                    username, identity
+                );
+                Ok(identity)
+            }
+            None => Err(anyhow!(VncError::AuthFailed(
                 "sasl_check_authz".to_string(),
-                "No SASL username set".to_string()
+                format!("authorization failed for user {}", username)
             ))),
         }
     }
 }
 
-/// Auth reject.
-fn auth_reject(buf: &mut Vec<u8>) {
-    let reason = String::from("Authentication failed");
-    buf.append(&mut (1_u32).to_be_bytes().to_vec());
-    buf.append(&mut (reason.len() as u32).to_be_bytes().to_vec());
-    buf.append(&mut reason.as_bytes().to_vec());
+/// Number of the clients which use the sasl server library.
+static SASL_LIB_USERS: Lazy<Mutex<usize>> = Lazy::new(|| Mutex::new(0));
+
+/// Serialize the tests which use the sasl server library, as the library
+/// and the number of its users are global.
+#[cfg(test)]
+pub(crate) static SASL_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+fn with_sasl_lib_users<T>(f: impl FnOnce(&mut usize) -> T) -> T {
+    f(&mut SASL_LIB_USERS.lock().unwrap())
+}
+
+/// Number of the clients which use the sasl server library.
+#[cfg(test)]
+pub(crate) fn sasl_lib_users() -> usize {
+    with_sasl_lib_users(|users| *users)
+}
+
+/// Init the sasl server library with the application name, the config file
+/// `<appname>.conf` is searched in `config_dir` if it is set. The library is
+/// initialized only once for all the clients, each successful call must be
+/// paired with `sasl_lib_done`.
+pub fn sasl_lib_init(appname: &str, config_dir: Option<&str>) -> Result<()> {
+    let appname = CString::new(appname)?;
+    let config_dir = config_dir.map(CString::new).transpose()?;
+    with_sasl_lib_users(|users| {
+        if *users == 0 {
+            let err = sasl_lib_init_raw(&appname, config_dir.as_deref());
+            if err != SASL_OK {
+                return Err(anyhow!(VncError::AuthFailed(
+                    "sasl_server_init".to_string(),
+                    format!("SASL_FAIL error code {}", err)
+                )));
+            }
+        }
+        *users += 1;
+        Ok(())
+    })
+}
+
+/// Drop a reference of the sasl server library, the library is released
+/// when the last client is gone.
+fn sasl_lib_done() {
+    with_sasl_lib_users(|users| {
+        if *users == 0 {
+            return;
+        }
+        *users -= 1;
+        if *users == 0 {
+            sasl_lib_done_raw();
+        }
+    })
+}
+
+/// Directory of the sasl config file, which is given to sasl by the
+/// `SASL_CB_GETCONFPATH` callback. The global path set by `sasl_set_path` is
+/// not used, as sasl can not be initialized again after `sasl_done` then.
+static SASL_CONF_DIR: Lazy<Mutex<Option<CString>>> = Lazy::new(|| Mutex::new(None));
+
+/// Global callbacks of the sasl server library.
+struct SaslLibCallbacks([sasl_callback_t; 2]);
+
+// SAFETY: The callbacks are constant and have no context.
+unsafe impl Sync for SaslLibCallbacks {}
+
+static SASL_LIB_CALLBACKS: SaslLibCallbacks = SaslLibCallbacks([
+    sasl_callback_t {
+        id: SASL_CB_GETCONFPATH,
+        // SAFETY: The callback is called by sasl with the signature of
+        // `sasl_getconfpath_t`.
+        proc_: Some(unsafe {
+            std::mem::transmute::<
+                unsafe extern "C" fn(*mut c_void, *mut *mut c_char) -> c_int,
+                unsafe extern "C" fn() -> c_int,
+            >(sasl_get_conf_path)
+        }),
+        context: ptr::null_mut(),
+    },
+    sasl_callback_t {
+        id: SASL_CB_LIST_END,
+        proc_: None,
+        context: ptr::null_mut(),
+    },
+]);
+
+/// Callback of sasl to get the directory of the config file.
+unsafe extern "C" fn sasl_get_conf_path(_context: *mut c_void, path: *mut *mut c_char) -> c_int {
+    match SASL_CONF_DIR.lock().unwrap().as_ref() {
+        Some(dir) => {
+            // The directory is kept until the library is initialized again.
+            *path = dir.as_ptr() as *mut c_char;
+            SASL_OK
+        }
+        None => SASL_FAIL,
+    }
+}
+
+fn sasl_lib_init_raw(appname: &CStr, config_dir: Option<&CStr>) -> c_int {
+    use sasl2_sys::prelude::sasl_server_init;
+
+    *SASL_CONF_DIR.lock().unwrap() = config_dir.map(CStr::to_owned);
+    let callbacks = match config_dir {
+        Some(_) => SASL_LIB_CALLBACKS.0.as_ptr(),
+        None => ptr::null(),
+    };
+    // SAFETY: sasl_server_init() is C function. The appname is a valid C
+    // string, and the callbacks are static.
+    unsafe { sasl_server_init(callbacks, appname.as_ptr()) }
+}
+
+fn sasl_lib_done_raw() {
+    // SAFETY: sasl_done() is C function. It is called after all the sasl
+    // connections have been disposed.
+    unsafe { sasl2_sys::sasl::sasl_done() }
+}
+
+/// Create the sasl connection of the vnc service for a client.
+///
+/// # Arguments
+///
+/// * `realm` - realm of the sasl server, None means the default realm of the host.
+/// * `local_addr` - local address of the transport, in the format of sasl.
+/// * `remote_addr` - remote address of the transport, in the format of sasl.
+/// * `conn` - the created sasl connection.
+fn sasl_server_conn_new(
+    realm: Option<&str>,
+    local_addr: Option<&CStr>,
+    remote_addr: Option<&CStr>,
+    conn: &mut *mut sasl_conn_t,
+) -> Result<()> {
+    let service = CString::new(SERVICE)?;
+    let realm = realm.map(CString::new).transpose()?;
+    let err = sasl_server_new_raw(&service, realm.as_deref(), local_addr, remote_addr, conn);
+    if err != SASL_OK {
+        return Err(anyhow!(VncError::AuthFailed(
+            "sasl_server_init".to_string(),
+            format!("SASL_FAIL error code {}", err)
+        )));
+    }
+    Ok(())
+}
+
+fn sasl_server_new_raw(
+    service: &CStr,
+    realm: Option<&CStr>,
+    local_addr: Option<&CStr>,
+    remote_addr: Option<&CStr>,
+    conn: &mut *mut sasl_conn_t,
+) -> c_int {
+    use sasl2_sys::prelude::{sasl_server_new, SASL_SUCCESS_DATA};
+
+    let as_ptr = |s: Option<&CStr>| s.map_or(ptr::null(), |s| s.as_ptr());
+    // SAFETY: sasl_server_new() is C function. All the strings are valid C
+    // strings or null. Memory will be allocated for the connection inside the function.
+    unsafe {
+        sasl_server_new(
+            service.as_ptr(),
+            ptr::null(),
+            as_ptr(realm),
+            as_ptr(local_addr),
+            as_ptr(remote_addr),
+            ptr::null(),
+            SASL_SUCCESS_DATA,
+            conn,
+        )
+    }
+}
+
+fn sasl_conn_dispose(conn: &mut *mut sasl_conn_t) {
+    use sasl2_sys::prelude::sasl_dispose;
+
+    // SAFETY: sasl_dispose() is C function. The sasl connection is not null,
+    // and it is set to null after disposed.
+    unsafe { sasl_dispose(conn) }
+}
+
+/// Signature of `sasl_encode` and `sasl_decode`.
+type SaslCodec = unsafe extern "C" fn(
+    *mut sasl_conn_t,
+    *const c_char,
+    c_uint,
+    *mut *const c_char,
+    *mut c_uint,
+) -> c_int;
+
+/// Transform the data by the security layer of sasl.
+///
+/// # Arguments
+///
+/// * `conn` - the sasl connection which has negotiated the security layer.
+/// * `codec` - `sasl_encode` or `sasl_decode`.
+/// * `name` - name of the codec.
+/// * `input` - the data to be transformed.
+fn sasl_codec_data(
+    conn: *mut sasl_conn_t,
+    codec: SaslCodec,
+    name: &str,
+    input: &[u8],
+) -> Result<Vec<u8>> {
+    if input.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut output: *const c_char = ptr::null();
+    let mut output_len: c_uint = 0;
+    // SAFETY: The codec is C function. The sasl connection is not null, and
+    // the output is kept by the connection until the next call of the codec.
+    let err = unsafe {
+        codec(
+            conn,
+            input.as_ptr() as *const c_char,
+            input.len() as c_uint,
+            &mut output,
+            &mut output_len,
+        )
+    };
+    if err != SASL_OK {
+        return Err(anyhow!(VncError::AuthFailed(
+            name.to_string(),
+            format!("SASL_FAIL error code {}", err)
+        )));
+    }
+    if output.is_null() || output_len == 0 {
+        return Ok(Vec::new());
+    }
+    // SAFETY: The output is not null and has `output_len` bytes.
+    let data = unsafe { std::slice::from_raw_parts(output as *const u8, output_len as usize) };
+    Ok(data.to_vec())
+}
+
+/// Encode the data sent to client by the security layer of sasl.
+pub fn sasl_encode_data(conn: *mut sasl_conn_t, input: &[u8]) -> Result<Vec<u8>> {
+    use sasl2_sys::prelude::sasl_encode;
+
+    sasl_codec_data(conn, sasl_encode, "sasl_encode", input)
+}
+
+/// Decode the data received from client by the security layer of sasl, the
+/// incomplete packet is kept by sasl until the rest of it is received.
+pub fn sasl_decode_data(conn: *mut sasl_conn_t, input: &[u8]) -> Result<Vec<u8>> {
+    use sasl2_sys::prelude::sasl_decode;
+
+    sasl_codec_data(conn, sasl_decode, "sasl_decode", input)
+}
+
+/// Build the message of the sasl server output which is sent to client. The
+/// output may be a binary token with NUL bytes, so it is sent as is with its
+/// length.
+///
+/// The length from libsasl is checked before the output is read, as the
+/// bytes are read from the pointer directly.
+///
+/// # Arguments
+///
+/// * `serverout` - the output of sasl server, which has `serverout_len` bytes.
+/// * `serverout_len` - the length of the output.
+fn sasl_server_out(serverout: *const c_char, serverout_len: c_uint) -> Result<Vec<u8>> {
+    if serverout_len > SASL_DATA_MAX_LEN {
+        return Err(anyhow!(VncError::AuthFailed(
+            "sasl_server_out".to_string(),
+            format!("SASL data length {} is too long", serverout_len)
+        )));
+    }
+    if serverout_len == 0 {
+        return Ok(0_u32.to_be_bytes().to_vec());
+    }
+    if serverout.is_null() {
+        return Err(anyhow!(VncError::AuthFailed(
+            "sasl_server_out".to_string(),
+            format!("SASL data of length {} is null", serverout_len)
+        )));
+    }
+    // SAFETY: The output of sasl server is not null and has `serverout_len`
+    // bytes, it is valid until the next call of sasl server.
+    let data =
+        unsafe { std::slice::from_raw_parts(serverout as *const u8, serverout_len as usize) };
+    let mut buf = serverout_len.to_be_bytes().to_vec();
+    buf.extend_from_slice(data);
+    Ok(buf)
+}
+
+/// Get the sasl username, invalid UTF-8 sequences are replaced.
+///
+/// # Safety
+///
+/// The `username` must be a valid pointer to a C string.
+unsafe fn sasl_username(username: *const c_char) -> String {
+    CStr::from_ptr(username).to_string_lossy().into_owned()
+}
+
+/// Build the security properties of sasl.
+///
+/// # Arguments
+///
+/// * `want_ssf` - whether the security layer of sasl is wanted.
+/// * `saslauth` - configuration of the security properties.
+fn sasl_security_props(want_ssf: bool, saslauth: &SaslAuth) -> Result<sasl_security_properties_t> {
+    if want_ssf && saslauth.maxbufsize == 0 {
+        return Err(anyhow!(VncError::AuthFailed(
+            "set_ssf_for_sasl".to_string(),
+            "maxbufsize must be nonzero when ssf layer is wanted".to_string()
+        )));
+    }
+
+    let (min_ssf, max_ssf) = if want_ssf {
+        (saslauth.min_ssf, saslauth.max_ssf)
+    } else {
+        (0, 0)
+    };
+    let props_name = ptr::null_mut() as *mut *const c_char;
+    let props_value = ptr::null_mut() as *mut *const c_char;
+    Ok(sasl_security_properties_t {
+        min_ssf,
+        max_ssf,
+        maxbufsize: saslauth.maxbufsize,
+        security_flags: saslauth.security_flags,
+        property_names: props_name,
+        property_values: props_value,
+    })
+}
+
+/// Ip addresses of the transport which the vnc client is connected with.
+pub trait TransportAddr {
+    /// Local address of the transport, None if the transport has no ip address.
+    fn local_ip_addr(&self) -> std::io::Result<Option<SocketAddr>>;
+    /// Remote address of the transport, None if the transport has no ip address.
+    fn peer_ip_addr(&self) -> std::io::Result<Option<SocketAddr>>;
+}
+
+impl TransportAddr for TcpStream {
+    fn local_ip_addr(&self) -> std::io::Result<Option<SocketAddr>> {
+        self.local_addr().map(Some)
+    }
+
+    fn peer_ip_addr(&self) -> std::io::Result<Option<SocketAddr>> {
+        self.peer_addr().map(Some)
+    }
+}
+
+impl TransportAddr for UnixStream {
+    fn local_ip_addr(&self) -> std::io::Result<Option<SocketAddr>> {
+        Ok(None)
+    }
+
+    fn peer_ip_addr(&self) -> std::io::Result<Option<SocketAddr>> {
+        Ok(None)
+    }
+}
+
+/// Format socket address as "ip;port" for ipv4, and "[ip];port" for ipv6,
+/// which is the convention of sasl.
+fn sasl_addr_string(addr: &SocketAddr) -> String {
+    match addr {
+        SocketAddr::V4(addr) => format!("{};{}", addr.ip(), addr.port()),
+        SocketAddr::V6(addr) => format!("[{}];{}", addr.ip(), addr.port()),
+    }
+}
+
+/// Get the local and remote addresses passed to sasl, None for the transport
+/// without ip address, such as unix socket.
+fn sasl_transport_addrs(
+    transport: &dyn TransportAddr,
+) -> Result<(Option<CString>, Option<CString>)> {
+    let to_cstring = |addr: std::io::Result<Option<SocketAddr>>| -> Result<Option<CString>> {
+        let addr = addr.map_err(|e| {
+            anyhow!(VncError::AuthFailed(
+                "sasl_server_init".to_string(),
+                format!("Failed to get socket address: {}", e)
+            ))
+        })?;
+        match addr {
+            Some(addr) => Ok(Some(CString::new(sasl_addr_string(&addr))?)),
+            None => Ok(None),
+        }
+    };
+    let local_addr = to_cstring(transport.local_ip_addr())?;
+    let remote_addr = to_cstring(transport.peer_ip_addr())?;
+    Ok((local_addr, remote_addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sasl2_sys::sasl::{
+        SASL_DEFUSERREALM, SASL_SEC_NOANONYMOUS, SASL_SEC_NOPLAINTEXT, SASL_SERVICE,
+    };
+    use std::{env, fs};
+
+    /// Get the string property of the sasl connection.
+    fn conn_str_prop(conn: *mut sasl_conn_t, prop: c_uint) -> Option<String> {
+        let mut val: *const c_void = ptr::null();
+        // SAFETY: The sasl connection is not null, and the property is kept
+        // by the connection.
+        let err = unsafe { sasl_getprop(conn, prop as c_int, &mut val) };
+        if err != SASL_OK || val.is_null() {
+            return None;
+        }
+        // SAFETY: The string property is a C string.
+        Some(unsafe { sasl_username(val as *const c_char) })
+    }
+
+    /// Get the mechanisms offered by the sasl connection.
+    fn conn_mech_list(conn: *mut sasl_conn_t) -> Option<String> {
+        let sep = CString::new(",").unwrap();
+        let mut mechlist: *const c_char = ptr::null();
+        // SAFETY: The sasl connection is not null, and the list is kept by
+        // the connection.
+        let err = unsafe {
+            sasl_listmech(
+                conn,
+                ptr::null(),
+                ptr::null(),
+                sep.as_ptr(),
+                ptr::null(),
+                &mut mechlist,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if err != SASL_OK || mechlist.is_null() {
+            return None;
+        }
+        // SAFETY: The mechanism list is a C string.
+        Some(unsafe { sasl_username(mechlist) })
+    }
+
+    #[test]
+    fn test_sasl_effective_ssf() {
+        let mut saslconfig = SaslConfig::default();
+        assert_eq!(saslconfig.effective_ssf(), 0);
+        assert!(!saslconfig.is_encrypted());
+
+        saslconfig.run_ssf = 1;
+        assert_eq!(saslconfig.effective_ssf(), 1);
+        assert!(saslconfig.is_encrypted());
+
+        // Encrypted by tls only.
+        let mut saslconfig = SaslConfig::default();
+        saslconfig.external_ssf = 256;
+        assert_eq!(saslconfig.effective_ssf(), 0);
+        assert!(saslconfig.is_encrypted());
+    }
+
+    #[test]
+    fn test_sasl_config_drop() {
+        let _guard = SASL_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        sasl_lib_init(APP_NAME, None).unwrap();
+        let new_conn = || {
+            let mut saslconfig = SaslConfig::default();
+            sasl_server_conn_new(None, None, None, &mut saslconfig.sasl_conn).unwrap();
+            saslconfig
+        };
+        drop(SaslConfig::default());
+
+        // Each client disposes its own connection only, the connection of
+        // the other client is still usable.
+        let saslconfig1 = new_conn();
+        let saslconfig2 = new_conn();
+        assert_ne!(saslconfig1.sasl_conn, saslconfig2.sasl_conn);
+        let mech_list = conn_mech_list(saslconfig1.sasl_conn);
+        assert!(mech_list.is_some());
+        drop(saslconfig1);
+        assert_eq!(conn_mech_list(saslconfig2.sasl_conn), mech_list);
+
+        // The connection disposed already is not disposed again.
+        let mut saslconfig = new_conn();
+        saslconfig.sasl_stage = SaslStage::SaslServerStep;
+        saslconfig.dispose_conn();
+        assert!(saslconfig.sasl_conn.is_null());
+        assert_eq!(saslconfig.sasl_stage, SaslStage::SaslServerStart);
+        drop(saslconfig);
+
+        drop(saslconfig2);
+        sasl_lib_done();
+    }
+
+    #[test]
+    fn test_sasl_server_conn_new() {
+        let _guard = SASL_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        sasl_lib_init(APP_NAME, None).unwrap();
+
+        // The default realm of the host.
+        let saslauth = SaslAuth::new(vec!["alice".to_string()], false);
+        assert!(saslauth.realm.is_none());
+        let mut saslconfig = SaslConfig::default();
+        sasl_server_conn_new(
+            saslauth.realm.as_deref(),
+            None,
+            None,
+            &mut saslconfig.sasl_conn,
+        )
+        .unwrap();
+        assert!(!saslconfig.sasl_conn.is_null());
+        assert_eq!(
+            conn_str_prop(saslconfig.sasl_conn, SASL_SERVICE),
+            Some(SERVICE.to_string())
+        );
+        assert_eq!(conn_str_prop(saslconfig.sasl_conn, SASL_DEFUSERREALM), None);
+
+        // The realm of kerberos is forwarded to sasl.
+        let mut saslauth = SaslAuth::new(vec!["alice@EXAMPLE.COM".to_string()], false);
+        saslauth.realm = Some("EXAMPLE.COM".to_string());
+        // The previous connection is disposed here, before the library is done.
+        saslconfig = SaslConfig::default();
+        let local = CString::new("127.0.0.1;5900").unwrap();
+        let remote = CString::new("127.0.0.1;40000").unwrap();
+        sasl_server_conn_new(
+            saslauth.realm.as_deref(),
+            Some(&local),
+            Some(&remote),
+            &mut saslconfig.sasl_conn,
+        )
+        .unwrap();
+        assert_eq!(
+            conn_str_prop(saslconfig.sasl_conn, SASL_DEFUSERREALM),
+            Some("EXAMPLE.COM".to_string())
+        );
+
+        // The realm can not be passed as C string.
+        saslauth.realm = Some("EXAMPLE\0.COM".to_string());
+        saslconfig = SaslConfig::default();
+        assert!(sasl_server_conn_new(
+            saslauth.realm.as_deref(),
+            None,
+            None,
+            &mut saslconfig.sasl_conn
+        )
+        .is_err());
+        assert!(saslconfig.sasl_conn.is_null());
+
+        drop(saslconfig);
+        sasl_lib_done();
+    }
+
+    #[test]
+    fn test_sasl_authorize() {
+        let saslauth = SaslAuth::new(vec!["alice".to_string(), "bob".to_string()], false);
+        // Match.
+        assert_eq!(saslauth.authorize("alice"), Some("alice".to_string()));
+        assert_eq!(saslauth.authorize("bob"), Some("bob".to_string()));
+        // Mismatch.
+        assert_eq!(saslauth.authorize("carol"), None);
+        assert_eq!(saslauth.authorize(""), None);
+
+        // Wildcard.
+        let saslauth = SaslAuth::new(vec!["alice".to_string()], true);
+        assert_eq!(saslauth.authorize("alice"), Some("alice".to_string()));
+        assert_eq!(
+            saslauth.authorize("carol"),
+            Some(SASL_IDENTITY_WILDCARD.to_string())
+        );
+    }
+
+    #[test]
+    fn test_sasl_security_props() {
+        let mut saslauth = SaslAuth::new(vec!["alice".to_string()], false);
+        assert_eq!(saslauth.maxbufsize, DEFAULT_SASL_MAXBUFSIZE);
+        let props = sasl_security_props(true, &saslauth).unwrap();
+        assert_eq!(props.min_ssf, DEFAULT_SASL_MIN_SSF);
+        assert_eq!(props.max_ssf, DEFAULT_SASL_MAX_SSF);
+        assert_eq!(props.security_flags, 0);
+
+        saslauth.maxbufsize = 65536;
+        let props = sasl_security_props(true, &saslauth).unwrap();
+        assert_eq!(props.maxbufsize, 65536);
+        assert_eq!(props.min_ssf, DEFAULT_SASL_MIN_SSF);
+
+        // Ssf layer is disabled.
+        let props = sasl_security_props(false, &saslauth).unwrap();
+        assert_eq!(props.maxbufsize, 65536);
+        assert_eq!(props.min_ssf, 0);
+        assert_eq!(props.max_ssf, 0);
+
+        // Configured ssf range and security flags.
+        saslauth.min_ssf = 112;
+        saslauth.max_ssf = 256;
+        saslauth.security_flags = SASL_SEC_NOPLAINTEXT | SASL_SEC_NOANONYMOUS;
+        let props = sasl_security_props(true, &saslauth).unwrap();
+        assert_eq!(props.min_ssf, 112);
+        assert_eq!(props.max_ssf, 256);
+        assert_eq!(
+            props.security_flags,
+            SASL_SEC_NOPLAINTEXT | SASL_SEC_NOANONYMOUS
+        );
+        // Security flags are kept when ssf layer is disabled.
+        let props = sasl_security_props(false, &saslauth).unwrap();
+        assert_eq!(
+            props.security_flags,
+            SASL_SEC_NOPLAINTEXT | SASL_SEC_NOANONYMOUS
+        );
+
+        // Maxbufsize must be nonzero if ssf layer is wanted.
+        saslauth.maxbufsize = 0;
+        assert!(sasl_security_props(true, &saslauth).is_err());
+        assert!(sasl_security_props(false, &saslauth).is_ok());
+    }
+
+    #[test]
+    fn test_sasl_config_path() {
+        let _guard = SASL_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let new_conn = || {
+            let mut saslconfig = SaslConfig::default();
+            sasl_server_conn_new(None, None, None, &mut saslconfig.sasl_conn).unwrap();
+            saslconfig
+        };
+
+        // Default appname and config path.
+        let mut saslauth = SaslAuth::new(vec!["alice".to_string()], false);
+        assert!(saslauth.set_config_path("", "").is_ok());
+        assert_eq!(saslauth.appname, APP_NAME);
+        assert_eq!(saslauth.config_dir, None);
+        sasl_lib_init(&saslauth.appname, saslauth.config_dir.as_deref()).unwrap();
+        drop(new_conn());
+        sasl_lib_done();
+
+        // The config file does not exist.
+        let dir = env::temp_dir().join("stratovirt_test_sasl_conf");
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+        assert!(saslauth.set_config_path("vm1", dir_str).is_err());
+
+        fs::write(dir.join("vm1.conf"), "mech_list: plain\n").unwrap();
+        assert!(saslauth.set_config_path("vm1", dir_str).is_ok());
+        assert_eq!(saslauth.appname, "vm1");
+        assert_eq!(saslauth.config_dir, Some(dir_str.to_string()));
+        // The mechanisms are restricted by the config file of the appname.
+        sasl_lib_init(&saslauth.appname, saslauth.config_dir.as_deref()).unwrap();
+        let saslconfig = new_conn();
+        assert_eq!(
+            conn_mech_list(saslconfig.sasl_conn),
+            Some("PLAIN".to_string())
+        );
+        drop(saslconfig);
+        sasl_lib_done();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sasl_server_out() {
+        // Binary token with NUL bytes, such as GSSAPI.
+        let token: &[u8] = &[0x60, 0x00, 0x82, 0x00, 0x00, 0xff, 0x01];
+        let buf = sasl_server_out(token.as_ptr() as *const c_char, token.len() as c_uint).unwrap();
+        assert_eq!(buf[..4], (token.len() as u32).to_be_bytes());
+        assert_eq!(&buf[4..], token);
+
+        // Invalid UTF-8 sequences are sent as is.
+        let token: &[u8] = &[0xc3, 0x28, 0xa0, 0xa1, 0xe2, 0x82, 0xfe, 0xff];
+        let buf = sasl_server_out(token.as_ptr() as *const c_char, token.len() as c_uint).unwrap();
+        assert_eq!(buf.len(), 4 + token.len());
+        assert_eq!(buf[..4], [0, 0, 0, 8]);
+        assert_eq!(&buf[4..], token);
+
+        // Only the given length is sent.
+        let token = b"challenge\0";
+        let buf = sasl_server_out(token.as_ptr() as *const c_char, 9).unwrap();
+        assert_eq!(
+            buf,
+            [9_u32.to_be_bytes().to_vec(), b"challenge".to_vec()].concat()
+        );
+
+        // No output.
+        assert_eq!(
+            sasl_server_out(token.as_ptr() as *const c_char, 0).unwrap(),
+            vec![0; 4]
+        );
+        assert_eq!(sasl_server_out(ptr::null(), 0).unwrap(), vec![0; 4]);
+
+        // The length is checked before the output is read, so the bytes
+        // out of the token are never touched.
+        let auth_failed = |serverout: *const c_char, serverout_len: c_uint| {
+            let err = sasl_server_out(serverout, serverout_len).unwrap_err();
+            matches!(
+                err.downcast_ref::<VncError>(),
+                Some(VncError::AuthFailed(func, _)) if func == "sasl_server_out"
+            )
+        };
+        assert!(auth_failed(
+            token.as_ptr() as *const c_char,
+            SASL_DATA_MAX_LEN + 1
+        ));
+        // Null output with length.
+        assert!(auth_failed(ptr::null(), 9));
+    }
+
+    #[test]
+    fn test_sasl_username() {
+        let username = b"alice\0";
+        // SAFETY: The username is a C string.
+        let name = unsafe { sasl_username(username.as_ptr() as *const c_char) };
+        assert_eq!(name, "alice");
+
+        // Invalid UTF-8.
+        let username = b"al\xffice\0";
+        // SAFETY: The username is a C string.
+        let name = unsafe { sasl_username(username.as_ptr() as *const c_char) };
+        assert_eq!(name, "al\u{fffd}ice");
+    }
+
+    #[test]
+    fn test_sasl_lib_refcount() {
+        let _guard = SASL_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let new_conn = || {
+            let mut saslconfig = SaslConfig::default();
+            sasl_server_conn_new(None, None, None, &mut saslconfig.sasl_conn).map(|_| saslconfig)
+        };
+        assert_eq!(sasl_lib_users(), 0);
+
+        // The library is initialized by the first client only.
+        sasl_lib_init(APP_NAME, None).unwrap();
+        sasl_lib_init(APP_NAME, None).unwrap();
+        assert_eq!(sasl_lib_users(), 2);
+
+        // The library is released by the last client only.
+        sasl_lib_done();
+        assert_eq!(sasl_lib_users(), 1);
+        assert!(new_conn().is_ok());
+        sasl_lib_done();
+        assert_eq!(sasl_lib_users(), 0);
+        assert!(new_conn().is_err());
+        // Unpaired release is ignored.
+        sasl_lib_done();
+        assert_eq!(sasl_lib_users(), 0);
+
+        // Init again after released.
+        sasl_lib_init(APP_NAME, None).unwrap();
+        assert!(new_conn().is_ok());
+        sasl_lib_done();
+        assert_eq!(sasl_lib_users(), 0);
+    }
+
+    struct MockStream {
+        local: std::io::Result<Option<SocketAddr>>,
+        peer: std::io::Result<Option<SocketAddr>>,
+    }
+
+    impl TransportAddr for MockStream {
+        fn local_ip_addr(&self) -> std::io::Result<Option<SocketAddr>> {
+            match &self.local {
+                Ok(addr) => Ok(*addr),
+                Err(e) => Err(std::io::Error::from(e.kind())),
+            }
+        }
+
+        fn peer_ip_addr(&self) -> std::io::Result<Option<SocketAddr>> {
+            match &self.peer {
+                Ok(addr) => Ok(*addr),
+                Err(e) => Err(std::io::Error::from(e.kind())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sasl_transport_addrs() {
+        let to_str = |addr: Option<CString>| addr.map(|a| a.into_string().unwrap());
+
+        // Tcp with ipv4.
+        let stream = MockStream {
+            local: Ok(Some("127.0.0.1:5900".parse().unwrap())),
+            peer: Ok(Some("192.168.1.2:40000".parse().unwrap())),
+        };
+        let (local, remote) = sasl_transport_addrs(&stream).unwrap();
+        assert_eq!(to_str(local), Some("127.0.0.1;5900".to_string()));
+        assert_eq!(to_str(remote), Some("192.168.1.2;40000".to_string()));
+
+        // Tcp with ipv6.
+        let stream = MockStream {
+            local: Ok(Some("[::1]:5900".parse().unwrap())),
+            peer: Ok(Some("[fe80::1:2]:40000".parse().unwrap())),
+        };
+        let (local, remote) = sasl_transport_addrs(&stream).unwrap();
+        assert_eq!(to_str(local), Some("[::1];5900".to_string()));
+        assert_eq!(to_str(remote), Some("[fe80::1:2];40000".to_string()));
+
+        // Unix socket has no ip address.
+        let stream = MockStream {
+            local: Ok(None),
+            peer: Ok(None),
+        };
+        assert_eq!(sasl_transport_addrs(&stream).unwrap(), (None, None));
+        let (unix_stream, _) = UnixStream::pair().unwrap();
+        assert_eq!(sasl_transport_addrs(&unix_stream).unwrap(), (None, None));
+
+        // Failed to get address.
+        let stream = MockStream {
+            local: Ok(Some("127.0.0.1:5900".parse().unwrap())),
+            peer: Err(std::io::Error::from(std::io::ErrorKind::NotConnected)),
+        };
+        assert!(sasl_transport_addrs(&stream).is_err());
+    }
 }
@@ -0,0 +1,385 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use crate::{
+    error::VncError,
+    vnc::client_io::{vnc_flush, vnc_write, ClientIoHandler},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Length of challenge in vnc authentication.
+pub const VNC_AUTH_CHALLENGE_SIZE: usize = 16;
+/// Only the first 8 bytes of password are used as the des key.
+const VNC_PASSWORD_MAX_LEN: usize = 8;
+
+/// Initial permutation.
+const DES_IP: [u8; 64] = [
+    58, 50, 42, 34, 26, 18, 10, 2, 60, 52, 44, 36, 28, 20, 12, 4, 62, 54, 46, 38, 30, 22, 14, 6,
+    64, 56, 48, 40, 32, 24, 16, 8, 57, 49, 41, 33, 25, 17, 9, 1, 59, 51, 43, 35, 27, 19, 11, 3, 61,
+    53, 45, 37, 29, 21, 13, 5, 63, 55, 47, 39, 31, 23, 15, 7,
+];
+/// Final permutation.
+const DES_FP: [u8; 64] = [
+    40, 8, 48, 16, 56, 24, 64, 32, 39, 7, 47, 15, 55, 23, 63, 31, 38, 6, 46, 14, 54, 22, 62, 30,
+    37, 5, 45, 13, 53, 21, 61, 29, 36, 4, 44, 12, 52, 20, 60, 28, 35, 3, 43, 11, 51, 19, 59, 27,
+    34, 2, 42, 10, 50, 18, 58, 26, 33, 1, 41, 9, 49, 17, 57, 25,
+];
+/// Expansion of the right half.
+const DES_E: [u8; 48] = [
+    32, 1, 2, 3, 4, 5, 4, 5, 6, 7, 8, 9, 8, 9, 10, 11, 12, 13, 12, 13, 14, 15, 16, 17, 16, 17, 18,
+    19, 20, 21, 20, 21, 22, 23, 24, 25, 24, 25, 26, 27, 28, 29, 28, 29, 30, 31, 32, 1,
+];
+/// Permutation after the substitution.
+const DES_P: [u8; 32] = [
+    16, 7, 20, 21, 29, 12, 28, 17, 1, 15, 23, 26, 5, 18, 31, 10, 2, 8, 24, 14, 32, 27, 3, 9, 19,
+    13, 30, 6, 22, 11, 4, 25,
+];
+/// Permuted choice 1 of the key schedule.
+const DES_PC1: [u8; 56] = [
+    57, 49, 41, 33, 25, 17, 9, 1, 58, 50, 42, 34, 26, 18, 10, 2, 59, 51, 43, 35, 27, 19, 11, 3, 60,
+    52, 44, 36, 63, 55, 47, 39, 31, 23, 15, 7, 62, 54, 46, 38, 30, 22, 14, 6, 61, 53, 45, 37, 29,
+    21, 13, 5, 28, 20, 12, 4,
+];
+/// Permuted choice 2 of the key schedule.
+const DES_PC2: [u8; 48] = [
+    14, 17, 11, 24, 1, 5, 3, 28, 15, 6, 21, 10, 23, 19, 12, 4, 26, 8, 16, 7, 27, 20, 13, 2, 41, 52,
+    31, 37, 47, 55, 30, 40, 51, 45, 33, 48, 44, 49, 39, 56, 34, 53, 46, 42, 50, 36, 29, 32,
+];
+/// Left rotations of each round in the key schedule.
+const DES_SHIFTS: [u32; 16] = [1, 1, 2, 2, 2, 2, 2, 2, 1, 2, 2, 2, 2, 2, 2, 1];
+/// Substitution boxes.
+const DES_SBOX: [[u8; 64]; 8] = [
+    [
+        14, 4, 13, 1, 2, 15, 11, 8, 3, 10, 6, 12, 5, 9, 0, 7, 0, 15, 7, 4, 14, 2, 13, 1, 10, 6, 12,
+        11, 9, 5, 3, 8, 4, 1, 14, 8, 13, 6, 2, 11, 15, 12, 9, 7, 3, 10, 5, 0, 15, 12, 8, 2, 4, 9,
+        1, 7, 5, 11, 3, 14, 10, 0, 6, 13,
+    ],
+    [
+        15, 1, 8, 14, 6, 11, 3, 4, 9, 7, 2, 13, 12, 0, 5, 10, 3, 13, 4, 7, 15, 2, 8, 14, 12, 0, 1,
+        10, 6, 9, 11, 5, 0, 14, 7, 11, 10, 4, 13, 1, 5, 8, 12, 6, 9, 3, 2, 15, 13, 8, 10, 1, 3, 15,
+        4, 2, 11, 6, 7, 12, 0, 5, 14, 9,
+    ],
+    [
+        10, 0, 9, 14, 6, 3, 15, 5, 1, 13, 12, 7, 11, 4, 2, 8, 13, 7, 0, 9, 3, 4, 6, 10, 2, 8, 5,
+        14, 12, 11, 15, 1, 13, 6, 4, 9, 8, 15, 3, 0, 11, 1, 2, 12, 5, 10, 14, 7, 1, 10, 13, 0, 6,
+        9, 8, 7, 4, 15, 14, 3, 11, 5, 2, 12,
+    ],
+    [
+        7, 13, 14, 3, 0, 6, 9, 10, 1, 2, 8, 5, 11, 12, 4, 15, 13, 8, 11, 5, 6, 15, 0, 3, 4, 7, 2,
+        12, 1, 10, 14, 9, 10, 6, 9, 0, 12, 11, 7, 13, 15, 1, 3, 14, 5, 2, 8, 4, 3, 15, 0, 6, 10, 1,
+        13, 8, 9, 4, 5, 11, 12, 7, 2, 14,
+    ],
+    [
+        2, 12, 4, 1, 7, 10, 11, 6, 8, 5, 3, 15, 13, 0, 14, 9, 14, 11, 2, 12, 4, 7, 13, 1, 5, 0, 15,
+        10, 3, 9, 8, 6, 4, 2, 1, 11, 10, 13, 7, 8, 15, 9, 12, 5, 6, 3, 0, 14, 11, 8, 12, 7, 1, 14,
+        2, 13, 6, 15, 0, 9, 10, 4, 5, 3,
+    ],
+    [
+        12, 1, 10, 15, 9, 2, 6, 8, 0, 13, 3, 4, 14, 7, 5, 11, 10, 15, 4, 2, 7, 12, 9, 5, 6, 1, 13,
+        14, 0, 11, 3, 8, 9, 14, 15, 5, 2, 8, 12, 3, 7, 0, 4, 10, 1, 13, 11, 6, 4, 3, 2, 12, 9, 5,
+        15, 10, 11, 14, 1, 7, 6, 0, 8, 13,
+    ],
+    [
+        4, 11, 2, 14, 15, 0, 8, 13, 3, 12, 9, 7, 5, 10, 6, 1, 13, 0, 11, 7, 4, 9, 1, 10, 14, 3, 5,
+        12, 2, 15, 8, 6, 1, 4, 11, 13, 12, 3, 7, 14, 10, 15, 6, 8, 0, 5, 9, 2, 6, 11, 13, 8, 1, 4,
+        10, 7, 9, 5, 0, 15, 14, 2, 3, 12,
+    ],
+    [
+        13, 2, 8, 4, 6, 15, 11, 1, 10, 9, 3, 14, 5, 0, 12, 7, 1, 15, 13, 8, 10, 3, 7, 4, 12, 5, 6,
+        11, 0, 14, 9, 2, 7, 11, 4, 1, 9, 12, 14, 2, 0, 6, 10, 13, 15, 3, 5, 8, 2, 1, 14, 7, 4, 10,
+        8, 13, 15, 12, 9, 0, 3, 5, 6, 11,
+    ],
+];
+
+/// Permute the `from` bits of `input` according to `table`, bit 1 is the most significant bit.
+fn des_permute(input: u64, from: u32, table: &[u8]) -> u64 {
+    table.iter().fold(0_u64, |out, &pos| {
+        (out << 1) | ((input >> (from - pos as u32)) & 1)
+    })
+}
+
+/// The feistel function of each round.
+fn des_feistel(right: u32, subkey: u64) -> u32 {
+    let expanded = des_permute(right as u64, 32, &DES_E) ^ subkey;
+    let mut out = 0_u32;
+    for (i, sbox) in DES_SBOX.iter().enumerate() {
+        let bits = ((expanded >> (42 - 6 * i)) & 0x3f) as usize;
+        let row = ((bits & 0x20) >> 4) | (bits & 1);
+        let col = (bits >> 1) & 0xf;
+        out = (out << 4) | sbox[row * 16 + col] as u32;
+    }
+    des_permute(out as u64, 32, &DES_P) as u32
+}
+
+/// Encrypt one block with DES.
+fn des_encrypt(key: &[u8; 8], block: &[u8; 8]) -> [u8; 8] {
+    let key = des_permute(u64::from_be_bytes(*key), 64, &DES_PC1);
+    let mut c = (key >> 28) as u32 & 0x0fff_ffff;
+    let mut d = key as u32 & 0x0fff_ffff;
+
+    let data = des_permute(u64::from_be_bytes(*block), 64, &DES_IP);
+    let mut left = (data >> 32) as u32;
+    let mut right = data as u32;
+    for shift in DES_SHIFTS.iter() {
+        c = ((c << shift) | (c >> (28 - shift))) & 0x0fff_ffff;
+        d = ((d << shift) | (d >> (28 - shift))) & 0x0fff_ffff;
+        let subkey = des_permute(((c as u64) << 28) | d as u64, 56, &DES_PC2);
+        let next = left ^ des_feistel(right, subkey);
+        left = right;
+        right = next;
+    }
+    let data = ((right as u64) << 32) | left as u64;
+    des_permute(data, 64, &DES_FP).to_be_bytes()
+}
+
+/// Password for vnc authentication.
+#[derive(Debug, Clone, Default)]
+pub struct VncPassword {
+    /// Current password, no client can be authenticated if it is not set.
+    pub password: Option<String>,
+    /// Time when the password expires, None means never.
+    pub expire: Option<Instant>,
+}
+
+impl VncPassword {
+    /// Whether the password is expired.
+    pub fn is_expired(&self) -> bool {
+        self.expire.map_or(false, |expire| Instant::now() >= expire)
+    }
+
+    /// Check the response of challenge sent by client.
+    /// Return the reason if authentication failed.
+    pub fn check_response(
+        &self,
+        challenge: &[u8],
+        response: &[u8],
+    ) -> std::result::Result<(), String> {
+        let password = match &self.password {
+            Some(password) => password,
+            None => return Err("password is not set".to_string()),
+        };
+        if self.is_expired() {
+            return Err("password is expired".to_string());
+        }
+        if challenge.len() != VNC_AUTH_CHALLENGE_SIZE || response.len() != VNC_AUTH_CHALLENGE_SIZE {
+            return Err("invalid challenge response".to_string());
+        }
+        if vnc_encrypt_challenge(password, challenge) != response {
+            return Err("password is incorrect".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Encrypt the challenge with password as the des key.
+/// The bits of each byte in password are reversed according to the vnc authentication.
+fn vnc_encrypt_challenge(password: &str, challenge: &[u8]) -> Vec<u8> {
+    let mut key = [0_u8; VNC_PASSWORD_MAX_LEN];
+    for (k, p) in key.iter_mut().zip(password.as_bytes().iter()) {
+        *k = p.reverse_bits();
+    }
+    let mut response = Vec::with_capacity(challenge.len());
+    for chunk in challenge.chunks(8) {
+        let mut block = [0_u8; 8];
+        block[..chunk.len()].copy_from_slice(chunk);
+        response.extend_from_slice(&des_encrypt(&key, &block));
+    }
+    response
+}
+
+/// Parse the expire time of password.
+///
+/// # Arguments
+///
+/// * `time` - "now" expires immediately, "never" disables expiry, "+N" expires after N
+///   seconds, and "N" expires at N seconds since the Epoch.
+pub fn parse_expire_time(time: &str) -> Result<Option<Instant>> {
+    let now = Instant::now();
+    match time {
+        "now" => Ok(Some(now)),
+        "never" => Ok(None),
+        _ => {
+            if let Some(secs) = time.strip_prefix('+') {
+                let secs = secs
+                    .parse::<u64>()
+                    .with_context(|| format!("Invalid expire time {}", time))?;
+                let expire = now
+                    .checked_add(Duration::from_secs(secs))
+                    .with_context(|| format!("Expire time {} is out of range", time))?;
+                return Ok(Some(expire));
+            }
+            let secs = time
+                .parse::<u64>()
+                .with_context(|| format!("Invalid expire time {}", time))?;
+            let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            // Absolute time in the past expires immediately.
+            let remain = Duration::from_secs(secs).saturating_sub(since_epoch);
+            let expire = now
+                .checked_add(remain)
+                .with_context(|| format!("Expire time {} is out of range", time))?;
+            Ok(Some(expire))
+        }
+    }
+}
+
+/// Generate random challenge.
+fn gen_challenge() -> Result<Vec<u8>> {
+    let mut challenge = vec![0_u8; VNC_AUTH_CHALLENGE_SIZE];
+    // SAFETY: the buffer is valid and its length is passed.
+    let ret = unsafe {
+        libc::getrandom(
+            challenge.as_mut_ptr() as *mut libc::c_void,
+            challenge.len(),
+            0,
+        )
+    };
+    if ret != challenge.len() as isize {
+        bail!(
+            "Failed to generate vnc challenge: {:?}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(challenge)
+}
+
+impl ClientIoHandler {
+    /// Send challenge to client for password authentication.
+    /// The response should be handled by `handle_vnc_auth`.
+    pub fn start_vnc_auth(&mut self) -> Result<()> {
+        let challenge = gen_challenge()?;
+        let client = self.client.clone();
+        vnc_write(&client, challenge.clone());
+        vnc_flush(&client);
+        self.challenge = challenge;
+        Ok(())
+    }
+
+    /// Check the response of challenge.
+    pub fn handle_vnc_auth(&mut self) -> Result<()> {
+        let response = self.read_incoming_msg();
+        let client = self.client.clone();
+        let challenge = std::mem::take(&mut self.challenge);
+        let result = self
+            .server
+            .security_type
+            .borrow()
+            .vncpass
+            .as_ref()
+            .map_or(Err("password auth is not enabled".to_string()), |pass| {
+                pass.check_response(&challenge, &response)
+            });
+
+        match result {
+            Ok(()) => {
+                info!("Vnc client {} is authenticated by password", client.addr);
+                // Security result: ok.
+                vnc_write(&client, 0_u32.to_be_bytes().to_vec());
+                vnc_flush(&client);
+                self.update_event_handler(1, ClientIoHandler::handle_client_init);
+                Ok(())
+            }
+            Err(reason) => {
+                self.fail_auth(&reason);
+                Err(anyhow!(VncError::AuthFailed(
+                    "handle_vnc_auth".to_string(),
+                    reason
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_des_encrypt() {
+        let key = 0x1334_5779_9BBC_DFF1_u64.to_be_bytes();
+        let block = 0x0123_4567_89AB_CDEF_u64.to_be_bytes();
+        assert_eq!(
+            u64::from_be_bytes(des_encrypt(&key, &block)),
+            0x85E8_1354_0F0A_B405
+        );
+
+        let key = 0x0E32_9232_EA6D_0D73_u64.to_be_bytes();
+        let block = 0x8787_8787_8787_8787_u64.to_be_bytes();
+        assert_eq!(u64::from_be_bytes(des_encrypt(&key, &block)), 0);
+    }
+
+    #[test]
+    fn test_vnc_password_auth() {
+        let challenge: Vec<u8> = (0..16).collect();
+        // Response of password "secret".
+        let response = [
+            0xee, 0x22, 0x53, 0x9f, 0x33, 0xa5, 0x98, 0x3e, 0xc1, 0x2f, 0x9c, 0x2e, 0xdb, 0xc9,
+            0x95, 0xdd,
+        ];
+
+        // Password is not set.
+        let mut vncpass = VncPassword::default();
+        assert!(vncpass.check_response(&challenge, &response).is_err());
+
+        vncpass.password = Some("secret".to_string());
+        assert!(vncpass.check_response(&challenge, &response).is_ok());
+        assert!(vncpass.check_response(&challenge, &[0_u8; 16]).is_err());
+        assert!(vncpass.check_response(&challenge, &response[..8]).is_err());
+
+        // Only the first 8 bytes of password are used.
+        vncpass.password = Some("secret12".to_string());
+        let response_long = vnc_encrypt_challenge("secret12", &challenge);
+        vncpass.password = Some("secret123".to_string());
+        assert!(vncpass.check_response(&challenge, &response_long).is_ok());
+
+        // Expire after a while.
+        vncpass.password = Some("secret".to_string());
+        vncpass.expire = parse_expire_time("+60").unwrap();
+        assert!(vncpass.check_response(&challenge, &response).is_ok());
+
+        // Expire immediately.
+        vncpass.expire = parse_expire_time("now").unwrap();
+        assert_eq!(
+            vncpass.check_response(&challenge, &response),
+            Err("password is expired".to_string())
+        );
+
+        // Never expire.
+        vncpass.expire = parse_expire_time("never").unwrap();
+        assert!(vncpass.check_response(&challenge, &response).is_ok());
+    }
+
+    #[test]
+    fn test_parse_expire_time() {
+        assert!(parse_expire_time("never").unwrap().is_none());
+        assert!(parse_expire_time("now").unwrap().unwrap() <= Instant::now());
+        assert!(parse_expire_time("+3600").unwrap().unwrap() > Instant::now());
+        // Absolute time in the past expires immediately.
+        assert!(parse_expire_time("1").unwrap().unwrap() <= Instant::now());
+        let future = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 3600;
+        assert!(parse_expire_time(&future.to_string()).unwrap().unwrap() > Instant::now());
+
+        assert!(parse_expire_time("").is_err());
+        assert!(parse_expire_time("+").is_err());
+        assert!(parse_expire_time("-60").is_err());
+        assert!(parse_expire_time("later").is_err());
+
+        // Expire time overflows the clock.
+        assert!(parse_expire_time(&format!("+{}", u64::MAX)).is_err());
+        assert!(parse_expire_time(&u64::MAX.to_string()).is_err());
+    }
+}
@@ -11,67 +11,130 @@
 // See the Mulan PSL v2 for more details.
 
 use crate::{
-    console::console_select,
+    console::{console_select, display_clipboard_receive, graphic_hardware_resize, DisplayMouse},
     error::VncError,
     input::{
-        key_event, keyboard_modifier_get, keyboard_state_reset, point_event, update_key_state,
-        KeyboardModifier, ABS_MAX, ASCII_A, ASCII_Z, INPUT_POINT_LEFT, INPUT_POINT_MIDDLE,
-        INPUT_POINT_RIGHT, KEYCODE_1, KEYCODE_9, UPPERCASE_TO_LOWERCASE,
+        kbd_led_state, key_event, keyboard_modifier_get, keyboard_state_reset, point_event,
+        pointer_is_absolute, rel_point_event, update_key_state, KeyboardModifier, ABS_MAX,
+        CAPS_LOCK_LED, INPUT_POINT_LEFT, INPUT_POINT_MIDDLE, INPUT_POINT_RIGHT, KEYCODE_1,
+        KEYCODE_9, NUM_LOCK_LED, SCROLL_LOCK_LED,
     },
+    keymap::keysym_lookup,
     pixman::{bytes_per_pixel, get_image_height, get_image_width, PixelFormat},
     utils::BuffPool,
     vnc::{
-        auth_sasl::AuthState, framebuffer_update, round_up_div, server_io::VncServer,
-        set_area_dirty, write_pixel, BIT_PER_BYTE, DIRTY_PIXELS_NUM, DIRTY_WIDTH_BITS,
-        MAX_IMAGE_SIZE, MAX_WINDOW_HEIGHT, MIN_OUTPUT_LIMIT, OUTPUT_THROTTLE_SCALE,
+        auth_sasl::{AuthState, SaslConfig, SaslOutput},
+        auth_vnc::VNC_AUTH_CHALLENGE_SIZE,
+        clipboard::{
+            client_latin1_text, ext_clipboard_caps, ext_clipboard_msg_max, ext_clipboard_request,
+            parse_ext_clipboard, ExtClipboardMsg, EXT_CLIPBOARD_ACTION_REQUEST,
+            EXT_CLIPBOARD_CLIENT_CAPS, EXT_CLIPBOARD_FORMAT_TEXT,
+        },
+        encoding::{enc_tight::TightStreams, enc_zlib::ZlibStream},
+        framebuffer_update, round_up_div,
+        server_io::VncServer,
+        set_area_dirty,
+        tile_hash::TileHashes,
+        write_pixel, BIT_PER_BYTE, DIRTY_PIXELS_NUM, DIRTY_WIDTH_BITS, MAX_IMAGE_SIZE,
+        MAX_WINDOW_HEIGHT, MAX_WINDOW_WIDTH, MIN_OUTPUT_LIMIT, OUTPUT_THROTTLE_SCALE,
     },
 };
 use anyhow::{anyhow, bail, Result};
-use log::error;
+use log::{error, warn};
+use machine_manager::event_loop::EventLoop;
 use sscanf::scanf;
 use std::{
     cell::RefCell,
     cmp,
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     io::{Read, Write},
     net::{Shutdown, TcpStream},
     os::unix::prelude::{AsRawFd, RawFd},
     rc::Rc,
     sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
 };
 use util::{
     bitmap::Bitmap,
     loop_context::{
-        gen_delete_notifiers, read_fd, EventNotifier, EventNotifierHelper, NotifierCallback,
-        NotifierOperation,
+        gen_delete_notifiers, read_fd, EventLoopContext, EventNotifier, EventNotifierHelper,
+        NotifierCallback, NotifierOperation,
     },
 };
 use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 pub const APP_NAME: &str = "stratovirt";
 const MAX_RECVBUF_LEN: usize = 1024;
-const NUM_OF_COLORMAP: u16 = 256;
+/// Sub-type of QEMU client message for extended key event.
+const QEMU_CLIENT_EXT_KEY_EVENT: u8 = 0;
+/// Bytes of the dropped message skipped in each step.
+const MSG_SKIP_CHUNK: usize = 4096;
+/// The inputs still held are released if client sends SetEncodings after no
+/// input for this long, as client may lose the release events.
+const INPUT_IDLE_RELEASE: Duration = Duration::from_secs(3);
+/// The client in relative pointer mode sends the motion offset by it.
+const POINTER_REL_CENTER: i32 = 0x7fff;
 
 // VNC encodings types.
 pub const ENCODING_RAW: i32 = 0;
+pub const ENCODING_COPYRECT: i32 = 1;
 pub const ENCODING_HEXTILE: i32 = 5;
-const ENCODING_ZLIB: i32 = 6;
-const ENCODING_TIGHT: i32 = 7;
-const ENCODING_ZRLE: i32 = 16;
+pub const ENCODING_ZLIB: i32 = 6;
+pub const ENCODING_TIGHT: i32 = 7;
+pub const ENCODING_ZRLE: i32 = 16;
 const ENCODING_ZYWRLE: i32 = 17;
 const ENCODING_DESKTOPRESIZE: i32 = -223;
 pub const ENCODING_RICH_CURSOR: i32 = -239;
+const ENCODING_COMPRESSLEVEL0: i32 = -256;
+const ENCODING_COMPRESSLEVEL9: i32 = -247;
 const ENCODING_POINTER_TYPE_CHANGE: i32 = -257;
+const ENCODING_EXT_KEY_EVENT: i32 = -258;
 const ENCODING_LED_STATE: i32 = -261;
 const ENCODING_DESKTOP_RESIZE_EXT: i32 = -308;
+const ENCODING_FENCE: i32 = -312;
+const ENCODING_CONTINUOUS_UPDATES: i32 = -313;
 pub const ENCODING_ALPHA_CURSOR: i32 = -314;
 const ENCODING_WMVI: i32 = 1464686185;
+const ENCODING_EXT_CLIPBOARD: i32 = 0xC0A1_E5CE_u32 as i32;
+// Lock bits of LED state pseudo-encoding.
+const VNC_SCROLL_LOCK: u8 = 1 << 0;
+const VNC_NUM_LOCK: u8 = 1 << 1;
+const VNC_CAPS_LOCK: u8 = 1 << 2;
+/// Reasons of the desktop size change in ExtendedDesktopSize.
+/// The messages before the fence must be handled before it.
+const FENCE_BLOCK_BEFORE: u32 = 1 << 0;
+/// The messages after the fence must not be handled until it is handled.
+const FENCE_BLOCK_AFTER: u32 = 1 << 1;
+/// The fence is replied after the next message is handled.
+const FENCE_SYNC_NEXT: u32 = 1 << 2;
+/// The fence is a request, which must be replied by the peer.
+const FENCE_REQUEST: u32 = 1 << 31;
+const FENCE_SUPPORTED_FLAGS: u32 =
+    FENCE_BLOCK_BEFORE | FENCE_BLOCK_AFTER | FENCE_SYNC_NEXT | FENCE_REQUEST;
+const FENCE_MAX_PAYLOAD: usize = 64;
+
+const DESKTOP_RESIZE_REASON_SERVER: u16 = 0;
+const DESKTOP_RESIZE_REASON_CLIENT: u16 = 1;
+/// Status of the desktop size change requested by client in ExtendedDesktopSize.
+const DESKTOP_RESIZE_STATUS_OK: u16 = 0;
+const DESKTOP_RESIZE_STATUS_PROHIBITED: u16 = 1;
+const DESKTOP_RESIZE_STATUS_INVALID_LAYOUT: u16 = 3;
+const DESKTOP_RESIZE_STATUS_FORWARDED: u16 = 4;
 
 /// This trait is used to send bytes,
 /// the return is the total number of bytes sented.
 pub trait IoOperations {
     fn channel_write(&mut self, buf: &[u8]) -> Result<usize>;
     fn channel_read(&mut self, buf: &mut Vec<u8>) -> Result<usize>;
+    /// Write the data queued inside the channel, return whether all of
+    /// it has been written.
+    fn channel_flush(&mut self) -> Result<bool> {
+        Ok(true)
+    }
+    /// Strength of the encryption provided by the channel, 0 for plaintext.
+    fn tls_ssf(&self) -> u32 {
+        0
+    }
 }
 
 /// Image display feature.
@@ -91,6 +154,10 @@ pub enum VncFeatures {
     VncFeatureLedState,
     VncFeatureXvp,
     VncFeatureClipboardExt,
+    VncFeatureCopyRect,
+    VncFeatureFence,
+    VncFeatureContinuousUpdates,
+    VncFeatureExtKeyEvent,
 }
 
 /// Client to server message in Remote Framebuffer Protocol.
@@ -101,6 +168,10 @@ pub enum ClientMsg {
     KeyEvent = 4,
     PointerEvent = 5,
     ClientCutText = 6,
+    EnableContinuousUpdates = 150,
+    Fence = 248,
+    SetDesktopSize = 251,
+    QemuClientMsg = 255,
     InvalidMsg,
 }
 
@@ -108,6 +179,9 @@ pub enum ClientMsg {
 pub enum ServerMsg {
     FramebufferUpdate = 0,
     SetColourMapEntries = 1,
+    ServerCutText = 3,
+    EndOfContinuousUpdates = 150,
+    Fence = 248,
 }
 
 impl From<u8> for ClientMsg {
@@ -119,11 +193,88 @@ impl From<u8> for ClientMsg {
             4 => ClientMsg::KeyEvent,
             5 => ClientMsg::PointerEvent,
             6 => ClientMsg::ClientCutText,
+            150 => ClientMsg::EnableContinuousUpdates,
+            248 => ClientMsg::Fence,
+            251 => ClientMsg::SetDesktopSize,
+            255 => ClientMsg::QemuClientMsg,
             _ => ClientMsg::InvalidMsg,
         }
     }
 }
 
+/// Length of the client message which is known but not supported, so that
+/// it can be skipped. According to the extension registry of RFB protocol,
+/// the length of some messages is described by the field in their header.
+///
+/// # Arguments
+///
+/// * `buf` - The message received, which starts with the message type.
+///
+/// # Returns
+///
+/// The length of the message header if `buf` is not long enough to contain
+/// it, otherwise the total length of the message. None is returned if the
+/// message can not be framed.
+fn unsupported_msg_len(buf: &[u8]) -> Option<usize> {
+    let header = |len: usize, total: &dyn Fn() -> Option<usize>| {
+        if buf.len() < len {
+            Some(len)
+        } else {
+            total()
+        }
+    };
+    match buf[0] {
+        // SetScale, SetServerInput and SetScaleFactor of UltraVNC, xvp.
+        8 | 9 | 15 | 250 => Some(4),
+        // SetSW of UltraVNC.
+        10 => Some(6),
+        // KeyFrameRequest and KeepAlive of UltraVNC.
+        12 | 13 => Some(1),
+        // FileTransfer of UltraVNC, followed by the data of `length`.
+        7 => header(12, &|| {
+            let len = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+            Some(12 + len as usize)
+        }),
+        // TextChat of UltraVNC, the length of open, close and finished
+        // commands are in reserved range, which are not followed by text.
+        11 => header(8, &|| {
+            let len = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+            match len {
+                0xffff_fffd..=0xffff_ffff => Some(8),
+                _ => Some(8 + len as usize),
+            }
+        }),
+        // VMware, the length includes the header.
+        127 | 254 => header(4, &|| {
+            let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+            (len >= 4).then_some(len)
+        }),
+        // SetDesktopSize, followed by the screens of 16 bytes.
+        251 => header(8, &|| Some(8 + buf[6] as usize * 16)),
+        // gII, the endian of length is specified by the highest bit of
+        // sub-type.
+        253 => header(4, &|| {
+            let len = if buf[1] & 0x80 != 0 {
+                u16::from_be_bytes([buf[2], buf[3]])
+            } else {
+                u16::from_le_bytes([buf[2], buf[3]])
+            };
+            Some(4 + len as usize)
+        }),
+        // QEMU extended key event and audio.
+        255 => header(2, &|| match buf[1] {
+            0 => Some(12),
+            1 => header(4, &|| match u16::from_be_bytes([buf[2], buf[3]]) {
+                0 | 1 => Some(4),
+                2 => Some(10),
+                _ => None,
+            }),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
 /// RFB protocol version.
 #[derive(Clone)]
 pub struct VncVersion {
@@ -151,7 +302,7 @@ pub enum UpdateState {
 }
 
 /// Dirty area of image
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Rectangle {
     pub x: i32,
     pub y: i32,
@@ -163,6 +314,67 @@ impl Rectangle {
     pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
         Rectangle { x, y, w, h }
     }
+
+    /// The overlapped area of two rectangles.
+    pub fn intersect(&self, other: &Rectangle) -> Option<Rectangle> {
+        let x = cmp::max(self.x, other.x);
+        let y = cmp::max(self.y, other.y);
+        let x2 = cmp::min(self.x + self.w, other.x + other.w);
+        let y2 = cmp::min(self.y + self.h, other.y + other.h);
+        if x2 <= x || y2 <= y {
+            return None;
+        }
+        Some(Rectangle::new(x, y, x2 - x, y2 - y))
+    }
+
+    /// The area of this rectangle which is not covered by the other one,
+    /// it is split into the top, bottom, left and right parts.
+    pub fn subtract(&self, other: &Rectangle) -> Vec<Rectangle> {
+        let mut rects = Vec::new();
+        let y2 = self.y + self.h;
+        let other_y2 = other.y + other.h;
+        if self.y < other.y {
+            let h = cmp::min(y2, other.y) - self.y;
+            rects.push(Rectangle::new(self.x, self.y, self.w, h));
+        }
+        if y2 > other_y2 {
+            let y = cmp::max(self.y, other_y2);
+            rects.push(Rectangle::new(self.x, y, self.w, y2 - y));
+        }
+        let top = cmp::max(self.y, other.y);
+        let bottom = cmp::min(y2, other_y2);
+        if bottom > top {
+            let x2 = self.x + self.w;
+            let other_x2 = other.x + other.w;
+            if self.x < other.x {
+                let w = cmp::min(x2, other.x) - self.x;
+                rects.push(Rectangle::new(self.x, top, w, bottom - top));
+            }
+            if x2 > other_x2 {
+                let x = cmp::max(self.x, other_x2);
+                rects.push(Rectangle::new(x, top, x2 - x, bottom - top));
+            }
+        }
+        rects
+    }
+}
+
+/// Area which is copied by client from the source position of its own
+/// framebuffer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CopyRect {
+    /// Destination area of image.
+    pub rect: Rectangle,
+    /// X coordinate of source area.
+    pub src_x: i32,
+    /// Y coordinate of source area.
+    pub src_y: i32,
+}
+
+impl CopyRect {
+    pub fn new(rect: Rectangle, src_x: i32, src_y: i32) -> Self {
+        CopyRect { rect, src_x, src_y }
+    }
 }
 
 /// Display Output mode information of client.
@@ -182,6 +394,12 @@ pub struct DisplayMode {
     pub convert: bool,
     /// Image pixel format in pixman.
     pub pf: PixelFormat,
+    /// Zlib compression level preferred by client, 0..=9.
+    pub compression: Option<u8>,
+    /// Lock bits of keyboard led state which is sent to client.
+    pub led_state: Option<u8>,
+    /// Pointer mode which is sent to client, true means absolute.
+    pub pointer_absolute: Option<bool>,
 }
 
 impl DisplayMode {
@@ -194,6 +412,9 @@ impl DisplayMode {
             client_be,
             convert,
             pf,
+            compression: None,
+            led_state: None,
+            pointer_absolute: None,
         }
     }
 
@@ -211,14 +432,21 @@ impl Default for DisplayMode {
 pub struct RectInfo {
     /// Vnc client state.
     pub client: Arc<ClientState>,
+    /// Area copied by client, which is sent before the dirty area.
+    pub copy_rects: Vec<CopyRect>,
     /// Dirty area of image.
     pub rects: Vec<Rectangle>,
 }
 
 impl RectInfo {
-    pub fn new(client: &Arc<ClientState>, rects: Vec<Rectangle>) -> Self {
+    pub fn new(
+        client: &Arc<ClientState>,
+        copy_rects: Vec<CopyRect>,
+        rects: Vec<Rectangle>,
+    ) -> Self {
         RectInfo {
             client: client.clone(),
+            copy_rects,
             rects,
         }
     }
@@ -232,6 +460,7 @@ impl Clone for RectInfo {
         }
         Self {
             client: self.client.clone(),
+            copy_rects: self.copy_rects.clone(),
             rects,
         }
     }
@@ -298,6 +527,66 @@ impl IoOperations for IoChannel {
     }
 }
 
+/// Pace the framebuffer updates to client, so that the updates are not
+/// generated faster than max fps.
+pub struct UpdatePacing {
+    /// Min interval between two updates, no limit if it is zero.
+    min_interval: Duration,
+    /// Time of the updates generated in the last second.
+    history: VecDeque<Instant>,
+}
+
+impl Default for UpdatePacing {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl UpdatePacing {
+    /// Create pacing with max fps, no limit if it is zero.
+    pub fn new(max_fps: u64) -> Self {
+        let min_interval = match max_fps {
+            0 => Duration::ZERO,
+            fps => Duration::from_secs(1) / fps as u32,
+        };
+        UpdatePacing {
+            min_interval,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Whether the next update is allowed to be generated at `now`.
+    pub fn is_ready(&self, now: Instant) -> bool {
+        match self.history.back() {
+            Some(last) => now.saturating_duration_since(*last) >= self.min_interval,
+            None => true,
+        }
+    }
+
+    /// Record the update generated at `now`.
+    pub fn record(&mut self, now: Instant) {
+        self.expire(now);
+        self.history.push_back(now);
+    }
+
+    /// Effective rate of updates, which is the number of updates generated
+    /// in the last second.
+    pub fn rate(&mut self, now: Instant) -> u64 {
+        self.expire(now);
+        self.history.len() as u64
+    }
+
+    /// Forget the updates generated one second before `now`.
+    fn expire(&mut self, now: Instant) {
+        while let Some(first) = self.history.front() {
+            if now.saturating_duration_since(*first) < Duration::from_secs(1) {
+                break;
+            }
+            self.history.pop_front();
+        }
+    }
+}
+
 /// The connection state of vnc client.
 pub struct ConnState {
     /// Dirty number need to update.
@@ -310,6 +599,17 @@ pub struct ConnState {
     pub version: VncVersion,
     /// Point to Client Io handler.
     pub client_io: Option<Weak<Mutex<ClientIoHandler>>>,
+    /// Area which is pushed to client without update request, if continuous
+    /// updates is enabled by client.
+    pub continuous_updates: Option<Rectangle>,
+    /// Pacing of the framebuffer updates.
+    pub pacing: UpdatePacing,
+    /// Max bytes of output queued for client, 0 means it depends on the
+    /// size of framebuffer.
+    pub output_limit: usize,
+    /// Whether the handshake is finished, so that the messages not requested
+    /// by client can be sent.
+    pub initialized: bool,
 }
 
 impl Default for ConnState {
@@ -320,6 +620,10 @@ impl Default for ConnState {
             update_state: UpdateState::No,
             version: VncVersion::default(),
             client_io: None,
+            continuous_updates: None,
+            pacing: UpdatePacing::default(),
+            output_limit: 0,
+            initialized: false,
         }
     }
 }
@@ -336,7 +640,7 @@ impl ConnState {
         }
 
         match self.update_state {
-            UpdateState::No => false,
+            UpdateState::No => self.continuous_updates.is_some() && self.dirty_num > 0,
             UpdateState::Incremental => self.dirty_num > 0,
             UpdateState::Force => true,
         }
@@ -366,6 +670,18 @@ pub struct ClientState {
     pub conn_state: Arc<Mutex<ConnState>>,
     /// Identify the image update area.
     pub dirty_bitmap: Arc<Mutex<Bitmap<u64>>>,
+    /// Zlib stream for the lifetime of connection.
+    pub zlib_stream: Arc<Mutex<ZlibStream>>,
+    /// Zlib streams of tight encoding for the lifetime of connection.
+    pub tight_streams: Arc<Mutex<TightStreams>>,
+    /// Scratch buffer of hextile encoding, reused by all the tiles.
+    pub hextile_buf: Arc<Mutex<Vec<u8>>>,
+    /// Zlib stream of zrle encoding for the lifetime of connection.
+    pub zrle_stream: Arc<Mutex<ZlibStream>>,
+    /// Area copied in server image, which is not sent to client yet.
+    pub copy_rects: Arc<Mutex<Vec<CopyRect>>>,
+    /// Content hash of the tiles sent to client.
+    pub tile_hashes: Arc<Mutex<TileHashes>>,
 }
 
 impl ClientState {
@@ -382,6 +698,12 @@ impl ClientState {
                 MAX_WINDOW_HEIGHT as usize
                     * round_up_div(DIRTY_WIDTH_BITS as u64, u64::BITS as u64) as usize,
             ))),
+            zlib_stream: Arc::new(Mutex::new(ZlibStream::new())),
+            tight_streams: Arc::new(Mutex::new(TightStreams::new())),
+            hextile_buf: Arc::new(Mutex::new(Vec::new())),
+            zrle_stream: Arc::new(Mutex::new(ZlibStream::new())),
+            copy_rects: Arc::new(Mutex::new(Vec::new())),
+            tile_hashes: Arc::new(Mutex::new(TileHashes::default())),
         }
     }
 }
@@ -404,6 +726,37 @@ pub struct ClientIoHandler {
     pub client: Arc<ClientState>,
     /// Configure for vnc server.
     pub server: Arc<VncServer>,
+    /// Challenge sent to client in password authentication.
+    pub challenge: Vec<u8>,
+    /// Sasl authentication is started by this client.
+    pub sasl_started: bool,
+    /// Sasl authentication state of this client.
+    pub saslconfig: SaslConfig,
+    /// Output written through the security layer of sasl.
+    pub sasl_output: SaslOutput,
+    /// Timer of the handshake deadline.
+    pub handshake_timer: Option<u64>,
+    /// Timer of the deadline for current handshake step.
+    pub auth_timer: Option<u64>,
+    /// Fence which is replied after the next message is handled.
+    pub sync_fence: Option<(u32, Vec<u8>)>,
+    /// Actions and formats of extended clipboard supported by client.
+    pub ext_clipboard_caps: u32,
+    /// Remaining bytes of the dropped message.
+    pub msg_skip: usize,
+    /// Keycodes of the keys pressed by client.
+    pub pressed_keys: HashSet<u16>,
+    /// Mask of the mouse buttons pressed by client.
+    pub pressed_buttons: u32,
+    /// Position of the last pointer event.
+    pub pointer_pos: (u32, u32),
+    /// Position of client in the last pointer event, which is used to
+    /// compute the motion for relative pointer device.
+    pub last_pointer: Option<(u16, u16)>,
+    /// Time of the last key or pointer event.
+    pub last_input: Instant,
+    /// Client is connected by websocket.
+    pub websocket: bool,
 }
 
 impl ClientIoHandler {
@@ -422,15 +775,127 @@ impl ClientIoHandler {
             expect: 12,
             client,
             server,
+            challenge: Vec::new(),
+            sasl_started: false,
+            saslconfig: SaslConfig::default(),
+            sasl_output: SaslOutput::default(),
+            handshake_timer: None,
+            auth_timer: None,
+            sync_fence: None,
+            ext_clipboard_caps: EXT_CLIPBOARD_CLIENT_CAPS,
+            msg_skip: 0,
+            pressed_keys: HashSet::new(),
+            pressed_buttons: 0,
+            pointer_pos: (0, 0),
+            last_pointer: None,
+            last_input: Instant::now(),
+            websocket: false,
+        }
+    }
+
+    /// Arm the deadline for client to finish the handshake, the client
+    /// will be disconnected if the deadline expires.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_io` - Io handler of client.
+    /// * `ctx` - Event loop to run the timer.
+    /// * `timeout` - Time limit of the handshake.
+    pub fn arm_handshake_timer(
+        client_io: &Arc<Mutex<ClientIoHandler>>,
+        ctx: &mut EventLoopContext,
+        timeout: Duration,
+    ) {
+        let weak_client_io = Arc::downgrade(client_io);
+        let func = Box::new(move || {
+            if let Some(client_io) = weak_client_io.upgrade() {
+                client_io.lock().unwrap().handshake_timeout();
+            }
+        });
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.cancel_handshake_timer(ctx);
+        locked_client_io.handshake_timer = Some(ctx.timer_add(func, timeout));
+    }
+
+    /// Cancel the deadline of handshake.
+    pub fn cancel_handshake_timer(&mut self, ctx: &mut EventLoopContext) {
+        if let Some(timer_id) = self.handshake_timer.take() {
+            ctx.timer_del(timer_id);
         }
     }
+
+    /// The handshake is not finished before deadline, release the sasl
+    /// connection and disconnect the client.
+    fn handshake_timeout(&mut self) {
+        self.handshake_timer = None;
+        let client = self.client.clone();
+        if client.conn_state.lock().unwrap().dis_conn {
+            return;
+        }
+        warn!(
+            "Vnc client {} does not finish the handshake in time, disconnect it",
+            client.addr
+        );
+        self.sasl_teardown();
+        client.conn_state.lock().unwrap().dis_conn = true;
+        vnc_disconnect_start(&client);
+    }
+
+    /// Arm the deadline for client to finish current handshake step, the
+    /// previous deadline is replaced. The client will be disconnected if
+    /// the deadline expires.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Event loop to run the timer.
+    pub fn arm_auth_timer(&mut self, ctx: &mut EventLoopContext) {
+        self.cancel_auth_timer(ctx);
+        let weak_client_io = match self.client.conn_state.lock().unwrap().client_io.clone() {
+            Some(client_io) => client_io,
+            None => return,
+        };
+        let func = Box::new(move || {
+            if let Some(client_io) = weak_client_io.upgrade() {
+                client_io.lock().unwrap().auth_timeout();
+            }
+        });
+        self.auth_timer = Some(ctx.timer_add(func, self.server.auth_timeout));
+    }
+
+    /// Cancel the deadline of current handshake step.
+    pub fn cancel_auth_timer(&mut self, ctx: &mut EventLoopContext) {
+        if let Some(timer_id) = self.auth_timer.take() {
+            ctx.timer_del(timer_id);
+        }
+    }
+
+    /// The client stalls in current handshake step, release the sasl
+    /// connection and disconnect the client.
+    fn auth_timeout(&mut self) {
+        self.auth_timer = None;
+        let client = self.client.clone();
+        if client.conn_state.lock().unwrap().dis_conn {
+            return;
+        }
+        error!(
+            "Vnc client {}: {:?}",
+            client.addr,
+            anyhow!(VncError::AuthFailed(
+                "auth_timeout".to_string(),
+                "auth timeout".to_string()
+            ))
+        );
+        self.sasl_teardown();
+        client.conn_state.lock().unwrap().dis_conn = true;
+        vnc_disconnect_start(&client);
+    }
 }
 
 impl ClientIoHandler {
     /// This function interacts with the client interface, it includs several
     /// steps: Read the data stream from the fd, save the data in buffer,
     /// and then process the data by io handle function.
-    fn client_handle_read(&mut self) -> Result<(), anyhow::Error> {
+    pub fn client_handle_read(&mut self) -> Result<(), anyhow::Error> {
         self.read_msg()?;
 
         let client = self.client.clone();
@@ -474,7 +939,14 @@ impl ClientIoHandler {
             }
         }
 
-        if !locked_buffer.is_empty() {
+        let flushed = match self.io_channel.borrow_mut().channel_flush() {
+            Ok(flushed) => flushed,
+            Err(_e) => {
+                self.client.conn_state.lock().unwrap().dis_conn = true;
+                return;
+            }
+        };
+        if !locked_buffer.is_empty() || !flushed {
             vnc_flush(&client);
         }
 
@@ -487,19 +959,37 @@ impl ClientIoHandler {
         let len = self.io_channel.borrow_mut().channel_read(&mut buf)?;
         if len > 0 {
             buf = buf[..len].to_vec();
+            if self.sasl_ssf_active() {
+                buf = self.sasl_read(&buf)?;
+            }
             self.client.in_buffer.lock().unwrap().append_limit(buf);
         }
+        // The channel may reply the client by itself, such as the pong of websocket.
+        if !self.io_channel.borrow_mut().channel_flush()? {
+            vnc_flush(&self.client);
+        }
         Ok(len)
     }
 
     /// Write buf to stream
     /// Choose different channel according to whether or not to encrypt
     pub fn write_msg(&mut self, buf: &[u8]) -> Result<usize> {
-        self.io_channel.borrow_mut().channel_write(buf)
+        if !self.sasl_ssf_active() {
+            return self.io_channel.borrow_mut().channel_write(buf);
+        }
+        if self.sasl_output.wait_write_ssf == 0 {
+            return self.sasl_write(buf);
+        }
+
+        // The output queued before the security layer starts is not encoded.
+        let len = cmp::min(buf.len(), self.sasl_output.wait_write_ssf);
+        let send_len = self.io_channel.borrow_mut().channel_write(&buf[..len])?;
+        self.sasl_output.wait_write_ssf -= send_len;
+        Ok(send_len)
     }
 
     /// Exchange RFB protocol version with client.
-    fn handle_version(&mut self) -> Result<()> {
+    pub fn handle_version(&mut self) -> Result<()> {
         let client = self.client.clone();
         let mut buf = self.read_incoming_msg();
         // The last character should be '\n'
@@ -517,10 +1007,8 @@ impl ClientIoHandler {
 
         let mut version = VncVersion::new(ver.0 as u16, ver.1 as u16);
         if version.major != 3 || ![3, 4, 5, 7, 8].contains(&version.minor) {
-            let mut buf = Vec::new();
-            buf.append(&mut (AuthState::Invalid as u32).to_be_bytes().to_vec());
-            vnc_write(&client, buf);
-            vnc_flush(&client);
+            // The version is not negotiated, reply in the format of RFB 3.3.
+            self.fail_negotiation("Unsupported RFB protocol version");
             return Err(anyhow!(VncError::UnsupportedRFBProtocolVersion));
         }
 
@@ -528,7 +1016,7 @@ impl ClientIoHandler {
             version.minor = 3;
         }
         self.client.conn_state.lock().unwrap().version = version;
-        let auth = self.server.security_type.borrow().auth;
+        let auth = self.auth_type();
 
         if self.client.conn_state.lock().unwrap().version.minor == 3 {
             match auth {
@@ -538,8 +1026,18 @@ impl ClientIoHandler {
                     vnc_write(&client, buf);
                     self.update_event_handler(1, ClientIoHandler::handle_client_init);
                 }
+                AuthState::Vnc => {
+                    let mut buf = Vec::new();
+                    buf.append(&mut (AuthState::Vnc as u32).to_be_bytes().to_vec());
+                    vnc_write(&client, buf);
+                    self.start_vnc_auth()?;
+                    self.update_event_handler(
+                        VNC_AUTH_CHALLENGE_SIZE,
+                        ClientIoHandler::handle_vnc_auth,
+                    );
+                }
                 _ => {
-                    self.auth_failed("Unsupported auth method");
+                    self.fail_negotiation("Unsupported auth method");
                     return Err(anyhow!(VncError::AuthFailed(
                         "handle_version".to_string(),
                         "Unsupported auth method".to_string()
@@ -599,19 +1097,36 @@ impl ClientIoHandler {
         buf.append(&mut APP_NAME.to_string().as_bytes().to_vec());
         vnc_write(&client, buf);
         vnc_flush(&client);
+        // The handshake is finished.
+        client.conn_state.lock().unwrap().initialized = true;
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            self.cancel_handshake_timer(ctx);
+            self.cancel_auth_timer(ctx);
+        }
         self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
         Ok(())
     }
 
+    /// Auth type offered to client. The websocket over tls is encrypted
+    /// already, so the auth is not wrapped in VeNCrypt.
+    fn auth_type(&self) -> AuthState {
+        let security = self.server.security_type.borrow();
+        if self.websocket {
+            security.ws_auth
+        } else {
+            security.auth
+        }
+    }
+
     /// Authentication
     fn handle_auth(&mut self) -> Result<()> {
         let buf = self.read_incoming_msg();
-        let auth = self.server.security_type.borrow().auth;
+        let auth = self.auth_type();
         let client = self.client.clone();
         let version = client.conn_state.lock().unwrap().version.clone();
 
         if buf[0] != auth as u8 {
-            self.auth_failed("Authentication failed");
+            self.fail_auth("Unsupported security type");
             return Err(anyhow!(VncError::AuthFailed(
                 "handle_auth".to_string(),
                 "auth type is not supported".to_string()
@@ -626,6 +1141,13 @@ impl ClientIoHandler {
                 }
                 self.update_event_handler(1, ClientIoHandler::handle_client_init);
             }
+            AuthState::Vnc => {
+                self.start_vnc_auth()?;
+                self.update_event_handler(
+                    VNC_AUTH_CHALLENGE_SIZE,
+                    ClientIoHandler::handle_vnc_auth,
+                );
+            }
             AuthState::Vencrypt => {
                 // Send VeNCrypt version 0.2.
                 let mut buf = [0u8; 2];
@@ -635,8 +1157,12 @@ impl ClientIoHandler {
                 vnc_write(&client, buf.to_vec());
                 self.update_event_handler(2, ClientIoHandler::client_vencrypt_init);
             }
+            AuthState::Sasl => {
+                self.start_sasl_auth()?;
+                self.update_event_handler(4, ClientIoHandler::get_mechname_length);
+            }
             _ => {
-                self.auth_failed("Unhandled auth method");
+                self.fail_auth("Unhandled auth method");
                 return Err(anyhow!(VncError::AuthFailed(
                     "handle_auth".to_string(),
                     "auth type is not supported".to_string()
@@ -670,43 +1196,74 @@ impl ClientIoHandler {
                     .unwrap_or_else(|e| error!("Point event error: {:?}", e));
             }
             ClientMsg::ClientCutText => {
-                self.client_cut_event();
+                self.client_cut_event()?;
+            }
+            ClientMsg::EnableContinuousUpdates => {
+                self.enable_continuous_updates()?;
+            }
+            ClientMsg::Fence => {
+                self.client_fence()?;
+            }
+            ClientMsg::QemuClientMsg => {
+                self.qemu_client_msg()?;
+            }
+            ClientMsg::SetDesktopSize => {
+                let resize_ext = self
+                    .client
+                    .client_dpm
+                    .lock()
+                    .unwrap()
+                    .has_feature(VncFeatures::VncFeatureResizeExt);
+                if resize_ext {
+                    self.set_desktop_size();
+                } else {
+                    self.skip_unsupported_msg()?;
+                }
             }
             _ => {
-                self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+                self.skip_unsupported_msg()?;
             }
         }
         Ok(())
     }
 
-    /// Tell the client that the specified pixel values should be
-    /// mapped to the given RGB intensities.
-    fn send_color_map(&mut self) {
-        let mut buf: Vec<u8> = Vec::new();
-        buf.append(
-            &mut (ServerMsg::SetColourMapEntries as u8)
-                .to_be_bytes()
-                .to_vec(),
-        );
-        buf.append(&mut (0_u8).to_be_bytes().to_vec());
-        // First color.
-        buf.append(&mut (0_u16).to_be_bytes().to_vec());
-        // Number of colors.
-        buf.append(&mut NUM_OF_COLORMAP.to_be_bytes().to_vec());
-
-        let pf = self.client.client_dpm.lock().unwrap().pf.clone();
-        for i in 0..NUM_OF_COLORMAP {
-            let r = ((i >> pf.red.shift) & pf.red.max as u16) << (16 - pf.red.bits);
-            let g = ((i >> pf.green.shift) & pf.green.max as u16) << (16 - pf.green.bits);
-            let b = ((i >> pf.blue.shift) & pf.blue.max as u16) << (16 - pf.blue.bits);
-            buf.append(&mut r.to_be_bytes().to_vec());
-            buf.append(&mut g.to_be_bytes().to_vec());
-            buf.append(&mut b.to_be_bytes().to_vec());
+    /// Skip the message which is not supported. The client is disconnected
+    /// if the length of message is unknown, as the following messages can
+    /// not be parsed.
+    fn skip_unsupported_msg(&mut self) -> Result<()> {
+        let buf = self.read_incoming_msg();
+        match unsupported_msg_len(&buf) {
+            Some(len) if len > MSG_SKIP_CHUNK => {
+                warn!(
+                    "Vnc client {} sends unsupported message type {}, skip {} bytes",
+                    self.client.addr, buf[0], len
+                );
+                self.start_skip_msg(len);
+            }
+            Some(len) if len > buf.len() => {
+                self.expect = len;
+            }
+            Some(_) => {
+                warn!(
+                    "Vnc client {} sends unsupported message type {}, skip {} bytes",
+                    self.client.addr,
+                    buf[0],
+                    buf.len()
+                );
+                self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+            }
+            None => {
+                error!(
+                    "Vnc client {} sends unknown message type {}",
+                    self.client.addr, buf[0]
+                );
+                return Err(anyhow!(VncError::ProtocolMessageFailed(format!(
+                    "unknown message type {}",
+                    buf[0]
+                ))));
+            }
         }
-
-        let client = self.client.clone();
-        vnc_write(&client, buf);
-        vnc_flush(&client);
+        Ok(())
     }
 
     /// Set image format.
@@ -717,55 +1274,23 @@ impl ClientIoHandler {
         }
 
         let buf = self.read_incoming_msg();
-        let mut bit_per_pixel: u8 = buf[4];
-        let big_endian_flag = buf[6];
-        let true_color_flag: u8 = buf[7];
-        let mut red_max: u16 = u16::from_be_bytes([buf[8], buf[9]]);
-        let mut green_max: u16 = u16::from_be_bytes([buf[10], buf[11]]);
-        let mut blue_max: u16 = u16::from_be_bytes([buf[12], buf[13]]);
-        let mut red_shift: u8 = buf[14];
-        let mut green_shift: u8 = buf[15];
-        let mut blue_shift: u8 = buf[16];
-        if true_color_flag == 0 {
-            bit_per_pixel = 8;
-            red_max = 7;
-            green_max = 7;
-            blue_max = 3;
-            red_shift = 0;
-            green_shift = 3;
-            blue_shift = 6;
-        }
-
-        // Verify the validity of pixel format.
-        // bit_per_pixel: Bits occupied by each pixel.
-        if ![8, 16, 32].contains(&bit_per_pixel) {
-            self.client.conn_state.lock().unwrap().dis_conn = true;
-            return Err(anyhow!(VncError::ProtocolMessageFailed(String::from(
-                "set pixel format"
-            ))));
-        }
-
-        let mut locked_dpm = self.client.client_dpm.lock().unwrap();
-        locked_dpm.pf.red.set_color_info(red_shift, red_max);
-        locked_dpm.pf.green.set_color_info(green_shift, green_max);
-        locked_dpm.pf.blue.set_color_info(blue_shift, blue_max);
-        locked_dpm.pf.pixel_bits = bit_per_pixel;
-        locked_dpm.pf.pixel_bytes = bit_per_pixel / BIT_PER_BYTE as u8;
-        // Standard pixel format, depth is equal to 24.
-        locked_dpm.pf.depth = if bit_per_pixel == 32 {
-            24
-        } else {
-            bit_per_pixel
+        let pf = match parse_pixel_format(&buf[4..20]) {
+            Ok(pf) => pf,
+            Err(e) => {
+                self.client.conn_state.lock().unwrap().dis_conn = true;
+                return Err(e);
+            }
         };
-        locked_dpm.client_be = big_endian_flag != 0;
+        let client_be = buf[6] != 0;
 
-        if !locked_dpm.pf.is_default_pixel_format() {
-            locked_dpm.convert = true;
-        }
+        let mut locked_dpm = self.client.client_dpm.lock().unwrap();
+        locked_dpm.convert =
+            !pf.is_default_pixel_format() || client_be != cfg!(target_endian = "big");
+        locked_dpm.pf = pf;
+        locked_dpm.client_be = client_be;
         drop(locked_dpm);
-        if true_color_flag == 0 {
-            self.send_color_map();
-        }
+        // The tiles sent in previous format are not trusted.
+        self.client.tile_hashes.lock().unwrap().reset();
 
         self.server.rect_jobs.lock().unwrap().clear();
         self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
@@ -793,9 +1318,23 @@ impl ClientIoHandler {
             num_encoding = u16::from_be_bytes([buf[2], buf[3]]);
         }
 
+        // Client resends the encodings after it is idle, such as when it
+        // regains the focus, the releases of the inputs may be lost.
+        if self.last_input.elapsed() >= INPUT_IDLE_RELEASE {
+            self.release_pressed_inputs();
+        }
+
         let mut locked_dpm = self.client.client_dpm.lock().unwrap();
+        let has_fence = locked_dpm.has_feature(VncFeatures::VncFeatureFence);
+        let has_continuous_updates =
+            locked_dpm.has_feature(VncFeatures::VncFeatureContinuousUpdates);
+        let has_ext_clipboard = locked_dpm.has_feature(VncFeatures::VncFeatureClipboardExt);
+        let has_ext_key_event = locked_dpm.has_feature(VncFeatures::VncFeatureExtKeyEvent);
         locked_dpm.feature = 0;
         locked_dpm.enc = 0;
+        locked_dpm.compression = None;
+        // Encodings are listed in the order of client preference, traverse
+        // them backwards so that the most preferred supported one is selected.
         while num_encoding > 0 {
             let offset = (4 * num_encoding) as usize;
             let enc = i32::from_be_bytes([
@@ -808,6 +1347,11 @@ impl ClientIoHandler {
                 ENCODING_RAW => {
                     locked_dpm.enc = enc;
                 }
+                // CopyRect is used only for the scrolled area, other area is
+                // sent by the selected encoding.
+                ENCODING_COPYRECT => {
+                    locked_dpm.feature |= 1 << VncFeatures::VncFeatureCopyRect as usize;
+                }
                 ENCODING_HEXTILE => {
                     locked_dpm.feature |= 1 << VncFeatures::VncFeatureHextile as usize;
                     locked_dpm.enc = enc;
@@ -817,19 +1361,16 @@ impl ClientIoHandler {
                     locked_dpm.enc = enc;
                 }
                 ENCODING_ZLIB => {
-                    // ZRLE compress better than ZLIB, so prioritize ZRLE.
-                    if locked_dpm.feature & (1 << VncFeatures::VncFeatureZrle as usize) == 0 {
-                        locked_dpm.feature |= 1 << VncFeatures::VncFeatureZlib as usize;
-                        locked_dpm.enc = enc;
-                    }
+                    locked_dpm.feature |= 1 << VncFeatures::VncFeatureZlib as usize;
+                    locked_dpm.enc = enc;
                 }
                 ENCODING_ZRLE => {
                     locked_dpm.feature |= 1 << VncFeatures::VncFeatureZrle as usize;
                     locked_dpm.enc = enc;
                 }
+                // ZYWRLE is not implemented, so the encoding is not selected.
                 ENCODING_ZYWRLE => {
                     locked_dpm.feature |= 1 << VncFeatures::VncFeatureZywrle as usize;
-                    locked_dpm.enc = enc;
                 }
                 ENCODING_DESKTOPRESIZE => {
                     locked_dpm.feature |= 1 << VncFeatures::VncFeatureResize as usize;
@@ -852,18 +1393,54 @@ impl ClientIoHandler {
                 ENCODING_LED_STATE => {
                     locked_dpm.feature |= 1 << VncFeatures::VncFeatureLedState as usize;
                 }
+                ENCODING_FENCE => {
+                    locked_dpm.feature |= 1 << VncFeatures::VncFeatureFence as usize;
+                }
+                ENCODING_CONTINUOUS_UPDATES => {
+                    locked_dpm.feature |= 1 << VncFeatures::VncFeatureContinuousUpdates as usize;
+                }
+                ENCODING_EXT_KEY_EVENT => {
+                    locked_dpm.feature |= 1 << VncFeatures::VncFeatureExtKeyEvent as usize;
+                }
+                ENCODING_EXT_CLIPBOARD if server.clipboard_limit != 0 => {
+                    locked_dpm.feature |= 1 << VncFeatures::VncFeatureClipboardExt as usize;
+                }
+                ENCODING_COMPRESSLEVEL0..=ENCODING_COMPRESSLEVEL9 => {
+                    locked_dpm.compression = Some((enc - ENCODING_COMPRESSLEVEL0) as u8);
+                }
                 _ => {}
             }
 
             num_encoding -= 1;
         }
 
+        // The extensions are advertised when they are enabled by client for
+        // the first time.
+        let advertise_fence = !has_fence && locked_dpm.has_feature(VncFeatures::VncFeatureFence);
+        let advertise_continuous_updates = !has_continuous_updates
+            && locked_dpm.has_feature(VncFeatures::VncFeatureContinuousUpdates);
+        let advertise_ext_clipboard =
+            !has_ext_clipboard && locked_dpm.has_feature(VncFeatures::VncFeatureClipboardExt);
+        let advertise_ext_key_event =
+            !has_ext_key_event && locked_dpm.has_feature(VncFeatures::VncFeatureExtKeyEvent);
         drop(locked_dpm);
         let mut buf: Vec<u8> = Vec::new();
         // VNC desktop resize.
         desktop_resize(&client, &server, &mut buf)?;
         // VNC display cursor define.
         display_cursor_define(&client, &server, &mut buf);
+        if advertise_fence {
+            fence_message(FENCE_REQUEST | FENCE_SUPPORTED_FLAGS, &[], &mut buf);
+        }
+        if advertise_continuous_updates {
+            buf.push(ServerMsg::EndOfContinuousUpdates as u8);
+        }
+        if advertise_ext_clipboard {
+            ext_clipboard_caps(server.clipboard_limit, &mut buf);
+        }
+        if advertise_ext_key_event {
+            ext_key_event_ack(&client, &mut buf);
+        }
         vnc_write(&client, buf);
         vnc_flush(&client);
         self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
@@ -880,6 +1457,7 @@ impl ClientIoHandler {
         let locked_dpm = self.client.client_dpm.lock().unwrap();
         let width = locked_dpm.client_width;
         let height = locked_dpm.client_height;
+        let resize_ext = locked_dpm.has_feature(VncFeatures::VncFeatureResizeExt);
         drop(locked_dpm);
         let client = self.client.clone();
         let mut locked_state = client.conn_state.lock().unwrap();
@@ -893,6 +1471,12 @@ impl ClientIoHandler {
             let y = u16::from_be_bytes([buf[4], buf[5]]) as i32;
             let w = u16::from_be_bytes([buf[6], buf[7]]) as i32;
             let h = u16::from_be_bytes([buf[8], buf[9]]) as i32;
+            // The whole area is resent, even if it is not changed.
+            client
+                .tile_hashes
+                .lock()
+                .unwrap()
+                .invalidate(&Rectangle::new(x, y, w, h));
             set_area_dirty(
                 &mut client.dirty_bitmap.lock().unwrap(),
                 x,
@@ -904,113 +1488,508 @@ impl ClientIoHandler {
             )?;
         }
         drop(locked_state);
+        // Tell the client that the desktop size can be changed by it.
+        if buf[1] == 0 && resize_ext {
+            let mut buf: Vec<u8> = Vec::new();
+            buf.append(&mut (ServerMsg::FramebufferUpdate as u8).to_be_bytes().to_vec());
+            buf.append(&mut (0_u8).to_be_bytes().to_vec());
+            buf.append(&mut (1_u16).to_be_bytes().to_vec());
+            desktop_resize_ext(
+                DESKTOP_RESIZE_REASON_SERVER,
+                DESKTOP_RESIZE_STATUS_OK,
+                width,
+                height,
+                &mut buf,
+            );
+            vnc_write(&client, buf);
+            vnc_flush(&client);
+        }
+        // The initial led state and pointer mode are sent after the first
+        // update request.
+        let mut buf: Vec<u8> = Vec::new();
+        vnc_update_led_state(&client, kbd_led_state(), &mut buf);
+        vnc_update_pointer_type(&client, pointer_is_absolute(), &mut buf);
+        if !buf.is_empty() {
+            vnc_write(&client, buf);
+            vnc_flush(&client);
+        }
         self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
         Ok(())
     }
 
-    /// Keyboard event.
-    pub fn key_envent(&mut self) -> Result<()> {
+    /// Client requests to change the desktop size, the request is forwarded
+    /// to the graphic hardware, and the result is replied to the client.
+    fn set_desktop_size(&mut self) {
         if self.expect == 1 {
             self.expect = 8;
-            return Ok(());
+            return;
         }
         let buf = self.read_incoming_msg();
-        let down: bool = buf[1] != 0;
-        let org_keysym = i32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
-        let mut keysym = org_keysym;
-        let server = self.server.clone();
-
-        // Uppercase -> Lowercase.
-        if (ASCII_A..=ASCII_Z).contains(&keysym) {
-            keysym += UPPERCASE_TO_LOWERCASE;
+        let num_screens = buf[6] as usize;
+        if self.expect == 8 && num_screens > 0 {
+            self.expect = 8 + num_screens * 16;
+            return;
         }
 
-        let keycode: u16 = match server.keysym2keycode.get(&(keysym as u16)) {
-            Some(k) => *k,
-            None => 0,
-        };
-
-        // Ctr + Alt + Num(1~9)
-        // Switch to the corresponding display device.
-        if (KEYCODE_1..KEYCODE_9 + 1).contains(&keycode)
-            && down
-            && self.server.display_listener.is_some()
-            && keyboard_modifier_get(KeyboardModifier::KeyModCtrl)
-            && keyboard_modifier_get(KeyboardModifier::KeyModAlt)
+        let width = u16::from_be_bytes([buf[2], buf[3]]);
+        let height = u16::from_be_bytes([buf[4], buf[5]]);
+        let status = if num_screens == 0
+            || !(1..=MAX_WINDOW_WIDTH).contains(&width)
+            || !(1..=MAX_WINDOW_HEIGHT).contains(&height)
         {
-            keyboard_state_reset();
-            console_select(Some((keycode - KEYCODE_1) as usize))?;
-        }
-
-        update_key_state(down, org_keysym, keycode)?;
-        key_event(keycode, down)?;
+            DESKTOP_RESIZE_STATUS_INVALID_LAYOUT
+        } else {
+            let con_id = self
+                .server
+                .display_listener
+                .as_ref()
+                .and_then(|dcl| dcl.upgrade())
+                .and_then(|dcl| dcl.lock().unwrap().con_id);
+            match graphic_hardware_resize(con_id, width as u32, height as u32) {
+                Ok(()) => DESKTOP_RESIZE_STATUS_FORWARDED,
+                Err(e) => {
+                    warn!(
+                        "Vnc client {} failed to set desktop size to {}x{}: {:?}",
+                        self.client.addr, width, height, e
+                    );
+                    DESKTOP_RESIZE_STATUS_PROHIBITED
+                }
+            }
+        };
 
+        // The desktop size is not changed until the graphic hardware finishes it.
+        let client = self.client.clone();
+        let locked_dpm = client.client_dpm.lock().unwrap();
+        let client_width = locked_dpm.client_width;
+        let client_height = locked_dpm.client_height;
+        drop(locked_dpm);
+        let mut buf: Vec<u8> = Vec::new();
+        buf.append(&mut (ServerMsg::FramebufferUpdate as u8).to_be_bytes().to_vec());
+        buf.append(&mut (0_u8).to_be_bytes().to_vec());
+        buf.append(&mut (1_u16).to_be_bytes().to_vec());
+        desktop_resize_ext(
+            DESKTOP_RESIZE_REASON_CLIENT,
+            status,
+            client_width,
+            client_height,
+            &mut buf,
+        );
+        vnc_write(&client, buf);
+        vnc_flush(&client);
         self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
-        Ok(())
     }
 
-    // Mouse event.
-    pub fn point_event(&mut self) -> Result<()> {
+    /// Client enables or disables the continuous updates of the area.
+    fn enable_continuous_updates(&mut self) -> Result<()> {
         if self.expect == 1 {
-            self.expect = 6;
+            self.expect = 10;
             return Ok(());
         }
-
         let buf = self.read_incoming_msg();
-        let mut x = ((buf[2] as u16) << 8) + buf[3] as u16;
-        let mut y = ((buf[4] as u16) << 8) + buf[5] as u16;
-
-        // Window size alignment.
-        let locked_surface = self.server.vnc_surface.lock().unwrap();
-        let width = get_image_width(locked_surface.server_image);
-        let height = get_image_height(locked_surface.server_image);
-        drop(locked_surface);
-        x = ((x as u64 * ABS_MAX) / width as u64) as u16;
-        y = ((y as u64 * ABS_MAX) / height as u64) as u16;
-
-        // ASCII -> HidCode.
-        let button_mask: u8 = match buf[1] {
-            INPUT_POINT_LEFT => 0x01,
-            INPUT_POINT_MIDDLE => 0x04,
-            INPUT_POINT_RIGHT => 0x02,
-            _ => buf[1],
-        };
+        let client = self.client.clone();
+        let locked_dpm = client.client_dpm.lock().unwrap();
+        let supported = locked_dpm.has_feature(VncFeatures::VncFeatureContinuousUpdates);
+        let width = locked_dpm.client_width;
+        let height = locked_dpm.client_height;
+        drop(locked_dpm);
+        if !supported {
+            warn!(
+                "Vnc client {} enables continuous updates without negotiation",
+                client.addr
+            );
+            self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+            return Ok(());
+        }
 
-        point_event(button_mask as u32, x as u32, y as u32)?;
+        let mut locked_state = client.conn_state.lock().unwrap();
+        if buf[1] != 0 {
+            let x = u16::from_be_bytes([buf[2], buf[3]]) as i32;
+            let y = u16::from_be_bytes([buf[4], buf[5]]) as i32;
+            let w = u16::from_be_bytes([buf[6], buf[7]]) as i32;
+            let h = u16::from_be_bytes([buf[8], buf[9]]) as i32;
+            // The whole area is sent at first, then only the changed part is pushed.
+            set_area_dirty(
+                &mut client.dirty_bitmap.lock().unwrap(),
+                x,
+                y,
+                w,
+                h,
+                width,
+                height,
+            )?;
+            locked_state.continuous_updates = Some(Rectangle::new(x, y, w, h));
+            locked_state.update_state = UpdateState::Force;
+        } else {
+            // The dirty area is kept until client requests the update again.
+            locked_state.continuous_updates = None;
+            locked_state.update_state = UpdateState::No;
+            drop(locked_state);
+            vnc_write(&client, vec![ServerMsg::EndOfContinuousUpdates as u8]);
+            vnc_flush(&client);
+        }
         self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
         Ok(())
     }
 
-    /// Client cut text.
-    pub fn client_cut_event(&mut self) {
-        let buf = self.read_incoming_msg();
+    /// Fence sent by client, it is replied if it is a request. As the
+    /// messages are handled in order, the blocking flags are always
+    /// satisfied.
+    fn client_fence(&mut self) -> Result<()> {
         if self.expect == 1 {
-            self.expect = 8;
-            return;
+            self.expect = 9;
+            return Ok(());
+        }
+        let buf = self.read_incoming_msg();
+        let len = buf[8] as usize;
+        if len > FENCE_MAX_PAYLOAD {
+            return Err(anyhow!(VncError::ProtocolMessageFailed(format!(
+                "fence payload length {} exceeds {}",
+                len, FENCE_MAX_PAYLOAD
+            ))));
+        }
+        if self.expect == 9 && len > 0 {
+            self.expect = 9 + len;
+            return Ok(());
+        }
+
+        let flags = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        let payload = buf[9..].to_vec();
+        self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+        // The response of the fence sent by server needs no reply.
+        if flags & FENCE_REQUEST == 0 {
+            return Ok(());
+        }
+        let flags = flags & FENCE_SUPPORTED_FLAGS & !FENCE_REQUEST;
+        if flags & FENCE_SYNC_NEXT != 0 {
+            self.sync_fence = Some((flags, payload));
+            return Ok(());
+        }
+        let mut buf = Vec::new();
+        fence_message(flags, &payload, &mut buf);
+        vnc_write(&self.client, buf);
+        vnc_flush(&self.client);
+        Ok(())
+    }
+
+    /// Keyboard event.
+    pub fn key_envent(&mut self) -> Result<()> {
+        if self.expect == 1 {
+            self.expect = 8;
+            return Ok(());
+        }
+        let buf = self.read_incoming_msg();
+        let down: bool = buf[1] != 0;
+        let keysym = i32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        self.keysym_event(down, keysym)?;
+
+        self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+        Ok(())
+    }
+
+    /// Message of QEMU client. The extended key event is handled if it is
+    /// negotiated, and the others are skipped.
+    fn qemu_client_msg(&mut self) -> Result<()> {
+        if self.expect == 1 {
+            self.expect = 2;
+            return Ok(());
+        }
+        let buf = self.read_incoming_msg();
+        let ext_key_event = self
+            .client
+            .client_dpm
+            .lock()
+            .unwrap()
+            .has_feature(VncFeatures::VncFeatureExtKeyEvent);
+        if buf[1] != QEMU_CLIENT_EXT_KEY_EVENT || !ext_key_event {
+            return self.skip_unsupported_msg();
+        }
+        if self.expect == 2 {
+            self.expect = 12;
+            return Ok(());
+        }
+
+        let down = u16::from_be_bytes([buf[2], buf[3]]) != 0;
+        let keysym = i32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        // The keycode is the XT scan code, with the high bit set for the
+        // keys of 0xe0 prefix. 0 means the key is unknown to client, then
+        // it falls back to the keysym.
+        let keycode = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+        match u16::try_from(keycode) {
+            Ok(keycode) if keycode != 0 && keycode <= 0xff => {
+                self.do_key_event(down, keysym, keycode)
+            }
+            _ => self.keysym_event(down, keysym),
+        }
+        .unwrap_or_else(|e| error!("Extended key event error: {:?}", e));
+
+        self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+        Ok(())
+    }
+
+    /// Key event of the keysym, which is translated with the keymap of
+    /// guest. Shift and AltGr are pressed or released around the key if the
+    /// keysym needs another state of them in the keymap.
+    fn keysym_event(&mut self, down: bool, keysym: i32) -> Result<()> {
+        let key = match keysym_lookup(&self.server.keysym2keycode, keysym as u32) {
+            Some(key) => key,
+            None => return self.do_key_event(down, keysym, 0),
+        };
+        if !down || self.pressed_keys.contains(&key.keycode) {
+            return self.do_key_event(down, keysym, key.keycode);
+        }
+
+        let mods = key.modifier_events(&self.pressed_keys);
+        for &(keycode, mod_down) in &mods {
+            modifier_key_event(keycode, mod_down)?;
+        }
+        let ret = self.do_key_event(true, keysym, key.keycode);
+        for &(keycode, mod_down) in mods.iter().rev() {
+            modifier_key_event(keycode, !mod_down)?;
+        }
+        ret
+    }
+
+    /// Forward the key to the keyboard. The keys pressed by client are
+    /// tracked, so that they are released when client is disconnected, and
+    /// the repeated presses of the held keys are dropped.
+    fn do_key_event(&mut self, down: bool, keysym: i32, keycode: u16) -> Result<()> {
+        self.last_input = Instant::now();
+        if keycode != 0 {
+            if down && !self.pressed_keys.insert(keycode) {
+                return Ok(());
+            } else if !down {
+                self.pressed_keys.remove(&keycode);
+            }
+        }
+
+        // Ctr + Alt + Num(1~9)
+        // Switch to the corresponding display device.
+        if (KEYCODE_1..KEYCODE_9 + 1).contains(&keycode)
+            && down
+            && self.server.display_listener.is_some()
+            && keyboard_modifier_get(KeyboardModifier::KeyModCtrl)
+            && keyboard_modifier_get(KeyboardModifier::KeyModAlt)
+        {
+            keyboard_state_reset();
+            console_select(Some((keycode - KEYCODE_1) as usize))?;
+        }
+
+        update_key_state(down, keysym, keycode)?;
+        key_event(keycode, down)
+    }
+
+    /// Release the keys and mouse buttons which are still pressed by client.
+    fn release_pressed_inputs(&mut self) {
+        for keycode in self.pressed_keys.drain() {
+            update_key_state(false, 0, keycode)
+                .and_then(|_| key_event(keycode, false))
+                .unwrap_or_else(|e| error!("Failed to release key {}: {:?}", keycode, e));
+        }
+        if self.pressed_buttons != 0 {
+            self.pressed_buttons = 0;
+            let (x, y) = self.pointer_pos;
+            if pointer_is_absolute() {
+                point_event(0, x, y)
+            } else {
+                rel_point_event(0, 0, 0)
+            }
+            .unwrap_or_else(|e| error!("Failed to release mouse buttons: {:?}", e));
+        }
+    }
+
+    // Mouse event.
+    pub fn point_event(&mut self) -> Result<()> {
+        if self.expect == 1 {
+            self.expect = 6;
+            return Ok(());
+        }
+
+        let buf = self.read_incoming_msg();
+        let mut x = ((buf[2] as u16) << 8) + buf[3] as u16;
+        let mut y = ((buf[4] as u16) << 8) + buf[5] as u16;
+
+        // ASCII -> HidCode.
+        let button_mask: u8 = match buf[1] {
+            INPUT_POINT_LEFT => 0x01,
+            INPUT_POINT_MIDDLE => 0x04,
+            INPUT_POINT_RIGHT => 0x02,
+            _ => buf[1],
+        };
+        self.last_input = Instant::now();
+        self.pressed_buttons = button_mask as u32;
+
+        // The motion is sent to the relative pointer device instead of the
+        // position, otherwise the cursor of guest drifts from the client.
+        if !pointer_is_absolute() {
+            let (dx, dy) = self.relative_motion(x, y);
+            rel_point_event(button_mask as u32, dx, dy)?;
+            self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+            return Ok(());
+        }
+        self.last_pointer = None;
+
+        // Window size alignment.
+        let locked_surface = self.server.vnc_surface.lock().unwrap();
+        let width = get_image_width(locked_surface.server_image);
+        let height = get_image_height(locked_surface.server_image);
+        drop(locked_surface);
+        x = ((x as u64 * ABS_MAX) / width as u64) as u16;
+        y = ((y as u64 * ABS_MAX) / height as u64) as u16;
+
+        self.pointer_pos = (x as u32, y as u32);
+        point_event(button_mask as u32, x as u32, y as u32)?;
+        self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+        Ok(())
+    }
+
+    /// Motion of the pointer for the relative pointer device. The client
+    /// which is switched to relative mode sends the motion offset by the
+    /// center, otherwise the motion is computed from the last position.
+    fn relative_motion(&mut self, x: u16, y: u16) -> (i32, i32) {
+        let locked_dpm = self.client.client_dpm.lock().unwrap();
+        let relative_mode = locked_dpm.has_feature(VncFeatures::VncFeaturePointerTypeChange)
+            && locked_dpm.pointer_absolute == Some(false);
+        drop(locked_dpm);
+        if relative_mode {
+            return (x as i32 - POINTER_REL_CENTER, y as i32 - POINTER_REL_CENTER);
+        }
+
+        let locked_surface = self.server.vnc_surface.lock().unwrap();
+        let width = get_image_width(locked_surface.server_image);
+        let height = get_image_height(locked_surface.server_image);
+        drop(locked_surface);
+        let (motion, pos) = pointer_motion(self.last_pointer, (x, y), width, height);
+        self.last_pointer = Some(pos);
+        motion
+    }
+
+    /// Client cut text, the text is passed to the clipboard notifiers.
+    pub fn client_cut_event(&mut self) -> Result<()> {
+        let buf = self.read_incoming_msg();
+        if self.expect == 1 {
+            self.expect = 8;
+            return Ok(());
         }
+        // The negative length means the payload is in the format of
+        // extended clipboard.
+        let len = i32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
         if self.expect == 8 {
-            let buf = [buf[4], buf[5], buf[6], buf[7]];
-            let len = u32::from_be_bytes(buf);
+            let limit = self.server.clipboard_limit;
+            let max = if len < 0 {
+                // Extended clipboard is valid only after the caps of server
+                // is sent in the negotiation.
+                if !self
+                    .client
+                    .client_dpm
+                    .lock()
+                    .unwrap()
+                    .has_feature(VncFeatures::VncFeatureClipboardExt)
+                {
+                    return Err(anyhow!(VncError::ProtocolMessageFailed(
+                        "extended clipboard is not negotiated".to_string()
+                    )));
+                }
+                ext_clipboard_msg_max(limit)
+            } else {
+                limit
+            };
+            let len = len.unsigned_abs() as usize;
+            if len > max {
+                if limit != 0 {
+                    warn!(
+                        "Drop the clipboard of {} bytes from vnc client {}",
+                        len, self.client.addr
+                    );
+                }
+                self.start_skip_msg(8 + len);
+                return Ok(());
+            }
             if len > 0 {
-                self.expect += len as usize;
-                return;
+                self.expect += len;
+                return Ok(());
             }
         }
 
+        if len < 0 {
+            self.ext_clipboard_event(&buf[8..])?;
+        } else if len > 0 {
+            display_clipboard_receive(&client_latin1_text(&buf[8..]));
+        }
         self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+        Ok(())
+    }
+
+    /// Drop the message of `len` bytes, which starts at the head of the
+    /// incoming buffer, in chunks without buffering the whole of it.
+    fn start_skip_msg(&mut self, len: usize) {
+        self.msg_skip = len;
+        self.expect = cmp::min(self.msg_skip, MSG_SKIP_CHUNK);
+        self.msg_handler = ClientIoHandler::skip_msg_data;
+    }
+
+    /// Skip the dropped message chunk by chunk.
+    fn skip_msg_data(&mut self) -> Result<()> {
+        self.client
+            .in_buffer
+            .lock()
+            .unwrap()
+            .remove_front(self.expect);
+        self.msg_skip -= self.expect;
+        self.expect = cmp::min(self.msg_skip, MSG_SKIP_CHUNK);
+        if self.expect == 0 {
+            self.update_event_handler(1, ClientIoHandler::handle_protocol_msg);
+        }
+        Ok(())
+    }
+
+    /// Handle the message of extended clipboard.
+    fn ext_clipboard_event(&mut self, data: &[u8]) -> Result<()> {
+        match parse_ext_clipboard(data, self.server.clipboard_limit)? {
+            ExtClipboardMsg::Caps(flags) => self.ext_clipboard_caps = flags,
+            ExtClipboardMsg::Provide(Some(text)) => display_clipboard_receive(&text),
+            ExtClipboardMsg::Notify(formats) => {
+                // Ask for the text, which is provided by client later.
+                if formats & EXT_CLIPBOARD_FORMAT_TEXT != 0
+                    && self.ext_clipboard_caps & EXT_CLIPBOARD_ACTION_REQUEST != 0
+                {
+                    let mut buf = Vec::new();
+                    ext_clipboard_request(&mut buf);
+                    vnc_write(&self.client, buf);
+                    vnc_flush(&self.client);
+                }
+            }
+            ExtClipboardMsg::Provide(None) | ExtClipboardMsg::Ignored => {}
+        }
+        Ok(())
     }
 
-    /// Invalid authentication, send 1 to reject.
-    fn auth_failed(&mut self, msg: &str) {
-        let auth_rej: u8 = 1;
-        let mut buf: Vec<u8> = vec![1u8];
-        buf.append(&mut (auth_rej as u32).to_be_bytes().to_vec());
-        // If the RFB protocol version is above 3.8, an error reason will be returned.
+    /// Reject the authentication, send the failed security result to client.
+    /// The reason is followed if the RFB protocol version is 3.8 or above.
+    pub fn fail_auth(&mut self, reason: &str) {
+        let mut buf = 1_u32.to_be_bytes().to_vec();
         if self.client.conn_state.lock().unwrap().version.minor >= 8 {
-            let err_msg = msg;
-            buf.append(&mut (err_msg.len() as u32).to_be_bytes().to_vec());
-            buf.append(&mut err_msg.as_bytes().to_vec());
+            append_reason(&mut buf, reason);
         }
+        self.reject_client(buf, reason);
+    }
+
+    /// Reject the client during security type negotiation. It is an invalid
+    /// security type in RFB 3.3 or zero security types in RFB 3.7 and above,
+    /// both followed by the reason.
+    fn fail_negotiation(&mut self, reason: &str) {
+        let mut buf = if self.client.conn_state.lock().unwrap().version.minor >= 7 {
+            vec![0_u8]
+        } else {
+            (AuthState::Invalid as u32).to_be_bytes().to_vec()
+        };
+        append_reason(&mut buf, reason);
+        self.reject_client(buf, reason);
+    }
+
+    /// Send the rejection message to client, and log the reason with the
+    /// address of client.
+    pub fn reject_client(&mut self, buf: Vec<u8>, reason: &str) {
+        error!("Vnc client {} is rejected: {}", self.client.addr, reason);
         let client = self.client.clone();
         vnc_write(&client, buf);
         vnc_flush(&client);
@@ -1042,6 +2021,33 @@ impl ClientIoHandler {
             .remove_front(self.expect);
         self.expect = expect;
         self.msg_handler = msg_handler;
+        // The message after the synchronous fence is handled, reply the fence.
+        if let Some((flags, payload)) = self.sync_fence.take() {
+            let mut buf = Vec::new();
+            fence_message(flags, &payload, &mut buf);
+            vnc_write(&self.client, buf);
+            vnc_flush(&self.client);
+        }
+        // Client moves to the next handshake step, renew the deadline.
+        if self.auth_timer.is_some() {
+            if let Some(ctx) = EventLoop::get_ctx(None) {
+                self.arm_auth_timer(ctx);
+            }
+        }
+    }
+
+    /// Release the resources held by the client when it is disconnected.
+    fn teardown(&mut self) {
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            self.cancel_handshake_timer(ctx);
+            self.cancel_auth_timer(ctx);
+        }
+        self.sasl_teardown();
+        self.release_pressed_inputs();
+        // Shutdown stream.
+        if let Err(e) = self.stream.shutdown(Shutdown::Both) {
+            error!("Shutdown stream failed: {:?}", e);
+        }
     }
 
     fn disconn_evt_handler(&mut self) -> Vec<EventNotifier> {
@@ -1126,10 +2132,7 @@ impl EventNotifierHelper for ClientIoHandler {
             let addr = client.addr.clone();
             let server = locked_client_io.server.clone();
             let notifiers = locked_client_io.disconn_evt_handler();
-            // Shutdown stream.
-            if let Err(e) = locked_client_io.stream.shutdown(Shutdown::Both) {
-                error!("Shutdown stream failed: {:?}", e);
-            }
+            locked_client_io.teardown();
             drop(locked_client_io);
             server.client_handlers.lock().unwrap().remove(&addr);
             Some(notifiers)
@@ -1156,7 +2159,17 @@ pub fn get_rects(client: &Arc<ClientState>, server: &Arc<VncServer>, dirty_num:
     if !locked_state.is_need_update() {
         return Ok(());
     }
+    let requested = locked_state.update_state != UpdateState::No;
+    let continuous_updates = locked_state.continuous_updates.clone();
+    let now = Instant::now();
+    let paced = locked_state.pacing.is_ready(now);
     drop(locked_state);
+    // The update is deferred if it comes faster than max fps, or the client
+    // is too slow to receive the previous updates. The dirty area is kept, so
+    // that it is merged into the next update.
+    if !paced || is_output_congested(client) {
+        return Ok(());
+    }
 
     let mut x: u64;
     let mut y: u64 = 0;
@@ -1211,22 +2224,136 @@ pub fn get_rects(client: &Arc<ClientState>, server: &Arc<VncServer>, dirty_num:
         }
     }
 
+    // Only the area of continuous updates is pushed to client, the dirty
+    // area outside it is kept. The area is aligned to the dirty bitmap, so
+    // that the kept area does not overlap with it.
+    if let Some(area) = continuous_updates.filter(|_| !requested) {
+        let x = area.x - area.x % DIRTY_PIXELS_NUM as i32;
+        let x2 = round_up_div((area.x + area.w) as u64, DIRTY_PIXELS_NUM as u64) as i32
+            * DIRTY_PIXELS_NUM as i32;
+        let area = Rectangle::new(x, area.y, x2 - x, area.h);
+        let mut area_rects = Vec::new();
+        for rect in rects {
+            for kept in rect.subtract(&area) {
+                set_area_dirty(
+                    &mut locked_dirty,
+                    kept.x,
+                    kept.y,
+                    kept.w,
+                    kept.h,
+                    width as i32,
+                    height as i32,
+                )?;
+            }
+            if let Some(rect) = rect.intersect(&area) {
+                area_rects.push(rect);
+            }
+        }
+        rects = area_rects;
+    }
     drop(locked_dirty);
 
+    if rects.is_empty() && !requested && client.copy_rects.lock().unwrap().is_empty() {
+        client.conn_state.lock().unwrap().dirty_num = 0;
+        return Ok(());
+    }
+    let copy_rects = std::mem::take(&mut *client.copy_rects.lock().unwrap());
     server
         .rect_jobs
         .lock()
         .unwrap()
-        .push(RectInfo::new(client, rects));
+        .push(RectInfo::new(client, copy_rects, rects));
 
-    client.conn_state.lock().unwrap().clear_update_state();
+    let mut locked_state = client.conn_state.lock().unwrap();
+    locked_state.clear_update_state();
+    locked_state.pacing.record(now);
     Ok(())
 }
 
+/// Whether the output of previous updates not sent to client exceeds the
+/// soft limit, which is a fraction of the output limit. It is not useful
+/// to generate more updates for the client in this case, but the messages
+/// of negotiation are still sent.
+fn is_output_congested(client: &Arc<ClientState>) -> bool {
+    let locked_buffer = client.out_buffer.lock().unwrap();
+    match locked_buffer.limit() {
+        Some(limit) => locked_buffer.len() >= limit / OUTPUT_THROTTLE_SCALE as usize,
+        None => false,
+    }
+}
+
+/// Fence message with the flags and payload.
+fn fence_message(flags: u32, payload: &[u8], buf: &mut Vec<u8>) {
+    buf.push(ServerMsg::Fence as u8);
+    buf.append(&mut vec![0_u8; 3]);
+    buf.append(&mut flags.to_be_bytes().to_vec());
+    buf.push(payload.len() as u8);
+    buf.extend_from_slice(payload);
+}
+
+/// Parse the pixel format requested by client from the 16 bytes of
+/// PIXEL_FORMAT. Only the true color format is supported, and
+/// each color should fit in the pixel with a max of 2^n - 1, n <= 8.
+fn parse_pixel_format(buf: &[u8]) -> Result<PixelFormat> {
+    let bit_per_pixel = buf[0];
+    let depth = buf[1];
+    if buf[3] == 0 {
+        return Err(anyhow!(VncError::UnsupportedPixelFormat(String::from(
+            "colour map is not supported"
+        ))));
+    }
+    // Verify the validity of pixel format.
+    // bit_per_pixel: Bits occupied by each pixel.
+    if ![8, 16, 32].contains(&bit_per_pixel) {
+        return Err(anyhow!(VncError::UnsupportedPixelFormat(format!(
+            "{} bits per pixel",
+            bit_per_pixel
+        ))));
+    }
+    if depth == 0 || depth > bit_per_pixel {
+        return Err(anyhow!(VncError::UnsupportedPixelFormat(format!(
+            "depth {} with {} bits per pixel",
+            depth, bit_per_pixel
+        ))));
+    }
+
+    let mut pf = PixelFormat {
+        pixel_bits: bit_per_pixel,
+        pixel_bytes: bit_per_pixel / BIT_PER_BYTE as u8,
+        depth,
+        ..Default::default()
+    };
+    let colors = [
+        ("red", &mut pf.red),
+        ("green", &mut pf.green),
+        ("blue", &mut pf.blue),
+    ];
+    for (i, (name, color)) in colors.into_iter().enumerate() {
+        let max = u16::from_be_bytes([buf[4 + i * 2], buf[5 + i * 2]]);
+        let shift = buf[10 + i];
+        let bits = max.count_ones() as u8;
+        if max == 0
+            || max > 0xff
+            || (max & (max + 1)) != 0
+            || u16::from(shift) + u16::from(bits) > u16::from(bit_per_pixel)
+        {
+            return Err(anyhow!(VncError::UnsupportedPixelFormat(format!(
+                "{} max {} shift {} with {} bits per pixel",
+                name, max, shift, bit_per_pixel
+            ))));
+        }
+        color.set_color_info(shift, max);
+    }
+    Ok(pf)
+}
+
 /// Set pixformat for client.
 fn pixel_format_message(client: &Arc<ClientState>, buf: &mut Vec<u8>) {
     let mut locked_dpm = client.client_dpm.lock().unwrap();
     locked_dpm.pf.init_pixelformat();
+    // The client switches to the pixel format of server.
+    locked_dpm.client_be = cfg!(target_endian = "big");
+    locked_dpm.convert = false;
     let big_endian: u8 = u8::from(cfg!(target_endian = "big"));
     buf.append(&mut locked_dpm.pf.pixel_bits.to_be_bytes().to_vec()); // Bit per pixel.
     buf.append(&mut locked_dpm.pf.depth.to_be_bytes().to_vec()); // Depth.
@@ -1256,8 +2383,8 @@ pub fn desktop_resize(
     }
     drop(locked_surface);
     let mut locked_dpm = client.client_dpm.lock().unwrap();
-    if (!locked_dpm.has_feature(VncFeatures::VncFeatureResizeExt)
-        && !locked_dpm.has_feature(VncFeatures::VncFeatureResize))
+    let resize_ext = locked_dpm.has_feature(VncFeatures::VncFeatureResizeExt);
+    if (!resize_ext && !locked_dpm.has_feature(VncFeatures::VncFeatureResize))
         || (locked_dpm.client_width == width && locked_dpm.client_height == height)
     {
         return Ok(());
@@ -1269,10 +2396,69 @@ pub fn desktop_resize(
     buf.append(&mut (ServerMsg::FramebufferUpdate as u8).to_be_bytes().to_vec());
     buf.append(&mut (0_u8).to_be_bytes().to_vec());
     buf.append(&mut (1_u16).to_be_bytes().to_vec());
-    framebuffer_update(0, 0, width, height, ENCODING_DESKTOPRESIZE, buf);
+    if resize_ext {
+        desktop_resize_ext(
+            DESKTOP_RESIZE_REASON_SERVER,
+            DESKTOP_RESIZE_STATUS_OK,
+            width,
+            height,
+            buf,
+        );
+    } else {
+        framebuffer_update(0, 0, width, height, ENCODING_DESKTOPRESIZE, buf);
+    }
     Ok(())
 }
 
+/// Press or release the modifier around the key of keysym, which is not
+/// tracked as the key pressed by client.
+fn modifier_key_event(keycode: u16, down: bool) -> Result<()> {
+    update_key_state(down, 0, keycode)?;
+    key_event(keycode, down)
+}
+
+/// Confirm the extended key event to client by a pseudo rectangle.
+fn ext_key_event_ack(client: &Arc<ClientState>, buf: &mut Vec<u8>) {
+    let locked_dpm = client.client_dpm.lock().unwrap();
+    let width = locked_dpm.client_width;
+    let height = locked_dpm.client_height;
+    drop(locked_dpm);
+    buf.push(ServerMsg::FramebufferUpdate as u8);
+    buf.push(0);
+    buf.append(&mut 1_u16.to_be_bytes().to_vec());
+    framebuffer_update(0, 0, width, height, ENCODING_EXT_KEY_EVENT, buf);
+}
+
+/// Append the ExtendedDesktopSize rectangle, the desktop consists of one
+/// screen which covers the whole framebuffer.
+///
+/// # Arguments
+///
+/// * `reason` - Reason of the desktop size change.
+/// * `status` - Status of the desktop size change requested by client.
+/// * `width` - Width of the framebuffer.
+/// * `height` - Height of the framebuffer.
+/// * `buf` - send buffer.
+fn desktop_resize_ext(reason: u16, status: u16, width: i32, height: i32, buf: &mut Vec<u8>) {
+    framebuffer_update(
+        reason as i32,
+        status as i32,
+        width,
+        height,
+        ENCODING_DESKTOP_RESIZE_EXT,
+        buf,
+    );
+    // Number of screens, followed by padding.
+    buf.append(&mut vec![1_u8, 0, 0, 0]);
+    // Screen id, x, y, width, height and flags.
+    buf.append(&mut 0_u32.to_be_bytes().to_vec());
+    buf.append(&mut 0_u16.to_be_bytes().to_vec());
+    buf.append(&mut 0_u16.to_be_bytes().to_vec());
+    buf.append(&mut (width as u16).to_be_bytes().to_vec());
+    buf.append(&mut (height as u16).to_be_bytes().to_vec());
+    buf.append(&mut 0_u32.to_be_bytes().to_vec());
+}
+
 /// Set color depth for client.
 pub fn set_color_depth(client: &Arc<ClientState>, buf: &mut Vec<u8>) {
     let mut locked_dpm = client.client_dpm.lock().unwrap();
@@ -1290,7 +2476,42 @@ pub fn set_color_depth(client: &Arc<ClientState>, buf: &mut Vec<u8>) {
     }
 }
 
-/// Send framebuf of mouse to the client.
+/// Build the bitmask of cursor for rich cursor encoding. Each row is padded
+/// to a whole byte, and the bit of pixel is set if the pixel is opaque.
+pub fn cursor_mask(cursor: &DisplayMouse) -> Vec<u8> {
+    let width = cursor.width as u64;
+    let height = cursor.height as u64;
+    let bpl = round_up_div(width, BIT_PER_BYTE as u64);
+    // Set the bit for mask.
+    let bit_mask: u8 = 0x80;
+
+    let mut mask: Vec<u8> = vec![0; (bpl * height) as usize];
+    let first_bit = if cfg!(target_endian = "big") {
+        0_usize
+    } else {
+        bytes_per_pixel() - 1
+    };
+
+    for j in 0..height {
+        let mut bit = bit_mask;
+        for i in 0..width {
+            let idx = ((i + j * width) as usize) * bytes_per_pixel() + first_bit;
+            if let Some(n) = cursor.data.get(idx) {
+                if *n == 0xff {
+                    mask[(j * bpl + i / BIT_PER_BYTE as u64) as usize] |= bit;
+                }
+            }
+            bit >>= 1;
+            if bit == 0 {
+                bit = bit_mask;
+            }
+        }
+    }
+    mask
+}
+
+/// Send framebuf of mouse to the client. The hidden cursor is sent as
+/// an empty rectangle.
 pub fn display_cursor_define(
     client: &Arc<ClientState>,
     server: &Arc<VncServer>,
@@ -1310,9 +2531,7 @@ pub fn display_cursor_define(
         }
     };
     drop(locked_cursor);
-    if cursor.data.is_empty()
-        || cursor.data.len() != ((cursor.width * cursor.height) as usize) * bytes_per_pixel()
-    {
+    if cursor.data.len() != ((cursor.width * cursor.height) as usize) * bytes_per_pixel() {
         return;
     }
     if client
@@ -1357,20 +2576,124 @@ pub fn display_cursor_define(
             buf,
         );
         let dpm = client.client_dpm.lock().unwrap().clone();
-        let data_size = cursor.width * cursor.height * dpm.pf.pixel_bytes as u32;
         let data_ptr = cursor.data.as_ptr() as *mut u8;
-        write_pixel(data_ptr, data_size as usize, &dpm, buf);
+        write_pixel(data_ptr, cursor.data.len(), &dpm, buf);
         buf.append(&mut mask);
     }
 }
 
+/// Send the keyboard led state to the client which supports LED state
+/// pseudo-encoding, if the state is changed since last sent.
+///
+/// # Arguments
+///
+/// * `client` - Vnc client state.
+/// * `kbd_led` - Keyboard led state, in HID led bits.
+/// * `buf` - Send buffer.
+pub fn vnc_update_led_state(client: &Arc<ClientState>, kbd_led: u8, buf: &mut Vec<u8>) {
+    let mut state = 0;
+    if kbd_led & SCROLL_LOCK_LED != 0 {
+        state |= VNC_SCROLL_LOCK;
+    }
+    if kbd_led & NUM_LOCK_LED != 0 {
+        state |= VNC_NUM_LOCK;
+    }
+    if kbd_led & CAPS_LOCK_LED != 0 {
+        state |= VNC_CAPS_LOCK;
+    }
+
+    let mut locked_dpm = client.client_dpm.lock().unwrap();
+    if !locked_dpm.has_feature(VncFeatures::VncFeatureLedState)
+        || locked_dpm.led_state == Some(state)
+    {
+        return;
+    }
+    locked_dpm.led_state = Some(state);
+    drop(locked_dpm);
+
+    buf.append(&mut (ServerMsg::FramebufferUpdate as u8).to_be_bytes().to_vec());
+    buf.append(&mut (0_u8).to_be_bytes().to_vec()); // padding
+    buf.append(&mut (1_u16).to_be_bytes().to_vec()); // number of rects
+    framebuffer_update(0, 0, 1, 1, ENCODING_LED_STATE, buf);
+    buf.push(state);
+}
+
+/// Send the pointer mode to client with the QEMU Pointer Motion Change
+/// pseudo-encoding, if the mode is changed since last sent. The client in
+/// relative mode sends the motion instead of the position.
+///
+/// # Arguments
+///
+/// * `client` - Vnc client state.
+/// * `absolute` - The active pointer device of guest is absolute.
+/// * `buf` - Send buffer.
+pub fn vnc_update_pointer_type(client: &Arc<ClientState>, absolute: bool, buf: &mut Vec<u8>) {
+    let mut locked_dpm = client.client_dpm.lock().unwrap();
+    if !locked_dpm.has_feature(VncFeatures::VncFeaturePointerTypeChange)
+        || locked_dpm.pointer_absolute == Some(absolute)
+    {
+        return;
+    }
+    locked_dpm.pointer_absolute = Some(absolute);
+    let width = locked_dpm.client_width;
+    let height = locked_dpm.client_height;
+    drop(locked_dpm);
+
+    buf.append(&mut (ServerMsg::FramebufferUpdate as u8).to_be_bytes().to_vec());
+    buf.append(&mut (0_u8).to_be_bytes().to_vec()); // padding
+    buf.append(&mut (1_u16).to_be_bytes().to_vec()); // number of rects
+    framebuffer_update(
+        i32::from(absolute),
+        0,
+        width,
+        height,
+        ENCODING_POINTER_TYPE_CHANGE,
+        buf,
+    );
+}
+
+/// Motion of the pointer from the last position to the new one. Both of the
+/// positions are clamped to the screen, so the motion out of the screen edge
+/// is dropped. The first position has no motion.
+///
+/// # Arguments
+///
+/// * `last` - The last position.
+/// * `pos` - The new position.
+/// * `width` - Width of the screen.
+/// * `height` - Height of the screen.
+fn pointer_motion(
+    last: Option<(u16, u16)>,
+    pos: (u16, u16),
+    width: i32,
+    height: i32,
+) -> ((i32, i32), (u16, u16)) {
+    let clamp = |value: u16, size: i32| cmp::min(value as i32, cmp::max(size - 1, 0)) as u16;
+    let pos = (clamp(pos.0, width), clamp(pos.1, height));
+    let motion = match last {
+        Some(last) => (pos.0 as i32 - last.0 as i32, pos.1 as i32 - last.1 as i32),
+        None => (0, 0),
+    };
+    (motion, pos)
+}
+
+/// Queue the message to be sent to client. The client which does not
+/// receive the output in time is disconnected once the output limit is
+/// exceeded.
 pub fn vnc_write(client: &Arc<ClientState>, buf: Vec<u8>) {
     if client.conn_state.lock().unwrap().dis_conn {
         return;
     }
     let mut locked_buffer = client.out_buffer.lock().unwrap();
     if !locked_buffer.is_enough(buf.len()) {
+        error!(
+            "Output queued for vnc client {} exceeds the limit of {} bytes, disconnect it",
+            client.addr,
+            locked_buffer.limit().unwrap_or_default()
+        );
+        drop(locked_buffer);
         client.conn_state.lock().unwrap().dis_conn = true;
+        vnc_disconnect_start(client);
         return;
     }
     locked_buffer.append_limit(buf);
@@ -1379,19 +2702,19 @@ pub fn vnc_write(client: &Arc<ClientState>, buf: Vec<u8>) {
 /// Set the limit size of the output buffer to prevent the client
 /// from stopping receiving data.
 pub fn vnc_update_output_throttle(client: &Arc<ClientState>) {
-    let locked_dpm = client.client_dpm.lock().unwrap();
-    let width = locked_dpm.client_width;
-    let height = locked_dpm.client_height;
-    let bytes_per_pixel = locked_dpm.pf.pixel_bytes;
-    let mut offset = width * height * (bytes_per_pixel as i32) * OUTPUT_THROTTLE_SCALE;
-    drop(locked_dpm);
-
-    offset = cmp::max(offset, MIN_OUTPUT_LIMIT);
-    client
-        .out_buffer
-        .lock()
-        .unwrap()
-        .set_limit(Some(offset as usize));
+    let output_limit = client.conn_state.lock().unwrap().output_limit;
+    let limit = if output_limit != 0 {
+        output_limit
+    } else {
+        let locked_dpm = client.client_dpm.lock().unwrap();
+        let width = locked_dpm.client_width;
+        let height = locked_dpm.client_height;
+        let bytes_per_pixel = locked_dpm.pf.pixel_bytes;
+        let offset = width * height * (bytes_per_pixel as i32) * OUTPUT_THROTTLE_SCALE;
+        drop(locked_dpm);
+        cmp::max(offset, MIN_OUTPUT_LIMIT) as usize
+    };
+    client.out_buffer.lock().unwrap().set_limit(Some(limit));
 }
 
 /// Flush the output buffer.
@@ -1404,6 +2727,20 @@ pub fn vnc_flush(client: &Arc<ClientState>) {
         .unwrap_or_else(|e| error!("Error occurs during data flush:{:?}", e));
 }
 
+/// Append the reason string of rejection to message.
+fn append_reason(buf: &mut Vec<u8>, reason: &str) {
+    buf.append(&mut (reason.len() as u32).to_be_bytes().to_vec());
+    buf.append(&mut reason.as_bytes().to_vec());
+}
+
+/// Get the reason of authentication failure which is sent to client.
+pub fn auth_failure_reason(err: &anyhow::Error) -> String {
+    match err.downcast_ref::<VncError>() {
+        Some(VncError::AuthFailed(_, reason)) => reason.clone(),
+        _ => String::from("Authentication failed"),
+    }
+}
+
 /// Disconnect for vnc client.
 pub fn vnc_disconnect_start(client: &Arc<ClientState>) {
     client
@@ -1413,3 +2750,1936 @@ pub fn vnc_disconnect_start(client: &Arc<ClientState>) {
         .write(1)
         .unwrap_or_else(|e| error!("Error occurs during disconnection: {:?}", e));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::{event_loop_test_init, register_clipboard_notifier};
+    use crate::data::keycode::KEYSYM2KEYCODE;
+    use crate::input::{
+        register_keyboard, register_pointer, unregister_keyboard, unregister_pointer, KeyboardOpts,
+        PointerOpts, INPUT_TEST_LOCK,
+    };
+    use crate::pixman::{create_pixman_image, unref_pixman_image};
+    use crate::vnc::auth_sasl::{
+        sasl_decode_data, sasl_encode_data, sasl_lib_users, SaslAuth, SaslStage, SASL_TEST_LOCK,
+    };
+    use crate::vnc::clipboard::{
+        EXT_CLIPBOARD_ACTION_CAPS, EXT_CLIPBOARD_ACTION_NOTIFY, EXT_CLIPBOARD_ACTION_PROVIDE,
+    };
+    use crate::vnc::raw_send_framebuffer_update;
+    use crate::vnc::websocket::{ws_encode_client_frame, WS_OPCODE_BINARY};
+    use libc::{c_char, c_int, c_uint, c_ulong, c_void};
+    use miniz_oxide::deflate::compress_to_vec_zlib;
+    use sasl2_sys::prelude::{
+        sasl_callback_t, sasl_client_init, sasl_client_new, sasl_client_start, sasl_client_step,
+        sasl_conn_t, sasl_dispose, sasl_secret_t, sasl_security_properties_t, sasl_setpass,
+        sasl_setprop, SASL_CB_AUTHNAME, SASL_CB_LIST_END, SASL_CB_PASS, SASL_CONTINUE, SASL_OK,
+        SASL_SEC_PROPS, SASL_SET_CREATE,
+    };
+    use std::{ffi::CString, net::TcpListener, ptr, thread};
+    use util::pixman::pixman_format_code_t;
+
+    fn create_client_io(server: &Arc<VncServer>) -> (Arc<Mutex<ClientIoHandler>>, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, addr) = listener.accept().unwrap();
+        let io_channel = Rc::new(RefCell::new(IoChannel::new(stream.try_clone().unwrap())));
+        let client = Arc::new(ClientState::new(addr.to_string()));
+        server
+            .client_handlers
+            .lock()
+            .unwrap()
+            .insert(addr.to_string(), client.clone());
+        let client_io = Arc::new(Mutex::new(ClientIoHandler::new(
+            stream,
+            io_channel,
+            client.clone(),
+            server.clone(),
+        )));
+        client.conn_state.lock().unwrap().client_io = Some(Arc::downgrade(&client_io));
+        (client_io, peer)
+    }
+
+    fn take_output(client: &Arc<ClientState>) -> Vec<u8> {
+        let mut locked_buffer = client.out_buffer.lock().unwrap();
+        let len = locked_buffer.len();
+        let mut buf = vec![0_u8; len];
+        locked_buffer.read_front(&mut buf, len);
+        locked_buffer.remove_front(len);
+        buf
+    }
+
+    /// Run the message handlers as the message is read from socket.
+    fn handle_msg(client_io: &mut ClientIoHandler, msg: Vec<u8>) -> Result<()> {
+        let client = client_io.client.clone();
+        client.in_buffer.lock().unwrap().append_limit(msg);
+        while client.in_buffer.lock().unwrap().len() >= client_io.expect {
+            let handler = client_io.msg_handler;
+            handler(client_io)?;
+        }
+        Ok(())
+    }
+
+    /// Feed the message to client, and return the output of handlers.
+    fn feed_msg(client_io: &mut ClientIoHandler, msg: Vec<u8>) -> Result<Vec<u8>> {
+        handle_msg(client_io, msg)?;
+        Ok(take_output(&client_io.client))
+    }
+
+    fn set_encodings_msg(encodings: &[i32]) -> Vec<u8> {
+        let mut msg = vec![ClientMsg::SetEncodings as u8, 0];
+        msg.append(&mut (encodings.len() as u16).to_be_bytes().to_vec());
+        for enc in encodings {
+            msg.append(&mut enc.to_be_bytes().to_vec());
+        }
+        msg
+    }
+
+    thread_local! {
+        /// Logs captured on the current thread, None if not capturing.
+        static CAPTURED_LOGS: RefCell<Option<Vec<String>>> = RefCell::new(None);
+    }
+
+    /// Logger which records the logs of the threads capturing them.
+    struct TestLogger;
+
+    impl log::Log for TestLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            CAPTURED_LOGS.with(|logs| logs.borrow().is_some())
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED_LOGS.with(|logs| {
+                if let Some(logs) = logs.borrow_mut().as_mut() {
+                    logs.push(format!("{}: {}", record.level(), record.args()));
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static TEST_LOGGER: TestLogger = TestLogger;
+
+    /// Run `f` and return the logs of it, the logs of other threads are not
+    /// captured and the log level is restored afterwards.
+    fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+        let _ = log::set_logger(&TEST_LOGGER);
+        let level = log::max_level();
+        log::set_max_level(log::LevelFilter::Debug);
+        CAPTURED_LOGS.with(|logs| *logs.borrow_mut() = Some(Vec::new()));
+        let ret = f();
+        let logs = CAPTURED_LOGS.with(|logs| logs.borrow_mut().take().unwrap());
+        log::set_max_level(level);
+        (ret, logs)
+    }
+
+    #[test]
+    fn test_sasl_mechname_log() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.saslconfig.mech_list = String::from("PLAIN,GSSAPI");
+        let client = locked_client_io.client.clone();
+
+        client
+            .in_buffer
+            .lock()
+            .unwrap()
+            .append_limit(b"X-MECH-42".to_vec());
+        locked_client_io.expect = 9;
+        // The error returned is not changed by the log.
+        let (result, records) = capture_logs(|| locked_client_io.get_sasl_mechname());
+        let err = result.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<VncError>(),
+            Some(VncError::AuthFailed(_, reason)) if reason == "Unsupported mechanism"
+        ));
+        let record = records
+            .iter()
+            .find(|record| record.contains("X-MECH-42"))
+            .unwrap();
+        assert!(record.starts_with("DEBUG"));
+        assert!(record.contains("PLAIN,GSSAPI"));
+    }
+
+    #[test]
+    fn test_fail_auth() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.saslconfig.mech_list = String::from("PLAIN,GSSAPI");
+        let client = locked_client_io.client.clone();
+
+        // Unsupported sasl mechanism, the reason is sent for RFB 3.8.
+        client.conn_state.lock().unwrap().version = VncVersion::new(3, 8);
+        client
+            .in_buffer
+            .lock()
+            .unwrap()
+            .append_limit(b"SCRAM".to_vec());
+        locked_client_io.expect = 5;
+        assert!(locked_client_io.get_sasl_mechname().is_err());
+        let reason = "Unsupported mechanism";
+        let mut expected = 1_u32.to_be_bytes().to_vec();
+        expected.append(&mut (reason.len() as u32).to_be_bytes().to_vec());
+        expected.append(&mut reason.as_bytes().to_vec());
+        assert_eq!(take_output(&client), expected);
+
+        // No reason for RFB 3.7.
+        client.conn_state.lock().unwrap().version = VncVersion::new(3, 7);
+        locked_client_io.fail_auth(reason);
+        assert_eq!(take_output(&client), 1_u32.to_be_bytes().to_vec());
+
+        // Reject in security type negotiation of RFB 3.8 and RFB 3.3.
+        let reason = "Unsupported auth method";
+        let mut reason_msg = (reason.len() as u32).to_be_bytes().to_vec();
+        reason_msg.append(&mut reason.as_bytes().to_vec());
+        client.conn_state.lock().unwrap().version = VncVersion::new(3, 8);
+        locked_client_io.fail_negotiation(reason);
+        assert_eq!(
+            take_output(&client),
+            [vec![0_u8], reason_msg.clone()].concat()
+        );
+        client.conn_state.lock().unwrap().version = VncVersion::new(3, 3);
+        locked_client_io.fail_negotiation(reason);
+        assert_eq!(
+            take_output(&client),
+            [0_u32.to_be_bytes().to_vec(), reason_msg].concat()
+        );
+    }
+
+    #[test]
+    fn test_handshake_timeout() {
+        let _guard = SASL_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let mut ctx = EventLoopContext::new();
+        let timeout = Duration::from_millis(10);
+
+        // The client stalls in sasl authentication.
+        let (client_io, _peer) = create_client_io(&server);
+        let client = client_io.lock().unwrap().client.clone();
+        client_io.lock().unwrap().sasl_server_init().unwrap();
+        client_io.lock().unwrap().saslconfig.sasl_stage = SaslStage::SaslServerStep;
+        ClientIoHandler::arm_handshake_timer(&client_io, &mut ctx, timeout);
+        ctx.run_timers();
+        assert!(!client.conn_state.lock().unwrap().dis_conn);
+
+        thread::sleep(timeout * 2);
+        ctx.run_timers();
+        assert!(client.conn_state.lock().unwrap().dis_conn);
+        assert!(client_io.lock().unwrap().handshake_timer.is_none());
+        let locked_client_io = client_io.lock().unwrap();
+        assert!(!locked_client_io.sasl_started);
+        assert!(locked_client_io.saslconfig.sasl_conn.is_null());
+        assert_eq!(
+            locked_client_io.saslconfig.sasl_stage,
+            SaslStage::SaslServerStart
+        );
+        drop(locked_client_io);
+        assert_eq!(sasl_lib_users(), 0);
+        // The normal disconnect path is triggered.
+        assert_eq!(read_fd(client.disconn_evt.lock().unwrap().as_raw_fd()), 1);
+
+        // The client finishes the handshake in time.
+        let (client_io, _peer) = create_client_io(&server);
+        let client = client_io.lock().unwrap().client.clone();
+        ClientIoHandler::arm_handshake_timer(&client_io, &mut ctx, timeout);
+        assert!(client_io.lock().unwrap().handshake_timer.is_some());
+        client_io.lock().unwrap().cancel_handshake_timer(&mut ctx);
+        assert!(client_io.lock().unwrap().handshake_timer.is_none());
+
+        thread::sleep(timeout * 2);
+        ctx.run_timers();
+        assert!(!client.conn_state.lock().unwrap().dis_conn);
+    }
+
+    #[test]
+    fn test_auth_timeout() {
+        let timeout = Duration::from_millis(30);
+        let mut server = VncServer::new(ptr::null_mut(), HashMap::new(), None);
+        server.auth_timeout = timeout;
+        let server = Arc::new(server);
+        let mut ctx = EventLoopContext::new();
+
+        // The client stalls after the version is negotiated.
+        let (client_io, _peer) = create_client_io(&server);
+        let client = client_io.lock().unwrap().client.clone();
+        client_io.lock().unwrap().arm_auth_timer(&mut ctx);
+        assert!(client_io.lock().unwrap().auth_timer.is_some());
+        ctx.run_timers();
+        assert!(!client.conn_state.lock().unwrap().dis_conn);
+
+        thread::sleep(timeout * 2);
+        ctx.run_timers();
+        assert!(client.conn_state.lock().unwrap().dis_conn);
+        assert!(client_io.lock().unwrap().auth_timer.is_none());
+        assert_eq!(read_fd(client.disconn_evt.lock().unwrap().as_raw_fd()), 1);
+
+        // The deadline is renewed when the client moves to the next step.
+        let (client_io, _peer) = create_client_io(&server);
+        let client = client_io.lock().unwrap().client.clone();
+        client_io.lock().unwrap().arm_auth_timer(&mut ctx);
+        thread::sleep(timeout * 2 / 3);
+        client_io.lock().unwrap().arm_auth_timer(&mut ctx);
+        thread::sleep(timeout * 2 / 3);
+        ctx.run_timers();
+        assert!(!client.conn_state.lock().unwrap().dis_conn);
+        thread::sleep(timeout);
+        ctx.run_timers();
+        assert!(client.conn_state.lock().unwrap().dis_conn);
+
+        // The handshake is finished in time.
+        let (client_io, _peer) = create_client_io(&server);
+        let client = client_io.lock().unwrap().client.clone();
+        client_io.lock().unwrap().arm_auth_timer(&mut ctx);
+        client_io.lock().unwrap().cancel_auth_timer(&mut ctx);
+        assert!(client_io.lock().unwrap().auth_timer.is_none());
+        thread::sleep(timeout * 2);
+        ctx.run_timers();
+        assert!(!client.conn_state.lock().unwrap().dis_conn);
+    }
+
+    #[test]
+    fn test_sasl_teardown() {
+        // Teardown cancels the timers in main loop.
+        event_loop_test_init();
+        let _guard = SASL_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let start_sasl = |client_io: &Arc<Mutex<ClientIoHandler>>| {
+            client_io.lock().unwrap().sasl_server_init().unwrap();
+            assert!(!client_io.lock().unwrap().saslconfig.sasl_conn.is_null());
+        };
+
+        // Disconnect after the authentication is finished.
+        let (client_io, _peer) = create_client_io(&server);
+        start_sasl(&client_io);
+        assert_eq!(sasl_lib_users(), 1);
+        client_io.lock().unwrap().teardown();
+        assert_eq!(sasl_lib_users(), 0);
+        assert!(!client_io.lock().unwrap().sasl_started);
+        assert!(client_io.lock().unwrap().saslconfig.sasl_conn.is_null());
+        // Nothing to release for the second time.
+        client_io.lock().unwrap().teardown();
+        assert_eq!(sasl_lib_users(), 0);
+
+        // Disconnect in the handshake.
+        let (client_io, _peer) = create_client_io(&server);
+        start_sasl(&client_io);
+        client_io.lock().unwrap().handshake_timeout();
+        assert_eq!(sasl_lib_users(), 0);
+        assert!(client_io.lock().unwrap().saslconfig.sasl_conn.is_null());
+
+        // Disconnect after the authentication is failed, the connection
+        // is disposed only once.
+        let (client_io, _peer) = create_client_io(&server);
+        start_sasl(&client_io);
+        client_io.lock().unwrap().saslconfig.dispose_conn();
+        assert!(client_io.lock().unwrap().saslconfig.sasl_conn.is_null());
+        client_io.lock().unwrap().teardown();
+        assert_eq!(sasl_lib_users(), 0);
+
+        // Each client has its own connection, and the library is kept
+        // until the last client is gone.
+        let (client_io1, _peer1) = create_client_io(&server);
+        let (client_io2, _peer2) = create_client_io(&server);
+        start_sasl(&client_io1);
+        start_sasl(&client_io2);
+        assert_ne!(
+            client_io1.lock().unwrap().saslconfig.sasl_conn,
+            client_io2.lock().unwrap().saslconfig.sasl_conn
+        );
+        assert_eq!(sasl_lib_users(), 2);
+        client_io1.lock().unwrap().teardown();
+        assert_eq!(sasl_lib_users(), 1);
+        assert!(!client_io2.lock().unwrap().saslconfig.sasl_conn.is_null());
+        client_io2.lock().unwrap().teardown();
+        assert_eq!(sasl_lib_users(), 0);
+    }
+    #[test]
+    fn test_set_encodings() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        let client = locked_client_io.client.clone();
+        let mut set_encodings = |encodings: &[i32]| {
+            let mut msg = vec![ClientMsg::SetEncodings as u8, 0];
+            msg.append(&mut (encodings.len() as u16).to_be_bytes().to_vec());
+            for enc in encodings {
+                msg.append(&mut enc.to_be_bytes().to_vec());
+            }
+            locked_client_io.expect = msg.len();
+            client.in_buffer.lock().unwrap().append_limit(msg);
+            locked_client_io.set_encodings().unwrap();
+            client.client_dpm.lock().unwrap().enc
+        };
+
+        // The most preferred encoding is selected.
+        assert_eq!(
+            set_encodings(&[ENCODING_TIGHT, ENCODING_ZLIB, ENCODING_RAW]),
+            ENCODING_TIGHT
+        );
+        assert_eq!(
+            set_encodings(&[ENCODING_HEXTILE, ENCODING_TIGHT]),
+            ENCODING_HEXTILE
+        );
+        assert_eq!(
+            set_encodings(&[ENCODING_ZLIB, ENCODING_ZRLE, ENCODING_TIGHT]),
+            ENCODING_ZLIB
+        );
+        assert_eq!(
+            set_encodings(&[ENCODING_ZRLE, ENCODING_ZYWRLE, ENCODING_RAW]),
+            ENCODING_ZRLE
+        );
+        // The unsupported encodings are skipped.
+        assert_eq!(
+            set_encodings(&[ENCODING_ZYWRLE, ENCODING_TIGHT]),
+            ENCODING_TIGHT
+        );
+        assert_eq!(set_encodings(&[ENCODING_DESKTOPRESIZE]), ENCODING_RAW);
+        // CopyRect is a feature, but not the encoding of dirty area.
+        assert_eq!(
+            set_encodings(&[ENCODING_COPYRECT, ENCODING_HEXTILE]),
+            ENCODING_HEXTILE
+        );
+        assert!(client
+            .client_dpm
+            .lock()
+            .unwrap()
+            .has_feature(VncFeatures::VncFeatureCopyRect));
+    }
+
+    #[test]
+    fn test_set_encodings_levels() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        let client = locked_client_io.client.clone();
+        let mut set_encodings = |encodings: &[i32]| {
+            let mut msg = vec![ClientMsg::SetEncodings as u8, 0];
+            msg.append(&mut (encodings.len() as u16).to_be_bytes().to_vec());
+            for enc in encodings {
+                msg.append(&mut enc.to_be_bytes().to_vec());
+            }
+            locked_client_io.expect = msg.len();
+            client.in_buffer.lock().unwrap().append_limit(msg);
+            locked_client_io.set_encodings().unwrap();
+            let locked_dpm = client.client_dpm.lock().unwrap();
+            (locked_dpm.enc, locked_dpm.compression)
+        };
+
+        // The most preferred levels are selected.
+        assert_eq!(
+            set_encodings(&[
+                ENCODING_TIGHT,
+                ENCODING_COMPRESSLEVEL0 + 2,
+                ENCODING_COMPRESSLEVEL0 + 5,
+            ]),
+            (ENCODING_TIGHT, Some(2))
+        );
+        assert_eq!(
+            set_encodings(&[ENCODING_ZLIB, ENCODING_COMPRESSLEVEL9]),
+            (ENCODING_ZLIB, Some(9))
+        );
+        // The levels are reset by the new message.
+        assert_eq!(set_encodings(&[ENCODING_ZLIB]), (ENCODING_ZLIB, None));
+        // Out of the range of levels.
+        assert_eq!(
+            set_encodings(&[ENCODING_COMPRESSLEVEL0 - 1, ENCODING_COMPRESSLEVEL9 + 1]),
+            (ENCODING_RAW, None)
+        );
+    }
+
+    #[test]
+    fn test_desktop_resize() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let client = client_io.lock().unwrap().client.clone();
+        let header = vec![ServerMsg::FramebufferUpdate as u8, 0, 0, 1];
+        let set_client = |width: i32, feature: VncFeatures| {
+            let mut locked_dpm = client.client_dpm.lock().unwrap();
+            locked_dpm.client_width = width;
+            locked_dpm.feature = 1 << feature as usize;
+        };
+
+        // DesktopSize.
+        set_client(640, VncFeatures::VncFeatureResize);
+        let mut buf = Vec::new();
+        desktop_resize(&client, &server, &mut buf).unwrap();
+        let mut expect = header.clone();
+        framebuffer_update(0, 0, 0, 0, ENCODING_DESKTOPRESIZE, &mut expect);
+        assert_eq!(buf, expect);
+        // The size is not changed.
+        let mut buf = Vec::new();
+        desktop_resize(&client, &server, &mut buf).unwrap();
+        assert!(buf.is_empty());
+
+        // ExtendedDesktopSize with the screen list.
+        set_client(640, VncFeatures::VncFeatureResizeExt);
+        let mut buf = Vec::new();
+        desktop_resize(&client, &server, &mut buf).unwrap();
+        let mut expect = header;
+        framebuffer_update(0, 0, 0, 0, ENCODING_DESKTOP_RESIZE_EXT, &mut expect);
+        expect.append(&mut vec![1, 0, 0, 0]);
+        expect.append(&mut vec![0; 16]);
+        assert_eq!(buf, expect);
+
+        // The client does not support resizing.
+        set_client(640, VncFeatures::VncFeatureHextile);
+        let mut buf = Vec::new();
+        desktop_resize(&client, &server, &mut buf).unwrap();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_cursor_mask() {
+        // Create cursor with the alpha of each pixel.
+        let create_cursor = |width: u32, height: u32, alpha: &[u8]| {
+            let mut cursor = DisplayMouse::new(width, height, 0, 0);
+            for (i, a) in alpha.iter().enumerate() {
+                cursor.data[i * bytes_per_pixel() + bytes_per_pixel() - 1] = *a;
+            }
+            cursor
+        };
+
+        let cursor = create_cursor(3, 3, &[0xff, 0xff, 0xff, 0, 0xff, 0x80, 0, 0, 0]);
+        assert_eq!(cursor_mask(&cursor), vec![0xe0, 0x40, 0x00]);
+
+        // Each row is padded to a whole byte.
+        let mut alpha = vec![0_u8; 18];
+        for i in [0, 7, 8, 10, 17] {
+            alpha[i] = 0xff;
+        }
+        let cursor = create_cursor(9, 2, &alpha);
+        assert_eq!(cursor_mask(&cursor), vec![0x81, 0x80, 0x40, 0x80]);
+
+        let cursor = create_cursor(1, 1, &[0xff]);
+        assert_eq!(cursor_mask(&cursor), vec![0x80]);
+        let cursor = create_cursor(0, 0, &[]);
+        assert!(cursor_mask(&cursor).is_empty());
+    }
+
+    #[test]
+    fn test_display_hidden_cursor() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let client = client_io.lock().unwrap().client.clone();
+        let cursor = DisplayMouse::new(0, 0, 0, 0);
+        let mut locked_cursor = server.vnc_cursor.lock().unwrap();
+        locked_cursor.mask = Some(cursor_mask(&cursor));
+        locked_cursor.cursor = Some(cursor);
+        drop(locked_cursor);
+        let header = vec![ServerMsg::FramebufferUpdate as u8, 0, 0, 1];
+
+        client.client_dpm.lock().unwrap().feature = 1 << VncFeatures::VncFeatureRichCursor as usize;
+        let mut buf = Vec::new();
+        display_cursor_define(&client, &server, &mut buf);
+        let mut expect = header.clone();
+        framebuffer_update(0, 0, 0, 0, ENCODING_RICH_CURSOR, &mut expect);
+        assert_eq!(buf, expect);
+
+        client.client_dpm.lock().unwrap().feature =
+            1 << VncFeatures::VncFeatureAlphaCursor as usize;
+        let mut buf = Vec::new();
+        display_cursor_define(&client, &server, &mut buf);
+        let mut expect = header;
+        framebuffer_update(0, 0, 0, 0, ENCODING_ALPHA_CURSOR, &mut expect);
+        expect.append(&mut (ENCODING_RAW as u32).to_be_bytes().to_vec());
+        assert_eq!(buf, expect);
+
+        // The client does not support cursor encodings.
+        client.client_dpm.lock().unwrap().feature = 0;
+        let mut buf = Vec::new();
+        display_cursor_define(&client, &server, &mut buf);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_led_state() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let client = client_io.lock().unwrap().client.clone();
+        let led_rect = |state: u8| {
+            let mut buf = vec![ServerMsg::FramebufferUpdate as u8, 0, 0, 1];
+            buf.append(&mut vec![0, 0, 0, 0, 0, 1, 0, 1, 0xff, 0xff, 0xfe, 0xfb]);
+            buf.push(state);
+            buf
+        };
+
+        // The client does not support led state.
+        let mut buf = Vec::new();
+        vnc_update_led_state(&client, CAPS_LOCK_LED, &mut buf);
+        assert!(buf.is_empty());
+
+        client.client_dpm.lock().unwrap().feature = 1 << VncFeatures::VncFeatureLedState as usize;
+        // Initial state without any lock.
+        let mut buf = Vec::new();
+        vnc_update_led_state(&client, 0, &mut buf);
+        assert_eq!(buf, led_rect(0));
+        let mut buf = Vec::new();
+        vnc_update_led_state(&client, CAPS_LOCK_LED, &mut buf);
+        assert_eq!(buf, led_rect(VNC_CAPS_LOCK));
+        // The state is not changed.
+        let mut buf = Vec::new();
+        vnc_update_led_state(&client, CAPS_LOCK_LED, &mut buf);
+        assert!(buf.is_empty());
+        let mut buf = Vec::new();
+        vnc_update_led_state(&client, NUM_LOCK_LED | SCROLL_LOCK_LED, &mut buf);
+        assert_eq!(buf, led_rect(VNC_NUM_LOCK | VNC_SCROLL_LOCK));
+        let mut buf = Vec::new();
+        vnc_update_led_state(
+            &client,
+            NUM_LOCK_LED | CAPS_LOCK_LED | SCROLL_LOCK_LED,
+            &mut buf,
+        );
+        assert_eq!(buf, led_rect(0x7));
+    }
+
+    #[test]
+    fn test_pointer_type_change() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let client = client_io.lock().unwrap().client.clone();
+        let mut locked_dpm = client.client_dpm.lock().unwrap();
+        locked_dpm.client_width = 640;
+        locked_dpm.client_height = 480;
+        drop(locked_dpm);
+        let type_rect = |absolute: u8| {
+            let mut buf = vec![ServerMsg::FramebufferUpdate as u8, 0, 0, 1];
+            buf.append(&mut vec![
+                0, absolute, 0, 0, 2, 128, 1, 224, 0xff, 0xff, 0xfe, 0xff,
+            ]);
+            buf
+        };
+
+        // The client does not support pointer type change.
+        let mut buf = Vec::new();
+        vnc_update_pointer_type(&client, true, &mut buf);
+        assert!(buf.is_empty());
+
+        client.client_dpm.lock().unwrap().feature =
+            1 << VncFeatures::VncFeaturePointerTypeChange as usize;
+        let mut buf = Vec::new();
+        vnc_update_pointer_type(&client, true, &mut buf);
+        assert_eq!(buf, type_rect(1));
+        // The mode is not changed.
+        let mut buf = Vec::new();
+        vnc_update_pointer_type(&client, true, &mut buf);
+        assert!(buf.is_empty());
+        let mut buf = Vec::new();
+        vnc_update_pointer_type(&client, false, &mut buf);
+        assert_eq!(buf, type_rect(0));
+        assert_eq!(
+            client.client_dpm.lock().unwrap().pointer_absolute,
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_pointer_motion() {
+        // The first position has no motion.
+        let (motion, pos) = pointer_motion(None, (10, 10), 640, 480);
+        assert_eq!((motion, pos), ((0, 0), (10, 10)));
+        // The position out of the screen is clamped to the edge.
+        let (motion, pos) = pointer_motion(Some(pos), (5000, 20), 640, 480);
+        assert_eq!((motion, pos), ((629, 10), (639, 20)));
+        let (motion, pos) = pointer_motion(Some(pos), (6000, 20), 640, 480);
+        assert_eq!((motion, pos), ((0, 0), (639, 20)));
+        let (motion, pos) = pointer_motion(Some(pos), (600, 0), 640, 480);
+        assert_eq!((motion, pos), ((-39, -20), (600, 0)));
+        let (motion, _) = pointer_motion(Some(pos), (600, 1000), 640, 480);
+        assert_eq!(motion, (0, 479));
+    }
+
+    #[test]
+    fn test_set_desktop_size() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        let client = locked_client_io.client.clone();
+        let mut locked_dpm = client.client_dpm.lock().unwrap();
+        locked_dpm.client_width = 640;
+        locked_dpm.client_height = 480;
+        locked_dpm.feature = 1 << VncFeatures::VncFeatureResizeExt as usize;
+        drop(locked_dpm);
+        let reply = |reason: u16, status: u16| {
+            let mut buf = vec![ServerMsg::FramebufferUpdate as u8, 0, 0, 1];
+            desktop_resize_ext(reason, status, 640, 480, &mut buf);
+            buf
+        };
+        assert_eq!(
+            reply(0, 0)[4..16],
+            [0, 0, 0, 0, 2, 128, 1, 224, 255, 255, 254, 204]
+        );
+        assert_eq!(
+            reply(0, 0)[16..],
+            [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 128, 1, 224, 0, 0, 0, 0]
+        );
+
+        // The client is told that the desktop size can be changed.
+        let mut update_request = |incremental: u8| {
+            let msg = vec![3, incremental, 0, 0, 0, 0, 2, 128, 1, 224];
+            locked_client_io.expect = msg.len();
+            client.in_buffer.lock().unwrap().append_limit(msg);
+            locked_client_io.update_frame_buff().unwrap();
+            take_output(&client)
+        };
+        assert_eq!(
+            update_request(0),
+            reply(DESKTOP_RESIZE_REASON_SERVER, DESKTOP_RESIZE_STATUS_OK)
+        );
+        assert!(update_request(1).is_empty());
+
+        let mut set_desktop_size = |width: u16, height: u16, num_screens: u8| {
+            let mut msg = vec![ClientMsg::SetDesktopSize as u8, 0];
+            msg.append(&mut width.to_be_bytes().to_vec());
+            msg.append(&mut height.to_be_bytes().to_vec());
+            msg.append(&mut vec![num_screens, 0]);
+            for id in 0..num_screens as u32 {
+                msg.append(&mut id.to_be_bytes().to_vec());
+                msg.append(&mut vec![0; 4]);
+                msg.append(&mut width.to_be_bytes().to_vec());
+                msg.append(&mut height.to_be_bytes().to_vec());
+                msg.append(&mut vec![0; 4]);
+            }
+            client.in_buffer.lock().unwrap().append_limit(msg);
+            locked_client_io.expect = 1;
+            while locked_client_io.expect != 1 || client.in_buffer.lock().unwrap().len() != 0 {
+                locked_client_io.set_desktop_size();
+            }
+            take_output(&client)
+        };
+
+        // There is no graphic hardware which supports resizing.
+        assert_eq!(
+            set_desktop_size(800, 600, 1),
+            reply(
+                DESKTOP_RESIZE_REASON_CLIENT,
+                DESKTOP_RESIZE_STATUS_PROHIBITED
+            )
+        );
+        // Invalid screen layout.
+        let invalid = reply(
+            DESKTOP_RESIZE_REASON_CLIENT,
+            DESKTOP_RESIZE_STATUS_INVALID_LAYOUT,
+        );
+        assert_eq!(set_desktop_size(800, 600, 0), invalid);
+        assert_eq!(set_desktop_size(0, 600, 2), invalid);
+        assert_eq!(set_desktop_size(MAX_WINDOW_WIDTH + 1, 600, 1), invalid);
+        assert_eq!(set_desktop_size(800, MAX_WINDOW_HEIGHT + 1, 1), invalid);
+        // The desktop size is not changed by client.
+        assert_eq!(client.client_dpm.lock().unwrap().client_width, 640);
+    }
+
+    #[test]
+    fn test_fence() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.expect = 1;
+        locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+        let fence = |flags: u32, payload: &[u8]| {
+            let mut buf = Vec::new();
+            fence_message(flags, payload, &mut buf);
+            buf
+        };
+        assert_eq!(
+            fence(FENCE_REQUEST, &[1]),
+            [248, 0, 0, 0, 128, 0, 0, 0, 1, 1]
+        );
+
+        // Fence is advertised when it is enabled by client for the first time.
+        let msg = set_encodings_msg(&[ENCODING_RAW, ENCODING_FENCE]);
+        assert_eq!(
+            feed_msg(&mut locked_client_io, msg.clone()).unwrap(),
+            fence(FENCE_REQUEST | FENCE_SUPPORTED_FLAGS, &[])
+        );
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+
+        // The request is echoed with the supported flags.
+        let msg = fence(FENCE_REQUEST | FENCE_BLOCK_BEFORE | 1 << 8, &[1, 2, 3]);
+        assert_eq!(
+            feed_msg(&mut locked_client_io, msg).unwrap(),
+            fence(FENCE_BLOCK_BEFORE, &[1, 2, 3])
+        );
+        // The response of server fence is not echoed.
+        let msg = fence(FENCE_BLOCK_AFTER, &[]);
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+
+        // The synchronous fence is replied after the next message.
+        let msg = fence(FENCE_REQUEST | FENCE_SYNC_NEXT | FENCE_BLOCK_AFTER, &[7]);
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+        assert_eq!(locked_client_io.expect, 1);
+        let msg = vec![3, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            feed_msg(&mut locked_client_io, msg).unwrap(),
+            fence(FENCE_SYNC_NEXT | FENCE_BLOCK_AFTER, &[7])
+        );
+
+        // The payload is split across reads.
+        let msg = fence(FENCE_REQUEST, &[0xaa; FENCE_MAX_PAYLOAD]);
+        assert!(feed_msg(&mut locked_client_io, msg[..20].to_vec())
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            feed_msg(&mut locked_client_io, msg[20..].to_vec()).unwrap(),
+            fence(0, &[0xaa; FENCE_MAX_PAYLOAD])
+        );
+
+        // The payload is too long.
+        let msg = fence(FENCE_REQUEST, &[0; FENCE_MAX_PAYLOAD + 1]);
+        assert!(feed_msg(&mut locked_client_io, msg).is_err());
+    }
+
+    #[test]
+    fn test_continuous_updates() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.expect = 1;
+        locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+        let client = locked_client_io.client.clone();
+        let mut locked_dpm = client.client_dpm.lock().unwrap();
+        locked_dpm.client_width = 640;
+        locked_dpm.client_height = 480;
+        drop(locked_dpm);
+        let enable_msg = |enable: u8, x: u16, y: u16, w: u16, h: u16| {
+            let mut msg = vec![ClientMsg::EnableContinuousUpdates as u8, enable];
+            for v in [x, y, w, h] {
+                msg.append(&mut v.to_be_bytes().to_vec());
+            }
+            msg
+        };
+        let job_rects = |idx: usize| server.rect_jobs.lock().unwrap()[idx].rects.clone();
+        let area = Rectangle::new(0, 0, 32, 16);
+
+        // Continuous updates is not negotiated.
+        let msg = enable_msg(1, 0, 0, 32, 16);
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+        assert!(client
+            .conn_state
+            .lock()
+            .unwrap()
+            .continuous_updates
+            .is_none());
+
+        // Continuous updates is advertised.
+        let msg = set_encodings_msg(&[ENCODING_RAW, ENCODING_CONTINUOUS_UPDATES]);
+        assert_eq!(
+            feed_msg(&mut locked_client_io, msg).unwrap(),
+            [ServerMsg::EndOfContinuousUpdates as u8]
+        );
+
+        // The whole area is sent after it is enabled.
+        let msg = enable_msg(1, 0, 0, 32, 16);
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+        assert_eq!(
+            client.conn_state.lock().unwrap().continuous_updates,
+            Some(area.clone())
+        );
+        get_rects(&client, &server, 0).unwrap();
+        assert_eq!(job_rects(0), [area.clone()]);
+        get_rects(&client, &server, 0).unwrap();
+        assert_eq!(server.rect_jobs.lock().unwrap().len(), 1);
+
+        // The changed area is pushed without update request, and the dirty
+        // area outside it is kept.
+        let mut locked_dirty = client.dirty_bitmap.lock().unwrap();
+        set_area_dirty(&mut locked_dirty, 0, 0, 64, 32, 640, 480).unwrap();
+        drop(locked_dirty);
+        get_rects(&client, &server, 1).unwrap();
+        assert_eq!(job_rects(1), [area.clone()]);
+        let locked_dirty = client.dirty_bitmap.lock().unwrap();
+        let bpl = locked_dirty.vol() / MAX_WINDOW_HEIGHT as usize;
+        assert!(!locked_dirty.contain(0).unwrap());
+        assert!(locked_dirty.contain(2).unwrap());
+        assert!(locked_dirty.contain(20 * bpl).unwrap());
+        drop(locked_dirty);
+        get_rects(&client, &server, 1).unwrap();
+        assert_eq!(server.rect_jobs.lock().unwrap().len(), 2);
+
+        // The dirty area is buffered after continuous updates is disabled.
+        let msg = enable_msg(0, 0, 0, 0, 0);
+        assert_eq!(
+            feed_msg(&mut locked_client_io, msg).unwrap(),
+            [ServerMsg::EndOfContinuousUpdates as u8]
+        );
+        assert!(client
+            .conn_state
+            .lock()
+            .unwrap()
+            .continuous_updates
+            .is_none());
+        get_rects(&client, &server, 1).unwrap();
+        assert_eq!(server.rect_jobs.lock().unwrap().len(), 2);
+        let msg = vec![3, 1, 0, 0, 0, 0, 2, 128, 1, 224];
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+        get_rects(&client, &server, 0).unwrap();
+        let rects = job_rects(2);
+        assert!(!rects.is_empty());
+        assert!(rects.iter().all(|rect| rect.intersect(&area).is_none()));
+    }
+
+    #[test]
+    fn test_update_pacing() {
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        let mut pacing = UpdatePacing::new(10);
+        assert!(pacing.is_ready(now));
+        assert_eq!(pacing.rate(now), 0);
+
+        pacing.record(now);
+        assert!(!pacing.is_ready(now + ms(50)));
+        assert!(pacing.is_ready(now + ms(100)));
+        for i in 1..10 {
+            pacing.record(now + ms(i * 100));
+        }
+        assert_eq!(pacing.rate(now + ms(950)), 10);
+        assert_eq!(pacing.rate(now + ms(1050)), 9);
+        assert_eq!(pacing.rate(now + ms(3000)), 0);
+
+        // No limit of fps.
+        let mut pacing = UpdatePacing::default();
+        pacing.record(now);
+        assert!(pacing.is_ready(now));
+    }
+
+    #[test]
+    fn test_slow_client() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let client = client_io.lock().unwrap().client.clone();
+        let mut locked_dpm = client.client_dpm.lock().unwrap();
+        locked_dpm.client_width = 640;
+        locked_dpm.client_height = 480;
+        locked_dpm.pf.pixel_bytes = 4;
+        drop(locked_dpm);
+        vnc_update_output_throttle(&client);
+        let frame = vec![0_u8; 640 * 480 * 4];
+        let jobs = || server.rect_jobs.lock().unwrap().len();
+        let request = |y: i32| {
+            client.conn_state.lock().unwrap().update_state = UpdateState::Incremental;
+            let mut locked_dirty = client.dirty_bitmap.lock().unwrap();
+            set_area_dirty(&mut locked_dirty, 0, y, 640, 16, 640, 480).unwrap();
+            drop(locked_dirty);
+            get_rects(&client, &server, 1).unwrap();
+        };
+
+        // The output of the first update is not read by client.
+        request(0);
+        assert_eq!(jobs(), 1);
+        vnc_write(&client, frame.clone());
+
+        // Update generation pauses instead of growing the output queue.
+        for i in 1..10 {
+            request(i * 16);
+            assert_eq!(jobs(), 1);
+        }
+        assert_eq!(client.out_buffer.lock().unwrap().len(), frame.len());
+        assert!(!client.conn_state.lock().unwrap().dis_conn);
+
+        // The client catches up, and the area changed in the meantime is
+        // merged into the next update.
+        take_output(&client);
+        get_rects(&client, &server, 0).unwrap();
+        assert_eq!(jobs(), 2);
+        assert_eq!(
+            server.rect_jobs.lock().unwrap()[1].rects,
+            [Rectangle::new(0, 16, 640, 144)]
+        );
+
+        // The updates faster than max fps are deferred.
+        client.conn_state.lock().unwrap().pacing = UpdatePacing::new(1);
+        request(0);
+        assert_eq!(jobs(), 3);
+        request(16);
+        assert_eq!(jobs(), 3);
+        let mut locked_state = client.conn_state.lock().unwrap();
+        assert_eq!(locked_state.pacing.rate(Instant::now()), 1);
+    }
+
+    /// Io channel of the client which never receives the output.
+    #[derive(Default)]
+    struct StalledChannel {
+        write_count: usize,
+    }
+
+    impl IoOperations for StalledChannel {
+        fn channel_write(&mut self, _buf: &[u8]) -> Result<usize> {
+            self.write_count += 1;
+            Ok(0)
+        }
+
+        fn channel_read(&mut self, _buf: &mut Vec<u8>) -> Result<usize> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let channel = Rc::new(RefCell::new(StalledChannel::default()));
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.io_channel = channel.clone();
+        let client = locked_client_io.client.clone();
+
+        // The limit depends on the size of framebuffer by default.
+        vnc_update_output_throttle(&client);
+        let limit = client.out_buffer.lock().unwrap().limit();
+        assert_eq!(limit, Some(MIN_OUTPUT_LIMIT as usize));
+
+        let output_limit = 64 * 1024;
+        let chunk = 4096;
+        client.conn_state.lock().unwrap().output_limit = output_limit;
+        vnc_update_output_throttle(&client);
+        assert_eq!(
+            client.out_buffer.lock().unwrap().limit(),
+            Some(output_limit)
+        );
+        for i in 1..=output_limit / chunk {
+            vnc_write(&client, vec![0_u8; chunk]);
+            locked_client_io.client_handle_write();
+            assert_eq!(client.out_buffer.lock().unwrap().len(), i * chunk);
+            assert!(!client.conn_state.lock().unwrap().dis_conn);
+            // Updates are paused over the soft limit, but the messages of
+            // negotiation are still queued.
+            let soft_limit = output_limit / OUTPUT_THROTTLE_SCALE as usize;
+            assert_eq!(is_output_congested(&client), i * chunk >= soft_limit);
+        }
+        assert_eq!(channel.borrow().write_count, output_limit / chunk);
+
+        // The client is disconnected once the hard limit is exceeded.
+        vnc_write(&client, vec![0_u8; 1]);
+        assert_eq!(client.out_buffer.lock().unwrap().len(), output_limit);
+        assert!(client.conn_state.lock().unwrap().dis_conn);
+        assert_eq!(read_fd(client.disconn_evt.lock().unwrap().as_raw_fd()), 1);
+
+        // Nothing is queued after disconnection.
+        vnc_write(&client, vec![0_u8; 1]);
+        assert_eq!(client.out_buffer.lock().unwrap().len(), output_limit);
+    }
+
+    /// Io channel of the client which records the output and feeds the input.
+    #[derive(Default)]
+    struct RecordChannel {
+        /// Max bytes accepted by each write, 0 means no limit.
+        write_limit: usize,
+        output: Vec<u8>,
+        input: Vec<u8>,
+    }
+
+    impl IoOperations for RecordChannel {
+        fn channel_write(&mut self, buf: &[u8]) -> Result<usize> {
+            let len = match self.write_limit {
+                0 => buf.len(),
+                limit => cmp::min(buf.len(), limit),
+            };
+            self.output.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn channel_read(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+            let len = self.input.len();
+            buf.append(&mut self.input);
+            Ok(len)
+        }
+    }
+
+    unsafe extern "C" fn sasl_test_authname(
+        _context: *mut c_void,
+        _id: c_int,
+        result: *mut *const c_char,
+        len: *mut c_uint,
+    ) -> c_int {
+        *result = b"alice\0".as_ptr() as *const c_char;
+        if !len.is_null() {
+            *len = 5;
+        }
+        SASL_OK
+    }
+
+    /// Password of the sasl client in tests, the layout is `sasl_secret_t`.
+    #[repr(C)]
+    struct SaslTestSecret {
+        len: c_ulong,
+        data: [u8; 7],
+    }
+
+    static SASL_TEST_SECRET: SaslTestSecret = SaslTestSecret {
+        len: 6,
+        data: *b"secret\0",
+    };
+
+    unsafe extern "C" fn sasl_test_pass(
+        _conn: *mut sasl_conn_t,
+        _context: *mut c_void,
+        _id: c_int,
+        secret: *mut *mut sasl_secret_t,
+    ) -> c_int {
+        *secret = &SASL_TEST_SECRET as *const SaslTestSecret as *mut sasl_secret_t;
+        SASL_OK
+    }
+
+    /// Sasl client which authenticates by DIGEST-MD5 as `alice`.
+    struct SaslTestClient {
+        conn: *mut sasl_conn_t,
+        _callbacks: Box<[sasl_callback_t; 3]>,
+    }
+
+    impl SaslTestClient {
+        fn new() -> Self {
+            // SAFETY: The callbacks match the signature of their ids.
+            let callbacks = Box::new(unsafe {
+                [
+                    sasl_callback_t {
+                        id: SASL_CB_AUTHNAME,
+                        proc_: Some(std::mem::transmute(sasl_test_authname as *const ())),
+                        context: ptr::null_mut(),
+                    },
+                    sasl_callback_t {
+                        id: SASL_CB_PASS,
+                        proc_: Some(std::mem::transmute(sasl_test_pass as *const ())),
+                        context: ptr::null_mut(),
+                    },
+                    sasl_callback_t {
+                        id: SASL_CB_LIST_END,
+                        proc_: None,
+                        context: ptr::null_mut(),
+                    },
+                ]
+            });
+            let service = CString::new("vnc").unwrap();
+            let host = CString::new("localhost").unwrap();
+            // The security layer of DIGEST-MD5 requires the addresses.
+            let local_addr = CString::new("127.0.0.1;40000").unwrap();
+            let remote_addr = CString::new("127.0.0.1;5900").unwrap();
+            let mut conn = ptr::null_mut();
+            // SAFETY: All the parameters are valid, the callbacks are kept
+            // together with the connection.
+            unsafe {
+                assert_eq!(sasl_client_init(ptr::null()), SASL_OK);
+                assert_eq!(
+                    sasl_client_new(
+                        service.as_ptr(),
+                        host.as_ptr(),
+                        local_addr.as_ptr(),
+                        remote_addr.as_ptr(),
+                        callbacks.as_ptr(),
+                        0,
+                        &mut conn,
+                    ),
+                    SASL_OK
+                );
+                let props = sasl_security_properties_t {
+                    min_ssf: 0,
+                    max_ssf: 256,
+                    maxbufsize: 8192,
+                    security_flags: 0,
+                    property_names: ptr::null_mut(),
+                    property_values: ptr::null_mut(),
+                };
+                assert_eq!(
+                    sasl_setprop(
+                        conn,
+                        SASL_SEC_PROPS as c_int,
+                        &props as *const sasl_security_properties_t as *const c_void,
+                    ),
+                    SASL_OK
+                );
+            }
+            Self {
+                conn,
+                _callbacks: callbacks,
+            }
+        }
+
+        /// Start the mechanism, return the initial data of client.
+        fn start(&mut self, mech: &str) -> Vec<u8> {
+            let mech = CString::new(mech).unwrap();
+            let mut out: *const c_char = ptr::null();
+            let mut out_len: c_uint = 0;
+            let mut chosen: *const c_char = ptr::null();
+            // SAFETY: The connection is valid, the output is copied at once.
+            unsafe {
+                let err = sasl_client_start(
+                    self.conn,
+                    mech.as_ptr(),
+                    ptr::null_mut(),
+                    &mut out,
+                    &mut out_len,
+                    &mut chosen,
+                );
+                assert!(err == SASL_OK || err == SASL_CONTINUE);
+                sasl_test_data(out, out_len)
+            }
+        }
+
+        /// Handle the data of server, return the reply of client.
+        fn step(&mut self, input: &[u8]) -> (c_int, Vec<u8>) {
+            let mut out: *const c_char = ptr::null();
+            let mut out_len: c_uint = 0;
+            // SAFETY: The connection is valid, the output is copied at once.
+            unsafe {
+                let err = sasl_client_step(
+                    self.conn,
+                    input.as_ptr() as *const c_char,
+                    input.len() as c_uint,
+                    ptr::null_mut(),
+                    &mut out,
+                    &mut out_len,
+                );
+                (err, sasl_test_data(out, out_len))
+            }
+        }
+    }
+
+    impl Drop for SaslTestClient {
+        fn drop(&mut self) {
+            // SAFETY: The connection is set to null after disposed.
+            unsafe { sasl_dispose(&mut self.conn) }
+        }
+    }
+
+    /// Copy the output of sasl.
+    ///
+    /// # Safety
+    ///
+    /// The `data` must have `len` bytes if it is not null.
+    unsafe fn sasl_test_data(data: *const c_char, len: c_uint) -> Vec<u8> {
+        if data.is_null() {
+            return Vec::new();
+        }
+        std::slice::from_raw_parts(data as *const u8, len as usize).to_vec()
+    }
+
+    /// Split the sasl message of server into the data and the rest.
+    fn sasl_server_msg(msg: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let len = u32::from_be_bytes([msg[0], msg[1], msg[2], msg[3]]) as usize;
+        (msg[4..4 + len].to_vec(), msg[4 + len..].to_vec())
+    }
+
+    /// The sasl message of client, the data is terminated by NUL.
+    fn sasl_client_msg(data: &[u8]) -> Vec<u8> {
+        if data.is_empty() {
+            return 0_u32.to_be_bytes().to_vec();
+        }
+        let mut msg = (data.len() as u32 + 1).to_be_bytes().to_vec();
+        msg.extend_from_slice(data);
+        msg.push(0);
+        msg
+    }
+
+    #[test]
+    fn test_sasl_security_layer() {
+        let _guard = SASL_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join("stratovirt_test_sasl_digest");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("stratovirt.conf"),
+            format!(
+                "pwcheck_method: auxprop\nauxprop_plugin: sasldb\n\
+                 sasldb_path: {}\nmech_list: digest-md5\n",
+                dir.join("sasldb2").display()
+            ),
+        )
+        .unwrap();
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let mut saslauth = SaslAuth::new(vec!["alice".to_string()], false);
+        saslauth.set_config_path("", dir.to_str().unwrap()).unwrap();
+        server.security_type.borrow_mut().saslauth = Some(saslauth);
+        server.security_type.borrow_mut().auth = AuthState::Sasl;
+        let (client_io, _peer) = create_client_io(&server);
+        let channel = Rc::new(RefCell::new(RecordChannel::default()));
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.io_channel = channel.clone();
+        let client = locked_client_io.client.clone();
+        let take_channel_output = || std::mem::take(&mut channel.borrow_mut().output);
+
+        // The mechanisms of server are sent to client.
+        locked_client_io.update_event_handler(1, ClientIoHandler::handle_auth);
+        let output = feed_msg(&mut locked_client_io, vec![AuthState::Sasl as u8]).unwrap();
+        let (mech_list, _) = sasl_server_msg(&output);
+        assert_eq!(mech_list, b"DIGEST-MD5");
+
+        // The password of user is stored in the sasldb of server.
+        let user = CString::new("alice").unwrap();
+        // SAFETY: The sasl connection of the client is created above.
+        let err = unsafe {
+            sasl_setpass(
+                locked_client_io.saslconfig.sasl_conn,
+                user.as_ptr(),
+                b"secret".as_ptr() as *const c_char,
+                6,
+                ptr::null(),
+                0,
+                SASL_SET_CREATE,
+            )
+        };
+        assert_eq!(err, SASL_OK);
+
+        // DIGEST-MD5 has no initial data, the challenge is sent to client at once.
+        let mut sasl_client = SaslTestClient::new();
+        let mut msg = 10_u32.to_be_bytes().to_vec();
+        msg.extend_from_slice(b"DIGEST-MD5");
+        msg.append(&mut sasl_client_msg(&sasl_client.start("DIGEST-MD5")));
+        let output = feed_msg(&mut locked_client_io, msg).unwrap();
+        let (challenge, rest) = sasl_server_msg(&output);
+        assert_eq!(rest, [0]);
+
+        // The response is accepted, the result is sent before the security layer starts.
+        let (err, response) = sasl_client.step(&challenge);
+        assert_eq!(err, SASL_CONTINUE);
+        handle_msg(&mut locked_client_io, sasl_client_msg(&response)).unwrap();
+        locked_client_io.client_handle_write();
+        let output = take_channel_output();
+        let (rspauth, rest) = sasl_server_msg(&output);
+        assert_eq!(rest, [1, 0, 0, 0, 0]);
+        assert_eq!(sasl_client.step(&rspauth).0, SASL_OK);
+        assert!(locked_client_io.sasl_ssf_active());
+        assert!(locked_client_io.saslconfig.run_ssf >= 56);
+
+        // The update is encoded, and removed from the output buffer only
+        // after all of its encoded data is sent.
+        let mut update = Vec::new();
+        framebuffer_update(0, 0, 1, 1, ENCODING_RAW, &mut update);
+        update.extend_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+        channel.borrow_mut().write_limit = 5;
+        vnc_write(&client, update.clone());
+        locked_client_io.client_handle_write();
+        assert_eq!(client.out_buffer.lock().unwrap().len(), update.len());
+        while !client.out_buffer.lock().unwrap().is_empty() {
+            locked_client_io.client_handle_write();
+        }
+        let output = take_channel_output();
+        assert_ne!(output, update);
+        assert_eq!(sasl_decode_data(sasl_client.conn, &output).unwrap(), update);
+        channel.borrow_mut().write_limit = 0;
+
+        // The input from client is decoded, the incomplete packet is kept
+        // until the rest of it is received.
+        let client_init = vec![1_u8];
+        let encoded = sasl_encode_data(sasl_client.conn, &client_init).unwrap();
+        channel.borrow_mut().input = encoded[..3].to_vec();
+        locked_client_io.read_msg().unwrap();
+        assert!(client.in_buffer.lock().unwrap().is_empty());
+        channel.borrow_mut().input = encoded[3..].to_vec();
+        locked_client_io.read_msg().unwrap();
+        let mut buf = vec![0_u8; client_init.len()];
+        let mut locked_in_buffer = client.in_buffer.lock().unwrap();
+        assert_eq!(locked_in_buffer.len(), client_init.len());
+        locked_in_buffer.read_front(&mut buf, client_init.len());
+        assert_eq!(buf, client_init);
+        drop(locked_in_buffer);
+
+        // The traffic is plain after the sasl connection is released.
+        drop(sasl_client);
+        locked_client_io.sasl_teardown();
+        assert!(!locked_client_io.sasl_ssf_active());
+        vnc_write(&client, update.clone());
+        locked_client_io.client_handle_write();
+        assert_eq!(take_channel_output(), update);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn key_event_msg(down: bool, keysym: u32) -> Vec<u8> {
+        let mut msg = vec![ClientMsg::KeyEvent as u8, u8::from(down), 0, 0];
+        msg.extend_from_slice(&keysym.to_be_bytes());
+        msg
+    }
+
+    fn ext_key_event_msg(down: bool, keysym: u32, keycode: u32) -> Vec<u8> {
+        let mut msg = vec![ClientMsg::QemuClientMsg as u8, QEMU_CLIENT_EXT_KEY_EVENT];
+        msg.extend_from_slice(&u16::from(down).to_be_bytes());
+        msg.extend_from_slice(&keysym.to_be_bytes());
+        msg.extend_from_slice(&keycode.to_be_bytes());
+        msg
+    }
+
+    #[test]
+    fn test_websocket_handshake() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let channel = Rc::new(RefCell::new(RecordChannel::default()));
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.io_channel = channel.clone();
+        let client = locked_client_io.client.clone();
+        locked_client_io.ws_start().unwrap();
+
+        // The handshake is replied once the whole request is received.
+        let request = "GET / HTTP/1.1\r\n\
+                       Host: 127.0.0.1:5700\r\n\
+                       Upgrade: websocket\r\n\
+                       Connection: Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Protocol: binary\r\n\
+                       Sec-WebSocket-Version: 13\r\n\r\n";
+        let (head, tail) = request.as_bytes().split_at(32);
+        channel.borrow_mut().input = head.to_vec();
+        locked_client_io.client_handle_read().unwrap();
+        locked_client_io.client_handle_write();
+        assert!(channel.borrow().output.is_empty());
+        channel.borrow_mut().input = tail.to_vec();
+        locked_client_io.client_handle_read().unwrap();
+        locked_client_io.client_handle_write();
+        let mut expected = b"HTTP/1.1 101 Switching Protocols\r\n\
+                             Upgrade: websocket\r\n\
+                             Connection: Upgrade\r\n\
+                             Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+                             Sec-WebSocket-Protocol: binary\r\n\r\n"
+            .to_vec();
+        // Version of server in a binary frame.
+        expected.extend_from_slice(b"\x82\x0cRFB 003.008\n");
+        assert_eq!(channel.borrow().output, expected);
+        channel.borrow_mut().output.clear();
+
+        // Version of client in a masked frame, the security types are replied.
+        channel.borrow_mut().input = ws_encode_client_frame(
+            WS_OPCODE_BINARY,
+            true,
+            b"RFB 003.008\n",
+            [0x12, 0x34, 0x56, 0x78],
+        );
+        locked_client_io.client_handle_read().unwrap();
+        locked_client_io.client_handle_write();
+        assert_eq!(client.conn_state.lock().unwrap().version.minor, 8);
+        assert_eq!(channel.borrow().output, [0x82, 2, 1, AuthState::No as u8]);
+        drop(locked_client_io);
+
+        // Invalid request is rejected by http response.
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.ws_start().unwrap();
+        let request = request.replace("Version: 13", "Version: 8");
+        let result = feed_msg(&mut locked_client_io, request.as_bytes().to_vec());
+        assert!(result.is_err());
+        let output = take_output(&locked_client_io.client);
+        assert!(output.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_ext_key_event() {
+        let keysym2keycode: HashMap<u16, u16> = KEYSYM2KEYCODE.iter().copied().collect();
+        let keycode_at = keysym2keycode[&0x40] & 0xff;
+        let server = Arc::new(VncServer::new(ptr::null_mut(), keysym2keycode, None));
+        let _guard = INPUT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        event_loop_test_init();
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.expect = 1;
+        locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+        let client = locked_client_io.client.clone();
+        let pressed = |client_io: &ClientIoHandler| {
+            let mut keys: Vec<u16> = client_io.pressed_keys.iter().copied().collect();
+            keys.sort_unstable();
+            keys
+        };
+
+        // AltGr + Q is '@' in German layout, which is translated to the
+        // key of '@' in US layout instead of Q by the keysym, with AltGr
+        // released and shift pressed around it.
+        let mut msg = key_event_msg(true, 0xfe03);
+        msg.append(&mut key_event_msg(true, 0x40));
+        feed_msg(&mut locked_client_io, msg).unwrap();
+        assert_ne!(keycode_at, 0x10);
+        assert_eq!(pressed(&locked_client_io), vec![keycode_at, 0xb8]);
+        let mut msg = key_event_msg(false, 0x40);
+        msg.append(&mut key_event_msg(false, 0xfe03));
+        feed_msg(&mut locked_client_io, msg).unwrap();
+        assert!(locked_client_io.pressed_keys.is_empty());
+
+        // The extended key event is skipped before it is negotiated.
+        feed_msg(&mut locked_client_io, ext_key_event_msg(true, 0xfe03, 0xb8)).unwrap();
+        assert!(locked_client_io.pressed_keys.is_empty());
+        assert_eq!(locked_client_io.expect, 1);
+
+        let msg = set_encodings_msg(&[ENCODING_EXT_KEY_EVENT]);
+        let output = feed_msg(&mut locked_client_io, msg).unwrap();
+        let mut ack = Vec::new();
+        ext_key_event_ack(&client, &mut ack);
+        assert!(output.ends_with(&ack));
+        // Acknowledged only once.
+        let msg = set_encodings_msg(&[ENCODING_EXT_KEY_EVENT]);
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+
+        // The keycodes of the keys pressed are forwarded.
+        let mut msg = ext_key_event_msg(true, 0xfe03, 0xb8);
+        msg.append(&mut ext_key_event_msg(true, 0x40, 0x10));
+        feed_msg(&mut locked_client_io, msg).unwrap();
+        assert_eq!(pressed(&locked_client_io), vec![0x10, 0xb8]);
+        feed_msg(&mut locked_client_io, ext_key_event_msg(false, 0x40, 0x10)).unwrap();
+        assert_eq!(pressed(&locked_client_io), vec![0xb8]);
+
+        // The unknown keycode falls back to the keysym.
+        feed_msg(&mut locked_client_io, ext_key_event_msg(true, 0x31, 0)).unwrap();
+        assert_eq!(pressed(&locked_client_io), vec![0x02, 0xb8]);
+
+        // The keys still pressed are released when client is disconnected.
+        locked_client_io.teardown();
+        assert!(locked_client_io.pressed_keys.is_empty());
+    }
+
+    #[derive(Default)]
+    struct TestInput {
+        keys: Vec<(u16, bool)>,
+        points: Vec<(u32, u32, u32)>,
+    }
+
+    struct TestKbd(Arc<Mutex<TestInput>>);
+
+    impl KeyboardOpts for TestKbd {
+        fn do_key_event(&mut self, keycode: u16, down: bool) -> Result<()> {
+            self.0.lock().unwrap().keys.push((keycode, down));
+            Ok(())
+        }
+    }
+
+    struct TestPointer(Arc<Mutex<TestInput>>);
+
+    impl PointerOpts for TestPointer {
+        fn do_point_event(&mut self, button: u32, x: u32, y: u32) -> Result<()> {
+            self.0.lock().unwrap().points.push((button, x, y));
+            Ok(())
+        }
+    }
+
+    fn point_event_msg(button: u8, x: u16, y: u16) -> Vec<u8> {
+        let mut msg = vec![ClientMsg::PointerEvent as u8, button];
+        msg.extend_from_slice(&x.to_be_bytes());
+        msg.extend_from_slice(&y.to_be_bytes());
+        msg
+    }
+
+    #[test]
+    fn test_release_pressed_inputs() {
+        let keysym2keycode: HashMap<u16, u16> = KEYSYM2KEYCODE.iter().copied().collect();
+        let server = Arc::new(VncServer::new(ptr::null_mut(), keysym2keycode, None));
+        let mut image_data = vec![0_u32; 4];
+        let image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            2,
+            2,
+            image_data.as_mut_ptr(),
+            8,
+        );
+        server.vnc_surface.lock().unwrap().server_image = image;
+
+        let _guard = INPUT_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        event_loop_test_init();
+        let input = Arc::new(Mutex::new(TestInput::default()));
+        register_keyboard(
+            "VncTestKeyboard",
+            Arc::new(Mutex::new(TestKbd(input.clone()))),
+        );
+        register_pointer(
+            "VncTestPointer",
+            Arc::new(Mutex::new(TestPointer(input.clone()))),
+        );
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.expect = 1;
+        locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+        let pos = (ABS_MAX as u32 / 2, ABS_MAX as u32 / 2);
+
+        // The repeated presses of Control_L are dropped.
+        let mut msg = key_event_msg(true, 0xffe3);
+        msg.append(&mut key_event_msg(true, 0xffe3));
+        msg.append(&mut point_event_msg(INPUT_POINT_LEFT, 1, 1));
+        feed_msg(&mut locked_client_io, msg).unwrap();
+        assert_eq!(input.lock().unwrap().keys, vec![(0x1d, true)]);
+        assert_eq!(input.lock().unwrap().points, vec![(0x01, pos.0, pos.1)]);
+
+        // Nothing is released if client resends the encodings in time.
+        feed_msg(&mut locked_client_io, set_encodings_msg(&[ENCODING_RAW])).unwrap();
+        assert_eq!(input.lock().unwrap().keys.len(), 1);
+        assert_eq!(input.lock().unwrap().points.len(), 1);
+
+        // Client resends the encodings after it is idle.
+        locked_client_io.last_input -= INPUT_IDLE_RELEASE;
+        feed_msg(&mut locked_client_io, set_encodings_msg(&[ENCODING_RAW])).unwrap();
+        assert_eq!(
+            input.lock().unwrap().keys,
+            vec![(0x1d, true), (0x1d, false)]
+        );
+        assert_eq!(input.lock().unwrap().points[1], (0, pos.0, pos.1));
+        assert!(locked_client_io.pressed_keys.is_empty());
+        assert_eq!(locked_client_io.pressed_buttons, 0);
+
+        // Client is disconnected while the key and button are pressed.
+        let mut msg = key_event_msg(true, 0xffe3);
+        msg.append(&mut point_event_msg(INPUT_POINT_RIGHT, 0, 1));
+        feed_msg(&mut locked_client_io, msg).unwrap();
+        input.lock().unwrap().keys.clear();
+        input.lock().unwrap().points.clear();
+        locked_client_io.teardown();
+        assert_eq!(input.lock().unwrap().keys, vec![(0x1d, false)]);
+        assert_eq!(input.lock().unwrap().points, vec![(0, 0, pos.1)]);
+        // Nothing is left to release.
+        locked_client_io.teardown();
+        assert_eq!(input.lock().unwrap().keys.len(), 1);
+        assert_eq!(input.lock().unwrap().points.len(), 1);
+
+        unregister_keyboard("VncTestKeyboard");
+        unregister_pointer("VncTestPointer");
+        server.vnc_surface.lock().unwrap().server_image = ptr::null_mut();
+        unref_pixman_image(image);
+    }
+
+    fn cut_text_msg(len: i32, payload: &[u8]) -> Vec<u8> {
+        let mut msg = vec![ClientMsg::ClientCutText as u8, 0, 0, 0];
+        msg.extend_from_slice(&len.to_be_bytes());
+        msg.extend_from_slice(payload);
+        msg
+    }
+
+    #[test]
+    fn test_client_cut_text() {
+        let mut server = VncServer::new(ptr::null_mut(), HashMap::new(), None);
+        server.clipboard_limit = 64;
+        let server = Arc::new(server);
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.expect = 1;
+        locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+        let client = locked_client_io.client.clone();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = received.clone();
+        // The notifiers are global, only the text of this test is recorded.
+        register_clipboard_notifier(Arc::new(move |text: &str| {
+            if text.starts_with("cut-") {
+                received_clone.lock().unwrap().push(text.to_string());
+            }
+        }));
+        let take_received = || std::mem::take(&mut *received.lock().unwrap());
+
+        // Base protocol: the text is in Latin-1.
+        let text = b"cut-a\ncaf\xe9";
+        feed_msg(&mut locked_client_io, cut_text_msg(text.len() as i32, text)).unwrap();
+        assert_eq!(take_received(), vec!["cut-a\ncaf\u{e9}".to_string()]);
+
+        // The text over the limit is skipped, and the next message is
+        // handled.
+        let text = format!("cut-{}", "x".repeat(10000));
+        let mut msg = cut_text_msg(text.len() as i32, text.as_bytes());
+        msg.append(&mut cut_text_msg(5, b"cut-b"));
+        feed_msg(&mut locked_client_io, msg).unwrap();
+        assert_eq!(take_received(), vec!["cut-b".to_string()]);
+        assert_eq!(locked_client_io.expect, 1);
+
+        // Extended clipboard is invalid before it is negotiated.
+        let notify = EXT_CLIPBOARD_ACTION_NOTIFY | EXT_CLIPBOARD_FORMAT_TEXT;
+        let msg = cut_text_msg(-4, &notify.to_be_bytes());
+        let len = msg.len();
+        assert!(feed_msg(&mut locked_client_io, msg).is_err());
+        client.in_buffer.lock().unwrap().remove_front(len);
+        locked_client_io.expect = 1;
+        locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+
+        let msg = set_encodings_msg(&[ENCODING_EXT_CLIPBOARD]);
+        let output = feed_msg(&mut locked_client_io, msg).unwrap();
+        let mut caps = Vec::new();
+        ext_clipboard_caps(64, &mut caps);
+        assert!(output.ends_with(&caps));
+
+        // Provide: zlib stream of the null terminated UTF-8 text.
+        let text = "cut-\u{4e2d}\r\nb\0".as_bytes();
+        let mut data = (text.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(text);
+        let stream = compress_to_vec_zlib(&data, 6);
+        let flags = EXT_CLIPBOARD_ACTION_PROVIDE | EXT_CLIPBOARD_FORMAT_TEXT;
+        let mut payload = flags.to_be_bytes().to_vec();
+        payload.extend_from_slice(&stream);
+        let msg = cut_text_msg(-(payload.len() as i32), &payload);
+        feed_msg(&mut locked_client_io, msg).unwrap();
+        assert_eq!(take_received(), vec!["cut-\u{4e2d}\nb".to_string()]);
+
+        // Notify is answered by the request of text.
+        let msg = cut_text_msg(-4, &notify.to_be_bytes());
+        let output = feed_msg(&mut locked_client_io, msg).unwrap();
+        let mut request = Vec::new();
+        ext_clipboard_request(&mut request);
+        assert_eq!(output, request);
+
+        // The client which does not support request provides the text
+        // directly.
+        let flags = EXT_CLIPBOARD_ACTION_CAPS | EXT_CLIPBOARD_ACTION_PROVIDE | 1;
+        let mut payload = flags.to_be_bytes().to_vec();
+        payload.extend_from_slice(&1024_u32.to_be_bytes());
+        feed_msg(&mut locked_client_io, cut_text_msg(-8, &payload)).unwrap();
+        assert_eq!(locked_client_io.ext_clipboard_caps, flags);
+        let msg = cut_text_msg(-4, &notify.to_be_bytes());
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+    }
+
+    fn pixel_format_msg(bpp: u8, depth: u8, be: bool, max: [u16; 3], shift: [u8; 3]) -> Vec<u8> {
+        let mut msg = vec![ClientMsg::SetPixelFormat as u8, 0, 0, 0];
+        msg.extend_from_slice(&[bpp, depth, u8::from(be), 1]);
+        for m in max {
+            msg.extend_from_slice(&m.to_be_bytes());
+        }
+        msg.extend_from_slice(&shift);
+        msg.extend_from_slice(&[0; 3]);
+        msg
+    }
+
+    /// Decode the pixels of client as the colors of (red, green, blue).
+    fn decode_pixels(dpm: &DisplayMode, data: &[u8]) -> Vec<(u32, u32, u32)> {
+        let pf = &dpm.pf;
+        data.chunks(pf.pixel_bytes as usize)
+            .map(|bytes| {
+                let mut v = 0_u32;
+                for (i, b) in bytes.iter().enumerate() {
+                    let shift = if dpm.client_be {
+                        (bytes.len() - 1 - i) * 8
+                    } else {
+                        i * 8
+                    };
+                    v |= u32::from(*b) << shift;
+                }
+                (
+                    (v >> pf.red.shift) & u32::from(pf.red.max),
+                    (v >> pf.green.shift) & u32::from(pf.green.max),
+                    (v >> pf.blue.shift) & u32::from(pf.blue.max),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_set_pixel_format() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.expect = 1;
+        locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+        let client = locked_client_io.client.clone();
+
+        // Image of 2 * 2 pixels in x8r8g8b8.
+        let mut image_data: Vec<u32> = vec![0x00ff_8040, 0x00ff_ffff, 0x0000_0000, 0x0012_3456];
+        let image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            2,
+            2,
+            image_data.as_mut_ptr(),
+            8,
+        );
+        let rect = Rectangle::new(0, 0, 2, 2);
+        let send_rect = |expected: &[u8]| {
+            let dpm = client.client_dpm.lock().unwrap().clone();
+            let mut buf = Vec::new();
+            raw_send_framebuffer_update(image, &rect, &dpm, &mut buf);
+            assert_eq!(buf, expected);
+            // Each color is the most significant bits of server color.
+            let colors: Vec<(u32, u32, u32)> = image_data
+                .iter()
+                .map(|c| {
+                    (
+                        ((c >> 16) & 0xff) >> (8 - dpm.pf.red.bits),
+                        ((c >> 8) & 0xff) >> (8 - dpm.pf.green.bits),
+                        (c & 0xff) >> (8 - dpm.pf.blue.bits),
+                    )
+                })
+                .collect();
+            assert_eq!(decode_pixels(&dpm, &buf), colors);
+        };
+
+        // RGB565 in little endian.
+        let msg = pixel_format_msg(16, 16, false, [31, 63, 31], [11, 5, 0]);
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+        assert!(client.client_dpm.lock().unwrap().convert);
+        send_rect(&[0x08, 0xfc, 0xff, 0xff, 0x00, 0x00, 0xaa, 0x11]);
+
+        // BGR233.
+        let msg = pixel_format_msg(8, 8, false, [7, 7, 3], [0, 3, 6]);
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+        send_rect(&[0x67, 0xff, 0x00, 0x48]);
+
+        // Big endian of 32 bits.
+        let msg = pixel_format_msg(32, 24, true, [255, 255, 255], [16, 8, 0]);
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+        assert!(client.client_dpm.lock().unwrap().convert);
+        send_rect(&[
+            0x00, 0xff, 0x80, 0x40, 0x00, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x12,
+            0x34, 0x56,
+        ]);
+
+        // The native format is not converted.
+        let msg = pixel_format_msg(32, 24, false, [255, 255, 255], [16, 8, 0]);
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+        assert!(!client.client_dpm.lock().unwrap().convert);
+        send_rect(&[
+            0x40, 0x80, 0xff, 0x00, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x56, 0x34,
+            0x12, 0x00,
+        ]);
+        drop(locked_client_io);
+        unref_pixman_image(image);
+
+        // Colour map and the invalid formats are rejected.
+        let mut colour_map = pixel_format_msg(8, 8, false, [7, 7, 3], [0, 3, 6]);
+        colour_map[7] = 0;
+        let invalid_msgs = [
+            colour_map,
+            pixel_format_msg(24, 24, false, [255, 255, 255], [16, 8, 0]),
+            pixel_format_msg(16, 0, false, [31, 63, 31], [11, 5, 0]),
+            pixel_format_msg(16, 16, false, [31, 62, 31], [11, 5, 0]),
+            pixel_format_msg(16, 16, false, [31, 63, 31], [12, 5, 0]),
+            pixel_format_msg(32, 32, false, [1023, 1023, 1023], [20, 10, 0]),
+            pixel_format_msg(32, 24, false, [0, 255, 255], [16, 8, 0]),
+        ];
+        for msg in invalid_msgs {
+            let (client_io, _peer) = create_client_io(&server);
+            let mut locked_client_io = client_io.lock().unwrap();
+            locked_client_io.expect = 1;
+            locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+            let err = feed_msg(&mut locked_client_io, msg).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<VncError>(),
+                Some(VncError::UnsupportedPixelFormat(_))
+            ));
+            assert!(locked_client_io.client.conn_state.lock().unwrap().dis_conn);
+        }
+    }
+
+    #[test]
+    fn test_rectangle_subtract() {
+        let rect = Rectangle::new(0, 0, 64, 32);
+        let area = Rectangle::new(16, 8, 16, 8);
+        assert_eq!(rect.intersect(&area), Some(area.clone()));
+        assert_eq!(
+            rect.subtract(&area),
+            [
+                Rectangle::new(0, 0, 64, 8),
+                Rectangle::new(0, 16, 64, 16),
+                Rectangle::new(0, 8, 16, 8),
+                Rectangle::new(32, 8, 32, 8),
+            ]
+        );
+        let area = Rectangle::new(64, 0, 16, 16);
+        assert!(rect.intersect(&area).is_none());
+        assert_eq!(
+            rect.subtract(&area),
+            [Rectangle::new(0, 16, 64, 16), Rectangle::new(0, 0, 64, 16)]
+        );
+    }
+
+    #[test]
+    fn test_skip_unsupported_msg() {
+        let server = Arc::new(VncServer::new(ptr::null_mut(), HashMap::new(), None));
+        let skippable: Vec<Vec<u8>> = vec![
+            vec![7, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0xaa, 0xbb],
+            vec![8, 2, 0, 0],
+            vec![9, 1, 0, 0],
+            vec![10, 1, 0, 8, 0, 8],
+            vec![11, 0, 0, 0, 0, 0, 0, 3, b'a', b'b', b'c'],
+            vec![11, 0, 0, 0, 0xff, 0xff, 0xff, 0xff],
+            vec![12],
+            vec![13],
+            vec![15, 2, 0, 0],
+            vec![127, 1, 0, 6, 0xaa, 0xbb],
+            vec![250, 0, 1, 2],
+            vec![
+                251, 0, 2, 128, 1, 224, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 128, 1, 224, 0, 0, 0, 0,
+            ],
+            vec![253, 0x81, 0, 2, 0xaa, 0xbb],
+            vec![253, 0x01, 2, 0, 0xaa, 0xbb],
+            vec![254, 1, 0, 4],
+            vec![255, 0, 0, 1, 0, 0, 0, 0x1e, 0, 0, 0, 0x1e],
+            vec![255, 1, 0, 0],
+            vec![255, 1, 0, 2, 0, 2, 0, 0, 0xac, 0x44],
+        ];
+        let supported = [0, 2, 3, 4, 5, 6, 150, 248];
+
+        // Pseudo random generator, so that the failed case can be reproduced.
+        let mut seed = 0x2545_f491_u32;
+        let mut rand = move |max: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize % max
+        };
+
+        // The skippable messages are fed in random order and split in
+        // random size, the connection survives and keeps in sync.
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.expect = 1;
+        locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+        let mut stream = Vec::new();
+        for _ in 0..200 {
+            stream.extend_from_slice(&skippable[rand(skippable.len())]);
+        }
+        while !stream.is_empty() {
+            let len = cmp::min(rand(16) + 1, stream.len());
+            let chunk: Vec<u8> = stream.drain(..len).collect();
+            assert!(feed_msg(&mut locked_client_io, chunk).unwrap().is_empty());
+        }
+        assert_eq!(locked_client_io.expect, 1);
+        assert_eq!(locked_client_io.client.in_buffer.lock().unwrap().len(), 0);
+        // The following message is parsed.
+        let msg = vec![ClientMsg::Fence as u8, 0, 0, 0, 0x80, 0, 0, 0, 0];
+        assert_eq!(
+            feed_msg(&mut locked_client_io, msg).unwrap(),
+            [ServerMsg::Fence as u8, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        drop(locked_client_io);
+
+        // The client is disconnected by the message which can not be framed.
+        for msg_type in 0..=u8::MAX {
+            if supported.contains(&msg_type) || skippable.iter().any(|msg| msg[0] == msg_type) {
+                continue;
+            }
+            let (client_io, _peer) = create_client_io(&server);
+            let mut locked_client_io = client_io.lock().unwrap();
+            locked_client_io.expect = 1;
+            locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+            let mut msg = vec![msg_type];
+            msg.extend((0..rand(16)).map(|_| rand(256) as u8));
+            assert!(feed_msg(&mut locked_client_io, msg).is_err());
+        }
+
+        // Unknown sub-type of QEMU message and invalid length of VMware.
+        for msg in [vec![255, 2, 0, 0], vec![255, 1, 0, 3], vec![127, 0, 0, 3]] {
+            let (client_io, _peer) = create_client_io(&server);
+            let mut locked_client_io = client_io.lock().unwrap();
+            locked_client_io.expect = 1;
+            locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+            assert!(feed_msg(&mut locked_client_io, msg).is_err());
+        }
+
+        // The long message is discarded in chunks without being buffered.
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.expect = 1;
+        locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+        let text_len = 3 * MSG_SKIP_CHUNK + 5;
+        let mut msg = vec![11, 0, 0, 0];
+        msg.extend_from_slice(&(text_len as u32).to_be_bytes());
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+        assert_eq!(locked_client_io.msg_skip, 8 + text_len);
+        assert!(locked_client_io.expect <= MSG_SKIP_CHUNK);
+        let mut text = vec![b'a'; text_len];
+        while !text.is_empty() {
+            let chunk: Vec<u8> = text.drain(..cmp::min(1000, text.len())).collect();
+            assert!(feed_msg(&mut locked_client_io, chunk).unwrap().is_empty());
+            assert!(locked_client_io.client.in_buffer.lock().unwrap().len() < MSG_SKIP_CHUNK);
+        }
+        assert_eq!(locked_client_io.expect, 1);
+        let msg = vec![ClientMsg::Fence as u8, 0, 0, 0, 0x80, 0, 0, 0, 0];
+        assert_eq!(
+            feed_msg(&mut locked_client_io, msg).unwrap(),
+            [ServerMsg::Fence as u8, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        // The huge length claimed by FileTransfer is not reserved.
+        let (client_io, _peer) = create_client_io(&server);
+        let mut locked_client_io = client_io.lock().unwrap();
+        locked_client_io.expect = 1;
+        locked_client_io.msg_handler = ClientIoHandler::handle_protocol_msg;
+        let msg = vec![7, 1, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0x00];
+        assert!(feed_msg(&mut locked_client_io, msg).unwrap().is_empty());
+        assert_eq!(locked_client_io.msg_skip, 12 + 0xffff_ff00);
+        assert_eq!(locked_client_io.expect, MSG_SKIP_CHUNK);
+    }
+}
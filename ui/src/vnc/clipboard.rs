@@ -0,0 +1,493 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use crate::{
+    error::VncError,
+    vnc::{
+        client_io::{vnc_flush, vnc_write, ServerMsg, VncFeatures},
+        server_io::VncServer,
+    },
+};
+use anyhow::{anyhow, Result};
+use log::warn;
+use machine_manager::event_loop::EventLoop;
+use miniz_oxide::{deflate::compress_to_vec_zlib, inflate::decompress_to_vec_zlib_with_limit};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Min interval between the clipboard updates sent to clients, the updates
+/// in the interval are merged into the latest one.
+const CLIPBOARD_UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+/// Text format of extended clipboard.
+pub const EXT_CLIPBOARD_FORMAT_TEXT: u32 = 1 << 0;
+/// Mask of the formats in the flags of extended clipboard.
+const EXT_CLIPBOARD_FORMAT_MASK: u32 = 0xffff;
+/// Capabilities of the sender in extended clipboard.
+pub const EXT_CLIPBOARD_ACTION_CAPS: u32 = 1 << 24;
+/// Request of the clipboard data in extended clipboard.
+pub const EXT_CLIPBOARD_ACTION_REQUEST: u32 = 1 << 25;
+/// Query of the available formats in extended clipboard.
+pub const EXT_CLIPBOARD_ACTION_PEEK: u32 = 1 << 26;
+/// Formats available in the clipboard of the sender in extended clipboard.
+pub const EXT_CLIPBOARD_ACTION_NOTIFY: u32 = 1 << 27;
+/// Data of the clipboard in extended clipboard.
+pub const EXT_CLIPBOARD_ACTION_PROVIDE: u32 = 1 << 28;
+/// Capabilities assumed for the client which does not send its own.
+pub const EXT_CLIPBOARD_CLIENT_CAPS: u32 = EXT_CLIPBOARD_ACTION_REQUEST
+    | EXT_CLIPBOARD_ACTION_PEEK
+    | EXT_CLIPBOARD_ACTION_NOTIFY
+    | EXT_CLIPBOARD_ACTION_PROVIDE
+    | EXT_CLIPBOARD_FORMAT_TEXT;
+/// Max formats of extended clipboard, the caps message carries the max size
+/// of each format.
+const EXT_CLIPBOARD_FORMAT_NUM: usize = 16;
+/// Compression level of the data in extended clipboard.
+const EXT_CLIPBOARD_ZLIB_LEVEL: u8 = 6;
+
+/// What to do with the clipboard update from guest.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClipboardAction {
+    /// Send the text to clients now.
+    Send(String),
+    /// Send the pending text after the delay.
+    Delay(Duration),
+    /// The pending text is replaced, which is sent by the armed timer.
+    Hold,
+}
+
+/// Rate limit of the guest clipboard sent to clients.
+#[derive(Default)]
+pub struct ClipboardThrottle {
+    /// Time of the last update sent to clients.
+    last_sent: Option<Instant>,
+    /// Text held back by the rate limit, only the latest one is kept.
+    pending: Option<String>,
+}
+
+impl ClipboardThrottle {
+    /// Decide whether the text of the guest clipboard can be sent now.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - text of the guest clipboard.
+    /// * `now` - time of the update.
+    pub fn update(&mut self, text: &str, now: Instant) -> ClipboardAction {
+        if self.pending.is_some() {
+            self.pending = Some(text.to_string());
+            return ClipboardAction::Hold;
+        }
+        if let Some(last_sent) = self.last_sent {
+            let elapsed = now.saturating_duration_since(last_sent);
+            if elapsed < CLIPBOARD_UPDATE_INTERVAL {
+                self.pending = Some(text.to_string());
+                return ClipboardAction::Delay(CLIPBOARD_UPDATE_INTERVAL - elapsed);
+            }
+        }
+        self.last_sent = Some(now);
+        ClipboardAction::Send(text.to_string())
+    }
+
+    /// Take the text held back when the delay expires.
+    pub fn take_pending(&mut self, now: Instant) -> Option<String> {
+        let text = self.pending.take()?;
+        self.last_sent = Some(now);
+        Some(text)
+    }
+}
+
+/// Convert the text to Latin-1 required by the base RFB protocol, the
+/// characters out of Latin-1 are replaced by '?', and the lines end with
+/// '\n' alone.
+fn latin1_text(text: &str, limit: usize) -> Vec<u8> {
+    text.replace("\r\n", "\n")
+        .chars()
+        .take(limit)
+        .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+        .collect()
+}
+
+/// Convert the text to the UTF-8 text of extended clipboard, the lines end
+/// with "\r\n" and the text is null terminated. The text is truncated on the
+/// boundary of character.
+fn utf8_text(text: &str, limit: usize) -> Vec<u8> {
+    let text = text.replace("\r\n", "\n").replace('\n', "\r\n");
+    let mut len = std::cmp::min(text.len(), limit);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut data = text.as_bytes()[..len].to_vec();
+    data.push(0);
+    data
+}
+
+/// Convert the Latin-1 text of ClientCutText in the base RFB protocol.
+pub fn client_latin1_text(data: &[u8]) -> String {
+    data.iter().map(|&b| char::from(b)).collect()
+}
+
+/// Message of extended clipboard sent by client.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExtClipboardMsg {
+    /// Actions and formats supported by client.
+    Caps(u32),
+    /// Text of client clipboard, none if the text is absent or dropped.
+    Provide(Option<String>),
+    /// Formats available in client clipboard.
+    Notify(u32),
+    /// Request or peek of server clipboard, which is ignored as the guest
+    /// clipboard is pushed to client by provide.
+    Ignored,
+}
+
+/// Max bytes of the extended clipboard message from client, which is the
+/// bound of the zlib stream of the text limit, see deflateBound() of zlib.
+///
+/// # Arguments
+///
+/// * `limit` - Max bytes of the text.
+pub fn ext_clipboard_msg_max(limit: usize) -> usize {
+    let len = 4 + limit;
+    let stream_max = len + (len >> 12) + (len >> 14) + (len >> 25) + 13;
+    4 + std::cmp::max(stream_max, EXT_CLIPBOARD_FORMAT_NUM * 4)
+}
+
+/// Extract the text from the zlib stream of provide message. The text is
+/// dropped if it exceeds the limit or the stream is corrupted.
+fn provided_text(formats: u32, stream: &[u8], limit: usize) -> Option<String> {
+    if formats & EXT_CLIPBOARD_FORMAT_TEXT == 0 {
+        return None;
+    }
+    // Only the text is advertised, so it is the only format in the stream.
+    let data = match decompress_to_vec_zlib_with_limit(stream, 4 + limit) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Drop the extended clipboard from client: {:?}", e);
+            return None;
+        }
+    };
+    if data.len() < 4 {
+        warn!("Drop the extended clipboard from client: no text size");
+        return None;
+    }
+    let len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    if len > data.len() - 4 {
+        warn!("Drop the extended clipboard from client: text is truncated");
+        return None;
+    }
+    // The text is null terminated and the lines end with "\r\n".
+    let text = &data[4..4 + len];
+    let text = match text.iter().position(|&b| b == 0) {
+        Some(end) => &text[..end],
+        None => text,
+    };
+    Some(String::from_utf8_lossy(text).replace("\r\n", "\n"))
+}
+
+/// Parse the message of extended clipboard from client.
+///
+/// # Arguments
+///
+/// * `data` - Payload of ClientCutText, which starts with the flags.
+/// * `limit` - Max bytes of the text accepted from client.
+pub fn parse_ext_clipboard(data: &[u8], limit: usize) -> Result<ExtClipboardMsg> {
+    if data.len() < 4 {
+        return Err(anyhow!(VncError::ProtocolMessageFailed(format!(
+            "extended clipboard message of {} bytes",
+            data.len()
+        ))));
+    }
+    let flags = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+    let formats = flags & EXT_CLIPBOARD_FORMAT_MASK;
+    let msg = if flags & EXT_CLIPBOARD_ACTION_CAPS != 0 {
+        ExtClipboardMsg::Caps(flags)
+    } else if flags & EXT_CLIPBOARD_ACTION_PROVIDE != 0 {
+        ExtClipboardMsg::Provide(provided_text(formats, &data[4..], limit))
+    } else if flags & EXT_CLIPBOARD_ACTION_NOTIFY != 0 {
+        ExtClipboardMsg::Notify(formats)
+    } else {
+        ExtClipboardMsg::Ignored
+    };
+    Ok(msg)
+}
+
+/// Append the header of ServerCutText, the negative length means the
+/// payload is in the format of extended clipboard.
+fn server_cut_text_header(len: i32, buf: &mut Vec<u8>) {
+    buf.push(ServerMsg::ServerCutText as u8);
+    buf.append(&mut [0_u8; 3].to_vec()); // padding
+    buf.append(&mut len.to_be_bytes().to_vec());
+}
+
+/// Advertise the capabilities of extended clipboard, only the text which
+/// is provided by server is supported.
+///
+/// # Arguments
+///
+/// * `limit` - Max bytes of the text.
+/// * `buf` - Output buffer.
+pub fn ext_clipboard_caps(limit: usize, buf: &mut Vec<u8>) {
+    server_cut_text_header(-8, buf);
+    let flags =
+        EXT_CLIPBOARD_ACTION_CAPS | EXT_CLIPBOARD_ACTION_PROVIDE | EXT_CLIPBOARD_FORMAT_TEXT;
+    buf.append(&mut flags.to_be_bytes().to_vec());
+    buf.append(&mut (limit as u32).to_be_bytes().to_vec());
+}
+
+/// Request the text of client clipboard in extended clipboard.
+pub fn ext_clipboard_request(buf: &mut Vec<u8>) {
+    server_cut_text_header(-4, buf);
+    let flags = EXT_CLIPBOARD_ACTION_REQUEST | EXT_CLIPBOARD_FORMAT_TEXT;
+    buf.append(&mut flags.to_be_bytes().to_vec());
+}
+
+/// Build ServerCutText with the text of guest clipboard, which is truncated
+/// to the limit.
+///
+/// # Arguments
+///
+/// * `text` - Text of the guest clipboard.
+/// * `limit` - Max bytes of the text.
+/// * `extended` - Whether extended clipboard is negotiated with client.
+/// * `buf` - Output buffer.
+pub fn server_cut_text(text: &str, limit: usize, extended: bool, buf: &mut Vec<u8>) {
+    if !extended {
+        let mut data = latin1_text(text, limit);
+        server_cut_text_header(data.len() as i32, buf);
+        buf.append(&mut data);
+        return;
+    }
+
+    let data = utf8_text(text, limit);
+    let mut payload = (data.len() as u32).to_be_bytes().to_vec();
+    payload.extend_from_slice(&data);
+    let mut payload = compress_to_vec_zlib(&payload, EXT_CLIPBOARD_ZLIB_LEVEL);
+    server_cut_text_header(-(4 + payload.len() as i32), buf);
+    let flags = EXT_CLIPBOARD_ACTION_PROVIDE | EXT_CLIPBOARD_FORMAT_TEXT;
+    buf.append(&mut flags.to_be_bytes().to_vec());
+    buf.append(&mut payload);
+}
+
+/// Send the text of guest clipboard to the clients which have finished the
+/// handshake.
+fn send_clipboard(server: &Arc<VncServer>, text: &str) {
+    let locked_handlers = server.client_handlers.lock().unwrap();
+    for client in locked_handlers.values() {
+        if !client.conn_state.lock().unwrap().initialized {
+            continue;
+        }
+        let extended = client
+            .client_dpm
+            .lock()
+            .unwrap()
+            .has_feature(VncFeatures::VncFeatureClipboardExt);
+        let mut buf = Vec::new();
+        server_cut_text(text, server.clipboard_limit, extended, &mut buf);
+        // The clipboard is not worth disconnecting the congested client.
+        if !client.out_buffer.lock().unwrap().is_enough(buf.len()) {
+            warn!(
+                "Output of vnc client {} is congested, drop the clipboard",
+                client.addr
+            );
+            continue;
+        }
+        vnc_write(client, buf);
+        vnc_flush(client);
+    }
+}
+
+/// Propagate the guest clipboard to clients, the updates are rate limited.
+///
+/// # Arguments
+///
+/// * `server` - Vnc server.
+/// * `text` - Text of the guest clipboard.
+pub fn vnc_clipboard_update(server: &Arc<VncServer>, text: &str) {
+    if server.clipboard_limit == 0 {
+        return;
+    }
+    let action = server
+        .clipboard
+        .lock()
+        .unwrap()
+        .update(text, Instant::now());
+    match action {
+        ClipboardAction::Send(text) => send_clipboard(server, &text),
+        ClipboardAction::Delay(delay) => {
+            let weak_server = Arc::downgrade(server);
+            let func = Box::new(move || {
+                if let Some(server) = weak_server.upgrade() {
+                    let pending = server
+                        .clipboard
+                        .lock()
+                        .unwrap()
+                        .take_pending(Instant::now());
+                    if let Some(text) = pending {
+                        send_clipboard(&server, &text);
+                    }
+                }
+            });
+            match EventLoop::get_ctx(None) {
+                Some(ctx) => {
+                    ctx.timer_add(func, delay);
+                }
+                None => func(),
+            }
+        }
+        ClipboardAction::Hold => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+    fn provide_msg(size: usize, text: &[u8]) -> Vec<u8> {
+        let mut payload = (size as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(text);
+        let flags = EXT_CLIPBOARD_ACTION_PROVIDE | EXT_CLIPBOARD_FORMAT_TEXT;
+        let mut msg = flags.to_be_bytes().to_vec();
+        let stream = compress_to_vec_zlib(&payload, EXT_CLIPBOARD_ZLIB_LEVEL);
+        msg.extend_from_slice(&stream);
+        msg
+    }
+
+    fn payload_len(buf: &[u8]) -> i32 {
+        i32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]])
+    }
+
+    #[test]
+    fn test_server_cut_text() {
+        // Base protocol: Latin-1 with the lines ending with '\n'.
+        let mut buf = Vec::new();
+        server_cut_text("ab\r\ncd\u{e9}\u{4e2d}", 1024, false, &mut buf);
+        assert_eq!(&buf[..4], &[ServerMsg::ServerCutText as u8, 0, 0, 0]);
+        assert_eq!(payload_len(&buf), 7);
+        assert_eq!(&buf[8..], &[b'a', b'b', b'\n', b'c', b'd', 0xe9, b'?']);
+
+        // Truncated to the limit.
+        let mut buf = Vec::new();
+        server_cut_text(&"x".repeat(100), 10, false, &mut buf);
+        assert_eq!(payload_len(&buf), 10);
+        assert_eq!(buf.len(), 8 + 10);
+
+        // Extended clipboard: flags followed by the zlib stream of the
+        // null terminated UTF-8 text.
+        let mut buf = Vec::new();
+        server_cut_text("a\n\u{4e2d}\u{6587}", 6, true, &mut buf);
+        assert_eq!(-payload_len(&buf) as usize, buf.len() - 8);
+        let flags = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+        assert_eq!(
+            flags,
+            EXT_CLIPBOARD_ACTION_PROVIDE | EXT_CLIPBOARD_FORMAT_TEXT
+        );
+        let data = decompress_to_vec_zlib(&buf[12..]).unwrap();
+        // The second character does not fit in the limit.
+        let text = "a\r\n\u{4e2d}\0".as_bytes();
+        assert_eq!(&data[..4], &(text.len() as u32).to_be_bytes());
+        assert_eq!(&data[4..], text);
+
+        let mut buf = Vec::new();
+        ext_clipboard_caps(1024, &mut buf);
+        assert_eq!(payload_len(&buf), -8);
+        let flags = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+        assert_eq!(flags & EXT_CLIPBOARD_ACTION_CAPS, EXT_CLIPBOARD_ACTION_CAPS);
+        assert_eq!(&buf[12..], &1024_u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_parse_ext_clipboard() {
+        // Provide: the null terminated UTF-8 text with the lines ending with
+        // "\r\n".
+        let text = "a\r\n\u{4e2d}\u{6587}\0".as_bytes();
+        let msg = provide_msg(text.len(), text);
+        assert!(msg.len() <= ext_clipboard_msg_max(16));
+        assert_eq!(
+            parse_ext_clipboard(&msg, 16).unwrap(),
+            ExtClipboardMsg::Provide(Some("a\n\u{4e2d}\u{6587}".to_string()))
+        );
+        // The text over the limit is dropped.
+        assert_eq!(
+            parse_ext_clipboard(&msg, 4).unwrap(),
+            ExtClipboardMsg::Provide(None)
+        );
+        // The text size beyond the stream.
+        let msg = provide_msg(100, b"abc\0");
+        assert_eq!(
+            parse_ext_clipboard(&msg, 1024).unwrap(),
+            ExtClipboardMsg::Provide(None)
+        );
+        // Corrupted zlib stream.
+        let mut msg = provide_msg(4, b"abc\0");
+        msg.truncate(8);
+        assert_eq!(
+            parse_ext_clipboard(&msg, 1024).unwrap(),
+            ExtClipboardMsg::Provide(None)
+        );
+
+        let flags = EXT_CLIPBOARD_ACTION_CAPS | EXT_CLIPBOARD_ACTION_REQUEST | 0x7;
+        let mut msg = flags.to_be_bytes().to_vec();
+        msg.append(&mut [0_u8; 12].to_vec());
+        assert_eq!(
+            parse_ext_clipboard(&msg, 1024).unwrap(),
+            ExtClipboardMsg::Caps(flags)
+        );
+        let flags = EXT_CLIPBOARD_ACTION_NOTIFY | EXT_CLIPBOARD_FORMAT_TEXT;
+        assert_eq!(
+            parse_ext_clipboard(&flags.to_be_bytes(), 1024).unwrap(),
+            ExtClipboardMsg::Notify(EXT_CLIPBOARD_FORMAT_TEXT)
+        );
+        let flags = EXT_CLIPBOARD_ACTION_PEEK;
+        assert_eq!(
+            parse_ext_clipboard(&flags.to_be_bytes(), 1024).unwrap(),
+            ExtClipboardMsg::Ignored
+        );
+        assert!(parse_ext_clipboard(&[0, 0], 1024).is_err());
+
+        assert_eq!(client_latin1_text(b"ab\ncd\xe9"), "ab\ncd\u{e9}");
+    }
+
+    #[test]
+    fn test_clipboard_throttle() {
+        let mut throttle = ClipboardThrottle::default();
+        let start = Instant::now();
+        assert_eq!(
+            throttle.update("a", start),
+            ClipboardAction::Send("a".to_string())
+        );
+
+        // The updates in the interval are merged into the latest one.
+        let now = start + Duration::from_millis(40);
+        assert_eq!(
+            throttle.update("b", now),
+            ClipboardAction::Delay(Duration::from_millis(60))
+        );
+        assert_eq!(throttle.update("c", now), ClipboardAction::Hold);
+        let now = start + CLIPBOARD_UPDATE_INTERVAL;
+        assert_eq!(throttle.take_pending(now), Some("c".to_string()));
+        assert_eq!(throttle.take_pending(now), None);
+
+        // The interval restarts from the delayed update.
+        assert!(matches!(
+            throttle.update("d", now + Duration::from_millis(50)),
+            ClipboardAction::Delay(_)
+        ));
+        throttle.take_pending(now + CLIPBOARD_UPDATE_INTERVAL);
+        let now = now + CLIPBOARD_UPDATE_INTERVAL * 2;
+        assert_eq!(
+            throttle.update("e", now),
+            ClipboardAction::Send("e".to_string())
+        );
+    }
+}
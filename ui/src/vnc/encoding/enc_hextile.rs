@@ -37,11 +37,13 @@ const SUBRECTS_COLOURED: u8 = 0x10;
 /// * `image` - pointer to the data need to be send.
 /// * `rect` - dirty area of image.
 /// * `client_dpm` - Output mode information of client display.
+/// * `tile_buf` - scratch buffer of client for the subrectangles of tile.
 /// * `buf` - send buffer.
 pub fn hextile_send_framebuffer_update(
     image: *mut pixman_image_t,
     rect: &Rectangle,
     client_dpm: &DisplayMode,
+    tile_buf: &mut Vec<u8>,
     buf: &mut Vec<u8>,
 ) -> i32 {
     let mut last_bg: Option<u32> = None;
@@ -58,6 +60,7 @@ pub fn hextile_send_framebuffer_update(
                 image,
                 &sub_rect,
                 client_dpm,
+                tile_buf,
                 buf,
                 &mut last_bg,
                 &mut last_fg,
@@ -74,6 +77,7 @@ pub fn hextile_send_framebuffer_update(
 /// * `image` - pointer to the data need to be send.
 /// * `sub_rect` - area of tile.
 /// * `client_dpm` - Output mode information of client display.
+/// * `tmp_buf` - scratch buffer for the subrectangles of tile.
 /// * `buf` - send buffer.
 /// * `last_bg` - background of last tile.
 /// * `last_fg` - foreground of last tile.
@@ -81,6 +85,7 @@ fn compress_each_tile<'a>(
     image: *mut pixman_image_t,
     sub_rect: &Rectangle,
     client_dpm: &DisplayMode,
+    tmp_buf: &mut Vec<u8>,
     buf: &mut Vec<u8>,
     last_bg: &'a mut Option<u32>,
     last_fg: &'a mut Option<u32>,
@@ -95,7 +100,7 @@ fn compress_each_tile<'a>(
     let mut fg: u32 = 0; // Pixel value of foreground.
     let n_colors = pixel_statistical(data_ptr, stride, sub_rect, &mut bg, &mut fg);
     let mut n_subtiles = 0; // Number of subrectangle.
-    let mut tmp_buf: Vec<u8> = Vec::new();
+    tmp_buf.clear();
 
     if last_bg.is_none() || Some(bg) != *last_bg {
         flag |= BACKGROUND_SPECIFIC;
@@ -109,33 +114,37 @@ fn compress_each_tile<'a>(
     match n_colors {
         2 => {
             flag |= ANY_SUBRECTS;
-            n_subtiles =
-                subrectangle_of_foreground(sub_rect, data_ptr, bg, fg, stride, &mut tmp_buf);
+            n_subtiles = subrectangle_of_foreground(sub_rect, data_ptr, bg, fg, stride, tmp_buf);
         }
         3 => {
             flag |= ANY_SUBRECTS | SUBRECTS_COLOURED;
             if last_bg.is_none() || Some(bg) != *last_bg {
                 flag |= BACKGROUND_SPECIFIC;
             }
-            n_subtiles = subrectangle_with_pixel_value(
-                sub_rect,
-                data_ptr,
-                bg,
-                stride,
-                client_dpm,
-                &mut tmp_buf,
-            );
-            //If the length becomes longer after compression, give up compression.
-            if tmp_buf.len() > (sub_rect.h * sub_rect.w * client_dpm.pf.pixel_bytes as i32) as usize
-            {
-                flag = RAW;
-                *last_bg = None;
-            }
+            n_subtiles =
+                subrectangle_with_pixel_value(sub_rect, data_ptr, bg, stride, client_dpm, tmp_buf);
             *last_fg = None;
         }
         _ => {}
     }
 
+    // If the length becomes longer after compression, give up compression.
+    if flag & ANY_SUBRECTS != 0 {
+        let pixel_bytes = client_dpm.pf.pixel_bytes as usize;
+        let mut len = tmp_buf.len() + 1;
+        if flag & BACKGROUND_SPECIFIC != 0 {
+            len += pixel_bytes;
+        }
+        if flag & FOREGROUND_SPECIFIC != 0 {
+            len += pixel_bytes;
+        }
+        if len > (sub_rect.w * sub_rect.h) as usize * pixel_bytes {
+            flag = RAW;
+            *last_bg = None;
+            *last_fg = None;
+        }
+    }
+
     buf.append(&mut flag.to_be_bytes().to_vec()); // SubEncoding-mask.
     if flag & RAW == 0 {
         if flag & BACKGROUND_SPECIFIC != 0 {
@@ -156,7 +165,7 @@ fn compress_each_tile<'a>(
         }
         if n_subtiles != 0 {
             buf.append(&mut (n_subtiles as u8).to_be_bytes().to_vec()); // Num of SubRectangles.
-            buf.append(&mut tmp_buf); // SubrectsColoured.
+            buf.extend_from_slice(tmp_buf); // SubrectsColoured.
         }
     } else {
         // Send data directly without compression.
@@ -368,6 +377,7 @@ mod tests {
             },
         },
     };
+    use std::cmp;
     use util::pixman::pixman_format_code_t;
     fn color_init() -> PixelFormat {
         let mut pf = PixelFormat::default();
@@ -407,7 +417,7 @@ mod tests {
             w: image_width,
             h: image_height,
         };
-        hextile_send_framebuffer_update(image, &rect, &client_dpm, &mut buf);
+        hextile_send_framebuffer_update(image, &rect, &client_dpm, &mut Vec::new(), &mut buf);
         assert_eq!(buf, target_data);
     }
 
@@ -438,7 +448,7 @@ mod tests {
             w: image_width,
             h: image_height,
         };
-        hextile_send_framebuffer_update(image, &rect, &client_dpm, &mut buf);
+        hextile_send_framebuffer_update(image, &rect, &client_dpm, &mut Vec::new(), &mut buf);
         assert_eq!(buf, target_data);
     }
 
@@ -469,7 +479,126 @@ mod tests {
             w: image_width,
             h: image_height,
         };
-        hextile_send_framebuffer_update(image, &rect, &client_dpm, &mut buf);
+        hextile_send_framebuffer_update(image, &rect, &client_dpm, &mut Vec::new(), &mut buf);
         assert_eq!(buf, target_data);
     }
+    /// Minimal hextile decoder of client side for 32 bit pixels.
+    fn hextile_decode(buf: &[u8], rect: &Rectangle, fb: &mut [u32], fb_width: usize) -> usize {
+        let read_pixel = |pos: &mut usize| {
+            *pos += 4;
+            u32::from_le_bytes(buf[*pos - 4..*pos].try_into().unwrap())
+        };
+        let mut pos = 0;
+        let mut bg = 0;
+        let mut fg = 0;
+        for ty in (rect.y..rect.y + rect.h).step_by(16) {
+            for tx in (rect.x..rect.x + rect.w).step_by(16) {
+                let tw = cmp::min(16, rect.x + rect.w - tx) as usize;
+                let th = cmp::min(16, rect.y + rect.h - ty) as usize;
+                let (tx, ty) = (tx as usize, ty as usize);
+                let flag = buf[pos];
+                pos += 1;
+                if flag & 0x01 != 0 {
+                    for j in 0..th {
+                        for i in 0..tw {
+                            fb[(ty + j) * fb_width + tx + i] = read_pixel(&mut pos);
+                        }
+                    }
+                    continue;
+                }
+                if flag & 0x02 != 0 {
+                    bg = read_pixel(&mut pos);
+                }
+                if flag & 0x04 != 0 {
+                    fg = read_pixel(&mut pos);
+                }
+                for j in 0..th {
+                    for i in 0..tw {
+                        fb[(ty + j) * fb_width + tx + i] = bg;
+                    }
+                }
+                if flag & 0x08 == 0 {
+                    continue;
+                }
+                let n_subrects = buf[pos];
+                pos += 1;
+                for _ in 0..n_subrects {
+                    let color = if flag & 0x10 != 0 {
+                        read_pixel(&mut pos)
+                    } else {
+                        fg
+                    };
+                    let (x, y) = ((buf[pos] >> 4) as usize, (buf[pos] & 0x0f) as usize);
+                    let (w, h) = (
+                        (buf[pos + 1] >> 4) as usize + 1,
+                        (buf[pos + 1] & 0x0f) as usize + 1,
+                    );
+                    pos += 2;
+                    assert!(x + w <= tw && y + h <= th);
+                    for j in y..y + h {
+                        for i in x..x + w {
+                            fb[(ty + j) * fb_width + tx + i] = color;
+                        }
+                    }
+                }
+            }
+        }
+        pos
+    }
+
+    #[test]
+    fn test_hextile_edge_tiles() {
+        let client_dpm = DisplayMode::new(ENCODING_HEXTILE, false, false, color_init());
+        let image_width: i32 = 40;
+        let image_height: i32 = 40;
+        // Solid, two colors, multiple colors and noise in different areas.
+        let mut image_data: Vec<u32> = (0..image_width * image_height)
+            .map(|p| {
+                let (x, y) = (p % image_width, p / image_width);
+                match (x / 10, y / 10) {
+                    (0, _) => 0x0011_2233,
+                    (1, _) => {
+                        if (x + y) % 3 == 0 {
+                            0x00ff_ffff
+                        } else {
+                            0x0000_0000
+                        }
+                    }
+                    (2, _) => 0x0000_00ff << (8 * (y % 3)),
+                    _ => (p as u32).wrapping_mul(0x9e37_79b9) & 0x00ff_ffff,
+                }
+            })
+            .collect();
+        let image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            image_width,
+            image_height,
+            image_data.as_mut_ptr(),
+            image_width * 4,
+        );
+
+        // The tiles at the right and bottom edges are narrower than 16 pixels.
+        let mut tile_buf = Vec::new();
+        for rect in [
+            Rectangle::new(0, 0, 37, 21),
+            Rectangle::new(3, 5, 35, 35),
+            Rectangle::new(30, 30, 7, 9),
+        ] {
+            let mut buf = Vec::new();
+            let n =
+                hextile_send_framebuffer_update(image, &rect, &client_dpm, &mut tile_buf, &mut buf);
+            assert_eq!(n, 1);
+            let mut fb = vec![0_u32; (image_width * image_height) as usize];
+            assert_eq!(
+                hextile_decode(&buf, &rect, &mut fb, image_width as usize),
+                buf.len()
+            );
+            for y in rect.y..rect.y + rect.h {
+                for x in rect.x..rect.x + rect.w {
+                    let index = (y * image_width + x) as usize;
+                    assert_eq!(fb[index], image_data[index]);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,559 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use crate::{
+    pixman::bytes_per_pixel,
+    vnc::{
+        client_io::{DisplayMode, Rectangle, ENCODING_TIGHT},
+        encoding::{enc_zlib::ZlibStream, read_pixels},
+        framebuffer_update, write_pixel,
+    },
+};
+use anyhow::Result;
+use std::cmp;
+use util::pixman::pixman_image_t;
+
+/// Number of zlib streams of tight encoding.
+pub const TIGHT_STREAM_NUM: usize = 4;
+/// Zlib stream for the pixels filtered by copy filter.
+const TIGHT_STREAM_FULL: usize = 0;
+/// Zlib stream for the pixels of two colors palette.
+const TIGHT_STREAM_MONO: usize = 1;
+/// Zlib stream for the pixels of palette with more than two colors.
+const TIGHT_STREAM_INDEXED: usize = 2;
+/// Compression type of compression control byte, in the high four bits.
+const TIGHT_FILL: u8 = 0x08;
+/// Filter id is followed in basic compression.
+const TIGHT_EXPLICIT_FILTER: u8 = 0x04;
+/// Filter id of basic compression.
+const TIGHT_FILTER_PALETTE: u8 = 0x01;
+/// Data smaller than this is sent without compression.
+const TIGHT_MIN_TO_COMPRESS: usize = 12;
+/// Max colors of palette.
+const TIGHT_MAX_PALETTE_COLORS: usize = 16;
+/// Max width of rectangle.
+const TIGHT_MAX_RECT_WIDTH: i32 = 2048;
+/// Max pixels of rectangle.
+const TIGHT_MAX_RECT_SIZE: i32 = 65536;
+
+/// Zlib streams of tight encoding for a vnc client. Each stream is kept
+/// for the lifetime of the connection, and is selected by the compression
+/// control byte of each rectangle.
+pub struct TightStreams {
+    streams: Vec<ZlibStream>,
+}
+
+impl Default for TightStreams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TightStreams {
+    pub fn new() -> Self {
+        TightStreams {
+            streams: (0..TIGHT_STREAM_NUM).map(|_| ZlibStream::new()).collect(),
+        }
+    }
+
+    /// Set the compression level of all the streams.
+    fn set_level(&mut self, level: Option<u8>) {
+        for stream in self.streams.iter_mut() {
+            stream.set_level(level);
+        }
+    }
+}
+
+/// Compress data by tight algorithm before sending. The rectangle is split
+/// into subrectangles with the limited size, and each subrectangle is sent
+/// with its own header.
+///
+/// # Arguments
+///
+/// * `image` - pointer to the data need to be send.
+/// * `rect` - dirty area of image.
+/// * `client_dpm` - Output mode information of client display.
+/// * `streams` - tight zlib streams of client.
+/// * `buf` - send buffer.
+pub fn tight_send_framebuffer_update(
+    image: *mut pixman_image_t,
+    rect: &Rectangle,
+    client_dpm: &DisplayMode,
+    streams: &mut TightStreams,
+    buf: &mut Vec<u8>,
+) -> Result<i32> {
+    streams.set_level(client_dpm.compression);
+    let max_w = cmp::min(rect.w, TIGHT_MAX_RECT_WIDTH);
+    let max_h = cmp::max(TIGHT_MAX_RECT_SIZE / cmp::max(max_w, 1), 1);
+    let mut n_rects = 0;
+    for j in (0..rect.h).step_by(max_h as usize) {
+        for i in (0..rect.w).step_by(max_w as usize) {
+            let sub_rect = Rectangle::new(
+                rect.x + i,
+                rect.y + j,
+                cmp::min(max_w, rect.w - i),
+                cmp::min(max_h, rect.h - j),
+            );
+            framebuffer_update(
+                sub_rect.x,
+                sub_rect.y,
+                sub_rect.w,
+                sub_rect.h,
+                ENCODING_TIGHT,
+                buf,
+            );
+            compress_each_rect(image, &sub_rect, client_dpm, streams, buf)?;
+            n_rects += 1;
+        }
+    }
+    Ok(n_rects)
+}
+
+/// Compress the subrectangle by fill, palette or copy filter according to
+/// the number of colors.
+///
+/// # Arguments
+///
+/// * `image` - pointer to the data need to be send.
+/// * `sub_rect` - area of subrectangle.
+/// * `client_dpm` - Output mode information of client display.
+/// * `streams` - tight zlib streams of client.
+/// * `buf` - send buffer.
+fn compress_each_rect(
+    image: *mut pixman_image_t,
+    sub_rect: &Rectangle,
+    client_dpm: &DisplayMode,
+    streams: &mut TightStreams,
+    buf: &mut Vec<u8>,
+) -> Result<()> {
+    let pixels = read_pixels(image, sub_rect);
+    let palette = get_palette(&pixels);
+
+    if palette.len() == 1 {
+        buf.push(TIGHT_FILL << 4);
+        write_tight_pixel(palette[0], client_dpm, buf);
+        return Ok(());
+    }
+
+    let mut data = Vec::new();
+    let stream_id = match palette.len() {
+        2 => {
+            // One bit per pixel, each row is padded to byte.
+            let row_bytes = (sub_rect.w as usize + 7) / 8;
+            for row in pixels.chunks(sub_rect.w as usize) {
+                let mut bits = vec![0_u8; row_bytes];
+                for (i, pixel) in row.iter().enumerate() {
+                    if *pixel == palette[1] {
+                        bits[i / 8] |= 0x80 >> (i % 8);
+                    }
+                }
+                data.append(&mut bits);
+            }
+            TIGHT_STREAM_MONO
+        }
+        3..=TIGHT_MAX_PALETTE_COLORS => {
+            for pixel in pixels.iter() {
+                let index = palette.iter().position(|color| color == pixel).unwrap();
+                data.push(index as u8);
+            }
+            TIGHT_STREAM_INDEXED
+        }
+        _ => {
+            for pixel in pixels.iter() {
+                write_tight_pixel(*pixel, client_dpm, &mut data);
+            }
+            TIGHT_STREAM_FULL
+        }
+    };
+
+    if stream_id == TIGHT_STREAM_FULL {
+        // Copy filter is used by default.
+        buf.push((stream_id as u8) << 4);
+    } else {
+        buf.push((stream_id as u8 | TIGHT_EXPLICIT_FILTER) << 4);
+        buf.push(TIGHT_FILTER_PALETTE);
+        buf.push((palette.len() - 1) as u8);
+        for color in palette.iter() {
+            write_tight_pixel(*color, client_dpm, buf);
+        }
+    }
+    send_compressed_data(&mut streams.streams[stream_id], &data, buf)
+}
+
+/// Get the colors of pixels, return all the colors in the order of first
+/// appearance if the number does not exceed the palette limit. Otherwise,
+/// the returned palette is longer than the limit.
+fn get_palette(pixels: &[u32]) -> Vec<u32> {
+    let mut palette: Vec<u32> = Vec::new();
+    for pixel in pixels.iter() {
+        if !palette.contains(pixel) {
+            palette.push(*pixel);
+            if palette.len() > TIGHT_MAX_PALETTE_COLORS {
+                break;
+            }
+        }
+    }
+    palette
+}
+
+/// Tight sends pixel of 24 bit color depth in three bytes, which is in the
+/// order of red, green and blue.
+fn is_tpixel(client_dpm: &DisplayMode) -> bool {
+    client_dpm.pf.pixel_bits == 32
+        && client_dpm.pf.depth == 24
+        && client_dpm.pf.red.max == 0xff
+        && client_dpm.pf.green.max == 0xff
+        && client_dpm.pf.blue.max == 0xff
+}
+
+/// Write pixel in tight format.
+///
+/// # Arguments
+///
+/// * `color` - the pixel value of image.
+/// * `client_dpm` - Output mode information of client display.
+/// * `buf` - send buffer.
+fn write_tight_pixel(color: u32, client_dpm: &DisplayMode, buf: &mut Vec<u8>) {
+    if is_tpixel(client_dpm) {
+        buf.push((color >> 16) as u8);
+        buf.push((color >> 8) as u8);
+        buf.push(color as u8);
+    } else {
+        write_pixel(
+            color.to_ne_bytes().as_ptr() as *mut u8,
+            bytes_per_pixel(),
+            client_dpm,
+            buf,
+        );
+    }
+}
+
+/// Write the length of compressed data in compact representation, which
+/// takes one to three bytes, and each byte holds seven bits except the last
+/// one. The highest bit is set if there is a following byte.
+fn write_compact_len(len: usize, buf: &mut Vec<u8>) {
+    if len < 0x80 {
+        buf.push(len as u8);
+    } else if len < 0x4000 {
+        buf.push((len & 0x7f) as u8 | 0x80);
+        buf.push((len >> 7) as u8);
+    } else {
+        buf.push((len & 0x7f) as u8 | 0x80);
+        buf.push(((len >> 7) & 0x7f) as u8 | 0x80);
+        buf.push((len >> 14) as u8);
+    }
+}
+
+/// Send data with zlib stream, the small data is sent directly.
+///
+/// # Arguments
+///
+/// * `stream` - zlib stream selected in compression control byte.
+/// * `data` - data to be sent.
+/// * `buf` - send buffer.
+fn send_compressed_data(stream: &mut ZlibStream, data: &[u8], buf: &mut Vec<u8>) -> Result<()> {
+    if data.len() < TIGHT_MIN_TO_COMPRESS {
+        buf.extend_from_slice(data);
+        return Ok(());
+    }
+    let mut compressed = stream.compress(data)?;
+    write_compact_len(compressed.len(), buf);
+    buf.append(&mut compressed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixman::{create_pixman_image, PixelFormat};
+    use miniz_oxide::{
+        inflate::stream::{inflate, InflateState},
+        DataFormat, MZFlush,
+    };
+    use util::pixman::pixman_format_code_t;
+
+    fn color_init() -> PixelFormat {
+        let mut pf = PixelFormat::default();
+        pf.red.set_color_info(16, 255);
+        pf.green.set_color_info(8, 255);
+        pf.blue.set_color_info(0, 255);
+        pf.pixel_bits = 32;
+        pf.pixel_bytes = 4;
+        pf.depth = 24;
+        pf
+    }
+
+    /// Minimal tight decoder of client side, which supports fill and basic
+    /// compression with copy and palette filter, for 24 bit color depth.
+    struct TightDecoder {
+        streams: Vec<Box<InflateState>>,
+        /// The decoded rectangles in the order of receiving.
+        rects: Vec<(Rectangle, u8)>,
+    }
+
+    impl TightDecoder {
+        fn new() -> Self {
+            TightDecoder {
+                streams: (0..4)
+                    .map(|_| InflateState::new_boxed(DataFormat::Zlib))
+                    .collect(),
+                rects: Vec::new(),
+            }
+        }
+
+        fn read_u8(buf: &[u8], pos: &mut usize) -> u8 {
+            *pos += 1;
+            buf[*pos - 1]
+        }
+
+        fn read_u16(buf: &[u8], pos: &mut usize) -> u16 {
+            *pos += 2;
+            u16::from_be_bytes([buf[*pos - 2], buf[*pos - 1]])
+        }
+
+        fn read_tpixel(buf: &[u8], pos: &mut usize) -> u32 {
+            *pos += 3;
+            (buf[*pos - 3] as u32) << 16 | (buf[*pos - 2] as u32) << 8 | buf[*pos - 1] as u32
+        }
+
+        fn read_compact_len(buf: &[u8], pos: &mut usize) -> usize {
+            let mut len = 0;
+            for i in 0..3 {
+                let b = Self::read_u8(buf, pos) as usize;
+                if i == 2 {
+                    return len | b << 14;
+                }
+                len |= (b & 0x7f) << (7 * i);
+                if b & 0x80 == 0 {
+                    break;
+                }
+            }
+            len
+        }
+
+        fn read_data(
+            &mut self,
+            buf: &[u8],
+            pos: &mut usize,
+            stream: usize,
+            size: usize,
+        ) -> Vec<u8> {
+            if size < 12 {
+                *pos += size;
+                return buf[*pos - size..*pos].to_vec();
+            }
+            let len = Self::read_compact_len(buf, pos);
+            let mut output = vec![0_u8; size];
+            let res = inflate(
+                &mut self.streams[stream],
+                &buf[*pos..*pos + len],
+                &mut output,
+                MZFlush::Sync,
+            );
+            assert!(res.status.is_ok());
+            assert_eq!(res.bytes_consumed, len);
+            assert_eq!(res.bytes_written, size);
+            *pos += len;
+            output
+        }
+
+        /// Decode the rectangles into framebuffer, return the end of data.
+        fn decode(&mut self, buf: &[u8], n_rects: usize, fb: &mut [u32], fb_width: usize) -> usize {
+            let mut pos = 0;
+            for _ in 0..n_rects {
+                let x = Self::read_u16(buf, &mut pos) as usize;
+                let y = Self::read_u16(buf, &mut pos) as usize;
+                let w = Self::read_u16(buf, &mut pos) as usize;
+                let h = Self::read_u16(buf, &mut pos) as usize;
+                pos += 4;
+                assert_eq!(
+                    i32::from_be_bytes(buf[pos - 4..pos].try_into().unwrap()),
+                    ENCODING_TIGHT
+                );
+                let ctl = Self::read_u8(buf, &mut pos);
+                for i in 0..4 {
+                    if ctl & (1 << i) != 0 {
+                        self.streams[i] = InflateState::new_boxed(DataFormat::Zlib);
+                    }
+                }
+                let comp = ctl >> 4;
+                let mut pixels = Vec::new();
+                if comp == 0x08 {
+                    let color = Self::read_tpixel(buf, &mut pos);
+                    pixels = vec![color; w * h];
+                } else {
+                    assert!(comp < 0x08);
+                    let stream = (comp & 0x03) as usize;
+                    let filter = if comp & 0x04 != 0 {
+                        Self::read_u8(buf, &mut pos)
+                    } else {
+                        0
+                    };
+                    match filter {
+                        0 => {
+                            let data = self.read_data(buf, &mut pos, stream, w * h * 3);
+                            let mut data_pos = 0;
+                            for _ in 0..w * h {
+                                pixels.push(Self::read_tpixel(&data, &mut data_pos));
+                            }
+                        }
+                        1 => {
+                            let n_colors = Self::read_u8(buf, &mut pos) as usize + 1;
+                            let palette: Vec<u32> = (0..n_colors)
+                                .map(|_| Self::read_tpixel(buf, &mut pos))
+                                .collect();
+                            if n_colors == 2 {
+                                let row_bytes = (w + 7) / 8;
+                                let data = self.read_data(buf, &mut pos, stream, row_bytes * h);
+                                for j in 0..h {
+                                    for i in 0..w {
+                                        let bit = (data[j * row_bytes + i / 8] >> (7 - i % 8)) & 1;
+                                        pixels.push(palette[bit as usize]);
+                                    }
+                                }
+                            } else {
+                                let data = self.read_data(buf, &mut pos, stream, w * h);
+                                pixels = data.iter().map(|i| palette[*i as usize]).collect();
+                            }
+                        }
+                        _ => panic!("Unsupported filter {}", filter),
+                    }
+                }
+                for j in 0..h {
+                    fb[(y + j) * fb_width + x..(y + j) * fb_width + x + w]
+                        .copy_from_slice(&pixels[j * w..(j + 1) * w]);
+                }
+                self.rects
+                    .push((Rectangle::new(x as i32, y as i32, w as i32, h as i32), ctl));
+            }
+            pos
+        }
+    }
+
+    fn check_tight_update(
+        decoder: &mut TightDecoder,
+        streams: &mut TightStreams,
+        image_data: &mut Vec<u32>,
+        width: i32,
+        rect: &Rectangle,
+    ) -> Vec<(Rectangle, u8)> {
+        let client_dpm = DisplayMode::new(ENCODING_TIGHT, false, false, color_init());
+        let height = image_data.len() as i32 / width;
+        let image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            width,
+            height,
+            image_data.as_mut_ptr(),
+            width * 4,
+        );
+        let mut buf = Vec::new();
+        let n = tight_send_framebuffer_update(image, rect, &client_dpm, streams, &mut buf).unwrap();
+
+        let mut fb = vec![0_u32; image_data.len()];
+        decoder.rects.clear();
+        assert_eq!(
+            decoder.decode(&buf, n as usize, &mut fb, width as usize),
+            buf.len()
+        );
+        for j in 0..height {
+            for i in 0..width {
+                let index = (j * width + i) as usize;
+                if i >= rect.x && i < rect.x + rect.w && j >= rect.y && j < rect.y + rect.h {
+                    assert_eq!(fb[index], image_data[index] & 0x00ff_ffff);
+                } else {
+                    assert_eq!(fb[index], 0);
+                }
+            }
+        }
+        decoder.rects.clone()
+    }
+
+    #[test]
+    fn test_tight_fill_and_palette() {
+        let mut decoder = TightDecoder::new();
+        let mut streams = TightStreams::new();
+        let width = 64;
+        let rect = Rectangle::new(8, 4, 40, 20);
+
+        // Solid color.
+        let mut image_data = vec![0x0012_3456_u32; 64 * 32];
+        let rects = check_tight_update(&mut decoder, &mut streams, &mut image_data, width, &rect);
+        assert_eq!(rects.len(), 1);
+        assert_eq!(rects[0].1, TIGHT_FILL << 4);
+
+        // Two colors.
+        let mut image_data: Vec<u32> = (0..64 * 32)
+            .map(|i| if i % 3 == 0 { 0x00ff_ffff } else { 0 })
+            .collect();
+        for _ in 0..2 {
+            let rects =
+                check_tight_update(&mut decoder, &mut streams, &mut image_data, width, &rect);
+            assert_eq!(rects[0].1, (TIGHT_EXPLICIT_FILTER | 1) << 4);
+        }
+
+        // Sixteen colors.
+        let mut image_data: Vec<u32> = (0..64 * 32).map(|i| (i % 16) * 0x0001_0203).collect();
+        for _ in 0..2 {
+            let rects =
+                check_tight_update(&mut decoder, &mut streams, &mut image_data, width, &rect);
+            assert_eq!(rects[0].1, (TIGHT_EXPLICIT_FILTER | 2) << 4);
+        }
+
+        // Small data is not compressed.
+        let rect = Rectangle::new(1, 1, 3, 1);
+        check_tight_update(&mut decoder, &mut streams, &mut image_data, width, &rect);
+    }
+
+    #[test]
+    fn test_tight_full_color() {
+        let mut decoder = TightDecoder::new();
+        let mut streams = TightStreams::new();
+
+        // Sequential updates share the streams, the highest byte is ignored.
+        for k in 1..4_u32 {
+            let width = 64;
+            let rect = Rectangle::new(0, 8, 64, 24);
+            let mut image_data: Vec<u32> = (0..64 * 32_u32)
+                .map(|i| i.wrapping_mul(0x0101_0101 * k))
+                .collect();
+            let rects =
+                check_tight_update(&mut decoder, &mut streams, &mut image_data, width, &rect);
+            assert_eq!(rects.len(), 1);
+            assert_eq!(rects[0].1, 0);
+        }
+
+        // The large rectangle is split.
+        let width = 2100;
+        let rect = Rectangle::new(10, 0, 2090, 40);
+        let mut image_data: Vec<u32> = (0..2100 * 40_u32).collect();
+        let rects = check_tight_update(&mut decoder, &mut streams, &mut image_data, width, &rect);
+        assert_eq!(rects.len(), 4);
+        assert_eq!(rects[0].0, Rectangle::new(10, 0, 2048, 32));
+        assert_eq!(rects[1].0, Rectangle::new(2058, 0, 42, 32));
+        assert_eq!(rects[2].0, Rectangle::new(10, 32, 2048, 8));
+        assert_eq!(rects[3].0, Rectangle::new(2058, 32, 42, 8));
+    }
+
+    #[test]
+    fn test_write_compact_len() {
+        let mut buf = Vec::new();
+        for len in [0, 0x7f, 0x80, 0x3fff, 0x4000, 0x3f_ffff] {
+            write_compact_len(len, &mut buf);
+        }
+        let mut pos = 0;
+        for len in [0, 0x7f, 0x80, 0x3fff, 0x4000, 0x3f_ffff] {
+            assert_eq!(TightDecoder::read_compact_len(&buf, &mut pos), len);
+        }
+        assert_eq!(pos, buf.len());
+        assert_eq!(buf[..5], [0x00, 0x7f, 0x80, 0x01, 0xff]);
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use crate::vnc::{
+    client_io::{DisplayMode, Rectangle},
+    raw_send_framebuffer_update,
+};
+use anyhow::{bail, Result};
+use miniz_oxide::{
+    deflate::{
+        core::{create_comp_flags_from_zip_params, CompressorOxide},
+        stream::deflate,
+    },
+    MZError, MZFlush,
+};
+use util::pixman::pixman_image_t;
+
+/// Compression level of zlib, which is used if client has no preference.
+const ZLIB_LEVEL: u8 = 6;
+/// Window bits of zlib, the positive value means the zlib header is used.
+const ZLIB_WINDOW_BITS: i32 = 15;
+/// Size of the output chunk for each compression step.
+const ZLIB_CHUNK_SIZE: usize = 4096;
+
+/// Zlib stream of a vnc client. RFB requires that a single stream is used
+/// for all the rectangles in the lifetime of the connection.
+pub struct ZlibStream {
+    compressor: Box<CompressorOxide>,
+    /// Current compression level.
+    level: u8,
+}
+
+impl Default for ZlibStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZlibStream {
+    pub fn new() -> Self {
+        let flags = create_comp_flags_from_zip_params(ZLIB_LEVEL as i32, ZLIB_WINDOW_BITS, 0);
+        ZlibStream {
+            compressor: Box::new(CompressorOxide::new(flags)),
+            level: ZLIB_LEVEL,
+        }
+    }
+
+    /// Set the compression level preferred by client, the default level is
+    /// used if there is no preference. The level can be changed in the middle
+    /// of the stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - compression level of client, 0..=9.
+    pub fn set_level(&mut self, level: Option<u8>) {
+        let level = level.unwrap_or(ZLIB_LEVEL);
+        if level != self.level {
+            self.compressor.set_compression_level_raw(level);
+            self.level = level;
+        }
+    }
+
+    /// Compress the data and sync flush the stream, so that the client can
+    /// decompress all the data of the rectangle.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - data to be compressed.
+    pub fn compress(&mut self, mut input: &[u8]) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut chunk = [0_u8; ZLIB_CHUNK_SIZE];
+        loop {
+            let res = deflate(&mut self.compressor, input, &mut chunk, MZFlush::Sync);
+            match res.status {
+                Ok(_) => {}
+                // No more data to flush.
+                Err(MZError::Buf) if input.is_empty() => break,
+                Err(e) => bail!("Failed to compress data by zlib: {:?}", e),
+            }
+            input = &input[res.bytes_consumed..];
+            output.extend_from_slice(&chunk[..res.bytes_written]);
+            if input.is_empty() && res.bytes_written < chunk.len() {
+                break;
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// Compress data by zlib before sending. The pixels of rectangle are
+/// compressed in the format of raw encoding, and then sent with the length.
+///
+/// # Arguments
+///
+/// * `image` - pointer to the data need to be send.
+/// * `rect` - dirty area of image.
+/// * `client_dpm` - Output mode information of client display.
+/// * `stream` - zlib stream of client.
+/// * `buf` - send buffer.
+pub fn zlib_send_framebuffer_update(
+    image: *mut pixman_image_t,
+    rect: &Rectangle,
+    client_dpm: &DisplayMode,
+    stream: &mut ZlibStream,
+    buf: &mut Vec<u8>,
+) -> Result<i32> {
+    let mut data = Vec::new();
+    raw_send_framebuffer_update(image, rect, client_dpm, &mut data);
+    stream.set_level(client_dpm.compression);
+    let mut compressed = stream.compress(&data)?;
+    buf.append(&mut (compressed.len() as u32).to_be_bytes().to_vec());
+    buf.append(&mut compressed);
+    Ok(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pixman::{create_pixman_image, PixelFormat},
+        vnc::client_io::ENCODING_ZLIB,
+    };
+    use miniz_oxide::{
+        inflate::stream::{inflate, InflateState},
+        DataFormat,
+    };
+    use util::pixman::pixman_format_code_t;
+
+    fn color_init() -> PixelFormat {
+        let mut pf = PixelFormat::default();
+        pf.red.set_color_info(16, 255);
+        pf.green.set_color_info(8, 255);
+        pf.blue.set_color_info(0, 255);
+        pf.pixel_bits = 32;
+        pf.pixel_bytes = 4;
+        pf.depth = 24;
+        pf
+    }
+
+    /// Decompress the zlib data of a rectangle with the stream of client.
+    fn decompress(state: &mut InflateState, buf: &[u8], size: usize) -> Vec<u8> {
+        let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        assert_eq!(buf.len(), len + 4);
+        let mut output = vec![0_u8; size];
+        let res = inflate(state, &buf[4..], &mut output, MZFlush::Sync);
+        assert!(res.status.is_ok());
+        assert_eq!(res.bytes_consumed, len);
+        assert_eq!(res.bytes_written, size);
+        output
+    }
+
+    #[test]
+    fn test_zlib_send_framebuffer_update() {
+        let client_dpm = DisplayMode::new(ENCODING_ZLIB, false, false, color_init());
+        let image_width: i32 = 64;
+        let image_height: i32 = 64;
+        let mut stream = ZlibStream::new();
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+
+        // Multiple sequential updates share the same stream.
+        for i in 0..4_u32 {
+            let mut image_data: Vec<u32> = (0..(image_width * image_height) as u32)
+                .map(|p| p.wrapping_mul(i + 1) & 0x00ff_ffff)
+                .collect();
+            let image = create_pixman_image(
+                pixman_format_code_t::PIXMAN_x8r8g8b8,
+                image_width,
+                image_height,
+                image_data.as_mut_ptr(),
+                image_width * 4,
+            );
+            let rect = Rectangle {
+                x: 8 * i as i32,
+                y: 4,
+                w: 32,
+                h: 16,
+            };
+            let mut raw = Vec::new();
+            raw_send_framebuffer_update(image, &rect, &client_dpm, &mut raw);
+
+            let mut buf = Vec::new();
+            let n = zlib_send_framebuffer_update(image, &rect, &client_dpm, &mut stream, &mut buf)
+                .unwrap();
+            assert_eq!(n, 1);
+            assert_eq!(decompress(&mut state, &buf, raw.len()), raw);
+        }
+    }
+
+    #[test]
+    fn test_zlib_stream_continuity() {
+        let mut stream = ZlibStream::new();
+        let data = vec![0x5a_u8; 0x10000];
+        let first = stream.compress(&data).unwrap();
+        let second = stream.compress(&data).unwrap();
+        // The second one refers to the history of the first one.
+        assert!(second.len() < first.len());
+
+        // A new stream can not decompress the second one.
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        let mut output = vec![0_u8; data.len()];
+        assert!(inflate(&mut state, &second, &mut output, MZFlush::Sync)
+            .status
+            .is_err());
+
+        // Empty data.
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        let empty = stream.compress(&[]).unwrap();
+        let mut full = first.clone();
+        full.extend_from_slice(&second);
+        full.extend_from_slice(&empty);
+        let mut output = vec![0_u8; data.len() * 2];
+        let res = inflate(&mut state, &full, &mut output, MZFlush::Sync);
+        assert!(res.status.is_ok());
+        assert_eq!(res.bytes_written, data.len() * 2);
+    }
+
+    #[test]
+    fn test_zlib_stream_level() {
+        // Data with repeated but not uniform patterns.
+        let data: Vec<u8> = (0..0x10000_u32)
+            .map(|i| (i % 251 ^ (i / 509) % 7) as u8)
+            .collect();
+        let compress = |level: Option<u8>| {
+            let mut stream = ZlibStream::new();
+            stream.set_level(level);
+            stream.compress(&data).unwrap().len()
+        };
+        let stored = compress(Some(0));
+        let fast = compress(Some(1));
+        let best = compress(Some(9));
+        assert!(stored > data.len());
+        assert!(fast < stored);
+        assert!(best <= fast);
+        assert_eq!(compress(None), compress(Some(ZLIB_LEVEL)));
+
+        // The level is changed in the middle of the stream.
+        let mut stream = ZlibStream::new();
+        let mut state = InflateState::new_boxed(DataFormat::Zlib);
+        let mut full = Vec::new();
+        for level in [Some(9), Some(0), None] {
+            stream.set_level(level);
+            full.extend_from_slice(&stream.compress(&data).unwrap());
+        }
+        let mut output = vec![0_u8; data.len() * 3];
+        let res = inflate(&mut state, &full, &mut output, MZFlush::Sync);
+        assert!(res.status.is_ok());
+        assert_eq!(res.bytes_written, data.len() * 3);
+        assert_eq!(output[data.len() * 2..], data[..]);
+    }
+}
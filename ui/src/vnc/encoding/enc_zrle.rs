@@ -0,0 +1,524 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use crate::{
+    pixman::bytes_per_pixel,
+    vnc::{
+        client_io::{DisplayMode, Rectangle},
+        encoding::{enc_zlib::ZlibStream, read_pixels},
+        write_pixel,
+    },
+};
+use anyhow::Result;
+use std::{cmp, collections::HashMap};
+use util::pixman::pixman_image_t;
+
+/// Size of tile.
+const ZRLE_TILE_SIZE: i32 = 64;
+/// Subencoding type of tile.
+const ZRLE_RAW: u8 = 0;
+const ZRLE_SOLID: u8 = 1;
+const ZRLE_PLAIN_RLE: u8 = 128;
+/// Subencoding type of palette RLE is the palette size plus this value.
+const ZRLE_PALETTE_RLE: u8 = 128;
+/// Max palette size of packed palette.
+const ZRLE_MAX_PACKED_PALETTE: usize = 16;
+/// Max palette size of palette RLE.
+const ZRLE_MAX_RLE_PALETTE: usize = 127;
+
+/// Compressed pixel of ZRLE. The pixel of 32 bits is sent in three bytes
+/// if all the color bits fit in the least or most significant three bytes.
+struct CPixel {
+    /// Index of the unused byte in pixel, which is not sent.
+    skip_byte: Option<usize>,
+}
+
+impl CPixel {
+    fn new(client_dpm: &DisplayMode) -> Self {
+        let pf = &client_dpm.pf;
+        let mask = pf.red.mask | pf.green.mask | pf.blue.mask;
+        let skip_byte = if pf.pixel_bits != 32 || pf.depth > 24 {
+            None
+        } else if mask & 0xff00_0000 == 0 {
+            Some(if client_dpm.client_be { 0 } else { 3 })
+        } else if mask & 0x0000_00ff == 0 {
+            Some(if client_dpm.client_be { 3 } else { 0 })
+        } else {
+            None
+        };
+        CPixel { skip_byte }
+    }
+
+    fn size(&self, client_dpm: &DisplayMode) -> usize {
+        match self.skip_byte {
+            Some(_) => 3,
+            None => client_dpm.pf.pixel_bytes as usize,
+        }
+    }
+
+    fn write(&self, color: u32, client_dpm: &DisplayMode, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        write_pixel(
+            color.to_ne_bytes().as_ptr() as *mut u8,
+            bytes_per_pixel(),
+            client_dpm,
+            buf,
+        );
+        if let Some(index) = self.skip_byte {
+            buf.remove(start + index);
+        }
+    }
+}
+
+/// Compress data by ZRLE algorithm before sending. The rectangle is split
+/// into 64 * 64 tiles, and all the tiles are compressed by the zlib stream
+/// of client, then sent with the length.
+///
+/// # Arguments
+///
+/// * `image` - pointer to the data need to be send.
+/// * `rect` - dirty area of image.
+/// * `client_dpm` - Output mode information of client display.
+/// * `stream` - ZRLE zlib stream of client.
+/// * `buf` - send buffer.
+pub fn zrle_send_framebuffer_update(
+    image: *mut pixman_image_t,
+    rect: &Rectangle,
+    client_dpm: &DisplayMode,
+    stream: &mut ZlibStream,
+    buf: &mut Vec<u8>,
+) -> Result<i32> {
+    let cpixel = CPixel::new(client_dpm);
+    let mut data = Vec::new();
+    for j in (0..rect.h).step_by(ZRLE_TILE_SIZE as usize) {
+        for i in (0..rect.w).step_by(ZRLE_TILE_SIZE as usize) {
+            let tile = Rectangle::new(
+                rect.x + i,
+                rect.y + j,
+                cmp::min(ZRLE_TILE_SIZE, rect.w - i),
+                cmp::min(ZRLE_TILE_SIZE, rect.h - j),
+            );
+            compress_each_tile(image, &tile, client_dpm, &cpixel, &mut data);
+        }
+    }
+    stream.set_level(client_dpm.compression);
+    let mut compressed = stream.compress(&data)?;
+    buf.append(&mut (compressed.len() as u32).to_be_bytes().to_vec());
+    buf.append(&mut compressed);
+    Ok(1)
+}
+
+/// Compress each tile by the subencoding with the smallest size.
+///
+/// # Arguments
+///
+/// * `image` - pointer to the data need to be send.
+/// * `tile` - area of tile.
+/// * `client_dpm` - Output mode information of client display.
+/// * `cpixel` - compressed pixel of client.
+/// * `buf` - uncompressed data of the rectangle.
+fn compress_each_tile(
+    image: *mut pixman_image_t,
+    tile: &Rectangle,
+    client_dpm: &DisplayMode,
+    cpixel: &CPixel,
+    buf: &mut Vec<u8>,
+) {
+    let pixels = read_pixels(image, tile);
+
+    // The runs of the same color, which may span rows, and the palette in
+    // the order of first appearance.
+    let mut runs: Vec<(u32, usize)> = Vec::new();
+    let mut palette: Vec<u32> = Vec::new();
+    let mut indexes: HashMap<u32, u8> = HashMap::new();
+    for pixel in pixels.iter() {
+        match runs.last_mut() {
+            Some((color, len)) if *color == *pixel => *len += 1,
+            _ => runs.push((*pixel, 1)),
+        }
+        if palette.len() <= ZRLE_MAX_RLE_PALETTE && !indexes.contains_key(pixel) {
+            indexes.insert(*pixel, palette.len() as u8);
+            palette.push(*pixel);
+        }
+    }
+
+    if palette.len() == 1 {
+        buf.push(ZRLE_SOLID);
+        cpixel.write(palette[0], client_dpm, buf);
+        return;
+    }
+
+    let cpixel_size = cpixel.size(client_dpm);
+    let raw_size = pixels.len() * cpixel_size;
+    let plain_rle_size: usize = runs
+        .iter()
+        .map(|(_, len)| cpixel_size + run_length_size(*len))
+        .sum();
+    let packed_size = match palette.len() {
+        2..=ZRLE_MAX_PACKED_PALETTE => {
+            let row_bytes = (tile.w as usize * packed_bits(palette.len()) + 7) / 8;
+            palette.len() * cpixel_size + row_bytes * tile.h as usize
+        }
+        _ => usize::MAX,
+    };
+    let palette_rle_size = if palette.len() <= ZRLE_MAX_RLE_PALETTE {
+        palette.len() * cpixel_size
+            + runs
+                .iter()
+                .map(|(_, len)| match len {
+                    1 => 1,
+                    _ => 1 + run_length_size(*len),
+                })
+                .sum::<usize>()
+    } else {
+        usize::MAX
+    };
+
+    let min_size = [raw_size, plain_rle_size, packed_size, palette_rle_size]
+        .into_iter()
+        .min()
+        .unwrap();
+    if min_size == packed_size {
+        buf.push(palette.len() as u8);
+        for color in palette.iter() {
+            cpixel.write(*color, client_dpm, buf);
+        }
+        let bits = packed_bits(palette.len());
+        for row in pixels.chunks(tile.w as usize) {
+            let mut byte: u8 = 0;
+            let mut n_bits = 0;
+            for pixel in row.iter() {
+                byte = byte << bits | indexes[pixel];
+                n_bits += bits;
+                if n_bits == 8 {
+                    buf.push(byte);
+                    byte = 0;
+                    n_bits = 0;
+                }
+            }
+            // Each row is padded to byte.
+            if n_bits != 0 {
+                buf.push(byte << (8 - n_bits));
+            }
+        }
+    } else if min_size == palette_rle_size {
+        buf.push(ZRLE_PALETTE_RLE + palette.len() as u8);
+        for color in palette.iter() {
+            cpixel.write(*color, client_dpm, buf);
+        }
+        for (color, len) in runs.iter() {
+            if *len == 1 {
+                buf.push(indexes[color]);
+            } else {
+                buf.push(indexes[color] | 0x80);
+                write_run_length(*len, buf);
+            }
+        }
+    } else if min_size == plain_rle_size {
+        buf.push(ZRLE_PLAIN_RLE);
+        for (color, len) in runs.iter() {
+            cpixel.write(*color, client_dpm, buf);
+            write_run_length(*len, buf);
+        }
+    } else {
+        buf.push(ZRLE_RAW);
+        for pixel in pixels.iter() {
+            cpixel.write(*pixel, client_dpm, buf);
+        }
+    }
+}
+
+/// Bits of each palette index in packed palette.
+fn packed_bits(palette_size: usize) -> usize {
+    match palette_size {
+        0..=2 => 1,
+        3..=4 => 2,
+        _ => 4,
+    }
+}
+
+/// Size of the run length, which is represented by bytes of 255 and
+/// followed by the remainder.
+fn run_length_size(len: usize) -> usize {
+    (len - 1) / 255 + 1
+}
+
+fn write_run_length(len: usize, buf: &mut Vec<u8>) {
+    let mut remainder = len - 1;
+    while remainder >= 255 {
+        buf.push(255);
+        remainder -= 255;
+    }
+    buf.push(remainder as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pixman::{create_pixman_image, PixelFormat},
+        vnc::client_io::ENCODING_ZRLE,
+    };
+    use miniz_oxide::{
+        inflate::stream::{inflate, InflateState},
+        DataFormat, MZFlush,
+    };
+    use util::pixman::pixman_format_code_t;
+
+    fn color_init() -> PixelFormat {
+        let mut pf = PixelFormat::default();
+        pf.red.set_color_info(16, 255);
+        pf.green.set_color_info(8, 255);
+        pf.blue.set_color_info(0, 255);
+        pf.pixel_bits = 32;
+        pf.pixel_bytes = 4;
+        pf.depth = 24;
+        pf
+    }
+
+    /// Minimal ZRLE decoder of client side for the pixel format above, in
+    /// which the cpixel is three bytes.
+    struct ZrleDecoder {
+        state: Box<InflateState>,
+        /// Subencoding types of the decoded tiles.
+        subencodings: Vec<u8>,
+    }
+
+    impl ZrleDecoder {
+        fn new() -> Self {
+            ZrleDecoder {
+                state: InflateState::new_boxed(DataFormat::Zlib),
+                subencodings: Vec::new(),
+            }
+        }
+
+        fn read_cpixel(data: &[u8], pos: &mut usize) -> u32 {
+            *pos += 3;
+            u32::from_le_bytes([data[*pos - 3], data[*pos - 2], data[*pos - 1], 0])
+        }
+
+        fn read_run_length(data: &[u8], pos: &mut usize) -> usize {
+            let mut len = 1;
+            loop {
+                let b = data[*pos];
+                *pos += 1;
+                len += b as usize;
+                if b != 255 {
+                    return len;
+                }
+            }
+        }
+
+        fn decode(&mut self, buf: &[u8], rect: &Rectangle, fb: &mut [u32], fb_width: usize) {
+            let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+            assert_eq!(buf.len(), len + 4);
+            let mut data = vec![0_u8; (rect.w * rect.h * 4) as usize + 0x10000];
+            let res = inflate(&mut self.state, &buf[4..], &mut data, MZFlush::Sync);
+            assert!(res.status.is_ok());
+            assert_eq!(res.bytes_consumed, len);
+            data.truncate(res.bytes_written);
+
+            let mut pos = 0;
+            for ty in (rect.y..rect.y + rect.h).step_by(64) {
+                for tx in (rect.x..rect.x + rect.w).step_by(64) {
+                    let tw = cmp::min(64, rect.x + rect.w - tx) as usize;
+                    let th = cmp::min(64, rect.y + rect.h - ty) as usize;
+                    let sub = data[pos];
+                    pos += 1;
+                    self.subencodings.push(sub);
+                    let mut pixels = Vec::new();
+                    match sub {
+                        0 => {
+                            for _ in 0..tw * th {
+                                pixels.push(Self::read_cpixel(&data, &mut pos));
+                            }
+                        }
+                        1 => pixels = vec![Self::read_cpixel(&data, &mut pos); tw * th],
+                        2..=16 => {
+                            let palette: Vec<u32> = (0..sub)
+                                .map(|_| Self::read_cpixel(&data, &mut pos))
+                                .collect();
+                            let bits = match sub {
+                                2 => 1,
+                                3..=4 => 2,
+                                _ => 4,
+                            };
+                            for _ in 0..th {
+                                let row_bytes = (tw * bits + 7) / 8;
+                                let row = &data[pos..pos + row_bytes];
+                                for i in 0..tw {
+                                    let bit = i * bits;
+                                    let index = (row[bit / 8] >> (8 - bits - bit % 8))
+                                        & ((1 << bits) - 1) as u8;
+                                    pixels.push(palette[index as usize]);
+                                }
+                                pos += row_bytes;
+                            }
+                        }
+                        128 => {
+                            while pixels.len() < tw * th {
+                                let color = Self::read_cpixel(&data, &mut pos);
+                                let len = Self::read_run_length(&data, &mut pos);
+                                pixels.append(&mut vec![color; len]);
+                            }
+                        }
+                        130..=255 => {
+                            let palette: Vec<u32> = (0..sub - 128)
+                                .map(|_| Self::read_cpixel(&data, &mut pos))
+                                .collect();
+                            while pixels.len() < tw * th {
+                                let index = data[pos];
+                                pos += 1;
+                                let len = if index & 0x80 != 0 {
+                                    Self::read_run_length(&data, &mut pos)
+                                } else {
+                                    1
+                                };
+                                let color = palette[(index & 0x7f) as usize];
+                                pixels.append(&mut vec![color; len]);
+                            }
+                        }
+                        _ => panic!("Invalid subencoding {}", sub),
+                    }
+                    assert_eq!(pixels.len(), tw * th);
+                    for j in 0..th {
+                        let start = (ty as usize + j) * fb_width + tx as usize;
+                        fb[start..start + tw].copy_from_slice(&pixels[j * tw..(j + 1) * tw]);
+                    }
+                }
+            }
+            assert_eq!(pos, data.len());
+        }
+    }
+
+    fn check_zrle_update(
+        decoder: &mut ZrleDecoder,
+        stream: &mut ZlibStream,
+        image_data: &mut [u32],
+        width: i32,
+        rect: &Rectangle,
+    ) {
+        let client_dpm = DisplayMode::new(ENCODING_ZRLE, false, false, color_init());
+        let height = image_data.len() as i32 / width;
+        let image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            width,
+            height,
+            image_data.as_mut_ptr(),
+            width * 4,
+        );
+        let mut buf = Vec::new();
+        let n = zrle_send_framebuffer_update(image, rect, &client_dpm, stream, &mut buf).unwrap();
+        assert_eq!(n, 1);
+
+        let mut fb = vec![0_u32; image_data.len()];
+        decoder.subencodings.clear();
+        decoder.decode(&buf, rect, &mut fb, width as usize);
+        for y in rect.y..rect.y + rect.h {
+            for x in rect.x..rect.x + rect.w {
+                let index = (y * width + x) as usize;
+                assert_eq!(fb[index], image_data[index] & 0x00ff_ffff);
+            }
+        }
+    }
+
+    #[test]
+    fn test_zrle_send_framebuffer_update() {
+        let width: i32 = 150;
+        let height: i32 = 70;
+        let mut image_data: Vec<u32> = (0..width * height)
+            .map(|p| {
+                let (x, y) = (p % width, p / width);
+                match (x / 64, y / 64) {
+                    // Solid tile.
+                    (0, 0) => 0x0033_6699,
+                    // Stripes of three colors.
+                    (1, 0) => [0x00ff_0000, 0x0000_ff00, 0x0000_00ff][(y as usize / 4) % 3],
+                    // Runs of different colors.
+                    (0, 1) => (((y - 64) * 64 + x) / 2) as u32 * 0x0001_0101,
+                    // Checkerboard of two colors.
+                    (1, 1) => 0x00ff_ffff * ((x + y) % 2) as u32,
+                    // Noise, the highest byte is ignored.
+                    _ => (p as u32).wrapping_mul(0x9e37_79b9),
+                }
+            })
+            .collect();
+        let mut decoder = ZrleDecoder::new();
+        let mut stream = ZlibStream::new();
+
+        // Full screen update.
+        let rect = Rectangle::new(0, 0, width, height);
+        check_zrle_update(&mut decoder, &mut stream, &mut image_data, width, &rect);
+        assert_eq!(
+            decoder.subencodings,
+            vec![
+                ZRLE_SOLID,
+                ZRLE_PALETTE_RLE + 3,
+                ZRLE_RAW,
+                ZRLE_PLAIN_RLE,
+                2,
+                ZRLE_RAW
+            ]
+        );
+
+        // Update of solid region with the same stream.
+        for y in 10..50 {
+            for x in 20..130 {
+                image_data[(y * width + x) as usize] = 0x0012_3456;
+            }
+        }
+        let rect = Rectangle::new(20, 10, 110, 40);
+        check_zrle_update(&mut decoder, &mut stream, &mut image_data, width, &rect);
+        assert_eq!(decoder.subencodings, vec![ZRLE_SOLID, ZRLE_SOLID]);
+
+        // The region across the border of solid color is sent by RLE.
+        let rect = Rectangle::new(0, 0, 64, 64);
+        check_zrle_update(&mut decoder, &mut stream, &mut image_data, width, &rect);
+        assert_eq!(decoder.subencodings, vec![ZRLE_PALETTE_RLE + 2]);
+    }
+
+    #[test]
+    fn test_zrle_cpixel() {
+        let mut client_dpm = DisplayMode::new(ENCODING_ZRLE, false, true, color_init());
+        let mut buf = Vec::new();
+        CPixel::new(&client_dpm).write(0x0011_2233, &client_dpm, &mut buf);
+        assert_eq!(buf, [0x33, 0x22, 0x11]);
+
+        // Big endian.
+        client_dpm.client_be = true;
+        buf.clear();
+        CPixel::new(&client_dpm).write(0x0011_2233, &client_dpm, &mut buf);
+        assert_eq!(buf, [0x11, 0x22, 0x33]);
+
+        // Color bits in the most significant three bytes.
+        client_dpm.client_be = false;
+        client_dpm.pf.red.set_color_info(24, 255);
+        client_dpm.pf.green.set_color_info(16, 255);
+        client_dpm.pf.blue.set_color_info(8, 255);
+        buf.clear();
+        CPixel::new(&client_dpm).write(0x0011_2233, &client_dpm, &mut buf);
+        assert_eq!(buf, [0x33, 0x22, 0x11]);
+
+        // The pixel of 16 bits is not compressed.
+        client_dpm.pf.red.set_color_info(11, 31);
+        client_dpm.pf.green.set_color_info(5, 63);
+        client_dpm.pf.blue.set_color_info(0, 31);
+        client_dpm.pf.pixel_bits = 16;
+        client_dpm.pf.pixel_bytes = 2;
+        client_dpm.pf.depth = 16;
+        let cpixel = CPixel::new(&client_dpm);
+        assert_eq!(cpixel.size(&client_dpm), 2);
+        buf.clear();
+        cpixel.write(0x00ff_ffff, &client_dpm, &mut buf);
+        assert_eq!(buf, [0xff, 0xff]);
+    }
+}
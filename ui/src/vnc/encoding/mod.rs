@@ -11,5 +11,37 @@
 // See the Mulan PSL v2 for more details.
 
 pub mod enc_hextile;
+pub mod enc_tight;
+pub mod enc_zlib;
+pub mod enc_zrle;
 #[cfg(test)]
 mod test_hextile_image_data;
+
+use crate::{
+    pixman::{bytes_per_pixel, get_image_data, get_image_stride},
+    vnc::client_io::Rectangle,
+};
+use util::pixman::pixman_image_t;
+
+/// Read the pixels of rectangle from image, the highest byte of pixel is
+/// ignored.
+///
+/// # Arguments
+///
+/// * `image` - pointer to the data need to be send.
+/// * `rect` - area of image.
+pub fn read_pixels(image: *mut pixman_image_t, rect: &Rectangle) -> Vec<u32> {
+    let stride = get_image_stride(image);
+    let data_ptr = (get_image_data(image) as usize
+        + (rect.y * stride) as usize
+        + rect.x as usize * bytes_per_pixel()) as *mut u8;
+    let mut pixels = Vec::with_capacity((rect.w * rect.h) as usize);
+    for j in 0..rect.h {
+        let ptr = (data_ptr as usize + (j * stride) as usize) as *mut u32;
+        for i in 0..rect.w {
+            // SAFETY: it can be ensure the raw pointer will not exceed the range.
+            pixels.push(unsafe { *ptr.add(i as usize) } & 0x00ff_ffff);
+        }
+    }
+    pixels
+}
@@ -12,9 +12,13 @@
 
 pub mod auth_sasl;
 pub mod auth_vencrypt;
+pub mod auth_vnc;
 pub mod client_io;
+pub mod clipboard;
 pub mod encoding;
 pub mod server_io;
+pub mod tile_hash;
+pub mod websocket;
 
 use crate::{
     console::{
@@ -22,24 +26,32 @@ use crate::{
         DisplayChangeListenerOperations, DisplayMouse, DisplaySurface,
         DISPLAY_UPDATE_INTERVAL_DEFAULT, DISPLAY_UPDATE_INTERVAL_INC, DISPLAY_UPDATE_INTERVAL_MAX,
     },
-    data::keycode::KEYSYM2KEYCODE,
     error::VncError,
+    input::{register_kbd_led_notifier, register_pointer_mode_notifier},
+    keymap::keysym2keycode_map,
     pixman::{
         bytes_per_pixel, create_pixman_image, get_image_data, get_image_height, get_image_stride,
         get_image_width, ref_pixman_image, unref_pixman_image,
     },
     vnc::{
+        auth_vnc::parse_expire_time,
         client_io::{
-            desktop_resize, display_cursor_define, get_rects, set_color_depth, vnc_flush,
-            vnc_update_output_throttle, vnc_write, DisplayMode, Rectangle, ServerMsg,
-            ENCODING_HEXTILE, ENCODING_RAW,
+            cursor_mask, desktop_resize, display_cursor_define, get_rects, set_color_depth,
+            vnc_flush, vnc_update_led_state, vnc_update_output_throttle, vnc_update_pointer_type,
+            vnc_write, ClientState, CopyRect, DisplayMode, Rectangle, ServerMsg, ENCODING_COPYRECT,
+            ENCODING_HEXTILE, ENCODING_RAW, ENCODING_TIGHT, ENCODING_ZLIB, ENCODING_ZRLE,
+        },
+        clipboard::vnc_clipboard_update,
+        encoding::{
+            enc_hextile::hextile_send_framebuffer_update, enc_tight::tight_send_framebuffer_update,
+            enc_zlib::zlib_send_framebuffer_update, enc_zrle::zrle_send_framebuffer_update,
         },
-        encoding::enc_hextile::hextile_send_framebuffer_update,
         server_io::{make_server_config, VncConnHandler, VncServer, VncSurface},
     },
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use core::time;
+use log::error;
 use machine_manager::{
     config::{ObjectConfig, VncConfig},
     event_loop::EventLoop,
@@ -48,7 +60,6 @@ use machine_manager::{
 use once_cell::sync::Lazy;
 use std::{
     cmp,
-    collections::HashMap,
     net::TcpListener,
     ptr,
     sync::{Arc, Mutex},
@@ -210,37 +221,10 @@ impl DisplayChangeListenerOperations for VncInterface {
             return Ok(());
         }
         let server = VNC_SERVERS.lock().unwrap()[0].clone();
-        let width = cursor.width as u64;
-        let height = cursor.height as u64;
-        let bpl = round_up_div(width, BIT_PER_BYTE as u64);
-        // Set the bit for mask.
-        let bit_mask: u8 = 0x80;
-
-        let mut mask: Vec<u8> = vec![0; (bpl * height) as usize];
-        let first_bit = if cfg!(target_endian = "big") {
-            0_usize
-        } else {
-            bytes_per_pixel() - 1
-        };
-
-        for j in 0..height {
-            let mut bit = bit_mask;
-            for i in 0..width {
-                let idx = ((i + j * width) as usize) * bytes_per_pixel() + first_bit;
-                if let Some(n) = cursor.data.get(idx) {
-                    if *n == 0xff {
-                        mask[(j * bpl + i / BIT_PER_BYTE as u64) as usize] |= bit;
-                    }
-                }
-                bit >>= 1;
-                if bit == 0 {
-                    bit = bit_mask;
-                }
-            }
-        }
+        let mask = cursor_mask(cursor);
 
         server.vnc_cursor.lock().unwrap().cursor = Some(cursor.clone());
-        server.vnc_cursor.lock().unwrap().mask = Some(mask.clone());
+        server.vnc_cursor.lock().unwrap().mask = Some(mask);
 
         let mut locked_handler = server.client_handlers.lock().unwrap();
         // Send the framebuff for each client.
@@ -252,6 +236,15 @@ impl DisplayChangeListenerOperations for VncInterface {
         }
         Ok(())
     }
+
+    fn dpy_clipboard_update(&self, text: &str) -> Result<()> {
+        if VNC_SERVERS.lock().unwrap().is_empty() {
+            return Ok(());
+        }
+        let server = VNC_SERVERS.lock().unwrap()[0].clone();
+        vnc_clipboard_update(&server, text);
+        Ok(())
+    }
 }
 
 /// Initizlization function of vnc
@@ -266,32 +259,31 @@ pub fn vnc_init(vnc: &Option<VncConfig>, object: &ObjectConfig) -> Result<()> {
     };
 
     let addr = format!("{}:{}", vnc_cfg.ip, vnc_cfg.port);
-    let listener: TcpListener = match TcpListener::bind(addr.as_str()) {
-        Ok(l) => l,
-        Err(e) => {
-            let msg = format!("Bind {} failed {}", addr, e);
-            return Err(anyhow!(VncError::TcpBindFailed(msg)));
-        }
+    let listener = vnc_listen(&addr)?;
+    // Websocket listener, noVNC is able to connect without proxy.
+    let ws_listener = if vnc_cfg.websocket.is_empty() {
+        None
+    } else {
+        Some(vnc_listen(&vnc_cfg.websocket)?)
     };
 
-    listener
-        .set_nonblocking(true)
-        .expect("Set noblocking for vnc socket failed");
-
-    let mut keysym2keycode: HashMap<u16, u16> = HashMap::new();
-    // Mapping ASCII to keycode.
-    for &(k, v) in KEYSYM2KEYCODE.iter() {
-        keysym2keycode.insert(k, v);
-    }
+    // Mapping keysym to keycode in the keymap of guest.
+    let keysym2keycode = keysym2keycode_map(&vnc_cfg.keymap)?;
 
     let vnc_opts = Arc::new(VncInterface::default());
     let dcl = Arc::new(Mutex::new(DisplayChangeListener::new(None, vnc_opts)));
 
-    let server = Arc::new(VncServer::new(
+    let mut server = VncServer::new(
         get_client_image(),
         keysym2keycode,
         Some(Arc::downgrade(&dcl)),
-    ));
+    );
+    server.handshake_timeout = time::Duration::from_secs(vnc_cfg.handshake_timeout);
+    server.auth_timeout = time::Duration::from_secs(vnc_cfg.auth_timeout);
+    server.max_fps = vnc_cfg.max_fps;
+    server.output_limit = vnc_cfg.output_limit as usize;
+    server.clipboard_limit = vnc_cfg.clipboard_limit as usize;
+    let server = Arc::new(server);
 
     // Parameter configuration for VncServeer.
     make_server_config(&server, vnc_cfg, object)?;
@@ -301,17 +293,43 @@ pub fn vnc_init(vnc: &Option<VncConfig>, object: &ObjectConfig) -> Result<()> {
 
     // Register in display console.
     register_display(&dcl)?;
+    register_kbd_led_notifier(Arc::new(vnc_kbd_led_changed));
+    register_pointer_mode_notifier(Arc::new(vnc_pointer_mode_changed));
 
     // Register the event to listen for client's connection.
-    let vnc_io = Arc::new(Mutex::new(VncConnHandler::new(listener, server)));
+    let vnc_io = Arc::new(Mutex::new(VncConnHandler::new(
+        listener,
+        server.clone(),
+        false,
+    )));
 
     // Vnc_thread: a thread to send the framebuffer
     start_vnc_thread()?;
 
     EventLoop::update_event(EventNotifierHelper::internal_notifiers(vnc_io), None)?;
+    if let Some(ws_listener) = ws_listener {
+        let ws_io = Arc::new(Mutex::new(VncConnHandler::new(ws_listener, server, true)));
+        EventLoop::update_event(EventNotifierHelper::internal_notifiers(ws_io), None)?;
+    }
     Ok(())
 }
 
+/// Listen for the connection of vnc client on the address.
+fn vnc_listen(addr: &str) -> Result<TcpListener> {
+    let listener: TcpListener = match TcpListener::bind(addr) {
+        Ok(l) => l,
+        Err(e) => {
+            let msg = format!("Bind {} failed {}", addr, e);
+            return Err(anyhow!(VncError::TcpBindFailed(msg)));
+        }
+    };
+
+    listener
+        .set_nonblocking(true)
+        .expect("Set noblocking for vnc socket failed");
+    Ok(listener)
+}
+
 fn start_vnc_thread() -> Result<()> {
     let interval = DEFAULT_REFRESH_INTERVAL;
     let server = VNC_SERVERS.lock().unwrap()[0].clone();
@@ -324,9 +342,13 @@ fn start_vnc_thread() -> Result<()> {
                 continue;
             }
 
-            let mut rect_info = match rect_jobs.lock().unwrap().get_mut(0) {
+            // Server image is not allowed to be copied until the job is sent,
+            // as the CopyRect is based on the image which is sent to client.
+            let locked_surface = server.vnc_surface.lock().unwrap();
+            let rect_info = match rect_jobs.lock().unwrap().get_mut(0) {
                 Some(rect) => rect.clone(),
                 None => {
+                    drop(locked_surface);
                     thread::sleep(time::Duration::from_millis(interval));
                     continue;
                 }
@@ -339,19 +361,40 @@ fn start_vnc_thread() -> Result<()> {
             buf.append(&mut (0_u8).to_be_bytes().to_vec());
             buf.append(&mut [0_u8; 2].to_vec());
 
-            for rect in rect_info.rects.iter_mut() {
-                let locked_surface = server.vnc_surface.lock().unwrap();
+            // The copied area must be sent before the dirty area.
+            let mut locked_hashes = rect_info.client.tile_hashes.lock().unwrap();
+            for copy in rect_info.copy_rects.iter() {
                 let dpm = rect_info.client.client_dpm.lock().unwrap().clone();
-                let width = dpm.client_width;
-                let height = dpm.client_height;
-                if check_rect(rect, width, height) {
-                    let n =
-                        send_framebuffer_update(locked_surface.server_image, rect, &dpm, &mut buf);
-                    if n >= 0 {
-                        num_rects += n;
-                    }
+                if check_copy_rect(copy, dpm.client_width, dpm.client_height) {
+                    num_rects += copy_rect_send_framebuffer_update(copy, &mut buf);
+                    locked_hashes.invalidate(&copy.rect);
+                }
+            }
+
+            let dpm = rect_info.client.client_dpm.lock().unwrap().clone();
+            let rects = rect_info
+                .rects
+                .into_iter()
+                .filter_map(|mut rect| {
+                    check_rect(&mut rect, dpm.client_width, dpm.client_height).then_some(rect)
+                })
+                .collect();
+            // Guest may redraw the area without changing it.
+            let rects = locked_hashes.filter_unchanged(locked_surface.server_image, rects);
+            drop(locked_hashes);
+            for rect in rects.iter() {
+                let n = send_framebuffer_update(
+                    locked_surface.server_image,
+                    rect,
+                    &dpm,
+                    &rect_info.client,
+                    &mut buf,
+                );
+                if n >= 0 {
+                    num_rects += n;
                 }
             }
+            drop(locked_surface);
             buf[2] = (num_rects >> 8) as u8;
             buf[3] = num_rects as u8;
 
@@ -362,6 +405,40 @@ fn start_vnc_thread() -> Result<()> {
     Ok(())
 }
 
+/// Sync the changed keyboard led state to all the clients.
+fn vnc_kbd_led_changed(kbd_led: u8) {
+    if VNC_SERVERS.lock().unwrap().is_empty() {
+        return;
+    }
+    let server = VNC_SERVERS.lock().unwrap()[0].clone();
+    let locked_handler = server.client_handlers.lock().unwrap();
+    for client in locked_handler.values() {
+        let mut buf: Vec<u8> = Vec::new();
+        vnc_update_led_state(client, kbd_led, &mut buf);
+        if !buf.is_empty() {
+            vnc_write(client, buf);
+            vnc_flush(client);
+        }
+    }
+}
+
+/// Sync the changed pointer mode to all the clients.
+fn vnc_pointer_mode_changed(absolute: bool) {
+    if VNC_SERVERS.lock().unwrap().is_empty() {
+        return;
+    }
+    let server = VNC_SERVERS.lock().unwrap()[0].clone();
+    let locked_handler = server.client_handlers.lock().unwrap();
+    for client in locked_handler.values() {
+        let mut buf: Vec<u8> = Vec::new();
+        vnc_update_pointer_type(client, absolute, &mut buf);
+        if !buf.is_empty() {
+            vnc_write(client, buf);
+            vnc_flush(client);
+        }
+    }
+}
+
 /// Add a vnc server during initialization.
 fn add_vnc_server(server: Arc<VncServer>) {
     VNC_SERVERS.lock().unwrap().push(server);
@@ -382,6 +459,12 @@ pub fn qmp_query_vnc() -> Option<VncInfo> {
     for client in locked_handler.values_mut() {
         let mut client_info = VncClientInfo {
             host: client.addr.clone(),
+            update_rate: client
+                .conn_state
+                .lock()
+                .unwrap()
+                .pacing
+                .rate(std::time::Instant::now()),
             ..Default::default()
         };
         client_info.family = "ipv4".to_string();
@@ -391,6 +474,56 @@ pub fn qmp_query_vnc() -> Option<VncInfo> {
     Some(vnc_info)
 }
 
+/// Qmp: reload the tls credentials of VNC server.
+pub fn qmp_reload_vnc_tls_creds() -> Result<()> {
+    let server = match VNC_SERVERS.lock().unwrap().first() {
+        Some(server) => server.clone(),
+        None => {
+            return Err(anyhow!(VncError::MakeTlsConnectionFailed(
+                "vnc server is not started".to_string()
+            )))
+        }
+    };
+    let result = server.security_type.borrow_mut().reload_tls_creds();
+    result
+}
+
+/// Qmp: set the password of VNC server. The clients already authenticated
+/// are not affected.
+pub fn qmp_set_vnc_password(password: &str) -> Result<()> {
+    let server = match VNC_SERVERS.lock().unwrap().first() {
+        Some(server) => server.clone(),
+        None => bail!("vnc server is not started"),
+    };
+    let mut security_type = server.security_type.borrow_mut();
+    let vncpass = security_type
+        .vncpass
+        .as_mut()
+        .with_context(|| "password auth of vnc server is not enabled")?;
+    vncpass.password = Some(password.to_string());
+    Ok(())
+}
+
+/// Qmp: set the expire time of VNC server password.
+///
+/// # Arguments
+///
+/// * `time` - "now", "never", "+N" seconds from now, or "N" seconds since the Epoch.
+pub fn qmp_expire_vnc_password(time: &str) -> Result<()> {
+    let expire = parse_expire_time(time)?;
+    let server = match VNC_SERVERS.lock().unwrap().first() {
+        Some(server) => server.clone(),
+        None => bail!("vnc server is not started"),
+    };
+    let mut security_type = server.security_type.borrow_mut();
+    let vncpass = security_type
+        .vncpass
+        .as_mut()
+        .with_context(|| "password auth of vnc server is not enabled")?;
+    vncpass.expire = expire;
+    Ok(())
+}
+
 /// Set dirty in bitmap.
 pub fn set_area_dirty(
     dirty: &mut Bitmap<u64>,
@@ -443,6 +576,12 @@ pub fn update_server_surface(server: &Arc<VncServer>) -> Result<()> {
     if server.client_handlers.lock().unwrap().is_empty() {
         return Ok(());
     }
+    // The whole image will be sent, the copied area and the tiles sent are
+    // useless.
+    for client in server.client_handlers.lock().unwrap().values() {
+        client.copy_rects.lock().unwrap().clear();
+        client.tile_hashes.lock().unwrap().reset();
+    }
 
     let g_width = get_image_width(locked_vnc_surface.guest_image);
     let g_height = get_image_height(locked_vnc_surface.guest_image);
@@ -506,6 +645,34 @@ fn check_rect(rect: &mut Rectangle, width: i32, height: i32) -> bool {
     true
 }
 
+/// Check if the copied area is in the range of client.
+fn check_copy_rect(copy: &CopyRect, width: i32, height: i32) -> bool {
+    let rect = &copy.rect;
+    rect.w > 0
+        && rect.h > 0
+        && rect.x >= 0
+        && rect.y >= 0
+        && copy.src_x >= 0
+        && copy.src_y >= 0
+        && cmp::max(rect.x, copy.src_x) + rect.w <= width
+        && cmp::max(rect.y, copy.src_y) + rect.h <= height
+}
+
+/// Send the copied area to client by CopyRect encoding, in which only the
+/// position of source area is sent. Return the number of rectangles.
+///
+/// # Arguments
+///
+/// * `copy` - the copied area.
+/// * `buf` - send buffer.
+pub fn copy_rect_send_framebuffer_update(copy: &CopyRect, buf: &mut Vec<u8>) -> i32 {
+    let rect = &copy.rect;
+    framebuffer_update(rect.x, rect.y, rect.w, rect.h, ENCODING_COPYRECT, buf);
+    buf.append(&mut (copy.src_x as u16).to_be_bytes().to_vec());
+    buf.append(&mut (copy.src_y as u16).to_be_bytes().to_vec());
+    1
+}
+
 /// Send updated pixel information to client
 ///
 /// # Arguments
@@ -520,7 +687,8 @@ pub fn framebuffer_update(x: i32, y: i32, w: i32, h: i32, encoding: i32, buf: &m
     buf.append(&mut encoding.to_be_bytes().to_vec());
 }
 
-/// Write pixel to client.
+/// Write pixel to client. The pixels are converted to the pixel format of
+/// client if needed, and the send buffer is reserved for all the pixels.
 ///
 /// # Arguments
 ///
@@ -535,24 +703,22 @@ pub fn write_pixel(
     buf: &mut Vec<u8>,
 ) {
     if !client_dpm.convert {
-        let mut con = vec![0; copy_bytes];
         // SAFETY: it can be ensure the raw pointer will not exceed the range.
-        unsafe {
-            ptr::copy(data_ptr as *mut u8, con.as_mut_ptr(), copy_bytes);
-        }
-        buf.append(&mut con);
-    } else if client_dpm.convert && bytes_per_pixel() == 4 {
+        let data = unsafe { std::slice::from_raw_parts(data_ptr as *const u8, copy_bytes) };
+        buf.extend_from_slice(data);
+    } else if bytes_per_pixel() == 4 {
         let num = copy_bytes >> 2;
-        let ptr = data_ptr as *mut u32;
+        buf.reserve(num * client_dpm.pf.pixel_bytes as usize);
+        let ptr = data_ptr as *const u32;
         for i in 0..num {
             // SAFETY: it can be ensure the raw pointer will not exceed the range.
-            let color = unsafe { *ptr.add(i) };
+            let color = unsafe { ptr.add(i).read_unaligned() };
             convert_pixel(client_dpm, buf, color);
         }
     }
 }
 
-/// Convert the sent information to a format supported  
+/// Convert the sent information to a format supported
 /// by the client depend on byte arrangement
 ///
 /// # Arguments
@@ -561,42 +727,18 @@ pub fn write_pixel(
 /// * `buf` - send buffer.
 /// * `color` - the pixel value need to be convert.
 pub fn convert_pixel(client_dpm: &DisplayMode, buf: &mut Vec<u8>, color: u32) {
-    let mut ret = [0u8; 4];
-    let r = ((color & 0x00ff0000) >> 16) << client_dpm.pf.red.bits >> 8;
-    let g = ((color & 0x0000ff00) >> 8) << client_dpm.pf.green.bits >> 8;
-    let b = (color & 0x000000ff) << client_dpm.pf.blue.bits >> 8;
-    let v = (r << client_dpm.pf.red.shift)
-        | (g << client_dpm.pf.green.shift)
-        | (b << client_dpm.pf.blue.shift);
-    match client_dpm.pf.pixel_bytes {
-        1 => {
-            ret[0] = v as u8;
-        }
-        2 => {
-            if client_dpm.client_be {
-                ret[0] = (v >> 8) as u8;
-                ret[1] = v as u8;
-            } else {
-                ret[1] = (v >> 8) as u8;
-                ret[0] = v as u8;
-            }
-        }
-        4 => {
-            if client_dpm.client_be {
-                ret = v.to_be_bytes();
-            } else {
-                ret = v.to_le_bytes();
-            }
-        }
-        _ => {
-            if client_dpm.client_be {
-                ret = v.to_be_bytes();
-            } else {
-                ret = v.to_le_bytes();
-            }
-        }
+    let pf = &client_dpm.pf;
+    let r = ((color & 0x00ff0000) >> 16) << pf.red.bits >> 8;
+    let g = ((color & 0x0000ff00) >> 8) << pf.green.bits >> 8;
+    let b = (color & 0x000000ff) << pf.blue.bits >> 8;
+    let v = (r << pf.red.shift) | (g << pf.green.shift) | (b << pf.blue.shift);
+    match (pf.pixel_bytes, client_dpm.client_be) {
+        (1, _) => buf.push(v as u8),
+        (2, true) => buf.extend_from_slice(&(v as u16).to_be_bytes()),
+        (2, false) => buf.extend_from_slice(&(v as u16).to_le_bytes()),
+        (_, true) => buf.extend_from_slice(&v.to_be_bytes()),
+        (_, false) => buf.extend_from_slice(&v.to_le_bytes()),
     }
-    buf.append(&mut ret[..client_dpm.pf.pixel_bytes as usize].to_vec());
 }
 
 /// Send raw data directly without compression
@@ -621,6 +763,8 @@ pub fn raw_send_framebuffer_update(
 
     let copy_bytes = rect.w as usize * bytes_per_pixel();
 
+    // The pixels of whole rectangle are converted to the send buffer.
+    buf.reserve((rect.w * rect.h) as usize * client_dpm.pf.pixel_bytes as usize);
     for _i in 0..rect.h {
         write_pixel(data_ptr, copy_bytes, client_dpm, buf);
         data_ptr = (data_ptr as usize + stride as usize) as *mut u8;
@@ -636,17 +780,86 @@ pub fn raw_send_framebuffer_update(
 /// * `image` = pointer to the data need to be send.
 /// * `rect` - dirty area of image.
 /// * `client_dpm` - Output mod information of client display.
+/// * `client` - The vnc client which the data is sent to.
 /// * `buf` - send buffer.
 fn send_framebuffer_update(
     image: *mut pixman_image_t,
     rect: &Rectangle,
     client_dpm: &DisplayMode,
+    client: &ClientState,
     buf: &mut Vec<u8>,
 ) -> i32 {
     match client_dpm.enc {
         ENCODING_HEXTILE => {
             framebuffer_update(rect.x, rect.y, rect.w, rect.h, ENCODING_HEXTILE, buf);
-            hextile_send_framebuffer_update(image, rect, client_dpm, buf)
+            let mut locked_buf = client.hextile_buf.lock().unwrap();
+            hextile_send_framebuffer_update(image, rect, client_dpm, &mut locked_buf, buf)
+        }
+        ENCODING_ZLIB => {
+            let mut data = Vec::new();
+            let mut locked_stream = client.zlib_stream.lock().unwrap();
+            match zlib_send_framebuffer_update(
+                image,
+                rect,
+                client_dpm,
+                &mut locked_stream,
+                &mut data,
+            ) {
+                Ok(n) => {
+                    framebuffer_update(rect.x, rect.y, rect.w, rect.h, ENCODING_ZLIB, buf);
+                    buf.append(&mut data);
+                    n
+                }
+                Err(e) => {
+                    error!("{:?}, fall back to raw encoding", e);
+                    framebuffer_update(rect.x, rect.y, rect.w, rect.h, ENCODING_RAW, buf);
+                    raw_send_framebuffer_update(image, rect, client_dpm, buf)
+                }
+            }
+        }
+        ENCODING_TIGHT => {
+            // Headers of the split rectangles are written by tight encoding.
+            let mut data = Vec::new();
+            let mut locked_streams = client.tight_streams.lock().unwrap();
+            match tight_send_framebuffer_update(
+                image,
+                rect,
+                client_dpm,
+                &mut locked_streams,
+                &mut data,
+            ) {
+                Ok(n) => {
+                    buf.append(&mut data);
+                    n
+                }
+                Err(e) => {
+                    error!("{:?}, fall back to raw encoding", e);
+                    framebuffer_update(rect.x, rect.y, rect.w, rect.h, ENCODING_RAW, buf);
+                    raw_send_framebuffer_update(image, rect, client_dpm, buf)
+                }
+            }
+        }
+        ENCODING_ZRLE => {
+            let mut data = Vec::new();
+            let mut locked_stream = client.zrle_stream.lock().unwrap();
+            match zrle_send_framebuffer_update(
+                image,
+                rect,
+                client_dpm,
+                &mut locked_stream,
+                &mut data,
+            ) {
+                Ok(n) => {
+                    framebuffer_update(rect.x, rect.y, rect.w, rect.h, ENCODING_ZRLE, buf);
+                    buf.append(&mut data);
+                    n
+                }
+                Err(e) => {
+                    error!("{:?}, fall back to raw encoding", e);
+                    framebuffer_update(rect.x, rect.y, rect.w, rect.h, ENCODING_RAW, buf);
+                    raw_send_framebuffer_update(image, rect, client_dpm, buf)
+                }
+            }
         }
         _ => {
             framebuffer_update(rect.x, rect.y, rect.w, rect.h, ENCODING_RAW, buf);
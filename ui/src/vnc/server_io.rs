@@ -19,28 +19,38 @@ use crate::{
         unref_pixman_image,
     },
     vnc::{
-        auth_sasl::{AuthState, SaslAuth, SaslConfig, SubAuthState},
+        auth_sasl::{AuthState, SaslAuth, SubAuthState},
         auth_vencrypt::{make_vencrypt_config, TlsCreds, ANON_CERT, X509_CERT},
-        client_io::{vnc_flush, vnc_write, ClientIoHandler, ClientState, IoChannel, RectInfo},
-        round_up_div, update_server_surface, DIRTY_PIXELS_NUM, MAX_WINDOW_HEIGHT, MAX_WINDOW_WIDTH,
-        VNC_BITMAP_WIDTH, VNC_SERVERS,
+        auth_vnc::VncPassword,
+        client_io::{
+            vnc_flush, vnc_update_output_throttle, vnc_write, ClientIoHandler, ClientState,
+            CopyRect, IoChannel, RectInfo, Rectangle, UpdatePacing, VncFeatures,
+        },
+        clipboard::ClipboardThrottle,
+        round_up_div, set_area_dirty, update_server_surface, DIRTY_PIXELS_NUM, MAX_WINDOW_HEIGHT,
+        MAX_WINDOW_WIDTH, VNC_BITMAP_WIDTH, VNC_SERVERS,
     },
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use log::{error, info};
 use machine_manager::{
-    config::{ObjectConfig, VncConfig},
+    config::{
+        ObjectConfig, VncConfig, DEFAULT_VNC_AUTH_TIMEOUT, DEFAULT_VNC_CLIPBOARD_LIMIT,
+        DEFAULT_VNC_HANDSHAKE_TIMEOUT, DEFAULT_VNC_MAX_FPS,
+    },
     event_loop::EventLoop,
 };
 use std::{
     cell::RefCell,
     cmp,
-    collections::HashMap,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::Hasher,
     net::{SocketAddr, TcpListener, TcpStream},
     os::unix::prelude::{AsRawFd, RawFd},
     ptr,
     rc::Rc,
     sync::{Arc, Mutex, Weak},
+    time::Duration,
 };
 use util::{
     bitmap::Bitmap,
@@ -52,6 +62,10 @@ use util::{
 use vmm_sys_util::epoll::EventSet;
 
 const CONNECTION_LIMIT: usize = 1;
+/// Min rows of the scrolled area which is sent by CopyRect.
+const MIN_SCROLL_ROWS: usize = 8;
+/// Max distance of the detected vertical scroll.
+const MAX_SCROLL_DISTANCE: usize = 512;
 
 /// Information of VncServer.
 pub struct VncServer {
@@ -73,6 +87,20 @@ pub struct VncServer {
     pub rect_jobs: Arc<Mutex<Vec<RectInfo>>>,
     /// Connection limit.
     pub conn_limits: usize,
+    /// Deadline for client to finish the handshake.
+    pub handshake_timeout: Duration,
+    /// Deadline for client to finish each handshake step.
+    pub auth_timeout: Duration,
+    /// Max number of framebuffer updates sent to each client per second.
+    pub max_fps: u64,
+    /// Max bytes of output queued for each client, 0 means it depends on
+    /// the size of framebuffer.
+    pub output_limit: usize,
+    /// Max bytes of the guest clipboard text sent to each client, 0 means
+    /// the guest clipboard is not shared.
+    pub clipboard_limit: usize,
+    /// Rate limit of the guest clipboard sent to clients.
+    pub clipboard: Arc<Mutex<ClipboardThrottle>>,
 }
 
 // SAFETY:
@@ -98,6 +126,12 @@ impl VncServer {
             display_listener,
             rect_jobs: Arc::new(Mutex::new(Vec::new())),
             conn_limits: CONNECTION_LIMIT,
+            handshake_timeout: Duration::from_secs(DEFAULT_VNC_HANDSHAKE_TIMEOUT),
+            auth_timeout: Duration::from_secs(DEFAULT_VNC_AUTH_TIMEOUT),
+            max_fps: DEFAULT_VNC_MAX_FPS,
+            output_limit: 0,
+            clipboard_limit: DEFAULT_VNC_CLIPBOARD_LIMIT as usize,
+            clipboard: Arc::new(Mutex::new(ClipboardThrottle::default())),
         }
     }
 }
@@ -107,11 +141,17 @@ pub struct VncConnHandler {
     listener: TcpListener,
     /// VncServer.
     server: Arc<VncServer>,
+    /// Connections are accepted by websocket.
+    websocket: bool,
 }
 
 impl VncConnHandler {
-    pub fn new(listener: TcpListener, server: Arc<VncServer>) -> Self {
-        VncConnHandler { listener, server }
+    pub fn new(listener: TcpListener, server: Arc<VncServer>, websocket: bool) -> Self {
+        VncConnHandler {
+            listener,
+            server,
+            websocket,
+        }
     }
 }
 
@@ -120,12 +160,13 @@ impl EventNotifierHelper for VncConnHandler {
     fn internal_notifiers(vnc_io: Arc<Mutex<VncConnHandler>>) -> Vec<EventNotifier> {
         let vnc_io_clone = vnc_io.clone();
         let server = vnc_io.lock().unwrap().server.clone();
+        let websocket = vnc_io.lock().unwrap().websocket;
         // Register event notifier for connection.
         let handler: Rc<NotifierCallback> = Rc::new(move |_event, fd: RawFd| {
             read_fd(fd);
             match vnc_io_clone.clone().lock().unwrap().listener.accept() {
                 Ok((stream, addr)) => {
-                    if let Err(e) = handle_connection(&server, stream, addr) {
+                    if let Err(e) = handle_connection(&server, stream, addr, websocket) {
                         error!("{:?}", e);
                     }
                 }
@@ -177,14 +218,18 @@ pub struct SecurityType {
     pub tlscreds: Option<TlsCreds>,
     /// Authentication for connection
     pub saslauth: Option<SaslAuth>,
-    /// Configuration for sasl Authentication.
-    pub saslconfig: SaslConfig,
+    /// Authorization for the certificate of tls client.
+    pub tlsauthz: Option<SaslAuth>,
+    /// Password for vnc authentication.
+    pub vncpass: Option<VncPassword>,
     /// Configuration to make tls channel.
     pub tls_config: Option<Arc<rustls::ServerConfig>>,
     /// Auth type.
     pub auth: AuthState,
     /// Subauth type.
     pub subauth: SubAuthState,
+    /// Auth type of websocket client.
+    pub ws_auth: AuthState,
 }
 
 impl Default for SecurityType {
@@ -192,10 +237,12 @@ impl Default for SecurityType {
         SecurityType {
             tlscreds: None,
             saslauth: None,
-            saslconfig: SaslConfig::default(),
+            tlsauthz: None,
+            vncpass: None,
             tls_config: None,
             auth: AuthState::No,
             subauth: SubAuthState::VncAuthVencryptPlain,
+            ws_auth: AuthState::No,
         }
     }
 }
@@ -225,25 +272,105 @@ impl SecurityType {
 
         // Sasl configuration.
         if let Some(sasl_auth) = object.sasl_object.get(&vnc_cfg.sasl_authz) {
-            self.saslauth = Some(SaslAuth::new(sasl_auth.identity.clone()));
+            let mut saslauth = SaslAuth::new(sasl_auth.identities.clone(), sasl_auth.allow_all);
+            saslauth.maxbufsize = sasl_auth.maxbufsize;
+            saslauth.min_ssf = sasl_auth.min_ssf;
+            saslauth.max_ssf = sasl_auth.max_ssf;
+            saslauth.security_flags = sasl_auth.security_flags;
+            saslauth.set_config_path(&vnc_cfg.sasl_appname, &vnc_cfg.sasl_config_dir)?;
+            if !vnc_cfg.sasl_realm.is_empty() {
+                saslauth.realm = Some(vnc_cfg.sasl_realm.clone());
+            }
+            self.saslauth = Some(saslauth);
+        }
+
+        // Authorization of tls client certificate.
+        if !vnc_cfg.tls_authz.is_empty() {
+            let tls_authz = object
+                .sasl_object
+                .get(&vnc_cfg.tls_authz)
+                .with_context(|| {
+                    VncError::MakeTlsConnectionFailed(format!(
+                        "authz object {} is not found",
+                        vnc_cfg.tls_authz
+                    ))
+                })?;
+            let verifypeer = self.tlscreds.as_ref().map_or(false, |tls| tls.verifypeer);
+            if !verifypeer {
+                return Err(anyhow!(VncError::MakeTlsConnectionFailed(String::from(
+                    "tls-authz requires verify-peer of tls-creds",
+                ))));
+            }
+            self.tlsauthz = Some(SaslAuth::new(
+                tls_authz.identities.clone(),
+                tls_authz.allow_all,
+            ));
+        }
+
+        // Password authentication, the password is set by qmp.
+        if vnc_cfg.password {
+            if self.saslauth.is_some() {
+                return Err(anyhow!(VncError::MakeTlsConnectionFailed(String::from(
+                    "password can not be used with sasl",
+                ))));
+            }
+            self.vncpass = Some(VncPassword::default());
         }
 
         Ok(())
     }
 
+    /// Reload the tls credentials from the certificate directory. New connections
+    /// use the new credentials, while the established connections keep their tls
+    /// state. The old credentials are kept if failed to reload.
+    pub fn reload_tls_creds(&mut self) -> Result<()> {
+        let tlscreds = self.tlscreds.as_ref().with_context(|| {
+            VncError::MakeTlsConnectionFailed("tls-creds is not configured".to_string())
+        })?;
+        let tls_config = make_vencrypt_config(tlscreds)
+            .with_context(|| format!("Failed to reload tls credentials from {}", tlscreds.dir))?;
+        self.tls_config = Some(tls_config);
+        info!("Tls credentials are reloaded from {}", tlscreds.dir);
+        Ok(())
+    }
+
     /// Encryption configuration.
     fn set_auth(&mut self) -> Result<()> {
         let is_x509: bool;
         let is_anon: bool;
         let is_sasl: bool = self.saslauth.is_some();
+        let is_password: bool = self.vncpass.is_some();
 
         if let Some(tlscred) = self.tlscreds.clone() {
             is_x509 = tlscred.cred_type == *X509_CERT;
             is_anon = tlscred.cred_type == *ANON_CERT;
             self.auth = AuthState::Vencrypt;
+            // Websocket is wrapped in tls by itself, the auth is used directly.
+            self.ws_auth = if is_sasl {
+                AuthState::Sasl
+            } else if is_password {
+                AuthState::Vnc
+            } else {
+                AuthState::No
+            };
         } else {
-            self.auth = AuthState::No;
+            // Without tls, sasl is only offered when its own security layer is required.
+            if let Some(saslauth) = &self.saslauth {
+                if saslauth.min_ssf == 0 {
+                    return Err(anyhow!(VncError::MakeTlsConnectionFailed(String::from(
+                        "sasl without tls-creds requires min-ssf larger than 0",
+                    ))));
+                }
+            }
+            self.auth = if is_sasl {
+                AuthState::Sasl
+            } else if is_password {
+                AuthState::Vnc
+            } else {
+                AuthState::No
+            };
             self.subauth = SubAuthState::VncAuthVencryptPlain;
+            self.ws_auth = self.auth;
             return Ok(());
         }
 
@@ -258,6 +385,12 @@ impl SecurityType {
             } else {
                 self.subauth = SubAuthState::VncAuthVencryptTlssasl;
             }
+        } else if is_password {
+            if is_x509 {
+                self.subauth = SubAuthState::VncAuthVencryptX509Vnc;
+            } else {
+                self.subauth = SubAuthState::VncAuthVencryptTlsVnc;
+            }
         } else if is_x509 {
             self.subauth = SubAuthState::VncAuthVencryptX509None;
         } else {
@@ -336,6 +469,14 @@ impl VncSurface {
             return Ok(dirty_num);
         }
 
+        // The scrolled area is copied in server image, so that only the newly
+        // exposed area is found dirty below.
+        if let Some(copy) = self.detect_vertical_scroll() {
+            self.apply_copy_rect(&copy);
+            copy_for_each_clients(&copy, self.get_min_width(), self.get_min_height())?;
+            dirty_num += 1;
+        }
+
         let mut s_info = ImageInfo::new(self.server_image);
         let mut g_info = ImageInfo::new(self.guest_image);
 
@@ -390,6 +531,129 @@ impl VncSurface {
         Ok(dirty_num)
     }
 
+    /// Detect the vertical scroll in the dirty rows of guest image, by
+    /// comparing them with the rows of server image, which holds the previous
+    /// frame, at different distances. Return the longest area in which all
+    /// the rows are moved by the same distance.
+    pub fn detect_vertical_scroll(&self) -> Option<CopyRect> {
+        if self.guest_image.is_null()
+            || self.server_image.is_null()
+            || self.guest_format != pixman_format_code_t::PIXMAN_x8r8g8b8
+        {
+            return None;
+        }
+
+        // Find the rows between the first and the last dirty row.
+        let height = self.get_min_height() as usize;
+        let g_bpl = self.guest_dirty_bitmap.vol() / MAX_WINDOW_HEIGHT as usize;
+        let total_dirty_bits = height.checked_mul(g_bpl).unwrap_or(0);
+        let offset = self
+            .guest_dirty_bitmap
+            .find_next_bit(0)
+            .unwrap_or(total_dirty_bits);
+        if offset >= total_dirty_bits {
+            return None;
+        }
+        let start_y = offset / g_bpl;
+        let mut end_y = start_y + 1;
+        loop {
+            let offset = self
+                .guest_dirty_bitmap
+                .find_next_bit(end_y * g_bpl)
+                .unwrap_or(total_dirty_bits);
+            if offset >= total_dirty_bits {
+                break;
+            }
+            end_y = offset / g_bpl + 1;
+        }
+        let rows = end_y - start_y;
+        if rows <= MIN_SCROLL_ROWS {
+            return None;
+        }
+
+        let line_bytes = self.get_min_width() as usize * bytes_per_pixel();
+        let s_info = ImageInfo::new(self.server_image);
+        let g_info = ImageInfo::new(self.guest_image);
+        let old: Vec<u64> = (start_y..end_y)
+            .map(|y| hash_line(&s_info, y, line_bytes))
+            .collect();
+        let new: Vec<u64> = (start_y..end_y)
+            .map(|y| hash_line(&g_info, y, line_bytes))
+            .collect();
+
+        // Row i of guest image is row i + distance of server image in the
+        // moved area. Find the longest one in (start, rows, distance).
+        let mut moved: Option<(usize, usize, isize)> = None;
+        let max_distance = cmp::min(rows - MIN_SCROLL_ROWS, MAX_SCROLL_DISTANCE) as isize;
+        for distance in (1..=max_distance).flat_map(|d| [d, -d]) {
+            let first = cmp::max(0, -distance) as usize;
+            let last = cmp::min(rows as isize, rows as isize - distance) as usize;
+            let mut run_start = first;
+            for i in first..=last {
+                if i < last && new[i] == old[(i as isize + distance) as usize] {
+                    continue;
+                }
+                let len = i - run_start;
+                // The rows not changed are not taken as moved.
+                if len >= MIN_SCROLL_ROWS
+                    && !matches!(moved, Some((_, n, _)) if n >= len)
+                    && (run_start..i).any(|j| new[j] != old[j])
+                {
+                    moved = Some((run_start, len, distance));
+                }
+                run_start = i + 1;
+            }
+        }
+
+        let (start, len, distance) = moved?;
+        let dst_y = start_y + start;
+        let src_y = (dst_y as isize + distance) as usize;
+        // Check the content in case of hash collision.
+        for i in 0..len {
+            // SAFETY: the rows are in the range of both images.
+            let equal = unsafe {
+                libc::memcmp(
+                    line_ptr(&g_info, dst_y + i) as *const libc::c_void,
+                    line_ptr(&s_info, src_y + i) as *const libc::c_void,
+                    line_bytes,
+                ) == 0
+            };
+            if !equal {
+                return None;
+            }
+        }
+
+        Some(CopyRect::new(
+            Rectangle::new(0, dst_y as i32, self.get_min_width(), len as i32),
+            0,
+            src_y as i32,
+        ))
+    }
+
+    /// Copy the area in server image as the client does.
+    fn apply_copy_rect(&mut self, copy: &CopyRect) {
+        let s_info = ImageInfo::new(self.server_image);
+        let line_bytes = copy.rect.w as usize * bytes_per_pixel();
+        let offset = copy.rect.x as usize * bytes_per_pixel();
+        let rows = 0..copy.rect.h as usize;
+        // Copy the rows in the order that the source rows are read before
+        // overwritten.
+        let rows: Vec<usize> = if copy.src_y > copy.rect.y {
+            rows.collect()
+        } else {
+            rows.rev().collect()
+        };
+        for i in rows {
+            let src = line_ptr(&s_info, copy.src_y as usize + i) as usize
+                + copy.src_x as usize * bytes_per_pixel();
+            let dst = line_ptr(&s_info, copy.rect.y as usize + i) as usize + offset;
+            // SAFETY: the area is in the range of server image.
+            unsafe {
+                ptr::copy(src as *const u8, dst as *mut u8, line_bytes);
+            }
+        }
+    }
+
     /// Update each line
     ///
     /// # Arguments
@@ -456,6 +720,92 @@ impl VncSurface {
     }
 }
 
+/// Start pointer of the line in image.
+fn line_ptr(info: &ImageInfo, y: usize) -> *mut u8 {
+    (info.data as usize + y * info.stride as usize) as *mut u8
+}
+
+/// Hash the pixels of the line in image.
+fn hash_line(info: &ImageInfo, y: usize, line_bytes: usize) -> u64 {
+    // SAFETY: the line is in the range of image.
+    let line = unsafe { std::slice::from_raw_parts(line_ptr(info, y), line_bytes) };
+    let mut hasher = DefaultHasher::new();
+    hasher.write(line);
+    hasher.finish()
+}
+
+/// Send the copied area to the clients supporting CopyRect, and set the area
+/// dirty for the others.
+///
+/// # Arguments
+///
+/// * `copy` - the copied area.
+/// * `width` `height` - size of image.
+fn copy_for_each_clients(copy: &CopyRect, width: i32, height: i32) -> Result<()> {
+    let server = VNC_SERVERS.lock().unwrap()[0].clone();
+    let mut locked_handlers = server.client_handlers.lock().unwrap();
+    let locked_jobs = server.rect_jobs.lock().unwrap();
+    for client in locked_handlers.values_mut() {
+        let support_copy = client
+            .client_dpm
+            .lock()
+            .unwrap()
+            .has_feature(VncFeatures::VncFeatureCopyRect);
+        // The queued jobs read server image when sent, so the copy can not be
+        // sent after them.
+        let job_queued = locked_jobs
+            .iter()
+            .any(|job| Arc::ptr_eq(&job.client, client));
+        let mut locked_dirty = client.dirty_bitmap.lock().unwrap();
+        if support_copy && !job_queued {
+            move_dirty_area(&mut locked_dirty, copy)?;
+            client.copy_rects.lock().unwrap().push(copy.clone());
+        } else {
+            set_area_dirty(
+                &mut locked_dirty,
+                copy.rect.x,
+                copy.rect.y,
+                copy.rect.w,
+                copy.rect.h,
+                width,
+                height,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Move the dirty bits along with the copied area, as the area not sent to
+/// client is still dirty after copied.
+///
+/// # Arguments
+///
+/// * `dirty` - dirty bitmap of client.
+/// * `copy` - the copied area.
+fn move_dirty_area(dirty: &mut Bitmap<u64>, copy: &CopyRect) -> Result<()> {
+    let start_x = copy.rect.x as usize / DIRTY_PIXELS_NUM as usize;
+    let end_x = round_up_div((copy.rect.x + copy.rect.w) as u64, DIRTY_PIXELS_NUM as u64) as usize;
+    let src_x = copy.src_x as usize / DIRTY_PIXELS_NUM as usize;
+    let rows = 0..copy.rect.h as usize;
+    let rows: Vec<usize> = if copy.src_y > copy.rect.y {
+        rows.collect()
+    } else {
+        rows.rev().collect()
+    };
+    for i in rows {
+        let src = (copy.src_y as usize + i) * VNC_BITMAP_WIDTH as usize + src_x;
+        let dst = (copy.rect.y as usize + i) * VNC_BITMAP_WIDTH as usize + start_x;
+        for x in 0..end_x - start_x {
+            if dirty.contain(src + x)? {
+                dirty.set(dst + x)?;
+            } else {
+                dirty.clear(dst + x)?;
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Set diry for each client.
 ///
 /// # Arguments
@@ -480,10 +830,12 @@ fn set_dirty_for_each_clients(x: usize, y: usize) -> Result<()> {
 ///
 /// * `stream` - TcpStream.
 /// * `addr`- SocketAddr.
+/// * `websocket` - The connection is accepted by websocket.
 pub fn handle_connection(
     server: &Arc<VncServer>,
     stream: TcpStream,
     addr: SocketAddr,
+    websocket: bool,
 ) -> Result<()> {
     info!("New Connection: {:?}", stream);
     stream
@@ -493,6 +845,12 @@ pub fn handle_connection(
     let io_channel = Rc::new(RefCell::new(IoChannel::new(stream.try_clone().unwrap())));
     // Register event notifier for vnc client.
     let client = Arc::new(ClientState::new(addr.to_string()));
+    let mut locked_state = client.conn_state.lock().unwrap();
+    locked_state.pacing = UpdatePacing::new(server.max_fps);
+    locked_state.output_limit = server.output_limit;
+    drop(locked_state);
+    // The output is limited since negotiation.
+    vnc_update_output_throttle(&client);
     let client_io = Arc::new(Mutex::new(ClientIoHandler::new(
         stream,
         io_channel,
@@ -500,15 +858,29 @@ pub fn handle_connection(
         server.clone(),
     )));
     client.conn_state.lock().unwrap().client_io = Some(Arc::downgrade(&client_io));
-    vnc_write(&client, "RFB 003.008\n".as_bytes().to_vec());
-    vnc_flush(&client);
+    // The RFB version of websocket client is sent after the handshake of websocket.
+    if !websocket {
+        vnc_write(&client, "RFB 003.008\n".as_bytes().to_vec());
+        vnc_flush(&client);
+    }
     server
         .client_handlers
         .lock()
         .unwrap()
         .insert(addr.to_string(), client);
 
-    EventLoop::update_event(EventNotifierHelper::internal_notifiers(client_io), None)?;
+    // The client is disconnected if the handshake is not finished in time.
+    if let Some(ctx) = EventLoop::get_ctx(None) {
+        ClientIoHandler::arm_handshake_timer(&client_io, ctx, server.handshake_timeout);
+        client_io.lock().unwrap().arm_auth_timer(ctx);
+    }
+    EventLoop::update_event(
+        EventNotifierHelper::internal_notifiers(client_io.clone()),
+        None,
+    )?;
+    if websocket {
+        client_io.lock().unwrap().ws_start()?;
+    }
 
     update_server_surface(server)
 }
@@ -534,3 +906,223 @@ pub fn make_server_config(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pixman::create_pixman_image,
+        vnc::{copy_rect_send_framebuffer_update, vnc_width},
+    };
+
+    const IMAGE_WIDTH: i32 = 128;
+    const IMAGE_HEIGHT: i32 = 96;
+
+    /// Each line of the image is different.
+    fn create_image_data() -> Vec<u32> {
+        let mut data = Vec::new();
+        for y in 0..IMAGE_HEIGHT as u32 {
+            for x in 0..IMAGE_WIDTH as u32 {
+                data.push(y << 16 | x);
+            }
+        }
+        data
+    }
+
+    fn create_surface(server_data: &mut [u32], guest_data: &mut [u32]) -> VncSurface {
+        let guest_image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            IMAGE_WIDTH,
+            IMAGE_HEIGHT,
+            guest_data.as_mut_ptr(),
+            IMAGE_WIDTH * 4,
+        );
+        let mut surface = VncSurface::new(guest_image);
+        surface.server_image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            IMAGE_WIDTH,
+            IMAGE_HEIGHT,
+            server_data.as_mut_ptr(),
+            IMAGE_WIDTH * 4,
+        );
+        surface
+    }
+
+    /// Move the line of `src_y` in `old` to `dst_y` in `new`.
+    fn move_line(old: &[u32], new: &mut [u32], src_y: usize, dst_y: usize) {
+        let w = IMAGE_WIDTH as usize;
+        new[dst_y * w..(dst_y + 1) * w].copy_from_slice(&old[src_y * w..(src_y + 1) * w]);
+    }
+
+    /// Fill the line of `y` with new content.
+    fn fill_line(data: &mut [u32], y: usize) {
+        let w = IMAGE_WIDTH as usize;
+        for (x, pixel) in data[y * w..(y + 1) * w].iter_mut().enumerate() {
+            *pixel = 0xff00_0000 | (y as u32) << 8 | x as u32;
+        }
+    }
+
+    /// Return the lines which are different in two images.
+    fn diff_lines(data1: &[u32], data2: &[u32]) -> Vec<usize> {
+        let w = IMAGE_WIDTH as usize;
+        (0..IMAGE_HEIGHT as usize)
+            .filter(|y| data1[y * w..(y + 1) * w] != data2[y * w..(y + 1) * w])
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_scroll_up() {
+        let mut server_data = create_image_data();
+        let mut guest_data = server_data.clone();
+        // Lines [16, 80) scroll up by 16 lines, and lines [64, 80) are new.
+        for y in 16..64 {
+            move_line(&server_data, &mut guest_data, y + 16, y);
+        }
+        for y in 64..80 {
+            fill_line(&mut guest_data, y);
+        }
+        let mut surface = create_surface(&mut server_data, &mut guest_data);
+        assert!(surface.detect_vertical_scroll().is_none());
+        set_area_dirty(
+            &mut surface.guest_dirty_bitmap,
+            0,
+            16,
+            IMAGE_WIDTH,
+            64,
+            IMAGE_WIDTH,
+            IMAGE_HEIGHT,
+        )
+        .unwrap();
+
+        let copy = surface.detect_vertical_scroll().unwrap();
+        assert_eq!(
+            copy,
+            CopyRect::new(Rectangle::new(0, 16, IMAGE_WIDTH, 48), 0, 32)
+        );
+        let mut buf = Vec::new();
+        assert_eq!(copy_rect_send_framebuffer_update(&copy, &mut buf), 1);
+        assert_eq!(buf, [0, 0, 0, 16, 0, 128, 0, 48, 0, 0, 0, 1, 0, 0, 0, 32]);
+
+        // Only the strip of new content needs to be sent after copied.
+        surface.apply_copy_rect(&copy);
+        assert_eq!(
+            diff_lines(&server_data, &guest_data),
+            (64..80).collect::<Vec<usize>>()
+        );
+    }
+
+    #[test]
+    fn test_detect_scroll_down() {
+        let mut server_data = create_image_data();
+        let mut guest_data = server_data.clone();
+        // Lines [8, 88) scroll down by 24 lines, and lines [8, 32) are new.
+        for y in 32..88 {
+            move_line(&server_data, &mut guest_data, y - 24, y);
+        }
+        for y in 8..32 {
+            fill_line(&mut guest_data, y);
+        }
+        let mut surface = create_surface(&mut server_data, &mut guest_data);
+        set_area_dirty(
+            &mut surface.guest_dirty_bitmap,
+            0,
+            8,
+            IMAGE_WIDTH,
+            80,
+            IMAGE_WIDTH,
+            IMAGE_HEIGHT,
+        )
+        .unwrap();
+
+        let copy = surface.detect_vertical_scroll().unwrap();
+        assert_eq!(
+            copy,
+            CopyRect::new(Rectangle::new(0, 32, IMAGE_WIDTH, 56), 0, 8)
+        );
+        surface.apply_copy_rect(&copy);
+        assert_eq!(
+            diff_lines(&server_data, &guest_data),
+            (8..32).collect::<Vec<usize>>()
+        );
+    }
+
+    #[test]
+    fn test_detect_no_scroll() {
+        let mut server_data = create_image_data();
+        let mut guest_data = server_data.clone();
+        for y in 16..80 {
+            fill_line(&mut guest_data, y);
+        }
+        let mut surface = create_surface(&mut server_data, &mut guest_data);
+        set_area_dirty(
+            &mut surface.guest_dirty_bitmap,
+            0,
+            16,
+            IMAGE_WIDTH,
+            64,
+            IMAGE_WIDTH,
+            IMAGE_HEIGHT,
+        )
+        .unwrap();
+        assert!(surface.detect_vertical_scroll().is_none());
+
+        // The moved area is too small.
+        let mut guest_data = server_data.clone();
+        for y in 16..20 {
+            move_line(&server_data, &mut guest_data, y + 4, y);
+        }
+        for y in 20..80 {
+            fill_line(&mut guest_data, y);
+        }
+        surface.guest_image = create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            IMAGE_WIDTH,
+            IMAGE_HEIGHT,
+            guest_data.as_mut_ptr(),
+            IMAGE_WIDTH * 4,
+        );
+        assert!(surface.detect_vertical_scroll().is_none());
+    }
+
+    #[test]
+    fn test_move_dirty_area() {
+        let mut dirty = Bitmap::<u64>::new(
+            MAX_WINDOW_HEIGHT as usize
+                * round_up_div(
+                    (MAX_WINDOW_WIDTH / DIRTY_PIXELS_NUM) as u64,
+                    u64::BITS as u64,
+                ) as usize,
+        );
+        let bpl = VNC_BITMAP_WIDTH as usize;
+        // The dirty lines 40, 50 and 70 are moved to 24, 34 and 54, and the
+        // line 70 which is not overwritten keeps dirty.
+        dirty.set(40 * bpl + 1).unwrap();
+        dirty.set(50 * bpl).unwrap();
+        dirty.set(70 * bpl).unwrap();
+        dirty.set(24 * bpl).unwrap();
+        let copy = CopyRect::new(Rectangle::new(0, 16, IMAGE_WIDTH, 48), 0, 32);
+        move_dirty_area(&mut dirty, &copy).unwrap();
+
+        let width = vnc_width(IMAGE_WIDTH) as usize / DIRTY_PIXELS_NUM as usize;
+        let dirty_bits: Vec<usize> = (0..IMAGE_HEIGHT as usize)
+            .flat_map(|y| (0..width).map(move |x| y * bpl + x))
+            .filter(|bit| dirty.contain(*bit).unwrap())
+            .collect();
+        assert_eq!(dirty_bits, vec![24 * bpl + 1, 34 * bpl, 54 * bpl, 70 * bpl]);
+    }
+
+    #[test]
+    fn test_set_auth_sasl_without_tls() {
+        let mut security = SecurityType::default();
+        let mut saslauth = SaslAuth::new(vec!["alice".to_string()], false);
+        security.saslauth = Some(saslauth.clone());
+        security.set_auth().unwrap();
+        assert!(matches!(security.auth, AuthState::Sasl));
+        assert!(matches!(security.ws_auth, AuthState::Sasl));
+
+        // Sasl without any encryption is rejected.
+        saslauth.min_ssf = 0;
+        security.saslauth = Some(saslauth);
+        assert!(security.set_auth().is_err());
+    }
+}
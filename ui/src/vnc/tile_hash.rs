@@ -0,0 +1,331 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use crate::{
+    pixman::{
+        bytes_per_pixel, get_image_data, get_image_height, get_image_stride, get_image_width,
+    },
+    vnc::{client_io::Rectangle, round_up_div},
+};
+use util::pixman::pixman_image_t;
+
+/// Width of the tile whose content hash is recorded.
+pub const TILE_WIDTH: i32 = 64;
+/// Height of the tile whose content hash is recorded.
+pub const TILE_HEIGHT: i32 = 16;
+
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+
+/// Content hash of the tiles which have been sent to client. Guest may redraw
+/// the area without changing it, the tiles whose content is the same as the
+/// client holds are dropped from the update.
+#[derive(Default)]
+pub struct TileHashes {
+    /// Width of image in pixels.
+    width: i32,
+    /// Height of image in pixels.
+    height: i32,
+    /// Number of tiles in one row.
+    cols: usize,
+    /// Hash of each tile, `None` if the content in client is unknown.
+    hashes: Vec<Option<u64>>,
+}
+
+impl TileHashes {
+    /// Forget all the tiles, as the content in client is unknown.
+    pub fn reset(&mut self) {
+        self.hashes.iter_mut().for_each(|hash| *hash = None);
+    }
+
+    /// Forget the tiles overlapped with the area, which is changed in client
+    /// other than by the tiles sent.
+    pub fn invalidate(&mut self, rect: &Rectangle) {
+        let bound = Rectangle::new(0, 0, self.width, self.height);
+        if let Some(rect) = rect.intersect(&bound) {
+            for (tx, ty) in tiles_in(&rect) {
+                self.hashes[ty * self.cols + tx] = None;
+            }
+        }
+    }
+
+    /// Drop the tiles not changed since they were sent to client from the
+    /// area to update, and record the hash of the tiles to be sent. The area
+    /// only covering part of a tile is kept, as the rest of the tile may be
+    /// stale in client.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - image to be sent.
+    /// * `rects` - area to update.
+    pub fn filter_unchanged(
+        &mut self,
+        image: *mut pixman_image_t,
+        rects: Vec<Rectangle>,
+    ) -> Vec<Rectangle> {
+        let width = get_image_width(image);
+        let height = get_image_height(image);
+        if width != self.width || height != self.height {
+            self.width = width;
+            self.height = height;
+            self.cols = round_up_div(width as u64, TILE_WIDTH as u64) as usize;
+            let rows = round_up_div(height as u64, TILE_HEIGHT as u64) as usize;
+            self.hashes = vec![None; self.cols * rows];
+        }
+
+        let bound = Rectangle::new(0, 0, width, height);
+        let mut filtered = Vec::new();
+        for rect in rects {
+            let rect = match rect.intersect(&bound) {
+                Some(rect) => rect,
+                None => continue,
+            };
+            // The parts of the rectangle in each tile, and whether they are
+            // kept.
+            let mut parts = Vec::new();
+            for (tx, ty) in tiles_in(&rect) {
+                let tile = Rectangle::new(
+                    tx as i32 * TILE_WIDTH,
+                    ty as i32 * TILE_HEIGHT,
+                    TILE_WIDTH,
+                    TILE_HEIGHT,
+                )
+                .intersect(&bound)
+                .unwrap();
+                let part = tile.intersect(&rect).unwrap();
+                let hash = &mut self.hashes[ty * self.cols + tx];
+                if part != tile {
+                    *hash = None;
+                    parts.push((part, true));
+                    continue;
+                }
+                let new_hash = tile_hash(image, &tile);
+                parts.push((part, *hash != Some(new_hash)));
+                *hash = Some(new_hash);
+            }
+
+            if parts.iter().all(|(_, keep)| *keep) {
+                filtered.push(rect);
+                continue;
+            }
+            // The kept parts next to each other in the row of tiles are merged.
+            let mut merged: Option<Rectangle> = None;
+            for (part, keep) in parts {
+                match merged.as_mut() {
+                    Some(last) if keep && last.y == part.y && last.x + last.w == part.x => {
+                        last.w += part.w;
+                        continue;
+                    }
+                    _ => {}
+                }
+                if let Some(last) = merged.take() {
+                    filtered.push(last);
+                }
+                if keep {
+                    merged = Some(part);
+                }
+            }
+            if let Some(last) = merged {
+                filtered.push(last);
+            }
+        }
+        filtered
+    }
+}
+
+/// Iterate the (column, row) of tiles overlapped with the area, row by row.
+fn tiles_in(rect: &Rectangle) -> impl Iterator<Item = (usize, usize)> {
+    let tx = (rect.x / TILE_WIDTH) as usize;
+    let tx2 = round_up_div((rect.x + rect.w) as u64, TILE_WIDTH as u64) as usize;
+    let ty = (rect.y / TILE_HEIGHT) as usize;
+    let ty2 = round_up_div((rect.y + rect.h) as u64, TILE_HEIGHT as u64) as usize;
+    (ty..ty2).flat_map(move |y| (tx..tx2).map(move |x| (x, y)))
+}
+
+/// One round of xxhash64 which mixes the lane into accumulator.
+fn hash_round(acc: u64, lane: u64) -> u64 {
+    acc.wrapping_add(lane.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+/// Hash the pixels of the area in image in the way of xxhash64, which
+/// processes 8 bytes each round.
+///
+/// # Arguments
+///
+/// * `image` - image to be hashed.
+/// * `rect` - area in the range of image.
+pub fn tile_hash(image: *mut pixman_image_t, rect: &Rectangle) -> u64 {
+    let data = get_image_data(image) as usize;
+    let stride = get_image_stride(image) as usize;
+    let line_bytes = rect.w as usize * bytes_per_pixel();
+    let mut acc = PRIME64_3.wrapping_add((line_bytes * rect.h as usize) as u64);
+    for y in rect.y as usize..(rect.y + rect.h) as usize {
+        let ptr = data + y * stride + rect.x as usize * bytes_per_pixel();
+        // SAFETY: the area is in the range of image.
+        let line = unsafe { std::slice::from_raw_parts(ptr as *const u8, line_bytes) };
+        let mut lanes = line.chunks_exact(8);
+        for lane in lanes.by_ref() {
+            let lane = u64::from_le_bytes(lane.try_into().unwrap());
+            acc = (acc ^ hash_round(0, lane))
+                .rotate_left(27)
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+        }
+        for byte in lanes.remainder() {
+            acc = (acc ^ u64::from(*byte).wrapping_mul(PRIME64_3))
+                .rotate_left(11)
+                .wrapping_mul(PRIME64_1);
+        }
+    }
+
+    // Avalanche the bits of accumulator.
+    acc ^= acc >> 33;
+    acc = acc.wrapping_mul(PRIME64_2);
+    acc ^= acc >> 29;
+    acc = acc.wrapping_mul(PRIME64_3);
+    acc ^ (acc >> 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::pixman::create_pixman_image;
+    use util::pixman::pixman_format_code_t;
+
+    const IMAGE_WIDTH: i32 = 256;
+    const IMAGE_HEIGHT: i32 = 64;
+
+    fn create_image(data: &mut [u32], width: i32, height: i32) -> *mut pixman_image_t {
+        create_pixman_image(
+            pixman_format_code_t::PIXMAN_x8r8g8b8,
+            width,
+            height,
+            data.as_mut_ptr(),
+            width * 4,
+        )
+    }
+
+    fn whole_image() -> Vec<Rectangle> {
+        vec![Rectangle::new(0, 0, IMAGE_WIDTH, IMAGE_HEIGHT)]
+    }
+
+    #[test]
+    fn test_filter_unchanged_tiles() {
+        let mut data: Vec<u32> = (0..(IMAGE_WIDTH * IMAGE_HEIGHT) as u32).collect();
+        let image = create_image(&mut data, IMAGE_WIDTH, IMAGE_HEIGHT);
+        let mut hashes = TileHashes::default();
+
+        // All the tiles are new to client.
+        assert_eq!(hashes.filter_unchanged(image, whole_image()), whole_image());
+        assert_eq!(hashes.hashes.len(), 16);
+        // Guest redraws the whole image with the same content.
+        assert!(hashes.filter_unchanged(image, whole_image()).is_empty());
+
+        // A single changed pixel still propagates by its tile.
+        data[20 * IMAGE_WIDTH as usize + 100] ^= 1;
+        assert_eq!(
+            hashes.filter_unchanged(image, whole_image()),
+            vec![Rectangle::new(64, 16, 64, 16)]
+        );
+        assert!(hashes.filter_unchanged(image, whole_image()).is_empty());
+
+        // The changed tiles next to each other are merged in the row.
+        data[0] ^= 1;
+        data[70] ^= 1;
+        data[50 * IMAGE_WIDTH as usize + 255] ^= 1;
+        assert_eq!(
+            hashes.filter_unchanged(image, whole_image()),
+            vec![
+                Rectangle::new(0, 0, 128, 16),
+                Rectangle::new(192, 48, 64, 16)
+            ]
+        );
+
+        // The area covering part of tile is kept, and the tile is resent as
+        // a whole next time.
+        let part = vec![Rectangle::new(16, 4, 32, 8)];
+        assert_eq!(hashes.filter_unchanged(image, part.clone()), part);
+        assert_eq!(
+            hashes.filter_unchanged(image, whole_image()),
+            vec![Rectangle::new(0, 0, 64, 16)]
+        );
+
+        // The content of client is changed by others.
+        hashes.invalidate(&Rectangle::new(130, 36, 10, 10));
+        assert_eq!(
+            hashes.filter_unchanged(image, whole_image()),
+            vec![Rectangle::new(128, 32, 64, 16)]
+        );
+        hashes.reset();
+        assert_eq!(hashes.filter_unchanged(image, whole_image()), whole_image());
+
+        // The area out of image is dropped.
+        assert!(hashes
+            .filter_unchanged(image, vec![Rectangle::new(IMAGE_WIDTH, 0, 16, 16)])
+            .is_empty());
+    }
+
+    const FRAME_WIDTH: i32 = 1920;
+    const FRAME_HEIGHT: i32 = 1080;
+
+    fn create_frame_data() -> Vec<u32> {
+        (0..(FRAME_WIDTH * FRAME_HEIGHT) as u32)
+            .map(|i| i.wrapping_mul(0x9E37_79B1))
+            .collect()
+    }
+
+    #[test]
+    fn test_tile_hash_memory() {
+        let mut data = create_frame_data();
+        let image = create_image(&mut data, FRAME_WIDTH, FRAME_HEIGHT);
+        let frame = vec![Rectangle::new(0, 0, FRAME_WIDTH, FRAME_HEIGHT)];
+        let mut hashes = TileHashes::default();
+        assert_eq!(hashes.filter_unchanged(image, frame.clone()), frame);
+
+        // One u64 with a tag is kept for each 64x16 tile.
+        let memory = hashes.hashes.len() * std::mem::size_of::<Option<u64>>();
+        assert_eq!(hashes.hashes.len(), 30 * 68);
+        assert_eq!(memory, 32640);
+    }
+
+    // Benchmark of hashing a full frame, run it with `--release --ignored`.
+    // The hashes of a frame must be computed within the interval of frames at 30 fps.
+    #[test]
+    #[ignore]
+    fn bench_tile_hash_cost() {
+        let mut data = create_frame_data();
+        let image = create_image(&mut data, FRAME_WIDTH, FRAME_HEIGHT);
+        let frame = vec![Rectangle::new(0, 0, FRAME_WIDTH, FRAME_HEIGHT)];
+        let mut hashes = TileHashes::default();
+        hashes.filter_unchanged(image, frame.clone());
+
+        let rounds = 20;
+        let start = Instant::now();
+        for _ in 0..rounds {
+            assert!(hashes.filter_unchanged(image, frame.clone()).is_empty());
+        }
+        let cost = start.elapsed() / rounds;
+        assert!(
+            cost < Duration::from_millis(33),
+            "hash of {}x{} frame costs {:?}",
+            FRAME_WIDTH,
+            FRAME_HEIGHT,
+            cost
+        );
+    }
+}
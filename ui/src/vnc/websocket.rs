@@ -0,0 +1,807 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use crate::{
+    error::VncError,
+    vnc::client_io::{vnc_flush, vnc_write, ClientIoHandler, IoOperations},
+};
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::error;
+use ring::digest;
+use rustls::Certificate;
+use std::{cell::RefCell, cmp, collections::HashMap, rc::Rc};
+
+/// Max length of the http upgrade request.
+const WS_HANDSHAKE_MAX_LEN: usize = 4096;
+/// Guid which is appended to the key of client in handshake, defined in RFC 6455.
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const WS_VERSION: &str = "13";
+/// Length of the nonce in the key of client.
+const WS_KEY_LEN: usize = 16;
+/// Subprotocol which carries the binary data, requested by noVNC.
+const WS_PROTOCOL_BINARY: &str = "binary";
+/// Response to the invalid upgrade request.
+const WS_BAD_REQUEST: &str =
+    "HTTP/1.1 400 Bad Request\r\nConnection: close\r\nSec-WebSocket-Version: 13\r\n\r\n";
+/// Bits of the first byte in frame header.
+const WS_HEAD_FIN: u8 = 0x80;
+const WS_HEAD_RSV: u8 = 0x70;
+const WS_HEAD_OPCODE: u8 = 0x0f;
+/// Bits of the second byte in frame header.
+const WS_HEAD_MASK: u8 = 0x80;
+const WS_HEAD_PAYLOAD_LEN: u8 = 0x7f;
+/// The payload length is in the following 2 or 8 bytes.
+const WS_PAYLOAD_LEN_16: u8 = 126;
+const WS_PAYLOAD_LEN_64: u8 = 127;
+/// Max payload length of control frames.
+const WS_CONTROL_PAYLOAD_MAX: u64 = 125;
+const WS_MASK_LEN: usize = 4;
+/// Opcodes of frame.
+pub const WS_OPCODE_CONTINUATION: u8 = 0x0;
+pub const WS_OPCODE_TEXT: u8 = 0x1;
+pub const WS_OPCODE_BINARY: u8 = 0x2;
+pub const WS_OPCODE_CLOSE: u8 = 0x8;
+pub const WS_OPCODE_PING: u8 = 0x9;
+pub const WS_OPCODE_PONG: u8 = 0xa;
+/// Status codes of close frame.
+const WS_CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const WS_CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+
+/// Header of the frame sent by client.
+struct WsFrameHeader {
+    fin: bool,
+    rsv: u8,
+    opcode: u8,
+    masked: bool,
+    mask: [u8; WS_MASK_LEN],
+    /// Length of the header, including the mask.
+    header_len: usize,
+    payload_len: u64,
+}
+
+/// Data frame whose payload is being received.
+struct WsDataFrame {
+    mask: [u8; WS_MASK_LEN],
+    /// Bytes of payload which have been received.
+    offset: u64,
+    /// Bytes of payload which have not been received.
+    remaining: u64,
+}
+
+/// Io channel which carries the RFB data in the binary frames of websocket.
+pub struct WsIoChannel {
+    /// Channel under websocket, which is tcp or tls.
+    channel: Rc<RefCell<dyn IoOperations>>,
+    /// Data received from channel which has not been decoded.
+    input: Vec<u8>,
+    /// Data frame whose payload is being received.
+    frame: Option<WsDataFrame>,
+    /// A fragmented message is being received.
+    in_message: bool,
+    /// Data which has not been written to channel.
+    output: Vec<u8>,
+    /// Payload of the latest ping which has not been replied. The pong is
+    /// queued after the pending output, and only the latest ping is replied.
+    pong: Option<Vec<u8>>,
+    /// Close frame has been sent.
+    closed: bool,
+}
+
+impl WsIoChannel {
+    pub fn new(channel: Rc<RefCell<dyn IoOperations>>) -> Self {
+        WsIoChannel {
+            channel,
+            input: Vec::new(),
+            frame: None,
+            in_message: false,
+            output: Vec::new(),
+            pong: None,
+            closed: false,
+        }
+    }
+
+    /// Queue the data which is sent without framing, such as the handshake response.
+    pub fn write_raw(&mut self, buf: &[u8]) {
+        self.output.extend_from_slice(buf);
+    }
+
+    /// Write the queued data to channel, return whether all of it has been written.
+    fn flush_output(&mut self) -> Result<bool> {
+        loop {
+            if self.output.is_empty() {
+                match self.pong.take() {
+                    Some(payload) => ws_encode_frame(WS_OPCODE_PONG, &payload, &mut self.output),
+                    None => return Ok(true),
+                }
+            }
+            let len = self.channel.borrow_mut().channel_write(&self.output)?;
+            self.output.drain(..len);
+            if !self.output.is_empty() {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Send the close frame and stop the websocket.
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - Status code of close frame, it may be empty.
+    /// * `reason` - Reason why the websocket is closed.
+    fn close(&mut self, status: &[u8], reason: &str) -> Result<usize> {
+        ws_encode_frame(WS_OPCODE_CLOSE, status, &mut self.output);
+        self.pong = None;
+        self.closed = true;
+        self.flush_output()?;
+        Err(anyhow!(VncError::WebSocketFailed(reason.to_string())))
+    }
+
+    /// Decode the received frames. The payload of data frames is appended to
+    /// buf as soon as it is received, the control frames are handled once the
+    /// whole frame is received.
+    fn decode(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let mut len = 0_usize;
+        loop {
+            if let Some(frame) = self.frame.as_mut() {
+                let size = cmp::min(frame.remaining, self.input.len() as u64) as usize;
+                for (i, byte) in self.input.drain(..size).enumerate() {
+                    let index = (frame.offset + i as u64) % WS_MASK_LEN as u64;
+                    buf.push(byte ^ frame.mask[index as usize]);
+                }
+                frame.offset += size as u64;
+                frame.remaining -= size as u64;
+                len += size;
+                if frame.remaining > 0 {
+                    break;
+                }
+                self.frame = None;
+            }
+
+            let header = match ws_parse_frame_header(&self.input) {
+                Some(header) => header,
+                None => break,
+            };
+            let status = WS_CLOSE_PROTOCOL_ERROR.to_be_bytes();
+            if header.rsv != 0 {
+                return self.close(&status, "reserved bits are set");
+            }
+            if !header.masked {
+                return self.close(&status, "frame of client is not masked");
+            }
+            if header.payload_len >> 63 != 0 {
+                return self.close(&status, "invalid payload length");
+            }
+
+            match header.opcode {
+                WS_OPCODE_BINARY | WS_OPCODE_CONTINUATION => {
+                    if header.opcode == WS_OPCODE_BINARY && self.in_message {
+                        return self.close(&status, "fragmented message is not finished");
+                    }
+                    if header.opcode == WS_OPCODE_CONTINUATION && !self.in_message {
+                        return self.close(&status, "no fragmented message to continue");
+                    }
+                    self.in_message = !header.fin;
+                    self.input.drain(..header.header_len);
+                    self.frame = Some(WsDataFrame {
+                        mask: header.mask,
+                        offset: 0,
+                        remaining: header.payload_len,
+                    });
+                }
+                WS_OPCODE_CLOSE | WS_OPCODE_PING | WS_OPCODE_PONG => {
+                    if !header.fin || header.payload_len > WS_CONTROL_PAYLOAD_MAX {
+                        return self.close(&status, "invalid control frame");
+                    }
+                    let frame_len = header.header_len + header.payload_len as usize;
+                    if self.input.len() < frame_len {
+                        break;
+                    }
+                    let payload: Vec<u8> = self
+                        .input
+                        .drain(..frame_len)
+                        .skip(header.header_len)
+                        .enumerate()
+                        .map(|(i, byte)| byte ^ header.mask[i % WS_MASK_LEN])
+                        .collect();
+                    match header.opcode {
+                        WS_OPCODE_CLOSE => {
+                            // Reply the close frame with the status code of client.
+                            let status = payload[..cmp::min(payload.len(), 2)].to_vec();
+                            return self.close(&status, "websocket is closed by client");
+                        }
+                        WS_OPCODE_PING => {
+                            self.pong = Some(payload);
+                        }
+                        // Unsolicited pong is ignored.
+                        _ => {}
+                    }
+                }
+                WS_OPCODE_TEXT => {
+                    let status = WS_CLOSE_UNSUPPORTED_DATA.to_be_bytes();
+                    return self.close(&status, "text frame is not supported");
+                }
+                opcode => {
+                    return self.close(&status, &format!("unknown opcode {}", opcode));
+                }
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl IoOperations for WsIoChannel {
+    fn channel_write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.closed {
+            return Err(anyhow!(VncError::WebSocketFailed(
+                "websocket is closed".to_string()
+            )));
+        }
+        // Nothing is framed until the previous frames are written, so that
+        // the output is still throttled by the buffer of client.
+        if !self.flush_output()? {
+            return Ok(0);
+        }
+        ws_encode_frame(WS_OPCODE_BINARY, buf, &mut self.output);
+        self.flush_output()?;
+        Ok(buf.len())
+    }
+
+    fn channel_read(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let mut data = Vec::new();
+        let len = self.channel.borrow_mut().channel_read(&mut data)?;
+        self.input.extend_from_slice(&data[..len]);
+        self.decode(buf)
+    }
+
+    fn channel_flush(&mut self) -> Result<bool> {
+        self.flush_output()
+    }
+
+    fn tls_ssf(&self) -> u32 {
+        self.channel.borrow().tls_ssf()
+    }
+}
+
+/// Parse the frame header at the beginning of data, return None if the
+/// header has not been received completely.
+fn ws_parse_frame_header(data: &[u8]) -> Option<WsFrameHeader> {
+    if data.len() < 2 {
+        return None;
+    }
+    let (payload_len, len_size) = match data[1] & WS_HEAD_PAYLOAD_LEN {
+        WS_PAYLOAD_LEN_16 => {
+            let bytes = data.get(2..4)?;
+            (u16::from_be_bytes([bytes[0], bytes[1]]) as u64, 2)
+        }
+        WS_PAYLOAD_LEN_64 => {
+            let mut bytes = [0_u8; 8];
+            bytes.copy_from_slice(data.get(2..10)?);
+            (u64::from_be_bytes(bytes), 8)
+        }
+        len => (len as u64, 0),
+    };
+    let masked = data[1] & WS_HEAD_MASK != 0;
+    let mut header_len = 2 + len_size;
+    let mut mask = [0_u8; WS_MASK_LEN];
+    if masked {
+        mask.copy_from_slice(data.get(header_len..header_len + WS_MASK_LEN)?);
+        header_len += WS_MASK_LEN;
+    }
+
+    Some(WsFrameHeader {
+        fin: data[0] & WS_HEAD_FIN != 0,
+        rsv: data[0] & WS_HEAD_RSV,
+        opcode: data[0] & WS_HEAD_OPCODE,
+        masked,
+        mask,
+        header_len,
+        payload_len,
+    })
+}
+
+/// Encode the frame sent to client, which is not masked.
+///
+/// # Arguments
+///
+/// * `opcode` - Opcode of frame.
+/// * `payload` - Payload of frame.
+/// * `buf` - Buffer which the frame is appended to.
+pub fn ws_encode_frame(opcode: u8, payload: &[u8], buf: &mut Vec<u8>) {
+    buf.push(WS_HEAD_FIN | opcode);
+    let len = payload.len();
+    if len < WS_PAYLOAD_LEN_16 as usize {
+        buf.push(len as u8);
+    } else if len <= u16::MAX as usize {
+        buf.push(WS_PAYLOAD_LEN_16);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        buf.push(WS_PAYLOAD_LEN_64);
+        buf.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    buf.extend_from_slice(payload);
+}
+
+/// Encode the frame sent by client, whose payload is masked.
+#[cfg(test)]
+pub fn ws_encode_client_frame(opcode: u8, fin: bool, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    ws_encode_frame(opcode, payload, &mut buf);
+    if !fin {
+        buf[0] &= !WS_HEAD_FIN;
+    }
+    buf[1] |= WS_HEAD_MASK;
+    let header_len = buf.len() - payload.len();
+    let masked: Vec<u8> = buf
+        .drain(header_len..)
+        .enumerate()
+        .map(|(i, byte)| byte ^ mask[i % WS_MASK_LEN])
+        .collect();
+    buf.extend_from_slice(&mask);
+    buf.extend_from_slice(&masked);
+    buf
+}
+
+/// Compute the value of Sec-WebSocket-Accept with the key of client.
+pub fn ws_accept_key(key: &str) -> String {
+    let mut ctx = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+    ctx.update(key.as_bytes());
+    ctx.update(WS_GUID.as_bytes());
+    STANDARD.encode(ctx.finish())
+}
+
+/// Check the http upgrade request of client, and make the response of handshake.
+///
+/// # Arguments
+///
+/// * `request` - Http request, which ends with an empty line.
+pub fn ws_handshake_response(request: &[u8]) -> Result<Vec<u8>> {
+    let request = String::from_utf8_lossy(request);
+    let mut lines = request.split("\r\n");
+    let request_line: Vec<&str> = lines.next().unwrap_or_default().split(' ').collect();
+    if request_line.len() != 3 || request_line[0] != "GET" || request_line[2] != "HTTP/1.1" {
+        return Err(anyhow!(VncError::WebSocketFailed(
+            "invalid request line".to_string()
+        )));
+    }
+
+    let mut headers = HashMap::new();
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or_else(|| {
+            anyhow!(VncError::WebSocketFailed(format!(
+                "invalid header {}",
+                line
+            )))
+        })?;
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim());
+    }
+    let header = |name: &str| headers.get(name).copied().unwrap_or_default();
+
+    if !header("upgrade").eq_ignore_ascii_case("websocket") {
+        return Err(anyhow!(VncError::WebSocketFailed(
+            "not upgraded to websocket".to_string()
+        )));
+    }
+    if !header("connection")
+        .split(',')
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+    {
+        return Err(anyhow!(VncError::WebSocketFailed(
+            "connection is not upgraded".to_string()
+        )));
+    }
+    if header("sec-websocket-version") != WS_VERSION {
+        return Err(anyhow!(VncError::WebSocketFailed(format!(
+            "unsupported version {}",
+            header("sec-websocket-version")
+        ))));
+    }
+    let key = header("sec-websocket-key");
+    if STANDARD
+        .decode(key)
+        .map_or(true, |nonce| nonce.len() != WS_KEY_LEN)
+    {
+        return Err(anyhow!(VncError::WebSocketFailed(format!(
+            "invalid key {}",
+            key
+        ))));
+    }
+
+    let mut response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n",
+        ws_accept_key(key)
+    );
+    // Subprotocol is optional, but only the binary one is supported.
+    if let Some(protocols) = headers.get("sec-websocket-protocol") {
+        if !protocols
+            .split(',')
+            .any(|protocol| protocol.trim() == WS_PROTOCOL_BINARY)
+        {
+            return Err(anyhow!(VncError::WebSocketFailed(format!(
+                "unsupported subprotocol {}",
+                protocols
+            ))));
+        }
+        response += &format!("Sec-WebSocket-Protocol: {}\r\n", WS_PROTOCOL_BINARY);
+    }
+    response += "\r\n";
+    Ok(response.into_bytes())
+}
+
+impl ClientIoHandler {
+    /// Start the connection of websocket client. The handshake of websocket
+    /// is done in the tls channel if the tls credentials are configured.
+    pub fn ws_start(&mut self) -> Result<()> {
+        self.websocket = true;
+        self.update_event_handler(1, ClientIoHandler::handle_ws_handshake);
+        if self.server.security_type.borrow().tls_config.is_some() {
+            self.tls_start(ClientIoHandler::ws_tls_handshake_done)?;
+        }
+        Ok(())
+    }
+
+    /// The tls handshake of websocket client is finished.
+    fn ws_tls_handshake_done(&mut self, peer_certs: Option<Vec<Certificate>>) -> Result<()> {
+        self.tls_restore_io_handler()?;
+        if let Err(reason) = self.tls_authorize(peer_certs) {
+            // No message is able to carry the reason before the handshake of websocket.
+            error!("Vnc client {} is rejected: {}", self.client.addr, reason);
+            return Err(anyhow!(VncError::AuthFailed(
+                "ws_tls_handshake_done".to_string(),
+                reason
+            )));
+        }
+        // The request may be received together with the end of tls handshake.
+        self.client_handle_read()
+    }
+
+    /// Reply the http upgrade request of client, then the RFB protocol starts
+    /// in the frames of websocket.
+    pub fn handle_ws_handshake(&mut self) -> Result<()> {
+        let client = self.client.clone();
+        let mut locked_buffer = client.in_buffer.lock().unwrap();
+        let buf_len = locked_buffer.len();
+        let mut buf = vec![0_u8; cmp::min(buf_len, WS_HANDSHAKE_MAX_LEN)];
+        let len = buf.len();
+        locked_buffer.read_front(&mut buf, len);
+        drop(locked_buffer);
+
+        let request_len = match buf.windows(4).position(|line| line == b"\r\n\r\n") {
+            Some(pos) => pos + 4,
+            None if len < WS_HANDSHAKE_MAX_LEN => {
+                // Wait for the rest of request.
+                self.expect = len + 1;
+                return Ok(());
+            }
+            None => {
+                return self.ws_reject(anyhow!(VncError::WebSocketFailed(
+                    "request is too long".to_string()
+                )));
+            }
+        };
+        // Client must wait for the response before sending the frames.
+        if buf_len > request_len {
+            return self.ws_reject(anyhow!(VncError::WebSocketFailed(
+                "unexpected data after request".to_string()
+            )));
+        }
+        let response = match ws_handshake_response(&buf[..request_len]) {
+            Ok(response) => response,
+            Err(e) => return self.ws_reject(e),
+        };
+
+        let mut ws_channel = WsIoChannel::new(self.io_channel.clone());
+        ws_channel.write_raw(&response);
+        self.io_channel = Rc::new(RefCell::new(ws_channel));
+        vnc_write(&client, "RFB 003.008\n".as_bytes().to_vec());
+        vnc_flush(&client);
+        self.expect = request_len;
+        self.update_event_handler(12, ClientIoHandler::handle_version);
+        Ok(())
+    }
+
+    /// Reply the invalid upgrade request of client.
+    fn ws_reject(&mut self, e: anyhow::Error) -> Result<()> {
+        self.reject_client(WS_BAD_REQUEST.as_bytes().to_vec(), &e.to_string());
+        Err(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    /// Channel in memory, which records the output and feeds the input.
+    #[derive(Default)]
+    struct MemChannel {
+        /// Max bytes accepted by each write, None means no limit.
+        write_limit: Option<usize>,
+        output: Vec<u8>,
+        input: Vec<u8>,
+    }
+
+    impl IoOperations for MemChannel {
+        fn channel_write(&mut self, buf: &[u8]) -> Result<usize> {
+            let len = cmp::min(buf.len(), self.write_limit.unwrap_or(buf.len()));
+            self.output.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn channel_read(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+            let len = self.input.len();
+            buf.append(&mut self.input);
+            Ok(len)
+        }
+    }
+
+    fn create_ws_channel() -> (WsIoChannel, Rc<RefCell<MemChannel>>) {
+        let channel = Rc::new(RefCell::new(MemChannel::default()));
+        (WsIoChannel::new(channel.clone()), channel)
+    }
+
+    fn ws_read(
+        ws_channel: &mut WsIoChannel,
+        channel: &RefCell<MemChannel>,
+        data: &[u8],
+    ) -> Result<Vec<u8>> {
+        channel.borrow_mut().input.extend_from_slice(data);
+        let mut buf = Vec::new();
+        let len = ws_channel.channel_read(&mut buf)?;
+        assert_eq!(len, buf.len());
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_ws_accept_key() {
+        // Example in RFC 6455.
+        assert_eq!(
+            ws_accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_ws_handshake_response() {
+        let request = "GET /websockify HTTP/1.1\r\n\
+                       Host: server.example.com\r\n\
+                       Upgrade: websocket\r\n\
+                       Connection: keep-alive, Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Protocol: binary, base64\r\n\
+                       Sec-WebSocket-Version: 13\r\n\r\n";
+        let response = ws_handshake_response(request.as_bytes()).unwrap();
+        assert_eq!(
+            String::from_utf8(response).unwrap(),
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+             Sec-WebSocket-Protocol: binary\r\n\r\n"
+        );
+
+        // The subprotocol is not replied if client does not request it.
+        let request = request.replace("Sec-WebSocket-Protocol: binary, base64\r\n", "");
+        let response = ws_handshake_response(request.as_bytes()).unwrap();
+        assert!(!String::from_utf8(response)
+            .unwrap()
+            .contains("Sec-WebSocket-Protocol"));
+
+        let invalid_requests = [
+            request.replace("GET", "POST"),
+            request.replace("HTTP/1.1", "HTTP/1.0"),
+            request.replace("Upgrade: websocket", "Upgrade: h2c"),
+            request.replace("keep-alive, Upgrade", "keep-alive"),
+            request.replace("Version: 13", "Version: 8"),
+            request.replace("dGhlIHNhbXBsZSBub25jZQ==", "c2hvcnQ="),
+            request.replace("Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n", ""),
+            request.replace("Host: server.example.com", "Host"),
+            request.replace(
+                "Version: 13\r\n",
+                "Version: 13\r\nSec-WebSocket-Protocol: base64\r\n",
+            ),
+        ];
+        for request in invalid_requests {
+            assert!(ws_handshake_response(request.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_ws_encode_frame() {
+        let mut buf = Vec::new();
+        ws_encode_frame(WS_OPCODE_BINARY, b"RFB", &mut buf);
+        assert_eq!(buf, [&[0x82, 3][..], b"RFB"].concat());
+
+        let payload = vec![0x5a_u8; 300];
+        let mut buf = Vec::new();
+        ws_encode_frame(WS_OPCODE_BINARY, &payload, &mut buf);
+        assert_eq!(buf[..4], [0x82, 126, 0x01, 0x2c]);
+        assert_eq!(buf[4..], payload);
+
+        let payload = vec![0x5a_u8; 65536];
+        let mut buf = Vec::new();
+        ws_encode_frame(WS_OPCODE_BINARY, &payload, &mut buf);
+        assert_eq!(buf[..10], [0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(buf.len(), 10 + payload.len());
+    }
+
+    #[test]
+    fn test_ws_read_frames() {
+        let (mut ws_channel, channel) = create_ws_channel();
+
+        // The payload is decoded as soon as it is received.
+        let frame = ws_encode_client_frame(WS_OPCODE_BINARY, true, b"RFB 003.008\n", TEST_MASK);
+        let mut data = Vec::new();
+        for byte in frame {
+            data.append(&mut ws_read(&mut ws_channel, &channel, &[byte]).unwrap());
+        }
+        assert_eq!(data, b"RFB 003.008\n");
+
+        // Fragmented message with a ping in the middle, the ping is replied.
+        let mut frames = ws_encode_client_frame(WS_OPCODE_BINARY, false, b"\x01", TEST_MASK);
+        frames.append(&mut ws_encode_client_frame(
+            WS_OPCODE_PING,
+            true,
+            b"hi",
+            TEST_MASK,
+        ));
+        frames.append(&mut ws_encode_client_frame(
+            WS_OPCODE_CONTINUATION,
+            false,
+            b"",
+            TEST_MASK,
+        ));
+        frames.append(&mut ws_encode_client_frame(
+            WS_OPCODE_CONTINUATION,
+            true,
+            b"\x02\x03",
+            TEST_MASK,
+        ));
+        frames.append(&mut ws_encode_client_frame(
+            WS_OPCODE_PONG,
+            true,
+            b"",
+            TEST_MASK,
+        ));
+        let (head, tail) = frames.split_at(5);
+        let mut data = ws_read(&mut ws_channel, &channel, head).unwrap();
+        data.append(&mut ws_read(&mut ws_channel, &channel, tail).unwrap());
+        assert_eq!(data, [1, 2, 3]);
+        assert!(channel.borrow().output.is_empty());
+        assert!(ws_channel.channel_flush().unwrap());
+        assert_eq!(channel.borrow().output, [0x8a, 2, b'h', b'i']);
+
+        // Payload with 16-bit length.
+        let payload: Vec<u8> = (0..=255).collect();
+        let frame = ws_encode_client_frame(WS_OPCODE_BINARY, true, &payload, TEST_MASK);
+        assert_eq!(ws_read(&mut ws_channel, &channel, &frame).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_ws_pong_coalesced() {
+        let (mut ws_channel, channel) = create_ws_channel();
+
+        // Client doesn't read, the frame is pending.
+        channel.borrow_mut().write_limit = Some(0);
+        assert_eq!(ws_channel.channel_write(b"abc").unwrap(), 3);
+        assert!(!ws_channel.channel_flush().unwrap());
+
+        // Pings are not queued up behind the pending frame, only the latest one is replied.
+        let mut frames = Vec::new();
+        for i in 0..100_u8 {
+            frames.append(&mut ws_encode_client_frame(
+                WS_OPCODE_PING,
+                true,
+                &[i],
+                TEST_MASK,
+            ));
+        }
+        assert!(ws_read(&mut ws_channel, &channel, &frames)
+            .unwrap()
+            .is_empty());
+        assert!(!ws_channel.channel_flush().unwrap());
+        assert_eq!(ws_channel.output.len(), 5);
+        assert!(channel.borrow().output.is_empty());
+
+        channel.borrow_mut().write_limit = None;
+        assert!(ws_channel.channel_flush().unwrap());
+        assert_eq!(
+            channel.borrow().output,
+            [0x82, 3, b'a', b'b', b'c', 0x8a, 1, 99]
+        );
+    }
+
+    #[test]
+    fn test_ws_close() {
+        // Close frame of client is replied with its status code.
+        let (mut ws_channel, channel) = create_ws_channel();
+        let frame = ws_encode_client_frame(WS_OPCODE_CLOSE, true, b"\x03\xe8bye", TEST_MASK);
+        assert!(ws_read(&mut ws_channel, &channel, &frame).is_err());
+        assert_eq!(channel.borrow().output, [0x88, 2, 0x03, 0xe8]);
+        assert!(ws_channel.channel_write(b"RFB").is_err());
+
+        let protocol_error = [0x88, 2, 0x03, 0xea];
+        let invalid_frames = [
+            // Frame is not masked.
+            ([&[0x82, 1][..], b"a"].concat(), protocol_error),
+            // Reserved bits are set.
+            (
+                ws_encode_client_frame(0x40 | WS_OPCODE_BINARY, true, b"a", TEST_MASK),
+                protocol_error,
+            ),
+            // No message to continue.
+            (
+                ws_encode_client_frame(WS_OPCODE_CONTINUATION, true, b"a", TEST_MASK),
+                protocol_error,
+            ),
+            // Fragmented control frame.
+            (
+                ws_encode_client_frame(WS_OPCODE_PING, false, b"", TEST_MASK),
+                protocol_error,
+            ),
+            // Control frame is too long.
+            (
+                ws_encode_client_frame(WS_OPCODE_PING, true, &[0; 126], TEST_MASK),
+                protocol_error,
+            ),
+            // Unknown opcode.
+            (
+                ws_encode_client_frame(0x3, true, b"a", TEST_MASK),
+                protocol_error,
+            ),
+            // Text is unsupported data.
+            (
+                ws_encode_client_frame(WS_OPCODE_TEXT, true, b"a", TEST_MASK),
+                [0x88, 2, 0x03, 0xeb],
+            ),
+        ];
+        for (frame, reply) in invalid_frames {
+            let (mut ws_channel, channel) = create_ws_channel();
+            assert!(ws_read(&mut ws_channel, &channel, &frame).is_err());
+            assert_eq!(channel.borrow().output, reply);
+        }
+
+        // New message is started before the fragmented one is finished.
+        let (mut ws_channel, channel) = create_ws_channel();
+        let mut frames = ws_encode_client_frame(WS_OPCODE_BINARY, false, b"a", TEST_MASK);
+        frames.append(&mut ws_encode_client_frame(
+            WS_OPCODE_BINARY,
+            true,
+            b"b",
+            TEST_MASK,
+        ));
+        assert!(ws_read(&mut ws_channel, &channel, &frames).is_err());
+        assert_eq!(channel.borrow().output, protocol_error);
+    }
+
+    #[test]
+    fn test_ws_write() {
+        let (mut ws_channel, channel) = create_ws_channel();
+        ws_channel.write_raw(b"HTTP/1.1 101 Switching Protocols\r\n\r\n");
+        channel.borrow_mut().write_limit = Some(8);
+
+        // Nothing is framed until the queued data is written.
+        assert_eq!(ws_channel.channel_write(b"RFB").unwrap(), 0);
+        assert!(!ws_channel.channel_flush().unwrap());
+        channel.borrow_mut().write_limit = None;
+        assert_eq!(ws_channel.channel_write(b"RFB").unwrap(), 3);
+        assert!(ws_channel.channel_flush().unwrap());
+        assert_eq!(
+            channel.borrow().output,
+            b"HTTP/1.1 101 Switching Protocols\r\n\r\n\x82\x03RFB"
+        );
+    }
+}
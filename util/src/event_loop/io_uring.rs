@@ -0,0 +1,467 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use ::io_uring::{opcode, types, IoUring};
+use anyhow::{bail, Context, Result};
+use log::error;
+use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
+
+use crate::aio::Iovec;
+use crate::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+/// Offset which means the current position of file, it is required by the
+/// files which are not seekable, such as pipes and sockets.
+pub const IO_URING_OFFSET_CURRENT: u64 = u64::MAX;
+/// User data of the cancel requests, whose completions are not reported.
+const CANCEL_USER_DATA: u64 = u64::MAX;
+
+/// Operations supported by the io_uring event loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoUringOpcode {
+    Read,
+    Write,
+    Readv,
+    Writev,
+    Fsync,
+}
+
+/// Request submitted to the io_uring event loop.
+pub struct IoUringRequest {
+    pub opcode: IoUringOpcode,
+    pub fd: RawFd,
+    /// Buffers of the request, `Read` and `Write` use the first one only,
+    /// and `Fsync` uses none of them.
+    pub iovecs: Vec<Iovec>,
+    /// Offset of the file.
+    pub offset: u64,
+}
+
+/// Callback of the request with the result of it, which is the bytes
+/// transferred or the negative errno.
+pub type IoUringCallback = Box<dyn FnOnce(i64) + Send>;
+
+struct InflightRequest {
+    /// The request is kept, as the iovecs are used by kernel.
+    _req: IoUringRequest,
+    cb: IoUringCallback,
+}
+
+/// Event loop which submits the I/O requests to io_uring, and runs the
+/// callbacks of the completed ones. The completion is notified by an eventfd,
+/// so the loop is driven by the epoll based `EventLoopContext` with the
+/// notifiers of it, or by `wait` directly.
+pub struct IoUringEventLoop {
+    ring: IoUring,
+    /// Eventfd notified by kernel when requests are completed.
+    eventfd: EventFd,
+    /// Requests submitted and not completed, indexed by user data.
+    inflight: HashMap<u64, InflightRequest>,
+    /// User data of the next request.
+    next_user_data: u64,
+}
+
+impl IoUringEventLoop {
+    /// Create the event loop.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - Size of the submission queue, must be the power of 2.
+    pub fn new(entries: u32) -> Result<Self> {
+        if !entries.is_power_of_two() {
+            bail!("Entries must be the power of 2 and larger than 0");
+        }
+        let ring = IoUring::new(entries).with_context(|| "Failed to create io_uring instance")?;
+        let eventfd = EventFd::new(libc::EFD_NONBLOCK)?;
+        ring.submitter()
+            .register_eventfd(eventfd.as_raw_fd())
+            .with_context(|| "Failed to register event fd")?;
+        Ok(IoUringEventLoop {
+            ring,
+            eventfd,
+            inflight: HashMap::new(),
+            next_user_data: 0,
+        })
+    }
+
+    /// Raw fd of the eventfd which notifies the completions.
+    pub fn eventfd(&self) -> RawFd {
+        self.eventfd.as_raw_fd()
+    }
+
+    /// Number of the requests which are not completed.
+    pub fn inflight(&self) -> usize {
+        self.inflight.len()
+    }
+
+    /// Submit the request to kernel, the callback is called when the request
+    /// is completed.
+    ///
+    /// # Safety
+    ///
+    /// The memory of the buffers described by `req.iovecs` must be valid until
+    /// the callback is called. The requests which are not completed are
+    /// canceled when the event loop is dropped.
+    pub unsafe fn submit(&mut self, req: IoUringRequest, cb: IoUringCallback) -> Result<()> {
+        self.push(req, cb)?;
+        self.flush()?;
+        Ok(())
+    }
+
+    /// Queue the request in the submission queue without submitting it to
+    /// kernel, which is done by `flush` for a batch of requests.
+    ///
+    /// # Safety
+    ///
+    /// The same as `submit`.
+    pub unsafe fn push(&mut self, req: IoUringRequest, cb: IoUringCallback) -> Result<()> {
+        if req.opcode != IoUringOpcode::Fsync && req.iovecs.is_empty() {
+            bail!("No buffer in io_uring request");
+        }
+        let user_data = self.next_user_data;
+        let fd = types::Fd(req.fd);
+        let iovs = req.iovecs.as_ptr() as *const libc::iovec;
+        let iovs_len = req.iovecs.len() as u32;
+        let entry = match req.opcode {
+            IoUringOpcode::Read => {
+                let iov = &req.iovecs[0];
+                opcode::Read::new(fd, iov.iov_base as *mut u8, iov.iov_len as u32)
+                    .offset(req.offset)
+                    .build()
+            }
+            IoUringOpcode::Write => {
+                let iov = &req.iovecs[0];
+                opcode::Write::new(fd, iov.iov_base as *const u8, iov.iov_len as u32)
+                    .offset(req.offset)
+                    .build()
+            }
+            IoUringOpcode::Readv => opcode::Readv::new(fd, iovs, iovs_len)
+                .offset(req.offset)
+                .build(),
+            IoUringOpcode::Writev => opcode::Writev::new(fd, iovs, iovs_len)
+                .offset(req.offset)
+                .build(),
+            IoUringOpcode::Fsync => opcode::Fsync::new(fd).build(),
+        }
+        .user_data(user_data);
+
+        // SAFETY: the iovecs are kept in the inflight requests, and the
+        // memory of the buffers is guaranteed by the caller.
+        self.ring
+            .submission()
+            .push(&entry)
+            .with_context(|| "Failed to push entry")?;
+        self.next_user_data = self.next_user_data.wrapping_add(1);
+        if self.next_user_data == CANCEL_USER_DATA {
+            self.next_user_data = 0;
+        }
+        self.inflight
+            .insert(user_data, InflightRequest { _req: req, cb });
+        Ok(())
+    }
+
+    /// Submit the queued requests to kernel, return the number of them.
+    pub fn flush(&mut self) -> Result<usize> {
+        self.ring.submit().with_context(|| "Failed to submit sqe")
+    }
+
+    /// Run the callbacks of the completed requests, return the number of
+    /// them.
+    pub fn handle_completions(&mut self) -> usize {
+        let cqes: Vec<(u64, i32)> = self
+            .ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .filter(|(user_data, _)| *user_data != CANCEL_USER_DATA)
+            .collect();
+        for &(user_data, res) in &cqes {
+            match self.inflight.remove(&user_data) {
+                Some(req) => (req.cb)(res as i64),
+                None => error!("Unknown io_uring completion {}", user_data),
+            }
+        }
+        cqes.len()
+    }
+
+    /// Wait until at least `want` requests are completed, and run the
+    /// callbacks of the completed ones.
+    pub fn wait(&mut self, want: usize) -> Result<usize> {
+        self.ring
+            .submit_and_wait(want)
+            .with_context(|| "Failed to wait for io_uring completions")?;
+        Ok(self.handle_completions())
+    }
+}
+
+impl Drop for IoUringEventLoop {
+    fn drop(&mut self) {
+        // The buffers may be still used by kernel, so wait for the requests.
+        // Cancel them first, as some of them may never complete, such as the
+        // read of an idle pipe. The callbacks get `-ECANCELED` for them.
+        let user_datas: Vec<u64> = self.inflight.keys().copied().collect();
+        for user_data in user_datas {
+            let entry = opcode::AsyncCancel::new(user_data)
+                .build()
+                .user_data(CANCEL_USER_DATA);
+            // SAFETY: the cancel request takes no buffer.
+            while unsafe { self.ring.submission().push(&entry) }.is_err() {
+                // Make room in the submission queue.
+                if let Err(e) = self.flush() {
+                    error!("Failed to cancel io_uring requests: {:?}", e);
+                    break;
+                }
+            }
+        }
+        while !self.inflight.is_empty() {
+            if let Err(e) = self.wait(1) {
+                error!("Failed to drain io_uring requests: {:?}", e);
+                break;
+            }
+        }
+    }
+}
+
+impl EventNotifierHelper for IoUringEventLoop {
+    fn internal_notifiers(uring_loop: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let uring_loop_clone = uring_loop.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            uring_loop_clone.lock().unwrap().handle_completions();
+            None
+        });
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            uring_loop.lock().unwrap().eventfd.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loop_context::get_notifiers_fds;
+
+    fn pipe() -> (RawFd, RawFd) {
+        let mut fds = [0; 2];
+        // SAFETY: fds is valid for the 2 file descriptors.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        (fds[0], fds[1])
+    }
+
+    fn close(fds: &[RawFd]) {
+        for &fd in fds {
+            // SAFETY: fd is created by the test.
+            unsafe { libc::close(fd) };
+        }
+    }
+
+    fn iovec(buf: &mut [u8]) -> Iovec {
+        Iovec::new(buf.as_mut_ptr() as u64, buf.len() as u64)
+    }
+
+    fn result_cb(result: &Arc<Mutex<Option<i64>>>) -> IoUringCallback {
+        let result = result.clone();
+        Box::new(move |res| *result.lock().unwrap() = Some(res))
+    }
+
+    #[test]
+    fn test_uring_loop_read() {
+        let mut uring_loop = IoUringEventLoop::new(8).unwrap();
+        let (rfd, wfd) = pipe();
+        let data = b"stratovirt";
+        // SAFETY: data is valid for its length.
+        let ret = unsafe { libc::write(wfd, data.as_ptr() as *const libc::c_void, data.len()) };
+        assert_eq!(ret, data.len() as isize);
+
+        let mut buf = [0_u8; 16];
+        let result = Arc::new(Mutex::new(None));
+        let req = IoUringRequest {
+            opcode: IoUringOpcode::Read,
+            fd: rfd,
+            iovecs: vec![iovec(&mut buf)],
+            offset: IO_URING_OFFSET_CURRENT,
+        };
+        // SAFETY: the buffer is valid until the request is completed.
+        unsafe { uring_loop.submit(req, result_cb(&result)) }.unwrap();
+        assert_eq!(uring_loop.wait(1).unwrap(), 1);
+        assert_eq!(*result.lock().unwrap(), Some(data.len() as i64));
+        assert_eq!(&buf[..data.len()], data);
+        assert_eq!(uring_loop.inflight(), 0);
+
+        // Request without buffer.
+        let req = IoUringRequest {
+            opcode: IoUringOpcode::Read,
+            fd: rfd,
+            iovecs: Vec::new(),
+            offset: IO_URING_OFFSET_CURRENT,
+        };
+        // SAFETY: the request is rejected before it is submitted.
+        assert!(unsafe { uring_loop.submit(req, result_cb(&result)) }.is_err());
+        assert!(IoUringEventLoop::new(6).is_err());
+        close(&[rfd, wfd]);
+    }
+
+    #[test]
+    fn test_uring_loop_vectored() {
+        let mut uring_loop = IoUringEventLoop::new(8).unwrap();
+        let (rfd, wfd) = pipe();
+
+        let mut head = *b"io_";
+        let mut tail = *b"uring";
+        let written = Arc::new(Mutex::new(None));
+        let req = IoUringRequest {
+            opcode: IoUringOpcode::Writev,
+            fd: wfd,
+            iovecs: vec![iovec(&mut head), iovec(&mut tail)],
+            offset: IO_URING_OFFSET_CURRENT,
+        };
+        // SAFETY: the buffer is valid until the request is completed.
+        unsafe { uring_loop.submit(req, result_cb(&written)) }.unwrap();
+        assert_eq!(uring_loop.wait(1).unwrap(), 1);
+        assert_eq!(*written.lock().unwrap(), Some(8));
+
+        let mut buf1 = [0_u8; 2];
+        let mut buf2 = [0_u8; 6];
+        let read = Arc::new(Mutex::new(None));
+        let req = IoUringRequest {
+            opcode: IoUringOpcode::Readv,
+            fd: rfd,
+            iovecs: vec![iovec(&mut buf1), iovec(&mut buf2)],
+            offset: IO_URING_OFFSET_CURRENT,
+        };
+        // SAFETY: the buffer is valid until the request is completed.
+        unsafe { uring_loop.submit(req, result_cb(&read)) }.unwrap();
+        assert_eq!(uring_loop.wait(1).unwrap(), 1);
+        assert_eq!(*read.lock().unwrap(), Some(8));
+        assert_eq!(&buf1, b"io");
+        assert_eq!(&buf2, b"_uring");
+        close(&[rfd, wfd]);
+    }
+
+    #[test]
+    fn test_uring_loop_eventfd() {
+        let uring_loop = Arc::new(Mutex::new(IoUringEventLoop::new(8).unwrap()));
+        let (rfd, wfd) = pipe();
+
+        // The read is pending until the data is written to the pipe.
+        let mut buf = [0_u8; 4];
+        let read = Arc::new(Mutex::new(None));
+        let req = IoUringRequest {
+            opcode: IoUringOpcode::Read,
+            fd: rfd,
+            iovecs: vec![iovec(&mut buf)],
+            offset: IO_URING_OFFSET_CURRENT,
+        };
+        let mut locked_loop = uring_loop.lock().unwrap();
+        // SAFETY: the buffer is valid until the request is completed.
+        unsafe { locked_loop.submit(req, result_cb(&read)) }.unwrap();
+        assert_eq!(locked_loop.handle_completions(), 0);
+        assert_eq!(locked_loop.inflight(), 1);
+
+        let mut data = *b"ping";
+        let written = Arc::new(Mutex::new(None));
+        let req = IoUringRequest {
+            opcode: IoUringOpcode::Write,
+            fd: wfd,
+            iovecs: vec![iovec(&mut data)],
+            offset: IO_URING_OFFSET_CURRENT,
+        };
+        // SAFETY: the buffer is valid until the request is completed.
+        unsafe { locked_loop.submit(req, result_cb(&written)) }.unwrap();
+        let eventfd = locked_loop.eventfd.as_raw_fd();
+        drop(locked_loop);
+
+        // The completions are notified by the eventfd.
+        let notifiers = EventNotifierHelper::internal_notifiers(uring_loop.clone());
+        assert_eq!(get_notifiers_fds(&notifiers), vec![eventfd]);
+        let mut completed = 0;
+        while completed < 2 {
+            let mut fds = libc::pollfd {
+                fd: eventfd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: fds is valid for the eventfd.
+            assert_eq!(unsafe { libc::poll(&mut fds, 1, 5000) }, 1);
+            read_fd(eventfd);
+            completed += uring_loop.lock().unwrap().handle_completions();
+        }
+        assert_eq!(*written.lock().unwrap(), Some(4));
+        assert_eq!(*read.lock().unwrap(), Some(4));
+        assert_eq!(&buf, b"ping");
+        close(&[rfd, wfd]);
+    }
+
+    #[test]
+    fn test_uring_loop_drop() {
+        let mut uring_loop = IoUringEventLoop::new(8).unwrap();
+        let (rfd, wfd) = pipe();
+
+        // The read of the idle pipe never completes, it is canceled on drop.
+        let mut buf = [0_u8; 4];
+        let read = Arc::new(Mutex::new(None));
+        let req = IoUringRequest {
+            opcode: IoUringOpcode::Read,
+            fd: rfd,
+            iovecs: vec![iovec(&mut buf)],
+            offset: IO_URING_OFFSET_CURRENT,
+        };
+        // SAFETY: the buffer is valid until the event loop is dropped.
+        unsafe { uring_loop.submit(req, result_cb(&read)) }.unwrap();
+        assert_eq!(uring_loop.handle_completions(), 0);
+        drop(uring_loop);
+        assert_eq!(*read.lock().unwrap(), Some(-libc::ECANCELED as i64));
+        close(&[rfd, wfd]);
+    }
+
+    #[test]
+    fn test_uring_loop_fsync() {
+        let mut uring_loop = IoUringEventLoop::new(8).unwrap();
+        let file = vmm_sys_util::tempfile::TempFile::new().unwrap();
+
+        let mut data = *b"fsync";
+        let written = Arc::new(Mutex::new(None));
+        let synced = Arc::new(Mutex::new(None));
+        let req = IoUringRequest {
+            opcode: IoUringOpcode::Write,
+            fd: file.as_file().as_raw_fd(),
+            iovecs: vec![iovec(&mut data)],
+            offset: 0,
+        };
+        // SAFETY: the buffer is valid until the request is completed.
+        unsafe { uring_loop.push(req, result_cb(&written)) }.unwrap();
+        assert_eq!(uring_loop.inflight(), 1);
+        assert_eq!(uring_loop.flush().unwrap(), 1);
+        assert_eq!(uring_loop.wait(1).unwrap(), 1);
+        let req = IoUringRequest {
+            opcode: IoUringOpcode::Fsync,
+            fd: file.as_file().as_raw_fd(),
+            iovecs: Vec::new(),
+            offset: 0,
+        };
+        // SAFETY: the request takes no buffer.
+        unsafe { uring_loop.submit(req, result_cb(&synced)) }.unwrap();
+        assert_eq!(uring_loop.wait(1).unwrap(), 1);
+        assert_eq!(*written.lock().unwrap(), Some(5));
+        assert_eq!(*synced.lock().unwrap(), Some(0));
+        assert_eq!(file.as_file().metadata().unwrap().len(), 5);
+    }
+}
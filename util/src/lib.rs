@@ -20,6 +20,7 @@ pub mod daemonize;
 pub mod device_tree;
 pub mod edid;
 pub mod error;
+pub mod event_loop;
 pub mod file;
 pub mod leak_bucket;
 mod link_list;
@@ -341,6 +341,20 @@ impl BpfRule {
         self
     }
 
+    /// Check whether the syscall is allowed when its argument `args_num`
+    /// equals `args_value`.
+    ///
+    /// # Arguments
+    /// * `args_num` - The index number of system call's arguments.
+    /// * `args_value` - The value of the argument to check.
+    pub fn allows_arg(&self, args_num: u32, args_value: u32) -> bool {
+        self.inner_rules.chunks(3).any(|rule| {
+            rule.len() == 3
+                && rule[0] == bpf_stmt(BPF_LD + BPF_W + BPF_ABS, SeccompData::args(args_num))
+                && rule[1] == bpf_jump(BPF_JMP + BPF_JEQ + BPF_K, args_value, 0, 1)
+        })
+    }
+
     /// Change `BpfRules` to a list of `SockFilter`. It will be used when
     /// seccomp taking effect.
     fn as_vec(&mut self) -> Vec<SockFilter> {
@@ -589,4 +603,16 @@ mod tests {
 
         assert_eq!(seccomp_filter.sock_filters, bpf_vec);
     }
+
+    #[test]
+    fn test_allows_arg() {
+        let rule = BpfRule::new(libc::SYS_ioctl)
+            .add_constraint(SeccompCmpOpt::Eq, 1, 0xAE80)
+            .add_constraint(SeccompCmpOpt::Ne, 1, 0xAE9A);
+
+        assert!(rule.allows_arg(1, 0xAE80));
+        assert!(!rule.allows_arg(0, 0xAE80));
+        assert!(!rule.allows_arg(1, 0xAE9A));
+        assert!(!BpfRule::new(libc::SYS_ioctl).allows_arg(1, 0xAE80));
+    }
 }
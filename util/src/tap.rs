@@ -134,7 +134,11 @@ impl Tap {
     }
 
     pub fn has_ufo(&self) -> bool {
-        let flags = TUN_F_CSUM | TUN_F_UFO;
+        self.has_offload(TUN_F_CSUM | TUN_F_UFO)
+    }
+
+    /// Probe if the kernel tap driver supports the offload `flags`.
+    pub fn has_offload(&self, flags: u32) -> bool {
         (unsafe { ioctl_with_val(&self.file, TUNSETOFFLOAD(), flags as libc::c_ulong) }) >= 0
     }
 
@@ -74,6 +74,7 @@ pub fn parse_unix_uri(uri: &str) -> Result<String> {
 /// * `read_only` - Allow to write or not.
 /// * `is_share` - Share the mapping or not.
 /// * `dump_guest_core` - Exclude from a core dump or not.
+/// * `prefault` - Populate page tables of the mapping in advance or not.
 ///
 /// # Errors
 ///
@@ -85,6 +86,7 @@ pub fn do_mmap(
     read_only: bool,
     is_share: bool,
     dump_guest_core: bool,
+    prefault: bool,
 ) -> Result<u64> {
     let mut flags: i32 = 0;
     let mut fd: i32 = -1;
@@ -100,6 +102,10 @@ pub fn do_mmap(
         flags |= libc::MAP_PRIVATE;
     }
 
+    if prefault {
+        flags |= libc::MAP_POPULATE;
+    }
+
     let mut prot = libc::PROT_READ;
     if !read_only {
         prot |= libc::PROT_WRITE;
@@ -17,7 +17,9 @@ use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::{Arc, Mutex, Weak};
 
 use crate::VfioError;
-use address_space::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region, RegionOps};
+use address_space::{
+    AddressSpace, FileBackend, GuestAddress, HostMemMapping, HostMemOptions, Region, RegionOps,
+};
 use anyhow::{anyhow, bail, Context, Result};
 use byteorder::{ByteOrder, LittleEndian};
 use hypervisor::kvm::{MsiVector, KVM_FDS};
@@ -697,9 +699,11 @@ impl VfioPciDevice {
                     None,
                     mmap.size,
                     fb,
-                    false,
-                    true,
-                    read_only,
+                    HostMemOptions {
+                        is_share: true,
+                        read_only,
+                        ..Default::default()
+                    },
                 )?;
 
                 let ram_device = Region::init_ram_device_region(Arc::new(host_mmap), "VfioRam");
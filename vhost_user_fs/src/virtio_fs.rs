@@ -33,7 +33,9 @@ use super::fs::FileSystem;
 use super::fuse_req::FuseReq;
 use super::vhost_user_server::VhostUserReqHandler;
 use crate::cmdline::FsConfig;
-use address_space::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region};
+use address_space::{
+    AddressSpace, FileBackend, GuestAddress, HostMemMapping, HostMemOptions, Region,
+};
 use machine_manager::event_loop::EventLoop;
 use util::loop_context::{
     gen_delete_notifiers, read_fd, EventNotifier, EventNotifierHelper, NotifierCallback,
@@ -283,15 +285,7 @@ impl VhostUserReqHandler for VirtioFs {
             };
 
             let mmap = Arc::new(
-                HostMemMapping::new(
-                    GuestAddress(region_config.guest_phys_addr),
-                    None,
-                    region_config.memory_size,
-                    Some(fileback),
-                    false,
-                    true,
-                    false,
-                )
+                HostMemMapping::new(GuestAddress(region_config.guest_phys_addr), None, region_config.memory_size, Some(fileback), HostMemOptions { is_share: true, ..Default::default() })
                     .with_context(||
                         format!("Failed to create the mapping of host memory for setting mem table, addr: 0x{:X}, size: {}, offset: {}",
                                 region_config.guest_phys_addr, region_config.memory_size, region_config.mmap_offset,
@@ -1247,7 +1247,7 @@ mod tests {
     pub use super::*;
     pub use crate::*;
 
-    use address_space::{AddressRange, HostMemMapping, Region};
+    use address_space::{AddressRange, HostMemMapping, HostMemOptions, Region};
 
     const MEMORY_SIZE: u64 = 1024 * 1024;
     const QUEUE_SIZE: u16 = 256;
@@ -1261,9 +1261,7 @@ mod tests {
                 None,
                 MEMORY_SIZE,
                 None,
-                false,
-                false,
-                false,
+                HostMemOptions::default(),
             )
             .unwrap(),
         );
@@ -1279,7 +1277,14 @@ mod tests {
 
     fn create_flat_range(addr: u64, size: u64, offset_in_region: u64) -> FlatRange {
         let mem_mapping = Arc::new(
-            HostMemMapping::new(GuestAddress(addr), None, size, None, false, false, false).unwrap(),
+            HostMemMapping::new(
+                GuestAddress(addr),
+                None,
+                size,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
         );
         FlatRange {
             addr_range: AddressRange::new(
@@ -1681,6 +1686,40 @@ mod tests {
         assert!(!btp.is_full(65));
     }
 
+    #[test]
+    fn test_balloon_reset() {
+        let bln_cfg = BalloonConfig {
+            id: "bln".to_string(),
+            deflate_on_oom: true,
+            free_page_reporting: Default::default(),
+            auto_balloon: true,
+            membuf_percent: 0,
+            monitor_interval: 0,
+        };
+        let mem_space = address_space_init();
+        let bln = Arc::new(Mutex::new(Balloon::new(&bln_cfg, mem_space.clone())));
+        let features = bln.lock().unwrap().device_features;
+        bln.lock().unwrap().set_num_pages(16);
+        let mut mmio_dev = VirtioMmioDevice::new(&mem_space, bln.clone());
+        mmio_dev.setup_driver(features);
+        assert_eq!(bln.lock().unwrap().driver_features, features);
+        for queue in 0..bln.lock().unwrap().queue_num() {
+            assert!(mmio_dev.queue_ready(queue));
+        }
+
+        // The target of the message queue is dropped with the negotiated features.
+        mmio_dev.reset_device().unwrap();
+        let locked_bln = bln.lock().unwrap();
+        assert_eq!(locked_bln.driver_features, 0);
+        assert_eq!(locked_bln.get_driver_features(0), 0);
+        assert_eq!(locked_bln.get_driver_features(1), 0);
+        assert_eq!(locked_bln.device_features, features);
+        assert_eq!(locked_bln.num_pages, 0);
+        for queue in 0..locked_bln.queue_num() {
+            assert!(!mmio_dev.queue_ready(queue));
+        }
+    }
+
     #[test]
     fn test_balloon_init_free_page_reporting() {
         let bln_cfg = BalloonConfig {
@@ -320,6 +320,10 @@ impl HardWareOperations for GpuOpts {
             );
         }
     }
+
+    fn hw_ui_resizable(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Default, Clone)]
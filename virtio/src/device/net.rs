@@ -34,15 +34,17 @@ use crate::{
     VIRTIO_NET_F_CTRL_MAC_ADDR, VIRTIO_NET_F_CTRL_RX, VIRTIO_NET_F_CTRL_RX_EXTRA,
     VIRTIO_NET_F_CTRL_VLAN, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_ECN,
     VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_GUEST_UFO,
-    VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_TSO6, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC,
-    VIRTIO_NET_F_MQ, VIRTIO_NET_OK, VIRTIO_TYPE_NET,
+    VIRTIO_NET_F_HOST_ECN, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_TSO6, VIRTIO_NET_F_HOST_UFO,
+    VIRTIO_NET_F_MAC, VIRTIO_NET_F_MQ, VIRTIO_NET_HDR_GSO_ECN, VIRTIO_NET_HDR_GSO_NONE,
+    VIRTIO_NET_HDR_GSO_TCPV4, VIRTIO_NET_HDR_GSO_TCPV6, VIRTIO_NET_HDR_GSO_UDP, VIRTIO_NET_OK,
+    VIRTIO_TYPE_NET,
 };
 use address_space::{AddressSpace, RegionCache};
 use anyhow::{anyhow, bail, Context, Result};
-use log::{error, warn};
+use log::{debug, error, warn};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
 use machine_manager::{
-    config::{ConfigCheck, NetworkInterfaceConfig},
+    config::{get_virtio_queue_size, ConfigCheck, NetworkInterfaceConfig},
     event_loop::EventLoop,
 };
 use migration::{
@@ -50,7 +52,13 @@ use migration::{
     StateTransfer,
 };
 use migration_derive::{ByteCode, Desc};
+#[cfg(feature = "uring-event-loop")]
+use util::aio::Iovec;
 use util::byte_code::ByteCode;
+#[cfg(feature = "uring-event-loop")]
+use util::event_loop::io_uring::{
+    IoUringEventLoop, IoUringOpcode, IoUringRequest, IO_URING_OFFSET_CURRENT,
+};
 use util::loop_context::gen_delete_notifiers;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
@@ -658,14 +666,40 @@ impl EventNotifierHelper for NetCtrlHandler {
     }
 }
 
+/// Head index and buffers of the tx packet blocked by the full tap.
+#[cfg(feature = "uring-event-loop")]
+type BlockedPacket = Arc<Mutex<Option<(u16, Vec<Iovec>)>>>;
+
 struct TxVirtio {
     queue: Arc<Mutex<Queue>>,
     queue_evt: Arc<EventFd>,
+    /// Io_uring event loop which sends the packets to tap, the used ring is
+    /// updated when the packets are sent.
+    #[cfg(feature = "uring-event-loop")]
+    uring: Arc<Mutex<IoUringEventLoop>>,
+    /// Packet blocked by the full tap, it is sent again before the new ones
+    /// in the next tx.
+    #[cfg(feature = "uring-event-loop")]
+    blocked: BlockedPacket,
 }
 
 impl TxVirtio {
-    fn new(queue: Arc<Mutex<Queue>>, queue_evt: Arc<EventFd>) -> Self {
-        TxVirtio { queue, queue_evt }
+    fn new(queue: Arc<Mutex<Queue>>, queue_evt: Arc<EventFd>) -> Result<Self> {
+        #[cfg(feature = "uring-event-loop")]
+        let uring = {
+            let entries = queue.lock().unwrap().vring.actual_size() as u32;
+            Arc::new(Mutex::new(IoUringEventLoop::new(
+                entries.next_power_of_two(),
+            )?))
+        };
+        Ok(TxVirtio {
+            queue,
+            queue_evt,
+            #[cfg(feature = "uring-event-loop")]
+            uring,
+            #[cfg(feature = "uring-event-loop")]
+            blocked: Arc::new(Mutex::new(None)),
+        })
     }
 }
 
@@ -698,7 +732,13 @@ struct NetIoHandler {
     device_broken: Arc<AtomicBool>,
     is_listening: bool,
     ctrl_info: Arc<Mutex<CtrlInfo>>,
-    queue_size: u16,
+    /// Max packets received in each wakeup of rx queue.
+    rx_burst: u16,
+    /// Max packets sent in each wakeup of tx queue.
+    tx_burst: u16,
+    /// The packets with invalid header are logged only once, as the guest
+    /// may send them continuously.
+    tx_hdr_err_logged: bool,
 }
 
 impl NetIoHandler {
@@ -843,7 +883,7 @@ impl NetIoHandler {
             }
 
             rx_packets += 1;
-            if rx_packets >= self.queue_size {
+            if rx_packets >= self.rx_burst {
                 self.rx
                     .queue_evt
                     .write(1)
@@ -879,8 +919,90 @@ impl NetIoHandler {
         0_i8
     }
 
+    /// Send the packet to tap by io_uring, the packet is added to used ring
+    /// when it is sent, or sent again by the next tx if the tap is full.
+    /// The tx queue is kicked when the packet is completed, as only one packet
+    /// is in flight. The packet is not in flight if it fails to be queued.
+    #[cfg(feature = "uring-event-loop")]
+    fn submit_packet(&self, tap_fd: RawFd, iovecs: Vec<Iovec>, index: u16) -> Result<()> {
+        let req = IoUringRequest {
+            opcode: IoUringOpcode::Writev,
+            fd: tap_fd,
+            iovecs: iovecs.clone(),
+            offset: IO_URING_OFFSET_CURRENT,
+        };
+        let queue = self.tx.queue.clone();
+        let queue_evt = self.tx.queue_evt.clone();
+        let blocked = self.tx.blocked.clone();
+        let mem_space = self.mem_space.clone();
+        let interrupt_cb = self.interrupt_cb.clone();
+        let driver_features = self.driver_features;
+        let cb = Box::new(move |res: i64| {
+            // The request is canceled as the device is deactivated.
+            if res == -libc::ECANCELED as i64 {
+                return;
+            }
+            // The tap is full, send the packet again as `handle_tx` does.
+            if res == -libc::EAGAIN as i64 {
+                *blocked.lock().unwrap() = Some((index, iovecs));
+            } else {
+                if res < 0 {
+                    // Ignore the errors which can not be handled, as `send_packets` does.
+                    error!("Failed to send packet {} for net handle_tx: {}", index, res);
+                }
+                let mut queue = queue.lock().unwrap();
+                if let Err(e) = queue.vring.add_used(&mem_space, index, 0) {
+                    error!("Net tx: Failed to add used ring {}: {:?}", index, e);
+                    return;
+                }
+                if queue.vring.should_notify(&mem_space, driver_features) {
+                    if let Err(e) = interrupt_cb(&VirtioInterruptType::Vring, Some(&queue), false) {
+                        error!("Failed to trigger interrupt for net tx: {:?}", e);
+                    }
+                }
+            }
+            if let Err(e) = queue_evt.write(1) {
+                error!("Failed to trigger tx queue event for net tx: {:?}", e);
+            }
+        });
+        let mut uring = self.tx.uring.lock().unwrap();
+        // SAFETY: the packet is in the guest memory, which is valid until the request is
+        // completed, or canceled when the event loop is dropped.
+        unsafe { uring.push(req, cb) }
+            .with_context(|| format!("Net tx: Failed to queue packet {}", index))?;
+        if let Err(e) = uring.flush() {
+            // The packet is kept in the submission queue, and submitted by the next flush.
+            error!("Net tx: Failed to submit packet {}: {:?}", index, e);
+        }
+        Ok(())
+    }
+
+    /// Check whether the tap is busy with the last packet, which is in flight
+    /// or blocked by the full tap. The tap writes are serialized to keep the
+    /// packet order, so the blocked packet is sent again before popping the
+    /// new ones.
+    #[cfg(feature = "uring-event-loop")]
+    fn tx_busy(&self) -> Result<bool> {
+        if self.tx.uring.lock().unwrap().inflight() != 0 {
+            return Ok(true);
+        }
+        let blocked = self.tx.blocked.lock().unwrap().take();
+        if let Some((index, iovecs)) = blocked {
+            if let Err(e) = self.submit_packet(self.tap_fd, iovecs.clone(), index) {
+                *self.tx.blocked.lock().unwrap() = Some((index, iovecs));
+                return Err(e);
+            }
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
     fn handle_tx(&mut self) -> Result<()> {
         self.trace_request("Net".to_string(), "to tx".to_string());
+        #[cfg(feature = "uring-event-loop")]
+        if self.tx_busy()? {
+            return Ok(());
+        }
         let mut queue = self.tx.queue.lock().unwrap();
 
         let mut tx_packets = 0;
@@ -900,12 +1022,39 @@ impl NetIoHandler {
                 queue.vring.get_cache(),
                 &elem.out_iovec,
             );
-            let tap_fd = if let Some(tap) = self.tap.as_mut() {
+            let mut tap_fd = if let Some(tap) = self.tap.as_mut() {
                 tap.as_raw_fd() as libc::c_int
             } else {
                 -1_i32
             };
-            if tap_fd != -1 && self.send_packets(tap_fd, &iovecs) == -1 {
+            let mut hdr = VirtioNetHdr::default();
+            let hdr_len = get_net_header(&iovecs, hdr.as_mut_bytes())?;
+            if let Err(e) = check_tx_net_header(&hdr, hdr_len, self.driver_features) {
+                // The kernel can't segment the packet as the header says, drop it.
+                if !self.tx_hdr_err_logged {
+                    error!("Net tx: drop the packet {}: {:?}", elem.index, e);
+                    self.tx_hdr_err_logged = true;
+                } else {
+                    debug!("Net tx: drop the packet {}: {:?}", elem.index, e);
+                }
+                tap_fd = -1;
+            }
+            // The packet sent by io_uring is added to used ring when it is completed.
+            let in_flight = cfg!(feature = "uring-event-loop") && tap_fd != -1;
+            #[cfg(feature = "uring-event-loop")]
+            if in_flight {
+                let iovecs = iovecs
+                    .iter()
+                    .map(|iov| Iovec::new(iov.iov_base as u64, iov.iov_len as u64))
+                    .collect();
+                if let Err(e) = self.submit_packet(tap_fd, iovecs, elem.index) {
+                    queue.vring.push_back();
+                    return Err(e);
+                }
+                // The next packet is popped when this one is completed.
+                break;
+            }
+            if !in_flight && tap_fd != -1 && self.send_packets(tap_fd, &iovecs) == -1 {
                 queue.vring.push_back();
                 self.tx.queue_evt.write(1).with_context(|| {
                     "Failed to trigger tx queue event when writev blocked".to_string()
@@ -913,23 +1062,25 @@ impl NetIoHandler {
                 return Ok(());
             }
 
-            queue
-                .vring
-                .add_used(&self.mem_space, elem.index, 0)
-                .with_context(|| format!("Net tx: Failed to add used ring {}", elem.index))?;
-
-            if queue
-                .vring
-                .should_notify(&self.mem_space, self.driver_features)
-            {
-                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false)
-                    .with_context(|| {
-                        VirtioError::InterruptTrigger("net", VirtioInterruptType::Vring)
-                    })?;
-                self.trace_send_interrupt("Net".to_string());
+            if !in_flight {
+                queue
+                    .vring
+                    .add_used(&self.mem_space, elem.index, 0)
+                    .with_context(|| format!("Net tx: Failed to add used ring {}", elem.index))?;
+
+                if queue
+                    .vring
+                    .should_notify(&self.mem_space, self.driver_features)
+                {
+                    (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false)
+                        .with_context(|| {
+                            VirtioError::InterruptTrigger("net", VirtioInterruptType::Vring)
+                        })?;
+                    self.trace_send_interrupt("Net".to_string());
+                }
             }
             tx_packets += 1;
-            if tx_packets >= self.queue_size {
+            if tx_packets >= self.tx_burst {
                 self.tx
                     .queue_evt
                     .write(1)
@@ -964,6 +1115,8 @@ impl NetIoHandler {
         if old_tap_fd != -1 {
             notifiers_fds.push(old_tap_fd);
         }
+        #[cfg(feature = "uring-event-loop")]
+        notifiers_fds.push(locked_net_io.tx.uring.lock().unwrap().eventfd());
         let mut notifiers = gen_delete_notifiers(&notifiers_fds);
         drop(locked_net_io);
 
@@ -990,6 +1143,39 @@ fn get_net_header(iovec: &[libc::iovec], buf: &mut [u8]) -> Result<usize> {
     Ok(end)
 }
 
+/// Check the virtio net header of the tx packet, the gso type passed to
+/// the kernel must be the one negotiated with the driver.
+///
+/// # Arguments
+///
+/// * `hdr` - The virtio net header of the packet.
+/// * `hdr_len` - The length of the header read from the packet.
+/// * `features` - The driver features.
+fn check_tx_net_header(hdr: &VirtioNetHdr, hdr_len: usize, features: u64) -> Result<()> {
+    if hdr_len < NET_HDR_LENGTH {
+        bail!("Invalid net header length {}", hdr_len);
+    }
+    let feature = match hdr.gso_type & !VIRTIO_NET_HDR_GSO_ECN {
+        VIRTIO_NET_HDR_GSO_NONE => return Ok(()),
+        VIRTIO_NET_HDR_GSO_TCPV4 => VIRTIO_NET_F_HOST_TSO4,
+        VIRTIO_NET_HDR_GSO_TCPV6 => VIRTIO_NET_F_HOST_TSO6,
+        VIRTIO_NET_HDR_GSO_UDP => VIRTIO_NET_F_HOST_UFO,
+        gso_type => bail!("Unknown gso type {}", gso_type),
+    };
+    if !virtio_has_feature(features, feature) {
+        bail!("Gso type {} is not negotiated", hdr.gso_type);
+    }
+    if hdr.gso_type & VIRTIO_NET_HDR_GSO_ECN != 0
+        && !virtio_has_feature(features, VIRTIO_NET_F_HOST_ECN)
+    {
+        bail!("Gso with ECN is not negotiated");
+    }
+    if hdr.gso_size == 0 {
+        bail!("Invalid gso size 0 for gso type {}", hdr.gso_type);
+    }
+    Ok(())
+}
+
 fn build_event_notifier(
     fd: RawFd,
     handler: Option<Rc<NotifierCallback>>,
@@ -1084,6 +1270,12 @@ impl EventNotifierHelper for NetIoHandler {
             EventSet::IN,
         ));
 
+        // Register event notifier for the completions of tx packets.
+        #[cfg(feature = "uring-event-loop")]
+        notifiers.append(&mut EventNotifierHelper::internal_notifiers(
+            locked_net_io.tx.uring.clone(),
+        ));
+
         // Register event notifier for tap.
         let cloned_net_io = net_io.clone();
         if let Some(tap) = locked_net_io.tap.as_ref() {
@@ -1195,6 +1387,26 @@ impl Net {
             ctrl_info: None,
         }
     }
+
+    /// Set the max packets sent to tap in each wakeup of tx queue, 0 means
+    /// the queue size. It takes effect when the device is activated.
+    pub fn set_tx_burst(&mut self, n: usize) {
+        self.net_cfg.tx_burst = u16::try_from(n).unwrap_or(u16::MAX);
+    }
+
+    /// Set the max packets received from tap in each wakeup of rx queue, 0
+    /// means the queue size. It takes effect when the device is activated.
+    pub fn set_rx_burst(&mut self, n: usize) {
+        self.net_cfg.rx_burst = u16::try_from(n).unwrap_or(u16::MAX);
+    }
+
+    fn burst_size(&self, burst: u16) -> u16 {
+        if burst == 0 {
+            self.queue_size()
+        } else {
+            burst
+        }
+    }
 }
 
 /// Set Mac address configured into the virtio configuration, and return features mask with
@@ -1355,6 +1567,26 @@ fn get_tap_offload_flags(features: u64) -> u32 {
     flags
 }
 
+/// Remove the offload features which are not supported by the kernel tap driver.
+///
+/// # Arguments
+///
+/// * `features` - The device features.
+/// * `has_offload` - Probe if the tap supports the offload flags.
+fn mask_tap_offload_features(mut features: u64, has_offload: impl Fn(u32) -> bool) -> u64 {
+    let offloads = [
+        (TUN_F_TSO4, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_HOST_TSO4),
+        (TUN_F_TSO6, VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_HOST_TSO6),
+        (TUN_F_UFO, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_UFO),
+    ];
+    for (flag, guest, host) in offloads {
+        if !has_offload(TUN_F_CSUM | flag) {
+            features &= !(1 << guest | 1 << host);
+        }
+    }
+    features
+}
+
 impl VirtioDevice for Net {
     /// Realize virtio network device.
     fn realize(&mut self) -> Result<()> {
@@ -1417,12 +1649,12 @@ impl VirtioDevice for Net {
             self.taps = None;
         }
 
-        // Using the first tap to test if all the taps have ufo.
+        // Using the first tap to test if all the taps have the offloads.
         if let Some(tap) = self.taps.as_ref().map(|t| &t[0]) {
-            if !tap.has_ufo() {
-                locked_state.device_features &=
-                    !(1 << VIRTIO_NET_F_GUEST_UFO | 1 << VIRTIO_NET_F_HOST_UFO);
-            }
+            locked_state.device_features =
+                mask_tap_offload_features(locked_state.device_features, |flags| {
+                    tap.has_offload(flags)
+                });
         }
 
         if let Some(mac) = &self.net_cfg.mac {
@@ -1470,6 +1702,14 @@ impl VirtioDevice for Net {
         self.net_cfg.queue_size
     }
 
+    fn queue_size_of(&self, queue_index: usize) -> u16 {
+        get_virtio_queue_size(
+            &self.net_cfg.queue_overrides,
+            queue_index,
+            self.net_cfg.queue_size,
+        )
+    }
+
     /// Get device features from host.
     fn get_device_features(&self, features_select: u32) -> u32 {
         read_u32(self.state.lock().unwrap().device_features, features_select)
@@ -1587,7 +1827,7 @@ impl VirtioDevice for Net {
             let update_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
             let mut handler = NetIoHandler {
                 rx: RxVirtio::new(rx_queue, rx_queue_evt),
-                tx: TxVirtio::new(tx_queue, tx_queue_evt),
+                tx: TxVirtio::new(tx_queue, tx_queue_evt)?,
                 tap: self.taps.as_ref().map(|t| t[index].clone()),
                 tap_fd: -1,
                 mem_space: mem_space.clone(),
@@ -1598,7 +1838,9 @@ impl VirtioDevice for Net {
                 device_broken: self.broken.clone(),
                 is_listening: true,
                 ctrl_info: ctrl_info.clone(),
-                queue_size: self.queue_size(),
+                rx_burst: self.burst_size(self.net_cfg.rx_burst),
+                tx_burst: self.burst_size(self.net_cfg.tx_burst),
+                tx_hdr_err_logged: false,
             };
             if let Some(tap) = &handler.tap {
                 handler.tap_fd = tap.as_raw_fd();
@@ -1718,6 +1960,13 @@ impl VirtioTrace for NetIoHandler {}
 mod tests {
     pub use super::super::*;
     pub use super::*;
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    use crate::{QueueConfig, SplitVringDesc, VirtioMmioDevice, QUEUE_TYPE_SPLIT_VRING};
+    use address_space::{GuestAddress, HostMemMapping, HostMemOptions, Region};
+    use machine_manager::config::DEFAULT_VIRTQUEUE_SIZE;
 
     #[test]
     fn test_net_init() {
@@ -1780,6 +2029,36 @@ mod tests {
         assert_eq!(net.write_config(offset, &mut data).is_ok(), false);
     }
 
+    #[test]
+    fn test_net_reset() {
+        let mem_space = address_space_init();
+        let mut net = Net::default();
+        net.realize().unwrap();
+        let device_features = net.state.lock().unwrap().device_features;
+        let net = Arc::new(Mutex::new(net));
+        let mut mmio_dev = VirtioMmioDevice::new(&mem_space, net.clone());
+        mmio_dev.setup_driver(device_features);
+        assert_eq!(
+            net.lock().unwrap().state.lock().unwrap().driver_features,
+            device_features
+        );
+        assert!(mmio_dev.queue_ready(0));
+        assert!(mmio_dev.queue_ready(1));
+
+        // Features are negotiated again after reset, the device features are kept.
+        mmio_dev.reset_device().unwrap();
+        let locked_net = net.lock().unwrap();
+        assert_eq!(locked_net.state.lock().unwrap().driver_features, 0);
+        assert_eq!(locked_net.get_driver_features(0), 0);
+        assert_eq!(locked_net.get_driver_features(1), 0);
+        assert_eq!(
+            locked_net.state.lock().unwrap().device_features,
+            device_features
+        );
+        assert!(!mmio_dev.queue_ready(0));
+        assert!(!mmio_dev.queue_ready(1));
+    }
+
     #[test]
     fn test_net_create_tap() {
         // Test None net_fds and host_dev_name.
@@ -1881,6 +2160,338 @@ mod tests {
         }
     }
 
+    const SYSTEM_SPACE_SIZE: u64 = 1024 * 1024;
+    const TX_PACKET_LEN: u32 = 64;
+
+    fn address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36, "sysmem");
+        let sys_space = AddressSpace::new(root, "sysmem").unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                SYSTEM_SPACE_SIZE,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone(), "sysmem"),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    fn queue_init(mem_space: &Arc<AddressSpace>, base: u64) -> Arc<Mutex<Queue>> {
+        let mut queue_config = QueueConfig::new(DEFAULT_VIRTQUEUE_SIZE);
+        queue_config.desc_table = GuestAddress(base);
+        queue_config.addr_cache.desc_table_host =
+            mem_space.get_host_address(queue_config.desc_table).unwrap();
+        queue_config.avail_ring = GuestAddress(base + 16 * DEFAULT_VIRTQUEUE_SIZE as u64);
+        queue_config.addr_cache.avail_ring_host =
+            mem_space.get_host_address(queue_config.avail_ring).unwrap();
+        queue_config.used_ring = GuestAddress(base + 32 * DEFAULT_VIRTQUEUE_SIZE as u64);
+        queue_config.addr_cache.used_ring_host =
+            mem_space.get_host_address(queue_config.used_ring).unwrap();
+        queue_config.ready = true;
+        Arc::new(Mutex::new(
+            Queue::new(queue_config, QUEUE_TYPE_SPLIT_VRING).unwrap(),
+        ))
+    }
+
+    /// Net io handler whose tap is the write end of a pipe, the read end is
+    /// returned to check the packets sent.
+    fn net_io_handler_init(mem_space: &Arc<AddressSpace>, tx_burst: u16) -> (NetIoHandler, File) {
+        let mut fds = [0; 2];
+        // SAFETY: the array of fds is valid for pipe2().
+        let ret = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) };
+        assert_eq!(ret, 0);
+        // SAFETY: the fds are just created and owned by the files.
+        let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let tap = Tap { file: writer };
+        let interrupt_cb = Arc::new(Box::new(
+            |_: &VirtioInterruptType, _: Option<&Queue>, _: bool| Ok(()),
+        ) as VirtioInterrupt);
+        let (_sender, receiver) = channel();
+        let state = Arc::new(Mutex::new(VirtioNetState::default()));
+        let handler = NetIoHandler {
+            rx: RxVirtio::new(
+                queue_init(mem_space, 0),
+                Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+            ),
+            tx: TxVirtio::new(
+                queue_init(mem_space, 0x10000),
+                Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+            )
+            .unwrap(),
+            tap_fd: tap.as_raw_fd(),
+            tap: Some(tap),
+            mem_space: mem_space.clone(),
+            interrupt_cb,
+            driver_features: 0,
+            receiver,
+            update_evt: Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+            device_broken: Arc::new(AtomicBool::new(false)),
+            is_listening: true,
+            ctrl_info: Arc::new(Mutex::new(CtrlInfo::new(state))),
+            rx_burst: DEFAULT_VIRTQUEUE_SIZE,
+            tx_burst,
+            tx_hdr_err_logged: false,
+        };
+        (handler, reader)
+    }
+
+    /// Make the packets available in tx queue.
+    fn push_tx_packets(mem_space: &Arc<AddressSpace>, queue: &Arc<Mutex<Queue>>, num: u16) {
+        let locked_queue = queue.lock().unwrap();
+        let config = locked_queue.vring.get_queue_config();
+        for i in 0..num {
+            let desc = SplitVringDesc {
+                addr: GuestAddress(0x40000 + u64::from(i) * u64::from(TX_PACKET_LEN)),
+                len: TX_PACKET_LEN,
+                flags: 0,
+                next: 0,
+            };
+            mem_space
+                .write_object(&desc, GuestAddress(config.desc_table.0 + 16 * u64::from(i)))
+                .unwrap();
+            mem_space
+                .write_object(&i, GuestAddress(config.avail_ring.0 + 4 + 2 * u64::from(i)))
+                .unwrap();
+        }
+        mem_space
+            .write_object(&num, GuestAddress(config.avail_ring.0 + 2))
+            .unwrap();
+    }
+
+    /// Drain the tx queue as the event loop does, and return the times of
+    /// the wakeups.
+    fn drain_tx_queue(handler: &mut NetIoHandler) -> usize {
+        let mut wakeups = 0;
+        loop {
+            handler.handle_tx().unwrap();
+            wakeups += 1;
+            wait_tx_completions(handler);
+            // The queue is kicked again if the burst is used up, or the packet
+            // sent by io_uring is completed.
+            if handler.tx.queue_evt.read().is_err() {
+                break;
+            }
+        }
+        wakeups
+    }
+
+    /// The packets sent by io_uring are added to used ring when they are completed.
+    fn wait_tx_completions(_handler: &mut NetIoHandler) {
+        #[cfg(feature = "uring-event-loop")]
+        {
+            let mut uring = _handler.tx.uring.lock().unwrap();
+            while uring.inflight() != 0 {
+                uring.wait(1).unwrap();
+            }
+        }
+    }
+
+    /// Run the callbacks of the completed packets sent by io_uring without waiting.
+    fn poll_tx_completions(_handler: &mut NetIoHandler) {
+        #[cfg(feature = "uring-event-loop")]
+        _handler.tx.uring.lock().unwrap().handle_completions();
+    }
+
+    fn used_idx(mem_space: &Arc<AddressSpace>, queue: &Arc<Mutex<Queue>>) -> u16 {
+        let config = queue.lock().unwrap().vring.get_queue_config();
+        mem_space
+            .read_object::<u16>(GuestAddress(config.used_ring.0 + 2))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_net_tx_burst() {
+        let packets = 64_u16;
+        let mem_space = address_space_init();
+        let mut wakeups = Vec::new();
+        // Single packet mode and burst mode.
+        for tx_burst in [1, 16] {
+            let (mut handler, mut reader) = net_io_handler_init(&mem_space, tx_burst);
+            push_tx_packets(&mem_space, &handler.tx.queue, packets);
+            wakeups.push(drain_tx_queue(&mut handler));
+
+            // All the packets are sent to tap.
+            let mut data = Vec::new();
+            let _ = reader.read_to_end(&mut data);
+            assert_eq!(data.len(), usize::from(packets) * TX_PACKET_LEN as usize);
+            assert_eq!(used_idx(&mem_space, &handler.tx.queue), packets);
+        }
+        // The wakeups are amortised by the burst, while the packets sent by
+        // io_uring are serialized and each of them wakes up tx.
+        assert_eq!(wakeups[0], usize::from(packets) + 1);
+        if cfg!(feature = "uring-event-loop") {
+            assert_eq!(wakeups[1], usize::from(packets) + 1);
+        } else {
+            assert_eq!(wakeups[1], usize::from(packets / 16) + 1);
+        }
+    }
+
+    #[test]
+    fn test_net_tx_blocked_order() {
+        let mem_space = address_space_init();
+        let (mut handler, mut reader) = net_io_handler_init(&mem_space, 16);
+        // Mark the payload of the packets after the header.
+        for i in 0..2_u8 {
+            let addr = 0x40000 + u64::from(i) * u64::from(TX_PACKET_LEN) + 32;
+            mem_space
+                .write_object(&(i + 1), GuestAddress(addr))
+                .unwrap();
+        }
+        push_tx_packets(&mem_space, &handler.tx.queue, 2);
+
+        // Fill the tap, the write of the first packet gets EAGAIN.
+        let filler = [0_u8; 4096];
+        let mut filled = 0;
+        loop {
+            // SAFETY: the buffer is valid for its length.
+            let ret = unsafe {
+                libc::write(
+                    handler.tap_fd,
+                    filler.as_ptr() as *const libc::c_void,
+                    filler.len(),
+                )
+            };
+            if ret < 0 {
+                break;
+            }
+            filled += ret as usize;
+        }
+        // Io_uring may also keep the write in flight until the tap has room.
+        handler.handle_tx().unwrap();
+        poll_tx_completions(&mut handler);
+        assert_eq!(used_idx(&mem_space, &handler.tx.queue), 0);
+
+        // The later packet is not sent before the blocked one.
+        handler.handle_tx().unwrap();
+        poll_tx_completions(&mut handler);
+        assert_eq!(used_idx(&mem_space, &handler.tx.queue), 0);
+        let mut data = vec![0_u8; filled];
+        reader.read_exact(&mut data).unwrap();
+
+        // Both packets are sent in order when the tap has room.
+        drain_tx_queue(&mut handler);
+        assert_eq!(used_idx(&mem_space, &handler.tx.queue), 2);
+        let mut data = Vec::new();
+        let _ = reader.read_to_end(&mut data);
+        assert_eq!(data.len(), 2 * TX_PACKET_LEN as usize);
+        assert_eq!(data[32], 1);
+        assert_eq!(data[TX_PACKET_LEN as usize + 32], 2);
+    }
+
+    #[test]
+    fn test_net_set_burst() {
+        let mut net = Net::default();
+        assert_eq!(net.burst_size(net.net_cfg.tx_burst), net.queue_size());
+        assert_eq!(net.burst_size(net.net_cfg.rx_burst), net.queue_size());
+        net.set_tx_burst(32);
+        net.set_rx_burst(usize::MAX);
+        assert_eq!(net.burst_size(net.net_cfg.tx_burst), 32);
+        assert_eq!(net.burst_size(net.net_cfg.rx_burst), u16::MAX);
+    }
+
+    #[test]
+    fn test_net_offload_features() {
+        let features = 1 << VIRTIO_NET_F_CSUM
+            | 1 << VIRTIO_NET_F_GUEST_CSUM
+            | 1 << VIRTIO_NET_F_GUEST_TSO4
+            | 1 << VIRTIO_NET_F_GUEST_TSO6
+            | 1 << VIRTIO_NET_F_GUEST_UFO
+            | 1 << VIRTIO_NET_F_HOST_TSO4
+            | 1 << VIRTIO_NET_F_HOST_TSO6
+            | 1 << VIRTIO_NET_F_HOST_UFO;
+
+        // The kernel supports all the offloads.
+        assert_eq!(mask_tap_offload_features(features, |_| true), features);
+
+        // The kernel doesn't support ufo.
+        let masked = mask_tap_offload_features(features, |flags| flags & TUN_F_UFO == 0);
+        assert_eq!(
+            masked,
+            features & !(1 << VIRTIO_NET_F_GUEST_UFO | 1 << VIRTIO_NET_F_HOST_UFO)
+        );
+
+        // The kernel doesn't support any offload, checksum features are kept.
+        let masked = mask_tap_offload_features(features, |_| false);
+        assert_eq!(
+            masked,
+            1 << VIRTIO_NET_F_CSUM | 1 << VIRTIO_NET_F_GUEST_CSUM
+        );
+
+        // The tap offload flags follow the features negotiated by the driver.
+        assert_eq!(
+            get_tap_offload_flags(features),
+            TUN_F_CSUM | TUN_F_TSO4 | TUN_F_TSO6 | TUN_F_UFO
+        );
+        assert_eq!(get_tap_offload_flags(masked), TUN_F_CSUM);
+        assert_eq!(get_tap_offload_flags(0), 0);
+    }
+
+    #[test]
+    fn test_net_check_tx_header() {
+        let features = 1 << VIRTIO_NET_F_HOST_TSO4 | 1 << VIRTIO_NET_F_HOST_UFO;
+        let mut hdr = VirtioNetHdr::default();
+        assert!(check_tx_net_header(&hdr, NET_HDR_LENGTH, 0).is_ok());
+        assert!(check_tx_net_header(&hdr, NET_HDR_LENGTH - 1, 0).is_err());
+
+        hdr.gso_size = 1448;
+        for (gso_type, ok) in [
+            (VIRTIO_NET_HDR_GSO_TCPV4, true),
+            (VIRTIO_NET_HDR_GSO_UDP, true),
+            (VIRTIO_NET_HDR_GSO_TCPV6, false),
+            (VIRTIO_NET_HDR_GSO_TCPV4 | VIRTIO_NET_HDR_GSO_ECN, false),
+            (2, false),
+        ] {
+            hdr.gso_type = gso_type;
+            assert_eq!(
+                check_tx_net_header(&hdr, NET_HDR_LENGTH, features).is_ok(),
+                ok
+            );
+        }
+
+        hdr.gso_type = VIRTIO_NET_HDR_GSO_TCPV4 | VIRTIO_NET_HDR_GSO_ECN;
+        let ecn_features = features | 1 << VIRTIO_NET_F_HOST_ECN;
+        assert!(check_tx_net_header(&hdr, NET_HDR_LENGTH, ecn_features).is_ok());
+        hdr.gso_size = 0;
+        assert!(check_tx_net_header(&hdr, NET_HDR_LENGTH, ecn_features).is_err());
+    }
+
+    #[test]
+    fn test_net_tx_gso() {
+        let hdr = VirtioNetHdr {
+            gso_type: VIRTIO_NET_HDR_GSO_TCPV4,
+            gso_size: 1448,
+            ..Default::default()
+        };
+        // The gso packet is dropped if the driver doesn't negotiate tso4.
+        for (features, sent) in [(0, 0), (1 << VIRTIO_NET_F_HOST_TSO4, TX_PACKET_LEN)] {
+            let mem_space = address_space_init();
+            let (mut handler, mut reader) = net_io_handler_init(&mem_space, 1);
+            handler.driver_features = features;
+            mem_space.write_object(&hdr, GuestAddress(0x40000)).unwrap();
+            push_tx_packets(&mem_space, &handler.tx.queue, 1);
+            drain_tx_queue(&mut handler);
+
+            let mut data = Vec::new();
+            let _ = reader.read_to_end(&mut data);
+            assert_eq!(data.len(), sent as usize);
+            let config = handler.tx.queue.lock().unwrap().vring.get_queue_config();
+            let used_idx = mem_space
+                .read_object::<u16>(GuestAddress(config.used_ring.0 + 2))
+                .unwrap();
+            assert_eq!(used_idx, 1);
+        }
+    }
+
     #[test]
     fn test_iothread() {
         let mut net = Net::default();
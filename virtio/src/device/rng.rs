@@ -397,7 +397,7 @@ mod tests {
     use std::sync::atomic::{AtomicU32, Ordering};
     use std::sync::{Arc, Mutex};
 
-    use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
+    use address_space::{AddressSpace, GuestAddress, HostMemMapping, HostMemOptions, Region};
     use machine_manager::config::{RngConfig, DEFAULT_VIRTQUEUE_SIZE};
     use vmm_sys_util::tempfile::TempFile;
 
@@ -415,9 +415,7 @@ mod tests {
                 None,
                 SYSTEM_SPACE_SIZE,
                 None,
-                false,
-                false,
-                false,
+                HostMemOptions::default(),
             )
             .unwrap(),
         );
@@ -513,6 +511,38 @@ mod tests {
         rng.state.driver_features = 0;
     }
 
+    #[test]
+    fn test_rng_reset() {
+        let mem_space = address_space_init();
+        let random_file = TempFile::new()
+            .unwrap()
+            .as_path()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let rng_config = RngConfig {
+            id: "".to_string(),
+            random_file,
+            bytes_per_sec: Some(64),
+        };
+        let rng = Arc::new(Mutex::new(Rng::new(rng_config)));
+        let features = 1_u64 << VIRTIO_F_VERSION_1 | 1_u64 << VIRTIO_F_RING_INDIRECT_DESC;
+        rng.lock().unwrap().state.device_features = features;
+        let mut mmio_dev = VirtioMmioDevice::new(&mem_space, rng.clone());
+        mmio_dev.setup_driver(features);
+        assert_eq!(rng.lock().unwrap().state.driver_features, features);
+        assert!(mmio_dev.queue_ready(0));
+
+        // Features are negotiated again after reset, the device features are kept.
+        mmio_dev.reset_device().unwrap();
+        let locked_rng = rng.lock().unwrap();
+        assert_eq!(locked_rng.state.driver_features, 0);
+        assert_eq!(locked_rng.get_driver_features(0), 0);
+        assert_eq!(locked_rng.get_driver_features(1), 0);
+        assert_eq!(locked_rng.state.device_features, features);
+        assert!(!mmio_dev.queue_ready(0));
+    }
+
     #[test]
     fn test_get_req_data_size() {
         // The size of request overflows
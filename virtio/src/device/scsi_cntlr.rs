@@ -34,10 +34,7 @@ use devices::ScsiBus::{
     EMULATE_SCSI_OPS, SCSI_CMD_BUF_SIZE, SCSI_SENSE_INVALID_OPCODE,
 };
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
-use machine_manager::{
-    config::{ScsiCntlrConfig, VIRTIO_SCSI_MAX_LUN, VIRTIO_SCSI_MAX_TARGET},
-    event_loop::EventLoop,
-};
+use machine_manager::{config::ScsiCntlrConfig, event_loop::EventLoop};
 use util::aio::Iovec;
 use util::byte_code::ByteCode;
 use util::loop_context::{
@@ -171,8 +168,8 @@ impl VirtioDevice for ScsiCntlr {
         self.state.config_space.cmd_per_lun = 128;
         // seg_max: queue size - 2, 32 bit.
         self.state.config_space.seg_max = self.queue_size() as u32 - 2;
-        self.state.config_space.max_target = VIRTIO_SCSI_MAX_TARGET;
-        self.state.config_space.max_lun = VIRTIO_SCSI_MAX_LUN as u32;
+        self.state.config_space.max_target = self.config.max_target;
+        self.state.config_space.max_lun = self.config.max_lun;
         // num_queues: request queues number.
         self.state.config_space.num_queues = self.config.queues;
 
@@ -42,6 +42,7 @@ use vmm_sys_util::eventfd::EventFd;
 use address_space::AddressSpace;
 use machine_manager::config::ConfigCheck;
 use util::aio::{mem_to_buf, Iovec};
+use util::byte_code::ByteCode;
 use util::num_ops::write_u32;
 use util::AsAny;
 
@@ -117,6 +118,8 @@ pub const VIRTIO_NET_F_GUEST_UFO: u32 = 10;
 pub const VIRTIO_NET_F_HOST_TSO4: u32 = 11;
 /// Device can receive TSOv6.
 pub const VIRTIO_NET_F_HOST_TSO6: u32 = 12;
+/// Device can receive TSO with ECN.
+pub const VIRTIO_NET_F_HOST_ECN: u32 = 13;
 /// Device can receive UFO.
 pub const VIRTIO_NET_F_HOST_UFO: u32 = 14;
 /// Device can merge receive buffers.
@@ -294,6 +297,17 @@ pub const VIRTIO_MMIO_INT_CONFIG: u32 = 0x02;
 /// Guest OS uses notify reg to notify the VMM.
 pub const NOTIFY_REG_OFFSET: u32 = 0x50;
 
+/// The packet is not a GSO packet.
+pub const VIRTIO_NET_HDR_GSO_NONE: u8 = 0;
+/// The packet is a GSO packet of TCPv4.
+pub const VIRTIO_NET_HDR_GSO_TCPV4: u8 = 1;
+/// The packet is a GSO packet of UDP.
+pub const VIRTIO_NET_HDR_GSO_UDP: u8 = 3;
+/// The packet is a GSO packet of TCPv6.
+pub const VIRTIO_NET_HDR_GSO_TCPV6: u8 = 4;
+/// The TCP segments of the GSO packet have the ECN bit set.
+pub const VIRTIO_NET_HDR_GSO_ECN: u8 = 0x80;
+
 /// Packet header, refer to Virtio Spec.
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
@@ -307,6 +321,8 @@ pub struct VirtioNetHdr {
     pub num_buffers: u16,
 }
 
+impl ByteCode for VirtioNetHdr {}
+
 #[derive(Debug)]
 pub enum VirtioInterruptType {
     Config,
@@ -335,6 +351,12 @@ pub trait VirtioDevice: Send + AsAny {
     /// Get the queue size of virtio device.
     fn queue_size(&self) -> u16;
 
+    /// Get the queue size of the virtqueue with index `queue_index`, it is the
+    /// same as `queue_size` unless the queue size is overridden.
+    fn queue_size_of(&self, _queue_index: usize) -> u16 {
+        self.queue_size()
+    }
+
     /// Get device features from host.
     fn get_device_features(&self, features_select: u32) -> u32;
 
@@ -362,6 +384,13 @@ pub trait VirtioDevice: Send + AsAny {
     /// Get driver features by guest.
     fn get_driver_features(&self, features_select: u32) -> u32;
 
+    /// Drop the features negotiated with driver, the driver negotiates them
+    /// again after the device is reset.
+    fn clear_driver_features(&mut self) {
+        self.set_driver_features(0, 0);
+        self.set_driver_features(1, 0);
+    }
+
     /// Read data of config from guest.
     fn read_config(&self, offset: u64, data: &mut [u8]) -> Result<()>;
 
@@ -395,7 +424,9 @@ pub trait VirtioDevice: Send + AsAny {
     }
 
     /// Reset virtio device, used to do some special reset action for
-    /// different device.
+    /// different device. It is called after the device is deactivated, when
+    /// the driver writes 0 to the device status or the VM is reset. The
+    /// features negotiated with driver are dropped by the transport.
     fn reset(&mut self) -> Result<()> {
         Ok(())
     }
@@ -425,6 +456,13 @@ pub trait VirtioDevice: Send + AsAny {
         false
     }
 
+    /// Get whether the virtio device supports packed virtqueue, the packed
+    /// virtqueue is used only if the device supports it and the driver acks
+    /// VIRTIO_F_RING_PACKED, devices supporting it should override this function.
+    fn negotiate_packed_queue_feature(&self) -> bool {
+        false
+    }
+
     fn get_device_broken(&self) -> &Arc<AtomicBool>;
 }
 
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+mod packed;
 mod split;
 
 use address_space::{AddressSpace, GuestAddress, RegionCache};
@@ -17,6 +18,7 @@ use anyhow::{bail, Result};
 use std::sync::Arc;
 use vmm_sys_util::eventfd::EventFd;
 
+pub use packed::*;
 pub use split::*;
 
 /// Split Virtqueue.
@@ -93,6 +95,56 @@ impl Element {
     }
 }
 
+/// Buffer described by a descriptor in the descriptor chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainBuffer {
+    /// Guest address of the buffer.
+    pub addr: GuestAddress,
+    /// Length of the buffer.
+    pub len: u32,
+    /// Whether the buffer is writable by device.
+    pub write_only: bool,
+}
+
+/// Descriptor chain starting from a head descriptor, the descriptors in the
+/// indirect table are visited in place of the indirect descriptor.
+pub struct DescriptorChain {
+    /// Index of the head descriptor in the table.
+    pub head: u16,
+    /// Buffers in the order of the chain.
+    buffers: std::vec::IntoIter<ChainBuffer>,
+}
+
+impl DescriptorChain {
+    fn new(elem: Element) -> Self {
+        // The read-only buffers must precede the write-only ones in the chain,
+        // which has been checked when walking the chain.
+        let out_iter = elem.out_iovec.iter().map(|iov| (iov, false));
+        let in_iter = elem.in_iovec.iter().map(|iov| (iov, true));
+        let buffers: Vec<ChainBuffer> = out_iter
+            .chain(in_iter)
+            .map(|(iov, write_only)| ChainBuffer {
+                addr: iov.addr,
+                len: iov.len,
+                write_only,
+            })
+            .collect();
+
+        DescriptorChain {
+            head: elem.index,
+            buffers: buffers.into_iter(),
+        }
+    }
+}
+
+impl Iterator for DescriptorChain {
+    type Item = ChainBuffer;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buffers.next()
+    }
+}
+
 /// Vring operations.
 pub trait VringOps {
     /// Return true if the vring is enable by driver.
@@ -116,6 +168,15 @@ pub trait VringOps {
     /// Rollback the entry which is pop from available queue by `pop_avail`.
     fn push_back(&mut self);
 
+    /// Get the element of the descriptor chain starting from the head descriptor,
+    /// the avail ring is not consumed.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_mem` - Address space to which the vring belongs.
+    /// * `head` - Index of the head descriptor in the table.
+    fn get_desc_chain(&mut self, sys_mem: &Arc<AddressSpace>, head: u16) -> Result<Element>;
+
     /// Fill the used vring after processing the IO request.
     ///
     /// # Arguments
@@ -179,6 +240,7 @@ impl Queue {
     pub fn new(queue_config: QueueConfig, queue_type: u16) -> Result<Self> {
         let vring: Box<dyn VringOps + Send> = match queue_type {
             QUEUE_TYPE_SPLIT_VRING => Box::new(SplitVring::new(queue_config)),
+            QUEUE_TYPE_PACKED_VRING => Box::new(PackedVring::new(queue_config)),
             _ => {
                 bail!("Unsupported queue type {}", queue_type);
             }
@@ -200,6 +262,22 @@ impl Queue {
     pub fn is_valid(&self, sys_mem: &Arc<AddressSpace>) -> bool {
         self.vring.is_valid(sys_mem)
     }
+
+    /// Get the descriptor chain starting from the head descriptor, the
+    /// indirect descriptor table in the chain is followed.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_mem` - Address space to which the vring belongs.
+    /// * `head` - Index of the head descriptor in the table.
+    pub fn pop_descriptor_chain(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        head: u16,
+    ) -> Result<DescriptorChain> {
+        let elem = self.vring.get_desc_chain(sys_mem, head)?;
+        Ok(DescriptorChain::new(elem))
+    }
 }
 
 /// Virt Queue Notify EventFds
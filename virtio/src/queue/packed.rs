@@ -0,0 +1,1154 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::min;
+use std::mem::size_of;
+use std::num::Wrapping;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{fence, Ordering};
+use std::sync::Arc;
+
+use address_space::{AddressSpace, GuestAddress, RegionCache, RegionType};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, warn};
+use util::byte_code::ByteCode;
+
+use super::{
+    checked_offset_mem, ElemIovec, Element, QueueConfig, VringOps, VIRTQ_DESC_F_INDIRECT,
+    VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE,
+};
+use crate::{virtio_has_feature, VirtioError, VIRTIO_F_RING_EVENT_IDX};
+
+/// This marks a descriptor as available, it equals to the wrap counter of
+/// driver when the descriptor is made available.
+const VIRTQ_DESC_F_AVAIL: u16 = 1 << 7;
+/// This marks a descriptor as used, it equals to the wrap counter of device
+/// when the descriptor is used.
+const VIRTQ_DESC_F_USED: u16 = 1 << 15;
+
+/// Enable events.
+const VRING_PACKED_EVENT_FLAG_ENABLE: u16 = 0x0;
+/// Disable events.
+const VRING_PACKED_EVENT_FLAG_DISABLE: u16 = 0x1;
+/// Enable events for a specific descriptor, it is only valid when
+/// VIRTIO_F_RING_EVENT_IDX is negotiated.
+const VRING_PACKED_EVENT_FLAG_DESC: u16 = 0x2;
+/// Mask of the event flags.
+const VRING_PACKED_EVENT_FLAG_MASK: u16 = 0x3;
+
+/// The bit of wrap counter in the descriptor offset of event suppression.
+const VRING_PACKED_EVENT_F_WRAP_CTR: u16 = 1 << 15;
+/// The bit of wrap counter in the ring position kept in `QueueConfig`, the
+/// bit is set when the wrap counter is 0, so that the position of a new queue
+/// starts with the wrap counter of 1 which is required by the Virtio Spec.
+const PACKED_RING_WRAP_INVERTED: u16 = 1 << 15;
+/// Max size of packed vring.
+const PACKED_VRING_MAX_SIZE: u16 = 1 << 15;
+
+/// Max total len of a descriptor chain.
+const DESC_CHAIN_MAX_TOTAL_LEN: u64 = 1u64 << 32;
+/// The length of virtio descriptor.
+const DESCRIPTOR_LEN: u64 = size_of::<PackedVringDesc>() as u64;
+/// The length of event suppression structure.
+const EVENT_LEN: u64 = size_of::<PackedVringEvent>() as u64;
+/// The position of length in the descriptor.
+const DESC_LEN_POSITION: u64 = size_of::<u64>() as u64;
+/// The position of buffer ID in the descriptor.
+const DESC_ID_POSITION: u64 = DESC_LEN_POSITION + size_of::<u32>() as u64;
+/// The position of flags in the descriptor.
+const DESC_FLAGS_POSITION: u64 = DESC_ID_POSITION + size_of::<u16>() as u64;
+
+/// Descriptor of packed vring.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+pub struct PackedVringDesc {
+    /// Address (guest-physical).
+    pub addr: GuestAddress,
+    /// Length.
+    pub len: u32,
+    /// Buffer ID.
+    pub id: u16,
+    /// The flags depending on descriptor type.
+    pub flags: u16,
+}
+
+impl ByteCode for PackedVringDesc {}
+
+impl PackedVringDesc {
+    /// Return true if this descriptor has next descriptor.
+    fn has_next(&self) -> bool {
+        self.flags & VIRTQ_DESC_F_NEXT != 0
+    }
+
+    /// Check whether this descriptor is write-only or read-only.
+    /// Write-only means that the emulated device can write and the driver can read.
+    fn write_only(&self) -> bool {
+        self.flags & VIRTQ_DESC_F_WRITE != 0
+    }
+
+    /// Return true if this descriptor is a indirect descriptor.
+    fn is_indirect_desc(&self) -> bool {
+        self.flags & VIRTQ_DESC_F_INDIRECT != 0
+    }
+
+    /// Return true if the descriptor is made available by driver in the round
+    /// of the wrap counter.
+    fn is_avail(&self, wrap_counter: bool) -> bool {
+        let avail = self.flags & VIRTQ_DESC_F_AVAIL != 0;
+        let used = self.flags & VIRTQ_DESC_F_USED != 0;
+        avail != used && avail == wrap_counter
+    }
+
+    /// Return true if the buffer of the descriptor is valid.
+    fn is_valid(&self, sys_mem: &Arc<AddressSpace>, cache: &mut Option<RegionCache>) -> bool {
+        if self.len == 0 {
+            error!("Zero sized buffers are not allowed");
+            return false;
+        }
+        let mut miss_cached = true;
+        if let Some(reg_cache) = cache {
+            let base = self.addr.0;
+            let end = match base.checked_add(u64::from(self.len)) {
+                Some(addr) => addr,
+                None => {
+                    error!("The memory of descriptor is invalid, range overflows");
+                    return false;
+                }
+            };
+            if base > reg_cache.start && end < reg_cache.end {
+                miss_cached = false;
+            }
+        } else {
+            let gotten_cache = sys_mem.get_region_cache(self.addr);
+            if let Some(obtained_cache) = gotten_cache {
+                if obtained_cache.reg_type == RegionType::Ram {
+                    *cache = gotten_cache;
+                }
+            }
+        }
+
+        if miss_cached {
+            if let Err(ref e) = checked_offset_mem(sys_mem, self.addr, u64::from(self.len)) {
+                error!("The memory of descriptor is invalid, {:?} ", e);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Return true if the indirect descriptor is valid.
+    /// The len can be divided evenly by the size of descriptor and can not be zero.
+    fn is_valid_indirect_desc(&self) -> bool {
+        if self.len == 0
+            || u64::from(self.len) % DESCRIPTOR_LEN != 0
+            || u64::from(self.len) / DESCRIPTOR_LEN > u16::MAX as u64
+        {
+            error!("The indirect descriptor is invalid, len: {}", self.len);
+            return false;
+        }
+        if self.has_next() {
+            error!("INDIRECT and NEXT flag should not be used together");
+            return false;
+        }
+        true
+    }
+
+    /// Get the num of descriptor in the table of indirect descriptor.
+    fn get_desc_num(&self) -> u16 {
+        (u64::from(self.len) / DESCRIPTOR_LEN) as u16
+    }
+}
+
+/// Event suppression structure of the driver area and the device area.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct PackedVringEvent {
+    /// Descriptor ring offset and wrap counter for VRING_PACKED_EVENT_FLAG_DESC.
+    off_wrap: u16,
+    /// Event flags.
+    flags: u16,
+}
+
+impl ByteCode for PackedVringEvent {}
+
+/// The descriptors of a chain gathered into an element.
+struct ChainGather {
+    /// The count of host writable descriptors.
+    write_elem_count: u32,
+    /// Total length of the buffers.
+    total_len: u64,
+}
+
+impl ChainGather {
+    fn new() -> Self {
+        ChainGather {
+            write_elem_count: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Put the buffer of descriptor into the element, the read-only buffers
+    /// must precede the write-only ones.
+    fn push(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        desc: &PackedVringDesc,
+        cache: &mut Option<RegionCache>,
+        elem: &mut Element,
+    ) -> Result<()> {
+        if !desc.is_valid(sys_mem, cache) {
+            return Err(anyhow!(VirtioError::QueueDescInvalid));
+        }
+        let iovec = ElemIovec {
+            addr: desc.addr,
+            len: desc.len,
+        };
+        if desc.write_only() {
+            elem.in_iovec.push(iovec);
+            self.write_elem_count += 1;
+        } else {
+            if self.write_elem_count > 0 {
+                bail!("Invalid order of the descriptor elem");
+            }
+            elem.out_iovec.push(iovec);
+        }
+        elem.desc_num += 1;
+        self.total_len += u64::from(iovec.len);
+        if self.total_len > DESC_CHAIN_MAX_TOTAL_LEN {
+            bail!("Find a descriptor chain longer than 4GB in total");
+        }
+        Ok(())
+    }
+}
+
+/// Split the ring position kept in `QueueConfig` into the index of the ring
+/// and the wrap counter.
+fn ring_pos(pos: Wrapping<u16>) -> (u16, bool) {
+    (
+        pos.0 & !PACKED_RING_WRAP_INVERTED,
+        pos.0 & PACKED_RING_WRAP_INVERTED == 0,
+    )
+}
+
+/// Combine the index of the ring and the wrap counter into the ring position
+/// kept in `QueueConfig`.
+fn to_ring_pos(index: u16, wrap_counter: bool) -> Wrapping<u16> {
+    if wrap_counter {
+        Wrapping(index)
+    } else {
+        Wrapping(index | PACKED_RING_WRAP_INVERTED)
+    }
+}
+
+/// Packed vring.
+#[derive(Default, Clone)]
+pub struct PackedVring {
+    /// Region cache information.
+    cache: Option<RegionCache>,
+    /// The configuration of virtqueue.
+    queue_config: QueueConfig,
+    /// The count of ring descriptors taken by each buffer in flight, indexed
+    /// by buffer ID.
+    desc_count: Vec<u16>,
+    /// The available position before the last popped element.
+    last_avail: Option<Wrapping<u16>>,
+}
+
+impl Deref for PackedVring {
+    type Target = QueueConfig;
+    fn deref(&self) -> &Self::Target {
+        &self.queue_config
+    }
+}
+
+impl DerefMut for PackedVring {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.queue_config
+    }
+}
+
+impl PackedVring {
+    /// Create a packed vring.
+    ///
+    /// # Arguments
+    ///
+    /// * `queue_config` - Configuration of the vring.
+    pub fn new(queue_config: QueueConfig) -> Self {
+        PackedVring {
+            cache: None,
+            queue_config,
+            desc_count: vec![0; queue_config.max_size as usize],
+            last_avail: None,
+        }
+    }
+
+    /// The actual size of the queue.
+    fn actual_size(&self) -> u16 {
+        min(self.size, self.max_size)
+    }
+
+    /// Move forward the ring index, the wrap counter flips when the index
+    /// goes over the end of ring.
+    fn advance(&self, index: u16, wrap_counter: bool, count: u16) -> (u16, bool) {
+        let next = u32::from(index) + u32::from(count);
+        let size = u32::from(self.actual_size());
+        if next >= size {
+            ((next - size) as u16, !wrap_counter)
+        } else {
+            (next as u16, wrap_counter)
+        }
+    }
+
+    /// Get the descriptor in the ring from guest memory.
+    fn get_desc(&self, sys_mem: &Arc<AddressSpace>, index: u16) -> Result<PackedVringDesc> {
+        if index >= self.actual_size() {
+            return Err(anyhow!(VirtioError::QueueIndex(index, self.actual_size())));
+        }
+        let desc_addr = self.addr_cache.desc_table_host + u64::from(index) * DESCRIPTOR_LEN;
+        sys_mem
+            .read_object_direct::<PackedVringDesc>(desc_addr)
+            .with_context(|| VirtioError::ReadObjectErr("a descriptor", desc_addr))
+    }
+
+    /// Get the event suppression structure of driver from guest memory.
+    fn get_driver_event(&self, sys_mem: &Arc<AddressSpace>) -> Result<PackedVringEvent> {
+        // Make sure the event read from sys_mem is new.
+        fence(Ordering::SeqCst);
+        sys_mem
+            .read_object_direct::<PackedVringEvent>(self.addr_cache.avail_ring_host)
+            .with_context(|| {
+                VirtioError::ReadObjectErr("driver event suppression", self.avail_ring.raw_value())
+            })
+    }
+
+    /// Set the event flags of device to suppress virtqueue notification or not.
+    fn set_device_event_flags(&self, sys_mem: &Arc<AddressSpace>, suppress: bool) -> Result<()> {
+        let event = PackedVringEvent {
+            off_wrap: 0,
+            flags: if suppress {
+                VRING_PACKED_EVENT_FLAG_DISABLE
+            } else {
+                VRING_PACKED_EVENT_FLAG_ENABLE
+            },
+        };
+        sys_mem
+            .write_object_direct::<PackedVringEvent>(&event, self.addr_cache.used_ring_host)
+            .with_context(|| {
+                format!(
+                    "Failed to set device event flags, device area: 0x{:X}",
+                    self.used_ring.raw_value()
+                )
+            })?;
+        // Make sure the data has been set.
+        fence(Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Return true if the descriptor offset in the event suppression of
+    /// driver is used since the last interrupt.
+    fn used_desc_need_event(&mut self, off_wrap: u16) -> bool {
+        let size = Wrapping(self.actual_size());
+        let (new, wrap_counter) = ring_pos(self.next_used);
+        let (old, old_wrap_counter) = ring_pos(self.last_signal_used);
+        let new = Wrapping(new);
+        // The index of the previous round is taken as negative.
+        let mut old = Wrapping(old);
+        if old_wrap_counter != wrap_counter {
+            old -= size;
+        }
+        let mut event_idx = Wrapping(off_wrap & !VRING_PACKED_EVENT_F_WRAP_CTR);
+        if (off_wrap & VRING_PACKED_EVENT_F_WRAP_CTR != 0) != wrap_counter {
+            event_idx -= size;
+        }
+
+        let valid = self.signal_used_valid;
+        self.signal_used_valid = true;
+        self.last_signal_used = self.next_used;
+        !valid || (new - event_idx - Wrapping(1)) < (new - old)
+    }
+
+    fn is_overlap(
+        start1: GuestAddress,
+        end1: GuestAddress,
+        start2: GuestAddress,
+        end2: GuestAddress,
+    ) -> bool {
+        !(start1 >= end2 || start2 >= end1)
+    }
+
+    fn is_invalid_memory(&self, sys_mem: &Arc<AddressSpace>, actual_size: u64) -> bool {
+        let desc_table_end =
+            match checked_offset_mem(sys_mem, self.desc_table, DESCRIPTOR_LEN * actual_size) {
+                Ok(addr) => addr,
+                Err(ref e) => {
+                    error!(
+                        "descriptor ring is out of bounds: start:0x{:X} size:{} {:?}",
+                        self.desc_table.raw_value(),
+                        DESCRIPTOR_LEN * actual_size,
+                        e
+                    );
+                    return true;
+                }
+            };
+
+        let driver_area_end = match checked_offset_mem(sys_mem, self.avail_ring, EVENT_LEN) {
+            Ok(addr) => addr,
+            Err(ref e) => {
+                error!(
+                    "driver area is out of bounds: start:0x{:X} size:{} {:?}",
+                    self.avail_ring.raw_value(),
+                    EVENT_LEN,
+                    e
+                );
+                return true;
+            }
+        };
+
+        let device_area_end = match checked_offset_mem(sys_mem, self.used_ring, EVENT_LEN) {
+            Ok(addr) => addr,
+            Err(ref e) => {
+                error!(
+                    "device area is out of bounds: start:0x{:X} size:{} {:?}",
+                    self.used_ring.raw_value(),
+                    EVENT_LEN,
+                    e
+                );
+                return true;
+            }
+        };
+
+        if PackedVring::is_overlap(
+            self.desc_table,
+            desc_table_end,
+            self.avail_ring,
+            driver_area_end,
+        ) || PackedVring::is_overlap(
+            self.avail_ring,
+            driver_area_end,
+            self.used_ring,
+            device_area_end,
+        ) || PackedVring::is_overlap(
+            self.desc_table,
+            desc_table_end,
+            self.used_ring,
+            device_area_end,
+        ) {
+            error!("The memory of descriptor ring: 0x{:X}, driver area: 0x{:X} or device area: 0x{:X} is overlapped. queue size:{}",
+                   self.desc_table.raw_value(), self.avail_ring.raw_value(), self.used_ring.raw_value(), actual_size);
+            return true;
+        }
+
+        if self.desc_table.0 & 0xf != 0 {
+            error!(
+                "descriptor ring: 0x{:X} is not aligned",
+                self.desc_table.raw_value()
+            );
+            true
+        } else if self.avail_ring.0 & 0x3 != 0 {
+            error!(
+                "driver area: 0x{:X} is not aligned",
+                self.avail_ring.raw_value()
+            );
+            true
+        } else if self.used_ring.0 & 0x3 != 0 {
+            error!(
+                "device area: 0x{:X} is not aligned",
+                self.used_ring.raw_value()
+            );
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Put the descriptors in the indirect table into the element.
+    fn get_indirect_element(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        desc: &PackedVringDesc,
+        elem: &mut Element,
+    ) -> Result<()> {
+        if !desc.is_valid_indirect_desc() || !desc.is_valid(sys_mem, &mut self.cache) {
+            return Err(anyhow!(VirtioError::QueueDescInvalid));
+        }
+        let (table_host, table_len) = sys_mem
+            .get_host_address_from_cache(desc.addr, &self.cache)
+            .with_context(|| "Failed to get descriptor table entry host address")?;
+        if table_len < u64::from(desc.len) {
+            bail!("The indirect descriptor table crosses the memory region");
+        }
+
+        let mut gather = ChainGather::new();
+        for i in 0..desc.get_desc_num() {
+            let desc_addr = table_host + u64::from(i) * DESCRIPTOR_LEN;
+            let indirect_desc = sys_mem
+                .read_object_direct::<PackedVringDesc>(desc_addr)
+                .with_context(|| VirtioError::ReadObjectErr("an indirect descriptor", desc_addr))?;
+            gather.push(sys_mem, &indirect_desc, &mut self.cache, elem)?;
+        }
+        Ok(())
+    }
+
+    /// Get the element of the descriptor chain starting from the position of
+    /// ring, and return the count of ring descriptors taken by the chain.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_mem` - Address space to which the vring belongs.
+    /// * `head` - Index of the head descriptor in the ring.
+    /// * `wrap_counter` - Wrap counter of the head descriptor, the descriptors
+    ///   are not checked to be available if it's `None`.
+    /// * `elem` - The element to be filled.
+    fn get_chain_element(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        head: u16,
+        wrap_counter: Option<bool>,
+        elem: &mut Element,
+    ) -> Result<u16> {
+        let mut gather = ChainGather::new();
+        let mut index = head;
+        let mut wrap = wrap_counter.unwrap_or(true);
+        let mut count: u16 = 0;
+
+        loop {
+            if count >= self.actual_size() {
+                bail!("The element desc number exceeds max allowed");
+            }
+            let desc = self.get_desc(sys_mem, index)?;
+            if count > 0 && wrap_counter.is_some() && !desc.is_avail(wrap) {
+                bail!("The descriptor {} in the chain is not available", index);
+            }
+            count += 1;
+            (index, wrap) = self.advance(index, wrap, 1);
+
+            if desc.is_indirect_desc() {
+                if count > 1 {
+                    bail!("Found indirect descriptor elem in the chain");
+                }
+                self.get_indirect_element(sys_mem, &desc, elem)?;
+            } else {
+                gather.push(sys_mem, &desc, &mut self.cache, elem)?;
+                if desc.has_next() {
+                    continue;
+                }
+            }
+
+            // The buffer ID is in the last descriptor of the chain.
+            if desc.id >= self.actual_size() {
+                return Err(anyhow!(VirtioError::QueueIndex(
+                    desc.id,
+                    self.actual_size()
+                )));
+            }
+            elem.index = desc.id;
+            return Ok(count);
+        }
+    }
+
+    fn get_vring_element(&mut self, sys_mem: &Arc<AddressSpace>, elem: &mut Element) -> Result<()> {
+        let (head, wrap_counter) = ring_pos(self.next_avail);
+        let count = self
+            .get_chain_element(sys_mem, head, Some(wrap_counter), elem)
+            .with_context(|| {
+                format!(
+                    "Failed to get element from descriptor chain {}, ring addr: 0x{:X}, size: {}",
+                    head,
+                    self.addr_cache.desc_table_host,
+                    self.actual_size(),
+                )
+            })?;
+
+        self.desc_count[elem.index as usize] = count;
+        self.last_avail = Some(self.next_avail);
+        let (next, wrap_counter) = self.advance(head, wrap_counter, count);
+        self.next_avail = to_ring_pos(next, wrap_counter);
+
+        Ok(())
+    }
+}
+
+impl VringOps for PackedVring {
+    fn is_enabled(&self) -> bool {
+        self.ready
+    }
+
+    fn is_valid(&self, sys_mem: &Arc<AddressSpace>) -> bool {
+        let size = u64::from(self.actual_size());
+        if !self.ready {
+            error!("The configuration of vring is not ready\n");
+            false
+        } else if self.size > self.max_size || self.size == 0 || self.size > PACKED_VRING_MAX_SIZE {
+            error!(
+                "vring with invalid size:{} max size:{}",
+                self.size, self.max_size
+            );
+            false
+        } else {
+            !self.is_invalid_memory(sys_mem, size)
+        }
+    }
+
+    fn pop_avail(&mut self, sys_mem: &Arc<AddressSpace>, _features: u64) -> Result<Element> {
+        let mut element = Element::new(0);
+        if !self.is_enabled() {
+            return Ok(element);
+        }
+        let (head, wrap_counter) = ring_pos(self.next_avail);
+        if !self.get_desc(sys_mem, head)?.is_avail(wrap_counter) {
+            return Ok(element);
+        }
+
+        // Make sure descriptor read does not bypass the flags read.
+        fence(Ordering::Acquire);
+
+        self.get_vring_element(sys_mem, &mut element)
+            .with_context(|| "Failed to get vring element")?;
+
+        Ok(element)
+    }
+
+    fn push_back(&mut self) {
+        if let Some(last_avail) = self.last_avail.take() {
+            self.next_avail = last_avail;
+        }
+    }
+
+    fn get_desc_chain(&mut self, sys_mem: &Arc<AddressSpace>, head: u16) -> Result<Element> {
+        let mut elem = Element::new(head);
+        self.get_chain_element(sys_mem, head, None, &mut elem)?;
+
+        Ok(elem)
+    }
+
+    fn add_used(&mut self, sys_mem: &Arc<AddressSpace>, index: u16, len: u32) -> Result<()> {
+        if index >= self.actual_size() {
+            return Err(anyhow!(VirtioError::QueueIndex(index, self.actual_size())));
+        }
+        let count = self.desc_count[index as usize];
+        if count == 0 {
+            bail!("The buffer {} is not in use", index);
+        }
+
+        let (next_used, wrap_counter) = ring_pos(self.next_used);
+        let desc_addr = self.addr_cache.desc_table_host + u64::from(next_used) * DESCRIPTOR_LEN;
+        let mut flags = if wrap_counter {
+            VIRTQ_DESC_F_AVAIL | VIRTQ_DESC_F_USED
+        } else {
+            0
+        };
+        if len != 0 {
+            flags |= VIRTQ_DESC_F_WRITE;
+        }
+        sys_mem
+            .write_object_direct(&len, desc_addr + DESC_LEN_POSITION)
+            .with_context(|| "Failed to write length of used descriptor")?;
+        sys_mem
+            .write_object_direct(&index, desc_addr + DESC_ID_POSITION)
+            .with_context(|| "Failed to write id of used descriptor")?;
+        // Make sure used descriptor is filled before updating the flags.
+        fence(Ordering::Release);
+        sys_mem
+            .write_object_direct(&flags, desc_addr + DESC_FLAGS_POSITION)
+            .with_context(|| "Failed to write flags of used descriptor")?;
+        // Make sure used descriptor is exposed before notifying guest.
+        fence(Ordering::SeqCst);
+
+        self.desc_count[index as usize] = 0;
+        let (next, next_wrap_counter) = self.advance(next_used, wrap_counter, count);
+        self.next_used = to_ring_pos(next, next_wrap_counter);
+
+        // Do we go over a whole round since the last interrupt?
+        let (last_signal_used, last_wrap_counter) = ring_pos(self.last_signal_used);
+        if last_wrap_counter != next_wrap_counter && next >= last_signal_used {
+            self.signal_used_valid = false;
+        }
+        Ok(())
+    }
+
+    fn should_notify(&mut self, sys_mem: &Arc<AddressSpace>, features: u64) -> bool {
+        let event = match self.get_driver_event(sys_mem) {
+            Ok(event) => event,
+            Err(ref e) => {
+                warn!("Failed to get the driver event suppression {:?}", e);
+                return true;
+            }
+        };
+
+        match event.flags & VRING_PACKED_EVENT_FLAG_MASK {
+            VRING_PACKED_EVENT_FLAG_DISABLE => false,
+            VRING_PACKED_EVENT_FLAG_DESC
+                if virtio_has_feature(features, VIRTIO_F_RING_EVENT_IDX) =>
+            {
+                self.used_desc_need_event(event.off_wrap)
+            }
+            _ => true,
+        }
+    }
+
+    fn suppress_queue_notify(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        _features: u64,
+        suppress: bool,
+    ) -> Result<()> {
+        self.set_device_event_flags(sys_mem, suppress)
+    }
+
+    fn actual_size(&self) -> u16 {
+        self.actual_size()
+    }
+
+    fn get_queue_config(&self) -> QueueConfig {
+        let mut config = self.queue_config;
+        config.signal_used_valid = false;
+        config
+    }
+
+    /// The number of descriptor chains made available by driver.
+    fn avail_ring_len(&mut self, sys_mem: &Arc<AddressSpace>) -> Result<u16> {
+        let (mut index, mut wrap_counter) = ring_pos(self.next_avail);
+        let mut len = 0;
+        for _ in 0..self.actual_size() {
+            let desc = self.get_desc(sys_mem, index)?;
+            if !desc.is_avail(wrap_counter) {
+                break;
+            }
+            if !desc.has_next() {
+                len += 1;
+            }
+            (index, wrap_counter) = self.advance(index, wrap_counter, 1);
+        }
+
+        Ok(len)
+    }
+
+    /// The available position with the wrap counter in the highest bit, which
+    /// is the format of vhost backends.
+    fn get_avail_idx(&self, _sys_mem: &Arc<AddressSpace>) -> Result<u16> {
+        let (index, wrap_counter) = ring_pos(self.next_avail);
+        Ok(index | (u16::from(wrap_counter) << 15))
+    }
+
+    fn get_cache(&self) -> &Option<RegionCache> {
+        &self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Queue, QUEUE_TYPE_PACKED_VRING};
+    use address_space::{AddressSpace, GuestAddress, HostMemMapping, HostMemOptions, Region};
+
+    const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
+    const QUEUE_SIZE: u16 = 256 as u16;
+
+    fn address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36, "sysmem");
+        let sys_space = AddressSpace::new(root, "sysmem").unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                SYSTEM_SPACE_SIZE,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
+        );
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone(), "sysmem"),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    fn queue_config_init(sys_space: &Arc<AddressSpace>, size: u16) -> QueueConfig {
+        let mut queue_config = QueueConfig::new(QUEUE_SIZE);
+        queue_config.desc_table = GuestAddress(0);
+        queue_config.addr_cache.desc_table_host =
+            sys_space.get_host_address(queue_config.desc_table).unwrap();
+        queue_config.avail_ring = GuestAddress((QUEUE_SIZE as u64) * DESCRIPTOR_LEN);
+        queue_config.addr_cache.avail_ring_host =
+            sys_space.get_host_address(queue_config.avail_ring).unwrap();
+        queue_config.used_ring = GuestAddress((QUEUE_SIZE as u64) * DESCRIPTOR_LEN + EVENT_LEN);
+        queue_config.addr_cache.used_ring_host =
+            sys_space.get_host_address(queue_config.used_ring).unwrap();
+        queue_config.ready = true;
+        queue_config.size = size;
+        queue_config
+    }
+
+    /// The flags of descriptor made available by driver.
+    fn avail_flags(wrap_counter: bool) -> u16 {
+        if wrap_counter {
+            VIRTQ_DESC_F_AVAIL
+        } else {
+            VIRTQ_DESC_F_USED
+        }
+    }
+
+    impl PackedVring {
+        fn set_desc(
+            &self,
+            sys_mem: &Arc<AddressSpace>,
+            index: u16,
+            addr: GuestAddress,
+            len: u32,
+            id: u16,
+            flags: u16,
+        ) -> Result<()> {
+            let desc = PackedVringDesc {
+                addr,
+                len,
+                id,
+                flags,
+            };
+            sys_mem.write_object::<PackedVringDesc>(
+                &desc,
+                GuestAddress(self.desc_table.0 + u64::from(index) * DESCRIPTOR_LEN),
+            )
+        }
+
+        fn set_driver_event(
+            &self,
+            sys_mem: &Arc<AddressSpace>,
+            off_wrap: u16,
+            flags: u16,
+        ) -> Result<()> {
+            let event = PackedVringEvent { off_wrap, flags };
+            sys_mem.write_object::<PackedVringEvent>(&event, self.avail_ring)
+        }
+
+        fn get_device_event(&self, sys_mem: &Arc<AddressSpace>) -> Result<PackedVringEvent> {
+            sys_mem.read_object::<PackedVringEvent>(self.used_ring)
+        }
+    }
+
+    #[test]
+    fn test_packed_valid_queue() {
+        let sys_space = address_space_init();
+
+        let queue_config = queue_config_init(&sys_space, QUEUE_SIZE);
+        let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING).unwrap();
+        assert_eq!(queue.is_valid(&sys_space), true);
+
+        // it is valid when the size of virtual ring isn't power of 2
+        let queue_config = queue_config_init(&sys_space, 15);
+        let vring = PackedVring::new(queue_config);
+        assert_eq!(vring.is_valid(&sys_space), true);
+
+        // it is invalid when the size of virtual ring is zero
+        let queue_config = queue_config_init(&sys_space, 0);
+        let vring = PackedVring::new(queue_config);
+        assert_eq!(vring.is_valid(&sys_space), false);
+
+        // it is invalid when the driver area is not aligned
+        let mut queue_config = queue_config_init(&sys_space, QUEUE_SIZE);
+        queue_config.avail_ring = GuestAddress(queue_config.avail_ring.0 + 2);
+        let vring = PackedVring::new(queue_config);
+        assert_eq!(vring.is_valid(&sys_space), false);
+
+        // it is invalid when the device area overlaps the descriptor ring
+        let mut queue_config = queue_config_init(&sys_space, QUEUE_SIZE);
+        queue_config.used_ring = GuestAddress(DESCRIPTOR_LEN);
+        let vring = PackedVring::new(queue_config);
+        assert_eq!(vring.is_valid(&sys_space), false);
+
+        // it is invalid when the device area is out of memory
+        let mut queue_config = queue_config_init(&sys_space, QUEUE_SIZE);
+        queue_config.used_ring = GuestAddress(SYSTEM_SPACE_SIZE);
+        let vring = PackedVring::new(queue_config);
+        assert_eq!(vring.is_valid(&sys_space), false);
+    }
+
+    #[test]
+    fn test_packed_pop_avail() {
+        let sys_space = address_space_init();
+        let queue_config = queue_config_init(&sys_space, QUEUE_SIZE);
+        let mut vring = PackedVring::new(queue_config);
+        let features = 0_u64;
+        let buf = SYSTEM_SPACE_SIZE / 2;
+
+        // nothing is available in the ring
+        let elem = vring.pop_avail(&sys_space, features).unwrap();
+        assert_eq!(elem.desc_num, 0);
+        assert_eq!(vring.avail_ring_len(&sys_space).unwrap(), 0);
+
+        // a chain of two descriptors with buffer id 5, and a single
+        // descriptor with buffer id 6
+        let flags = avail_flags(true);
+        vring
+            .set_desc(
+                &sys_space,
+                0,
+                GuestAddress(buf),
+                16,
+                0,
+                flags | VIRTQ_DESC_F_NEXT,
+            )
+            .unwrap();
+        vring
+            .set_desc(
+                &sys_space,
+                1,
+                GuestAddress(buf + 0x100),
+                32,
+                5,
+                flags | VIRTQ_DESC_F_WRITE,
+            )
+            .unwrap();
+        vring
+            .set_desc(&sys_space, 2, GuestAddress(buf + 0x200), 64, 6, flags)
+            .unwrap();
+        assert_eq!(vring.avail_ring_len(&sys_space).unwrap(), 2);
+
+        let elem = vring.pop_avail(&sys_space, features).unwrap();
+        assert_eq!(elem.index, 5);
+        assert_eq!(elem.desc_num, 2);
+        assert_eq!(elem.out_iovec.len(), 1);
+        assert_eq!(elem.out_iovec[0].addr, GuestAddress(buf));
+        assert_eq!(elem.in_iovec.len(), 1);
+        assert_eq!(elem.in_iovec[0].len, 32);
+        assert_eq!(vring.avail_ring_len(&sys_space).unwrap(), 1);
+
+        // the element is popped again after rollback
+        vring.push_back();
+        let elem = vring.pop_avail(&sys_space, features).unwrap();
+        assert_eq!(elem.index, 5);
+
+        let elem = vring.pop_avail(&sys_space, features).unwrap();
+        assert_eq!(elem.index, 6);
+        assert_eq!(elem.desc_num, 1);
+        assert_eq!(vring.get_avail_idx(&sys_space).unwrap(), 3 | 1 << 15);
+        let elem = vring.pop_avail(&sys_space, features).unwrap();
+        assert_eq!(elem.desc_num, 0);
+
+        // the descriptor is available with the flags of next round
+        vring
+            .set_desc(&sys_space, 3, GuestAddress(buf), 16, 7, avail_flags(false))
+            .unwrap();
+        let elem = vring.pop_avail(&sys_space, features).unwrap();
+        assert_eq!(elem.desc_num, 0);
+
+        // it is invalid when the write-only descriptor precedes the read-only one
+        vring
+            .set_desc(
+                &sys_space,
+                3,
+                GuestAddress(buf),
+                16,
+                0,
+                flags | VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT,
+            )
+            .unwrap();
+        vring
+            .set_desc(&sys_space, 4, GuestAddress(buf + 0x100), 16, 7, flags)
+            .unwrap();
+        assert!(vring.pop_avail(&sys_space, features).is_err());
+
+        // it is invalid when the buffer id exceeds the queue size
+        vring
+            .set_desc(&sys_space, 3, GuestAddress(buf), 16, QUEUE_SIZE, flags)
+            .unwrap();
+        assert!(vring.pop_avail(&sys_space, features).is_err());
+
+        // the descriptors in the indirect table are gathered in order
+        let table = buf + 0x1000;
+        let indirect_desc = |addr: u64, len: u32, flags: u16| PackedVringDesc {
+            addr: GuestAddress(addr),
+            len,
+            id: 0,
+            flags,
+        };
+        sys_space
+            .write_object(&indirect_desc(buf, 8, 0), GuestAddress(table))
+            .unwrap();
+        sys_space
+            .write_object(
+                &indirect_desc(buf + 0x100, 16, VIRTQ_DESC_F_WRITE),
+                GuestAddress(table + DESCRIPTOR_LEN),
+            )
+            .unwrap();
+        vring
+            .set_desc(
+                &sys_space,
+                3,
+                GuestAddress(table),
+                (DESCRIPTOR_LEN * 2) as u32,
+                8,
+                flags | VIRTQ_DESC_F_INDIRECT,
+            )
+            .unwrap();
+        let elem = vring.pop_avail(&sys_space, features).unwrap();
+        assert_eq!(elem.index, 8);
+        assert_eq!(elem.desc_num, 2);
+        assert_eq!(elem.out_iovec[0].len, 8);
+        assert_eq!(elem.in_iovec[0].len, 16);
+        assert_eq!(vring.desc_count[8], 1);
+
+        // the chain in the ring is got by the head without consuming it
+        let elem = vring.get_desc_chain(&sys_space, 0).unwrap();
+        assert_eq!(elem.index, 5);
+        assert_eq!(elem.desc_num, 2);
+        assert_eq!(vring.get_avail_idx(&sys_space).unwrap(), 4 | 1 << 15);
+    }
+
+    #[test]
+    fn test_packed_add_used() {
+        let sys_space = address_space_init();
+        let queue_config = queue_config_init(&sys_space, 4);
+        let mut vring = PackedVring::new(queue_config);
+        let features = 0_u64;
+        let buf = SYSTEM_SPACE_SIZE / 2;
+
+        // it is invalid when the buffer is not in use
+        assert!(vring.add_used(&sys_space, 1, 0).is_err());
+        assert!(vring.add_used(&sys_space, 4, 0).is_err());
+
+        // three chains take the whole ring
+        let flags = avail_flags(true);
+        vring
+            .set_desc(
+                &sys_space,
+                0,
+                GuestAddress(buf),
+                16,
+                0,
+                flags | VIRTQ_DESC_F_NEXT,
+            )
+            .unwrap();
+        vring
+            .set_desc(&sys_space, 1, GuestAddress(buf), 16, 1, flags)
+            .unwrap();
+        vring
+            .set_desc(&sys_space, 2, GuestAddress(buf), 16, 2, flags)
+            .unwrap();
+        vring
+            .set_desc(&sys_space, 3, GuestAddress(buf), 16, 3, flags)
+            .unwrap();
+        for id in 1..4 {
+            assert_eq!(vring.pop_avail(&sys_space, features).unwrap().index, id);
+        }
+        assert_eq!(ring_pos(vring.next_avail), (0, false));
+
+        // the used descriptors are written in the order of completion, and
+        // each one skips the descriptors taken by the chain
+        vring.add_used(&sys_space, 3, 0).unwrap();
+        vring.add_used(&sys_space, 1, 100).unwrap();
+        let desc = vring.get_desc(&sys_space, 0).unwrap();
+        assert_eq!(desc.id, 3);
+        assert_eq!(desc.flags, VIRTQ_DESC_F_AVAIL | VIRTQ_DESC_F_USED);
+        let desc = vring.get_desc(&sys_space, 1).unwrap();
+        assert_eq!(desc.id, 1);
+        assert_eq!(desc.len, 100);
+        assert_eq!(
+            desc.flags,
+            VIRTQ_DESC_F_AVAIL | VIRTQ_DESC_F_USED | VIRTQ_DESC_F_WRITE
+        );
+        assert!(!desc.is_avail(true));
+        assert_eq!(ring_pos(vring.next_used), (3, true));
+        vring.add_used(&sys_space, 2, 0).unwrap();
+        assert_eq!(ring_pos(vring.next_used), (0, false));
+
+        // the descriptor of next round is used with the flags cleared
+        let flags = avail_flags(false);
+        vring
+            .set_desc(&sys_space, 0, GuestAddress(buf), 16, 2, flags)
+            .unwrap();
+        assert_eq!(vring.pop_avail(&sys_space, features).unwrap().index, 2);
+        vring.add_used(&sys_space, 2, 0).unwrap();
+        let desc = vring.get_desc(&sys_space, 0).unwrap();
+        assert_eq!(desc.flags, 0);
+        assert_eq!(ring_pos(vring.next_used), (1, false));
+
+        // the state of ring is kept in the configuration
+        let vring = PackedVring::new(vring.get_queue_config());
+        assert_eq!(ring_pos(vring.next_avail), (1, false));
+        assert_eq!(ring_pos(vring.next_used), (1, false));
+    }
+
+    #[test]
+    fn test_packed_event_suppression() {
+        let sys_space = address_space_init();
+        let queue_config = queue_config_init(&sys_space, QUEUE_SIZE);
+        let mut vring = PackedVring::new(queue_config);
+        let buf = SYSTEM_SPACE_SIZE / 2;
+
+        // device asks driver to disable or enable the notifications
+        vring.suppress_queue_notify(&sys_space, 0, true).unwrap();
+        let event = vring.get_device_event(&sys_space).unwrap();
+        assert_eq!(event.flags, VRING_PACKED_EVENT_FLAG_DISABLE);
+        vring.suppress_queue_notify(&sys_space, 0, false).unwrap();
+        let event = vring.get_device_event(&sys_space).unwrap();
+        assert_eq!(event.flags, VRING_PACKED_EVENT_FLAG_ENABLE);
+
+        // driver disables or enables the interrupts
+        let features = 0_u64;
+        vring
+            .set_driver_event(&sys_space, 0, VRING_PACKED_EVENT_FLAG_DISABLE)
+            .unwrap();
+        assert_eq!(vring.should_notify(&sys_space, features), false);
+        vring
+            .set_driver_event(&sys_space, 0, VRING_PACKED_EVENT_FLAG_ENABLE)
+            .unwrap();
+        assert_eq!(vring.should_notify(&sys_space, features), true);
+        // the descriptor offset is ignored without event idx
+        vring
+            .set_driver_event(&sys_space, 100, VRING_PACKED_EVENT_FLAG_DESC)
+            .unwrap();
+        assert_eq!(vring.should_notify(&sys_space, features), true);
+
+        // driver asks for the interrupt when the descriptor 2 is used
+        let features = 1 << VIRTIO_F_RING_EVENT_IDX as u64;
+        let flags = avail_flags(true);
+        for i in 0..4 {
+            vring
+                .set_desc(&sys_space, i, GuestAddress(buf), 16, i, flags)
+                .unwrap();
+            vring.pop_avail(&sys_space, features).unwrap();
+        }
+        vring
+            .set_driver_event(
+                &sys_space,
+                2 | VRING_PACKED_EVENT_F_WRAP_CTR,
+                VRING_PACKED_EVENT_FLAG_DESC,
+            )
+            .unwrap();
+        // the first interrupt is always sent
+        vring.add_used(&sys_space, 0, 0).unwrap();
+        assert_eq!(vring.should_notify(&sys_space, features), true);
+        vring.add_used(&sys_space, 1, 0).unwrap();
+        assert_eq!(vring.should_notify(&sys_space, features), false);
+        vring.add_used(&sys_space, 2, 0).unwrap();
+        assert_eq!(vring.should_notify(&sys_space, features), true);
+        vring.add_used(&sys_space, 3, 0).unwrap();
+        assert_eq!(vring.should_notify(&sys_space, features), false);
+
+        // the descriptor offset of the next round is not reached
+        vring
+            .set_driver_event(&sys_space, 2, VRING_PACKED_EVENT_FLAG_DESC)
+            .unwrap();
+        for i in 4..8 {
+            vring
+                .set_desc(&sys_space, i, GuestAddress(buf), 16, i, flags)
+                .unwrap();
+            vring.pop_avail(&sys_space, features).unwrap();
+            vring.add_used(&sys_space, i, 0).unwrap();
+            assert_eq!(vring.should_notify(&sys_space, features), false);
+        }
+    }
+}
@@ -82,13 +82,13 @@ pub struct QueueConfig {
     /// Interrupt vector index of the queue for msix
     pub vector: u16,
     /// The next index which can be popped in the available vring.
-    next_avail: Wrapping<u16>,
+    pub(super) next_avail: Wrapping<u16>,
     /// The next index which can be pushed in the used vring.
-    next_used: Wrapping<u16>,
+    pub(super) next_used: Wrapping<u16>,
     /// The index of last descriptor used which has triggered interrupt.
-    last_signal_used: Wrapping<u16>,
+    pub(super) last_signal_used: Wrapping<u16>,
     /// The last_signal_used is valid or not.
-    signal_used_valid: bool,
+    pub(super) signal_used_valid: bool,
 }
 
 impl QueueConfig {
@@ -765,23 +765,31 @@ impl SplitVring {
                 .with_context(|| "Failed to set avail event for popping avail ring")?;
         }
 
+        self.get_chain_element(sys_mem, desc_index, desc, elem)?;
+        self.next_avail += Wrapping(1);
+
+        Ok(())
+    }
+
+    fn get_chain_element(
+        &mut self,
+        sys_mem: &Arc<AddressSpace>,
+        desc_index: u16,
+        desc: SplitVringDesc,
+        elem: &mut Element,
+    ) -> Result<()> {
         let desc_info = DescInfo {
             table_host: self.addr_cache.desc_table_host,
             size: self.actual_size(),
             index: desc_index,
             desc,
         };
-        SplitVringDesc::get_element(sys_mem, &desc_info, &mut self.cache, elem).with_context(
-            || {
-                format!(
-                    "Failed to get element from descriptor chain {}, table addr: 0x{:X}, size: {}",
-                    desc_info.index, desc_info.table_host, desc_info.size,
-                )
-            },
-        )?;
-        self.next_avail += Wrapping(1);
-
-        Ok(())
+        SplitVringDesc::get_element(sys_mem, &desc_info, &mut self.cache, elem).with_context(|| {
+            format!(
+                "Failed to get element from descriptor chain {}, table addr: 0x{:X}, size: {}",
+                desc_info.index, desc_info.table_host, desc_info.size,
+            )
+        })
     }
 }
 
@@ -826,6 +834,20 @@ impl VringOps for SplitVring {
         self.next_avail -= Wrapping(1);
     }
 
+    fn get_desc_chain(&mut self, sys_mem: &Arc<AddressSpace>, head: u16) -> Result<Element> {
+        let desc = SplitVringDesc::new(
+            sys_mem,
+            self.addr_cache.desc_table_host,
+            self.actual_size(),
+            head,
+            &mut self.cache,
+        )?;
+        let mut elem = Element::new(head);
+        self.get_chain_element(sys_mem, head, desc, &mut elem)?;
+
+        Ok(elem)
+    }
+
     fn add_used(&mut self, sys_mem: &Arc<AddressSpace>, index: u16, len: u32) -> Result<()> {
         if index >= self.size {
             return Err(anyhow!(VirtioError::QueueIndex(index, self.size)));
@@ -912,8 +934,8 @@ impl VringOps for SplitVring {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Queue, QUEUE_TYPE_PACKED_VRING, QUEUE_TYPE_SPLIT_VRING};
-    use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
+    use crate::{ChainBuffer, Queue, QUEUE_TYPE_PACKED_VRING, QUEUE_TYPE_SPLIT_VRING};
+    use address_space::{AddressSpace, GuestAddress, HostMemMapping, HostMemOptions, Region};
 
     fn address_space_init() -> Arc<AddressSpace> {
         let root = Region::init_container_region(1 << 36, "sysmem");
@@ -924,9 +946,7 @@ mod tests {
                 None,
                 SYSTEM_SPACE_SIZE,
                 None,
-                false,
-                false,
-                false,
+                HostMemOptions::default(),
             )
             .unwrap(),
         );
@@ -1106,8 +1126,9 @@ mod tests {
         // failed when the type of queue is invalid
         let queue = Queue::new(queue_config, 0);
         assert!(queue.is_err());
+        // packed vring is supported
         let queue = Queue::new(queue_config, QUEUE_TYPE_PACKED_VRING);
-        assert!(queue.is_err());
+        assert!(queue.is_ok());
 
         // it is valid
         queue_config.desc_table = GuestAddress(0);
@@ -1979,4 +2000,126 @@ mod tests {
         assert!(vring.set_used_event_idx(&sys_space, 4).is_ok()); //event_idx
         assert_eq!(vring.should_notify(&sys_space, features), false);
     }
+
+    #[test]
+    fn test_pop_descriptor_chain() {
+        let sys_space = address_space_init();
+
+        let mut queue_config = QueueConfig::new(QUEUE_SIZE);
+        queue_config.desc_table = GuestAddress(0);
+        queue_config.addr_cache.desc_table_host =
+            sys_space.get_host_address(queue_config.desc_table).unwrap();
+        queue_config.avail_ring = GuestAddress((QUEUE_SIZE as u64) * DESCRIPTOR_LEN);
+        queue_config.addr_cache.avail_ring_host =
+            sys_space.get_host_address(queue_config.avail_ring).unwrap();
+        queue_config.used_ring = GuestAddress(align(
+            (QUEUE_SIZE as u64) * DESCRIPTOR_LEN
+                + VRING_AVAIL_LEN_EXCEPT_AVAILELEM
+                + AVAILELEM_LEN * (QUEUE_SIZE as u64),
+            4096,
+        ));
+        queue_config.addr_cache.used_ring_host =
+            sys_space.get_host_address(queue_config.used_ring).unwrap();
+        queue_config.ready = true;
+        queue_config.size = QUEUE_SIZE;
+        let vring = SplitVring::new(queue_config);
+        let mut queue = Queue::new(queue_config, QUEUE_TYPE_SPLIT_VRING).unwrap();
+        assert!(queue.is_valid(&sys_space));
+
+        let buffer = |addr: u64, len: u32, write_only: bool| ChainBuffer {
+            addr: GuestAddress(addr),
+            len,
+            write_only,
+        };
+
+        // Direct chain: 3 -> 7 -> 5.
+        vring
+            .set_desc(&sys_space, 3, GuestAddress(0x111), 10, VIRTQ_DESC_F_NEXT, 7)
+            .unwrap();
+        vring
+            .set_desc(
+                &sys_space,
+                7,
+                GuestAddress(0x222),
+                20,
+                VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE,
+                5,
+            )
+            .unwrap();
+        vring
+            .set_desc(
+                &sys_space,
+                5,
+                GuestAddress(0x333),
+                30,
+                VIRTQ_DESC_F_WRITE,
+                0,
+            )
+            .unwrap();
+        let chain = queue.pop_descriptor_chain(&sys_space, 3).unwrap();
+        assert_eq!(chain.head, 3);
+        assert_eq!(
+            chain.collect::<Vec<ChainBuffer>>(),
+            vec![
+                buffer(0x111, 10, false),
+                buffer(0x222, 20, true),
+                buffer(0x333, 30, true)
+            ]
+        );
+
+        // Indirect chain: 9 -> indirect table [0 -> 2 -> 1].
+        let table = SYSTEM_SPACE_SIZE / 2;
+        vring
+            .set_desc(
+                &sys_space,
+                9,
+                GuestAddress(table),
+                (DESCRIPTOR_LEN * 3) as u32,
+                VIRTQ_DESC_F_INDIRECT,
+                0,
+            )
+            .unwrap();
+        for (index, addr, len, flags, next) in [
+            (0, 0x444, 40, VIRTQ_DESC_F_NEXT, 2),
+            (1, 0x666, 60, VIRTQ_DESC_F_WRITE, 0),
+            (2, 0x555, 50, VIRTQ_DESC_F_NEXT, 1),
+        ] {
+            set_indirect_desc(
+                &sys_space,
+                GuestAddress(table + DESCRIPTOR_LEN * index),
+                GuestAddress(addr),
+                len,
+                flags,
+                next,
+            )
+            .unwrap();
+        }
+        let chain = queue.pop_descriptor_chain(&sys_space, 9).unwrap();
+        assert_eq!(chain.head, 9);
+        assert_eq!(
+            chain.collect::<Vec<ChainBuffer>>(),
+            vec![
+                buffer(0x444, 40, false),
+                buffer(0x555, 50, false),
+                buffer(0x666, 60, true)
+            ]
+        );
+
+        // The avail ring is not consumed.
+        assert_eq!(queue.vring.avail_ring_len(&sys_space).unwrap(), 0);
+
+        // The head exceeds the queue size.
+        assert!(queue.pop_descriptor_chain(&sys_space, QUEUE_SIZE).is_err());
+        // The indirect table is followed by another indirect descriptor.
+        set_indirect_desc(
+            &sys_space,
+            GuestAddress(table + DESCRIPTOR_LEN),
+            GuestAddress(table),
+            (DESCRIPTOR_LEN * 3) as u32,
+            VIRTQ_DESC_F_INDIRECT,
+            0,
+        )
+        .unwrap();
+        assert!(queue.pop_descriptor_chain(&sys_space, 9).is_err());
+    }
 }
@@ -142,10 +142,9 @@ impl VirtioMmioCommonConfig {
     pub fn new(device: &Arc<Mutex<dyn VirtioDevice>>) -> Self {
         let locked_device = device.lock().unwrap();
         let mut queues_config = [QueueConfig::default(); 8];
-        let queue_size = locked_device.queue_size();
         let queue_num = locked_device.queue_num();
-        for queue_config in queues_config.iter_mut().take(queue_num) {
-            *queue_config = QueueConfig::new(queue_size);
+        for (index, queue_config) in queues_config.iter_mut().take(queue_num).enumerate() {
+            *queue_config = QueueConfig::new(locked_device.queue_size_of(index));
         }
 
         VirtioMmioCommonConfig {
@@ -269,12 +268,11 @@ impl VirtioMmioCommonConfig {
                     CONFIG_STATUS_DRIVER,
                     CONFIG_STATUS_FEATURES_OK | CONFIG_STATUS_FAILED,
                 ) {
-                    device
-                        .lock()
-                        .unwrap()
-                        .set_driver_features(self.acked_features_select, value);
+                    let mut locked_dev = device.lock().unwrap();
+                    locked_dev.set_driver_features(self.acked_features_select, value);
                     if self.acked_features_select == 1
                         && virtio_has_feature(u64::from(value) << 32, VIRTIO_F_RING_PACKED)
+                        && locked_dev.negotiate_packed_queue_feature()
                     {
                         self.queue_type = QUEUE_TYPE_PACKED_VRING;
                     }
@@ -454,6 +452,30 @@ impl VirtioMmioDevice {
         Ok(())
     }
 
+    /// Reset the virtio device, this function is called by vcpu thread when frontend
+    /// virtio driver writes 0 to the device status.
+    pub(crate) fn reset_device(&mut self) -> Result<()> {
+        let mut locked_state = self.state.lock().unwrap();
+        let activated = locked_state.activated;
+        locked_state.activated = false;
+        locked_state.config_space = VirtioMmioCommonConfig::new(&self.device);
+        drop(locked_state);
+        self.queues.clear();
+        self.interrupt_status.store(0, Ordering::SeqCst);
+
+        let mut locked_dev = self.device.lock().unwrap();
+        if activated {
+            locked_dev
+                .deactivate()
+                .with_context(|| "Failed to deactivate virtio device")?;
+        }
+        locked_dev
+            .reset()
+            .with_context(|| "Failed to reset virtio device")?;
+        locked_dev.clear_driver_features();
+        Ok(())
+    }
+
     fn assign_interrupt_cb(&mut self) {
         let interrupt_status = self.interrupt_status.clone();
         let interrupt_evt = self.interrupt_evt.clone();
@@ -547,6 +569,7 @@ impl SysBusDevOps for VirtioMmioDevice {
         match offset {
             0x00..=0xff if data.len() == 4 => {
                 let value = LittleEndian::read_u32(data);
+                let old_status = locked_state.config_space.get_device_status();
                 if let Err(ref e) = locked_state.config_space.write_common_config(
                     &self.device,
                     &self.interrupt_status,
@@ -580,6 +603,16 @@ impl SysBusDevOps for VirtioMmioDevice {
                         return false;
                     }
                     self.state.lock().unwrap().activated = true;
+                } else if offset == STATUS_REG && value == 0 && old_status != 0 {
+                    drop(locked_state);
+                    if let Err(ref e) = self.reset_device() {
+                        error!(
+                            "Failed to reset dev, type: {}, {:?}",
+                            self.device.lock().unwrap().device_type(),
+                            e,
+                        );
+                        return false;
+                    }
                 }
             }
             0x100..=0xfff => {
@@ -732,13 +765,47 @@ impl MigrationHook for VirtioMmioDevice {
     }
 }
 
+#[cfg(test)]
+impl VirtioMmioDevice {
+    /// Negotiate the features and make all the queues ready as the driver does,
+    /// without activating the device.
+    pub(crate) fn setup_driver(&mut self, features: u64) {
+        let mut locked_state = self.state.lock().unwrap();
+        let queue_num = locked_state.config_space.queue_num as u32;
+        let mut write = |offset: u64, value: u32| {
+            locked_state
+                .config_space
+                .write_common_config(&self.device, &self.interrupt_status, offset, value)
+                .unwrap();
+        };
+        write(STATUS_REG, CONFIG_STATUS_ACKNOWLEDGE | CONFIG_STATUS_DRIVER);
+        write(DRIVER_FEATURES_SEL_REG, 0);
+        write(DRIVER_FEATURES_REG, features as u32);
+        write(DRIVER_FEATURES_SEL_REG, 1);
+        write(DRIVER_FEATURES_REG, (features >> 32) as u32);
+        write(
+            STATUS_REG,
+            CONFIG_STATUS_ACKNOWLEDGE | CONFIG_STATUS_DRIVER | CONFIG_STATUS_FEATURES_OK,
+        );
+        for queue in 0..queue_num {
+            write(QUEUE_SEL_REG, queue);
+            write(QUEUE_READY_REG, 1);
+        }
+    }
+
+    /// Whether the queue is made ready by the driver.
+    pub(crate) fn queue_ready(&self, index: usize) -> bool {
+        self.state.lock().unwrap().config_space.queues_config[index].ready
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Write;
 
     use super::*;
-    use crate::VIRTIO_TYPE_BLOCK;
-    use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
+    use crate::{VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK};
+    use address_space::{AddressSpace, GuestAddress, HostMemMapping, HostMemOptions, Region};
     use std::sync::atomic::AtomicBool;
     use util::num_ops::read_u32;
 
@@ -751,9 +818,7 @@ mod tests {
                 None,
                 SYSTEM_SPACE_SIZE,
                 None,
-                false,
-                false,
-                false,
+                HostMemOptions::default(),
             )
             .unwrap(),
         );
@@ -876,6 +941,15 @@ mod tests {
             Ok(())
         }
 
+        fn deactivate(&mut self) -> Result<()> {
+            self.b_active = false;
+            Ok(())
+        }
+
+        fn negotiate_packed_queue_feature(&self) -> bool {
+            true
+        }
+
         fn get_device_broken(&self) -> &Arc<AtomicBool> {
             &self.broken
         }
@@ -1581,5 +1655,18 @@ mod tests {
                 | CONFIG_STATUS_DRIVER_OK
                 | CONFIG_STATUS_FEATURES_OK
         );
+
+        // Driver writes 0 to the device status, the device is reset.
+        virtio_device_clone.lock().unwrap().driver_features = 1_u64 << VIRTIO_F_VERSION_1;
+        let buf = 0_u32.to_le_bytes();
+        assert_eq!(virtio_mmio_device.write(&buf[..], addr, STATUS_REG), true);
+        assert_eq!(virtio_mmio_device.state.lock().unwrap().activated, false);
+        assert_eq!(virtio_device_clone.lock().unwrap().b_active, false);
+        assert_eq!(virtio_device_clone.lock().unwrap().driver_features, 0);
+        assert!(virtio_mmio_device.queues.is_empty());
+        let locked_state = virtio_mmio_device.state.lock().unwrap();
+        assert_eq!(locked_state.config_space.device_status, 0);
+        assert_eq!(locked_state.config_space.queues_config[0].ready, false);
+        assert_eq!(locked_state.config_space.queues_config[1].size, QUEUE_SIZE);
     }
 }
@@ -355,8 +355,11 @@ impl VirtioPciCommonConfig {
                     .set_driver_features(self.acked_features_select, value);
 
                 if self.acked_features_select == 1 {
-                    let features = (device.lock().unwrap().get_driver_features(1) as u64) << 32;
-                    if virtio_has_feature(features, VIRTIO_F_RING_PACKED) {
+                    let locked_dev = device.lock().unwrap();
+                    let features = (locked_dev.get_driver_features(1) as u64) << 32;
+                    if virtio_has_feature(features, VIRTIO_F_RING_PACKED)
+                        && locked_dev.negotiate_packed_queue_feature()
+                    {
                         self.queue_type = QUEUE_TYPE_PACKED_VRING;
                     } else {
                         self.queue_type = QUEUE_TYPE_SPLIT_VRING;
@@ -395,7 +398,9 @@ impl VirtioPciCommonConfig {
                     virtio_pci_dev.activate_device(self);
                 } else if old_status != 0 && self.device_status.load(Ordering::Acquire) == 0 {
                     self.reset();
-                    virtio_pci_dev.deactivate_device();
+                    if let Err(e) = virtio_pci_dev.reset_device() {
+                        error!("{:?}", e);
+                    }
                 }
             }
             COMMON_Q_SELECT_REG => {
@@ -617,6 +622,10 @@ impl VirtioPciDevice {
     ) -> Self {
         let queue_num = device.lock().unwrap().queue_num();
         let queue_size = device.lock().unwrap().queue_size();
+        let mut common_config = VirtioPciCommonConfig::new(queue_size, queue_num);
+        for (index, queue_config) in common_config.queues_config.iter_mut().enumerate() {
+            *queue_config = QueueConfig::new(device.lock().unwrap().queue_size_of(index));
+        }
 
         VirtioPciDevice {
             name,
@@ -627,9 +636,7 @@ impl VirtioPciDevice {
             sys_mem,
             config: PciConfig::new(PCIE_CONFIG_SPACE_SIZE, VIRTIO_PCI_BAR_MAX),
             cfg_cap_offset: 0,
-            common_config: Arc::new(Mutex::new(VirtioPciCommonConfig::new(
-                queue_size, queue_num,
-            ))),
+            common_config: Arc::new(Mutex::new(common_config)),
             parent_bus,
             notify_eventfds: Arc::new(NotifyEventFds::new(queue_num)),
             interrupt_cb: None,
@@ -813,6 +820,18 @@ impl VirtioPciDevice {
         true
     }
 
+    /// Reset the virtio device after it is deactivated, the driver negotiates
+    /// the features again after reset.
+    fn reset_device(&self) -> PciResult<()> {
+        self.deactivate_device();
+        let mut locked_dev = self.device.lock().unwrap();
+        locked_dev
+            .reset()
+            .with_context(|| "Failed to reset virtio device")?;
+        locked_dev.clear_driver_features();
+        Ok(())
+    }
+
     fn build_common_cfg_ops(&mut self) -> RegionOps {
         let cloned_virtio_dev = self.device.clone();
         let cloned_common_cfg = self.common_config.clone();
@@ -1273,12 +1292,7 @@ impl PciDevOps for VirtioPciDevice {
     }
 
     fn reset(&mut self, _reset_child_device: bool) -> PciResult<()> {
-        self.deactivate_device();
-        self.device
-            .lock()
-            .unwrap()
-            .reset()
-            .with_context(|| "Failed to reset virtio device")?;
+        self.reset_device()?;
         self.common_config.lock().unwrap().reset();
 
         self.config.reset()?;
@@ -1458,7 +1472,7 @@ impl MigrationHook for VirtioPciDevice {
 mod tests {
     use std::sync::{Arc, Mutex};
 
-    use address_space::{AddressSpace, GuestAddress, HostMemMapping};
+    use address_space::{AddressSpace, GuestAddress, HostMemMapping, HostMemOptions};
     use pci::{
         config::{HEADER_TYPE, HEADER_TYPE_MULTIFUNC},
         le_read_u16,
@@ -1539,6 +1553,10 @@ mod tests {
             Ok(())
         }
 
+        fn negotiate_packed_queue_feature(&self) -> bool {
+            true
+        }
+
         fn get_device_broken(&self) -> &Arc<AtomicBool> {
             &self.broken
         }
@@ -1785,8 +1803,14 @@ mod tests {
         .unwrap();
         let mem_size: u64 = 1024 * 1024;
         let host_mmap = Arc::new(
-            HostMemMapping::new(GuestAddress(0), None, mem_size, None, false, false, false)
-                .unwrap(),
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                mem_size,
+                None,
+                HostMemOptions::default(),
+            )
+            .unwrap(),
         );
         sys_mem
             .root()
@@ -1796,8 +1820,8 @@ mod tests {
             )
             .unwrap();
 
-        let virtio_dev: Arc<Mutex<dyn VirtioDevice>> =
-            Arc::new(Mutex::new(VirtioDeviceTest::new()));
+        let dev = Arc::new(Mutex::new(VirtioDeviceTest::new()));
+        let virtio_dev = dev.clone() as Arc<Mutex<dyn VirtioDevice>>;
         let parent_bus = Arc::new(Mutex::new(PciBus::new(
             String::from("test bus"),
             #[cfg(target_arch = "x86_64")]
@@ -1875,8 +1899,11 @@ mod tests {
         assert_eq!(virtio_pci.device_activated.load(Ordering::Relaxed), true);
 
         // If device status(not zero) is set to zero, reset the device
+        dev.lock().unwrap().driver_features = 1_u64 << VIRTIO_F_VERSION_1;
         (common_cfg_ops.write)(0_u32.as_bytes(), GuestAddress(0), COMMON_STATUS_REG);
         assert_eq!(virtio_pci.device_activated.load(Ordering::Relaxed), false);
+        assert_eq!(dev.lock().unwrap().driver_features, 0);
+        assert!(virtio_pci.queues.lock().unwrap().is_empty());
     }
 
     #[test]
@@ -19,7 +19,7 @@ use std::sync::{Arc, Mutex};
 use crate::error::VirtioError;
 use address_space::AddressSpace;
 use anyhow::{anyhow, bail, Context, Result};
-use machine_manager::config::NetworkInterfaceConfig;
+use machine_manager::config::{get_virtio_queue_size, NetworkInterfaceConfig};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
 use util::byte_code::ByteCode;
 use util::loop_context::EventNotifierHelper;
@@ -201,6 +201,14 @@ impl VirtioDevice for Net {
         self.net_cfg.queue_size
     }
 
+    fn queue_size_of(&self, queue_index: usize) -> u16 {
+        get_virtio_queue_size(
+            &self.net_cfg.queue_overrides,
+            queue_index,
+            self.net_cfg.queue_size,
+        )
+    }
+
     /// Get device features from host.
     fn get_device_features(&self, features_select: u32) -> u32 {
         read_u32(self.state.lock().unwrap().device_features, features_select)
@@ -459,9 +467,7 @@ mod tests {
                 None,
                 SYSTEM_SPACE_SIZE,
                 None,
-                false,
-                false,
-                false,
+                HostMemOptions::default(),
             )
             .unwrap(),
         );
@@ -489,6 +495,9 @@ mod tests {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            queue_overrides: Vec::new(),
+            tx_burst: 0,
+            rx_burst: 0,
         };
         let conf = vec![net1];
         let confs = Some(conf);
@@ -511,6 +520,9 @@ mod tests {
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            queue_overrides: Vec::new(),
+            tx_burst: 0,
+            rx_burst: 0,
         };
         let conf = vec![net1];
         let confs = Some(conf);
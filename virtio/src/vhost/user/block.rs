@@ -18,7 +18,7 @@ use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
 
 use address_space::AddressSpace;
-use machine_manager::config::BlkDevConfig;
+use machine_manager::config::{get_virtio_queue_size, BlkDevConfig};
 use util::byte_code::ByteCode;
 use util::num_ops::read_u32;
 use vmm_sys_util::eventfd::EventFd;
@@ -192,6 +192,14 @@ impl VirtioDevice for Block {
         self.blk_cfg.queue_size
     }
 
+    fn queue_size_of(&self, queue_index: usize) -> u16 {
+        get_virtio_queue_size(
+            &self.blk_cfg.queue_overrides,
+            queue_index,
+            self.blk_cfg.queue_size,
+        )
+    }
+
     /// Get device features from host.
     fn get_device_features(&self, features_select: u32) -> u32 {
         read_u32(self.state.device_features, features_select)
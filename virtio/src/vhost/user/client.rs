@@ -450,6 +450,7 @@ impl VhostUserClient {
                     true,
                     true,
                     false,
+                    false,
                 )?;
                 let inflight = VhostInflight {
                     file,
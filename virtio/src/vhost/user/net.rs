@@ -17,7 +17,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use address_space::AddressSpace;
-use machine_manager::config::NetworkInterfaceConfig;
+use machine_manager::config::{get_virtio_queue_size, NetworkInterfaceConfig};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
 use util::byte_code::ByteCode;
 use util::loop_context::EventNotifierHelper;
@@ -179,6 +179,14 @@ impl VirtioDevice for Net {
         self.net_cfg.queue_size
     }
 
+    fn queue_size_of(&self, queue_index: usize) -> u16 {
+        get_virtio_queue_size(
+            &self.net_cfg.queue_overrides,
+            queue_index,
+            self.net_cfg.queue_size,
+        )
+    }
+
     /// Get device features from host.
     fn get_device_features(&self, features_select: u32) -> u32 {
         read_u32(self.state.lock().unwrap().device_features, features_select)